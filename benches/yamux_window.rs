@@ -0,0 +1,151 @@
+// Attachment fetch throughput between two nodes on a loopback TCP link capped at 100 Mbps, for
+// Yamux receive windows from libp2p's default up. `cargo bench --bench yamux_window` runs it;
+// with `--profile dev` every write is also delayed by 10 ms, as on a link with some latency.
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    time::{Duration, Instant},
+};
+
+use concurrent_chat_server::{attachment::CHUNK_BYTES, chat::ChatNode, cli::Cli};
+use libp2p::{futures::StreamExt, swarm::SwarmEvent, Multiaddr};
+
+/// Bytes of the file fetched in each run.
+const FILE_BYTES: u64 = 16 * CHUNK_BYTES;
+
+/// The link's capacity: 100 Mbps.
+const LINK_KBPS: u32 = 100_000;
+
+/// Receive windows compared, in bytes; none is libp2p's own default.
+const WINDOWS: [Option<u32>; 4] = [None, Some(256 * 1024), Some(1024 * 1024), Some(4096 * 1024)];
+
+/// How long setting up or fetching may take before a run gives up.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+#[tokio::main]
+async fn main() {
+    let dir = env::temp_dir().join(format!("p2p-chat-yamux-bench-{}", process::id()));
+    fs::create_dir_all(&dir).expect("the bench directory can be created");
+    let file = dir.join("attachment.bin");
+    let data: Vec<u8> = (0..FILE_BYTES).map(|n| (n * 31 % 251) as u8).collect();
+    fs::write(&file, &data).expect("the attachment can be written");
+
+    println!(
+        "fetching {} KiB over a {} Mbps link{}",
+        FILE_BYTES / 1024,
+        LINK_KBPS / 1000,
+        if cfg!(debug_assertions) {
+            " with 10 ms of latency"
+        } else {
+            ""
+        }
+    );
+    println!("{:>12} {:>10} {:>10}", "window", "seconds", "Mbit/s");
+    for window in WINDOWS {
+        let elapsed = fetch(&dir, &file, window).await;
+        let mbps = (FILE_BYTES * 8) as f64 / elapsed.as_secs_f64() / 1e6;
+        let window = window.map_or("default".to_string(), |w| format!("{} KiB", w / 1024));
+        println!("{window:>12} {:>10.2} {mbps:>10.1}", elapsed.as_secs_f64());
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+// A node keeping its config in `dir`, with every connection capped at the link's capacity.
+fn node_cli(dir: &Path, name: &str, window: Option<u32>) -> Cli {
+    Cli {
+        nick: Some(name.to_string()),
+        config: Some(dir.join(format!("{name}.json"))),
+        no_mdns: true,
+        no_notify: true,
+        yamux_window_size: window,
+        // The buffer must hold a full window, or a stream that fills it is reset
+        yamux_max_buffer: window.map(|window| (window as usize).max(1024 * 1024)),
+        max_upload_kbps: Some(LINK_KBPS),
+        #[cfg(debug_assertions)]
+        simulate_latency_ms: Some(vec![10, 0]),
+        ..Cli::default()
+    }
+}
+
+async fn listen(node: &mut ChatNode) -> Multiaddr {
+    node.swarm
+        .listen_on(
+            "/ip4/127.0.0.1/tcp/0"
+                .parse()
+                .expect("the address is valid"),
+        )
+        .expect("the node listens");
+    loop {
+        match node.swarm.select_next_some().await {
+            SwarmEvent::NewListenAddr { address, .. } => return address,
+            event => node.handle_event(event),
+        }
+    }
+}
+
+// Drive both nodes until `done`, panicking after the timeout.
+async fn run_until(
+    a: &mut ChatNode,
+    b: &mut ChatNode,
+    mut done: impl FnMut(&ChatNode, &ChatNode) -> bool,
+) {
+    tokio::time::timeout(TIMEOUT, async {
+        while !done(a, b) {
+            tokio::select! {
+                event = a.swarm.select_next_some() => a.handle_event(event),
+                event = b.swarm.select_next_some() => b.handle_event(event),
+            }
+        }
+    })
+    .await
+    .expect("the run finished in time");
+}
+
+// How long Bob takes to fetch `file` from Alice over connections with the given window.
+async fn fetch(dir: &Path, file: &Path, window: Option<u32>) -> Duration {
+    let mut alice = ChatNode::new(&node_cli(dir, "alice", window)).expect("alice builds");
+    let mut bob = ChatNode::new(&node_cli(dir, "bob", window)).expect("bob builds");
+    let address = listen(&mut alice).await;
+    bob.swarm.dial(address).expect("bob dials alice");
+    let topic = alice.topic().hash();
+    run_until(&mut alice, &mut bob, |alice, bob| {
+        [alice, bob].iter().all(|node| {
+            node.swarm
+                .behaviour()
+                .gossipsub
+                .all_peers()
+                .any(|(_, topics)| topics.contains(&&topic))
+        })
+    })
+    .await;
+
+    alice
+        .handle_line(&format!("/attach {}", file.display()))
+        .await;
+    alice.handle_line("the attachment").await;
+    run_until(&mut alice, &mut bob, |_, bob| bob.history().count() == 1).await;
+    let id = bob
+        .history()
+        .next()
+        .expect("the message arrived")
+        .id
+        .clone();
+    let saved: PathBuf = dir.join("fetched.bin");
+    let _ = fs::remove_file(&saved);
+
+    let started = Instant::now();
+    bob.handle_line(&format!("/fetch {id} {}", saved.display()))
+        .await;
+    run_until(&mut alice, &mut bob, |_, bob| bob.fetching() == 0).await;
+    let elapsed = started.elapsed();
+
+    alice.flush_writes().await;
+    bob.flush_writes().await;
+    assert_eq!(
+        fs::metadata(&saved).map(|meta| meta.len()).ok(),
+        Some(FILE_BYTES),
+        "the whole file arrived"
+    );
+    elapsed
+}
@@ -0,0 +1,47 @@
+// Feeds arbitrary bytes to the decoders of payloads that aren't plain JSON chat messages, and of
+// attachment fetches and the transfers they feed.
+#![no_main]
+
+use concurrent_chat_server::{
+    attachment::{Content, Fetch},
+    batch::Batch,
+    fragment::{self, Fragment},
+    invite::SignedInvite,
+    message::Incoming,
+    passphrase::{RoomKey, SECRET_LEN},
+    transfer::{Manifest, State, Transfer},
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Payloads sealed with a room key; no input guesses a real key, so this covers the framing
+    let _ = RoomKey::from_secret([7; SECRET_LEN]).open(data);
+
+    // Payloads that aren't text keep their bytes and get a preview as their body
+    let _ = Incoming::decode(data, 0);
+
+    // Signed values in their compact binary form, as carried by invite tokens
+    if let Some(signed) = SignedInvite::from_bytes(data) {
+        let _ = signed.verify();
+    }
+
+    // Pieces of a large message, and small messages published together
+    if let Some(fragment) = Fragment::decode(data) {
+        let _ = fragment::reassemble(vec![fragment]);
+    }
+    let _ = Batch::decode(data);
+
+    // Attachment fetches and their answers
+    let _ = serde_json::from_slice::<Fetch>(data);
+    let _ = serde_json::from_slice::<Content>(data);
+
+    // Manifests of interrupted transfers, read back at startup
+    if let Ok(manifest) = serde_json::from_slice::<Manifest>(data) {
+        let mut transfer = Transfer::new(manifest, State::Active);
+        let _ = transfer.received();
+        let _ = transfer.is_complete();
+        for (offset, len) in transfer.next_chunks() {
+            transfer.arrived(offset, len);
+        }
+    }
+});
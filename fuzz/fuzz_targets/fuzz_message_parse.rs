@@ -0,0 +1,24 @@
+// Feeds arbitrary bytes to everything that parses data received from other peers.
+#![no_main]
+
+use concurrent_chat_server::{control::SignedControl, invite, message::ChatMessage, sanitize};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Chat topic: JSON messages, falling back to plain text
+    let message = ChatMessage::decode(data, 0);
+    let _ = sanitize::body(&message.body);
+    let _ = sanitize::nick(&message.nick);
+
+    // Control topic: signed control messages
+    if let Ok(signed) = serde_json::from_slice::<SignedControl>(data) {
+        let _ = signed.verify();
+    }
+
+    // Invite tokens passed to --join-with
+    if let Ok(token) = std::str::from_utf8(data) {
+        if let Ok(signed) = invite::decode_token(token) {
+            let _ = signed.verify();
+        }
+    }
+});
@@ -0,0 +1,904 @@
+// Cross-posting between the room and the fediverse over ActivityPub: the room's messages are
+// delivered as notes, signed by the node's actor, to the inboxes given with
+// `--activitypub-inbox`, and notes the actors of those inboxes post to the HTTP server's
+// `/inbox` are published in the room. Requests are signed and checked as the HTTP Signatures
+// draft has it, with RSA or Ed25519 keys.
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use data_encoding::BASE64;
+use ring::{
+    rand::SystemRandom,
+    signature::{self, Ed25519KeyPair, KeyPair, RsaKeyPair, UnparsedPublicKey},
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tracing::debug;
+use url::{Host, Url};
+use uuid::Uuid;
+
+use crate::{
+    cli::Cli,
+    clock,
+    error::ConfigError,
+    http::{self, RequestHead},
+    runtime, sanitize,
+};
+
+/// The `origin` of messages copied in from the fediverse, which bridges don't copy out again.
+pub const ORIGIN: &str = "fediverse";
+
+/// Notes waiting for one inbox. Once that many are, more are dropped until some go out.
+pub const QUEUE: usize = 256;
+
+/// Times a note is delivered again after a server error or a lost connection.
+pub const RETRIES: u32 = 3;
+
+/// First wait before delivering a note again, doubled after each failure.
+pub const RETRY_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait before delivering a note again.
+pub const RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Largest activity taken at the inbox, in bytes.
+pub const MAX_ACTIVITY_BYTES: usize = 256 * 1024;
+
+/// How far the date a request was signed at may be from ours.
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// How long fetching the actor of a signature may take.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The content type of activities and actors.
+pub const CONTENT_TYPE: &str = "application/activity+json";
+
+// The audience of public posts
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+// The headers our requests are signed over, in order
+const SIGNED_HEADERS: &str = "(request-target) host date digest content-type";
+
+// Object identifiers of the keys spoken, DER encoded
+const RSA_OID: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+const ED25519_OID: &[u8] = &[0x2b, 0x65, 0x70];
+
+/// The actor the node cross-posts as, and where its notes go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityPubSettings {
+    /// The actor's id, where the node's HTTP server serves it.
+    pub actor: Url,
+    /// The inboxes notes are delivered to. Only their actors' notes are taken.
+    pub inboxes: Vec<Url>,
+    /// The PEM file with the actor's private key.
+    pub key: PathBuf,
+}
+
+impl ActivityPubSettings {
+    /// The settings given on the command line, if the room is cross-posted.
+    pub fn from_cli(cli: &Cli) -> Option<Self> {
+        Some(ActivityPubSettings {
+            actor: cli.activitypub_actor.clone()?,
+            inboxes: cli.activitypub_inbox.clone(),
+            key: cli.activitypub_key.clone()?,
+        })
+    }
+}
+
+/// The private key an actor signs its requests with.
+pub enum SigningKey {
+    Rsa(RsaKeyPair),
+    Ed25519(Ed25519KeyPair),
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SigningKey::Rsa(_) => write!(f, "SigningKey::Rsa"),
+            SigningKey::Ed25519(_) => write!(f, "SigningKey::Ed25519"),
+        }
+    }
+}
+
+impl SigningKey {
+    /// Read the key at `path`, generating and saving an Ed25519 key if the file doesn't exist.
+    pub fn load_or_create(path: &Path) -> Result<Self, ConfigError> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let invalid = |reason: &str| ConfigError::ActivityPubKey {
+            path: path.to_path_buf(),
+            reason: reason.to_string(),
+        };
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| invalid("generating a key failed"))?;
+        fs::write(path, pem("PRIVATE KEY", pkcs8.as_ref())).map_err(|source| {
+            ConfigError::Write {
+                path: path.to_path_buf(),
+                source,
+            }
+        })?;
+        Self::load(path)
+    }
+
+    /// Read the key at `path`.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_pem(&text).map_err(|reason| ConfigError::ActivityPubKey {
+            path: path.to_path_buf(),
+            reason,
+        })
+    }
+
+    /// The key in a PEM file: a PKCS#8 `PRIVATE KEY` holding an RSA or Ed25519 key, or an
+    /// `RSA PRIVATE KEY`.
+    pub fn from_pem(text: &str) -> Result<Self, String> {
+        let (label, der) = unpem(text)?;
+        match label.as_str() {
+            "PRIVATE KEY" => Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+                .map(SigningKey::Ed25519)
+                .or_else(|_| RsaKeyPair::from_pkcs8(&der).map(SigningKey::Rsa))
+                .map_err(|e| format!("not an RSA or Ed25519 key: {e}")),
+            "RSA PRIVATE KEY" => RsaKeyPair::from_der(&der)
+                .map(SigningKey::Rsa)
+                .map_err(|e| format!("not an RSA key: {e}")),
+            label => Err(format!("a {label} isn't a private key")),
+        }
+    }
+
+    /// The `algorithm` of signatures made with the key.
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            SigningKey::Rsa(_) => "rsa-sha256",
+            SigningKey::Ed25519(_) => "hs2019",
+        }
+    }
+
+    /// The public half of the key, as the `PUBLIC KEY` PEM actors publish.
+    pub fn public_key_pem(&self) -> String {
+        let (algorithm, key) = match self {
+            SigningKey::Rsa(key) => (
+                [der(0x06, RSA_OID), der(0x05, &[])].concat(),
+                key.public().as_ref().to_vec(),
+            ),
+            SigningKey::Ed25519(key) => {
+                (der(0x06, ED25519_OID), key.public_key().as_ref().to_vec())
+            }
+        };
+        let bits = [&[0][..], &key].concat();
+        let info = der(0x30, &[der(0x30, &algorithm), der(0x03, &bits)].concat());
+        pem("PUBLIC KEY", &info)
+    }
+
+    /// Sign `message`, with RSASSA-PKCS1-v1_5 and SHA-256 for an RSA key.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match self {
+            SigningKey::Rsa(key) => {
+                let mut signature = vec![0; key.public().modulus_len()];
+                key.sign(
+                    &signature::RSA_PKCS1_SHA256,
+                    &SystemRandom::new(),
+                    message,
+                    &mut signature,
+                )
+                .expect("a buffer the size of the modulus takes the signature");
+                signature
+            }
+            SigningKey::Ed25519(key) => key.sign(message).as_ref().to_vec(),
+        }
+    }
+}
+
+/// Check `signature` of `message` with the key in a `PUBLIC KEY` PEM, RSA or Ed25519.
+pub fn verify(public_key_pem: &str, message: &[u8], signature: &[u8]) -> Result<(), String> {
+    let (label, info) = unpem(public_key_pem)?;
+    if label != "PUBLIC KEY" {
+        return Err(format!("a {label} isn't a public key"));
+    }
+    let (info, _) = der_take(0x30, &info)?;
+    let (algorithm, rest) = der_take(0x30, info)?;
+    let (oid, _) = der_take(0x06, algorithm)?;
+    let (bits, _) = der_take(0x03, rest)?;
+    let key = bits.strip_prefix(&[0]).ok_or("the key is malformed")?;
+    let algorithm: &'static dyn signature::VerificationAlgorithm = match oid {
+        RSA_OID => &signature::RSA_PKCS1_2048_8192_SHA256,
+        ED25519_OID => &signature::ED25519,
+        _ => return Err("the key is neither RSA nor Ed25519".to_string()),
+    };
+    UnparsedPublicKey::new(algorithm, key)
+        .verify(message, signature)
+        .map_err(|_| "the signature doesn't verify".to_string())
+}
+
+/// The node's actor: its id, and the key it signs with.
+#[derive(Debug)]
+pub struct Actor {
+    id: Url,
+    key: SigningKey,
+}
+
+impl Actor {
+    pub fn new(id: Url, key: SigningKey) -> Self {
+        Actor { id, key }
+    }
+
+    pub fn id(&self) -> &Url {
+        &self.id
+    }
+
+    /// The id of the actor's key, which signatures name.
+    pub fn key_id(&self) -> String {
+        format!("{}#main-key", self.id)
+    }
+
+    /// Where notes for the actor are posted: `/inbox` on the actor's host.
+    pub fn inbox(&self) -> Url {
+        let mut inbox = self.id.clone();
+        inbox.set_path("/inbox");
+        inbox.set_query(None);
+        inbox.set_fragment(None);
+        inbox
+    }
+
+    /// The name the actor goes by on its host: the last part of its path.
+    pub fn username(&self) -> &str {
+        self.id
+            .path_segments()
+            .and_then(|segments| segments.rev().find(|segment| !segment.is_empty()))
+            .unwrap_or("p2p-chat")
+    }
+
+    /// The actor as served at its id, with its public key.
+    pub fn document(&self) -> String {
+        json!({
+            "@context": [
+                "https://www.w3.org/ns/activitystreams",
+                "https://w3id.org/security/v1"
+            ],
+            "id": self.id.as_str(),
+            "type": "Service",
+            "preferredUsername": self.username(),
+            "name": "p2p-chat",
+            "inbox": self.inbox().as_str(),
+            "publicKey": {
+                "id": self.key_id(),
+                "owner": self.id.as_str(),
+                "publicKeyPem": self.key.public_key_pem()
+            }
+        })
+        .to_string()
+    }
+
+    /// A public `Create` activity of a note saying `text`, said by `nick` at Unix time `now`.
+    pub fn note(&self, nick: &str, text: &str, now: u64) -> String {
+        let id = format!(
+            "{}/notes/{}",
+            self.id.as_str().trim_end_matches('/'),
+            Uuid::new_v4()
+        );
+        let published = clock::rfc3339(now);
+        let content = format!("<p>{}: {}</p>", escape(nick), escape(text));
+        json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": format!("{id}/activity"),
+            "type": "Create",
+            "actor": self.id.as_str(),
+            "published": published,
+            "to": [PUBLIC],
+            "object": {
+                "id": id,
+                "type": "Note",
+                "attributedTo": self.id.as_str(),
+                "content": content,
+                "published": published,
+                "to": [PUBLIC]
+            }
+        })
+        .to_string()
+    }
+
+    /// The headers signing a `method` request for `url` with `body`, sent at `date`.
+    pub fn sign(
+        &self,
+        method: &str,
+        url: &Url,
+        body: &str,
+        date: SystemTime,
+    ) -> Vec<(&'static str, String)> {
+        let date = httpdate::fmt_http_date(date);
+        let digest = format!("SHA-256={}", BASE64.encode(&Sha256::digest(body)));
+        let signed = format!(
+            "(request-target): {} {}\nhost: {}\ndate: {date}\ndigest: {digest}\ncontent-type: {CONTENT_TYPE}",
+            method.to_ascii_lowercase(),
+            http::resource(url),
+            http::authority(url)
+        );
+        let signature = format!(
+            "keyId=\"{}\",algorithm=\"{}\",headers=\"{SIGNED_HEADERS}\",signature=\"{}\"",
+            self.key_id(),
+            self.key.algorithm(),
+            BASE64.encode(&self.key.sign(signed.as_bytes()))
+        );
+        vec![
+            ("Content-Type", CONTENT_TYPE.to_string()),
+            ("Date", date),
+            ("Digest", digest),
+            ("Signature", signature),
+        ]
+    }
+}
+
+/// What happened to the notes delivered to the fediverse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FediverseEvent {
+    /// `inbox` took a note.
+    Delivered { inbox: Url },
+    /// A note for `inbox` was given up on.
+    Failed { inbox: Url, error: String },
+}
+
+/// The room's messages on their way to the inboxes, each delivered from a task of its own.
+/// Dropping it stops delivering.
+#[derive(Debug)]
+pub struct Fediverse {
+    actor: Arc<Actor>,
+    inboxes: Vec<(Url, mpsc::Sender<String>)>,
+    events: mpsc::Receiver<FediverseEvent>,
+    tasks: Vec<runtime::Task<()>>,
+}
+
+impl Drop for Fediverse {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+impl Fediverse {
+    /// Deliver notes as `actor` to `inboxes`.
+    pub fn spawn(actor: Arc<Actor>, inboxes: Vec<Url>) -> Self {
+        let (sender, events) = mpsc::channel(QUEUE);
+        let mut queues = Vec::new();
+        let mut tasks = Vec::new();
+        for inbox in inboxes {
+            let (queue, outgoing) = mpsc::channel(QUEUE);
+            let task = deliver(actor.clone(), inbox.clone(), outgoing, sender.clone());
+            tasks.push(runtime::spawn(task));
+            queues.push((inbox, queue));
+        }
+        Fediverse {
+            actor,
+            inboxes: queues,
+            events,
+            tasks,
+        }
+    }
+
+    pub fn actor(&self) -> &Arc<Actor> {
+        &self.actor
+    }
+
+    /// The inboxes notes are delivered to.
+    pub fn inboxes(&self) -> impl Iterator<Item = &Url> {
+        self.inboxes.iter().map(|(inbox, _)| inbox)
+    }
+
+    /// Queue a note saying `text`, said by `nick`, for every inbox. Fails for the inboxes
+    /// [`QUEUE`] notes are already waiting for.
+    pub fn relay(&self, nick: &str, text: &str) -> Result<(), String> {
+        let note = self.actor.note(nick, text, clock::unix_time());
+        let full: Vec<_> = self
+            .inboxes
+            .iter()
+            .filter(|(_, queue)| queue.try_send(note.clone()).is_err())
+            .map(|(inbox, _)| inbox.as_str())
+            .collect();
+        if !full.is_empty() {
+            return Err(format!(
+                "{QUEUE} notes are already waiting for {}",
+                full.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// The next thing that happened to delivered notes.
+    pub async fn next(&mut self) -> Option<FediverseEvent> {
+        self.events.recv().await
+    }
+}
+
+// Deliver the notes queued for `inbox` until the node goes away.
+async fn deliver(
+    actor: Arc<Actor>,
+    inbox: Url,
+    mut queue: mpsc::Receiver<String>,
+    events: mpsc::Sender<FediverseEvent>,
+) {
+    while let Some(note) = queue.recv().await {
+        let event = match post(&actor, &inbox, &note).await {
+            Ok(()) => FediverseEvent::Delivered {
+                inbox: inbox.clone(),
+            },
+            Err(error) => FediverseEvent::Failed {
+                inbox: inbox.clone(),
+                error,
+            },
+        };
+        if events.send(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Post `activity` to `inbox`, signed afresh each time, again after server errors and lost
+// connections up to [`RETRIES`] times. Other answers aren't tried again.
+async fn post(actor: &Actor, inbox: &Url, activity: &str) -> Result<(), String> {
+    let mut backoff = RETRY_MIN;
+    let mut retries = 0;
+    loop {
+        let headers = actor.sign("POST", inbox, activity, SystemTime::now());
+        let sent = http::send(
+            "POST",
+            inbox,
+            &headers,
+            Some(activity),
+            http::REQUEST_TIMEOUT,
+        );
+        let error = match sent.await {
+            Ok(response) if response.is_success() => return Ok(()),
+            Ok(response) if response.status >= 500 || response.status == 429 => {
+                format!("the inbox answered {}", response.status)
+            }
+            Ok(response) => return Err(format!("the inbox answered {}", response.status)),
+            Err(e) => e,
+        };
+        if retries >= RETRIES {
+            return Err(error);
+        }
+        retries += 1;
+        debug!("[fediverse] {inbox}: {error}, delivering again in {backoff:?}");
+        runtime::sleep(backoff).await;
+        backoff = (backoff * 2).min(RETRY_MAX);
+    }
+}
+
+/// A note from the fediverse, taken at the inbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Received {
+    /// Who posted it, as `user@host`.
+    pub actor: String,
+    pub text: String,
+}
+
+/// Why the inbox refused a post: the status to answer with, and the error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refusal {
+    pub status: u16,
+    pub error: String,
+}
+
+impl Refusal {
+    fn new(status: u16, error: impl Into<String>) -> Self {
+        Refusal {
+            status,
+            error: error.into(),
+        }
+    }
+
+    fn unsigned(error: impl Into<String>) -> Self {
+        Refusal::new(401, error)
+    }
+}
+
+/// The actor's inbox on the node's HTTP server, taking notes from the actors of the inboxes
+/// notes are delivered to.
+#[derive(Debug)]
+pub struct Inbox {
+    actor: Arc<Actor>,
+    senders: Vec<Url>,
+}
+
+impl Inbox {
+    /// Take notes for `actor` from the actors whose inbox or shared inbox is one of `senders`.
+    pub fn new(actor: Arc<Actor>, senders: Vec<Url>) -> Self {
+        Inbox { actor, senders }
+    }
+
+    pub fn actor(&self) -> &Actor {
+        &self.actor
+    }
+
+    /// Check an activity posted to the inbox with `head`: its digest, its signature by its
+    /// actor's key, fetched from the actor, and whether that actor may post. Returns the note
+    /// it creates, or `None` for other activities, which are taken and ignored.
+    pub async fn receive(
+        &self,
+        head: &RequestHead,
+        body: &[u8],
+    ) -> Result<Option<Received>, Refusal> {
+        let activity: Value = serde_json::from_slice(body)
+            .map_err(|_| Refusal::new(400, "the body must be a JSON activity"))?;
+        let actor = match &activity["actor"] {
+            Value::String(actor) => actor.as_str(),
+            actor => actor["id"].as_str().unwrap_or_default(),
+        };
+        let actor =
+            Url::parse(actor).map_err(|_| Refusal::new(400, "the activity has no actor"))?;
+        // Keys are only fetched from the hosts notes go to, so a stranger can't have us fetch
+        // whatever URL it likes
+        let known_host = self.senders.iter().any(|inbox| {
+            inbox.host_str() == actor.host_str()
+                && inbox.port_or_known_default() == actor.port_or_known_default()
+        });
+        if !known_host {
+            return Err(Refusal::new(
+                403,
+                format!("{actor} isn't on a host notes go to"),
+            ));
+        }
+        check_digest(head, body)?;
+        let signature = head
+            .header("signature")
+            .ok_or_else(|| Refusal::unsigned("the request isn't signed"))?;
+        let params = parameters(signature);
+        let (Some(key_id), Some(signature)) = (params.get("keyId"), params.get("signature")) else {
+            return Err(Refusal::unsigned("the signature has no keyId or signature"));
+        };
+        let signature = BASE64
+            .decode(signature.as_bytes())
+            .map_err(|_| Refusal::unsigned("the signature isn't base64"))?;
+        let covered: Vec<&str> = params
+            .get("headers")
+            .map_or("date", String::as_str)
+            .split_whitespace()
+            .collect();
+        let dated = covered.contains(&"date") || covered.contains(&"(created)");
+        if !covered.contains(&"(request-target)") || !covered.contains(&"digest") || !dated {
+            return Err(Refusal::unsigned(
+                "the signature must cover (request-target), digest and date or (created)",
+            ));
+        }
+        check_date(head, &params, &covered)?;
+        let signed = signing_string(head, &params, &covered)?;
+
+        // The key is the actor's, fetched from the actor's own host
+        let key_url = Url::parse(key_id).map_err(|_| Refusal::unsigned("the keyId isn't a URL"))?;
+        if key_url.host_str() != actor.host_str() || key_url.port() != actor.port() {
+            return Err(Refusal::unsigned(format!(
+                "{actor} can't sign with {key_url}"
+            )));
+        }
+        let document = fetch(&key_url)
+            .await
+            .map_err(|e| Refusal::unsigned(format!("the key of {actor} can't be fetched: {e}")))?;
+        if document["id"].as_str() != Some(actor.as_str()) {
+            return Err(Refusal::unsigned(format!("{key_url} isn't {actor}")));
+        }
+        let keys = match &document["publicKey"] {
+            Value::Array(keys) => keys.iter().collect(),
+            key => vec![key],
+        };
+        let key = keys
+            .into_iter()
+            .find(|key| key["id"].as_str() == Some(key_id.as_str()))
+            .filter(|key| key["owner"].as_str() == Some(actor.as_str()))
+            .and_then(|key| key["publicKeyPem"].as_str())
+            .ok_or_else(|| Refusal::unsigned(format!("{actor} has no key {key_id}")))?;
+        verify(key, signed.as_bytes(), &signature).map_err(Refusal::unsigned)?;
+
+        let inboxes = [&document["inbox"], &document["endpoints"]["sharedInbox"]];
+        let known = inboxes
+            .into_iter()
+            .filter_map(|inbox| Url::parse(inbox.as_str()?).ok())
+            .any(|inbox| self.senders.contains(&inbox));
+        if !known {
+            return Err(Refusal::new(
+                403,
+                format!("{actor} isn't among the inboxes notes go to"),
+            ));
+        }
+        let object = &activity["object"];
+        if activity["type"] != "Create" || object["type"] != "Note" {
+            return Ok(None);
+        }
+        let text = text_of(object["content"].as_str().unwrap_or_default());
+        if text.is_empty() {
+            return Ok(None);
+        }
+        let username = document["preferredUsername"]
+            .as_str()
+            .map(sanitize::nick)
+            .filter(|username| !username.is_empty())
+            .unwrap_or_else(|| "someone".to_string());
+        Ok(Some(Received {
+            actor: format!("{username}@{}", http::authority(&actor)),
+            text,
+        }))
+    }
+}
+
+// Check that the `Digest` of the request is the SHA-256 of `body`.
+fn check_digest(head: &RequestHead, body: &[u8]) -> Result<(), Refusal> {
+    let digest = head
+        .header("digest")
+        .ok_or_else(|| Refusal::unsigned("the request has no digest"))?;
+    let sha256 = digest
+        .split(',')
+        .filter_map(|digest| digest.trim().split_once('='))
+        .find(|(algorithm, _)| algorithm.eq_ignore_ascii_case("sha-256"))
+        .map(|(_, value)| value)
+        .ok_or_else(|| Refusal::unsigned("the request has no SHA-256 digest"))?;
+    if sha256 != BASE64.encode(&Sha256::digest(body)) {
+        return Err(Refusal::unsigned("the digest doesn't match the body"));
+    }
+    Ok(())
+}
+
+// Check that the request was signed within [`MAX_CLOCK_SKEW`] of now, and hasn't expired.
+fn check_date(
+    head: &RequestHead,
+    params: &BTreeMap<String, String>,
+    covered: &[&str],
+) -> Result<(), Refusal> {
+    let param = |name: &str| params.get(name).and_then(|value| value.parse::<u64>().ok());
+    let signed_at = if covered.contains(&"(created)") {
+        param("created")
+    } else {
+        head.header("date")
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs())
+    };
+    let signed_at = signed_at.ok_or_else(|| Refusal::unsigned("the signature isn't dated"))?;
+    let now = clock::unix_time();
+    if now.abs_diff(signed_at) > MAX_CLOCK_SKEW.as_secs() {
+        return Err(Refusal::unsigned(format!(
+            "the signature is dated {}s off",
+            now.abs_diff(signed_at)
+        )));
+    }
+    if covered.contains(&"(expires)") && param("expires").is_none_or(|expires| expires < now) {
+        return Err(Refusal::unsigned("the signature has expired"));
+    }
+    Ok(())
+}
+
+// The string the headers `covered` by a signature were signed as.
+fn signing_string(
+    head: &RequestHead,
+    params: &BTreeMap<String, String>,
+    covered: &[&str],
+) -> Result<String, Refusal> {
+    let mut lines = Vec::new();
+    for &name in covered {
+        let value = match name {
+            "(request-target)" => format!("{} {}", head.method.to_ascii_lowercase(), head.path),
+            "(created)" | "(expires)" => {
+                let param = &name[1..name.len() - 1];
+                params
+                    .get(param)
+                    .cloned()
+                    .ok_or_else(|| Refusal::unsigned(format!("the signature has no {param}")))?
+            }
+            name => head
+                .header(name)
+                .ok_or_else(|| Refusal::unsigned(format!("the signed header {name} is missing")))?
+                .to_string(),
+        };
+        lines.push(format!("{name}: {value}"));
+    }
+    Ok(lines.join("\n"))
+}
+
+// The parameters of a `Signature` header, such as `keyId="…",signature="…"`.
+fn parameters(header: &str) -> BTreeMap<String, String> {
+    let mut params = BTreeMap::new();
+    let mut rest = header.trim();
+    while let Some((name, after)) = rest.split_once('=') {
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        params.insert(name.trim().to_string(), value.to_string());
+        rest = after.trim_start_matches([',', ' ']);
+    }
+    params
+}
+
+// The actor at `url`, over https. Debug builds also fetch over plain http from this machine,
+// for the instances tests run.
+async fn fetch(url: &Url) -> Result<Value, String> {
+    let loopback = match url.host() {
+        Some(Host::Domain(domain)) => domain == "localhost",
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    };
+    if url.scheme() != "https" && !(cfg!(debug_assertions) && loopback) {
+        return Err("actors are fetched over https".to_string());
+    }
+    let mut url = url.clone();
+    url.set_fragment(None);
+    let headers = [("Accept", CONTENT_TYPE.to_string())];
+    let response = http::send("GET", &url, &headers, None, FETCH_TIMEOUT).await?;
+    if !response.is_success() {
+        return Err(format!("{url} answered {}", response.status));
+    }
+    serde_json::from_slice(&response.body).map_err(|_| format!("{url} isn't JSON"))
+}
+
+/// The text of a note's HTML `content`: tags dropped, paragraphs and line breaks as new lines,
+/// and entities decoded.
+pub fn text_of(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&decode(&rest[..start]));
+        let Some(end) = rest[start..].find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = rest[start + 1..start + end].trim().to_ascii_lowercase();
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        if name == "br" || (name == "p" && tag.starts_with('/')) {
+            text.push('\n');
+        }
+        rest = &rest[start + end + 1..];
+    }
+    text.push_str(&decode(rest));
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    lines.join("\n")
+}
+
+// `text` with its character references decoded. Unknown ones are left as they are.
+fn decode(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let reference = after
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| Some((end, character(&after[..end])?)));
+        match reference {
+            Some((end, c)) => {
+                decoded.push(c);
+                rest = &after[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = after;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+// The character a reference such as `amp` or `#39` stands for.
+fn character(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        _ => {
+            let number = name.strip_prefix('#')?;
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+// `text` safe to put in HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// `der` as a PEM file labelled `label`.
+fn pem(label: &str, der: &[u8]) -> String {
+    let encoded = BASE64.encode(der);
+    let mut pem = format!("-----BEGIN {label}-----\n");
+    for line in encoded.as_bytes().chunks(64) {
+        pem.push_str(str::from_utf8(line).expect("base64 is ASCII"));
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {label}-----\n"));
+    pem
+}
+
+// The label and contents of the first PEM block in `text`.
+fn unpem(text: &str) -> Result<(String, Vec<u8>), String> {
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| !line.starts_with("-----BEGIN "));
+    let label = lines
+        .next()
+        .and_then(|line| line.strip_prefix("-----BEGIN ")?.strip_suffix("-----"))
+        .ok_or("not a PEM file")?;
+    let end = format!("-----END {label}-----");
+    let mut encoded = String::new();
+    for line in lines {
+        if line == end {
+            let der = BASE64
+                .decode(encoded.as_bytes())
+                .map_err(|_| format!("the {label} isn't base64"))?;
+            return Ok((label.to_string(), der));
+        }
+        encoded.push_str(line);
+    }
+    Err(format!("the {label} has no end"))
+}
+
+// A DER element: `tag`, the length of `contents`, and `contents`.
+fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        element.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|&byte| byte == 0)
+            .collect();
+        element.push(0x80 | bytes.len() as u8);
+        element.extend(bytes);
+    }
+    element.extend_from_slice(contents);
+    element
+}
+
+// The contents of the DER element with `tag` at the start of `input`, and what follows it.
+fn der_take(tag: u8, input: &[u8]) -> Result<(&[u8], &[u8]), String> {
+    let malformed = || "the key is malformed".to_string();
+    let (&first, rest) = input.split_first().ok_or_else(malformed)?;
+    let (&len, mut rest) = rest.split_first().ok_or_else(malformed)?;
+    if first != tag {
+        return Err(malformed());
+    }
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let bytes = (len & 0x7f) as usize;
+        if bytes == 0 || bytes > 4 || rest.len() < bytes {
+            return Err(malformed());
+        }
+        let (len, after) = rest.split_at(bytes);
+        rest = after;
+        len.iter().fold(0, |len, &byte| len << 8 | byte as usize)
+    };
+    if rest.len() < len {
+        return Err(malformed());
+    }
+    Ok(rest.split_at(len))
+}
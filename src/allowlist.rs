@@ -0,0 +1,32 @@
+// Peers allowed to connect, read from `--allowlist-file`.
+use std::{collections::HashSet, fs, path::Path};
+
+use libp2p::PeerId;
+
+use crate::error::ConfigError;
+
+/// Read an allowlist: one PeerId per line. Blank lines and lines starting with `#` are skipped.
+pub fn load(path: &Path) -> Result<HashSet<PeerId>, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse(&contents).map_err(|reason| ConfigError::Allowlist {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+/// Parse the contents of an allowlist file.
+pub fn parse(contents: &str) -> Result<HashSet<PeerId>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            line.parse()
+                .map_err(|_| format!("line {number}: invalid peer id {line:?}"))
+        })
+        .collect()
+}
@@ -0,0 +1,350 @@
+// Files attached to chat messages by reference: the message carries the content id, and peers
+// who want the file fetch it from the sender, chunk by chunk.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use data_encoding::BASE32_NOPAD;
+use libp2p::{
+    request_response::{self, ProtocolSupport},
+    PeerId, StreamProtocol,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::sanitize;
+
+/// Protocol name of the content exchange.
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p-chat/content/1");
+
+/// Largest file that can be attached.
+pub const MAX_ATTACHMENT_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Most bytes a fetch asks for at once. Responses are hex in JSON, and twice this has to fit
+/// in the 10 MiB a request-response answer may take.
+pub const CHUNK_BYTES: u64 = 256 * 1024;
+
+/// Most files kept to serve to peers; the oldest is forgotten beyond it.
+pub const MAX_SHARED_FILES: usize = 256;
+
+/// Most attachments seen in the room that are remembered for `/fetch`.
+pub const MAX_OFFERS: usize = 256;
+
+/// Longest file name and MIME type shown; longer ones are cut.
+pub const MAX_NAME_LEN: usize = 128;
+
+// CIDv1 header of raw content hashed with SHA-256: version 1, codec raw (0x55), multihash
+// sha2-256 (0x12) of 32 bytes
+const CID_HEADER: [u8; 4] = [0x01, 0x55, 0x12, 0x20];
+
+/// Request-response carrying fetches of content by id, answered with the content if the
+/// peer has it.
+pub type Behaviour = request_response::json::Behaviour<Fetch, Content>;
+
+/// Content id of some bytes: a CIDv1 of raw content hashed with SHA-256, written in base32
+/// like `bafkrei…`, as IPFS writes it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cid([u8; 32]);
+
+impl Cid {
+    /// The id of `data`.
+    pub fn of(data: &[u8]) -> Cid {
+        Cid(Sha256::digest(data).into())
+    }
+
+    /// The id of the file at `path`, read a piece at a time, and its size.
+    pub fn of_file(path: &Path) -> io::Result<(Cid, u64)> {
+        let mut file = fs::File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            match file.read(&mut buf)? {
+                0 => return Ok((Cid(hasher.finalize().into()), size)),
+                read => {
+                    hasher.update(&buf[..read]);
+                    size += read as u64;
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Cid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = CID_HEADER.to_vec();
+        bytes.extend_from_slice(&self.0);
+        write!(f, "b{}", BASE32_NOPAD.encode(&bytes).to_ascii_lowercase())
+    }
+}
+
+impl FromStr for Cid {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid content id {s:?}");
+        let encoded = s.strip_prefix('b').ok_or_else(invalid)?;
+        let bytes = BASE32_NOPAD
+            .decode(encoded.to_ascii_uppercase().as_bytes())
+            .map_err(|_| invalid())?;
+        let digest = bytes.strip_prefix(&CID_HEADER[..]).ok_or_else(invalid)?;
+        digest.try_into().map(Cid).map_err(|_| invalid())
+    }
+}
+
+impl TryFrom<String> for Cid {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cid> for String {
+    fn from(cid: Cid) -> Self {
+        cid.to_string()
+    }
+}
+
+/// A file attached to a chat message, which peers fetch from the sender by its id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentRef {
+    pub cid: Cid,
+    pub size: u64,
+    pub mime_type: String,
+    /// Name of the file, without the directories it was in.
+    pub filename: String,
+}
+
+impl AttachmentRef {
+    /// Read the file at `path` to attach it.
+    pub fn read(path: &Path) -> Result<AttachmentRef, String> {
+        let size = fs::metadata(path)
+            .map_err(|e| format!("can't read {}: {e}", path.display()))?
+            .len();
+        if size > MAX_ATTACHMENT_BYTES {
+            return Err(format!(
+                "{} is {} bytes, over the limit of {MAX_ATTACHMENT_BYTES}",
+                path.display(),
+                size
+            ));
+        }
+        let (cid, size) =
+            Cid::of_file(path).map_err(|e| format!("can't read {}: {e}", path.display()))?;
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(AttachmentRef {
+            cid,
+            size,
+            mime_type: mime_type(path).to_string(),
+            filename,
+        })
+    }
+
+    /// The reference fit to show: a file name and MIME type without paths, control characters
+    /// or more than [`MAX_NAME_LEN`] characters.
+    pub fn sanitized(mut self) -> AttachmentRef {
+        let name: String = self.filename.replace(['/', '\\'], "_");
+        self.filename = sanitize::line(&name)
+            .trim()
+            .chars()
+            .take(MAX_NAME_LEN)
+            .collect();
+        if self.filename.is_empty() || self.filename.chars().all(|c| c == '.') {
+            self.filename = "attachment".to_string();
+        }
+        self.mime_type = sanitize::line(&self.mime_type)
+            .chars()
+            .take(MAX_NAME_LEN)
+            .collect();
+        self
+    }
+}
+
+/// Shown after the body of a message, like `[📎 notes.pdf (1.2 MiB)]`.
+impl fmt::Display for AttachmentRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[📎 {} ({})]", self.filename, format_size(self.size))
+    }
+}
+
+/// A request for `len` bytes from `offset` of the content with the given id. Peers answer
+/// with at most [`CHUNK_BYTES`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fetch {
+    pub cid: Cid,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// The answer to a [`Fetch`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Content {
+    /// The bytes asked for, fewer only where the content ends.
+    Found(#[serde(with = "hex")] Vec<u8>),
+    /// The peer doesn't have the content, or no longer.
+    Missing,
+}
+
+/// The content behaviour, answering and fetching on [`PROTOCOL`].
+pub fn behaviour() -> Behaviour {
+    Behaviour::new(
+        [(PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+/// Files kept by id, to answer fetches: our own attachments and the ones we fetched. Only
+/// their paths are kept, and chunks are read from the files when asked for.
+#[derive(Debug, Default)]
+pub struct ContentStore {
+    files: HashMap<Cid, (PathBuf, u64)>,
+    order: VecDeque<Cid>,
+}
+
+impl ContentStore {
+    /// Serve the file at `path`, `size` bytes long, as the content with id `cid`, forgetting
+    /// the oldest file beyond [`MAX_SHARED_FILES`].
+    pub fn insert(&mut self, cid: Cid, path: PathBuf, size: u64) {
+        if self.files.insert(cid, (path, size)).is_some() {
+            return;
+        }
+        self.order.push_back(cid);
+        if self.order.len() > MAX_SHARED_FILES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.files.remove(&oldest);
+            }
+        }
+    }
+
+    /// The file holding the content with id `cid`.
+    pub fn path(&self, cid: &Cid) -> Option<&Path> {
+        self.files.get(cid).map(|(path, _)| path.as_path())
+    }
+
+    /// Up to [`CHUNK_BYTES`] of the content with id `cid` from `offset`. `None` if it isn't
+    /// kept, or its file changed size since.
+    pub fn read(&self, cid: &Cid, offset: u64, len: u64) -> Option<io::Result<Vec<u8>>> {
+        let (path, size) = self.files.get(cid)?;
+        let read = || -> io::Result<Option<Vec<u8>>> {
+            let mut file = fs::File::open(path)?;
+            if file.metadata()?.len() != *size {
+                return Ok(None);
+            }
+            let len = len.min(CHUNK_BYTES).min(size.saturating_sub(offset));
+            let mut data = vec![0; len as usize];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut data)?;
+            Ok(Some(data))
+        };
+        read().transpose()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+/// Attachments seen in the room, and who sent them, for `/fetch`. The oldest are forgotten
+/// beyond [`MAX_OFFERS`].
+#[derive(Debug, Default)]
+pub struct Offers {
+    offers: VecDeque<(PeerId, AttachmentRef)>,
+}
+
+impl Offers {
+    /// Remember that `sender` attached `attachment` to a message.
+    pub fn offer(&mut self, sender: PeerId, attachment: AttachmentRef) {
+        self.offers
+            .retain(|(peer, offered)| !(*peer == sender && offered.cid == attachment.cid));
+        if self.offers.len() >= MAX_OFFERS {
+            self.offers.pop_front();
+        }
+        self.offers.push_back((sender, attachment));
+    }
+
+    /// The latest offer of the content with id `cid`.
+    pub fn find(&self, cid: &Cid) -> Option<&(PeerId, AttachmentRef)> {
+        self.offers
+            .iter()
+            .rev()
+            .find(|(_, offered)| offered.cid == *cid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.offers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offers.is_empty()
+    }
+}
+
+/// The MIME type of a file, guessed from its extension.
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+/// The MIME type of some bytes, guessed from how they start, for content that came without a
+/// file name.
+pub fn sniff_mime_type(data: &[u8]) -> &'static str {
+    const SIGNATURES: [(&[u8], &str); 7] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"ID3", "audio/mpeg"),
+    ];
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        return "image/webp";
+    }
+    SIGNATURES
+        .iter()
+        .find(|(signature, _)| data.starts_with(signature))
+        .map_or("application/octet-stream", |(_, mime_type)| mime_type)
+}
+
+/// A size in bytes as shown to people, like `512 B` or `1.2 MiB`.
+pub fn format_size(bytes: u64) -> String {
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    match bytes {
+        bytes if bytes >= MIB => format!("{:.1} MiB", bytes as f64 / MIB as f64),
+        bytes if bytes >= KIB => format!("{:.1} KiB", bytes as f64 / KIB as f64),
+        bytes => format!("{bytes} B"),
+    }
+}
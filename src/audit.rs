@@ -0,0 +1,271 @@
+// An append-only record of the security-relevant things the node did, and why.
+use std::{
+    fmt, fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    autoban::Violation,
+    blocklist::{BlockAction, BlocklistUpdate},
+    clock,
+    error::ConfigError,
+    room::{ModAction, Moderation},
+};
+
+/// Name of the audit log, kept next to the config file.
+pub const AUDIT_FILE: &str = "audit.jsonl";
+
+/// Entries `/audit tail` shows without a count.
+pub const DEFAULT_TAIL: usize = 20;
+
+/// One line of the audit log.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Unix time (seconds) at which it happened.
+    pub timestamp: u64,
+    /// The peer that acted: the local node for its own decisions, otherwise the remote peer
+    /// whose message was acted on.
+    pub actor: PeerId,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// What happened.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// A peer was banned automatically.
+    AutoBan {
+        peer: PeerId,
+        violation: Violation,
+        offense: u32,
+        expires_at: u64,
+    },
+    /// A blocklist update shared by `author` was applied.
+    BlocklistApplied {
+        author: PeerId,
+        entry: u64,
+        action: BlockAction,
+        target: PeerId,
+        reason: String,
+    },
+    /// A moderator's kick or room ban was honored.
+    Moderation {
+        action: ModAction,
+        room: String,
+        target: PeerId,
+        reason: String,
+    },
+    /// The user confirmed a peer's fingerprint.
+    Verified { peer: PeerId, nick: String },
+    /// The user forgot a verification.
+    Unverified { peer: PeerId },
+    /// A peer proved that it moved from `old` to `new`.
+    KeyRotation { old: PeerId, new: PeerId },
+    /// The user issued an invite.
+    InviteCreated {
+        room: String,
+        invitee: Option<PeerId>,
+        expires_at: u64,
+    },
+    /// A moderator's kick or ban dealt with a report a member had sent.
+    ReportActedOn {
+        action: ModAction,
+        room: String,
+        target: PeerId,
+        reporter: PeerId,
+        message_id: String,
+        reason: String,
+    },
+    /// A peer was admitted to a room with an invite, or the node joined one with `--join-with`.
+    InviteUsed { room: String },
+}
+
+impl AuditEvent {
+    pub fn blocklist_applied(author: PeerId, entry: u64, update: &BlocklistUpdate) -> Self {
+        AuditEvent::BlocklistApplied {
+            author,
+            entry,
+            action: update.action,
+            target: update.target,
+            reason: update.reason.clone(),
+        }
+    }
+
+    pub fn moderation(moderation: &Moderation) -> Self {
+        AuditEvent::Moderation {
+            action: moderation.action,
+            room: moderation.room.clone(),
+            target: moderation.target,
+            reason: moderation.reason.clone(),
+        }
+    }
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let actor = self.actor;
+        write!(f, "{} {actor}: ", self.timestamp)?;
+        match &self.event {
+            AuditEvent::AutoBan {
+                peer,
+                violation,
+                offense,
+                expires_at,
+            } => write!(
+                f,
+                "banned {peer} until {expires_at} (offense #{offense}, {violation})"
+            ),
+            AuditEvent::BlocklistApplied {
+                author,
+                entry,
+                action,
+                target,
+                ..
+            } => {
+                let verb = match action {
+                    BlockAction::Add => "block",
+                    BlockAction::Remove => "unblock",
+                };
+                write!(
+                    f,
+                    "applied blocklist #{entry} from {author}: {verb} {target}"
+                )
+            }
+            AuditEvent::Moderation {
+                action,
+                room,
+                target,
+                ..
+            } => {
+                let verb = match action {
+                    ModAction::Kick => "kicked",
+                    ModAction::RoomBan => "banned",
+                };
+                write!(f, "{verb} {target} from {room}")
+            }
+            AuditEvent::Verified { peer, nick } => write!(f, "verified {peer} ({nick})"),
+            AuditEvent::Unverified { peer } => write!(f, "unverified {peer}"),
+            AuditEvent::KeyRotation { old, new } => write!(f, "rotated key {old} -> {new}"),
+            AuditEvent::InviteCreated {
+                room,
+                invitee,
+                expires_at,
+            } => match invitee {
+                Some(invitee) => write!(f, "invited {invitee} to {room}, valid until {expires_at}"),
+                None => write!(f, "created an invite to {room}, valid until {expires_at}"),
+            },
+            AuditEvent::ReportActedOn {
+                action,
+                room,
+                target,
+                reporter,
+                message_id,
+                ..
+            } => {
+                let verb = match action {
+                    ModAction::Kick => "kicked",
+                    ModAction::RoomBan => "banned",
+                };
+                write!(
+                    f,
+                    "{verb} {target} from {room} on {reporter}'s report of message {message_id}"
+                )
+            }
+            AuditEvent::InviteUsed { room } => write!(f, "joined {room} with an invite"),
+        }
+    }
+}
+
+/// Appends entries to the audit log, one JSON object per line.
+///
+/// Every entry is written with a single append and synced to disk before `record` returns, so
+/// a crash can at worst cut off the last line. [`AuditLog::tail`] skips such a line, and the
+/// next entry starts on a line of its own.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    path: Option<PathBuf>,
+}
+
+impl AuditLog {
+    /// A log at `path`, or one that records nothing without a path.
+    pub fn new(path: Option<PathBuf>) -> Self {
+        AuditLog { path }
+    }
+
+    /// The log next to the config file at `config_path`.
+    pub fn beside(config_path: Option<&Path>) -> Self {
+        AuditLog::new(config_path.map(|path| path.with_file_name(AUDIT_FILE)))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Append an entry stamped with the current time.
+    pub fn record(&self, actor: PeerId, event: AuditEvent) -> Result<(), ConfigError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let entry = AuditEntry {
+            timestamp: clock::unix_time(),
+            actor,
+            event,
+        };
+        let mut line = serde_json::to_vec(&entry).expect("audit entries always serialize");
+        line.push(b'\n');
+        append(path, &line).map_err(|source| ConfigError::Write {
+            path: path.clone(),
+            source,
+        })
+    }
+
+    /// The last `n` complete entries, oldest first.
+    pub fn tail(&self, n: usize) -> Result<Vec<AuditEntry>, ConfigError> {
+        let Some(path) = &self.path else {
+            return Ok(Vec::new());
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.clone(),
+                    source,
+                })
+            }
+        };
+        let entries: Vec<AuditEntry> = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        Ok(entries[entries.len().saturating_sub(n)..].to_vec())
+    }
+}
+
+fn append(path: &Path, line: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    // End a line cut off by a crash, so it doesn't swallow this entry
+    let mut last = [b'\n'];
+    if file.metadata()?.len() > 0 {
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+    }
+    if last[0] == b'\n' {
+        file.write_all(line)?;
+    } else {
+        file.write_all(&[b"\n", line].concat())?;
+    }
+    file.sync_data()
+}
@@ -0,0 +1,382 @@
+// Custom authentication of peers: a challenge sent when a connection is established, whose
+// answer decides whether the peer may stay.
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    futures::{future::BoxFuture, task, AsyncRead, AsyncWrite, FutureExt},
+    request_response::{self, ProtocolSupport, ResponseChannel},
+    PeerId, StreamProtocol,
+};
+
+use crate::error::AuthError;
+
+/// Protocol name of the challenge exchange.
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p-chat/auth/1");
+
+/// How long a peer has to pass the challenge before it is disconnected.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request-response carrying a challenge and its answer as JSON.
+pub type Behaviour = request_response::json::Behaviour<Vec<u8>, Vec<u8>>;
+
+/// The authentication behaviour, challenging and answering on [`PROTOCOL`].
+pub fn behaviour() -> Behaviour {
+    Behaviour::new(
+        [(PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default().with_request_timeout(AUTH_TIMEOUT),
+    )
+}
+
+/// A stream both read and written by an authenticator.
+pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncReadWrite for T {}
+
+/// A custom check a peer must pass after connecting, before its messages are accepted and, if
+/// found by mDNS, before it becomes an explicit Gossipsub peer. Set one with
+/// [`ChatNode::set_authenticator`](crate::chat::ChatNode::set_authenticator).
+///
+/// The exchange is one round trip: what [`authenticate`](Self::authenticate) writes before it
+/// first reads is sent to the peer as the challenge, the peer's
+/// [`respond`](Self::respond) reads it and everything that writes comes back as the answer.
+pub trait ConnectionAuthenticator: Send + Sync + 'static {
+    /// Challenge `peer_id`: write a challenge to `stream`, read the answer and check it.
+    fn authenticate(
+        &self,
+        peer_id: PeerId,
+        stream: &mut impl AsyncReadWrite,
+    ) -> impl Future<Output = Result<(), AuthError>> + Send;
+
+    /// Answer the challenge of `peer_id`: read it from `stream` and write the answer.
+    fn respond(
+        &self,
+        peer_id: PeerId,
+        stream: &mut impl AsyncReadWrite,
+    ) -> impl Future<Output = Result<(), AuthError>> + Send;
+}
+
+/// Which side of the exchange an authenticator runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Challenge,
+    Respond,
+}
+
+type AuthResult = Result<(), AuthError>;
+
+type Run = dyn Fn(Role, PeerId, Exchange) -> BoxFuture<'static, AuthResult> + Send + Sync;
+
+/// A [`ConnectionAuthenticator`] as the node holds it.
+#[derive(Clone)]
+pub struct Authenticator(Arc<Run>);
+
+impl Authenticator {
+    pub fn new(authenticator: impl ConnectionAuthenticator) -> Self {
+        let authenticator = Arc::new(authenticator);
+        Authenticator(Arc::new(move |role, peer, mut exchange| {
+            let authenticator = authenticator.clone();
+            async move {
+                match role {
+                    Role::Challenge => authenticator.authenticate(peer, &mut exchange).await,
+                    Role::Respond => authenticator.respond(peer, &mut exchange).await,
+                }
+            }
+            .boxed()
+        }))
+    }
+
+    fn run(&self, role: Role, peer: PeerId, exchange: Exchange) -> BoxFuture<'static, AuthResult> {
+        (self.0)(role, peer, exchange)
+    }
+}
+
+impl std::fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Authenticator")
+    }
+}
+
+/// The stream an authenticator runs on: writes are collected to go to the peer in one message,
+/// reads return the message from the peer, then end.
+#[derive(Debug, Clone, Default)]
+pub struct Exchange(Arc<Mutex<Buffers>>);
+
+#[derive(Debug, Default)]
+struct Buffers {
+    incoming: Vec<u8>,
+    read: usize,
+    // Whether the peer's message arrived, so reads past it end the stream
+    complete: bool,
+    // Whether a read is waiting for the peer's message
+    waiting: bool,
+    outgoing: Vec<u8>,
+}
+
+impl Exchange {
+    /// An exchange whose peer's message is already in: `incoming`.
+    pub fn with_incoming(incoming: Vec<u8>) -> Self {
+        let exchange = Exchange::default();
+        exchange.deliver(incoming);
+        exchange
+    }
+
+    /// Hand over the peer's message.
+    pub fn deliver(&self, incoming: Vec<u8>) {
+        let mut buffers = self.buffers();
+        buffers.incoming = incoming;
+        buffers.read = 0;
+        buffers.complete = true;
+        buffers.waiting = false;
+    }
+
+    /// Whether the authenticator waits for the peer's message.
+    pub fn is_waiting(&self) -> bool {
+        self.buffers().waiting
+    }
+
+    /// What the authenticator wrote since this was last called.
+    pub fn take_outgoing(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffers().outgoing)
+    }
+
+    fn buffers(&self) -> std::sync::MutexGuard<'_, Buffers> {
+        self.0.lock().expect("exchange buffers are never poisoned")
+    }
+}
+
+// Nothing wakes a waiting read: the node polls again when the peer's message arrives.
+impl AsyncRead for Exchange {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut buffers = self.buffers();
+        let available = &buffers.incoming[buffers.read..];
+        if available.is_empty() && !buffers.complete {
+            buffers.waiting = true;
+            return Poll::Pending;
+        }
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        buffers.read += n;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for Exchange {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.buffers().outgoing.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Where our challenge to a peer stands.
+#[derive(Debug)]
+pub enum Progress {
+    /// Nothing to do until the answer arrives or the authenticator is done.
+    Waiting,
+    /// Send this challenge to the peer.
+    Send(Vec<u8>),
+    Passed,
+    Failed(AuthError),
+}
+
+/// An answer to a peer's challenge that is done: the peer, the channel to answer on and the
+/// answer, or why there is none.
+pub type Answered = (PeerId, ResponseChannel<Vec<u8>>, Result<Vec<u8>, AuthError>);
+
+struct Attempt {
+    run: BoxFuture<'static, AuthResult>,
+    exchange: Exchange,
+    sent: bool,
+    deadline: Instant,
+}
+
+struct Answer {
+    peer: PeerId,
+    run: BoxFuture<'static, AuthResult>,
+    exchange: Exchange,
+    channel: ResponseChannel<Vec<u8>>,
+    deadline: Instant,
+}
+
+/// Peers whose authentication isn't decided yet, those who passed it, and our answers to
+/// their challenges that are still being worked out.
+///
+/// Authenticators are polled whenever the exchange moves on, and on every tick for any that
+/// wait on something else.
+#[derive(Default)]
+pub struct PendingAuth {
+    challenges: HashMap<PeerId, Attempt>,
+    answers: Vec<Answer>,
+    authenticated: HashSet<PeerId>,
+    // Peers found by mDNS that become explicit Gossipsub peers once they pass
+    discovered: HashSet<PeerId>,
+}
+
+impl PendingAuth {
+    /// Start challenging `peer`, as of `now`.
+    pub fn challenge(&mut self, peer: PeerId, authenticator: &Authenticator, now: Instant) {
+        let exchange = Exchange::default();
+        self.authenticated.remove(&peer);
+        self.challenges.insert(
+            peer,
+            Attempt {
+                run: authenticator.run(Role::Challenge, peer, exchange.clone()),
+                exchange,
+                sent: false,
+                deadline: now + AUTH_TIMEOUT,
+            },
+        );
+    }
+
+    /// Move the challenge of `peer` on as far as it goes at `now`. A decided challenge is
+    /// forgotten, and the peer remembered as authenticated if it passed.
+    pub fn poll(&mut self, peer: &PeerId, now: Instant) -> Progress {
+        let Some(attempt) = self.challenges.get_mut(peer) else {
+            return Progress::Waiting;
+        };
+        let outcome = match poll_once(&mut attempt.run) {
+            Poll::Ready(outcome) => outcome,
+            Poll::Pending if now >= attempt.deadline => Err(AuthError::TimedOut(AUTH_TIMEOUT)),
+            Poll::Pending if !attempt.sent && attempt.exchange.is_waiting() => {
+                attempt.sent = true;
+                return Progress::Send(attempt.exchange.take_outgoing());
+            }
+            Poll::Pending => return Progress::Waiting,
+        };
+        self.challenges.remove(peer);
+        match outcome {
+            Ok(()) => {
+                self.authenticated.insert(*peer);
+                Progress::Passed
+            }
+            Err(e) => Progress::Failed(e),
+        }
+    }
+
+    /// Hand the answer of `peer` to its challenge.
+    pub fn answered(&mut self, peer: &PeerId, answer: Vec<u8>) {
+        if let Some(attempt) = self.challenges.get(peer) {
+            attempt.exchange.deliver(answer);
+        }
+    }
+
+    /// The challenge of `peer` failed to get an answer; returns whether one was pending.
+    pub fn unanswered(&mut self, peer: &PeerId) -> bool {
+        self.challenges.remove(peer).is_some()
+    }
+
+    /// Start answering the `challenge` of `peer`, as of `now`.
+    pub fn respond(
+        &mut self,
+        peer: PeerId,
+        challenge: Vec<u8>,
+        channel: ResponseChannel<Vec<u8>>,
+        authenticator: &Authenticator,
+        now: Instant,
+    ) {
+        let exchange = Exchange::with_incoming(challenge);
+        self.answers.push(Answer {
+            peer,
+            run: authenticator.run(Role::Respond, peer, exchange.clone()),
+            exchange,
+            channel,
+            deadline: now + AUTH_TIMEOUT,
+        });
+    }
+
+    /// Our answers that are done at `now`: what to send back on each channel, or why not.
+    pub fn poll_answers(&mut self, now: Instant) -> Vec<Answered> {
+        let mut done = Vec::new();
+        let mut waiting = Vec::new();
+        for mut answer in self.answers.drain(..) {
+            let outcome = match poll_once(&mut answer.run) {
+                Poll::Ready(outcome) => outcome,
+                Poll::Pending if now >= answer.deadline => Err(AuthError::TimedOut(AUTH_TIMEOUT)),
+                Poll::Pending => {
+                    waiting.push(answer);
+                    continue;
+                }
+            };
+            let answered = outcome.map(|()| answer.exchange.take_outgoing());
+            done.push((answer.peer, answer.channel, answered));
+        }
+        self.answers = waiting;
+        done
+    }
+
+    /// The peers whose challenge is still undecided.
+    pub fn pending(&self) -> Vec<PeerId> {
+        self.challenges.keys().copied().collect()
+    }
+
+    /// Whether `peer` was challenged and hasn't passed or failed yet.
+    pub fn is_pending(&self, peer: &PeerId) -> bool {
+        self.challenges.contains_key(peer)
+    }
+
+    /// Whether `peer` passed its challenge on the connection it still has.
+    pub fn is_authenticated(&self, peer: &PeerId) -> bool {
+        self.authenticated.contains(peer)
+    }
+
+    /// Remember that mDNS found `peer`, to make it an explicit peer once it passes.
+    pub fn discovered(&mut self, peer: PeerId) {
+        self.discovered.insert(peer);
+    }
+
+    /// Forget that mDNS found `peer`, its announcement having expired.
+    pub fn expired(&mut self, peer: &PeerId) {
+        self.discovered.remove(peer);
+    }
+
+    /// Whether mDNS found `peer`.
+    pub fn was_discovered(&self, peer: &PeerId) -> bool {
+        self.discovered.contains(peer)
+    }
+
+    /// Forget everything about `peer` but its discovery, its last connection having closed.
+    pub fn disconnected(&mut self, peer: &PeerId) {
+        self.challenges.remove(peer);
+        self.authenticated.remove(peer);
+        self.answers.retain(|answer| answer.peer != *peer);
+    }
+}
+
+impl std::fmt::Debug for PendingAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PendingAuth")
+            .field("pending", &self.challenges.len())
+            .field("answering", &self.answers.len())
+            .field("authenticated", &self.authenticated)
+            .finish()
+    }
+}
+
+// Authenticators only wait on the exchange or on what the node polls them again for, so nothing
+// needs waking.
+fn poll_once(run: &mut BoxFuture<'static, AuthResult>) -> Poll<AuthResult> {
+    run.poll_unpin(&mut Context::from_waker(task::noop_waker_ref()))
+}
@@ -0,0 +1,217 @@
+// Temporary bans imposed automatically on peers that flood us or keep sending invalid messages.
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// Window (seconds) over which inbound messages are counted against the rate limit.
+pub const RATE_WINDOW: u64 = 10;
+
+/// Window (seconds) over which invalid messages and hidden repeats are counted.
+pub const INVALID_WINDOW: u64 = 60;
+
+/// Maximum number of peers whose recent activity is tracked at once.
+pub const MAX_TRACKED_PEERS: usize = 4096;
+
+// Repeat offenses double the ban, up to 2^MAX_DOUBLINGS times the base duration.
+const MAX_DOUBLINGS: u32 = 10;
+
+/// Thresholds that trigger a ban and how long it lasts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoBanSettings {
+    /// Messages allowed per peer within [`RATE_WINDOW`].
+    pub rate_limit: usize,
+    /// Invalid messages tolerated per peer within [`INVALID_WINDOW`].
+    pub invalid_limit: usize,
+    /// Length (seconds) of a first ban. Every further offense doubles it.
+    pub ban_duration: u64,
+}
+
+/// What a peer did to get banned.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Violation {
+    /// Sent more messages than the rate limit allows.
+    RateLimit,
+    /// Sent too many messages that failed validation.
+    InvalidMessages,
+    /// Kept repeating a message after copies of it were being hidden.
+    Repetition,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::RateLimit => write!(f, "exceeded the rate limit"),
+            Violation::InvalidMessages => write!(f, "sent too many invalid messages"),
+            Violation::Repetition => write!(f, "kept repeating the same message"),
+        }
+    }
+}
+
+/// An active temporary ban, persisted so that a restart doesn't lift it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TempBan {
+    pub peer: PeerId,
+    pub violation: Violation,
+    /// How many times the peer has been banned, this ban included.
+    pub offense: u32,
+    /// Unix time (seconds) at which the ban started.
+    pub started_at: u64,
+    /// Unix time (seconds) at which the ban is lifted.
+    pub expires_at: u64,
+}
+
+// Recent activity of one peer.
+#[derive(Debug, Default)]
+struct PeerRecord {
+    messages: VecDeque<u64>,
+    invalid: VecDeque<u64>,
+    repeats: VecDeque<u64>,
+    offenses: u32,
+}
+
+impl PeerRecord {
+    // Nothing recent to count and no offense to remember.
+    fn is_idle(&self, now: u64) -> bool {
+        self.offenses == 0
+            && self.messages.back().is_none_or(|&t| t + RATE_WINDOW <= now)
+            && self
+                .invalid
+                .back()
+                .is_none_or(|&t| t + INVALID_WINDOW <= now)
+            && self
+                .repeats
+                .back()
+                .is_none_or(|&t| t + INVALID_WINDOW <= now)
+    }
+}
+
+/// Tracks inbound activity per peer and bans the ones crossing a threshold.
+#[derive(Debug)]
+pub struct AutoBanner {
+    settings: AutoBanSettings,
+    peers: HashMap<PeerId, PeerRecord>,
+    bans: HashMap<PeerId, TempBan>,
+}
+
+impl AutoBanner {
+    pub fn new(settings: AutoBanSettings) -> Self {
+        AutoBanner {
+            settings,
+            peers: HashMap::new(),
+            bans: HashMap::new(),
+        }
+    }
+
+    /// Reinstate bans loaded from disk, dropping the ones that expired in the meantime.
+    pub fn restore(&mut self, bans: impl IntoIterator<Item = TempBan>, now: u64) {
+        for ban in bans.into_iter().filter(|ban| ban.expires_at > now) {
+            self.peers.entry(ban.peer).or_default().offenses = ban.offense;
+            self.bans.insert(ban.peer, ban);
+        }
+    }
+
+    /// Whether `peer` is currently banned.
+    pub fn is_banned(&self, peer: &PeerId) -> bool {
+        self.bans.contains_key(peer)
+    }
+
+    /// Active bans.
+    pub fn bans(&self) -> impl Iterator<Item = &TempBan> {
+        self.bans.values()
+    }
+
+    /// Count a message received from `peer`, returning the ban it triggered, if any.
+    pub fn record_message(&mut self, peer: PeerId, now: u64) -> Option<TempBan> {
+        let limit = self.settings.rate_limit;
+        self.record(peer, now, Violation::RateLimit, limit, RATE_WINDOW)
+    }
+
+    /// Count an invalid message received from `peer`, returning the ban it triggered, if any.
+    pub fn record_invalid(&mut self, peer: PeerId, now: u64) -> Option<TempBan> {
+        let limit = self.settings.invalid_limit;
+        self.record(peer, now, Violation::InvalidMessages, limit, INVALID_WINDOW)
+    }
+
+    /// Count a message from `peer` hidden as a repeat. Repeats are tolerated up to the same
+    /// limit as invalid messages.
+    pub fn record_repeat(&mut self, peer: PeerId, now: u64) -> Option<TempBan> {
+        let limit = self.settings.invalid_limit;
+        self.record(peer, now, Violation::Repetition, limit, INVALID_WINDOW)
+    }
+
+    /// Lift a ban early. The offense still counts towards the length of the next one.
+    pub fn unban(&mut self, peer: &PeerId) -> Option<TempBan> {
+        self.bans.remove(peer)
+    }
+
+    /// Remove and return the bans that have run out.
+    pub fn expire(&mut self, now: u64) -> Vec<TempBan> {
+        let expired: Vec<PeerId> = self
+            .bans
+            .values()
+            .filter(|ban| ban.expires_at <= now)
+            .map(|ban| ban.peer)
+            .collect();
+        expired
+            .iter()
+            .filter_map(|peer| self.bans.remove(peer))
+            .collect()
+    }
+
+    fn record(
+        &mut self,
+        peer: PeerId,
+        now: u64,
+        violation: Violation,
+        limit: usize,
+        window: u64,
+    ) -> Option<TempBan> {
+        if self.is_banned(&peer) {
+            return None;
+        }
+        if !self.peers.contains_key(&peer) && self.peers.len() >= MAX_TRACKED_PEERS {
+            // Make room by forgetting peers that have been quiet and never offended
+            self.peers.retain(|_, record| !record.is_idle(now));
+            if self.peers.len() >= MAX_TRACKED_PEERS {
+                return None;
+            }
+        }
+
+        let record = self.peers.entry(peer).or_default();
+        let times = match violation {
+            Violation::RateLimit => &mut record.messages,
+            Violation::InvalidMessages => &mut record.invalid,
+            Violation::Repetition => &mut record.repeats,
+        };
+        while times.front().is_some_and(|&t| t + window <= now) {
+            times.pop_front();
+        }
+        times.push_back(now);
+        if times.len() <= limit {
+            return None;
+        }
+
+        record.messages.clear();
+        record.invalid.clear();
+        record.repeats.clear();
+        record.offenses += 1;
+        let duration = self
+            .settings
+            .ban_duration
+            .saturating_mul(1 << (record.offenses - 1).min(MAX_DOUBLINGS));
+        let ban = TempBan {
+            peer,
+            violation,
+            offense: record.offenses,
+            started_at: now,
+            expires_at: now.saturating_add(duration),
+        };
+        self.bans.insert(peer, ban.clone());
+        Some(ban)
+    }
+}
@@ -0,0 +1,207 @@
+// A cap on outbound bandwidth, for metered connections like mobile data (`--max-upload-kbps`).
+use std::{
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{Arc, Once, Weak},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use libp2p::futures::{AsyncRead, AsyncWrite};
+use tokio::sync::Semaphore;
+
+use crate::runtime;
+
+/// How often the bucket is topped up.
+pub const REFILL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Smallest burst allowed whatever the rate, so low caps still send whole small frames.
+pub const MIN_BURST: usize = 4096;
+
+/// A token bucket of bytes shared by every connection it throttles. Writes take tokens for
+/// their bytes and wait while the bucket is empty; a task refills it at the configured rate,
+/// up to a quarter of a second's worth.
+#[derive(Clone)]
+pub struct BandwidthLimiter {
+    bucket: Arc<Semaphore>,
+    bytes_per_second: u64,
+    burst: usize,
+    refill: Arc<Once>,
+}
+
+impl fmt::Debug for BandwidthLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BandwidthLimiter")
+            .field("bytes_per_second", &self.bytes_per_second)
+            .field("burst", &self.burst)
+            .finish()
+    }
+}
+
+impl BandwidthLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let burst = usize::try_from(bytes_per_second / 4)
+            .unwrap_or(usize::MAX)
+            .clamp(MIN_BURST, Semaphore::MAX_PERMITS);
+        BandwidthLimiter {
+            bucket: Arc::new(Semaphore::new(burst)),
+            bytes_per_second,
+            burst,
+            refill: Arc::new(Once::new()),
+        }
+    }
+
+    /// A limiter for a cap in kilobits per second, as `--max-upload-kbps` takes it.
+    pub fn from_kbps(kbps: u32) -> Self {
+        BandwidthLimiter::new(u64::from(kbps) * 1000 / 8)
+    }
+
+    /// The cap in bytes per second.
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Wait until `bytes` may be sent, or as many as a burst allows when they are more.
+    /// Returns how many may be sent; give back what wasn't with [`BandwidthLimiter::release`].
+    pub async fn acquire(&self, bytes: usize) -> usize {
+        self.start_refill();
+        let bytes = bytes.clamp(1, self.burst);
+        let permits = u32::try_from(bytes).unwrap_or(u32::MAX);
+        self.bucket
+            .acquire_many(permits)
+            .await
+            .expect("the bucket is never closed")
+            .forget();
+        permits as usize
+    }
+
+    /// Return tokens taken for bytes that weren't sent after all.
+    pub fn release(&self, bytes: usize) {
+        let room = self.burst.saturating_sub(self.bucket.available_permits());
+        self.bucket.add_permits(bytes.min(room));
+    }
+
+    // The refill task runs on the runtime of the first write, for as long as the limiter
+    // is in use.
+    fn start_refill(&self) {
+        self.refill.call_once(|| {
+            let bucket = Arc::downgrade(&self.bucket);
+            runtime::spawn(refill(bucket, self.bytes_per_second, self.burst));
+        });
+    }
+}
+
+async fn refill(bucket: Weak<Semaphore>, bytes_per_second: u64, burst: usize) {
+    let mut ticks = runtime::interval(REFILL_INTERVAL);
+    let mut last = Instant::now();
+    // Fractions of a byte earned but not yet added
+    let mut earned = 0.0;
+    loop {
+        ticks.tick().await;
+        let Some(bucket) = bucket.upgrade() else {
+            return;
+        };
+        let now = Instant::now();
+        earned += bytes_per_second as f64 * (now - last).as_secs_f64();
+        last = now;
+        let room = burst.saturating_sub(bucket.available_permits());
+        let whole = (earned as usize).min(room);
+        bucket.add_permits(whole);
+        earned = if whole == room {
+            0.0
+        } else {
+            earned - whole as f64
+        };
+    }
+}
+
+type Acquire = Pin<Box<dyn Future<Output = usize> + Send>>;
+
+// Tokens for the write in progress.
+enum Grant {
+    None,
+    Waiting(Acquire),
+    Granted(usize),
+}
+
+/// A byte stream whose writes wait for tokens from a [`BandwidthLimiter`], so a full bucket
+/// holds them back. Reads pass straight through. Without a limiter it passes everything
+/// through.
+///
+/// The wrapper sits below the security upgrade, so the cap counts every byte on the wire,
+/// whichever protocol sent it.
+pub struct ThrottledStream<S> {
+    inner: S,
+    limiter: Option<BandwidthLimiter>,
+    grant: Grant,
+}
+
+impl<S> ThrottledStream<S> {
+    pub fn new(inner: S, limiter: Option<BandwidthLimiter>) -> Self {
+        ThrottledStream {
+            inner,
+            limiter,
+            grant: Grant::None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ThrottledStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ThrottledStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let Some(limiter) = &this.limiter else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        loop {
+            match &mut this.grant {
+                Grant::None => {
+                    let limiter = limiter.clone();
+                    let bytes = buf.len();
+                    this.grant =
+                        Grant::Waiting(Box::pin(async move { limiter.acquire(bytes).await }));
+                }
+                Grant::Waiting(acquire) => {
+                    let granted = ready!(acquire.as_mut().poll(cx));
+                    this.grant = Grant::Granted(granted);
+                }
+                Grant::Granted(granted) => {
+                    let granted = *granted;
+                    let allowed = granted.min(buf.len());
+                    let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..allowed]));
+                    this.grant = Grant::None;
+                    let sent = *written.as_ref().unwrap_or(&0);
+                    limiter.release(granted - sent);
+                    return Poll::Ready(written);
+                }
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
@@ -0,0 +1,159 @@
+// Every block and ban the node enforces, in the one list saved in the config file.
+use std::fmt;
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::autoban::{TempBan, Violation};
+
+/// Where a ban applies.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BanScope {
+    /// Connections are refused and the peer's relayed messages ignored everywhere.
+    Global,
+    /// The peer's messages are ignored in this room only.
+    Room(String),
+}
+
+/// Who or what put a ban in place.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BanOrigin {
+    /// The local user, with `/block`.
+    Manual,
+    /// The automatic ban of a peer that crossed a threshold.
+    Auto { violation: Violation, offense: u32 },
+    /// A shared blocklist update from the trusted peer `via`.
+    Blocklist { via: PeerId },
+    /// A room ban by the moderator `by`.
+    Moderator { by: PeerId },
+}
+
+impl BanOrigin {
+    /// Heading of the origin's group in `/bans`.
+    pub fn group(&self) -> &'static str {
+        match self {
+            BanOrigin::Manual => "manual",
+            BanOrigin::Auto { .. } => "automatic",
+            BanOrigin::Blocklist { .. } => "shared blocklist",
+            BanOrigin::Moderator { .. } => "moderator",
+        }
+    }
+
+    // Manual and shared blocks are both blocklist entries, of which a peer has at most one
+    fn slot(&self) -> u8 {
+        match self {
+            BanOrigin::Manual | BanOrigin::Blocklist { .. } => 0,
+            BanOrigin::Auto { .. } => 1,
+            BanOrigin::Moderator { .. } => 2,
+        }
+    }
+}
+
+/// One block or ban.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BanRecord {
+    pub peer: PeerId,
+    pub scope: BanScope,
+    pub origin: BanOrigin,
+    /// Unix time (seconds) at which the ban was put in place.
+    pub created_at: u64,
+    /// Unix time (seconds) at which the ban is lifted, if ever.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub reason: String,
+}
+
+impl BanRecord {
+    /// The record of an automatic ban.
+    pub fn auto(ban: &TempBan) -> Self {
+        BanRecord {
+            peer: ban.peer,
+            scope: BanScope::Global,
+            origin: BanOrigin::Auto {
+                violation: ban.violation,
+                offense: ban.offense,
+            },
+            created_at: ban.started_at,
+            expires_at: Some(ban.expires_at),
+            reason: ban.violation.to_string(),
+        }
+    }
+
+    /// The automatic ban a record stands for, if it is one.
+    pub fn temp_ban(&self) -> Option<TempBan> {
+        match self.origin {
+            BanOrigin::Auto { violation, offense } => Some(TempBan {
+                peer: self.peer,
+                violation,
+                offense,
+                started_at: self.created_at,
+                expires_at: self.expires_at.unwrap_or(u64::MAX),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl fmt::Display for BanScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BanScope::Global => write!(f, "everywhere"),
+            BanScope::Room(room) => write!(f, "in {room}"),
+        }
+    }
+}
+
+/// The persisted blocks and bans. A peer has at most one record per scope and kind of origin.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct BanList {
+    records: Vec<BanRecord>,
+}
+
+impl BanList {
+    pub fn iter(&self) -> impl Iterator<Item = &BanRecord> {
+        self.records.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Add a record, replacing the one of the same peer, scope and kind of origin.
+    pub fn insert(&mut self, record: BanRecord) {
+        self.records.retain(|r| {
+            r.peer != record.peer
+                || r.scope != record.scope
+                || r.origin.slot() != record.origin.slot()
+        });
+        self.records.push(record);
+    }
+
+    /// Remove the records of `peer` that `matches` selects, returning them.
+    pub fn remove(
+        &mut self,
+        peer: &PeerId,
+        mut matches: impl FnMut(&BanRecord) -> bool,
+    ) -> Vec<BanRecord> {
+        self.remove_where(|r| r.peer == *peer && matches(r))
+    }
+
+    /// Remove every record `matches` selects, returning them.
+    pub fn remove_where(&mut self, mut matches: impl FnMut(&BanRecord) -> bool) -> Vec<BanRecord> {
+        let (removed, kept) = self.records.drain(..).partition(|r| matches(r));
+        self.records = kept;
+        removed
+    }
+
+    /// Remove and return the records that have run out.
+    pub fn expire(&mut self, now: u64) -> Vec<BanRecord> {
+        self.remove_where(|r| r.is_expired(now))
+    }
+}
@@ -0,0 +1,100 @@
+// Small chat messages sent together in one Gossipsub message, and taken apart on receipt.
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{fragment, message::ChatMessage};
+
+/// Encoded bytes at which a batch goes out before its window is up, unless configured.
+pub const DEFAULT_BATCH_BYTES: usize = 16 * 1024;
+
+/// Largest a batch may be configured to grow. Larger messages are fragmented instead.
+pub const MAX_BATCH_BYTES: usize = fragment::FRAGMENT_THRESHOLD;
+
+/// Most messages in one batch. Received batches holding more are ignored.
+pub const MAX_BATCH_MESSAGES: usize = 256;
+
+/// Several chat messages published as one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub batch: Vec<ChatMessage>,
+}
+
+impl Batch {
+    /// JSON encoding published on the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("batches always serialize")
+    }
+
+    /// Decode received data, if it is a batch of at most [`MAX_BATCH_MESSAGES`] messages rather
+    /// than a single message.
+    pub fn decode(data: &[u8]) -> Option<Batch> {
+        serde_json::from_slice(data)
+            .ok()
+            .filter(|batch: &Batch| !batch.batch.is_empty())
+            .filter(|batch| batch.batch.len() <= MAX_BATCH_MESSAGES)
+    }
+}
+
+/// Chat messages waiting to be published together, sent once the first of them has waited
+/// the window or they reach a size.
+#[derive(Debug)]
+pub struct Batcher {
+    window: Duration,
+    max_bytes: usize,
+    pending: Vec<ChatMessage>,
+    bytes: usize,
+    deadline: Option<Instant>,
+}
+
+impl Batcher {
+    /// Batch messages for up to `window`, or until they add up to `max_bytes` encoded, capped
+    /// at [`MAX_BATCH_BYTES`].
+    pub fn new(window: Duration, max_bytes: usize) -> Self {
+        Batcher {
+            window,
+            max_bytes: max_bytes.min(MAX_BATCH_BYTES),
+            pending: Vec::new(),
+            bytes: 0,
+            deadline: None,
+        }
+    }
+
+    /// Messages encoding to more than this are sent on their own.
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// When the waiting messages are due to go out, if there are any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Add a message, returning the messages to publish right away: those that waited, if
+    /// this one wouldn't fit with them, or all of them once they are full.
+    pub fn push(&mut self, message: ChatMessage, now: Instant) -> Option<Batch> {
+        let bytes = message.encoded_len() + 1;
+        let flushed = if self.bytes + bytes > self.max_bytes {
+            self.take()
+        } else {
+            None
+        };
+        self.deadline.get_or_insert(now + self.window);
+        self.pending.push(message);
+        self.bytes += bytes;
+        if flushed.is_none()
+            && (self.bytes >= self.max_bytes || self.pending.len() >= MAX_BATCH_MESSAGES)
+        {
+            return self.take();
+        }
+        flushed
+    }
+
+    /// Take every waiting message.
+    pub fn take(&mut self) -> Option<Batch> {
+        self.bytes = 0;
+        self.deadline = None;
+        let batch = std::mem::take(&mut self.pending);
+        (!batch.is_empty()).then_some(Batch { batch })
+    }
+}
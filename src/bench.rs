@@ -0,0 +1,325 @@
+// Throughput and latency of chat messages between nodes running in this process.
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs, io,
+    path::Path,
+    pin::pin,
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    futures::{stream, StreamExt},
+    swarm::SwarmEvent,
+    Multiaddr, PeerId,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    chat::{self, ChatNode},
+    cli::{BenchTransport, Cli},
+    config::Config,
+    error::ChatError,
+    filter::TopicFilter,
+    runtime, validator,
+};
+
+/// How long nodes get to connect and join each other's mesh before a run gives up.
+pub const SETUP_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How long a receiver waits for the next message before counting the rest as lost.
+pub const QUIET_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One benchmark run: `nodes - 1` receivers each get `messages` bodies of `payload` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchSpec {
+    pub transport: BenchTransport,
+    pub nodes: u64,
+    pub payload: usize,
+    pub messages: u64,
+}
+
+impl BenchSpec {
+    /// Messages the receivers should get between them.
+    pub fn expected(&self) -> u64 {
+        self.messages * (self.nodes - 1)
+    }
+}
+
+/// What a run measured.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub spec: BenchSpec,
+    /// End-to-end latency of every message delivered, shortest first.
+    pub latencies: Vec<Duration>,
+    /// From the first message going out to the last one arriving anywhere.
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    /// Messages the receivers got between them.
+    pub fn delivered(&self) -> u64 {
+        self.latencies.len() as u64
+    }
+
+    /// Messages delivered per second to each receiver.
+    pub fn rate(&self) -> f64 {
+        let receivers = (self.spec.nodes - 1) as f64;
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.delivered() as f64 / receivers / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// The latency `percent` of the messages arrived within, by nearest rank.
+    pub fn percentile(&self, percent: u32) -> Option<Duration> {
+        let rank = (self.latencies.len() * percent as usize).div_ceil(100);
+        self.latencies.get(rank.saturating_sub(1)).copied()
+    }
+}
+
+/// Run every spec in turn, keeping the nodes' config files in a scratch directory that is
+/// removed afterwards.
+pub async fn run_all(specs: &[BenchSpec]) -> Result<Vec<BenchResult>, ChatError> {
+    let dir = std::env::temp_dir().join(format!("p2p-chat-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    let mut results = Vec::with_capacity(specs.len());
+    for spec in specs {
+        match run(spec, &dir).await {
+            Ok(result) => results.push(result),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                return Err(e);
+            }
+        }
+    }
+    let _ = fs::remove_dir_all(&dir);
+    Ok(results)
+}
+
+/// Start `spec.nodes` chat nodes keeping their files in `dir`, have the receivers dial the
+/// first one, and publish `spec.messages` messages from it through its input loop.
+///
+/// Every message takes the path a typed line does: it is encoded, signed, published, then
+/// validated, decoded and stored by each receiver. Receivers hide the messages with a filter
+/// instead of printing them. Each body starts with its sequence number and the time it was
+/// sent, which the receivers read back to measure latency.
+pub async fn run(spec: &BenchSpec, dir: &Path) -> Result<BenchResult, ChatError> {
+    let mut sender = ChatNode::new(&node_cli(spec, dir, 0)?)?;
+    sender.swarm.listen_on(listen_address(spec.transport))?;
+    let address = runtime::timeout(SETUP_TIMEOUT, async {
+        loop {
+            match sender.swarm.select_next_some().await {
+                SwarmEvent::NewListenAddr { address, .. } => return address,
+                event => sender.handle_event(event),
+            }
+        }
+    })
+    .await
+    .map_err(|_| setup_timed_out("the first node never started listening"))?;
+
+    let epoch = Instant::now();
+    let (ready, mut readied) = mpsc::channel(spec.nodes as usize);
+    let mut receivers = Vec::new();
+    for n in 1..spec.nodes {
+        let node = ChatNode::new(&node_cli(spec, dir, n)?)?;
+        let receiver = receive(
+            node,
+            address.clone(),
+            sender.local_peer_id(),
+            spec.messages,
+            epoch,
+            ready.clone(),
+        );
+        receivers.push(runtime::spawn(receiver));
+    }
+    drop(ready);
+
+    // Wait for every receiver and the sender to have each other in their meshes
+    let topic = sender.topic().hash();
+    let setup = runtime::timeout(SETUP_TIMEOUT, async {
+        let mut waiting = spec.nodes - 1;
+        while waiting > 0
+            || (sender
+                .swarm
+                .behaviour()
+                .gossipsub
+                .mesh_peers(&topic)
+                .count() as u64)
+                < spec.nodes - 1
+        {
+            tokio::select! {
+                event = sender.swarm.select_next_some() => sender.handle_event(event),
+                Some(()) = readied.recv() => waiting -= 1,
+            }
+        }
+    })
+    .await;
+    if setup.is_err() {
+        receivers.iter().for_each(runtime::Task::abort);
+        return Err(setup_timed_out("the nodes never joined each other's mesh"));
+    }
+    runtime::sleep(chat::CONNECT_GRACE.saturating_sub(epoch.elapsed())).await;
+
+    // Bodies are made as the input loop takes them, so queueing there counts as latency
+    let payload = spec.payload;
+    let lines = stream::iter(0..spec.messages).map(move |seq| body(seq, epoch, payload));
+    let mut arrivals = Vec::new();
+    let receivers_done = async {
+        for receiver in receivers {
+            if let Some(received) = receiver.await {
+                arrivals.extend(received);
+            }
+        }
+    };
+    sender.run(lines, receivers_done).await;
+
+    let first_sent = arrivals.iter().map(|(sent, _)| *sent).min();
+    let last_arrived = arrivals.iter().map(|(_, arrived)| *arrived).max();
+    let elapsed = match (first_sent, last_arrived) {
+        (Some(sent), Some(arrived)) => arrived.saturating_sub(sent),
+        _ => Duration::ZERO,
+    };
+    let mut latencies: Vec<Duration> = arrivals
+        .iter()
+        .map(|(sent, arrived)| arrived.saturating_sub(*sent))
+        .collect();
+    latencies.sort_unstable();
+    Ok(BenchResult {
+        spec: *spec,
+        latencies,
+        elapsed,
+    })
+}
+
+/// The results as a table, one run per row.
+pub fn table(results: &[BenchResult]) -> String {
+    let mut table = format!(
+        "{:<9} {:>5} {:>9} {:>15} {:>10} {:>10} {:>10}\n",
+        "transport", "nodes", "payload", "delivered", "msg/s", "p50", "p99"
+    );
+    let millis = |latency: Option<Duration>| match latency {
+        Some(latency) => format!("{:.2} ms", latency.as_secs_f64() * 1000.0),
+        None => "-".to_string(),
+    };
+    for result in results {
+        let spec = &result.spec;
+        let transport = match spec.transport {
+            BenchTransport::Tcp => "tcp",
+            BenchTransport::Quic => "quic",
+            BenchTransport::Memory => "memory",
+        };
+        let _ = writeln!(
+            table,
+            "{transport:<9} {:>5} {:>9} {:>15} {:>10.0} {:>10} {:>10}",
+            spec.nodes,
+            format!("{} B", spec.payload),
+            format!("{}/{}", result.delivered(), spec.expected()),
+            result.rate(),
+            millis(result.percentile(50)),
+            millis(result.percentile(99)),
+        );
+    }
+    table
+}
+
+// Flags of one benchmark node: no discovery, no rate limit, and a config file hiding the
+// chat topic's messages from the terminal.
+fn node_cli(spec: &BenchSpec, dir: &Path, n: u64) -> Result<Cli, ChatError> {
+    let path = dir.join(format!("node-{n}.json"));
+    let hidden = TopicFilter {
+        min_timestamp: Some(u64::MAX),
+        ..TopicFilter::default()
+    };
+    let config = Config {
+        filters: HashMap::from([(crate::node::default_topic().hash().into_string(), hidden)]),
+        ..Config::default()
+    };
+    config.save(&path)?;
+    Ok(Cli {
+        nick: Some(format!("bench-{n}")),
+        config: Some(path),
+        no_mdns: true,
+        rate_limit: usize::MAX,
+        max_body: spec.payload.max(validator::MAX_BODY_BYTES),
+        ..Cli::default()
+    })
+}
+
+fn setup_timed_out(what: &str) -> ChatError {
+    let message = format!("{what} within {SETUP_TIMEOUT:?}");
+    io::Error::new(io::ErrorKind::TimedOut, message).into()
+}
+
+fn listen_address(transport: BenchTransport) -> Multiaddr {
+    let address = match transport {
+        BenchTransport::Tcp => "/ip4/127.0.0.1/tcp/0",
+        BenchTransport::Quic => "/ip4/127.0.0.1/udp/0/quic-v1",
+        BenchTransport::Memory => "/memory/0",
+    };
+    address.parse().expect("the listen addresses are valid")
+}
+
+// A body of `payload` bytes, or just its header if that is longer: the sequence number and
+// the time since `epoch` in microseconds.
+fn body(seq: u64, epoch: Instant, payload: usize) -> String {
+    let mut body = format!("{seq} {} ", epoch.elapsed().as_micros());
+    let padding = payload.saturating_sub(body.len());
+    body.extend(std::iter::repeat_n('x', padding));
+    body
+}
+
+// When a body was sent, relative to the epoch.
+fn sent_at(body: &str) -> Option<Duration> {
+    let micros = body.split(' ').nth(1)?.parse().ok()?;
+    Some(Duration::from_micros(micros))
+}
+
+// Dial the sender, report once it is in our mesh, then note when each message was sent and
+// when it arrived, relative to `epoch`, until all of them are in or the sender goes quiet.
+async fn receive(
+    mut node: ChatNode,
+    sender_address: Multiaddr,
+    sender: PeerId,
+    messages: u64,
+    epoch: Instant,
+    ready: mpsc::Sender<()>,
+) -> Vec<(Duration, Duration)> {
+    let mut arrivals = Vec::with_capacity(messages as usize);
+    if node.swarm.dial(sender_address).is_err() {
+        return arrivals;
+    }
+    let topic = node.topic().hash();
+    let mut ready = Some(ready);
+    let mut last_id = None;
+    while (arrivals.len() as u64) < messages {
+        let next = pin!(node.swarm.select_next_some());
+        let Ok(event) = runtime::timeout(QUIET_TIMEOUT, next).await else {
+            break;
+        };
+        node.handle_event(event);
+        let meshed = node
+            .swarm
+            .behaviour()
+            .gossipsub
+            .mesh_peers(&topic)
+            .any(|peer| *peer == sender);
+        if let Some(ready) = ready.take_if(|_| meshed) {
+            let _ = ready.send(()).await;
+        }
+        // The history only ever grows by the message just handled
+        let Some(stored) = node.history().last() else {
+            continue;
+        };
+        if last_id.as_ref() == Some(&stored.id) {
+            continue;
+        }
+        last_id = Some(stored.id.clone());
+        if let Some(sent) = sent_at(&stored.message.body) {
+            arrivals.push((sent, epoch.elapsed()));
+        }
+    }
+    // Leave quietly so the sender isn't left waiting on a dead connection
+    node.shutdown().await;
+    arrivals
+}
@@ -0,0 +1,299 @@
+// Local block list and blocklist updates shared between trusted peers.
+use std::collections::{HashMap, VecDeque};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of blocked peers, so a misbehaving trusted peer can't grow the list forever.
+pub const MAX_BLOCKED_PEERS: usize = 1024;
+
+/// Maximum number of received updates kept for review with `/blocklist show`.
+pub const MAX_TRACKED_UPDATES: usize = 256;
+
+/// Maximum number of (author, target) pairs remembered for replay protection.
+const MAX_SEEN_PAIRS: usize = 4 * MAX_BLOCKED_PEERS;
+
+/// Whether a shared update adds a block or lifts one.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockAction {
+    Add,
+    Remove,
+}
+
+/// A blocklist change published by a peer on the control topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlocklistUpdate {
+    pub action: BlockAction,
+    pub target: PeerId,
+    pub reason: String,
+    /// Unix time (seconds) at which the author made the change.
+    pub timestamp: u64,
+}
+
+/// Where a block came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockOrigin {
+    /// Blocked by the local user.
+    Manual,
+    /// Applied from the shared update `update`, published by the trusted peer `via`.
+    Shared { via: PeerId, update: u64 },
+}
+
+/// A blocked peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockEntry {
+    pub reason: String,
+    pub origin: BlockOrigin,
+    /// Unix time (seconds) at which the block was added locally.
+    pub added_at: u64,
+}
+
+/// Review state of a received update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateStatus {
+    /// Waiting for `/blocklist apply`.
+    Pending,
+    Applied,
+    Reverted,
+}
+
+/// An update received from a trusted peer, with everything needed to undo it.
+#[derive(Debug, Clone)]
+pub struct ReceivedUpdate {
+    pub id: u64,
+    pub author: PeerId,
+    pub update: BlocklistUpdate,
+    pub status: UpdateStatus,
+    // The entry that applying this update replaced or removed, restored on revert.
+    previous: Option<BlockEntry>,
+}
+
+/// What happened to a received update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateOutcome {
+    /// The author is not trusted, so the update was ignored.
+    Untrusted,
+    /// The update is our own, or not newer than one already seen from this author.
+    Stale,
+    /// The update targets the local node and was ignored.
+    TargetsSelf,
+    /// Too many peers are tracked already.
+    Full,
+    /// Recorded for review under this id.
+    Pending(u64),
+    /// Recorded under this id and applied immediately.
+    Applied(u64, Change),
+}
+
+/// A change the caller has to enforce on the network side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Blocked(PeerId),
+    Unblocked(PeerId),
+    Unchanged,
+}
+
+/// The blocklist has reached [`MAX_BLOCKED_PEERS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlocklistFull;
+
+/// Blocked peers together with the log of shared updates received from trusted peers.
+#[derive(Debug)]
+pub struct Blocklist {
+    local_peer: PeerId,
+    entries: HashMap<PeerId, BlockEntry>,
+    updates: VecDeque<ReceivedUpdate>,
+    // Newest timestamp seen per (author, target), so replays and loops are dropped.
+    latest: HashMap<(PeerId, PeerId), u64>,
+    next_id: u64,
+}
+
+impl Blocklist {
+    /// Create an empty blocklist for the node `local_peer`.
+    pub fn new(local_peer: PeerId) -> Self {
+        Blocklist {
+            local_peer,
+            entries: HashMap::new(),
+            updates: VecDeque::new(),
+            latest: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    /// Whether `peer` is currently blocked.
+    pub fn is_blocked(&self, peer: &PeerId) -> bool {
+        self.entries.contains_key(peer)
+    }
+
+    /// The block on `peer`, if any.
+    pub fn entry(&self, peer: &PeerId) -> Option<&BlockEntry> {
+        self.entries.get(peer)
+    }
+
+    /// All blocked peers.
+    pub fn entries(&self) -> impl Iterator<Item = (&PeerId, &BlockEntry)> {
+        self.entries.iter()
+    }
+
+    /// Received updates, oldest first.
+    pub fn updates(&self) -> impl Iterator<Item = &ReceivedUpdate> {
+        self.updates.iter()
+    }
+
+    /// Block `peer`, replacing any existing entry for it.
+    pub fn block(
+        &mut self,
+        peer: PeerId,
+        reason: String,
+        origin: BlockOrigin,
+        now: u64,
+    ) -> Result<Option<BlockEntry>, BlocklistFull> {
+        if !self.entries.contains_key(&peer) && self.entries.len() >= MAX_BLOCKED_PEERS {
+            return Err(BlocklistFull);
+        }
+        let entry = BlockEntry {
+            reason,
+            origin,
+            added_at: now,
+        };
+        Ok(self.entries.insert(peer, entry))
+    }
+
+    /// Lift the block on `peer`, returning the removed entry.
+    pub fn unblock(&mut self, peer: &PeerId) -> Option<BlockEntry> {
+        self.entries.remove(peer)
+    }
+
+    /// Record an update published by `author`, applying it right away when `auto_apply` is set.
+    pub fn receive(
+        &mut self,
+        author: PeerId,
+        update: BlocklistUpdate,
+        trusted: bool,
+        auto_apply: bool,
+        now: u64,
+    ) -> UpdateOutcome {
+        if !trusted {
+            return UpdateOutcome::Untrusted;
+        }
+        if update.target == self.local_peer {
+            return UpdateOutcome::TargetsSelf;
+        }
+
+        // Our own updates come back to us through gossip; updates must also move forward in time.
+        let key = (author, update.target);
+        if author == self.local_peer || self.latest.get(&key) >= Some(&update.timestamp) {
+            return UpdateOutcome::Stale;
+        }
+        if !self.latest.contains_key(&key) && self.latest.len() >= MAX_SEEN_PAIRS {
+            return UpdateOutcome::Full;
+        }
+        self.latest.insert(key, update.timestamp);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.updates.push_back(ReceivedUpdate {
+            id,
+            author,
+            update,
+            status: UpdateStatus::Pending,
+            previous: None,
+        });
+        if self.updates.len() > MAX_TRACKED_UPDATES {
+            self.updates.pop_front();
+        }
+
+        if auto_apply {
+            match self.apply(id, now) {
+                Ok(change) => UpdateOutcome::Applied(id, change),
+                Err(_) => UpdateOutcome::Full,
+            }
+        } else {
+            UpdateOutcome::Pending(id)
+        }
+    }
+
+    /// Apply a pending update.
+    pub fn apply(&mut self, id: u64, now: u64) -> Result<Change, String> {
+        let index = self.index_of(id)?;
+        let received = &self.updates[index];
+        if received.status != UpdateStatus::Pending {
+            return Err(format!("update #{id} is not pending"));
+        }
+        let (author, update) = (received.author, received.update.clone());
+
+        let (change, previous) = match update.action {
+            BlockAction::Add => {
+                let origin = BlockOrigin::Shared {
+                    via: author,
+                    update: id,
+                };
+                let previous = self
+                    .block(update.target, update.reason, origin, now)
+                    .map_err(|_| format!("blocklist is full ({MAX_BLOCKED_PEERS} peers)"))?;
+                (Change::Blocked(update.target), previous)
+            }
+            // A shared removal only lifts blocks that the same author put in place, never
+            // manual blocks or blocks shared by someone else.
+            BlockAction::Remove => match self.entries.get(&update.target) {
+                Some(entry) if matches!(entry.origin, BlockOrigin::Shared { via, .. } if via == author) => {
+                    (
+                        Change::Unblocked(update.target),
+                        self.unblock(&update.target),
+                    )
+                }
+                _ => (Change::Unchanged, None),
+            },
+        };
+
+        let received = &mut self.updates[index];
+        received.status = UpdateStatus::Applied;
+        received.previous = previous;
+        Ok(change)
+    }
+
+    /// Undo an applied update, or discard a pending one.
+    pub fn revert(&mut self, id: u64) -> Result<Change, String> {
+        let index = self.index_of(id)?;
+        let received = &mut self.updates[index];
+        let status = received.status;
+        received.status = UpdateStatus::Reverted;
+        let previous = received.previous.take();
+        let (action, target) = (received.update.action, received.update.target);
+
+        match status {
+            UpdateStatus::Reverted => Err(format!("update #{id} is already reverted")),
+            UpdateStatus::Pending => Ok(Change::Unchanged),
+            UpdateStatus::Applied => match (action, previous) {
+                // Put back whatever the update replaced or removed.
+                (_, Some(previous)) => {
+                    self.entries.insert(target, previous);
+                    Ok(Change::Blocked(target))
+                }
+                // Only lift the block if it is still the one this update added.
+                (BlockAction::Add, None) => match self.entries.get(&target) {
+                    Some(entry)
+                        if entry.origin
+                            == (BlockOrigin::Shared {
+                                via: self.updates[index].author,
+                                update: id,
+                            }) =>
+                    {
+                        self.entries.remove(&target);
+                        Ok(Change::Unblocked(target))
+                    }
+                    _ => Ok(Change::Unchanged),
+                },
+                (BlockAction::Remove, None) => Ok(Change::Unchanged),
+            },
+        }
+    }
+
+    fn index_of(&self, id: u64) -> Result<usize, String> {
+        self.updates
+            .iter()
+            .position(|received| received.id == id)
+            .ok_or_else(|| format!("no blocklist update #{id}"))
+    }
+}
@@ -0,0 +1,355 @@
+// A room's bulletin board: long-lived posts kept identical on every peer.
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use libp2p::{gossipsub, PeerId};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{error::ConfigError, node, sanitize, signed::Signed};
+
+/// Most posts kept; the oldest unpinned ones are dropped to make room.
+pub const MAX_POSTS: usize = 200;
+
+/// Longest post title, in characters.
+pub const MAX_TITLE_CHARS: usize = 80;
+
+/// Longest post body, in characters.
+pub const MAX_POST_BODY_CHARS: usize = 1000;
+
+/// Most posts sent to a newcomer in one sync, newest first.
+pub const MAX_SYNC_POSTS: usize = 50;
+
+/// Every board message is signed by the node that sent it.
+pub type SignedBoard = Signed<BoardMessage>;
+
+/// Posts are signed by their author, so they can be passed on by anyone.
+pub type SignedPost = Signed<Post>;
+
+/// How many changes each moderator made to a post's pin, so concurrent changes can be told
+/// apart from later ones.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct VersionVector(BTreeMap<PeerId, u64>);
+
+impl VersionVector {
+    /// Count one more change by `peer`.
+    pub fn increment(&mut self, peer: PeerId) {
+        *self.0.entry(peer).or_default() += 1;
+    }
+
+    /// Take the newest count of every peer from `other`.
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (peer, count) in &other.0 {
+            let entry = self.0.entry(*peer).or_default();
+            *entry = (*entry).max(*count);
+        }
+    }
+
+    /// How this vector relates to `other`: `None` when they are concurrent, each having seen
+    /// changes the other hasn't.
+    pub fn compare(&self, other: &VersionVector) -> Option<Ordering> {
+        let count = |vector: &VersionVector, peer| vector.0.get(peer).copied().unwrap_or(0);
+        let (mut less, mut greater) = (false, false);
+        for peer in self.0.keys().chain(other.0.keys()) {
+            match count(self, peer).cmp(&count(other, peer)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (less, greater) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+/// A post on the board. Its author signs it unpinned; `pinned` is the state moderators gave it
+/// since.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Post {
+    pub id: Uuid,
+    pub author: PeerId,
+    pub author_nick: String,
+    pub title: String,
+    pub body: String,
+    pub posted_at: u64,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+impl Post {
+    /// A new post by `author`, with a fresh id.
+    pub fn new(author: PeerId, author_nick: &str, title: &str, body: &str, now: u64) -> Post {
+        Post {
+            id: Uuid::new_v4(),
+            author,
+            author_nick: author_nick.to_string(),
+            title: title.to_string(),
+            body: body.to_string(),
+            posted_at: now,
+            pinned: false,
+        }
+    }
+}
+
+/// Whether a post is pinned, and after which changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PinState {
+    pub id: Uuid,
+    pub pinned: bool,
+    pub version: VersionVector,
+}
+
+/// Messages on a room's board topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BoardMessage {
+    /// A new post.
+    Post(SignedPost),
+    /// A moderator pinned or unpinned a post.
+    Pin(PinState),
+    /// A member's board, sent when someone new subscribes to it. Pin states are only taken
+    /// from moderators.
+    Sync {
+        posts: Vec<SignedPost>,
+        pins: Vec<PinState>,
+    },
+}
+
+/// The topic that carries the board of the room on `topic`.
+pub fn board_topic_for(topic: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{topic}/_board"))
+}
+
+/// The topic that carries the board of the default chat topic.
+pub fn board_topic() -> gossipsub::IdentTopic {
+    board_topic_for(node::default_topic().hash().as_str())
+}
+
+/// The file kept next to the config file with the boards of every room: `config.json` keeps
+/// them in `config.board.json`.
+pub fn path_beside(config_path: &Path) -> PathBuf {
+    config_path.with_extension("board.json")
+}
+
+// A post as received and as saved: the author's signed original and its pin state.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Entry {
+    signed: SignedPost,
+    #[serde(skip)]
+    post: Option<Post>,
+    pin: PinState,
+}
+
+/// A room's posts, merged so every peer ends up with the same board whatever order messages
+/// arrive in. Posts never change after publishing. A post's pin is settled by its version
+/// vector, and when two moderators change it concurrently, pinning wins.
+#[derive(Debug, Clone)]
+pub struct BulletinBoard {
+    room: String,
+    posts: HashMap<Uuid, Entry>,
+    path: Option<PathBuf>,
+}
+
+impl BulletinBoard {
+    /// An empty board of `room`, saved to `path` if given.
+    pub fn new(room: &str, path: Option<PathBuf>) -> Self {
+        BulletinBoard {
+            room: room.to_string(),
+            posts: HashMap::new(),
+            path,
+        }
+    }
+
+    /// The board of `room` saved at `path`, or an empty one if there is none yet. Saved posts
+    /// are checked again like received ones.
+    pub fn load(room: &str, path: PathBuf) -> Result<Self, ConfigError> {
+        let mut boards = read_boards(&path)?;
+        let mut board = BulletinBoard::new(room, Some(path));
+        for entry in boards.remove(room).unwrap_or_default() {
+            if board.insert(&entry.signed).is_ok() {
+                board.apply_pin(&entry.pin);
+            }
+        }
+        Ok(board)
+    }
+
+    /// Write the board to its file, if it has one, keeping the boards of other rooms there.
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut boards = read_boards(path)?;
+        let entries = self
+            .sorted()
+            .iter()
+            .map(|post| self.posts[&post.id].clone())
+            .collect();
+        boards.insert(self.room.clone(), entries);
+        let contents = serde_json::to_string_pretty(&boards).expect("boards serialize");
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, contents))
+            .map_err(|source| ConfigError::Write {
+                path: path.clone(),
+                source,
+            })
+    }
+
+    /// Check a post's signature and add it, unpinned. Returns its id if it is new.
+    pub fn insert(&mut self, signed: &SignedPost) -> Result<Option<Uuid>, String> {
+        let (signer, mut post) = signed.verify().map_err(|e| e.to_string())?;
+        if post.author != signer {
+            return Err(format!("post {} not signed by its author", post.id));
+        }
+        if self.posts.contains_key(&post.id) {
+            return Ok(None);
+        }
+        if self.posts.len() >= MAX_POSTS && !self.make_room() {
+            return Ok(None);
+        }
+        post.author_nick = sanitize::nick(&post.author_nick);
+        post.title = sanitize::line(&post.title)
+            .chars()
+            .take(MAX_TITLE_CHARS)
+            .collect();
+        post.body = sanitize::line(&post.body)
+            .chars()
+            .take(MAX_POST_BODY_CHARS)
+            .collect();
+        post.pinned = false;
+        let pin = PinState {
+            id: post.id,
+            pinned: false,
+            version: VersionVector::default(),
+        };
+        let id = post.id;
+        self.posts.insert(
+            id,
+            Entry {
+                signed: signed.clone(),
+                post: Some(post),
+                pin,
+            },
+        );
+        Ok(Some(id))
+    }
+
+    /// Merge a moderator's pin change. Returns whether the board changed; changes to posts we
+    /// don't have are dropped, and arrive with the post when it is synced.
+    pub fn apply_pin(&mut self, change: &PinState) -> bool {
+        let Some(entry) = self.posts.get_mut(&change.id) else {
+            return false;
+        };
+        let pin = &mut entry.pin;
+        let before = pin.clone();
+        match change.version.compare(&pin.version) {
+            Some(Ordering::Greater) => pin.pinned = change.pinned,
+            Some(_) => return false,
+            None => pin.pinned |= change.pinned,
+        }
+        pin.version.merge(&change.version);
+        if let Some(post) = &mut entry.post {
+            post.pinned = pin.pinned;
+        }
+        *pin != before
+    }
+
+    /// Pin or unpin post `id` as `moderator`, returning the change to publish.
+    pub fn pin(&mut self, id: &Uuid, moderator: PeerId, pinned: bool) -> Option<PinState> {
+        let entry = self.posts.get_mut(id)?;
+        entry.pin.version.increment(moderator);
+        entry.pin.pinned = pinned;
+        if let Some(post) = &mut entry.post {
+            post.pinned = pinned;
+        }
+        Some(entry.pin.clone())
+    }
+
+    /// The newest posts and their pin states, to send to a newcomer.
+    pub fn sync(&self) -> (Vec<SignedPost>, Vec<PinState>) {
+        self.sorted()
+            .iter()
+            .take(MAX_SYNC_POSTS)
+            .map(|post| {
+                let entry = &self.posts[&post.id];
+                (entry.signed.clone(), entry.pin.clone())
+            })
+            .unzip()
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<&Post> {
+        self.posts.get(id)?.post.as_ref()
+    }
+
+    /// The post whose id starts with `prefix`, if exactly one does.
+    pub fn find(&self, prefix: &str) -> Result<&Post, String> {
+        let prefix = prefix.to_lowercase();
+        let mut matches = self
+            .posts()
+            .filter(|post| post.id.to_string().starts_with(&prefix));
+        match (matches.next(), matches.next()) {
+            (Some(post), None) => Ok(post),
+            (None, _) => Err(format!("no post {prefix}")),
+            (Some(_), Some(_)) => Err(format!("more than one post starts with {prefix}")),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.posts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.posts.is_empty()
+    }
+
+    /// Pinned posts first, then newest first. Ties are broken by id, so every peer lists the
+    /// same board in the same order.
+    pub fn sorted(&self) -> Vec<&Post> {
+        let mut posts: Vec<&Post> = self.posts().collect();
+        posts.sort_by(|a, b| {
+            b.pinned
+                .cmp(&a.pinned)
+                .then(b.posted_at.cmp(&a.posted_at))
+                .then(a.id.cmp(&b.id))
+        });
+        posts
+    }
+
+    fn posts(&self) -> impl Iterator<Item = &Post> {
+        self.posts.values().filter_map(|entry| entry.post.as_ref())
+    }
+
+    // Drop the oldest unpinned post. Returns false when every post is pinned.
+    fn make_room(&mut self) -> bool {
+        let oldest = self
+            .posts()
+            .filter(|post| !post.pinned)
+            .min_by_key(|post| (post.posted_at, post.id))
+            .map(|post| post.id);
+        oldest.is_some_and(|id| self.posts.remove(&id).is_some())
+    }
+}
+
+// The boards of every room saved at `path`.
+fn read_boards(path: &Path) -> Result<BTreeMap<String, Vec<Entry>>, ConfigError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(source) => Err(ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
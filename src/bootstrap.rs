@@ -0,0 +1,250 @@
+// Bootstrap peers dialed at startup: given with `--bootstrap`, or published in DNS TXT records
+// of `--bootstrap-domain` and looked up again now and then to pick up changes.
+use std::{net::IpAddr, thread};
+
+use hickory_resolver::{error::ResolveErrorKind, Resolver};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tracing::debug;
+
+/// Seconds between lookups of the domain's records once they were found.
+pub const REFRESH_INTERVAL: u64 = 300;
+
+/// Seconds before looking the records up again after a failed lookup.
+pub const RETRY_INTERVAL: u64 = 30;
+
+/// Addresses taken from the records at most; the rest are left out.
+pub const MAX_ADDRESSES: usize = 64;
+
+/// The result of looking up a domain's bootstrap peers: their addresses, or why there are none.
+pub type LookupResult = Result<Vec<Multiaddr>, String>;
+
+/// A bootstrap peer's address, which must end in `/p2p/<peer id>` so a peer we are connected
+/// to already isn't dialed again.
+pub fn parse_address(s: &str) -> Result<Multiaddr, String> {
+    let addr = s.parse::<Multiaddr>().map_err(|e| e.to_string())?;
+    match addr.iter().last() {
+        Some(Protocol::P2p(_)) => Ok(addr),
+        _ => Err("the address must end in /p2p/<peer id>".to_string()),
+    }
+}
+
+/// The peer a bootstrap address belongs to.
+pub fn peer_id(addr: &Multiaddr) -> Option<PeerId> {
+    match addr.iter().last() {
+        Some(Protocol::P2p(peer)) => Some(peer),
+        _ => None,
+    }
+}
+
+/// The names whose TXT records list the bootstrap peers of `domain`: our own
+/// `_p2pchat._tcp.<domain>`, and `_dnsaddr.<domain>` as libp2p's dnsaddr uses.
+pub fn record_names(domain: &str) -> [String; 2] {
+    let domain = domain.trim_end_matches('.');
+    [
+        format!("_p2pchat._tcp.{domain}."),
+        format!("_dnsaddr.{domain}."),
+    ]
+}
+
+/// The bootstrap addresses in TXT record values, each either a multiaddr or
+/// `dnsaddr=<multiaddr>`. Other records, addresses without a peer id and `/dnsaddr` addresses,
+/// which would need more lookups, are skipped, as are duplicates.
+pub fn parse_records<'a>(records: impl IntoIterator<Item = &'a str>) -> Vec<Multiaddr> {
+    let mut addresses = Vec::new();
+    for record in records {
+        let value = record.trim();
+        let value = value.strip_prefix("dnsaddr=").unwrap_or(value);
+        let Ok(addr) = parse_address(value) else {
+            continue;
+        };
+        if addr.iter().any(|p| matches!(p, Protocol::Dnsaddr(_))) || addresses.contains(&addr) {
+            continue;
+        }
+        if addresses.len() == MAX_ADDRESSES {
+            break;
+        }
+        addresses.push(addr);
+    }
+    addresses
+}
+
+/// Look up the bootstrap peers published for `domain` with the system's resolver. This blocks
+/// until the lookup is done. With `resolve_hosts`, host names in the addresses are resolved as
+/// well, since the swarm can only dial IP addresses itself; a proxy resolves them instead.
+pub fn lookup(domain: &str, resolve_hosts: bool) -> LookupResult {
+    let resolver = Resolver::from_system_conf()
+        .map_err(|e| format!("can't read the system's DNS configuration: {e}"))?;
+    let mut records = Vec::new();
+    let mut error = None;
+    for name in record_names(domain) {
+        match resolver.txt_lookup(name.as_str()) {
+            Ok(txt) => records.extend(txt.iter().map(|txt| {
+                txt.txt_data()
+                    .iter()
+                    .map(|chunk| String::from_utf8_lossy(chunk))
+                    .collect::<String>()
+            })),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {}
+            Err(e) => error = Some(format!("{name}: {e}")),
+        }
+    }
+    let addresses = parse_records(records.iter().map(String::as_str));
+    if addresses.is_empty() {
+        return Err(error.unwrap_or_else(|| {
+            let [ours, dnsaddr] = record_names(domain);
+            format!("no bootstrap addresses in the TXT records of {ours} or {dnsaddr}")
+        }));
+    }
+    if !resolve_hosts {
+        return Ok(addresses);
+    }
+    Ok(addresses
+        .into_iter()
+        .filter_map(|addr| resolve_host(&resolver, addr))
+        .collect())
+}
+
+// The address with its `/dns`, `/dns4` or `/dns6` host replaced by one of its IP addresses.
+fn resolve_host(resolver: &Resolver, addr: Multiaddr) -> Option<Multiaddr> {
+    let mut protocols = addr.iter();
+    let (host, family): (_, fn(&IpAddr) -> bool) = match protocols.next()? {
+        Protocol::Dns(host) => (host, |_| true),
+        Protocol::Dns4(host) => (host, IpAddr::is_ipv4),
+        Protocol::Dns6(host) => (host, IpAddr::is_ipv6),
+        _ => return Some(addr),
+    };
+    let ip = match resolver.lookup_ip(&*host) {
+        Ok(ips) => ips.iter().find(family),
+        Err(e) => {
+            debug!("[bootstrap] can't resolve {host}: {e}");
+            return None;
+        }
+    }?;
+    let first = match ip {
+        IpAddr::V4(ip) => Protocol::Ip4(ip),
+        IpAddr::V6(ip) => Protocol::Ip6(ip),
+    };
+    Some(std::iter::once(first).chain(protocols).collect())
+}
+
+/// The bootstrap peers to dial and, with a domain, the lookups of its records.
+#[derive(Debug)]
+pub struct Bootstrap {
+    static_addresses: Vec<Multiaddr>,
+    domain: Option<String>,
+    resolve_hosts: bool,
+    resolved: Vec<Multiaddr>,
+    resolved_at: Option<u64>,
+    last_error: Option<String>,
+    lookup: Option<oneshot::Receiver<LookupResult>>,
+    next_lookup: u64,
+}
+
+impl Bootstrap {
+    /// Bootstrap peers at `static_addresses`, and those published for `domain` if there is
+    /// one, with their host names resolved if `resolve_hosts`.
+    pub fn new(
+        static_addresses: Vec<Multiaddr>,
+        domain: Option<String>,
+        resolve_hosts: bool,
+    ) -> Self {
+        Bootstrap {
+            static_addresses,
+            domain,
+            resolve_hosts,
+            resolved: Vec::new(),
+            resolved_at: None,
+            last_error: None,
+            lookup: None,
+            next_lookup: 0,
+        }
+    }
+
+    /// The domain whose records are looked up, if any.
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    /// The addresses given with `--bootstrap`.
+    pub fn static_addresses(&self) -> &[Multiaddr] {
+        &self.static_addresses
+    }
+
+    /// The addresses the last successful lookup found, and when it was.
+    pub fn resolved(&self) -> Option<(&[Multiaddr], u64)> {
+        self.resolved_at.map(|at| (self.resolved.as_slice(), at))
+    }
+
+    /// Why the last lookup failed, unless it succeeded.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// The addresses to dial: those found by the last successful lookup, or the static ones
+    /// while no lookup has succeeded. A failed refresh keeps what was found before.
+    pub fn addresses(&self) -> &[Multiaddr] {
+        match self.resolved_at {
+            Some(_) => &self.resolved,
+            None => &self.static_addresses,
+        }
+    }
+
+    /// Start looking up the domain's records on a thread of their own, if a lookup is due and
+    /// none is running. Returns whether one started.
+    pub fn start_lookup(&mut self, now: u64) -> bool {
+        let Some(domain) = &self.domain else {
+            return false;
+        };
+        if self.lookup.is_some() || now < self.next_lookup {
+            return false;
+        }
+        let (sender, receiver) = oneshot::channel();
+        let (domain, resolve_hosts) = (domain.clone(), self.resolve_hosts);
+        let started = thread::Builder::new()
+            .name("bootstrap".to_string())
+            .spawn(move || {
+                let _ = sender.send(lookup(&domain, resolve_hosts));
+            });
+        match started {
+            Ok(_) => self.lookup = Some(receiver),
+            Err(e) => {
+                self.next_lookup = now + RETRY_INTERVAL;
+                debug!("[bootstrap] can't start the lookup: {e}");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The result of the running lookup, once it is done.
+    pub fn poll_lookup(&mut self) -> Option<LookupResult> {
+        let result = match self.lookup.as_mut()?.try_recv() {
+            Ok(result) => result,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Closed) => Err("the lookup stopped".to_string()),
+        };
+        self.lookup = None;
+        Some(result)
+    }
+
+    /// Keep the result of a lookup done at `now`, and plan the next one. Returns whether the
+    /// addresses to dial changed.
+    pub fn record(&mut self, result: LookupResult, now: u64) -> bool {
+        match result {
+            Ok(addresses) => {
+                let changed = self.resolved_at.is_none() || addresses != self.resolved;
+                self.resolved = addresses;
+                self.resolved_at = Some(now);
+                self.last_error = None;
+                self.next_lookup = now + REFRESH_INTERVAL;
+                changed
+            }
+            Err(e) => {
+                self.last_error = Some(e);
+                self.next_lookup = now + RETRY_INTERVAL;
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+// A room's shared drawing canvas: cells set by whoever wrote them last, so concurrent strokes
+// merge the same way everywhere.
+use std::{cmp::Ordering, collections::BTreeMap, fmt::Write};
+
+use libp2p::{gossipsub, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{node, sanitize, signed::Signed};
+
+/// Columns of the canvas.
+pub const WIDTH: u8 = 80;
+
+/// Rows of the canvas.
+pub const HEIGHT: u8 = 24;
+
+/// What an empty or cleared cell holds.
+pub const BLANK: char = ' ';
+
+/// Every delta is signed by the peer that drew it.
+pub type SignedDelta = Signed<CanvasDelta>;
+
+/// A cell set to `ch` by `author`, the PeerId of whoever drew it, at `ts`. Drawing [`BLANK`]
+/// clears the cell.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CanvasDelta {
+    pub x: u8,
+    pub y: u8,
+    pub ch: char,
+    pub author: String,
+    pub ts: u64,
+}
+
+impl CanvasDelta {
+    // Later timestamps win, and the author breaks ties, so every peer picks the same delta
+    fn precedence(&self, other: &CanvasDelta) -> Ordering {
+        (self.ts, &self.author).cmp(&(other.ts, &other.author))
+    }
+
+    /// Whether the delta is one the canvas can hold: inside it, with a printable character.
+    pub fn check(&self) -> Result<(), String> {
+        if self.x >= WIDTH || self.y >= HEIGHT {
+            return Err(format!(
+                "({}, {}) is off the canvas, which is {WIDTH}×{HEIGHT}",
+                self.x, self.y
+            ));
+        }
+        if self.ch != BLANK && (self.ch.is_control() || sanitize::is_invisible(self.ch)) {
+            return Err(format!("{:?} can't be drawn", self.ch));
+        }
+        Ok(())
+    }
+}
+
+/// The canvas as a last-writer-wins map of cells: applying a delta keeps, for its cell,
+/// whichever of the two has the later timestamp. Merging is idempotent, commutative and
+/// associative, so every peer ends up with the same picture once it has every delta, in
+/// whatever order they came.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Canvas {
+    cells: BTreeMap<(u8, u8), CanvasDelta>,
+}
+
+impl Canvas {
+    /// Draw `ch` at (`x`, `y`) as `author`, returning the delta to publish. It is stamped
+    /// after `now` and after the cell's current delta, so a stroke made after seeing another
+    /// always wins even if clocks disagree.
+    pub fn draw(
+        &mut self,
+        x: u8,
+        y: u8,
+        ch: char,
+        author: PeerId,
+        now: u64,
+    ) -> Result<CanvasDelta, String> {
+        let ts = self
+            .cells
+            .get(&(x, y))
+            .map_or(now, |current| now.max(current.ts + 1));
+        let delta = CanvasDelta {
+            x,
+            y,
+            ch,
+            author: author.to_string(),
+            ts,
+        };
+        self.apply(&delta)?;
+        Ok(delta)
+    }
+
+    /// Merge a delta. Returns whether it changed the cell, or why it can't be drawn.
+    pub fn apply(&mut self, delta: &CanvasDelta) -> Result<bool, String> {
+        delta.check()?;
+        let cell = (delta.x, delta.y);
+        match self.cells.get(&cell) {
+            Some(current) if delta.precedence(current) != Ordering::Greater => Ok(false),
+            _ => {
+                self.cells.insert(cell, delta.clone());
+                Ok(true)
+            }
+        }
+    }
+
+    /// The character at (`x`, `y`), [`BLANK`] if nothing was drawn there.
+    pub fn get(&self, x: u8, y: u8) -> char {
+        self.cells.get(&(x, y)).map_or(BLANK, |delta| delta.ch)
+    }
+
+    /// Cells holding something other than [`BLANK`].
+    pub fn drawn(&self) -> usize {
+        self.cells
+            .values()
+            .filter(|delta| delta.ch != BLANK)
+            .count()
+    }
+
+    /// The canvas in a frame, one line per row. With `ansi`, each character is put in its
+    /// column with a cursor escape, so wide characters don't push the rest of the row along;
+    /// otherwise rows are padded with spaces, for output that isn't a terminal.
+    pub fn render(&self, ansi: bool) -> String {
+        let border = format!("+{}+", "-".repeat(WIDTH.into()));
+        let mut out = border.clone();
+        for y in 0..HEIGHT {
+            out.push_str("\n|");
+            for x in 0..WIDTH {
+                let ch = self.get(x, y);
+                if !ansi {
+                    out.push(ch);
+                } else if ch != BLANK {
+                    // Columns count from one, after the frame's
+                    let _ = write!(out, "\x1b[{}G{ch}", u16::from(x) + 2);
+                }
+            }
+            if ansi {
+                let _ = write!(out, "\x1b[{}G", u16::from(WIDTH) + 2);
+            }
+            out.push('|');
+        }
+        out.push('\n');
+        out.push_str(&border);
+        out
+    }
+}
+
+/// The topic that carries the canvas of the room on `topic`.
+pub fn canvas_topic_for(topic: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{topic}/_canvas"))
+}
+
+/// The topic that carries the canvas of the default chat topic.
+pub fn canvas_topic() -> gossipsub::IdentTopic {
+    canvas_topic_for(node::default_topic().hash().as_str())
+}
@@ -0,0 +1,7699 @@
+// The chat node: the swarm plus the application state driven by user input and swarm events.
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    env, fs,
+    future::Future,
+    io::{self, IsTerminal},
+    mem,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    allow_block_list,
+    futures::{Stream, StreamExt},
+    core::transport::ListenerId,
+    dcutr,
+    gossipsub::{self, MessageAcceptance, PeerScoreParams, PeerScoreThresholds, TopicScoreParams},
+    identify,
+    identity::{Keypair, PublicKey},
+    mdns,
+    multiaddr::Protocol,
+    relay,
+    request_response::{self, OutboundRequestId},
+    swarm::{
+        self,
+        dial_opts::{DialOpts, PeerCondition},
+        ConnectionDenied, ConnectionId, ListenError, SwarmEvent,
+    },
+    Multiaddr, PeerId, Swarm,
+};
+
+use serde_json::{json, Value};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    activitypub::{self, ActivityPubSettings, Actor, Fediverse, FediverseEvent, Inbox, SigningKey},
+    attachment::{self, AttachmentRef, Cid, Content, ContentStore, Fetch, Offers, CHUNK_BYTES},
+    audit::{AuditEvent, AuditLog},
+    auth::{Authenticator, ConnectionAuthenticator, PendingAuth, Progress},
+    autoban::{AutoBanSettings, AutoBanner, TempBan, MAX_TRACKED_PEERS},
+    bans::{BanOrigin, BanRecord, BanScope},
+    batch::{self, Batcher},
+    blocklist::{
+        BlockAction, BlockOrigin, Blocklist, BlocklistUpdate, Change, UpdateOutcome, UpdateStatus,
+    },
+    board::{self, BoardMessage, BulletinBoard, Post, SignedBoard, SignedPost},
+    bootstrap::{self, Bootstrap, LookupResult},
+    canvas::{self, Canvas, CanvasDelta, SignedDelta},
+    cli::{Cli, IoMode, StdinEof},
+    clock,
+    collision::{self, Collisions},
+    commands::{
+        self, AliasCommand, BansCommand, BlocklistCommand, ContactCommand, DndCommand,
+        FilterCommand, ProfileCommand, ReportTarget, StatusCommand, TaskCommand, TransfersCommand,
+        UserCommand, WordleCommand,
+    },
+    config::{self, Config},
+    connections::{ConnectedPeer, ConnectionManager},
+    contacts::Contacts,
+    content_type::{ContentType, ContentTypeNegotiator},
+    control::{self, ControlMessage, SignedControl},
+    decision::{self, AggregateDecision, Decisions, PartialSig},
+    disk::DiskWriter,
+    dnd::DoNotDisturb,
+    doctor::{self, ClockSamples},
+    dtn::{Bundle, DtnEvent, DtnSettings, Radio, SignedBundle},
+    error::{self, ChatError, CryptoError, DialError},
+    filter::TopicFilter,
+    flood::{FloodDetector, FloodSettings, Run, Verdict},
+    fragment::{self, Assembly, Fragment, Reassembler, ReassemblyLimits},
+    gateway::{ClientEvent, Gateway, GatewayEvent},
+    gossip::{self, Validation},
+    hooks::{self, HookEvent, HookServer},
+    identity::{self, Rotation, SignedRotation, ROTATION_INTERVAL},
+    invite::{self, Invite, Join, SignedInvite},
+    irc::{self, IrcBridge, IrcEvent},
+    keyshare::{
+        self, HeldShare, KeptShare, KeyShareMessage, Recovery, SealedShare, SignedKeyShare,
+    },
+    latency::PingScorer,
+    limits::{EvictionWatch, LruMap, MemoryReport, Usage},
+    liveness::Liveness,
+    logging::TRANSCRIPT,
+    matrix::{self, MatrixBridge, MatrixEvent, MatrixSettings, MatrixState},
+    membership::{self, MembershipBatcher},
+    message::{self, ChatMessage, Identity, Incoming, StoredMessage},
+    mqtt::{self, MqttBridge, MqttEvent},
+    node::{self, MyBehaviour, MyBehaviourEvent},
+    nostr::{self, NostrKeys, Relay, RelayEvent},
+    notify::{Notifier, Reason},
+    outbox::{Outbox, PUBLISH_TIMEOUT},
+    passphrase::{OpenError, RoomKey, SECRET_LEN},
+    presence::{self, Presence, PresenceStatus},
+    profile::{self, Profile, ProfileField},
+    qr::{self, QrCode},
+    report::{ReceivedReport, Report, ReportOutcome, Reports},
+    room::{ModAction, Moderation, ModerationOutcome, Rooms},
+    roster::{Roster, RosterEvent},
+    runtime,
+    sanitize::{self, Link},
+    say, signed,
+    snapshot::{self, Member, Snapshot},
+    spam::{self, SpamMessage, SpamReport, SpamReportStore},
+    stall::{self, PublishDiagnosis, StallWatch},
+    stats::{HealthStatus, NetworkStats, SessionCounters, TimedDedup, TopicStats},
+    stdio::{self, ChatCommand, ChatEvent, Query},
+    tasks::{self, SignedTasks, TaskList},
+    topic_alias::{self, TopicAlias},
+    topology::{Link as TopologyLink, Topology, TopologyPeer},
+    transfer::{self, Transfers},
+    transport::MuxerCounts,
+    validator::AppValidator,
+    verify::{Fingerprint, VerifiedPeer},
+    watchdog::{self, Activity},
+    webhook::{self, Forwarded, WebhookEvent, Webhooks},
+    wordle::{self, SignedMove, Wordle, WordleMove},
+    xmpp::{self, XmppBridge, XmppEvent, XmppSettings},
+};
+
+/// Number of received chat messages kept in memory, including filtered ones.
+pub const MAX_HISTORY: usize = 1000;
+
+/// Number of peers whose nick is remembered for naming them in notices.
+pub const MAX_KNOWN_NICKS: usize = 4096;
+
+/// Seconds after startup at which a passphrase room with connected peers but nobody in it is
+/// reported, since a mistyped phrase leads to an empty room.
+pub const EMPTY_ROOM_NOTICE_AFTER: u64 = 30;
+
+/// How long [`ChatNode::shutdown`] waits for the leaving message to go out and connections to
+/// close.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Time given to the leaving message and unsubscriptions to reach peers before hanging up
+const LEAVE_FLUSH: Duration = Duration::from_millis(250);
+
+/// Time after startup given to peers to connect before the first chat message goes out.
+pub const CONNECT_GRACE: Duration = Duration::from_secs(2);
+
+/// Payload bytes the node may publish between two ticks of [`ChatNode::run`] before it stops
+/// taking input until the next one. Gossipsub 0.47 keeps its send queues private, so this
+/// bounds how fast input can fill them instead of watching their depth.
+pub const OUTBOUND_HIGH_WATER: u64 = 4 * 1024 * 1024;
+
+/// Seconds to wait before asking the relay for a new reservation after losing one.
+pub const RELAY_RETRY: u64 = 30;
+
+/// Seconds to wait before listening again on an address whose listener closed.
+pub const LISTEN_RETRY: u64 = 5;
+
+/// Number of hyperlinks from untrusted peers held for `/link`.
+pub const MAX_HELD_LINKS: usize = 100;
+
+/// A running chat node.
+pub struct ChatNode {
+    /// The underlying libp2p swarm; poll it and pass its events to [`ChatNode::handle_event`].
+    pub swarm: Swarm<MyBehaviour>,
+    keypair: Keypair,
+    // The chat topic, the topic carrying signed control messages, the room's board, its task
+    // list, its Wordle games, its canvas and its spam reports, and the deployment's topic for
+    // shares of room keys
+    topic: gossipsub::IdentTopic,
+    // The topic chat messages travel under: the chat topic, or the alias its moderators gave it
+    wire_topic: gossipsub::IdentTopic,
+    control_topic: gossipsub::IdentTopic,
+    board_topic: gossipsub::IdentTopic,
+    tasks_topic: gossipsub::IdentTopic,
+    wordle_topic: gossipsub::IdentTopic,
+    canvas_topic: gossipsub::IdentTopic,
+    spam_topic: gossipsub::IdentTopic,
+    keyshare_topic: gossipsub::IdentTopic,
+    // Peers whose shared blocklist updates we accept
+    trusted: HashSet<PeerId>,
+    blocklist: Blocklist,
+    // Temporary bans for peers that flood us or send invalid messages
+    bans: AutoBanner,
+    // Apply trusted blocklist updates without waiting for `/blocklist apply`
+    auto_apply: bool,
+    // Timestamp of the last control message we published per target, so ours always move forward
+    last_published: HashMap<PeerId, u64>,
+    nick: String,
+    // Persisted settings and where to save them (nowhere if no config directory is known)
+    config: Config,
+    config_path: Option<PathBuf>,
+    // Security-relevant events, appended next to the config file
+    audit: AuditLog,
+    // Saves of the files above, done off the event loop
+    disk: DiskWriter,
+    // Posts on the room's board, saved beside the config file
+    board: BulletinBoard,
+    // The room's shared tasks, and the file beside the config file they are saved in
+    tasks: TaskList,
+    tasks_path: Option<PathBuf>,
+    // Moves of the room's Wordle games, kept for this session only
+    wordle: Wordle,
+    // The room's canvas, also kept for this session only
+    canvas: Canvas,
+    // Members' reports of spam, hiding what enough of them reported
+    spam: SpamReportStore,
+    // Shares of our room key handed back after `/recover-key`, and where the key goes
+    recovery: Option<(Recovery, PathBuf)>,
+    // Received chat messages, oldest first
+    history: VecDeque<StoredMessage>,
+    // Messages hidden by the filter, per topic
+    filtered: HashMap<String, FilterCount>,
+    // Moderators and peers kicked or banned from rooms
+    rooms: Rooms,
+    // Room bans waiting for a quorum of moderators' signatures
+    decisions: Decisions,
+    // Reports members sent us as a moderator
+    reports: Reports,
+    // Last nick seen from each peer, to name peers in notices
+    nicks: LruMap<PeerId, String>,
+    // Nicks several online peers use, shown with a PeerId suffix
+    collisions: Collisions,
+    // Profiles peers published, sanitized, and whether messages show their display names
+    profiles: HashMap<PeerId, Profile>,
+    display_names: bool,
+    // Whether invites from `/invite create` are shown as QR codes, with `--qr`
+    invite_qr: bool,
+    // Desktop notifications of mentions and favorites' messages, unless `--no-notify`
+    notifier: Option<Notifier>,
+    // Peers ignored for lacking an invite, so each is only reported once
+    uninvited: HashSet<PeerId>,
+    // Our topic prefix, and chat nodes identified with another one, which are left alone
+    topic_prefix: String,
+    other_deployments: HashSet<PeerId>,
+    // Peers already warned about for using a verified peer's nick
+    impostors: HashSet<PeerId>,
+    // How long `connect_to` waits for the outcome of a dial
+    dial_timeout: Duration,
+    // Picks the peers to disconnect when nearing `--max-peers`
+    connections: ConnectionManager,
+    // Score adjustments from ping round-trip times, applied once a minute
+    pings: PingScorer,
+    next_ping_score: u64,
+    // How dead each connection looks, and where to dial peers again once a dead connection to
+    // them is closed
+    liveness: Liveness,
+    redial: HashMap<PeerId, Multiaddr>,
+    // Message counters for `/stats`, and the multiplexers TCP connections negotiated
+    counters: SessionCounters,
+    // Bytes sent as of the last tick, to hold input back past the outbound high-water mark
+    outbound_mark: u64,
+    muxers: MuxerCounts,
+    // Open connections running over QUIC, to tell which transport a hole punch went over
+    quic_connections: HashSet<ConnectionId>,
+    // The message formats connected peers accept, from Identify
+    formats: ContentTypeNegotiator,
+    // When the node was created and when the last message arrived, for `health`
+    started: Instant,
+    last_received: Option<Instant>,
+    dedup: TimedDedup,
+    // Copies of messages peers keep repeating, collapsed into a single line
+    floods: FloodDetector,
+    // Drop unsigned messages instead of marking them
+    require_signed: bool,
+    // Drop messages for topics we aren't subscribed to
+    strict_topic: bool,
+    // Publish nothing, only receive and relay (`--no-publish`)
+    read_only: bool,
+    // Whether input lines are slash commands and chat messages, or JSON commands
+    io: IoMode,
+    // Number of verified signed messages per author, for `/whois`
+    signers: HashMap<PeerId, u64>,
+    // Hyperlinks from untrusted peers, numbered, until the user opens them with `/link`
+    links: VecDeque<(u64, Link)>,
+    next_link: u64,
+    // Our own key rotation, announced until its grace period is over, and when it last was
+    rotation: Option<SignedRotation>,
+    rotation_announced: Option<u64>,
+    // Old identities whose rotation we already applied, so each is only reported once
+    rotated: HashSet<PeerId>,
+    // Key of a passphrase room, peers whose messages it couldn't open, and whether an empty
+    // room was reported
+    room_key: Option<RoomKey>,
+    // HMAC and content checks on the chat topic
+    validator: AppValidator,
+    // Fragments of large messages waiting for the rest
+    fragments: Reassembler,
+    // Our small messages waiting to go out together, when the room batches them
+    batcher: Option<Batcher>,
+    undecryptable: HashSet<PeerId>,
+    empty_room_reported: bool,
+    // When peers were last heard from, our heartbeat interval (0 when off) and when the next
+    // heartbeat is due
+    presence: Presence,
+    presence_interval: u64,
+    next_heartbeat: Option<u64>,
+    // Who is online in each room, and the embedders told about changes
+    roster: Roster,
+    roster_listeners: Vec<mpsc::UnboundedSender<RosterEvent>>,
+    // Whoever is told what happens as JSON events, like a program reading `--io json`
+    event_listeners: Vec<mpsc::UnboundedSender<ChatEvent>>,
+    // Membership snapshots applied from other members since we joined
+    snapshots_applied: usize,
+    // Roster changes waiting to be printed as one summary per room, unless each gets a line
+    membership: MembershipBatcher,
+    verbose_presence: bool,
+    // How long the user may stay idle before showing as away (never when `None`), when they
+    // last typed, whether that made them away, and a `/status` overriding it
+    away_after: Option<Duration>,
+    last_input: Instant,
+    idle_away: bool,
+    manual_away: Option<bool>,
+    // Whether `run` exits or keeps listening once its input ends
+    on_input_end: StdinEof,
+    // The status line set with `/status set`, sent with every heartbeat
+    status_line: Option<String>,
+    // The relay of `--relay-server`, the listener holding our reservation on it, whether
+    // the reservation was accepted, and when to try again after losing it
+    relay_server: Option<Multiaddr>,
+    relay_listener: Option<ListenerId>,
+    relay_reserved: bool,
+    relay_retry: Option<u64>,
+    // The bootstrap peers of `--bootstrap` and `--bootstrap-domain`
+    bootstrap: Bootstrap,
+    // Addresses given to `listen_on` by their listener, and those to listen on again after
+    // their listener closed, with when
+    listeners: HashMap<ListenerId, Multiaddr>,
+    relisten: Vec<(Multiaddr, u64)>,
+    // Warnings about bounded structures that keep evicting
+    evictions: EvictionWatch,
+    // Messages waiting for a peer to publish them to
+    outbox: Outbox,
+    // Our chat messages awaiting a receipt, and the ids of received ones to confirm next tick
+    stall: StallWatch,
+    receipts: Vec<String>,
+    // The check peers must pass after connecting, set by embedders, and who passed it
+    authenticator: Option<Authenticator>,
+    pending_auth: PendingAuth,
+    // Files we serve to peers by content id, the file going with our next message,
+    // attachments seen in the room, our fetches of them, and the chunk each request in flight
+    // asked for
+    content: ContentStore,
+    next_attachment: Option<AttachmentRef>,
+    offers: Offers,
+    transfers: Transfers,
+    fetches: HashMap<OutboundRequestId, (Cid, u64)>,
+    // The Nostr relay chat messages are bridged to, and whether they go only there
+    nostr: Option<Relay>,
+    nostr_only: bool,
+    // The IRC channel mirrored into the room and back
+    irc: Option<IrcBridge>,
+    // The IRC server local IRC clients chat in the room through
+    gateway: Option<Gateway>,
+    // The HTTP server hooks post messages for the room to
+    hook_server: Option<HookServer>,
+    // The Matrix room mirrored into the room and back, and where its sync token is kept
+    matrix: Option<MatrixBridge>,
+    matrix_state_path: Option<PathBuf>,
+    // The XMPP room mirrored into the room and back
+    xmpp: Option<XmppBridge>,
+    // The MQTT topics mirrored into the room, and the ones the room is mirrored to
+    mqtt: Option<MqttBridge>,
+    // The inboxes the room is cross-posted to as notes
+    fediverse: Option<Fediverse>,
+    // The simulated Bluetooth radio our messages are carried over, for when there's no internet
+    dtn: Option<Radio>,
+    // The incoming webhooks rooms' messages are posted to
+    webhooks: Webhooks,
+    // How far our clock is from the timestamps on signed messages, for `/doctor`
+    clock_samples: ClockSamples,
+}
+
+/// How many messages a topic's filter has hidden, and how many of those were reported.
+#[derive(Debug, Default, Clone, Copy)]
+struct FilterCount {
+    hidden: u64,
+    reported: u64,
+}
+
+impl ChatNode {
+    /// Create a node with the identity key from `--identity`, or a fresh one without it.
+    pub fn new(cli: &Cli) -> Result<Self, ChatError> {
+        let keypair = match &cli.identity {
+            Some(path) => identity::load_or_create(path)?,
+            None => Keypair::generate_ed25519(),
+        };
+        Self::with_identity(keypair, cli)
+    }
+
+    /// Create a node with the given identity and subscribe it to the chat and control topics.
+    pub fn with_identity(keypair: Keypair, cli: &Cli) -> Result<Self, ChatError> {
+        let muxers = MuxerCounts::default();
+        let mut swarm = node::build_swarm_with_identity(keypair.clone(), cli, &muxers)?;
+
+        // Subscribe to the chat topic, its control topic, its board, its task list, its Wordle
+        // games, its canvas and its spam reports so that this node can receive and publish
+        // messages on them, and to the key shares of the whole deployment
+        let room_key = match (&cli.room_pass, &cli.room_key_file) {
+            (Some(phrase), _) => Some(RoomKey::derive(phrase)),
+            (None, Some(path)) => Some(RoomKey::load(path)?),
+            (None, None) => None,
+        };
+        let name = room_key.as_ref().map_or(node::TOPIC, RoomKey::topic);
+        let topic = node::chat_topic(&cli.topic_prefix, name);
+        let control_topic = control::control_topic_for(topic.hash().as_str());
+        let board_topic = board::board_topic_for(topic.hash().as_str());
+        swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+        swarm.behaviour_mut().gossipsub.subscribe(&control_topic)?;
+        let tasks_topic = tasks::tasks_topic_for(topic.hash().as_str());
+        swarm.behaviour_mut().gossipsub.subscribe(&board_topic)?;
+        swarm.behaviour_mut().gossipsub.subscribe(&tasks_topic)?;
+        let wordle_topic = wordle::wordle_topic_for(topic.hash().as_str());
+        swarm.behaviour_mut().gossipsub.subscribe(&wordle_topic)?;
+        let canvas_topic = canvas::canvas_topic_for(topic.hash().as_str());
+        swarm.behaviour_mut().gossipsub.subscribe(&canvas_topic)?;
+        let spam_topic = spam::spam_topic_for(topic.hash().as_str());
+        swarm.behaviour_mut().gossipsub.subscribe(&spam_topic)?;
+        let lobby = node::chat_topic(&cli.topic_prefix, node::TOPIC);
+        let keyshare_topic = keyshare::keyshare_topic_for(lobby.hash().as_str());
+        swarm.behaviour_mut().gossipsub.subscribe(&keyshare_topic)?;
+
+        // With a shared HMAC key, forwarding messages with a bad tag lowers a peer's score
+        let validator = AppValidator::new(
+            cli.hmac_key
+                .as_deref()
+                .map(AppValidator::load_key)
+                .transpose()?,
+        )
+        .with_max_body(cli.max_body);
+        if validator.has_key() {
+            let mut params = PeerScoreParams::default();
+            params
+                .topics
+                .insert(topic.hash(), TopicScoreParams::default());
+            swarm
+                .behaviour_mut()
+                .gossipsub
+                .with_peer_score(params, PeerScoreThresholds::default())
+                .map_err(|e| {
+                    ChatError::Behaviour(format!("invalid peer score parameters: {e}").into())
+                })?;
+        }
+
+        let config_path = cli.config.clone().or_else(config::default_path);
+        let mut config = match &config_path {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        let audit = AuditLog::beside(config_path.as_deref());
+        let board = match &config_path {
+            Some(path) => BulletinBoard::load(topic.hash().as_str(), board::path_beside(path))?,
+            None => BulletinBoard::new(topic.hash().as_str(), None),
+        };
+        // Fetches a previous run didn't finish, kept beside the config file
+        let transfers = Transfers::load(config_path.as_deref().map_or_else(
+            || env::temp_dir().join("p2p-chat-transfers"),
+            transfer::dir_beside,
+        ));
+        if !transfers.is_empty() {
+            say!(
+                "[attach] {} interrupted fetches resume once their senders are back, see /transfers",
+                transfers.len()
+            );
+        }
+        // A room its moderators gave an alias is carried on the alias rather than its own topic
+        let wire_topic = gossipsub::IdentTopic::new(
+            config
+                .topic_aliases
+                .resolve(topic.hash().as_str())
+                .into_string(),
+        );
+        if wire_topic.hash() != topic.hash() {
+            swarm.behaviour_mut().gossipsub.unsubscribe(&topic)?;
+            swarm.behaviour_mut().gossipsub.subscribe(&wire_topic)?;
+        }
+        let tasks_path = config_path.as_deref().map(tasks::path_beside);
+        let tasks = match &tasks_path {
+            Some(path) => tasks::load(path, topic.hash().as_str())?,
+            None => TaskList::default(),
+        };
+        let presence_interval = config
+            .rooms
+            .get(topic.hash().as_str())
+            .and_then(|settings| settings.presence_interval)
+            .unwrap_or(cli.presence_interval);
+        let room_settings = config.rooms.get(topic.hash().as_str());
+        let batcher = match room_settings.and_then(|settings| settings.batch_ms) {
+            Some(0) => None,
+            Some(ms) => Some(Duration::from_millis(ms)),
+            None => (cli.batch_ms > 0).then(|| Duration::from_millis(cli.batch_ms)),
+        }
+        .map(|window| {
+            let bytes = room_settings.and_then(|settings| settings.batch_bytes);
+            Batcher::new(window, bytes.unwrap_or(cli.batch_bytes))
+        });
+
+        // Blocks and bans outlive restarts, so put back the ones that haven't run out yet
+        let local_peer_id = *swarm.local_peer_id();
+        let now = clock::unix_time();
+        let mut changed = config.migrate_bans(now);
+        if config.dnd.is_some_and(|dnd| dnd.is_over(now)) {
+            config.dnd = None;
+            changed = true;
+        }
+        for record in config.banlist.expire(now) {
+            info!(
+                "[ban] {} ban on {} {} has ended",
+                record.origin.group(),
+                record.peer,
+                record.scope
+            );
+            changed = true;
+        }
+        let mut bans = AutoBanner::new(AutoBanSettings {
+            rate_limit: cli.rate_limit,
+            invalid_limit: cli.invalid_limit,
+            ban_duration: cli.ban_duration,
+        });
+        bans.restore(config.banlist.iter().filter_map(BanRecord::temp_ban), now);
+        let mut blocklist = Blocklist::new(local_peer_id);
+        for record in config.banlist.iter() {
+            let origin = match (&record.scope, record.origin) {
+                (BanScope::Room(room), _) => {
+                    let banned = &mut config.rooms.entry(room.clone()).or_default().banned;
+                    if !banned.contains(&record.peer) {
+                        banned.push(record.peer);
+                    }
+                    continue;
+                }
+                (BanScope::Global, BanOrigin::Blocklist { via }) => {
+                    // Update ids only last a session
+                    Some(BlockOrigin::Shared { via, update: 0 })
+                }
+                (BanScope::Global, BanOrigin::Manual | BanOrigin::Moderator { .. }) => {
+                    Some(BlockOrigin::Manual)
+                }
+                (BanScope::Global, BanOrigin::Auto { .. }) => None,
+            };
+            if let Some(origin) = origin {
+                let reason = record.reason.clone();
+                let _ = blocklist.block(record.peer, reason, origin, record.created_at);
+            }
+            swarm.behaviour_mut().blocked.block_peer(record.peer);
+        }
+        if changed {
+            if let Some(path) = &config_path {
+                config.save(path)?;
+            }
+        }
+
+        // A recent rotation to this key is announced so peers move their trust over
+        let rotation = cli
+            .identity
+            .as_deref()
+            .and_then(|path| identity::pending_rotation(path, local_peer_id, clock::unix_time()));
+        // Joining with an invite makes the room invite-only under the owner who signed it
+        if let Some(token) = &cli.join_with {
+            accept_invite(&mut config, topic.hash().as_str(), token, local_peer_id)?;
+            if let Some(path) = &config_path {
+                config.save(path)?;
+            }
+            audit.record(
+                local_peer_id,
+                AuditEvent::InviteUsed {
+                    room: topic.hash().into_string(),
+                },
+            )?;
+        }
+
+        let nostr = cli.nostr_relay.clone().map(|url| {
+            let hashtag = nostr::hashtag_for(topic.hash().as_str());
+            Relay::spawn(url, NostrKeys::derive(&keypair), hashtag)
+        });
+        // Passphrase rooms are private, so they aren't mirrored anywhere
+        let irc = match config.irc.clone() {
+            Some(_) if room_key.is_some() => {
+                say!("[irc] passphrase rooms aren't bridged to IRC");
+                None
+            }
+            Some(settings) => match settings.check() {
+                Ok(()) => Some(IrcBridge::spawn(settings)),
+                Err(e) => {
+                    say!("[irc] not bridging: {e}");
+                    None
+                }
+            },
+            None => None,
+        };
+        let matrix_state_path = config_path.as_deref().map(matrix::path_beside);
+        let matrix = match MatrixSettings::from_cli(cli) {
+            Some(settings) => {
+                let state = match &matrix_state_path {
+                    Some(path) => MatrixState::load(path)?,
+                    None => None,
+                };
+                Some(MatrixBridge::spawn(settings, state))
+            }
+            None => None,
+        };
+        let xmpp = XmppSettings::from_cli(cli).map(XmppBridge::spawn);
+        let mqtt = match config.mqtt.clone() {
+            Some(_) if !cfg!(feature = "mqtt") => {
+                say!("[mqtt] this build has no MQTT bridge, rebuild it with --features mqtt");
+                None
+            }
+            Some(_) if room_key.is_some() => {
+                say!("[mqtt] passphrase rooms aren't bridged to MQTT");
+                None
+            }
+            Some(mut settings) => {
+                settings.mappings.retain(|mapping| {
+                    let here = mapping.room == name;
+                    if !here {
+                        say!(
+                            "[mqtt] not mirroring {}: this node is in {name}, not {}",
+                            mapping.mqtt_topic,
+                            mapping.room
+                        );
+                    }
+                    here
+                });
+                match settings.check() {
+                    Ok(()) => {
+                        // Payloads attached to messages, kept beside the config file
+                        let spool = config_path.as_deref().map_or_else(
+                            || env::temp_dir().join("p2p-chat-mqtt"),
+                            mqtt::dir_beside,
+                        );
+                        Some(MqttBridge::spawn(settings, spool))
+                    }
+                    Err(e) => {
+                        say!("[mqtt] not bridging: {e}");
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let dtn = match DtnSettings::from_cli(cli) {
+            Some(settings) => {
+                let radio = Radio::spawn(settings, local_peer_id)?;
+                say!(
+                    "[dtn] carrying messages over the simulated Bluetooth link, advertising at {}",
+                    radio.advertisement().display()
+                );
+                Some(radio)
+            }
+            None => None,
+        };
+        let fediverse = match ActivityPubSettings::from_cli(cli) {
+            Some(settings) => {
+                let key = SigningKey::load_or_create(&settings.key)?;
+                if settings.inboxes.is_empty() {
+                    say!("[fediverse] no --activitypub-inbox, notes go nowhere and none are taken");
+                }
+                let actor = Arc::new(Actor::new(settings.actor, key));
+                Some(Fediverse::spawn(actor, settings.inboxes))
+            }
+            None => None,
+        };
+        let mut webhooks = Webhooks::default();
+        for (room, settings) in &config.webhooks {
+            if room_key.is_some() && room == topic.hash().as_str() {
+                say!("[webhook] passphrase rooms aren't forwarded to webhooks");
+                continue;
+            }
+            if let Err(e) = webhooks.add(room, settings.clone()) {
+                say!("[webhook] not forwarding {room}: {e}");
+            }
+        }
+        let gateway = match cli.irc_gateway {
+            Some(address) => {
+                let gateway = Gateway::bind(address, name)?;
+                say!(
+                    "[gateway] IRC clients can connect to {} and join {}",
+                    gateway.local_addr(),
+                    gateway.channel()
+                );
+                Some(gateway)
+            }
+            None => None,
+        };
+        let hook_server = match cli.http {
+            Some(address) => {
+                let mut hooks = config.hooks.clone();
+                hooks.retain(|hook, settings| match settings.check() {
+                    Ok(()) => true,
+                    Err(e) => {
+                        say!("[hooks] not taking posts from {hook}: {e}");
+                        false
+                    }
+                });
+                if hooks.is_empty() {
+                    say!("[hooks] no hooks in the config file, every post will be refused");
+                }
+                let inbox = fediverse.as_ref().map(|fediverse| {
+                    let senders = fediverse.inboxes().cloned().collect();
+                    Inbox::new(fediverse.actor().clone(), senders)
+                });
+                let server = HookServer::bind(address, name, cli.max_body, hooks, inbox)?;
+                say!(
+                    "[hooks] taking posts for {name} at http://{}/hooks/<token>",
+                    server.local_addr()
+                );
+                if let Some(fediverse) = &fediverse {
+                    say!(
+                        "[fediverse] serving {} and taking notes at http://{}/inbox",
+                        fediverse.actor().id(),
+                        server.local_addr()
+                    );
+                }
+                Some(server)
+            }
+            None => {
+                if fediverse.is_some() {
+                    say!(
+                        "[fediverse] without --http, notes from the fediverse can't reach the node"
+                    );
+                }
+                None
+            }
+        };
+
+        let mut rooms = Rooms::new(local_peer_id);
+        for moderator in &cli.moderator {
+            rooms.add_moderator(topic.hash().as_str(), *moderator);
+        }
+        // Without a chosen nick, the end of the PeerId is short yet still tells peers apart
+        let nick = cli.nick.clone().unwrap_or_else(|| {
+            let id = local_peer_id.to_base58();
+            id[id.len() - 6..].to_string()
+        });
+        Ok(ChatNode {
+            swarm,
+            keypair,
+            topic,
+            wire_topic,
+            control_topic,
+            board_topic,
+            tasks_topic,
+            wordle_topic,
+            canvas_topic,
+            spam_topic,
+            keyshare_topic,
+            trusted: cli.trust.iter().copied().collect(),
+            blocklist,
+            bans,
+            auto_apply: cli.auto_apply,
+            last_published: HashMap::new(),
+            nick,
+            config,
+            config_path,
+            audit,
+            disk: DiskWriter::spawn(),
+            board,
+            tasks,
+            tasks_path,
+            wordle: Wordle::default(),
+            canvas: Canvas::default(),
+            spam: SpamReportStore::new(cli.spam_threshold),
+            recovery: None,
+            // Allocated once up front; the history never grows past it
+            history: VecDeque::with_capacity(MAX_HISTORY),
+            filtered: HashMap::new(),
+            rooms,
+            decisions: Decisions::default(),
+            reports: Reports::new(local_peer_id),
+            nicks: LruMap::new(cli.max_known_nicks),
+            collisions: Collisions::default(),
+            profiles: HashMap::new(),
+            display_names: cli.display_names,
+            invite_qr: cli.qr,
+            notifier: (cfg!(feature = "notify") && !cli.no_notify).then(Notifier::desktop),
+            uninvited: HashSet::new(),
+            topic_prefix: cli.topic_prefix.clone(),
+            other_deployments: HashSet::new(),
+            impostors: HashSet::new(),
+            dial_timeout: Duration::from_secs(cli.dial_timeout),
+            connections: ConnectionManager::new(cli.max_peers as usize),
+            pings: PingScorer::default(),
+            next_ping_score: now + 60,
+            liveness: Liveness::default(),
+            redial: HashMap::new(),
+            counters: SessionCounters::default(),
+            outbound_mark: 0,
+            muxers,
+            quic_connections: HashSet::new(),
+            formats: ContentTypeNegotiator::default(),
+            started: Instant::now(),
+            last_received: None,
+            require_signed: cli.require_signed,
+            strict_topic: cli.strict_topic,
+            read_only: cli.no_publish,
+            io: cli.io,
+            signers: HashMap::new(),
+            links: VecDeque::new(),
+            next_link: 1,
+            rotation,
+            rotation_announced: None,
+            rotated: HashSet::new(),
+            room_key,
+            validator,
+            fragments: Reassembler::new(ReassemblyLimits {
+                bytes: cli.max_reassembly_bytes,
+                peer_bytes: cli.max_reassembly_peer_bytes,
+            }),
+            batcher,
+            undecryptable: HashSet::new(),
+            empty_room_reported: false,
+            presence: Presence::new(match presence_interval {
+                0 => presence::DEFAULT_INTERVAL,
+                interval => interval,
+            })
+            .with_limit(cli.max_tracked_peers),
+            presence_interval,
+            next_heartbeat: None,
+            roster: Roster::default(),
+            roster_listeners: Vec::new(),
+            event_listeners: Vec::new(),
+            snapshots_applied: 0,
+            membership: MembershipBatcher::new(cli.presence_batch),
+            verbose_presence: cli.verbose_presence,
+            away_after: (cli.away_after > 0).then(|| Duration::from_secs(cli.away_after)),
+            last_input: Instant::now(),
+            idle_away: false,
+            manual_away: None,
+            on_input_end: cli.on_stdin_eof.unwrap_or_default(),
+            status_line: None,
+            relay_server: cli.relay_server.clone(),
+            relay_listener: None,
+            relay_reserved: false,
+            relay_retry: None,
+            bootstrap: Bootstrap::new(
+                cli.bootstrap.clone(),
+                cli.bootstrap_domain.clone(),
+                cli.proxy.is_none(),
+            ),
+            listeners: HashMap::new(),
+            relisten: Vec::new(),
+            evictions: EvictionWatch::default(),
+            outbox: Outbox::default(),
+            stall: StallWatch::default(),
+            receipts: Vec::new(),
+            authenticator: None,
+            pending_auth: PendingAuth::default(),
+            content: ContentStore::default(),
+            next_attachment: None,
+            offers: Offers::default(),
+            transfers,
+            fetches: HashMap::new(),
+            nostr,
+            nostr_only: cli.nostr_only,
+            irc,
+            gateway,
+            hook_server,
+            matrix,
+            matrix_state_path,
+            xmpp,
+            mqtt,
+            fediverse,
+            dtn,
+            webhooks,
+            clock_samples: ClockSamples::default(),
+            dedup: TimedDedup::default(),
+            floods: FloodDetector::new(FloodSettings {
+                threshold: cli.repeat_threshold,
+                window: cli.repeat_window,
+                min_length: cli.repeat_min_length,
+            }),
+        })
+    }
+
+    /// The local node's PeerId.
+    pub fn local_peer_id(&self) -> PeerId {
+        *self.swarm.local_peer_id()
+    }
+
+    /// Listen for connections on `address`. Should the listener close, for example because the
+    /// network interface went away, the node listens on the address again after
+    /// [`LISTEN_RETRY`] seconds.
+    pub fn listen_on(&mut self, address: Multiaddr) -> Result<ListenerId, ChatError> {
+        let listener = self.swarm.listen_on(address.clone())?;
+        self.listeners.insert(listener, address);
+        Ok(listener)
+    }
+
+    /// Ask the relay of `--relay-server` for a reservation, so peers can dial us through it at
+    /// `<relay>/p2p-circuit/p2p/<our peer id>`. The reservation is renewed while it lasts and
+    /// requested again [`RELAY_RETRY`] seconds after it is lost. Does nothing without a relay.
+    pub fn listen_on_relay(&mut self) -> Result<(), ChatError> {
+        let Some(relay) = &self.relay_server else {
+            return Ok(());
+        };
+        let listener = self
+            .swarm
+            .listen_on(relay.clone().with(Protocol::P2pCircuit))?;
+        self.relay_listener = Some(listener);
+        self.relay_retry = None;
+        Ok(())
+    }
+
+    /// Dial the bootstrap peers of `--bootstrap`, and start looking up those published for
+    /// `--bootstrap-domain`. The published ones are dialed once found, in place of the others,
+    /// and looked up again every [`bootstrap::REFRESH_INTERVAL`] seconds.
+    pub fn bootstrap(&mut self) {
+        self.bootstrap.start_lookup(clock::unix_time());
+        self.dial_bootstrap();
+    }
+
+    /// Take the result of looking up the bootstrap peers of `--bootstrap-domain`, dialing the
+    /// ones found. When nothing was found yet, those of `--bootstrap` are dialed instead.
+    pub fn handle_bootstrap_lookup(&mut self, result: LookupResult) {
+        let domain = self.bootstrap.domain().unwrap_or_default().to_string();
+        match &result {
+            Ok(addresses) if self.bootstrap.resolved().map(|(old, _)| old) != Some(addresses) => {
+                say!(
+                    "[bootstrap] {} peers published for {domain}",
+                    addresses.len()
+                )
+            }
+            Ok(_) => {}
+            // A lookup failing the same way every retry is only said once
+            Err(e) if self.bootstrap.last_error() == Some(e) => {}
+            Err(e) if self.bootstrap.resolved().is_some() => {
+                say!("[bootstrap] can't look up {domain} again: {e}, keeping the peers found")
+            }
+            Err(e) if self.bootstrap.static_addresses().is_empty() => say!(
+                "[bootstrap] can't look up {domain}: {e}, retrying in {}s",
+                bootstrap::RETRY_INTERVAL
+            ),
+            Err(e) => say!("[bootstrap] can't look up {domain}: {e}, dialing those of --bootstrap"),
+        }
+        self.bootstrap.record(result, clock::unix_time());
+        self.dial_bootstrap();
+    }
+
+    /// The bootstrap peers, and how the lookups of `--bootstrap-domain` went.
+    pub fn bootstrap_peers(&self) -> &Bootstrap {
+        &self.bootstrap
+    }
+
+    // Dial the bootstrap peers we aren't connected to.
+    fn dial_bootstrap(&mut self) {
+        let local = self.local_peer_id();
+        for address in self.bootstrap.addresses().to_vec() {
+            let Some(peer) = bootstrap::peer_id(&address) else {
+                continue;
+            };
+            if peer == local || self.swarm.is_connected(&peer) {
+                continue;
+            }
+            let opts = DialOpts::peer_id(peer)
+                .addresses(vec![address.clone()])
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            match self.swarm.dial(opts) {
+                Ok(()) => debug!("[bootstrap] dialing {address}"),
+                Err(e) => debug!("[bootstrap] can't dial {address}: {e}"),
+            }
+        }
+    }
+
+    /// Challenge every peer that connects from now on with `authenticator`. Until a peer passes,
+    /// its messages are ignored and, if mDNS found it, it isn't made an explicit Gossipsub peer;
+    /// a peer that fails is disconnected.
+    pub fn set_authenticator(&mut self, authenticator: impl ConnectionAuthenticator) {
+        self.authenticator = Some(Authenticator::new(authenticator));
+    }
+
+    /// Peers being authenticated and those who passed.
+    pub fn pending_auth(&self) -> &PendingAuth {
+        &self.pending_auth
+    }
+
+    /// Whether the relay of `--relay-server` accepted our reservation and still holds it.
+    pub fn has_relay_reservation(&self) -> bool {
+        self.relay_reserved
+    }
+
+    /// Change how long the user may go without typing before showing as away, or turn
+    /// auto-away off with `None`, e.g. when nobody is at the keyboard to begin with.
+    pub fn set_away_after(&mut self, after: Option<Duration>) {
+        self.away_after = after;
+        self.idle_away = false;
+    }
+
+    /// Change what [`ChatNode::run`] does once its input ends, e.g. to exit when the input is
+    /// a pipe that nobody will type into again.
+    pub fn set_on_input_end(&mut self, on_end: StdinEof) {
+        self.on_input_end = on_end;
+    }
+
+    /// Whether peers are told we are away, by `/status` or after `--away-after` without input.
+    pub fn is_away(&self) -> bool {
+        self.manual_away.unwrap_or(self.idle_away)
+    }
+
+    /// Whether do not disturb is on, set with `/dnd` now or in an earlier run. Peers are told,
+    /// and embedders should keep quiet about new messages while it is.
+    pub fn is_dnd(&self) -> bool {
+        self.config
+            .dnd
+            .is_some_and(|dnd| !dnd.is_over(clock::unix_time()))
+    }
+
+    /// Show notifications through `notifier` rather than on the desktop.
+    pub fn set_notifier(&mut self, notifier: Notifier) {
+        self.notifier = Some(notifier);
+    }
+
+    /// Change how long [`ChatNode::connect_to`] waits for a connection.
+    pub fn set_dial_timeout(&mut self, timeout: Duration) {
+        self.dial_timeout = timeout;
+    }
+
+    /// Dial `addr` and wait until the connection is established or has failed.
+    ///
+    /// If the address ends in `/p2p/<peer id>`, the remote must prove that identity. Being
+    /// connected to that peer already counts as success. Swarm events that arrive meanwhile
+    /// are handled as usual.
+    pub async fn connect_to(&mut self, addr: Multiaddr) -> Result<(), DialError> {
+        let opts = match addr.iter().last() {
+            Some(Protocol::P2p(peer)) if peer == self.local_peer_id() => {
+                return Err(DialError::SelfDial)
+            }
+            Some(Protocol::P2p(peer)) => DialOpts::peer_id(peer)
+                .addresses(vec![addr])
+                .condition(PeerCondition::Disconnected)
+                .build(),
+            _ => DialOpts::unknown_peer_id().address(addr).build(),
+        };
+        let connection = opts.connection_id();
+        match self.swarm.dial(opts) {
+            Err(libp2p::swarm::DialError::DialPeerConditionFalse(_)) => return Ok(()),
+            result => result?,
+        }
+
+        let outcome = runtime::timeout(self.dial_timeout, async {
+            loop {
+                match self.swarm.select_next_some().await {
+                    event @ SwarmEvent::ConnectionEstablished { connection_id, .. }
+                        if connection_id == connection =>
+                    {
+                        self.handle_event(event);
+                        return Ok(());
+                    }
+                    SwarmEvent::OutgoingConnectionError {
+                        connection_id,
+                        error,
+                        ..
+                    } if connection_id == connection => return Err(DialError::Swarm(error)),
+                    event => self.handle_event(event),
+                }
+            }
+        });
+        outcome
+            .await
+            .unwrap_or(Err(DialError::Timeout(self.dial_timeout)))
+    }
+
+    /// The chat topic.
+    pub fn topic(&self) -> &gossipsub::IdentTopic {
+        &self.topic
+    }
+
+    /// The topic chat messages travel under: the chat topic, or the alias its moderators gave
+    /// it.
+    pub fn wire_topic(&self) -> &gossipsub::IdentTopic {
+        &self.wire_topic
+    }
+
+    // The name of the room whose messages travel under `topic`, which is the topic's own name
+    // unless it is the room's alias.
+    fn room_of(&self, topic: &gossipsub::TopicHash) -> String {
+        match *topic == self.wire_topic.hash() {
+            true => self.topic.hash().into_string(),
+            false => topic.to_string(),
+        }
+    }
+
+    /// Blocked peers and received blocklist updates.
+    pub fn blocklist(&self) -> &Blocklist {
+        &self.blocklist
+    }
+
+    /// The nick sent along with our chat messages.
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// Received chat messages, oldest first, including those hidden by a filter.
+    pub fn history(&self) -> impl Iterator<Item = &StoredMessage> {
+        self.history.iter()
+    }
+
+    /// The profile `peer` published, sanitized, or our own for the local peer id.
+    pub fn profile(&self, peer: &PeerId) -> Option<&Profile> {
+        if *peer == self.local_peer_id() {
+            return Some(&self.config.profile);
+        }
+        self.profiles.get(peer)
+    }
+
+    /// Round-trip times to connected peers and the score adjustments they earned.
+    pub fn pings(&self) -> &PingScorer {
+        &self.pings
+    }
+
+    /// Reports received as a moderator.
+    pub fn reports(&self) -> &Reports {
+        &self.reports
+    }
+
+    /// When peers were last heard from in each room.
+    pub fn presence(&self) -> &Presence {
+        &self.presence
+    }
+
+    /// The posts on the room's board.
+    pub fn board(&self) -> &BulletinBoard {
+        &self.board
+    }
+
+    /// The room's shared task list.
+    pub fn tasks(&self) -> &TaskList {
+        &self.tasks
+    }
+
+    /// The room's Wordle games.
+    pub fn wordle(&self) -> &Wordle {
+        &self.wordle
+    }
+
+    /// The room's shared canvas.
+    pub fn canvas(&self) -> &Canvas {
+        &self.canvas
+    }
+
+    /// The spam reports of the room.
+    pub fn spam(&self) -> &SpamReportStore {
+        &self.spam
+    }
+
+    /// The peers saved with `/contact add`.
+    pub fn contacts(&self) -> &Contacts {
+        &self.config.contacts
+    }
+
+    /// The shares of other peers' room keys dealt to us, sealed to our identity.
+    pub fn key_shares(&self) -> &[KeptShare] {
+        &self.config.key_shares
+    }
+
+    /// Who is online in each room.
+    pub fn roster(&self) -> &Roster {
+        &self.roster
+    }
+
+    /// Receive every arrival in and departure from a room's roster, and peers going away or
+    /// coming back, e.g. to greet newcomers from a bot. Changes are sent from [`ChatNode::tick`].
+    pub fn subscribe_roster(&mut self) -> mpsc::UnboundedReceiver<RosterEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.roster_listeners.push(sender);
+        receiver
+    }
+
+    /// Receive what happens as [`ChatEvent`]s, as written to stdout with `--io json`: messages,
+    /// connections, roster changes, and the replies to JSON commands.
+    pub fn subscribe_events(&mut self) -> mpsc::UnboundedReceiver<ChatEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.event_listeners.push(sender);
+        receiver
+    }
+
+    /// Messages the history can hold without reallocating.
+    pub fn history_capacity(&self) -> usize {
+        self.history.capacity()
+    }
+
+    /// Fingerprints of recent messages, used to estimate duplicates.
+    pub fn dedup(&self) -> &TimedDedup {
+        &self.dedup
+    }
+
+    /// The display filter for the current topic, if one is set.
+    pub fn filter(&self) -> Option<&TopicFilter> {
+        self.config.filters.get(self.topic.hash().as_str())
+    }
+
+    /// Number of messages on the current topic hidden by its filter.
+    pub fn filtered_count(&self) -> u64 {
+        self.filtered
+            .get(self.topic.hash().as_str())
+            .map_or(0, |count| count.hidden)
+    }
+
+    /// Whether `peer` is blocked, either by the blocklist or by an automatic ban.
+    pub fn is_blocked(&self, peer: &PeerId) -> bool {
+        self.blocklist.is_blocked(peer) || self.bans.is_banned(peer)
+    }
+
+    /// Room bans proposed in the current room, waiting for a quorum of moderators.
+    pub fn proposals(&self) -> impl Iterator<Item = &Moderation> {
+        let room = self.topic.hash().into_string();
+        self.decisions
+            .pending()
+            .map(|(_, pending)| &pending.moderation)
+            .filter(move |moderation| moderation.room == room)
+    }
+
+    /// Whether a moderator removed `peer` from the current room.
+    pub fn is_removed(&self, peer: &PeerId) -> bool {
+        let room = self.topic.hash().into_string();
+        self.rooms
+            .is_ignored(&room, peer, self.config.rooms.get(&room))
+    }
+
+    /// The public key that signed `peer`'s messages, once a signed message from it has been
+    /// verified, along with how many were.
+    pub fn verified_key(&self, peer: &PeerId) -> Option<(PublicKey, u64)> {
+        let count = *self.signers.get(peer)?;
+        Some((signed::peer_public_key(peer)?, count))
+    }
+
+    /// Active automatic bans.
+    pub fn bans(&self) -> &AutoBanner {
+        &self.bans
+    }
+
+    /// The peers we know, from connections, Gossipsub and their messages, and how we are
+    /// connected to each.
+    pub fn topology(&self) -> Topology {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let mesh: HashSet<&PeerId> = gossipsub.all_mesh_peers().collect();
+        let mut known: Vec<PeerId> = self
+            .swarm
+            .connected_peers()
+            .chain(gossipsub.all_peers().map(|(peer, _)| peer))
+            .chain(self.nicks.keys())
+            .copied()
+            .collect();
+        known.sort();
+        known.dedup();
+        let peers = known
+            .into_iter()
+            .map(|peer| TopologyPeer {
+                peer,
+                name: self.display_name(&peer),
+                score: gossipsub.peer_score(&peer),
+                link: if mesh.contains(&peer) {
+                    Some(TopologyLink::Mesh)
+                } else if self.swarm.is_connected(&peer) {
+                    Some(TopologyLink::Metadata)
+                } else {
+                    None
+                },
+            })
+            .collect();
+        Topology {
+            local: TopologyPeer {
+                peer: self.local_peer_id(),
+                name: self.nick.clone(),
+                score: None,
+                link: None,
+            },
+            peers,
+        }
+    }
+
+    /// Sizes of the structures peers can make grow, against their limits (`/stats memory`).
+    pub fn memory(&self) -> MemoryReport {
+        let limits = self.fragments.limits();
+        MemoryReport {
+            structures: vec![
+                Usage {
+                    name: "peer registry",
+                    flag: "--max-tracked-peers",
+                    entries: self.presence.len(),
+                    max_entries: self.presence.limit(),
+                    bytes: None,
+                    peer_bytes: None,
+                    evictions: self.presence.evictions(),
+                },
+                Usage {
+                    name: "nick cache",
+                    flag: "--max-known-nicks",
+                    entries: self.nicks.len(),
+                    max_entries: self.nicks.limit(),
+                    bytes: None,
+                    peer_bytes: None,
+                    evictions: self.nicks.evictions(),
+                },
+                Usage {
+                    name: "fragment reassembly",
+                    flag: "--max-reassembly-bytes",
+                    entries: self.fragments.pending(),
+                    max_entries: fragment::MAX_BUFFERS,
+                    bytes: Some((self.fragments.bytes(), limits.bytes)),
+                    peer_bytes: Some((self.fragments.largest_peer_bytes(), limits.peer_bytes)),
+                    evictions: self.fragments.evictions(),
+                },
+            ],
+        }
+    }
+
+    /// Snapshot of the Gossipsub mesh and the session's message counters.
+    pub fn stats(&self) -> NetworkStats {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let mut topics: Vec<TopicStats> = gossipsub
+            .topics()
+            .map(|topic| TopicStats {
+                topic: topic.clone(),
+                mesh_peers: gossipsub.mesh_peers(topic).count(),
+                subscribed_peers: gossipsub
+                    .all_peers()
+                    .filter(|(_, topics)| topics.contains(&topic))
+                    .count(),
+            })
+            .collect();
+        topics.sort_by(|a, b| a.topic.as_str().cmp(b.topic.as_str()));
+        // Peer scores are only tracked when scoring is enabled
+        let peer_scores = gossipsub
+            .all_peers()
+            .filter_map(|(peer, _)| Some((*peer, gossipsub.peer_score(peer)?)))
+            .collect();
+        NetworkStats {
+            topics,
+            counters: SessionCounters {
+                yamux_connections: self.muxers.yamux(),
+                mplex_connections: self.muxers.mplex(),
+                ..self.counters
+            },
+            peer_scores,
+        }
+    }
+
+    /// Check whether the node is listening and connected to anyone.
+    pub fn health(&self) -> HealthStatus {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        HealthStatus {
+            listening: self.swarm.listeners().next().is_some(),
+            peer_count: self.swarm.connected_peers().count(),
+            mesh_peer_count_per_topic: gossipsub
+                .topics()
+                .map(|topic| (topic.to_string(), gossipsub.mesh_peers(topic).count()))
+                .collect(),
+            bytes_received: self.counters.bytes_received,
+            bytes_sent: self.counters.bytes_sent,
+            uptime: self.started.elapsed(),
+            last_message_received: self.last_received,
+        }
+    }
+
+    /// Mark `peer` as trusted, so its shared blocklist updates are accepted.
+    pub fn trust(&mut self, peer: PeerId) {
+        self.trusted.insert(peer);
+    }
+
+    /// Handle a line typed by the user: either a slash command or a chat message. Any line
+    /// counts as activity and ends auto-away.
+    pub async fn handle_line(&mut self, line: &str) {
+        let was_away = self.is_away();
+        self.last_input = Instant::now();
+        self.idle_away = false;
+        if self.io == IoMode::Json {
+            self.announce_away(was_away);
+            return self.handle_request(line).await;
+        }
+        match commands::parse(line) {
+            Some(Ok(UserCommand::Markdown(text))) if !self.read_only => {
+                self.announce_away(was_away);
+                return self.send_chat(&text, Some(ContentType::Markdown)).await;
+            }
+            Some(Ok(command)) => self.run_command(command),
+            Some(Err(e)) => say!("{e}"),
+            // Lines typed in read-only mode are notes for ourselves
+            None if self.read_only => say!("[note] {}", sanitize::line(line)),
+            None => {
+                // Say we are back before the slow publish of the message
+                self.announce_away(was_away);
+                return self.send_chat(line, None).await;
+            }
+        }
+        self.announce_away(was_away);
+    }
+
+    /// Publish a chat message to the chat topic, its body written in `content_type` if not
+    /// plain text.
+    async fn send_chat(&mut self, line: &str, content_type: Option<ContentType>) {
+        // Give peers time to connect before sending the first message. The event loop holds
+        // input back until then rather than sleeping here, so this only waits for direct callers.
+        runtime::sleep_until(self.started + CONNECT_GRACE).await;
+
+        // Peers would ignore a longer message anyway
+        if line.len() > self.validator.max_body() {
+            say!(
+                "Message not sent: {} bytes is over the limit of {} (--max-body)",
+                line.len(),
+                self.validator.max_body()
+            );
+            return;
+        }
+
+        let message = ChatMessage {
+            nick: self.nick.clone(),
+            body: line.into(),
+            timestamp: clock::unix_time(),
+            attachment: self.next_attachment.take(),
+            origin: None,
+            content_type,
+        };
+        if let Some(gateway) = &mut self.gateway {
+            gateway.deliver(&message.nick, &message.body);
+        }
+        self.send_message(message);
+    }
+
+    // Run a JSON command from `--io json` and reply with its outcome, echoing its id.
+    async fn handle_request(&mut self, line: &str) {
+        let (id, command) = stdio::parse(line);
+        let outcome = match command {
+            Ok(ChatCommand::Publish { text }) => self.publish_text(&text).await.map(|()| None),
+            Ok(ChatCommand::Join { invite }) => self
+                .join_with(&invite)
+                .map(|()| None)
+                .map_err(|e| e.to_string()),
+            Ok(ChatCommand::Dial { address }) => self
+                .connect_to(address)
+                .await
+                .map(|()| None)
+                .map_err(|e| e.to_string()),
+            Ok(ChatCommand::Query { what }) => Ok(Some(self.query(what))),
+            Err(error) => Err(error),
+        };
+        self.emit(match outcome {
+            Ok(result) => ChatEvent::Done { id, result },
+            Err(error) => ChatEvent::Error { id, error },
+        });
+    }
+
+    // Publish `text` for a JSON command, saying why it can't be sent rather than printing it.
+    async fn publish_text(&mut self, text: &str) -> Result<(), String> {
+        if self.read_only {
+            return Err("nothing can be sent with --no-publish".to_string());
+        }
+        if text.len() > self.validator.max_body() {
+            return Err(format!(
+                "{} bytes is over the limit of {} (--max-body)",
+                text.len(),
+                self.validator.max_body()
+            ));
+        }
+        self.send_chat(text, None).await;
+        Ok(())
+    }
+
+    // The answer to a JSON query.
+    fn query(&self, what: Query) -> Value {
+        match what {
+            Query::Peers => {
+                let now = clock::unix_time();
+                let room = self.topic.hash().into_string();
+                let peers: Vec<Value> = self
+                    .presence
+                    .room(&room, now)
+                    .into_iter()
+                    .map(|(peer, status, seen_at)| {
+                        json!({
+                            "peer": peer,
+                            "nick": self.display_name(&peer),
+                            "status": status.to_string(),
+                            "away": self.presence.is_away(&room, &peer),
+                            "dnd": self.presence.is_dnd(&room, &peer),
+                            "status_line": self.presence.status_line(&room, &peer, now),
+                            "last_seen": seen_at,
+                        })
+                    })
+                    .collect();
+                json!(peers)
+            }
+            Query::History => {
+                let messages: Vec<ChatEvent> =
+                    self.history.iter().map(ChatEvent::message).collect();
+                json!(messages)
+            }
+            Query::Stats => {
+                let health = self.health();
+                json!({
+                    "listening": health.listening,
+                    "peers": health.peer_count,
+                    "mesh_peers": health.mesh_peer_count_per_topic,
+                    "published": self.counters.published,
+                    "received": self.counters.received,
+                    "bytes_sent": health.bytes_sent,
+                    "bytes_received": health.bytes_received,
+                    "uptime": health.uptime.as_secs(),
+                })
+            }
+        }
+    }
+
+    /// Join the invite-only room with a token from `/invite create`, as `--join-with` does at
+    /// startup, and present the invite to the members.
+    pub fn join_with(&mut self, token: &str) -> Result<(), ChatError> {
+        let room = self.topic.hash().into_string();
+        let local_peer_id = self.local_peer_id();
+        accept_invite(&mut self.config, &room, token, local_peer_id)?;
+        self.save_config();
+        self.audit(local_peer_id, AuditEvent::InviteUsed { room });
+        self.announce_join();
+        Ok(())
+    }
+
+    // Tell whoever subscribed to events, dropping those who stopped listening.
+    fn emit(&mut self, event: ChatEvent) {
+        self.event_listeners
+            .retain(|listener| listener.send(event.clone()).is_ok());
+    }
+
+    // Send a message of ours to the IRC bridge, the Nostr relay and the room.
+    fn send_message(&mut self, message: ChatMessage) {
+        if !self.read_only {
+            self.relay_to_irc(&message.nick, &message);
+            self.relay_to_matrix(&message.nick, &message);
+            self.relay_to_xmpp(&message.nick, &message);
+            self.relay_to_mqtt(&message.nick, &message);
+            self.relay_to_fediverse(&message.nick, &message);
+            self.carry_over_dtn(&message);
+            let (room, own) = (self.topic.hash().into_string(), self.local_peer_id());
+            log_transcript(Some(&own), &room, &message);
+            self.forward_to_webhook(&room, &own, &message.nick, &message);
+        }
+        if self.nostr.is_some() {
+            self.publish_note(&message);
+            if self.nostr_only {
+                return;
+            }
+        }
+        self.publish_chat(message);
+    }
+
+    // Publish a chat message. If an error occurs while publishing it, print the error. With
+    // nobody in the room yet, the message waits for someone to join instead.
+    fn publish_chat(&mut self, message: ChatMessage) {
+        match self.publish_or_batch(message.clone()) {
+            Ok(()) => {}
+            Err(ChatError::Gossipsub(gossipsub::PublishError::InsufficientPeers))
+                if self.batcher.is_none() =>
+            {
+                self.hold(&message)
+            }
+            Err(e) => say!("Publish error: {e}"),
+        }
+    }
+
+    // Queue a chat message nobody could receive, to go out once someone joins.
+    // It is encoded when it goes out, in a format every peer in the room by then reads.
+    fn hold(&mut self, message: &ChatMessage) {
+        self.outbox
+            .hold(self.wire_topic.hash(), message.clone(), Instant::now());
+        say!(
+            "[queued] no peers in the room yet, sending once someone joins (for up to {} s)",
+            PUBLISH_TIMEOUT.as_secs()
+        );
+    }
+
+    // Publish a chat message to the Nostr relay as a text note. Attachments aren't bridged:
+    // only peers on the libp2p network could fetch them.
+    fn publish_note(&mut self, message: &ChatMessage) {
+        let Some(relay) = &self.nostr else {
+            return;
+        };
+        if self.read_only {
+            if self.nostr_only {
+                say!("Publish error: {}", ChatError::ReadOnlyMode);
+            }
+            return;
+        }
+        match relay.publish(&message.body, message.timestamp) {
+            Ok(_) => self.counters.notes_published += 1,
+            Err(e) => say!("[nostr] not sent to the relay: {e}"),
+        }
+    }
+
+    /// Handle news from the Nostr relay: show the notes others tagged with the room's
+    /// hashtag like chat messages, through the room's filter, and say when the relay is lost
+    /// or refuses a note.
+    pub fn handle_relay_event(&mut self, event: RelayEvent) {
+        let Some(relay) = &self.nostr else {
+            return;
+        };
+        let url = relay.url();
+        match event {
+            RelayEvent::Connected => {
+                say!("[nostr] connected to {url}, following #{}", relay.hashtag())
+            }
+            RelayEvent::Disconnected(e) => {
+                say!("[nostr] no connection to {url}: {e}, trying again")
+            }
+            RelayEvent::Rejected { id, reason } => say!(
+                "[nostr] {url} refused note {}: {}",
+                short_note_id(&id),
+                sanitize::line(&reason)
+            ),
+            RelayEvent::Notice(notice) => {
+                say!("[nostr] notice from {url}: {}", sanitize::line(&notice))
+            }
+            RelayEvent::Note(note) => {
+                let chat = ChatMessage {
+                    nick: format!("nostr:{}", short_note_id(&note.pubkey)),
+                    body: note.content.into(),
+                    timestamp: note.created_at,
+                    attachment: None,
+                    origin: None,
+                    content_type: None,
+                };
+                let topic = self.topic.hash().into_string();
+                let shown = self
+                    .config
+                    .filters
+                    .get(&topic)
+                    .is_none_or(|filter| filter.matches(&chat));
+                if !shown {
+                    self.filtered.entry(topic).or_default().hidden += 1;
+                    return;
+                }
+                self.counters.notes_received += 1;
+                // Links from Nostr are held back like those of peers we don't trust
+                let (body, _) = sanitize::body(&chat.body);
+                say!(
+                    "Got note: '{body}' from {} with id: {} from relay: {url}",
+                    chat.nick,
+                    short_note_id(&note.id)
+                );
+            }
+        }
+    }
+
+    // Say a chat message on the IRC channel, unless a bridge copied it in from elsewhere.
+    // Attachments aren't bridged, like on Nostr.
+    fn relay_to_irc(&mut self, nick: &str, message: &ChatMessage) {
+        let Some(bridge) = &self.irc else {
+            return;
+        };
+        if message.origin.is_some() {
+            return;
+        }
+        match bridge.relay(nick, &message.body) {
+            Ok(()) => self.counters.irc_sent += 1,
+            Err(e) => say!("[irc] not sent to {}: {e}", bridge.settings().channel),
+        }
+    }
+
+    /// Handle news from the IRC channel: publish what is said there to the room, prefixed
+    /// with the IRC nick and marked as copied from IRC, announce joins and parts if the bridge
+    /// is set to, and say when the server is lost.
+    pub fn handle_irc_event(&mut self, event: IrcEvent) {
+        let Some(bridge) = &self.irc else {
+            return;
+        };
+        let settings = bridge.settings();
+        let (server, channel) = (&settings.server, &settings.channel);
+        let body = match event {
+            IrcEvent::Connected { nick } => {
+                return say!(
+                    "[irc] joined {channel} on {server} as {}",
+                    sanitize::line(&nick)
+                );
+            }
+            IrcEvent::NickInUse { taken, trying } => {
+                return say!(
+                    "[irc] {} is taken on {server}, trying {}",
+                    sanitize::line(&taken),
+                    sanitize::line(&trying)
+                );
+            }
+            IrcEvent::Disconnected(e) => {
+                return say!(
+                    "[irc] no connection to {server}: {}, trying again",
+                    sanitize::line(&e)
+                );
+            }
+            IrcEvent::Message { nick, text } => format!("<{nick}> {text}"),
+            IrcEvent::Action { nick, text } => format!("* {nick} {text}"),
+            IrcEvent::Joined(nick) if settings.announce => {
+                format!("* {nick} joined {channel} on IRC")
+            }
+            IrcEvent::Left { nick, reason } if settings.announce => match reason.as_str() {
+                "" => format!("* {nick} left {channel} on IRC"),
+                reason => format!("* {nick} left {channel} on IRC ({reason})"),
+            },
+            IrcEvent::Joined(_) | IrcEvent::Left { .. } => return,
+        };
+        let message = ChatMessage {
+            nick: self.nick.clone(),
+            body: body.into(),
+            timestamp: clock::unix_time(),
+            attachment: None,
+            origin: Some(irc::ORIGIN.to_string()),
+            content_type: None,
+        };
+        // Links from IRC are held back like those of peers we don't trust
+        let (shown, _) = sanitize::body(&message.body);
+        say!("[irc] {shown}");
+        self.counters.irc_received += 1;
+        if let Some(gateway) = &mut self.gateway {
+            gateway.deliver(&message.nick, &message.body);
+        }
+        if !self.read_only {
+            self.publish_chat(message);
+        }
+    }
+
+    /// Handle what happens on an IRC gateway client's connection: publish what the client
+    /// says in the channel to the room under its nick, and say when clients come and go.
+    pub fn handle_gateway_event(&mut self, event: ClientEvent) {
+        let Some(gateway) = &mut self.gateway else {
+            return;
+        };
+        match gateway.handle(event) {
+            Some(GatewayEvent::Registered { nick, address }) => {
+                say!("[gateway] {address} connected as {nick}");
+            }
+            Some(GatewayEvent::Left { nick, reason }) => {
+                say!("[gateway] {nick} left: {}", sanitize::line(&reason));
+            }
+            Some(GatewayEvent::Said { nick, text }) => {
+                let (shown, _) = sanitize::body(&text);
+                say!("[gateway] <{nick}> {shown}");
+                if self.read_only {
+                    return say!("[gateway] not sent: this node is read-only");
+                }
+                if text.len() > self.validator.max_body() {
+                    return say!(
+                        "[gateway] not sent: {} bytes is over the limit of {} (--max-body)",
+                        text.len(),
+                        self.validator.max_body()
+                    );
+                }
+                let message = ChatMessage {
+                    nick,
+                    body: text.into(),
+                    timestamp: clock::unix_time(),
+                    attachment: None,
+                    origin: None,
+                    content_type: None,
+                };
+                self.send_message(message);
+            }
+            None => {}
+        }
+    }
+
+    /// Handle what happens on the HTTP server: publish what a hook posted, marked as posted
+    /// through a hook, and notes from the fediverse, marked as copied from it, and report
+    /// requests turned away.
+    pub fn handle_hook_event(&mut self, event: HookEvent) {
+        match event {
+            HookEvent::Posted { hook, nick, text } => {
+                let (shown, _) = sanitize::body(&text);
+                say!("[hooks] {hook}: <{nick}> {shown}");
+                self.counters.hook_posted += 1;
+                let message = ChatMessage {
+                    nick,
+                    body: text.into(),
+                    timestamp: clock::unix_time(),
+                    attachment: None,
+                    origin: Some(hooks::ORIGIN.to_string()),
+                    content_type: None,
+                };
+                if let Some(gateway) = &mut self.gateway {
+                    gateway.deliver(&message.nick, &message.body);
+                }
+                self.publish_chat(message);
+            }
+            HookEvent::Note { actor, text } => {
+                let (shown, _) = sanitize::body(&text);
+                say!("[fediverse] {actor}: {shown}");
+                self.counters.fediverse_received += 1;
+                let message = ChatMessage {
+                    nick: self.nick.clone(),
+                    body: format!("[fediverse] {actor}: {text}").into(),
+                    timestamp: clock::unix_time(),
+                    attachment: None,
+                    origin: Some(activitypub::ORIGIN.to_string()),
+                    content_type: None,
+                };
+                if let Some(gateway) = &mut self.gateway {
+                    gateway.deliver(&message.nick, &message.body);
+                }
+                self.publish_chat(message);
+            }
+            HookEvent::Rejected {
+                address,
+                status,
+                error,
+            } => {
+                say!(
+                    "[hooks] refused a post from {address} ({status}): {}",
+                    sanitize::line(&error)
+                );
+                self.counters.hook_rejected += 1;
+            }
+        }
+    }
+
+    // Queue a message `sender` sent to `room` as `nick` for the room's webhook, if it has one
+    // that wants the message.
+    fn forward_to_webhook(
+        &mut self,
+        room: &str,
+        sender: &PeerId,
+        nick: &str,
+        message: &ChatMessage,
+    ) {
+        let forwarded = Forwarded {
+            nick: nick.to_string(),
+            room: room.to_string(),
+            timestamp: message.timestamp,
+            body: message.body.to_string(),
+        };
+        if let Err(e) = self.webhooks.forward(sender, forwarded, &self.nick) {
+            self.counters.webhook_dropped += 1;
+            debug!("[webhook] {room}: message dropped: {e}");
+        }
+    }
+
+    /// Handle news of the messages forwarded to webhooks, counting them for `/stats` and
+    /// saying when they are given up on.
+    pub fn handle_webhook_event(&mut self, event: WebhookEvent) {
+        match event {
+            WebhookEvent::Delivered { room, messages } => {
+                self.counters.webhook_delivered += messages as u64;
+                debug!("[webhook] {room}: {messages} message(s) delivered");
+            }
+            WebhookEvent::Failed {
+                room,
+                messages,
+                error,
+            } => {
+                self.counters.webhook_failed += messages as u64;
+                say!("[webhook] {room}: {messages} message(s) not delivered: {error}");
+            }
+            WebhookEvent::Opened { room } => say!(
+                "[webhook] {room}: the webhook keeps failing, dropping its messages for {}s",
+                webhook::BREAKER_COOLDOWN.as_secs()
+            ),
+            WebhookEvent::Closed { room } => {
+                say!("[webhook] {room}: the webhook is taking messages again")
+            }
+        }
+    }
+
+    /// The webhooks rooms' messages are forwarded to.
+    pub fn webhooks(&self) -> &Webhooks {
+        &self.webhooks
+    }
+
+    /// Wait for news of the messages forwarded to webhooks, to pass to
+    /// [`ChatNode::handle_webhook_event`] when driving the node without [`ChatNode::run`].
+    /// Never resolves without a webhook.
+    pub async fn next_webhook_event(&mut self) -> Option<WebhookEvent> {
+        self.webhooks.next().await
+    }
+
+    /// The HTTP server hooks post messages for the room to, if any.
+    pub fn hook_server(&self) -> Option<&HookServer> {
+        self.hook_server.as_ref()
+    }
+
+    /// Wait for what happens on the HTTP server, to pass to [`ChatNode::handle_hook_event`]
+    /// when driving the node without [`ChatNode::run`]. Never resolves without a server.
+    pub async fn next_hook_event(&mut self) -> Option<HookEvent> {
+        next_hook_event(&mut self.hook_server).await
+    }
+
+    /// The IRC server local IRC clients chat in the room through, if any.
+    pub fn gateway(&self) -> Option<&Gateway> {
+        self.gateway.as_ref()
+    }
+
+    /// Wait for what happens on the IRC gateway's connections, to pass to
+    /// [`ChatNode::handle_gateway_event`] when driving the node without [`ChatNode::run`].
+    /// Never resolves without a gateway.
+    pub async fn next_gateway_event(&mut self) -> Option<ClientEvent> {
+        next_gateway_event(&mut self.gateway).await
+    }
+
+    // Post a chat message to the Matrix room, unless a bridge copied it in from elsewhere.
+    // Attachments aren't bridged, like on IRC.
+    fn relay_to_matrix(&mut self, nick: &str, message: &ChatMessage) {
+        let Some(bridge) = &self.matrix else {
+            return;
+        };
+        if message.origin.is_some() {
+            return;
+        }
+        match bridge.relay(nick, &message.body) {
+            Ok(()) => self.counters.matrix_sent += 1,
+            Err(e) => say!("[matrix] not sent to {}: {e}", bridge.settings().room),
+        }
+    }
+
+    /// Handle news from the Matrix room: publish what is said there to the room, prefixed
+    /// with the sender and marked as copied from Matrix, save how far the bridge has synced,
+    /// and say when the homeserver is lost.
+    pub fn handle_matrix_event(&mut self, event: MatrixEvent) {
+        let Some(bridge) = &self.matrix else {
+            return;
+        };
+        let settings = bridge.settings();
+        let (homeserver, room) = (&settings.homeserver, &settings.room);
+        let body = match event {
+            MatrixEvent::Connected { user_id, room_id } => {
+                return say!(
+                    "[matrix] joined {room} ({}) on {homeserver} as {}",
+                    sanitize::line(&room_id),
+                    sanitize::line(&user_id)
+                );
+            }
+            MatrixEvent::Disconnected(e) => {
+                return say!(
+                    "[matrix] no connection to {homeserver}: {}, trying again",
+                    sanitize::line(&e)
+                );
+            }
+            MatrixEvent::Synced(state) => {
+                let Some(path) = self.matrix_state_path.clone() else {
+                    return;
+                };
+                return self.disk.submit(move || {
+                    if let Err(e) = state.save(&path) {
+                        say!("[matrix] failed to save the sync token: {e}");
+                    }
+                });
+            }
+            MatrixEvent::Message { sender, body } => format!("[matrix] {sender}: {body}"),
+            MatrixEvent::Emote { sender, body } => format!("[matrix] * {sender} {body}"),
+        };
+        let message = ChatMessage {
+            nick: self.nick.clone(),
+            body: body.into(),
+            timestamp: clock::unix_time(),
+            attachment: None,
+            origin: Some(matrix::ORIGIN.to_string()),
+            content_type: None,
+        };
+        // Links from Matrix are held back like those of peers we don't trust
+        let (shown, _) = sanitize::body(&message.body);
+        say!("{shown}");
+        self.counters.matrix_received += 1;
+        if let Some(gateway) = &mut self.gateway {
+            gateway.deliver(&message.nick, &message.body);
+        }
+        if !self.read_only {
+            self.publish_chat(message);
+        }
+    }
+
+    /// The Matrix room mirrored into the room, if any.
+    pub fn matrix(&self) -> Option<&MatrixBridge> {
+        self.matrix.as_ref()
+    }
+
+    /// Wait for news from the Matrix room, to pass to [`ChatNode::handle_matrix_event`] when
+    /// driving the node without [`ChatNode::run`]. Never resolves without a bridge.
+    pub async fn next_matrix_event(&mut self) -> Option<MatrixEvent> {
+        next_matrix_event(&mut self.matrix).await
+    }
+
+    // Say a chat message in the XMPP room, unless a bridge copied it in from elsewhere.
+    // Attachments aren't bridged, like on IRC.
+    fn relay_to_xmpp(&mut self, nick: &str, message: &ChatMessage) {
+        let Some(bridge) = &self.xmpp else {
+            return;
+        };
+        if message.origin.is_some() {
+            return;
+        }
+        match bridge.relay(nick, &message.body) {
+            Ok(()) => self.counters.xmpp_sent += 1,
+            Err(e) => say!("[xmpp] not sent to {}: {e}", bridge.settings().room),
+        }
+    }
+
+    /// Handle news from the XMPP room: publish what is said there, and who joins and leaves
+    /// it, to the room, marked as copied from XMPP, and say when the server is lost.
+    pub fn handle_xmpp_event(&mut self, event: XmppEvent) {
+        let Some(bridge) = &self.xmpp else {
+            return;
+        };
+        let settings = bridge.settings();
+        let (server, room) = (&settings.server, &settings.room);
+        let body = match event {
+            XmppEvent::Connected { jid, nick } => {
+                return say!(
+                    "[xmpp] joined {room} on {server} as {} ({})",
+                    sanitize::line(&nick),
+                    sanitize::line(&jid)
+                );
+            }
+            XmppEvent::Disconnected(e) => {
+                return say!(
+                    "[xmpp] no connection to {server}: {}, trying again",
+                    sanitize::line(&e)
+                );
+            }
+            XmppEvent::Message { nick, text } => format!("<{nick}> {text}"),
+            XmppEvent::Action { nick, text } => format!("* {nick} {text}"),
+            XmppEvent::Joined(nick) => format!("* {nick} joined {room} on XMPP"),
+            XmppEvent::Left(nick) => format!("* {nick} left {room} on XMPP"),
+        };
+        let message = ChatMessage {
+            nick: self.nick.clone(),
+            body: body.into(),
+            timestamp: clock::unix_time(),
+            attachment: None,
+            origin: Some(xmpp::ORIGIN.to_string()),
+            content_type: None,
+        };
+        // Links from XMPP are held back like those of peers we don't trust
+        let (shown, _) = sanitize::body(&message.body);
+        say!("[xmpp] {shown}");
+        self.counters.xmpp_received += 1;
+        if let Some(gateway) = &mut self.gateway {
+            gateway.deliver(&message.nick, &message.body);
+        }
+        if !self.read_only {
+            self.publish_chat(message);
+        }
+    }
+
+    /// The XMPP room mirrored into the room, if any.
+    pub fn xmpp(&self) -> Option<&XmppBridge> {
+        self.xmpp.as_ref()
+    }
+
+    /// Wait for news from the XMPP room, to pass to [`ChatNode::handle_xmpp_event`] when
+    /// driving the node without [`ChatNode::run`]. Never resolves without a bridge.
+    pub async fn next_xmpp_event(&mut self) -> Option<XmppEvent> {
+        next_xmpp_event(&mut self.xmpp).await
+    }
+
+    // Publish a chat message to the MQTT topics mapped out of the room, unless a bridge copied
+    // it in from elsewhere.
+    fn relay_to_mqtt(&mut self, nick: &str, message: &ChatMessage) {
+        let Some(bridge) = &self.mqtt else {
+            return;
+        };
+        if message.origin.is_some() {
+            return;
+        }
+        match bridge.relay(nick, &message.body) {
+            Ok(published) => self.counters.mqtt_sent += published as u64,
+            Err(e) => say!("[mqtt] not published to {}: {e}", bridge.settings().broker),
+        }
+    }
+
+    /// Handle news from the MQTT broker: publish what arrives on the topics mapped into the
+    /// room, marked as copied from MQTT and with payloads that aren't text attached, and say
+    /// when the broker is lost.
+    pub fn handle_mqtt_event(&mut self, event: MqttEvent) {
+        let Some(bridge) = &self.mqtt else {
+            return;
+        };
+        let broker = &bridge.settings().broker;
+        let (body, attachment) = match event {
+            MqttEvent::Connected { subscriptions } => {
+                return say!("[mqtt] connected to {broker}, subscribed to {subscriptions} topics");
+            }
+            MqttEvent::Disconnected(e) => {
+                return say!(
+                    "[mqtt] no connection to {broker}: {}, trying again",
+                    sanitize::line(&e)
+                );
+            }
+            MqttEvent::Refused(filter) => {
+                return say!(
+                    "[mqtt] {broker} refused the subscription to {}",
+                    sanitize::line(&filter)
+                );
+            }
+            MqttEvent::Message {
+                body, attachment, ..
+            } => (body, attachment),
+        };
+        // A payload that isn't text is served to peers like our own attachments
+        let attachment = attachment.map(|(attachment, path)| {
+            self.content.insert(attachment.cid, path, attachment.size);
+            attachment
+        });
+        let message = ChatMessage {
+            nick: self.nick.clone(),
+            body: body.into(),
+            timestamp: clock::unix_time(),
+            attachment,
+            origin: Some(mqtt::ORIGIN.to_string()),
+            content_type: None,
+        };
+        // Links from MQTT are held back like those of peers we don't trust
+        let (shown, _) = sanitize::body(&message.body);
+        say!("[mqtt] {shown}");
+        self.counters.mqtt_received += 1;
+        if let Some(gateway) = &mut self.gateway {
+            gateway.deliver(&message.nick, &message.body);
+        }
+        if !self.read_only {
+            self.publish_chat(message);
+        }
+    }
+
+    /// The MQTT broker whose topics are mirrored, if any.
+    pub fn mqtt(&self) -> Option<&MqttBridge> {
+        self.mqtt.as_ref()
+    }
+
+    /// Wait for news from the MQTT broker, to pass to [`ChatNode::handle_mqtt_event`] when
+    /// driving the node without [`ChatNode::run`]. Never resolves without a bridge.
+    pub async fn next_mqtt_event(&mut self) -> Option<MqttEvent> {
+        next_mqtt_event(&mut self.mqtt).await
+    }
+
+    // Deliver a chat message to the fediverse as a note, unless a bridge copied it in from
+    // elsewhere.
+    fn relay_to_fediverse(&mut self, nick: &str, message: &ChatMessage) {
+        let Some(fediverse) = &self.fediverse else {
+            return;
+        };
+        if message.origin.is_some() {
+            return;
+        }
+        if let Err(e) = fediverse.relay(nick, &message.body) {
+            self.counters.fediverse_failed += 1;
+            say!("[fediverse] note dropped: {e}");
+        }
+    }
+
+    /// Handle news of the notes delivered to the fediverse, counting them for `/stats` and
+    /// saying when they are given up on.
+    pub fn handle_fediverse_event(&mut self, event: FediverseEvent) {
+        match event {
+            FediverseEvent::Delivered { inbox } => {
+                self.counters.fediverse_sent += 1;
+                debug!("[fediverse] note delivered to {inbox}");
+            }
+            FediverseEvent::Failed { inbox, error } => {
+                self.counters.fediverse_failed += 1;
+                say!(
+                    "[fediverse] note not delivered to {inbox}: {}",
+                    sanitize::line(&error)
+                );
+            }
+        }
+    }
+
+    /// The inboxes the room is cross-posted to, if any.
+    pub fn fediverse(&self) -> Option<&Fediverse> {
+        self.fediverse.as_ref()
+    }
+
+    /// Wait for news of the notes delivered to the fediverse, to pass to
+    /// [`ChatNode::handle_fediverse_event`] when driving the node without [`ChatNode::run`].
+    /// Never resolves without an actor.
+    pub async fn next_fediverse_event(&mut self) -> Option<FediverseEvent> {
+        next_fediverse_event(&mut self.fediverse).await
+    }
+
+    // Sign a chat message of ours as a bundle for the radio to hand to the neighbours it meets.
+    fn carry_over_dtn(&mut self, message: &ChatMessage) {
+        let Some(radio) = &self.dtn else {
+            return;
+        };
+        let bundle = Bundle::new(self.topic.hash().as_str(), message, clock::unix_time());
+        let carried = SignedBundle::sign(&self.keypair, &bundle)
+            .map_err(|e| e.to_string())
+            .and_then(|signed| radio.carry(signed));
+        if let Err(e) = carried {
+            say!("[dtn] message not carried: {e}");
+        }
+    }
+
+    /// Handle what happens on the simulated Bluetooth link: show the room's messages that
+    /// neighbours carried to us, marked as carried, and say what each encounter moved.
+    pub fn handle_dtn_event(&mut self, event: DtnEvent) {
+        match event {
+            DtnEvent::Met {
+                peer,
+                sent,
+                received,
+                forged,
+            } => {
+                self.counters.dtn_encounters += 1;
+                self.counters.dtn_sent += sent as u64;
+                if forged > 0 {
+                    say!("[dtn] dropped {forged} messages from {peer} with bad signatures");
+                }
+                if sent + received > 0 {
+                    say!("[dtn] met {peer}: handed on {sent} messages, took {received}");
+                }
+            }
+            DtnEvent::Received { author, bundle } => self.take_bundle(author, bundle),
+            DtnEvent::Failed { peer, error } => debug!("[dtn] couldn't meet {peer}: {error}"),
+        }
+    }
+
+    // Show a message a neighbour carried to us, unless it is for another room, from a peer we
+    // ignore, or already arrived over the swarm while there was internet. Messages for other
+    // rooms are only carried on.
+    fn take_bundle(&mut self, author: PeerId, bundle: Bundle) {
+        if bundle.room != self.topic.hash().as_str()
+            || author == self.local_peer_id()
+            || self.is_blocked(&author)
+            || self.is_removed(&author)
+            || bundle.message.body.len() > self.validator.max_body()
+        {
+            return;
+        }
+        let message = bundle.message;
+        if self.history.iter().any(|stored| {
+            stored.source == Some(author)
+                && stored.message.timestamp == message.timestamp
+                && stored.message.body == message.body
+        }) {
+            return;
+        }
+        self.counters.dtn_received += 1;
+        let identity = self.identity(&author, &message.nick);
+        let id = gossipsub::MessageId::new(bundle.id.as_bytes());
+        let (line, links) = message::render(&message, true, identity, &id, &author);
+        say!("[dtn] {line}");
+        self.show_links(author, links);
+        let stored = StoredMessage {
+            id: bundle.id,
+            source: Some(author),
+            topic: bundle.room,
+            message,
+            binary: None,
+            shown: true,
+        };
+        if !self.event_listeners.is_empty() {
+            self.emit(ChatEvent::message(&stored));
+        }
+        self.remember(stored);
+    }
+
+    /// The simulated Bluetooth radio, with `--dtn-mode`.
+    pub fn dtn(&self) -> Option<&Radio> {
+        self.dtn.as_ref()
+    }
+
+    /// Wait for news of the simulated Bluetooth link, to pass to
+    /// [`ChatNode::handle_dtn_event`] when driving the node without [`ChatNode::run`]. Never
+    /// resolves without `--dtn-mode`.
+    pub async fn next_dtn_event(&mut self) -> Option<DtnEvent> {
+        next_dtn_event(&mut self.dtn).await
+    }
+
+    /// The IRC channel mirrored into the room, if any.
+    pub fn irc(&self) -> Option<&IrcBridge> {
+        self.irc.as_ref()
+    }
+
+    /// Wait for news from the IRC channel, to pass to [`ChatNode::handle_irc_event`] when
+    /// driving the node without [`ChatNode::run`]. Never resolves without a bridge.
+    pub async fn next_irc_event(&mut self) -> Option<IrcEvent> {
+        next_irc_event(&mut self.irc).await
+    }
+
+    /// The Nostr relay chat messages are bridged to, if any.
+    pub fn nostr(&self) -> Option<&Relay> {
+        self.nostr.as_ref()
+    }
+
+    /// Wait for news from the Nostr relay, to pass to [`ChatNode::handle_relay_event`] when
+    /// driving the node without [`ChatNode::run`]. Never resolves without a relay.
+    pub async fn next_relay_event(&mut self) -> Option<RelayEvent> {
+        next_relay_event(&mut self.nostr).await
+    }
+
+    /// Publish `data` on `topic`, waiting for a peer to publish it to when there is none yet.
+    ///
+    /// The message is queued and tried again with exponential backoff, and whenever a peer
+    /// subscribes to `topic`, for up to [`PUBLISH_TIMEOUT`]. The future doesn't borrow the
+    /// node, which has to keep running ([`ChatNode::run`], or its events passed to
+    /// [`ChatNode::handle_event`]) for the message to go out. Dropping the future cancels the
+    /// message if it hasn't gone out yet.
+    pub fn publish_with_backpressure(
+        &mut self,
+        topic: impl Into<gossipsub::TopicHash>,
+        data: impl Into<Vec<u8>>,
+    ) -> impl Future<Output = Result<gossipsub::MessageId, ChatError>> + 'static {
+        let (reply, outcome) = oneshot::channel();
+        if self.read_only {
+            let _ = reply.send(Err(ChatError::ReadOnlyMode));
+        } else {
+            self.outbox
+                .push(topic.into(), data.into(), Some(reply), Instant::now());
+            self.retry_publishes(None);
+        }
+        async move { outcome.await.unwrap_or(Err(ChatError::Shutdown)) }
+    }
+
+    /// Messages waiting for a peer to publish them to.
+    pub fn queued(&self) -> usize {
+        self.outbox.len()
+    }
+
+    // Try the queued messages that are due, and all those for `subscribed`, a topic a peer
+    // just subscribed to.
+    fn retry_publishes(&mut self, subscribed: Option<&gossipsub::TopicHash>) {
+        let now = Instant::now();
+        let mut held_sent = false;
+        for queued in self.outbox.take_due(now, subscribed) {
+            let payloads = match &queued.message {
+                Some(message) => match self.payloads(message) {
+                    Ok(payloads) => payloads.into_iter().map(|data| self.seal(data)).collect(),
+                    Err(e) => {
+                        say!("Publish error: {e}");
+                        continue;
+                    }
+                },
+                None => vec![queued.data.clone()],
+            };
+            let mut published = Err(gossipsub::PublishError::InsufficientPeers);
+            for data in payloads {
+                let len = data.len() as u64;
+                published = self
+                    .swarm
+                    .behaviour_mut()
+                    .gossipsub
+                    .publish(queued.topic.clone(), data);
+                let Ok(id) = &published else { break };
+                self.counters.published += 1;
+                self.counters.bytes_sent += len;
+                if queued.topic == self.wire_topic.hash() {
+                    self.stall.sent(id, now);
+                }
+            }
+            let unreported = match published {
+                Ok(id) => {
+                    held_sent |= queued.is_held();
+                    queued.resolve(Ok(id))
+                }
+                Err(gossipsub::PublishError::InsufficientPeers) => {
+                    self.outbox.retry_later(queued, now).and_then(|expired| {
+                        expired.resolve(Err(ChatError::PublishTimedOut(PUBLISH_TIMEOUT)))
+                    })
+                }
+                Err(e) => queued.resolve(Err(e.into())),
+            };
+            if let Some(e) = unreported {
+                say!("Publish error: {e}");
+            }
+        }
+        if held_sent {
+            say!("[queued] someone joined, sent what was waiting");
+        }
+    }
+
+    // Publish a chat message, or add it to the waiting batch when the room batches messages.
+    // Messages too large to batch go out on their own, after those waiting.
+    fn publish_or_batch(&mut self, message: ChatMessage) -> Result<(), ChatError> {
+        let Some(batcher) = &mut self.batcher else {
+            return self.publish(&message);
+        };
+        if message.encoded_len() > batcher.max_bytes() {
+            self.flush_batch()?;
+            return self.publish(&message);
+        }
+        match batcher.push(message, Instant::now()) {
+            Some(full) => self.publish_batch(full),
+            None => Ok(()),
+        }
+    }
+
+    /// Publish the chat messages waiting in a batch now, instead of when their window is up.
+    pub fn flush_batch(&mut self) -> Result<(), ChatError> {
+        match self.batcher.as_mut().and_then(Batcher::take) {
+            Some(waiting) => self.publish_batch(waiting),
+            None => Ok(()),
+        }
+    }
+
+    // A batch of one is published as a plain message, and so is each message of a batch when
+    // someone in the room only reads plain text. Otherwise each message is fitted to the room.
+    fn publish_batch(&mut self, waiting: batch::Batch) -> Result<(), ChatError> {
+        let format = self.room_format();
+        if waiting.batch.len() == 1 || format == ContentType::PlainText {
+            for message in &waiting.batch {
+                self.publish(message)?;
+            }
+            return Ok(());
+        }
+        let fitted = batch::Batch {
+            batch: waiting
+                .batch
+                .iter()
+                .map(|message| message.fit(format).into_owned())
+                .collect(),
+        };
+        self.publish_payload(fitted.encode())
+    }
+
+    /// Publish a chat message to the chat topic, in fragments if it is too large for one.
+    /// Fails with [`ChatError::ReadOnlyMode`] under `--no-publish`.
+    pub fn publish(&mut self, message: &ChatMessage) -> Result<(), ChatError> {
+        for data in self.payloads(message)? {
+            self.publish_payload(data)?;
+        }
+        Ok(())
+    }
+
+    // The encoded message, or its fragments when it is too large for one Gossipsub message.
+    fn payloads(&self, message: &ChatMessage) -> Result<Vec<Vec<u8>>, ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        // In a format every member of the room reads
+        let format = self.room_format();
+        if format == ContentType::PlainText {
+            return Ok(vec![message.plain_text().as_bytes().to_vec()]);
+        }
+        let message = &*message.fit(format);
+        let encoded = message.encode();
+        let payloads = if encoded.len() > fragment::FRAGMENT_THRESHOLD {
+            let fragments = fragment::fragment(message, fragment::MAX_CHUNK);
+            if fragments.len() > fragment::MAX_FRAGMENTS as usize {
+                return Err(gossipsub::PublishError::MessageTooLarge.into());
+            }
+            fragments.iter().map(Fragment::encode).collect()
+        } else {
+            vec![encoded]
+        };
+        Ok(payloads)
+    }
+
+    // The richest message format every peer in the room reads.
+    fn room_format(&self) -> ContentType {
+        let topic = self.wire_topic.hash();
+        let members = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic))
+            .map(|(peer, _)| *peer);
+        self.formats.pick_for_all(members)
+    }
+
+    /// The message formats connected peers said they accept.
+    pub fn formats(&self) -> &ContentTypeNegotiator {
+        &self.formats
+    }
+
+    // Seal and tag an encoded message, fragment or batch for the chat topic.
+    fn seal(&self, mut data: Vec<u8>) -> Vec<u8> {
+        if let Some(key) = &self.room_key {
+            data = key.seal(&data);
+        }
+        self.validator.tag(data)
+    }
+
+    // Seal, tag and publish an encoded message, fragment or batch on the chat topic.
+    fn publish_payload(&mut self, data: Vec<u8>) -> Result<(), ChatError> {
+        let data = self.seal(data);
+        let len = data.len() as u64;
+        let id = self
+            .swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.wire_topic.clone(), data)?;
+        self.stall.sent(&id, Instant::now());
+        self.counters.published += 1;
+        self.counters.bytes_sent += len;
+        Ok(())
+    }
+
+    /// Whether the node publishes nothing, as with `--no-publish`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether more than [`OUTBOUND_HIGH_WATER`] bytes were published since the last tick, so
+    /// input should wait.
+    pub fn is_backlogged(&self) -> bool {
+        self.counters.bytes_sent - self.outbound_mark > OUTBOUND_HIGH_WATER
+    }
+
+    /// Run the node, taking user input from `input`, until `shutdown` resolves, then leave
+    /// gracefully. When `input` ends the node keeps receiving messages, or with
+    /// [`StdinEof::Exit`] sends what it has waiting, for up to [`PUBLISH_TIMEOUT`], and leaves.
+    ///
+    /// Swarm events are handled before input whenever both are ready, and input waits while
+    /// more than [`OUTBOUND_HIGH_WATER`] bytes went out since the last tick, so a flood of
+    /// lines can neither starve the network nor pile up in Gossipsub's queues. Input also waits
+    /// out [`CONNECT_GRACE`] after the start, while the network runs. Iterations taking longer
+    /// than [`watchdog::SLOW_ITERATION`] are logged with what they were doing.
+    pub async fn run(
+        &mut self,
+        input: impl Stream<Item = String>,
+        shutdown: impl Future<Output = ()>,
+    ) {
+        let mut input = pin!(input);
+        let mut shutdown = pin!(shutdown);
+        let mut input_open = true;
+        let mut draining = false;
+        // Check once a second for temporary bans that have run out
+        let mut tick = runtime::interval(Duration::from_secs(1));
+        // Do not disturb may have been left on in an earlier run
+        if let Some(dnd) = self.config.dnd {
+            say!(
+                "[dnd] do not disturb is on {}",
+                dnd.describe(clock::unix_time())
+            );
+        }
+        self.emit(ChatEvent::Ready {
+            peer: self.local_peer_id(),
+            nick: self.nick.clone(),
+            room: self.topic.hash().into_string(),
+        });
+        let mut paused = false;
+        let connected_at = self.started + CONNECT_GRACE;
+        let mut connecting = Instant::now() < connected_at;
+        loop {
+            if draining && self.outbox.is_empty() {
+                break;
+            }
+            let backlogged = self.is_backlogged();
+            if backlogged && !paused {
+                self.counters.input_pauses += 1;
+                debug!("[input] too much sent since the last tick, input paused until the next");
+            }
+            paused = backlogged;
+            let flush_at = self.batcher.as_ref().and_then(Batcher::deadline);
+            let retry_at = self.outbox.next_retry();
+            let (activity, started) = tokio::select! {
+                // Arms are tried in order, so input only runs when the network is quiet
+                biased;
+                () = &mut shutdown => break,
+                // Lift expired bans
+                _ = tick.tick() => {
+                    let started = Instant::now();
+                    self.tick();
+                    (Activity::Tick, started)
+                }
+                // Send batched messages once the first of them has waited its window
+                () = runtime::sleep_until(flush_at.unwrap_or_else(Instant::now)),
+                    if flush_at.is_some() =>
+                {
+                    let started = Instant::now();
+                    if let Err(e) = self.flush_batch() {
+                        say!("Publish error: {e}");
+                    }
+                    (Activity::BatchFlush, started)
+                }
+                // Try again to send messages that had nobody to go to
+                () = runtime::sleep_until(retry_at.unwrap_or_else(Instant::now)),
+                    if retry_at.is_some() =>
+                {
+                    let started = Instant::now();
+                    self.retry_publishes(None);
+                    (Activity::PublishRetry, started)
+                }
+                // Handle events from the swarm (e.g., peer discovery, message receipt)
+                event = self.swarm.select_next_some() => {
+                    let started = Instant::now();
+                    let kind = event_kind(&event);
+                    self.handle_event(event);
+                    (Activity::Event(kind), started)
+                }
+                // Notes from the Nostr relay, and news of the connection to it
+                Some(event) = next_relay_event(&mut self.nostr) => {
+                    let started = Instant::now();
+                    self.handle_relay_event(event);
+                    (Activity::Nostr, started)
+                }
+                // Lines from the IRC channel, and news of the connection to it
+                Some(event) = next_irc_event(&mut self.irc) => {
+                    let started = Instant::now();
+                    self.handle_irc_event(event);
+                    (Activity::Irc, started)
+                }
+                // Messages from the Matrix room, and news of the homeserver
+                Some(event) = next_matrix_event(&mut self.matrix) => {
+                    let started = Instant::now();
+                    self.handle_matrix_event(event);
+                    (Activity::Matrix, started)
+                }
+                // Messages and presence from the XMPP room, and news of the server
+                Some(event) = next_xmpp_event(&mut self.xmpp) => {
+                    let started = Instant::now();
+                    self.handle_xmpp_event(event);
+                    (Activity::Xmpp, started)
+                }
+                // Payloads from the MQTT broker, and news of the connection to it
+                Some(event) = next_mqtt_event(&mut self.mqtt) => {
+                    let started = Instant::now();
+                    self.handle_mqtt_event(event);
+                    (Activity::Mqtt, started)
+                }
+                // Lines from IRC gateway clients, and news of their connections
+                Some(event) = next_gateway_event(&mut self.gateway) => {
+                    let started = Instant::now();
+                    self.handle_gateway_event(event);
+                    (Activity::Gateway, started)
+                }
+                // Messages posted by hooks, and requests turned away
+                Some(event) = next_hook_event(&mut self.hook_server) => {
+                    let started = Instant::now();
+                    self.handle_hook_event(event);
+                    (Activity::Hook, started)
+                }
+                // Notes delivered to the fediverse, or given up on
+                Some(event) = next_fediverse_event(&mut self.fediverse) => {
+                    let started = Instant::now();
+                    self.handle_fediverse_event(event);
+                    (Activity::Fediverse, started)
+                }
+                // Messages neighbours carried to us, and news of the encounters
+                Some(event) = next_dtn_event(&mut self.dtn) => {
+                    let started = Instant::now();
+                    self.handle_dtn_event(event);
+                    (Activity::Dtn, started)
+                }
+                // Messages forwarded to webhooks, delivered or given up on
+                Some(event) = self.webhooks.next() => {
+                    let started = Instant::now();
+                    self.handle_webhook_event(event);
+                    (Activity::Webhook, started)
+                }
+                // Give peers time to connect before taking input
+                () = runtime::sleep_until(connected_at), if connecting => {
+                    connecting = false;
+                    continue;
+                }
+                // If there's user input (a line of text), run it as a command or send it
+                line = input.next(), if input_open && !paused && !connecting => match line {
+                    Some(line) => {
+                        let started = Instant::now();
+                        self.handle_line(&line).await;
+                        (Activity::input(&line), started)
+                    }
+                    None => {
+                        input_open = false;
+                        draining = self.input_ended();
+                        continue;
+                    }
+                },
+            };
+            if watchdog::check(&activity, started.elapsed()) {
+                self.counters.slow_iterations += 1;
+            }
+        }
+        self.shutdown().await;
+    }
+
+    // Input ended: send the waiting batch if we are about to exit, or say that we only listen
+    // now. Returns whether to exit once the messages waiting for a peer are sent.
+    fn input_ended(&mut self) -> bool {
+        match self.on_input_end {
+            StdinEof::Exit => {
+                if let Err(e) = self.flush_batch() {
+                    say!("Publish error: {e}");
+                }
+                let queued = self.outbox.len();
+                if queued > 0 {
+                    say!("[input] input ended, exiting once {queued} queued messages are sent");
+                }
+                true
+            }
+            StdinEof::Listen => {
+                say!("[input] input ended, still receiving messages but nothing more can be sent");
+                false
+            }
+        }
+    }
+
+    /// Wait until the config, board, task list and audit log writes queued so far are done.
+    /// The event loop leaves them to a thread of their own.
+    pub async fn flush_writes(&mut self) {
+        self.disk.flush().await;
+    }
+
+    /// Leave the room: tell peers, unsubscribe from every topic and close all connections,
+    /// giving up after [`SHUTDOWN_TIMEOUT`].
+    pub async fn shutdown(&mut self) {
+        if let Err(e) = self.flush_batch() {
+            say!("Publish error: {e}");
+        }
+        if self.remember_contacts() {
+            self.save_config();
+        }
+        let room = self.topic.hash().into_string();
+        let announced = self
+            .publish_control(&ControlMessage::Leave { room })
+            .is_ok();
+        let topics = [
+            self.wire_topic.clone(),
+            self.control_topic.clone(),
+            self.board_topic.clone(),
+            self.tasks_topic.clone(),
+            self.wordle_topic.clone(),
+            self.canvas_topic.clone(),
+            self.spam_topic.clone(),
+            self.keyshare_topic.clone(),
+        ];
+        for topic in &topics {
+            let _ = self.swarm.behaviour_mut().gossipsub.unsubscribe(topic);
+        }
+
+        let closed = runtime::timeout(SHUTDOWN_TIMEOUT, async {
+            if announced {
+                let mut flush = runtime::sleep(LEAVE_FLUSH);
+                loop {
+                    tokio::select! {
+                        event = self.swarm.select_next_some() => self.handle_event(event),
+                        () = &mut flush => break,
+                    }
+                }
+            }
+            let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+            for peer in peers {
+                let _ = self.swarm.disconnect_peer_id(peer);
+            }
+            while self.swarm.connected_peers().next().is_some() {
+                let event = self.swarm.select_next_some().await;
+                self.handle_event(event);
+            }
+        })
+        .await;
+        if closed.is_err() {
+            say!("Shutdown timed out with connections still open");
+        }
+        if let Some(relay) = &self.nostr {
+            let _ = runtime::timeout(SHUTDOWN_TIMEOUT, async {
+                while relay.pending() > 0 {
+                    runtime::sleep(Duration::from_millis(50)).await;
+                }
+            })
+            .await;
+            let pending = relay.pending();
+            if pending > 0 {
+                say!(
+                    "[nostr] {pending} notes not sent, {} couldn't be reached",
+                    relay.url()
+                );
+            }
+        }
+        let held = self.outbox.held();
+        if held > 0 {
+            say!("[queued] {held} messages not sent, nobody joined the room");
+        }
+        self.flush_writes().await;
+    }
+
+    /// Handle an event from the swarm (e.g., peer discovery, message receipt).
+    pub fn handle_event(&mut self, event: SwarmEvent<MyBehaviourEvent>) {
+        // Whether the node's readiness may change, for the HTTP server to hear of at once
+        let connectivity = matches!(
+            event,
+            SwarmEvent::ConnectionEstablished { .. }
+                | SwarmEvent::ConnectionClosed { .. }
+                | SwarmEvent::NewListenAddr { .. }
+                | SwarmEvent::ExpiredListenAddr { .. }
+                | SwarmEvent::ListenerClosed { .. }
+        );
+        match event {
+            // Peers discovered or expired by mDNS on the local network
+            SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(event)) => self.discovery(event),
+            // Gossipsub messages and subscriptions; tell Gossipsub whether to forward messages
+            SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(event)) => {
+                if let Some(validation) = self.receive(event) {
+                    validation.report(&mut self.swarm.behaviour_mut().gossipsub);
+                }
+            }
+            // Reservations on the relay and circuits through it
+            SwarmEvent::Behaviour(MyBehaviourEvent::Relay(event)) => self.relay_event(event),
+            // Round-trip times, which rank peers by latency, and failed pings, which tell
+            // connections that died without closing
+            SwarmEvent::Behaviour(MyBehaviourEvent::Ping(event)) => {
+                self.liveness.ping(&event, Instant::now());
+                self.pings.handle(&event);
+            }
+            // Direct connections replacing relayed ones
+            SwarmEvent::Behaviour(MyBehaviourEvent::Dcutr(event)) => self.hole_punch_event(event),
+            // Members of the room telling us who else is in it
+            SwarmEvent::Behaviour(MyBehaviourEvent::Snapshot(event)) => self.snapshot_event(event),
+            SwarmEvent::Behaviour(MyBehaviourEvent::Content(event)) => self.content_event(event),
+            // Challenges to peers and from them
+            SwarmEvent::Behaviour(MyBehaviourEvent::Auth(event)) => self.auth_event(event),
+            // What peers say they run, to tell chat nodes of other deployments
+            SwarmEvent::Behaviour(MyBehaviourEvent::Identify(identify::Event::Received {
+                peer_id,
+                info,
+                ..
+            })) => self.identified(peer_id, info),
+            // The swarm refuses connections from blocked peers, but one established before the
+            // block took effect, or over a path the block list missed, is closed here
+            SwarmEvent::ConnectionEstablished { peer_id, .. } if self.is_blocked(&peer_id) => {
+                info!("[blocked peer attempted connection: {peer_id}]");
+                let _ = self.swarm.disconnect_peer_id(peer_id);
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                let address = endpoint.get_remote_address();
+                if address.iter().any(|protocol| protocol == Protocol::QuicV1) {
+                    self.quic_connections.insert(connection_id);
+                }
+                let dialed = endpoint.is_dialer().then(|| address.clone());
+                self.liveness
+                    .connected(connection_id, peer_id, dialed, Instant::now());
+                if num_established.get() == 1 {
+                    self.challenge(peer_id);
+                    self.resume_from(peer_id);
+                    self.emit(ChatEvent::Connected { peer: peer_id });
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                num_established,
+                ..
+            } => {
+                self.quic_connections.remove(&connection_id);
+                self.liveness.closed(&connection_id, &peer_id);
+                if num_established == 0 {
+                    self.emit(ChatEvent::Disconnected { peer: peer_id });
+                    self.pings.remove(&peer_id);
+                    self.pending_auth.disconnected(&peer_id);
+                    self.formats.forget(&peer_id);
+                    if let Some(address) = self.redial.remove(&peer_id) {
+                        self.dial_again(peer_id, address);
+                    }
+                }
+            }
+            // The relay refused or dropped our reservation; ask again later
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                reason,
+                ..
+            } if Some(listener_id) == self.relay_listener => {
+                let reason = match reason {
+                    Ok(()) => "closed".to_string(),
+                    Err(e) => e.to_string(),
+                };
+                say!("[relay] reservation lost ({reason}), retrying in {RELAY_RETRY}s");
+                self.relay_listener = None;
+                self.relay_reserved = false;
+                self.relay_retry = Some(clock::unix_time() + RELAY_RETRY);
+            }
+            // A listener we opened failed, so listen on its address again in a moment
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                reason,
+                ..
+            } => {
+                if let Some(address) = self.listeners.remove(&listener_id) {
+                    let reason = match reason {
+                        Ok(()) => "closed".to_string(),
+                        Err(e) => e.to_string(),
+                    };
+                    say!(
+                        "[listen] stopped listening on {address} ({reason}), listening again \
+                         in {LISTEN_RETRY}s"
+                    );
+                    self.relisten
+                        .push((address, clock::unix_time() + LISTEN_RETRY));
+                }
+            }
+            // The listener keeps going after an error, so only say so
+            SwarmEvent::ListenerError { listener_id, error } => {
+                match self.listeners.get(&listener_id) {
+                    Some(address) => say!("[listen] error listening on {address}: {error}"),
+                    None => say!("[listen] listener error: {error}"),
+                }
+            }
+            // When the local node starts listening on a new network address
+            SwarmEvent::NewListenAddr { address, .. } => {
+                // Print the address the local node is listening on
+                say!("Local node is listening on {address}");
+                self.emit(ChatEvent::Listening { address });
+            }
+            // When dialing a peer fails (including a swarm key mismatch on private networks)
+            SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                if let swarm::DialError::Denied { cause } = &error {
+                    self.count_not_allowlisted(cause);
+                }
+                let reason = error::describe_dial_error(&error);
+                match peer_id {
+                    Some(peer) => say!("Failed to connect to {peer}: {reason}"),
+                    None => say!("Failed to connect: {reason}"),
+                }
+            }
+            // When an incoming connection fails before it is fully established
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error,
+                ..
+            } => {
+                if let ListenError::Denied { cause } = &error {
+                    self.count_not_allowlisted(cause);
+                }
+                say!("Incoming connection from {send_back_addr} failed: {error}");
+            }
+            // Catch all other events (not handled explicitly)
+            _ => {}
+        }
+        if connectivity {
+            self.report_health();
+        }
+    }
+
+    fn relay_event(&mut self, event: relay::client::Event) {
+        match event {
+            relay::client::Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal,
+                ..
+            } => {
+                if renewal {
+                    debug!("[relay] reservation on {relay_peer_id} renewed");
+                } else {
+                    say!("[relay] reservation accepted by {relay_peer_id}");
+                }
+                self.relay_reserved = true;
+            }
+            relay::client::Event::InboundCircuitEstablished { src_peer_id, .. } => {
+                info!("[relay] {src_peer_id} connected through the relay")
+            }
+            relay::client::Event::OutboundCircuitEstablished { relay_peer_id, .. } => {
+                info!("[relay] connected to a peer through {relay_peer_id}")
+            }
+        }
+    }
+
+    fn hole_punch_event(&mut self, event: dcutr::Event) {
+        let peer = event.remote_peer_id;
+        match event.result {
+            Ok(connection) if self.quic_connections.contains(&connection) => {
+                self.counters.quic_hole_punches += 1;
+                say!("[quic-punch succeeded to {peer}]");
+            }
+            Ok(_) => {
+                self.counters.tcp_hole_punches += 1;
+                say!("[hole-punch succeeded to {peer} over tcp]");
+            }
+            // Every address is tried, QUIC ones included, before giving up
+            Err(e) => {
+                self.counters.failed_hole_punches += 1;
+                say!("[quic-punch failed, using relay] {peer}: {e}");
+            }
+        }
+    }
+
+    // Peers found by mDNS only become explicit Gossipsub peers once they pass the
+    // authenticator, so with one they are dialed to be challenged first.
+    fn discovery(&mut self, event: mdns::Event) {
+        let list = match event {
+            mdns::Event::Discovered(list) => self.discoverable(list),
+            mdns::Event::Expired(list) => {
+                for (peer, _) in &list {
+                    self.pending_auth.expired(peer);
+                }
+                let event = mdns::Event::Expired(list);
+                return gossip::discovery(event, &mut self.swarm.behaviour_mut().gossipsub);
+            }
+        };
+        if self.authenticator.is_none() {
+            let event = mdns::Event::Discovered(list);
+            return gossip::discovery(event, &mut self.swarm.behaviour_mut().gossipsub);
+        }
+        let (passed, unknown): (Vec<_>, Vec<_>) = list
+            .into_iter()
+            .partition(|(peer, _)| self.pending_auth.is_authenticated(peer));
+        gossip::discovery(
+            mdns::Event::Discovered(passed),
+            &mut self.swarm.behaviour_mut().gossipsub,
+        );
+        for (peer, address) in unknown {
+            say!("mDNS discovered a new peer: {peer}, authenticating it first");
+            self.pending_auth.discovered(peer);
+            let opts = DialOpts::peer_id(peer)
+                .addresses(vec![address])
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            let _ = self.swarm.dial(opts);
+        }
+    }
+
+    // Leave blocked peers out of an mDNS discovery, so they never become explicit peers, say
+    // when a peer banned in an earlier session shows up on the network again. So are peers of
+    // other deployments: mDNS finds every chat node on the network, whatever its prefix.
+    fn discoverable(&self, list: Vec<(PeerId, Multiaddr)>) -> Vec<(PeerId, Multiaddr)> {
+        list.into_iter()
+            .filter(|(peer, _)| {
+                if self.is_blocked(peer) {
+                    debug!("[ban] ignored blocked peer {peer} discovered over mDNS");
+                    return false;
+                }
+                !self.other_deployments.contains(peer)
+            })
+            .collect()
+    }
+
+    // A chat node with another topic prefix shares none of our topics: disconnect it, and
+    // stop Gossipsub dialing it again as an explicit peer found by mDNS.
+    fn identified(&mut self, peer: PeerId, info: identify::Info) {
+        self.formats.learn(peer, &info.agent_version);
+        if !node::is_other_deployment(&info.protocol_version, &self.topic_prefix) {
+            return;
+        }
+        debug!(
+            "[identify] {peer} runs another deployment ({}), disconnecting",
+            info.protocol_version
+        );
+        if self.other_deployments.len() < MAX_TRACKED_PEERS {
+            self.other_deployments.insert(peer);
+        }
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .remove_explicit_peer(&peer);
+        let _ = self.swarm.disconnect_peer_id(peer);
+    }
+
+    // Challenge a peer that just connected, if there is an authenticator.
+    fn challenge(&mut self, peer: PeerId) {
+        let Some(authenticator) = &self.authenticator else {
+            return;
+        };
+        self.pending_auth
+            .challenge(peer, authenticator, Instant::now());
+        self.poll_auth(peer);
+    }
+
+    // Move the challenge of `peer` on, and act on its outcome once there is one.
+    fn poll_auth(&mut self, peer: PeerId) {
+        match self.pending_auth.poll(&peer, Instant::now()) {
+            Progress::Waiting => {}
+            Progress::Send(challenge) => {
+                self.swarm
+                    .behaviour_mut()
+                    .auth
+                    .send_request(&peer, challenge);
+            }
+            Progress::Passed => {
+                say!("[auth] {peer} authenticated");
+                if self.pending_auth.was_discovered(&peer) {
+                    self.swarm
+                        .behaviour_mut()
+                        .gossipsub
+                        .add_explicit_peer(&peer);
+                    say!("Added explicit peer: {:?}", peer);
+                }
+            }
+            Progress::Failed(e) => {
+                say!("[auth] {peer} failed authentication ({e}), disconnecting");
+                let _ = self.swarm.disconnect_peer_id(peer);
+            }
+        }
+    }
+
+    // Send back the answers to peers' challenges that are ready.
+    fn poll_answers(&mut self) {
+        for (peer, channel, answer) in self.pending_auth.poll_answers(Instant::now()) {
+            match answer {
+                Ok(answer) => {
+                    let _ = self
+                        .swarm
+                        .behaviour_mut()
+                        .auth
+                        .send_response(channel, answer);
+                }
+                Err(e) => say!("[auth] cannot answer the challenge of {peer}: {e}"),
+            }
+        }
+    }
+
+    fn auth_event(&mut self, event: request_response::Event<Vec<u8>, Vec<u8>>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                // Without an authenticator there is nothing to answer with, and the peer
+                // decides what to make of no answer
+                let Some(authenticator) = &self.authenticator else {
+                    debug!("[auth] {peer} sent a challenge, but no authenticator is set");
+                    return;
+                };
+                self.pending_auth
+                    .respond(peer, request, channel, authenticator, Instant::now());
+                self.poll_answers();
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            } => {
+                self.pending_auth.answered(&peer, response);
+                self.poll_auth(peer);
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                if self.pending_auth.unanswered(&peer) {
+                    say!("[auth] {peer} failed authentication (no answer: {error}), disconnecting");
+                    let _ = self.swarm.disconnect_peer_id(peer);
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("[auth] answering the challenge of {peer} failed: {error}")
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    fn snapshot_event(&mut self, event: request_response::Event<Snapshot, ()>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request {
+                    request, channel, ..
+                },
+            } => {
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .snapshot
+                    .send_response(channel, ());
+                self.apply_snapshot(peer, request);
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                if matches!(error, request_response::OutboundFailure::Timeout) {
+                    self.liveness.timed_out(&peer);
+                }
+                debug!("[roster] snapshot for {peer} not delivered: {error}")
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("[roster] snapshot from {peer} not received: {error}")
+            }
+            request_response::Event::Message { .. }
+            | request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    // Tell a newcomer who is in the room, unless enough other members are likely to.
+    fn send_snapshot(&mut self, newcomer: PeerId) {
+        if self.read_only {
+            return;
+        }
+        let room = self.topic.hash().into_string();
+        let online: Vec<PeerId> = self
+            .roster
+            .online(&room)
+            .filter(|peer| **peer != newcomer)
+            .copied()
+            .collect();
+        if !snapshot::should_answer(online.len()) {
+            return;
+        }
+        let now = clock::unix_time();
+        let mut members = vec![Member {
+            peer: self.local_peer_id(),
+            nick: self.nick.clone(),
+            away: self.is_away(),
+            last_seen: now,
+        }];
+        members.extend(online.iter().take(snapshot::MAX_MEMBERS - 1).map(|peer| Member {
+            peer: *peer,
+            nick: self.nicks.get(peer).cloned().unwrap_or_default(),
+            away: self.roster.is_away(&room, peer),
+            last_seen: self
+                .presence
+                .status(&room, peer, now)
+                .map_or(now, |(_, seen_at)| seen_at),
+        }));
+        self.swarm
+            .behaviour_mut()
+            .snapshot
+            .send_request(&newcomer, Snapshot { room, members });
+    }
+
+    // Fill in the roster from a member's snapshot, shortly after joining. The members it lists
+    // only stay on the roster while they keep showing signs of life, and its nicks never
+    // replace one we know or the nick of a verified peer.
+    fn apply_snapshot(&mut self, sender: PeerId, snapshot: Snapshot) {
+        if snapshot.room != self.topic.hash().as_str()
+            || self.snapshots_applied >= snapshot::MAX_APPLIED
+            || self.started.elapsed() >= Duration::from_secs(snapshot::ACCEPT_FOR)
+        {
+            return;
+        }
+        self.snapshots_applied += 1;
+        let Snapshot { room, members } = snapshot.sanitized(clock::unix_time());
+        let count = members.len();
+        for member in members {
+            if member.peer == self.local_peer_id() || self.is_blocked(&member.peer) {
+                continue;
+            }
+            self.presence
+                .hint(&room, member.peer, member.last_seen, member.away);
+            self.roster.vouch(&room, member.peer);
+            let claims_verified_nick = self
+                .config
+                .verified
+                .iter()
+                .any(|v| v.nick.eq_ignore_ascii_case(&member.nick));
+            if !member.nick.is_empty()
+                && !claims_verified_nick
+                && !self.is_verified(&member.peer)
+                && !self.nicks.contains_key(&member.peer)
+                // Hearsay doesn't evict nicks we learned ourselves
+                && self.nicks.len() < self.nicks.limit()
+            {
+                self.nicks.insert(member.peer, member.nick);
+            }
+        }
+        debug!(
+            "[roster] {} sent a snapshot of {count} members",
+            self.display_name(&sender)
+        );
+    }
+
+    /// Handle a Gossipsub event. For a received message, returns the verdict to report back
+    /// to Gossipsub.
+    pub fn receive(&mut self, event: gossipsub::Event) -> Option<Validation> {
+        // Someone to publish the messages waiting for this topic to
+        if let gossipsub::Event::Subscribed { topic, .. } = &event {
+            self.retry_publishes(Some(topic));
+        }
+        match event {
+            // When a Gossipsub message is received from a peer
+            gossipsub::Event::Message {
+                propagation_source, // The peer that sent the message
+                message_id,         // Unique ID of the message
+                message,            // The actual message content (bytes)
+            } => {
+                let chat = message.topic == self.wire_topic.hash();
+                let acceptance = self.handle_message(propagation_source, &message_id, message);
+                if chat && matches!(acceptance, MessageAcceptance::Accept) {
+                    self.confirm(&message_id);
+                }
+                Some(Validation {
+                    message_id,
+                    propagation_source,
+                    acceptance,
+                })
+            }
+            // When a peer starts listening for control messages, present our invite to it, tell
+            // it about a recent key rotation, that we are here and who we are
+            gossipsub::Event::Subscribed { topic, .. } if topic == self.control_topic.hash() => {
+                let now = clock::unix_time();
+                self.announce_join();
+                self.announce_rotation(now);
+                self.announce_topic_alias();
+                self.send_heartbeat(now);
+                self.announce_profile();
+                None
+            }
+            // A newcomer to the board may get the posts it missed from us
+            gossipsub::Event::Subscribed { topic, .. } if topic == self.board_topic.hash() => {
+                self.send_board_sync();
+                None
+            }
+            // And the tasks
+            gossipsub::Event::Subscribed { topic, .. } if topic == self.tasks_topic.hash() => {
+                self.send_tasks_sync();
+                None
+            }
+            // And proofs of the spam reported before it came
+            gossipsub::Event::Subscribed { topic, .. } if topic == self.spam_topic.hash() => {
+                self.send_spam_proofs();
+                None
+            }
+            // Peers subscribed to the chat topic join the roster once they are heard from. A
+            // newcomer may get a snapshot of who else is in the room from us.
+            gossipsub::Event::Subscribed { peer_id, topic } if topic == self.wire_topic.hash() => {
+                let room = self.topic.hash().into_string();
+                let known = self.roster.online(&room).any(|peer| *peer == peer_id);
+                self.roster.subscribe(&room, peer_id);
+                if !known {
+                    self.send_snapshot(peer_id);
+                }
+                None
+            }
+            gossipsub::Event::Unsubscribed { peer_id, topic } => {
+                let room = self.room_of(&topic);
+                self.roster.unsubscribe(&room, &peer_id);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_message(
+        &mut self,
+        peer_id: PeerId,
+        id: &gossipsub::MessageId,
+        message: gossipsub::Message,
+    ) -> MessageAcceptance {
+        // Signed messages name their author even when another peer relayed them; unsigned ones
+        // are attributed to the peer that relayed them
+        let sender = message.source.unwrap_or(peer_id);
+        self.liveness.heard(&peer_id, Instant::now());
+        if self.pending_auth.is_pending(&peer_id) {
+            debug!("[auth] ignored a message from {peer_id}, which isn't authenticated yet");
+            return MessageAcceptance::Ignore;
+        }
+        self.counters.received += 1;
+        self.counters.bytes_received += message.data.len() as u64;
+        self.last_received = Some(Instant::now());
+        if self.strict_topic
+            && !self
+                .swarm
+                .behaviour()
+                .gossipsub
+                .topics()
+                .any(|topic| *topic == message.topic)
+        {
+            self.counters.out_of_topic += 1;
+            debug!("[topic] dropped a message for unsubscribed topic {}", message.topic);
+            return MessageAcceptance::Ignore;
+        }
+        // Only chat messages are counted for `/whois`, not heartbeats, other control messages,
+        // posts, tasks, game moves, strokes on the canvas, spam reports or key shares
+        let is_control = message.topic == self.control_topic.hash();
+        let is_board = message.topic == self.board_topic.hash();
+        let is_tasks = message.topic == self.tasks_topic.hash();
+        let is_wordle = message.topic == self.wordle_topic.hash();
+        let is_canvas = message.topic == self.canvas_topic.hash();
+        let is_spam = message.topic == self.spam_topic.hash();
+        let is_keyshare = message.topic == self.keyshare_topic.hash();
+        match message.source {
+            Some(author)
+                if !is_control
+                    && !is_board
+                    && !is_tasks
+                    && !is_wordle
+                    && !is_canvas
+                    && !is_spam
+                    && !is_keyshare
+                    && (self.signers.len() < MAX_KNOWN_NICKS
+                        || self.signers.contains_key(&author)) =>
+            {
+                *self.signers.entry(author).or_default() += 1;
+            }
+            None if self.require_signed => {
+                warn!("[unsigned] dropped an unsigned message relayed by {peer_id}");
+                return MessageAcceptance::Reject;
+            }
+            _ => {}
+        }
+        if self.dedup.check(&sender, &message.data, Instant::now()) {
+            self.counters.duplicates += 1;
+            debug!("[dedup] {sender} sent a message it already sent");
+        }
+        // Blocked peers can't connect to us, but their messages may still be relayed
+        if self.is_blocked(&sender) {
+            debug!("[ban] dropped a message from blocked peer {sender}");
+            return MessageAcceptance::Accept;
+        }
+        let now = clock::unix_time();
+        if let Some(ban) = self.bans.record_message(sender, now) {
+            self.start_ban(ban);
+            return MessageAcceptance::Accept;
+        }
+
+        // Messages on the control topic configure the chat rather than being displayed
+        if is_control {
+            if !self.handle_control(&message.data) {
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        // Posts, pins and syncs of the room's board
+        if is_board {
+            if !self.handle_board(&message.data) {
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        // Changes to the room's task list
+        if is_tasks {
+            if !self.handle_tasks(&message.data) {
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        // Moves of Wordle games
+        if is_wordle {
+            if !self.handle_wordle(&message.data) {
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        // Strokes on the room's canvas
+        if is_canvas {
+            if !self.handle_canvas(&message.data) {
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        // Reports of spam, and proofs of them
+        if is_spam {
+            if !self.handle_spam(&message.data) {
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+        // Shares of room keys, dealt, asked for and handed back
+        if is_keyshare {
+            if !self.handle_keyshare(&message.data) {
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+            }
+            return MessageAcceptance::Accept;
+        }
+
+        let topic = self.room_of(&message.topic);
+        // Peers removed from the room by a moderator are ignored there
+        let settings = self.config.rooms.get(&topic);
+        if self.rooms.is_ignored(&topic, &sender, settings) {
+            return MessageAcceptance::Accept;
+        }
+        // Invite-only rooms ignore peers until they have presented a valid invite
+        if settings.is_some_and(|settings| !settings.admits(&sender)) {
+            if self.uninvited.len() < MAX_KNOWN_NICKS && self.uninvited.insert(sender) {
+                say!("[invite] ignoring {sender} in {topic}: no valid invite presented");
+            }
+            return MessageAcceptance::Accept;
+        }
+        // With a shared key, messages without a valid tag are rejected and count as invalid
+        let data = match self.validator.verify(&message.data) {
+            Ok(data) => data,
+            Err(acceptance) => {
+                warn!("[hmac] rejected a message from {sender}: missing or invalid HMAC");
+                if let Some(ban) = self.bans.record_invalid(sender, now) {
+                    self.start_ban(ban);
+                }
+                return acceptance;
+            }
+        };
+        // Passphrase rooms only carry messages sealed with the room key
+        let data = match &self.room_key {
+            Some(key) => match key.open(data) {
+                Ok(data) => Cow::Owned(data),
+                Err(e) => {
+                    self.report_undecryptable(sender, e);
+                    return MessageAcceptance::Ignore;
+                }
+            },
+            None => Cow::Borrowed(data),
+        };
+        // Fragments of a large message are passed on as they come, and the message is handled
+        // once the last one is in
+        let chats = match Fragment::decode(&data) {
+            Some(fragment) => match self.fragments.add(sender, fragment, now) {
+                Assembly::Pending => return MessageAcceptance::Accept,
+                Assembly::Complete(Ok(chat)) => vec![chat.into()],
+                Assembly::Complete(Err(e)) => {
+                    debug!("[fragment] ignored a message from {sender}: {e}");
+                    return MessageAcceptance::Ignore;
+                }
+                Assembly::Dropped => {
+                    debug!("[fragment] dropped a fragment from {sender}");
+                    return MessageAcceptance::Ignore;
+                }
+            },
+            // A batch of small messages is taken apart and each is handled on its own
+            None => match batch::Batch::decode(&data) {
+                Some(received) => received.batch.into_iter().map(Incoming::from).collect(),
+                None => vec![Incoming::decode(&data, now)],
+            },
+        };
+        // Authentic messages failing the content checks aren't passed on, without a penalty. A
+        // batch is passed on whole or not at all
+        for incoming in &chats {
+            if let Err(acceptance) = self.validator.check_content(&incoming.chat) {
+                debug!("[validator] ignored a message from {sender}: empty or oversized body");
+                return acceptance;
+            }
+        }
+        let mut acceptance = MessageAcceptance::Ignore;
+        for incoming in chats {
+            let taken = self.take_chat(incoming, &message, sender, id, peer_id, now);
+            if matches!(taken, MessageAcceptance::Accept) {
+                acceptance = taken;
+            }
+        }
+        acceptance
+    }
+
+    // Show and store one chat message from `sender`, carried by `message`.
+    fn take_chat(
+        &mut self,
+        incoming: Incoming,
+        message: &gossipsub::Message,
+        sender: PeerId,
+        id: &gossipsub::MessageId,
+        peer_id: PeerId,
+        now: u64,
+    ) -> MessageAcceptance {
+        let Incoming { mut chat, binary } = incoming;
+        let topic = self.room_of(&message.topic);
+        if let Some(attachment) = chat.attachment.take() {
+            let attachment = attachment.sanitized();
+            self.offers.offer(sender, attachment.clone());
+            self.resume_offered(sender, &attachment);
+            chat.attachment = Some(attachment);
+        }
+        self.presence.seen(&topic, sender, now);
+        if message.source.is_some() {
+            self.clock_samples.record(chat.timestamp, now);
+        }
+        if binary.is_some() {
+            self.counters.binary += 1;
+        }
+        // Once the table is full, a new peer's nick evicts the one learned longest ago. An
+        // unsigned message can't set the nick of the peer that merely relayed it.
+        let nick = sanitize::nick(&chat.nick);
+        let identity = self.identity(&sender, &nick);
+        if !nick.is_empty()
+            && message.source.is_some()
+            && identity != Identity::Impostor
+            && self.nicks.insert(sender, nick.clone()).as_ref() != Some(&nick)
+        {
+            // A new or changed nick may start or end a clash
+            self.refresh_collisions();
+        }
+        // Copies of a message the sender keeps repeating are hidden and not forwarded
+        let (verdict, ended) = self.floods.check(sender, &chat.body, now);
+        if let Some(run) = ended {
+            self.report_run(run);
+        }
+        if verdict == Verdict::Collapse {
+            if let Some(ban) = self.bans.record_repeat(sender, now) {
+                self.start_ban(ban);
+            }
+            return MessageAcceptance::Ignore;
+        }
+        // Spam enough members reported isn't shown, and doesn't count as filtered
+        let reported = self.spam.is_suppressed(id);
+        let shown = !reported
+            && self
+                .config
+                .filters
+                .get(&topic)
+                .is_none_or(|filter| filter.matches(&chat));
+        if shown {
+            self.report_filtered(&topic);
+            let signed = message.source.is_some();
+            // Our alias for the signed author comes first. A display name is only shown for the
+            // signed author it belongs to. A nick other online peers use as well, or that is our
+            // alias for someone else, gets the sender's PeerId suffix
+            let display_name = message
+                .source
+                .and_then(|author| self.config.aliases.get(&author).cloned())
+                .or_else(|| {
+                    message
+                        .source
+                        .filter(|_| self.display_names && identity != Identity::Impostor)
+                        .and_then(|author| self.profiles.get(&author))
+                        .map(|profile| profile.display_name.clone())
+                        .filter(|name| !name.is_empty())
+                })
+                .or_else(|| {
+                    self.nick_clashes(&sender, &nick)
+                        .then(|| collision::disambiguate(&nick, &sender))
+                });
+            self.notify(message.source, display_name.as_deref().unwrap_or(&nick), &chat);
+            let (line, links) = match display_name {
+                Some(nick) => {
+                    let chat = ChatMessage { nick, ..chat.clone() };
+                    message::render(&chat, signed, identity, id, &peer_id)
+                }
+                None => message::render(&chat, signed, identity, id, &peer_id),
+            };
+            say!("{line}");
+            self.show_links(sender, links);
+        } else if !reported {
+            self.filtered.entry(topic.clone()).or_default().hidden += 1;
+        }
+        // The channel gets the room's messages whether our filter shows them or not
+        if topic == self.topic.hash().as_str() {
+            let name = self.irc_name(&sender, &nick);
+            self.relay_to_irc(&name, &chat);
+            self.relay_to_matrix(&name, &chat);
+            self.relay_to_xmpp(&name, &chat);
+            self.relay_to_mqtt(&name, &chat);
+            self.relay_to_fediverse(&name, &chat);
+            if let Some(gateway) = &mut self.gateway {
+                gateway.deliver(&name, &chat.body);
+            }
+        }
+        self.forward_to_webhook(&topic, &sender, &nick, &chat);
+        let stored = StoredMessage {
+            id: id.to_string(),
+            source: message.source,
+            topic,
+            message: chat,
+            binary,
+            shown,
+        };
+        if !self.event_listeners.is_empty() {
+            self.emit(ChatEvent::message(&stored));
+        }
+        self.remember(stored);
+        MessageAcceptance::Accept
+    }
+
+    // Notify of a message from `author`, shown as `name`, if it mentions us or comes from a
+    // favorite contact. Unsigned messages can't come from a contact.
+    fn notify(&mut self, author: Option<PeerId>, name: &str, chat: &ChatMessage) {
+        if self.is_dnd() {
+            return;
+        }
+        let reason = if webhook::mentions(&chat.body, &self.nick) {
+            Reason::Mention
+        } else if author
+            .and_then(|author| self.config.contacts.get(&author))
+            .is_some_and(|contact| contact.favorite)
+        {
+            Reason::Favorite
+        } else {
+            return;
+        };
+        if let Some(notifier) = &mut self.notifier {
+            notifier.notify(name, &chat.body, reason, Instant::now());
+        }
+    }
+
+    fn report_undecryptable(&mut self, sender: PeerId, error: OpenError) {
+        if self.undecryptable.len() >= MAX_KNOWN_NICKS || !self.undecryptable.insert(sender) {
+            return;
+        }
+        match error {
+            OpenError::NotSealed => {
+                say!("[room] ignoring {sender}: its messages aren't encrypted for this room")
+            }
+            OpenError::WrongKey => say!(
+                "[room] can't decrypt messages from {sender}: {error}. \
+                 Is it using a different passphrase?"
+            ),
+        }
+    }
+
+    // A mistyped passphrase leads to a room of its own, so say so rather than stay silent.
+    fn check_empty_room(&mut self) {
+        if self.room_key.is_none()
+            || self.empty_room_reported
+            || self.started.elapsed() < Duration::from_secs(EMPTY_ROOM_NOTICE_AFTER)
+        {
+            return;
+        }
+        let connected = self.swarm.connected_peers().count();
+        let topic = self.wire_topic.hash();
+        let in_room = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .any(|(_, topics)| topics.contains(&&topic));
+        if in_room {
+            self.empty_room_reported = true;
+        } else if connected > 0 {
+            self.empty_room_reported = true;
+            say!(
+                "[room] connected to {connected} peers, but none of them knows this passphrase. \
+                 Check it for typos: a different phrase is a different room."
+            );
+        }
+    }
+
+    fn report_run(&self, run: Run) {
+        say!(
+            "[flood] {} repeated this {}×: '{}'",
+            self.display_name(&run.peer),
+            run.hidden,
+            sanitize::line(&run.body)
+        );
+    }
+
+    /// Links from trusted peers are clickable right away; others wait for `/link`.
+    fn show_links(&mut self, sender: PeerId, links: Vec<Link>) {
+        for link in links {
+            if self.trusted.contains(&sender) {
+                say!("[link] {}", sanitize::clickable(&link));
+                continue;
+            }
+            let number = self.next_link;
+            self.next_link += 1;
+            say!(
+                "[link] #{number} '{}' from an untrusted peer, /link {number} to open",
+                link.text
+            );
+            self.links.push_back((number, link));
+            if self.links.len() > MAX_HELD_LINKS {
+                self.links.pop_front();
+            }
+        }
+    }
+
+    /// Verify and act on a signed control message. Returns false if the message was invalid.
+    fn handle_control(&mut self, data: &[u8]) -> bool {
+        let verified = serde_json::from_slice::<SignedControl>(data)
+            .map_err(|e| e.to_string())
+            .and_then(|signed| signed.verify().map_err(|e| e.to_string()));
+        let (author, message) = match verified {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!("[control] dropped invalid control message: {e}");
+                return false;
+            }
+        };
+
+        match message {
+            ControlMessage::BlocklistUpdate(update) => {
+                self.receive_blocklist_update(author, update)
+            }
+            ControlMessage::Moderation(moderation) => self.receive_moderation(author, moderation),
+            ControlMessage::Proposal {
+                moderation,
+                partial,
+            } => return self.receive_proposal(author, moderation, partial),
+            ControlMessage::Approval(partial) => return self.receive_approval(author, partial),
+            ControlMessage::Decision {
+                moderation,
+                decision,
+            } => return self.receive_decision(author, moderation, decision),
+            ControlMessage::Join(join) => return self.receive_join(author, join),
+            ControlMessage::Rotation(rotation) => return self.receive_rotation(author, rotation),
+            ControlMessage::TopicAlias(alias) => return self.receive_topic_alias(author, alias),
+            ControlMessage::Report(report) => self.receive_report(author, *report),
+            ControlMessage::Leave { room } => {
+                if room == self.topic.hash().as_str() {
+                    self.presence.depart(&room, author, clock::unix_time());
+                }
+                say!(
+                    "{} left {}",
+                    self.display_name(&author),
+                    sanitize::line(&room)
+                )
+            }
+            ControlMessage::Profile { room, profile } => {
+                return self.receive_profile(author, &room, profile)
+            }
+            // Heartbeats are only tracked, never shown or kept in the history
+            ControlMessage::Presence {
+                room,
+                interval,
+                away,
+                dnd,
+                status,
+            } => {
+                if room == self.topic.hash().as_str() {
+                    self.presence
+                        .heartbeat(&room, author, interval, away, clock::unix_time());
+                    self.presence.set_dnd(&room, &author, dnd);
+                    let status = status.as_deref().and_then(presence::clean_status_line);
+                    self.presence.set_status_line(&room, &author, status);
+                }
+            }
+            // Receipts are only tracked, to tell when our messages stop getting through
+            ControlMessage::Receipt { room, mut ids } => {
+                if room == self.topic.hash().as_str() {
+                    ids.truncate(stall::MAX_RECEIPT_IDS);
+                    self.stall.confirmed(&ids, Instant::now());
+                }
+            }
+        }
+        true
+    }
+
+    fn receive_blocklist_update(&mut self, author: PeerId, update: BlocklistUpdate) {
+        let trusted = self.trusted.contains(&author);
+        let summary = describe_update(&update);
+        match self.blocklist.receive(
+            author,
+            update,
+            trusted,
+            self.auto_apply,
+            clock::unix_time(),
+        ) {
+            UpdateOutcome::Untrusted => {
+                say!("[blocklist] ignored update from untrusted peer {author}: {summary}")
+            }
+            UpdateOutcome::Stale | UpdateOutcome::TargetsSelf => {}
+            UpdateOutcome::Full => {
+                say!("[blocklist] ignored update via {author}: blocklist is full")
+            }
+            UpdateOutcome::Pending(id) => say!(
+                "[blocklist] #{id} via {author}: {summary} (pending, /blocklist apply {id} to accept)"
+            ),
+            UpdateOutcome::Applied(id, change) => {
+                self.enforce(change);
+                self.audit_update(author, id);
+                say!("[blocklist] #{id} via {author}: {summary} (applied)");
+            }
+        }
+    }
+
+    fn receive_moderation(&mut self, author: PeerId, moderation: Moderation) {
+        let room = moderation.room.clone();
+        let quorum = self.quorum(&room);
+        if moderation.action == ModAction::RoomBan && quorum > 1 {
+            return say!(
+                "[quorum] ignored a room ban of {} from {author}: bans in {} take the signatures \
+                 of {quorum} moderators",
+                moderation.target,
+                sanitize::line(&room),
+            );
+        }
+        let mut settings = self.config.rooms.get(&room).cloned().unwrap_or_default();
+        match self.rooms.receive(author, &moderation, &mut settings) {
+            ModerationOutcome::NotModerator => say!(
+                "[moderation] ignored {} of {} from {author}, who is not a moderator of {}",
+                describe_action(moderation.action),
+                moderation.target,
+                sanitize::line(&room),
+            ),
+            ModerationOutcome::Stale | ModerationOutcome::TargetsSelf => {}
+            ModerationOutcome::Honored => {
+                if moderation.action == ModAction::RoomBan {
+                    self.record_room_ban(author, &moderation);
+                    self.config.rooms.insert(room, settings);
+                    self.save_config();
+                }
+                self.audit(author, AuditEvent::moderation(&moderation));
+                self.print_removal(author, &moderation);
+            }
+        }
+    }
+
+    /// Admit a peer that presents a valid invite. Returns false if the invite was invalid.
+    fn receive_join(&mut self, author: PeerId, join: Join) -> bool {
+        let room = join.room.clone();
+        let Some(settings) = self.config.rooms.get_mut(&room) else {
+            return true;
+        };
+        // Open rooms need no invite, and known members only repeat theirs for newcomers
+        let Some(owner) = settings.owner else {
+            return true;
+        };
+        if settings.admits(&author) {
+            return true;
+        }
+
+        match invite::check(&join.invite, &room, Some(owner), author, clock::unix_time()) {
+            Ok((_, invite)) => {
+                if !settings.admit(author) {
+                    say!("[invite] {room} has too many members, ignoring {author}");
+                    return true;
+                }
+                for moderator in invite.moderators {
+                    if !settings.moderators.contains(&moderator) {
+                        settings.moderators.push(moderator);
+                    }
+                }
+                self.uninvited.remove(&author);
+                self.save_config();
+                say!("[invite] {} joined {room}", self.display_name(&author));
+                self.audit(author, AuditEvent::InviteUsed { room });
+                true
+            }
+            Err(e) => {
+                say!("[invite] refused join from {author}: {e}");
+                false
+            }
+        }
+    }
+
+    /// Cache a peer's profile. Returns false if it was invalid.
+    fn receive_profile(&mut self, author: PeerId, room: &str, profile: Profile) -> bool {
+        if let Err(e) = profile.check() {
+            warn!("[profile] dropped the profile of {author}: {e}");
+            return false;
+        }
+        if room != self.topic.hash().as_str()
+            || (self.profiles.len() >= MAX_KNOWN_NICKS && !self.profiles.contains_key(&author))
+        {
+            return true;
+        }
+        if profile.is_empty() {
+            self.profiles.remove(&author);
+        } else {
+            self.profiles.insert(author, profile.sanitized());
+        }
+        true
+    }
+
+    /// Publish our profile to the room, unless it is empty.
+    fn announce_profile(&mut self) {
+        if self.config.profile.is_empty() {
+            return;
+        }
+        let message = ControlMessage::Profile {
+            room: self.topic.hash().into_string(),
+            profile: self.config.profile.clone(),
+        };
+        // Nobody to tell yet; peers get it when they subscribe
+        if let Err(e) = self.publish_control(&message) {
+            debug!("[profile] profile not sent: {e}");
+        }
+    }
+
+    /// Present our invite, if we joined the room with one, so members we haven't met admit us.
+    fn announce_join(&mut self) {
+        let room = self.topic.hash().into_string();
+        let Some(token) = self
+            .config
+            .rooms
+            .get(&room)
+            .and_then(|settings| settings.invite.clone())
+        else {
+            return;
+        };
+        let invite = match invite::decode_token(&token) {
+            Ok(invite) => invite,
+            Err(e) => {
+                say!("[invite] stored invite for {room} is unusable: {e}");
+                return;
+            }
+        };
+        if let Err(e) = self.publish_control(&ControlMessage::Join(Join { room, invite })) {
+            say!("[invite] failed to present invite: {e}");
+        }
+    }
+
+    /// Publish our own key rotation, if it is still in its grace period.
+    fn announce_rotation(&mut self, now: u64) {
+        let Some(signed) = self.rotation.clone() else {
+            return;
+        };
+        if identity::check(&signed, self.local_peer_id(), now).is_err() {
+            self.rotation = None;
+            return;
+        }
+        // Fails while no peer is connected; the next tick tries again
+        if self
+            .publish_control(&ControlMessage::Rotation(signed))
+            .is_ok()
+        {
+            self.rotation_announced = Some(now);
+        }
+    }
+
+    // Move the trust placed in a peer's old identity to its new one. Only a statement signed
+    // by the old key itself is applied.
+    fn receive_rotation(&mut self, author: PeerId, signed: SignedRotation) -> bool {
+        let Rotation { old, new, .. } = match identity::check(&signed, author, clock::unix_time()) {
+            Ok(rotation) => rotation,
+            Err(e) => {
+                say!("[identity] ignored key rotation announced by {author}: {e}");
+                return false;
+            }
+        };
+        if self.rotated.contains(&old) || self.rotated.len() >= MAX_KNOWN_NICKS {
+            return true;
+        }
+        self.rotated.insert(old);
+        self.audit(new, AuditEvent::KeyRotation { old, new });
+
+        let mut moved = Vec::new();
+        if self.config.verified.iter().any(|v| v.peer == old) {
+            if self.is_verified(&new) {
+                self.config.verified.retain(|v| v.peer != old);
+            } else {
+                for verified in self.config.verified.iter_mut().filter(|v| v.peer == old) {
+                    verified.peer = new;
+                }
+            }
+            self.impostors.remove(&new);
+            moved.push("verification");
+        }
+        if self.trusted.remove(&old) {
+            self.trusted.insert(new);
+            moved.push("blocklist trust");
+        }
+        let mut moderator = false;
+        for settings in self.config.rooms.values_mut() {
+            for peer in settings.moderators.iter_mut().filter(|peer| **peer == old) {
+                *peer = new;
+                moderator = true;
+            }
+        }
+        if moderator {
+            moved.push("moderator status");
+        }
+        let name = self.display_name(&old);
+        if let Some(nick) = self.nicks.remove(&old) {
+            self.nicks.insert(new, nick);
+            moved.push("nick");
+        }
+        if !moved.is_empty() {
+            self.save_config();
+            say!(
+                "[identity] {name} ({old}) rotated its key to {new}; moved {}",
+                moved.join(", ")
+            );
+        }
+        true
+    }
+
+    /// Sign an invite to the current room, making it invite-only with us as owner if it
+    /// wasn't already, and return its token.
+    pub fn create_invite(&mut self, ttl: u64, invitee: Option<PeerId>) -> Result<String, String> {
+        self.sign_invite(ttl, invitee)
+            .map(|signed| invite::encode_token(&signed))
+    }
+
+    fn sign_invite(&mut self, ttl: u64, invitee: Option<PeerId>) -> Result<SignedInvite, String> {
+        let room = self.topic.hash().into_string();
+        let local = self.local_peer_id();
+        let mut settings = self.config.rooms.get(&room).cloned().unwrap_or_default();
+        match settings.owner {
+            Some(owner) if owner != local => {
+                return Err(format!(
+                    "only the owner of {room} ({owner}) can create invites"
+                ))
+            }
+            Some(_) => {}
+            None => {
+                settings.owner = Some(local);
+                say!("[invite] {room} is now invite-only, owned by you");
+            }
+        }
+
+        let now = clock::unix_time();
+        let mut moderators = self.rooms.moderators(&room, Some(&settings));
+        if !moderators.contains(&local) {
+            moderators.push(local);
+        }
+        let invite = Invite {
+            room: room.clone(),
+            moderators,
+            invitee,
+            issued_at: now,
+            expires_at: now.saturating_add(ttl),
+        };
+        let signed = SignedInvite::sign(&self.keypair, &invite).map_err(|e| e.to_string())?;
+        self.config.rooms.insert(room.clone(), settings);
+        self.save_config();
+        self.audit(
+            local,
+            AuditEvent::InviteCreated {
+                room,
+                invitee,
+                expires_at: invite.expires_at,
+            },
+        );
+        Ok(signed)
+    }
+
+    /// Print an invite as a QR code drawn with half blocks, with its token underneath. Only the
+    /// token is printed where the terminal can't take the half blocks or is too narrow for the
+    /// code.
+    fn print_invite_qr(&self, signed: &SignedInvite, ttl: u64) {
+        let token = invite::encode_qr_token(signed);
+        let columns = qr::terminal_columns();
+        if !qr::utf8_terminal() {
+            say!("[invite] the terminal isn't UTF-8, so the invite is only shown as text");
+        } else {
+            match QrCode::encode(&token, qr::max_version(columns)) {
+                // In one piece, so the printer neither skips nor merges lines of it
+                Some(code) => say!("{}", code.render().trim_end_matches('\n')),
+                None => say!(
+                    "[invite] the invite is too long for a QR code {columns} columns wide, \
+                     so it is only shown as text"
+                ),
+            }
+        }
+        say!(
+            "[invite] valid for {}, join with: --join-with {token}",
+            clock::format_duration(ttl)
+        );
+    }
+
+    /// Whether `peer` presented a valid invite to the current room.
+    pub fn is_member(&self, peer: &PeerId) -> bool {
+        self.config
+            .rooms
+            .get(self.topic.hash().as_str())
+            .is_some_and(|settings| settings.members.contains(peer))
+    }
+
+    /// Kick or ban a peer from the current room and tell the other members.
+    fn moderate(&mut self, action: ModAction, target: PeerId, reason: String) {
+        let room = self.topic.hash().into_string();
+        let moderation = Moderation {
+            action,
+            room: room.clone(),
+            target,
+            reason,
+            timestamp: self.next_timestamp(target),
+        };
+        if action == ModAction::RoomBan && self.quorum(&room) > 1 {
+            return self.propose(moderation);
+        }
+        let mut settings = self.config.rooms.get(&room).cloned().unwrap_or_default();
+        self.rooms.apply(&moderation, &mut settings);
+        if action == ModAction::RoomBan {
+            self.record_room_ban(self.local_peer_id(), &moderation);
+            self.config.rooms.insert(room.clone(), settings.clone());
+            self.save_config();
+        }
+        self.print_removal(self.local_peer_id(), &moderation);
+        // Record which reports the action answered
+        for received in self.reports.resolve(&room, &target) {
+            self.audit(
+                self.local_peer_id(),
+                AuditEvent::ReportActedOn {
+                    action,
+                    room: room.clone(),
+                    target,
+                    reporter: received.reporter,
+                    message_id: received.report.message_id,
+                    reason: received.report.reason,
+                },
+            );
+        }
+
+        if !self
+            .rooms
+            .moderators(&room, Some(&settings))
+            .contains(&self.local_peer_id())
+        {
+            say!(
+                "[moderation] you are not a moderator of {room}, so other members will ignore this"
+            );
+        }
+        if let Err(e) = self.publish_control(&ControlMessage::Moderation(moderation)) {
+            say!(
+                "[moderation] failed to publish {}: {e}",
+                describe_action(action)
+            );
+        }
+    }
+
+    /// Report a received message to the moderators of its room.
+    // The message in the history a `/report` or `/spam` is about.
+    fn reported_message(&self, target: &ReportTarget) -> Result<&StoredMessage, String> {
+        let stored = match target {
+            ReportTarget::Id(id) => self.history.iter().rev().find(|m| m.id == *id),
+            ReportTarget::LastFrom(name) => {
+                let peer = self.resolve_peer(name)?;
+                self.history.iter().rev().find(|m| m.source == Some(peer))
+            }
+        };
+        stored.ok_or_else(|| "no such message in the history".to_string())
+    }
+
+    fn send_report(&mut self, target: ReportTarget, reason: String) {
+        let stored = match self.reported_message(&target) {
+            Ok(stored) => stored,
+            Err(e) => return say!("[report] {e}"),
+        };
+        let room = stored.topic.clone();
+        let moderators = self.rooms.moderators(&room, self.config.rooms.get(&room));
+        if moderators.is_empty() {
+            return say!("[report] {room} has no moderators to report to");
+        }
+        let report = Report {
+            room,
+            moderators,
+            message_id: stored.id.clone(),
+            author: stored.source,
+            message: stored.message.clone(),
+            reason,
+            timestamp: clock::unix_time(),
+        };
+        let count = report.moderators.len();
+        match self.publish_control(&ControlMessage::Report(Box::new(report))) {
+            Ok(()) => say!("[report] sent to {count} moderators"),
+            Err(e) => say!("[report] failed to send: {e}"),
+        }
+    }
+
+    fn receive_report(&mut self, reporter: PeerId, report: Report) {
+        let room = sanitize::line(&report.room);
+        match self.reports.receive(reporter, report, clock::unix_time()) {
+            ReportOutcome::NotAddressed | ReportOutcome::Duplicate(_) => {}
+            ReportOutcome::RateLimited => {
+                warn!("[report] ignored a report from {reporter}: too many reports")
+            }
+            ReportOutcome::New(id) => {
+                if let Some(received) = self.reports.all().find(|r| r.id == id) {
+                    say!("{}", self.describe_report(received));
+                    say!("[report]   /kick or /roomban the author in {room} to act on it");
+                }
+            }
+        }
+    }
+
+    // Report a message as spam to the room, hiding it once enough members did.
+    fn send_spam(&mut self, target: ReportTarget) {
+        let id = match self.reported_message(&target) {
+            Ok(stored) => message_id_of(&stored.id),
+            Err(e) => return say!("[spam] {e}"),
+        };
+        if self.spam.reported_by(&id, &self.local_peer_id()) {
+            return say!("[spam] you already reported {id}");
+        }
+        let report = match SpamReport::sign(&self.keypair, id.clone()) {
+            Ok(report) => report,
+            Err(e) => return say!("[spam] {e}"),
+        };
+        if let Err(e) = self.publish_spam(&SpamMessage::Report(report.clone())) {
+            return say!("[spam] failed to send the report: {e}");
+        }
+        let hidden = self.spam.is_suppressed(&id);
+        if let Err(e) = self.spam.add_report(report) {
+            return say!("[spam] {e}");
+        }
+        let (count, threshold) = (self.spam.count(&id), self.spam.threshold());
+        say!("[spam] reported {id}: {count} of the {threshold} reports it takes to hide it");
+        if !hidden && self.spam.is_suppressed(&id) {
+            self.hide_spam(&id);
+        }
+    }
+
+    // Where we listen, how many peers we are connected to, and the bootstrap peers.
+    fn print_net(&self) {
+        for address in self.swarm.listeners() {
+            say!("[net] listening on {address}");
+        }
+        say!(
+            "[net] connected to {} peers",
+            self.swarm.connected_peers().count()
+        );
+        let domain = self.bootstrap.domain();
+        match (domain, self.bootstrap.resolved()) {
+            (Some(domain), Some((_, at))) => say!(
+                "[net] bootstrap peers published for {domain}, looked up {}s ago:",
+                clock::unix_time().saturating_sub(at)
+            ),
+            (Some(domain), None) => say!("[net] no bootstrap peers found for {domain} yet"),
+            (None, _) => {}
+        }
+        if let Some(e) = self.bootstrap.last_error() {
+            say!("[net] the last lookup failed: {e}");
+        }
+        if self.bootstrap.resolved().is_none() && !self.bootstrap.static_addresses().is_empty() {
+            say!("[net] bootstrap peers of --bootstrap:");
+        }
+        for address in self.bootstrap.addresses() {
+            let connected =
+                bootstrap::peer_id(address).is_some_and(|peer| self.swarm.is_connected(&peer));
+            say!(
+                "[net]   {address}{}",
+                if connected { " (connected)" } else { "" }
+            );
+        }
+    }
+
+    fn print_peers(&self) {
+        let now = clock::unix_time();
+        let room = self.topic.hash().into_string();
+        let peers = self.presence.room(&room, now);
+        if peers.is_empty() {
+            return say!("[peers] nobody seen in {room} yet");
+        }
+        for (peer, status, seen_at) in peers {
+            let away = if self.presence.is_dnd(&room, &peer) {
+                " (dnd)"
+            } else if self.presence.is_away(&room, &peer) {
+                " (away)"
+            } else {
+                ""
+            };
+            // Long status lines are cut short here; /whois shows them in full
+            let line = match self.presence.status_line(&room, &peer, now) {
+                Some(line) => format!(
+                    " \"{}\"",
+                    presence::shorten(line, presence::ROSTER_STATUS_CHARS)
+                ),
+                None => String::new(),
+            };
+            say!(
+                "[peers] {} ({peer}) {status}{away}{line}, last seen {}s ago",
+                self.display_name(&peer),
+                now.saturating_sub(seen_at)
+            );
+        }
+    }
+
+    fn print_reports(&self) {
+        let mut pending = self.reports.pending().peekable();
+        if pending.peek().is_none() {
+            return say!("[report] no pending reports");
+        }
+        for received in pending {
+            say!("{}", self.describe_report(received));
+        }
+    }
+
+    fn describe_report(&self, received: &ReceivedReport) -> String {
+        let report = &received.report;
+        let author = match &report.author {
+            Some(author) => format!("{} ({author})", self.display_name(author)),
+            None => format!("{} (unsigned)", sanitize::nick(&report.message.nick)),
+        };
+        let reason = match report.reason.as_str() {
+            "" => String::new(),
+            reason => format!(": {}", sanitize::line(reason)),
+        };
+        format!(
+            "[report] #{} {} reported {author} in {}, message {}: '{}'{reason}",
+            received.id,
+            self.display_name(&received.reporter),
+            sanitize::line(&report.room),
+            sanitize::line(&report.message_id),
+            sanitize::line(&report.message.body),
+        )
+    }
+
+    /// How many moderators must sign a room ban in `room`.
+    fn quorum(&self, room: &str) -> usize {
+        let settings = self.config.rooms.get(room);
+        settings.and_then(|settings| settings.quorum).unwrap_or(1)
+    }
+
+    fn is_moderator(&self, room: &str, peer: &PeerId) -> bool {
+        self.rooms
+            .moderators(room, self.config.rooms.get(room))
+            .contains(peer)
+    }
+
+    /// Propose a room ban to the room's moderators, signed by us, to be honored once a quorum
+    /// of them has signed it.
+    fn propose(&mut self, moderation: Moderation) {
+        let (local, room) = (self.local_peer_id(), moderation.room.clone());
+        if !self.is_moderator(&room, &local) {
+            return say!("[quorum] only moderators of {room} can propose a room ban");
+        }
+        self.expire_proposals();
+        let hash = match self.decisions.propose(local, moderation.clone()) {
+            Ok(hash) => hash,
+            Err(e) => return say!("[quorum] {e}"),
+        };
+        let partial = match PartialSig::sign(&self.keypair, hash) {
+            Ok(partial) => partial,
+            Err(e) => return say!("[quorum] can't sign the proposal: {e}"),
+        };
+        if let Err(e) = self.decisions.sign(local, &partial) {
+            return say!("[quorum] can't sign the proposal: {e}");
+        }
+        say!(
+            "[quorum] proposed banning {} from {room} as #{}; it takes the signatures of {} \
+             moderators",
+            self.display_name(&moderation.target),
+            decision::short_id(&hash),
+            self.quorum(&room)
+        );
+        if let Err(e) = self.publish_control(&ControlMessage::Proposal {
+            moderation,
+            partial,
+        }) {
+            say!("[quorum] failed to publish the proposal: {e}");
+        }
+    }
+
+    /// Take a moderator's proposal of a room ban, signed by the proposer. Returns false if the
+    /// signature is invalid.
+    fn receive_proposal(
+        &mut self,
+        author: PeerId,
+        moderation: Moderation,
+        partial: PartialSig,
+    ) -> bool {
+        let room = moderation.room.clone();
+        if !self.is_moderator(&room, &author) {
+            say!(
+                "[quorum] ignored a proposal from {author}, who is not a moderator of {}",
+                sanitize::line(&room)
+            );
+            return true;
+        }
+        if partial.proposal_hash != decision::proposal_hash(&moderation)
+            || partial.verify(&author).is_err()
+        {
+            return false;
+        }
+        let hash = partial.proposal_hash;
+        if self.decisions.is_decided(&hash) {
+            return true;
+        }
+        self.expire_proposals();
+        if let Err(e) = self.decisions.propose(author, moderation.clone()) {
+            say!("[quorum] ignored a proposal from {author}: {e}");
+            return true;
+        }
+        let Ok(signatures) = self.decisions.sign(author, &partial) else {
+            return false;
+        };
+        let id = decision::short_id(&hash);
+        say!(
+            "[quorum] {} proposes to ban {} from {} ({}): #{id}, {signatures} of {} signatures, \
+             /approve {id} to sign",
+            self.display_name(&author),
+            self.display_name(&moderation.target),
+            sanitize::line(&room),
+            sanitize::line(&moderation.reason),
+            self.quorum(&room)
+        );
+        self.check_quorum(hash);
+        true
+    }
+
+    /// Sign the proposal whose id starts with `id`, as a moderator.
+    fn approve(&mut self, id: &str) {
+        let local = self.local_peer_id();
+        let Some(hash) = self.decisions.find(id) else {
+            return say!("[quorum] no proposal #{id}, see /proposals");
+        };
+        let room = match self.decisions.get(&hash) {
+            Some(pending) => pending.moderation.room.clone(),
+            None => return,
+        };
+        if !self.is_moderator(&room, &local) {
+            return say!("[quorum] only moderators of {room} can approve its bans");
+        }
+        let partial = match PartialSig::sign(&self.keypair, hash) {
+            Ok(partial) => partial,
+            Err(e) => return say!("[quorum] can't sign the proposal: {e}"),
+        };
+        match self.decisions.sign(local, &partial) {
+            Ok(signatures) => say!(
+                "[quorum] approved #{}: {signatures} of {} signatures",
+                decision::short_id(&hash),
+                self.quorum(&room)
+            ),
+            Err(e) => return say!("[quorum] can't sign the proposal: {e}"),
+        }
+        if let Err(e) = self.publish_control(&ControlMessage::Approval(partial)) {
+            say!("[quorum] failed to publish the approval: {e}");
+        }
+        self.check_quorum(hash);
+    }
+
+    /// Take a moderator's signature of a proposal. Returns false if the signature is invalid.
+    fn receive_approval(&mut self, author: PeerId, partial: PartialSig) -> bool {
+        let hash = partial.proposal_hash;
+        // Proposals we haven't seen, or that are decided already, have nothing to add to
+        let Some(pending) = self.decisions.get(&hash) else {
+            return true;
+        };
+        let room = pending.moderation.room.clone();
+        if !self.is_moderator(&room, &author) {
+            say!(
+                "[quorum] ignored an approval from {author}, who is not a moderator of {}",
+                sanitize::line(&room)
+            );
+            return true;
+        }
+        let Ok(signatures) = self.decisions.sign(author, &partial) else {
+            return false;
+        };
+        say!(
+            "[quorum] {} approved #{}: {signatures} of {} signatures",
+            self.display_name(&author),
+            decision::short_id(&hash),
+            self.quorum(&room)
+        );
+        self.check_quorum(hash);
+        true
+    }
+
+    /// Honor the proposal with this hash once a quorum of moderators has signed it. If our
+    /// signature completed the quorum, publish the decision for members who missed some of
+    /// the signatures.
+    fn check_quorum(&mut self, hash: [u8; 32]) {
+        let Some(pending) = self.decisions.get(&hash) else {
+            return;
+        };
+        let quorum = self.quorum(&pending.moderation.room);
+        if pending.signatures.len() < quorum {
+            return;
+        }
+        let lifted = self.lifted_at(&pending.moderation);
+        if let Err(e) = decision::check_fresh(&pending.moderation, lifted, clock::unix_time()) {
+            self.decisions.decide(hash);
+            return say!("[quorum] dropped #{}: {e}", decision::short_id(&hash));
+        }
+        let decision = AggregateDecision::aggregate(hash, &pending.signatures);
+        let Some(pending) = self.decisions.decide(hash) else {
+            return;
+        };
+        say!(
+            "[quorum] #{} is decided by {} moderators",
+            decision::short_id(&hash),
+            decision.signers.len()
+        );
+        self.honor_decision(pending.proposer, &pending.moderation);
+        if decision.signers.last() == Some(&self.local_peer_id()) {
+            let message = ControlMessage::Decision {
+                moderation: pending.moderation,
+                decision,
+            };
+            if let Err(e) = self.publish_control(&message) {
+                say!("[quorum] failed to publish the decision: {e}");
+            }
+        }
+    }
+
+    /// Honor a room ban a quorum of moderators decided on, once its signatures check out
+    /// against the room's moderators and quorum.
+    fn receive_decision(
+        &mut self,
+        author: PeerId,
+        moderation: Moderation,
+        decision: AggregateDecision,
+    ) -> bool {
+        let hash = decision.proposal_hash;
+        if self.decisions.is_decided(&hash) {
+            return true;
+        }
+        let room = moderation.room.clone();
+        let moderators = self.rooms.moderators(&room, self.config.rooms.get(&room));
+        let lifted = self.lifted_at(&moderation);
+        let checked = decision::check_fresh(&moderation, lifted, clock::unix_time())
+            .and_then(|()| decision.verify(&moderation, &moderators, self.quorum(&room)));
+        if let Err(e) = checked {
+            say!(
+                "[quorum] ignored decision #{} from {author}: {e}",
+                decision::short_id(&hash)
+            );
+            return true;
+        }
+        let proposer = match self.decisions.decide(hash) {
+            Some(pending) => pending.proposer,
+            None => decision.signers.first().copied().unwrap_or(author),
+        };
+        say!(
+            "[quorum] #{} is decided by {} moderators",
+            decision::short_id(&hash),
+            decision.signers.len()
+        );
+        self.honor_decision(proposer, &moderation);
+        true
+    }
+
+    // Drop the proposals that could no longer be honored, so they don't keep new ones out
+    fn expire_proposals(&mut self) {
+        let rooms = &self.config.rooms;
+        let expired = self.decisions.expire(clock::unix_time(), |moderation| {
+            let settings = rooms.get(&moderation.room)?;
+            settings.lifted.get(&moderation.target).copied()
+        });
+        if expired > 0 {
+            debug!("[quorum] dropped {expired} proposals too old to honor");
+        }
+    }
+
+    // When the target of `moderation` was last let back into its room, if it was
+    fn lifted_at(&self, moderation: &Moderation) -> Option<u64> {
+        let settings = self.config.rooms.get(&moderation.room)?;
+        settings.lifted.get(&moderation.target).copied()
+    }
+
+    // Apply a room ban decided by a quorum, proposed by `proposer`
+    fn honor_decision(&mut self, proposer: PeerId, moderation: &Moderation) {
+        if moderation.target == self.local_peer_id() {
+            return;
+        }
+        let room = moderation.room.clone();
+        let mut settings = self.config.rooms.get(&room).cloned().unwrap_or_default();
+        self.rooms.apply(moderation, &mut settings);
+        self.record_room_ban(proposer, moderation);
+        self.config.rooms.insert(room, settings);
+        self.save_config();
+        self.audit(proposer, AuditEvent::moderation(moderation));
+        self.print_removal(proposer, moderation);
+    }
+
+    fn print_proposals(&self) {
+        let room = self.topic.hash().into_string();
+        let quorum = self.quorum(&room);
+        let mut pending: Vec<_> = self
+            .decisions
+            .pending()
+            .filter(|(_, pending)| pending.moderation.room == room)
+            .collect();
+        if pending.is_empty() {
+            say!("No proposals waiting in {room}");
+        }
+        pending.sort_by_key(|(_, pending)| pending.moderation.timestamp);
+        for (hash, pending) in pending {
+            say!(
+                "#{}: ban {} ({}), proposed by {}, {} of {quorum} signatures",
+                decision::short_id(hash),
+                self.display_name(&pending.moderation.target),
+                sanitize::line(&pending.moderation.reason),
+                self.display_name(&pending.proposer),
+                pending.signatures.len()
+            );
+        }
+    }
+
+    /// Show or set how many moderators must sign a room ban in the current room.
+    fn set_quorum(&mut self, quorum: Option<usize>) {
+        let room = self.topic.hash().into_string();
+        if let Some(quorum) = quorum {
+            let settings = self.config.rooms.entry(room.clone()).or_default();
+            settings.quorum = (quorum > 1).then_some(quorum);
+            self.save_config();
+        }
+        match self.quorum(&room) {
+            1 => say!("[quorum] any moderator can ban peers from {room}"),
+            quorum => say!("[quorum] room bans in {room} take {quorum} moderators' signatures"),
+        }
+    }
+
+    fn print_removal(&self, moderator: PeerId, moderation: &Moderation) {
+        let verb = match moderation.action {
+            ModAction::Kick => "removed",
+            ModAction::RoomBan => "banned",
+        };
+        let target = self.display_name(&moderation.target);
+        let moderator = self.display_name(&moderator);
+        if moderation.reason.is_empty() {
+            say!("{target} was {verb} by {moderator}");
+        } else {
+            say!(
+                "{target} was {verb} by {moderator} ({})",
+                sanitize::line(&moderation.reason)
+            );
+        }
+    }
+
+    /// The nick a peer last used, or its PeerId if it hasn't sent anything yet. Verified peers
+    /// get a ✓.
+    pub fn display_name(&self, peer: &PeerId) -> String {
+        if *peer == self.local_peer_id() {
+            return self.nick.clone();
+        }
+        let name = match (self.config.aliases.get(peer), self.nicks.get(peer)) {
+            (Some(alias), _) => alias.clone(),
+            (None, Some(nick)) if self.nick_clashes(peer, nick) => {
+                collision::disambiguate(nick, peer)
+            }
+            (None, Some(nick)) => nick.clone(),
+            (None, None) => peer.to_string(),
+        };
+        if self.is_verified(peer) {
+            format!("{name} ✓")
+        } else {
+            name
+        }
+    }
+
+    // Whether the nick `peer` uses needs its PeerId suffix to be told apart: other peers online
+    // use it too, or it is our alias for another peer.
+    fn nick_clashes(&self, peer: &PeerId, nick: &str) -> bool {
+        self.collisions.is_ambiguous(peer)
+            || self
+                .config
+                .aliases
+                .iter()
+                .any(|(other, alias)| other != peer && alias.eq_ignore_ascii_case(nick))
+    }
+
+    /// Whether the user confirmed `peer`'s fingerprint with `/verify`.
+    pub fn is_verified(&self, peer: &PeerId) -> bool {
+        self.config.verified.iter().any(|v| v.peer == *peer)
+    }
+
+    // Check `sender` against the verified peers, warning loudly the first time a peer claims
+    // the nick a verified peer was verified with.
+    fn identity(&mut self, sender: &PeerId, nick: &str) -> Identity {
+        if self.is_verified(sender) {
+            return Identity::Verified;
+        }
+        let Some(verified) = self
+            .config
+            .verified
+            .iter()
+            .find(|v| !nick.is_empty() && v.nick.eq_ignore_ascii_case(nick))
+        else {
+            return Identity::Unverified;
+        };
+        if self.impostors.len() < MAX_KNOWN_NICKS && self.impostors.insert(*sender) {
+            say!(
+                "[verify] !!! WARNING: {sender} is using the nick '{nick}' of verified peer {}, \
+                 but its key is different. It is NOT the peer you verified. !!!",
+                verified.peer
+            );
+        }
+        Identity::Impostor
+    }
+
+    // A peer given by PeerId, by its contact label or alias, or by the nick it uses (or was
+    // verified with). Labels and aliases come before the nicks of whoever else is in the room.
+    fn resolve_peer(&self, target: &str) -> Result<PeerId, String> {
+        if let Ok(peer) = target.parse() {
+            return Ok(peer);
+        }
+        if let Some(contact) = self.config.contacts.by_label(target) {
+            return Ok(contact.peer);
+        }
+        let mut aliased = self.config.aliases.iter();
+        if let Some((peer, _)) = aliased.find(|(_, alias)| alias.eq_ignore_ascii_case(target)) {
+            return Ok(*peer);
+        }
+        // A shared nick as it is shown, with the suffix of one of the peers using it
+        if let Some((nick, suffix)) = collision::split(target) {
+            let mut matches = self.nicks.iter().filter(|(peer, known)| {
+                known.eq_ignore_ascii_case(nick) && collision::suffix(peer) == suffix
+            });
+            if let (Some((peer, _)), None) = (matches.next(), matches.next()) {
+                return Ok(*peer);
+            }
+        }
+        let mut matches: Vec<PeerId> = self
+            .nicks
+            .iter()
+            .filter(|(_, nick)| nick.eq_ignore_ascii_case(target))
+            .map(|(peer, _)| *peer)
+            .chain(
+                self.config
+                    .verified
+                    .iter()
+                    .filter(|v| v.nick.eq_ignore_ascii_case(target))
+                    .map(|v| v.peer),
+            )
+            .collect();
+        matches.sort();
+        matches.dedup();
+        match matches.as_slice() {
+            [] => Err(format!("no peer known as {target:?}")),
+            [peer] => Ok(*peer),
+            peers => Err(format!(
+                "{} peers use the nick {target:?}, give one of: {}",
+                peers.len(),
+                peers
+                    .iter()
+                    .map(|peer| {
+                        let nick = self.nicks.get(peer).map_or(target, String::as_str);
+                        format!("{} ({peer})", collision::disambiguate(nick, peer))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+        }
+    }
+
+    fn run_verify(&mut self, target: Option<String>, confirm: bool) {
+        let Some(target) = target else {
+            let fingerprint = Fingerprint::of(&self.local_peer_id());
+            say!("[verify] your fingerprint: {}", fingerprint.hex());
+            say!("[verify]   {}", fingerprint.words());
+            return;
+        };
+        let peer = match self.resolve_peer(&target) {
+            Ok(peer) => peer,
+            Err(e) => return say!("[verify] {e}"),
+        };
+        let fingerprint = Fingerprint::of(&peer);
+        say!(
+            "[verify] {} ({peer}): {}",
+            self.display_name(&peer),
+            fingerprint.hex()
+        );
+        say!("[verify]   {}", fingerprint.words());
+        if !confirm {
+            say!("[verify] compare this with the peer over another channel, then /verify {target} confirm");
+        } else if self.is_verified(&peer) {
+            say!("[verify] {peer} is already verified");
+        } else {
+            let nick = self.nicks.get(&peer).cloned().unwrap_or_default();
+            self.config.verified.push(VerifiedPeer {
+                peer,
+                nick: nick.clone(),
+                verified_at: clock::unix_time(),
+            });
+            self.save_config();
+            self.audit(self.local_peer_id(), AuditEvent::Verified { peer, nick });
+            say!("[verify] marked {} as verified", self.display_name(&peer));
+        }
+    }
+
+    fn run_unverify(&mut self, target: &str) {
+        let peer = match self.resolve_peer(target) {
+            Ok(peer) => peer,
+            Err(e) => return say!("[verify] {e}"),
+        };
+        let before = self.config.verified.len();
+        self.config.verified.retain(|v| v.peer != peer);
+        if self.config.verified.len() == before {
+            say!("[verify] {peer} was not verified");
+        } else {
+            self.save_config();
+            self.audit(self.local_peer_id(), AuditEvent::Unverified { peer });
+            say!("[verify] {peer} is no longer verified");
+        }
+    }
+
+    fn print_whois(&self, peer: PeerId) {
+        say!("[whois] {peer}: {}", self.display_name(&peer));
+        if self.config.aliases.contains_key(&peer) {
+            let nick = self.nicks.get(&peer).map_or("unknown", String::as_str);
+            say!("[whois]   your alias; the peer calls itself {nick}");
+        }
+        let room = self.topic.hash().into_string();
+        if let Some(status) = self.presence.status_line(&room, &peer, clock::unix_time()) {
+            say!("[whois]   status: {status}");
+        }
+        match self.verified_key(&peer) {
+            Some((key, count)) => say!(
+                "[whois]   signing key: {} (verified on {count} messages)",
+                signed::describe_key(&key)
+            ),
+            None if self.signers.contains_key(&peer) => {
+                say!("[whois]   signed messages verified, but the key isn't inlined in the peer id")
+            }
+            None => say!("[whois]   no signed messages received"),
+        }
+        if let Some(rtt) = self.pings.rtt(&peer) {
+            say!(
+                "[whois]   ping: {}ms, score adjustment {:+.2}",
+                rtt.as_millis(),
+                self.pings.adjustment(&peer)
+            );
+        }
+    }
+
+    fn print_moderators(&self) {
+        let room = self.topic.hash().into_string();
+        let moderators = self.rooms.moderators(&room, self.config.rooms.get(&room));
+        if moderators.is_empty() {
+            say!("No moderators for {room}");
+        }
+        for moderator in moderators {
+            say!("Moderator: {} ({moderator})", self.display_name(&moderator));
+        }
+    }
+
+    /// Run a slash command.
+    fn run_command(&mut self, command: UserCommand) {
+        match command {
+            UserCommand::Help => say!("{}", commands::HELP),
+            // Only reaches here in read-only mode, as a note like any other line
+            UserCommand::Markdown(text) => say!("[note] {}", sanitize::line(&text)),
+            UserCommand::Trust(None) => {
+                if self.trusted.is_empty() {
+                    say!("No trusted peers");
+                }
+                for peer in &self.trusted {
+                    say!("Trusted: {peer}");
+                }
+            }
+            UserCommand::Trust(Some(peer)) => {
+                self.trust(peer);
+                say!("Trusting blocklist updates from {peer}");
+            }
+            UserCommand::Untrust(peer) => {
+                if self.trusted.remove(&peer) {
+                    say!("No longer trusting {peer}");
+                } else {
+                    say!("{peer} was not trusted");
+                }
+            }
+            UserCommand::Block { peer, reason } => self.block(peer, reason),
+            UserCommand::BlockList => self.print_blocked(),
+            UserCommand::Unblock(peer) => self.unblock(peer),
+            UserCommand::Blocklist(command) => self.run_blocklist_command(command),
+            UserCommand::Filter(command) => self.run_filter_command(command),
+            UserCommand::Stats => say!("{}", self.stats()),
+            UserCommand::MemoryStats => say!("{}", self.memory()),
+            UserCommand::Doctor => self.doctor(),
+            UserCommand::DoctorPublish => self.doctor_publish(),
+            UserCommand::Kick { peer, reason } => self.moderate(ModAction::Kick, peer, reason),
+            UserCommand::RoomBan { peer, reason } => {
+                self.moderate(ModAction::RoomBan, peer, reason)
+            }
+            UserCommand::ModList => self.print_moderators(),
+            UserCommand::Quorum(quorum) => self.set_quorum(quorum),
+            UserCommand::Proposals => self.print_proposals(),
+            UserCommand::TopicAlias => self.print_topic_alias(),
+            UserCommand::TopicAliasRotate => self.rotate_topic_alias(clock::unix_time()),
+            UserCommand::Approve(id) => self.approve(&id),
+            UserCommand::InviteCreate { ttl, invitee, qr } => {
+                match self.sign_invite(ttl, invitee) {
+                    Ok(signed) if qr || self.invite_qr => self.print_invite_qr(&signed, ttl),
+                    Ok(signed) => say!(
+                        "[invite] valid for {}, join with: --join-with {}",
+                        clock::format_duration(ttl),
+                        invite::encode_token(&signed)
+                    ),
+                    Err(e) => say!("[invite] {e}"),
+                }
+            }
+            UserCommand::Whois(peer) => self.print_whois(peer),
+            UserCommand::Verify { target, confirm } => self.run_verify(target, confirm),
+            UserCommand::Unverify(target) => self.run_unverify(&target),
+            UserCommand::Link(number) => match self.links.iter().find(|(n, _)| *n == number) {
+                Some((_, link)) => say!("[link] {}", sanitize::clickable(link)),
+                None => say!("[link] no link #{number}"),
+            },
+            UserCommand::AuditTail(n) => self.print_audit(n),
+            UserCommand::History(n) => self.print_history(n),
+            UserCommand::Report { target, reason } => self.send_report(target, reason),
+            UserCommand::Reports => self.print_reports(),
+            UserCommand::Spam(target) => self.send_spam(target),
+            UserCommand::ShareKey { threshold, holders } => self.share_key(threshold, holders),
+            UserCommand::RecoverKey(path) => self.recover_key(path),
+            UserCommand::Bans(command) => self.run_bans_command(command),
+            UserCommand::Peers => self.print_peers(),
+            UserCommand::Net => self.print_net(),
+            UserCommand::Status(command) => self.run_status_command(command),
+            UserCommand::Profile(command) => self.run_profile_command(command),
+            UserCommand::Dnd(command) => self.run_dnd_command(command),
+            UserCommand::Contacts => self.print_contacts(),
+            UserCommand::Contact(command) => self.run_contact_command(command),
+            UserCommand::Alias(command) => self.run_alias_command(command),
+            UserCommand::Post { title, body } => self.post(&title, &body),
+            UserCommand::Board => self.print_board(),
+            UserCommand::Pin { post, pinned } => self.pin(&post, pinned),
+            UserCommand::Task(command) => self.run_task_command(command),
+            UserCommand::Wordle(command) => self.run_wordle_command(command),
+            UserCommand::Draw { x, y, ch } => self.draw(x, y, ch),
+            UserCommand::Canvas => {
+                let ansi = std::io::stdout().is_terminal();
+                say!("{}", self.canvas.render(ansi));
+            }
+            UserCommand::ExportTopology(path) => self.export_topology(path),
+            UserCommand::Save { id, path } => self.save_message(&id, path),
+            UserCommand::Attach(path) => self.attach(&path),
+            UserCommand::Transfers(command) => self.run_transfers_command(command),
+            UserCommand::Fetch { target, path } => match self.attachment_of(&target) {
+                Some(cid) => self.fetch(cid, path),
+                None => say!("[attach] no message with id {target} had an attachment"),
+            },
+        }
+    }
+
+    /// Attach the file at `path` to our next chat message, serving its content to peers who
+    /// fetch it.
+    pub fn attach(&mut self, path: &Path) {
+        match AttachmentRef::read(path) {
+            Ok(attachment) => {
+                self.content
+                    .insert(attachment.cid, path.to_path_buf(), attachment.size);
+                say!("[attach] {attachment} goes with your next message");
+                self.next_attachment = Some(attachment);
+            }
+            Err(e) => say!("[attach] {e}"),
+        }
+    }
+
+    // The content id `target` names, or that of the attachment of the message with id `target`.
+    fn attachment_of(&self, target: &str) -> Option<Cid> {
+        if let Ok(cid) = target.parse() {
+            return Some(cid);
+        }
+        let stored = self.history.iter().rev().find(|m| m.id == target)?;
+        Some(stored.message.attachment.as_ref()?.cid)
+    }
+
+    /// Fetch the attachment with id `cid` from the peer that sent it, and save it to `path`.
+    /// A fetch interrupted before, in this run or an earlier one, resumes where it stopped.
+    pub fn fetch(&mut self, cid: Cid, path: PathBuf) {
+        if let Some(source) = self.content.path(&cid).map(Path::to_path_buf) {
+            return self.copy_attachment(source, path);
+        }
+        let offered = self.offers.find(&cid).cloned().or_else(|| {
+            let manifest = &self.transfers.get(&cid)?.manifest;
+            Some((manifest.sender, manifest.attachment.clone()))
+        });
+        let Some((sender, attachment)) = offered else {
+            return say!("[attach] no message in the room had the attachment {cid}");
+        };
+        let transfer = self.transfers.start(attachment.clone(), sender, path);
+        let received = transfer.received();
+        let name = self.display_name(&sender);
+        if received > 0 {
+            say!(
+                "[attach] resuming {attachment} from {name}, {} already here",
+                attachment::format_size(received)
+            );
+        } else {
+            say!("[attach] fetching {attachment} from {name}");
+        }
+        self.request_chunks(cid);
+    }
+
+    /// Fetches of attachments in progress, not counting paused ones.
+    pub fn fetching(&self) -> usize {
+        self.transfers
+            .iter()
+            .filter(|(_, transfer)| transfer.state == transfer::State::Active)
+            .count()
+    }
+
+    /// Fetches of attachments in progress or paused.
+    pub fn transfers(&self) -> &Transfers {
+        &self.transfers
+    }
+
+    // Request the next chunks of a transfer, or finish it once every chunk is here.
+    fn request_chunks(&mut self, cid: Cid) {
+        let Some(transfer) = self.transfers.get_mut(&cid) else {
+            return;
+        };
+        if transfer.is_complete() {
+            if transfer.in_flight() == 0 {
+                self.finish_transfer(cid);
+            }
+            return;
+        }
+        let sender = transfer.manifest.sender;
+        for (offset, len) in transfer.next_chunks() {
+            let request = self
+                .swarm
+                .behaviour_mut()
+                .content
+                .send_request(&sender, Fetch { cid, offset, len });
+            self.fetches.insert(request, (cid, offset));
+        }
+    }
+
+    // A peer connected: resume the paused fetches from it.
+    fn resume_from(&mut self, peer: PeerId) {
+        for cid in self.transfers.parked_from(&peer) {
+            if let Some(transfer) = self.transfers.get_mut(&cid) {
+                transfer.state = transfer::State::Active;
+                say!("[attach] resuming {}", transfer.manifest.attachment);
+            }
+            self.request_chunks(cid);
+        }
+    }
+
+    // An attachment was offered again: resume a paused fetch of it from whoever offered it.
+    fn resume_offered(&mut self, sender: PeerId, attachment: &AttachmentRef) {
+        let Some(transfer) = self.transfers.get_mut(&attachment.cid) else {
+            return;
+        };
+        if transfer.state == transfer::State::Parked
+            && attachment.size == transfer.manifest.attachment.size
+        {
+            transfer.manifest.sender = sender;
+            transfer.state = transfer::State::Active;
+            say!("[attach] {attachment} was offered again, resuming");
+            self.request_chunks(attachment.cid);
+        }
+    }
+
+    // Pause a transfer, keeping what arrived, after a request for it failed.
+    fn park_transfer(&mut self, cid: Cid, reason: &str) {
+        let Some(transfer) = self.transfers.get_mut(&cid) else {
+            return;
+        };
+        if transfer.park() {
+            let attachment = &transfer.manifest.attachment;
+            say!(
+                "[attach] fetching {attachment} paused at {} of {}: {reason}. It resumes once \
+                 the sender is back or the file is offered again",
+                attachment::format_size(transfer.received()),
+                attachment::format_size(attachment.size)
+            );
+        }
+    }
+
+    // Check the complete file against its id and move it into place, on the disk thread after
+    // the chunks queued before. It is served to peers from there on.
+    fn finish_transfer(&mut self, cid: Cid) {
+        let Some(transfer) = self.transfers.remove(&cid) else {
+            return;
+        };
+        self.fetches.retain(|_, (fetched, _)| *fetched != cid);
+        let manifest = transfer.manifest;
+        let part = self.transfers.part_path(&cid);
+        let manifest_path = self.transfers.manifest_path(&cid);
+        self.content
+            .insert(cid, manifest.destination.clone(), manifest.attachment.size);
+        self.disk.submit(move || {
+            let finished = transfer::finish(&part, &manifest_path, &manifest);
+            match finished {
+                Ok(()) => say!(
+                    "[attach] saved {} bytes to {}",
+                    manifest.attachment.size,
+                    manifest.destination.display()
+                ),
+                Err(e) => say!("[attach] {e}"),
+            }
+        });
+    }
+
+    fn run_transfers_command(&mut self, command: TransfersCommand) {
+        let cid = match command {
+            TransfersCommand::List => return self.print_transfers(),
+            TransfersCommand::Discard(prefix) => match self.transfers.find(&prefix) {
+                Ok(cid) => cid,
+                Err(e) => return say!("[attach] {e}"),
+            },
+        };
+        let Some(transfer) = self.transfers.remove(&cid) else {
+            return;
+        };
+        self.fetches.retain(|_, (fetched, _)| *fetched != cid);
+        let part = self.transfers.part_path(&cid);
+        let manifest_path = self.transfers.manifest_path(&cid);
+        self.disk
+            .submit(move || transfer::discard(&part, &manifest_path));
+        say!(
+            "[attach] discarded the fetch of {}",
+            transfer.manifest.attachment
+        );
+    }
+
+    fn print_transfers(&self) {
+        if self.transfers.is_empty() {
+            return say!("[attach] no fetches in progress");
+        }
+        for (cid, transfer) in self.transfers.iter() {
+            let attachment = &transfer.manifest.attachment;
+            let state = match transfer.state {
+                transfer::State::Active => "fetching",
+                transfer::State::Parked => "paused",
+            };
+            say!(
+                "[attach] {cid} {} {} of {} {state} from {}, to {}",
+                attachment.filename,
+                attachment::format_size(transfer.received()),
+                attachment::format_size(attachment.size),
+                self.display_name(&transfer.manifest.sender),
+                transfer.manifest.destination.display()
+            );
+        }
+    }
+
+    fn content_event(&mut self, event: request_response::Event<Fetch, Content>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Request {
+                        request, channel, ..
+                    },
+            } => {
+                let content = match self.content.read(&request.cid, request.offset, request.len) {
+                    Some(Ok(data)) => Content::Found(data),
+                    Some(Err(e)) => {
+                        debug!("[attach] can't read {} for {peer}: {e}", request.cid);
+                        Content::Missing
+                    }
+                    None => Content::Missing,
+                };
+                let _ = self
+                    .swarm
+                    .behaviour_mut()
+                    .content
+                    .send_response(channel, content);
+            }
+            request_response::Event::Message {
+                peer,
+                message:
+                    request_response::Message::Response {
+                        request_id,
+                        response,
+                    },
+            } => {
+                let Some((cid, offset)) = self.fetches.remove(&request_id) else {
+                    return;
+                };
+                let Some(transfer) = self.transfers.get_mut(&cid) else {
+                    return;
+                };
+                let size = transfer.manifest.attachment.size;
+                let expected = CHUNK_BYTES.min(size.saturating_sub(offset));
+                let data = match response {
+                    Content::Found(data) if data.len() as u64 == expected => data,
+                    Content::Found(_) => {
+                        let reason = format!(
+                            "{} sent a chunk of the wrong size",
+                            self.display_name(&peer)
+                        );
+                        return self.park_transfer(cid, &reason);
+                    }
+                    Content::Missing => {
+                        let reason = format!("{} no longer has it", self.display_name(&peer));
+                        return self.park_transfer(cid, &reason);
+                    }
+                };
+                // The chunk is written before the manifest that lists it, on the same thread
+                transfer.arrived(offset, expected);
+                let manifest = transfer.manifest.clone();
+                let part = self.transfers.part_path(&cid);
+                let manifest_path = self.transfers.manifest_path(&cid);
+                self.disk.submit(move || {
+                    let written = transfer::write_chunk(&part, offset, &data)
+                        .and_then(|()| transfer::save_manifest(&manifest_path, &manifest));
+                    if let Err(e) = written {
+                        say!("[attach] can't write {}: {e}", part.display());
+                    }
+                });
+                self.request_chunks(cid);
+            }
+            request_response::Event::OutboundFailure {
+                peer,
+                request_id,
+                error,
+                ..
+            } => {
+                if matches!(error, request_response::OutboundFailure::Timeout) {
+                    self.liveness.timed_out(&peer);
+                }
+                if let Some((cid, _)) = self.fetches.remove(&request_id) {
+                    self.park_transfer(cid, &error.to_string());
+                }
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!("[attach] fetch from {peer} not answered: {error}")
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
+    // Copy content we already have to a new file on the disk thread, never over an existing
+    // file.
+    fn copy_attachment(&self, source: PathBuf, path: PathBuf) {
+        self.disk.submit(move || {
+            let copied = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .and_then(|mut file| io::copy(&mut fs::File::open(&source)?, &mut file));
+            match copied {
+                Ok(size) => say!("[attach] saved {size} bytes to {}", path.display()),
+                Err(e) => say!("[attach] can't write {}: {e}", path.display()),
+            }
+        });
+    }
+
+    fn save_message(&self, id: &str, path: PathBuf) {
+        let Some(stored) = self.history.iter().rev().find(|m| m.id == id) else {
+            say!("[save] no message with id {id} in the history");
+            return;
+        };
+        let contents = match &stored.binary {
+            Some(binary) => binary.to_vec(),
+            None => stored.message.body.as_bytes().to_vec(),
+        };
+        self.disk.submit(move || match fs::write(&path, &contents) {
+            Ok(()) => say!(
+                "[save] wrote {} bytes to {}",
+                contents.len(),
+                path.display()
+            ),
+            Err(e) => say!("[save] can't write {}: {e}", path.display()),
+        });
+    }
+
+    fn export_topology(&self, path: PathBuf) {
+        let topology = self.topology();
+        self.disk
+            .submit(move || match fs::write(&path, topology.to_dot()) {
+                Ok(()) => say!(
+                    "[topology] wrote {} peers to {}, render it with: dot -Tsvg {} -o topology.svg",
+                    topology.peers.len(),
+                    path.display(),
+                    path.display()
+                ),
+                Err(e) => say!("[topology] can't write {}: {e}", path.display()),
+            });
+    }
+
+    fn run_profile_command(&mut self, command: ProfileCommand) {
+        let (field, value) = match command {
+            ProfileCommand::Show(None) => {
+                return say!("[profile] {}: {}", self.nick, self.config.profile)
+            }
+            ProfileCommand::Show(Some(target)) => {
+                let peer = match self.resolve_peer(&target) {
+                    Ok(peer) => peer,
+                    Err(e) => return say!("[profile] {e}"),
+                };
+                return match self.profile(&peer) {
+                    Some(profile) => {
+                        say!("[profile] {}: {profile}", self.display_name(&peer))
+                    }
+                    None => say!("[profile] {} sent no profile", self.display_name(&peer)),
+                };
+            }
+            ProfileCommand::Set {
+                field: ProfileField::Avatar,
+                value,
+            } => match profile::avatar_hash(value.as_ref()) {
+                Ok(hash) => (ProfileField::Avatar, hash),
+                Err(e) => return say!("[profile] can't read avatar {value}: {e}"),
+            },
+            ProfileCommand::Set { field, value } => (field, value),
+            ProfileCommand::Clear(field) => (field, String::new()),
+        };
+        let mut updated = self.config.profile.clone();
+        if let Err(e) = updated.set(field, &value) {
+            return say!("[profile] {e}");
+        }
+        self.config.profile = updated;
+        self.save_config();
+        // Unlike on joining, an empty profile is sent too, so peers forget the cleared fields
+        let message = ControlMessage::Profile {
+            room: self.topic.hash().into_string(),
+            profile: self.config.profile.clone(),
+        };
+        match self.publish_control(&message) {
+            Ok(()) => say!("[profile] {}: {}", self.nick, self.config.profile),
+            Err(_) => say!(
+                "[profile] {}: {} (peers get it when they connect)",
+                self.nick,
+                self.config.profile
+            ),
+        }
+    }
+
+    fn run_status_command(&mut self, command: StatusCommand) {
+        match command {
+            StatusCommand::Show => {
+                match self.manual_away {
+                    Some(true) => say!("[status] away, until /status auto"),
+                    Some(false) => say!("[status] online, until /status auto"),
+                    None => say!("[status] {}", self.describe_auto_away()),
+                }
+                if let Some(dnd) = self.config.dnd {
+                    say!(
+                        "[status] do not disturb {}",
+                        dnd.describe(clock::unix_time())
+                    );
+                }
+                if let Some(status) = &self.status_line {
+                    say!("[status] status line: {status}");
+                }
+            }
+            StatusCommand::Away => {
+                self.manual_away = Some(true);
+                say!("[status] away, until /status auto");
+            }
+            StatusCommand::Online => {
+                self.manual_away = Some(false);
+                say!("[status] online, until /status auto");
+            }
+            StatusCommand::Auto => {
+                self.manual_away = None;
+                say!("[status] {}", self.describe_auto_away());
+            }
+            StatusCommand::Set(text) => {
+                if sanitize::line(&text).trim().chars().count() > presence::MAX_STATUS_CHARS {
+                    return say!(
+                        "[status] a status line is at most {} characters",
+                        presence::MAX_STATUS_CHARS
+                    );
+                }
+                let Some(status) = presence::clean_status_line(&text) else {
+                    return say!("[status] the status line is empty");
+                };
+                say!("[status] status line: {status}");
+                self.set_status_line(Some(status));
+            }
+            StatusCommand::Clear => {
+                say!("[status] status line cleared");
+                self.set_status_line(None);
+            }
+        }
+    }
+
+    // Change the status line and tell the room right away if that changed it.
+    fn set_status_line(&mut self, status: Option<String>) {
+        if self.status_line != status {
+            self.status_line = status;
+            self.send_heartbeat(clock::unix_time());
+        }
+    }
+
+    fn run_dnd_command(&mut self, command: DndCommand) {
+        let now = clock::unix_time();
+        let dnd = match command {
+            DndCommand::Show => {
+                return match self.config.dnd {
+                    Some(dnd) => say!("[dnd] on {}", dnd.describe(now)),
+                    None => say!("[dnd] off"),
+                }
+            }
+            DndCommand::On => Some(DoNotDisturb::indefinite()),
+            DndCommand::For(secs) => Some(DoNotDisturb::for_secs(secs, now)),
+            DndCommand::Off => None,
+        };
+        self.set_dnd(dnd, now);
+        match dnd {
+            Some(dnd) => say!("[dnd] on {}", dnd.describe(now)),
+            None => say!("[dnd] off"),
+        }
+    }
+
+    // Turn do not disturb on or off, save it and tell the room right away if that changed.
+    fn set_dnd(&mut self, dnd: Option<DoNotDisturb>, now: u64) {
+        let was_dnd = self.config.dnd.is_some();
+        self.config.dnd = dnd;
+        self.save_config();
+        if dnd.is_some() != was_dnd {
+            self.send_heartbeat(now);
+        }
+    }
+
+    fn run_contact_command(&mut self, command: ContactCommand) {
+        let target = match &command {
+            ContactCommand::Add { target, .. }
+            | ContactCommand::Remove(target)
+            | ContactCommand::Note { target, .. }
+            | ContactCommand::Favorite { target, .. } => target,
+        };
+        let peer = match self.resolve_peer(target) {
+            Ok(peer) => peer,
+            Err(e) => return say!("[contact] {e}"),
+        };
+        let adding = matches!(command, ContactCommand::Add { .. });
+        if !adding && self.config.contacts.get(&peer).is_none() {
+            return say!("[contact] {} is not a contact", self.display_name(&peer));
+        }
+        match command {
+            ContactCommand::Add { label, .. } => {
+                let label = label
+                    .or_else(|| self.nicks.get(&peer).cloned())
+                    .unwrap_or_else(|| collision::suffix(&peer));
+                match self.config.contacts.add(peer, &label) {
+                    Ok(contact) => say!("[contact] saved {peer} as {}", contact.label),
+                    Err(e) => return say!("[contact] {e}"),
+                }
+                self.remember_contacts();
+            }
+            ContactCommand::Remove(_) => {
+                if let Some(contact) = self.config.contacts.remove(&peer) {
+                    say!("[contact] forgot {}", contact.label);
+                }
+            }
+            ContactCommand::Note { notes, .. } => {
+                if let Err(e) = self.config.contacts.set_notes(&peer, &notes) {
+                    return say!("[contact] {e}");
+                }
+                say!("[contact] notes saved");
+            }
+            ContactCommand::Favorite { favorite, .. } => {
+                let contact = self.config.contacts.get_mut(&peer).expect("checked above");
+                contact.favorite = favorite;
+                let what = if favorite { "is" } else { "is no longer" };
+                say!("[contact] {} {what} a favorite", contact.label);
+            }
+        }
+        self.save_config();
+    }
+
+    fn run_alias_command(&mut self, command: AliasCommand) {
+        let (target, alias) = match command {
+            AliasCommand::List => return self.print_aliases(),
+            AliasCommand::Set { target, alias } => (target, Some(alias)),
+            AliasCommand::Remove(target) => (target, None),
+        };
+        let peer = match self.resolve_peer(&target) {
+            Ok(peer) => peer,
+            Err(e) => return say!("[alias] {e}"),
+        };
+        let Some(alias) = alias else {
+            return match self.config.aliases.remove(&peer) {
+                Some(alias) => {
+                    self.save_config();
+                    say!("[alias] {alias} is {} again", self.display_name(&peer))
+                }
+                None => say!("[alias] {} has no alias", self.display_name(&peer)),
+            };
+        };
+        let alias = sanitize::nick(&alias);
+        if peer == self.local_peer_id() {
+            return say!("[alias] that's you, use --nick to change your name");
+        }
+        if alias.is_empty() {
+            return say!("[alias] the alias is empty");
+        }
+        let mut aliases = self.config.aliases.iter();
+        if let Some((other, _)) =
+            aliases.find(|(other, a)| **other != peer && a.eq_ignore_ascii_case(&alias))
+        {
+            return say!("[alias] {alias} is already your alias for {other}");
+        }
+        if !self.config.aliases.contains_key(&peer) && self.config.aliases.len() >= MAX_KNOWN_NICKS
+        {
+            return say!("[alias] you already have {MAX_KNOWN_NICKS} aliases");
+        }
+        self.config.aliases.insert(peer, alias.clone());
+        self.save_config();
+        say!("[alias] {peer} is shown as {alias}");
+    }
+
+    fn print_aliases(&self) {
+        if self.config.aliases.is_empty() {
+            return say!("[alias] none yet, name a peer with /alias <peer|nick> <name>");
+        }
+        for (peer, alias) in &self.config.aliases {
+            let nick = self.nicks.get(peer).map_or("unknown", String::as_str);
+            say!("[alias] {alias}: {peer}, nick {nick}");
+        }
+    }
+
+    /// Publish a post on the room's board.
+    fn post(&mut self, title: &str, body: &str) {
+        if title.chars().count() > board::MAX_TITLE_CHARS
+            || body.chars().count() > board::MAX_POST_BODY_CHARS
+        {
+            return say!(
+                "[board] titles are at most {} characters and posts {}",
+                board::MAX_TITLE_CHARS,
+                board::MAX_POST_BODY_CHARS
+            );
+        }
+        let post = Post::new(
+            self.local_peer_id(),
+            &self.nick,
+            title,
+            body,
+            clock::unix_time(),
+        );
+        let signed = match SignedPost::sign(&self.keypair, &post) {
+            Ok(signed) => signed,
+            Err(e) => return say!("[board] failed to sign the post: {e}"),
+        };
+        if let Err(e) = self.publish_board(&BoardMessage::Post(signed.clone())) {
+            return say!("[board] failed to publish the post: {e}");
+        }
+        let _ = self.board.insert(&signed);
+        self.save_board();
+        say!("[board] posted {}", short_id(&post.id));
+    }
+
+    /// Pin or unpin a post, as a moderator of the room.
+    fn pin(&mut self, prefix: &str, pinned: bool) {
+        let room = self.topic.hash().into_string();
+        let local = self.local_peer_id();
+        if !self
+            .rooms
+            .moderators(&room, self.config.rooms.get(&room))
+            .contains(&local)
+        {
+            return say!("[board] only moderators of {room} can pin posts");
+        }
+        let id = match self.board.find(prefix) {
+            Ok(post) => post.id,
+            Err(e) => return say!("[board] {e}"),
+        };
+        let change = self
+            .board
+            .pin(&id, local, pinned)
+            .expect("the post was just found");
+        self.save_board();
+        if let Err(e) = self.publish_board(&BoardMessage::Pin(change)) {
+            say!("[board] failed to publish the change: {e}");
+        }
+        let post = self.board.get(&id).expect("the post was just found");
+        let action = if pinned { "pinned" } else { "unpinned" };
+        say!("[board] {action} {} \"{}\"", short_id(&post.id), post.title);
+    }
+
+    fn print_board(&self) {
+        if self.board.is_empty() {
+            return say!("[board] no posts yet, write one with /post <title> | <body>");
+        }
+        let now = clock::unix_time();
+        for post in self.board.sorted() {
+            let pin = if post.pinned { "📌 " } else { "" };
+            say!(
+                "[board] {pin}{} \"{}\" by {}, {} ago",
+                short_id(&post.id),
+                post.title,
+                self.post_author(post),
+                clock::format_duration(now.saturating_sub(post.posted_at))
+            );
+            say!("[board]   {}", post.body);
+        }
+    }
+
+    // The author of a post: the name we know the peer by, or the nick it posted with.
+    fn post_author(&self, post: &Post) -> String {
+        if self.nicks.contains_key(&post.author) || self.config.aliases.contains_key(&post.author) {
+            self.display_name(&post.author)
+        } else {
+            post.author_nick.clone()
+        }
+    }
+
+    /// Verify and merge a message from the board topic. Returns false if it was invalid.
+    fn handle_board(&mut self, data: &[u8]) -> bool {
+        let data = match &self.room_key {
+            Some(key) => match key.open(data) {
+                Ok(data) => Cow::Owned(data),
+                Err(e) => {
+                    warn!("[board] dropped a board message: {e}");
+                    return false;
+                }
+            },
+            None => Cow::Borrowed(data),
+        };
+        let verified = serde_json::from_slice::<SignedBoard>(&data)
+            .map_err(|e| e.to_string())
+            .and_then(|signed| signed.verify().map_err(|e| e.to_string()));
+        let (author, message) = match verified {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!("[board] dropped invalid board message: {e}");
+                return false;
+            }
+        };
+        let room = self.topic.hash().into_string();
+        let settings = self.config.rooms.get(&room);
+        let is_moderator = self.rooms.moderators(&room, settings).contains(&author);
+        let changed = match message {
+            BoardMessage::Post(signed) => {
+                if self.rooms.is_ignored(&room, &author, settings) {
+                    return true;
+                }
+                match self.board.insert(&signed) {
+                    Ok(Some(id)) => {
+                        let post = self.board.get(&id).expect("the post was just added");
+                        say!(
+                            "[board] {} posted {} \"{}\"",
+                            self.post_author(post),
+                            short_id(&post.id),
+                            post.title
+                        );
+                        true
+                    }
+                    Ok(None) => false,
+                    Err(e) => {
+                        warn!("[board] dropped a post from {author}: {e}");
+                        return false;
+                    }
+                }
+            }
+            BoardMessage::Pin(change) if is_moderator => self.board.apply_pin(&change),
+            BoardMessage::Pin(_) => {
+                debug!("[board] ignored a pin from {author}, who isn't a moderator");
+                false
+            }
+            // Posts from a sync carry their authors' signatures; their pins are only taken
+            // from moderators
+            BoardMessage::Sync { posts, pins } => {
+                let mut changed = false;
+                for signed in posts.iter().take(board::MAX_SYNC_POSTS) {
+                    changed |= self.board.insert(signed).is_ok_and(|new| new.is_some());
+                }
+                if is_moderator {
+                    for change in pins.iter().take(board::MAX_SYNC_POSTS) {
+                        changed |= self.board.apply_pin(change);
+                    }
+                }
+                changed
+            }
+        };
+        if changed {
+            self.save_board();
+        }
+        true
+    }
+
+    // Send our board to everyone when a newcomer subscribes to it. Like roster snapshots,
+    // only a few members answer on average, so a big room doesn't flood the newcomer.
+    fn send_board_sync(&mut self) {
+        if self.read_only || self.board.is_empty() {
+            return;
+        }
+        let room = self.topic.hash().into_string();
+        if !snapshot::should_answer(self.roster.online(&room).count()) {
+            return;
+        }
+        let (posts, pins) = self.board.sync();
+        if let Err(e) = self.publish_board(&BoardMessage::Sync { posts, pins }) {
+            debug!("[board] board not sent: {e}");
+        }
+    }
+
+    fn save_board(&self) {
+        let board = self.board.clone();
+        self.disk.submit(move || {
+            if let Err(e) = board.save() {
+                say!("[board] failed to save the board: {e}");
+            }
+        });
+    }
+
+    fn run_task_command(&mut self, command: TaskCommand) {
+        if command == TaskCommand::List {
+            return self.print_tasks();
+        }
+        if self.read_only {
+            return say!("[task] {}", ChatError::ReadOnlyMode);
+        }
+        let (now, local) = (clock::unix_time(), self.local_peer_id());
+        let (delta, summary) = match command {
+            TaskCommand::List => unreachable!("listed above"),
+            TaskCommand::Add(title) => {
+                if title.chars().count() > tasks::MAX_TASK_TITLE_CHARS {
+                    return say!(
+                        "[task] titles are at most {} characters",
+                        tasks::MAX_TASK_TITLE_CHARS
+                    );
+                }
+                let (id, delta) = self.tasks.add(&title, now);
+                (delta, format!("added {} \"{title}\"", short_id(&id)))
+            }
+            TaskCommand::Done { task, done } => {
+                let task = match self.tasks.find(&task) {
+                    Ok(task) => task,
+                    Err(e) => return say!("[task] {e}"),
+                };
+                let delta = self.tasks.set_done(&task.id, done, local, now);
+                let action = if done { "done" } else { "not done" };
+                let summary = format!("marked {} \"{}\" {action}", short_id(&task.id), task.title);
+                (delta.expect("the task was just found"), summary)
+            }
+            TaskCommand::Assign { task, assignee } => {
+                let task = match self.tasks.find(&task) {
+                    Ok(task) => task,
+                    Err(e) => return say!("[task] {e}"),
+                };
+                let delta = self.tasks.assign(&task.id, assignee.as_deref(), local, now);
+                let to = assignee.map_or("nobody".to_string(), |nick| sanitize::nick(&nick));
+                let summary = format!("assigned {} \"{}\" to {to}", short_id(&task.id), task.title);
+                (delta.expect("the task was just found"), summary)
+            }
+            TaskCommand::Remove(task) => {
+                let task = match self.tasks.find(&task) {
+                    Ok(task) => task,
+                    Err(e) => return say!("[task] {e}"),
+                };
+                let delta = self.tasks.remove(&task.id);
+                let summary = format!("removed {} \"{}\"", short_id(&task.id), task.title);
+                (delta.expect("the task was just found"), summary)
+            }
+        };
+        self.save_tasks();
+        if let Err(e) = self.publish_tasks(&delta) {
+            say!("[task] failed to publish the change: {e}");
+        }
+        say!("[task] {summary}");
+    }
+
+    fn print_tasks(&self) {
+        let tasks = self.tasks.tasks();
+        if tasks.is_empty() {
+            return say!("[task] no tasks yet, add one with /task add <title>");
+        }
+        for task in tasks {
+            let check = if task.done { "x" } else { " " };
+            let assignee = task
+                .assignee
+                .map_or(String::new(), |nick| format!(" ({nick})"));
+            say!(
+                "[task] [{check}] {} {}{assignee}",
+                short_id(&task.id),
+                task.title
+            );
+        }
+    }
+
+    /// Verify and merge a change to the task list. Returns false if it was invalid.
+    fn handle_tasks(&mut self, data: &[u8]) -> bool {
+        let data = match &self.room_key {
+            Some(key) => match key.open(data) {
+                Ok(data) => Cow::Owned(data),
+                Err(e) => {
+                    warn!("[task] dropped a task list change: {e}");
+                    return false;
+                }
+            },
+            None => Cow::Borrowed(data),
+        };
+        let verified = serde_json::from_slice::<SignedTasks>(&data)
+            .map_err(|e| e.to_string())
+            .and_then(|signed| signed.verify().map_err(|e| e.to_string()));
+        let (author, delta) = match verified {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!("[task] dropped invalid task list change: {e}");
+                return false;
+            }
+        };
+        let room = self.topic.hash().into_string();
+        if self
+            .rooms
+            .is_ignored(&room, &author, self.config.rooms.get(&room))
+        {
+            return true;
+        }
+        let before: HashSet<Uuid> = self.tasks.tasks().iter().map(|task| task.id).collect();
+        if !self.tasks.merge(&delta) {
+            return true;
+        }
+        self.save_tasks();
+        for task in self.tasks.tasks() {
+            if !before.contains(&task.id) {
+                say!(
+                    "[task] {} added {} \"{}\"",
+                    self.display_name(&author),
+                    short_id(&task.id),
+                    task.title
+                );
+            }
+        }
+        true
+    }
+
+    // Send our task list to everyone when a newcomer subscribes to it, answering like board
+    // syncs do.
+    fn send_tasks_sync(&mut self) {
+        if self.read_only || self.tasks.is_empty() {
+            return;
+        }
+        let room = self.topic.hash().into_string();
+        if !snapshot::should_answer(self.roster.online(&room).count()) {
+            return;
+        }
+        if let Err(e) = self.publish_tasks(&self.tasks.clone()) {
+            debug!("[task] task list not sent: {e}");
+        }
+    }
+
+    fn save_tasks(&self) {
+        let Some(path) = self.tasks_path.clone() else {
+            return;
+        };
+        let (room, tasks) = (self.topic.hash().into_string(), self.tasks.clone());
+        self.disk.submit(move || {
+            if let Err(e) = tasks::save(&path, &room, &tasks) {
+                say!("[task] failed to save the task list: {e}");
+            }
+        });
+    }
+
+    fn run_wordle_command(&mut self, command: WordleCommand) {
+        if command == WordleCommand::Show {
+            return self.print_wordle();
+        }
+        if self.read_only {
+            return say!("[wordle] {}", ChatError::ReadOnlyMode);
+        }
+        let local = self.local_peer_id();
+        let made = match &command {
+            WordleCommand::Show => unreachable!("shown above"),
+            WordleCommand::Start(word) => self.wordle.start(local, word),
+            WordleCommand::Guess(word) => self.wordle.guess(local, word),
+            WordleCommand::End => self.wordle.reveal(local),
+        };
+        match made {
+            Ok(wordle_move) => self.send_move(&wordle_move),
+            Err(e) => say!("[wordle] {e}"),
+        }
+    }
+
+    fn print_wordle(&self) {
+        let Some(game) = self.wordle.game() else {
+            return say!("[wordle] no game yet, host one with /wordle start <word>");
+        };
+        let state = match &game.word {
+            Some(word) => format!("over, the word was {}", word.to_uppercase()),
+            None => "on".to_string(),
+        };
+        say!(
+            "[wordle] game hosted by {} is {state}",
+            self.display_name(&game.host)
+        );
+        if game.guesses.is_empty() {
+            say!("[wordle]   no guesses yet");
+        }
+        for (player, guesses) in &game.guesses {
+            say!(
+                "[wordle]   {} ({}/{})",
+                self.display_name(player),
+                guesses.len(),
+                wordle::MAX_GUESSES
+            );
+            for guess in guesses {
+                say!("[wordle]     {}", wordle::render(guess));
+            }
+        }
+    }
+
+    // Publish a move of ours and show it.
+    fn send_move(&mut self, wordle_move: &WordleMove) {
+        if let Err(e) = self.publish_move(wordle_move) {
+            say!("[wordle] failed to publish the move: {e}");
+        }
+        self.announce_move(self.local_peer_id(), wordle_move);
+    }
+
+    /// Verify and add a move from the Wordle topic. Returns false if it was invalid.
+    fn handle_wordle(&mut self, data: &[u8]) -> bool {
+        let data = match &self.room_key {
+            Some(key) => match key.open(data) {
+                Ok(data) => Cow::Owned(data),
+                Err(e) => {
+                    warn!("[wordle] dropped a move: {e}");
+                    return false;
+                }
+            },
+            None => Cow::Borrowed(data),
+        };
+        let verified = serde_json::from_slice::<SignedMove>(&data)
+            .map_err(|e| e.to_string())
+            .and_then(|signed| signed.verify().map_err(|e| e.to_string()));
+        let (author, wordle_move) = match verified {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!("[wordle] dropped invalid move: {e}");
+                return false;
+            }
+        };
+        let room = self.topic.hash().into_string();
+        if self
+            .rooms
+            .is_ignored(&room, &author, self.config.rooms.get(&room))
+            || !self.wordle.insert(author, wordle_move.clone())
+        {
+            return true;
+        }
+        self.announce_move(author, &wordle_move);
+        // As the host, score new guesses at once, and end the game once the word is found
+        let local = self.local_peer_id();
+        for score in self.wordle.score_waiting(local) {
+            self.send_move(&score);
+        }
+        let solved = self.wordle.game().is_some_and(|game| {
+            game.host == local && !game.is_over() && game.solvers().count() > 0
+        });
+        if solved {
+            if let Ok(reveal) = self.wordle.reveal(local) {
+                self.send_move(&reveal);
+            }
+        }
+        true
+    }
+
+    // Tell the user about a move of the current game.
+    fn announce_move(&self, author: PeerId, wordle_move: &WordleMove) {
+        let current = self.wordle.game();
+        let Some(game) = current.filter(|game| game.id == wordle_move.game()) else {
+            return;
+        };
+        let name = self.display_name(&author);
+        match wordle_move {
+            WordleMove::Start { .. } => {
+                say!("[wordle] {name} started a game, guess with /wordle guess <word>");
+            }
+            WordleMove::Guess { n, .. } => {
+                debug!("[wordle] {name} made guess {n}");
+            }
+            WordleMove::Score { player, n, .. } => {
+                let guess = game
+                    .guesses
+                    .get(player)
+                    .and_then(|guesses| guesses.get(n.checked_sub(1)?));
+                if let Some(guess) = guess.filter(|guess| guess.marks.is_some()) {
+                    say!(
+                        "[wordle] {} {n}/{}: {}",
+                        self.display_name(player),
+                        wordle::MAX_GUESSES,
+                        wordle::render(guess)
+                    );
+                }
+            }
+            WordleMove::Reveal { .. } => {
+                let Some(word) = &game.word else {
+                    return;
+                };
+                let solvers: Vec<String> =
+                    game.solvers().map(|peer| self.display_name(peer)).collect();
+                let found = if solvers.is_empty() {
+                    "nobody found it".to_string()
+                } else {
+                    format!("found by {}", solvers.join(", "))
+                };
+                say!("[wordle] the word was {}, {found}", word.to_uppercase());
+                if game.wrong_scores > 0 {
+                    say!(
+                        "[wordle] {name} scored {} guesses wrong for that word",
+                        game.wrong_scores
+                    );
+                }
+            }
+        }
+    }
+
+    fn draw(&mut self, x: u8, y: u8, ch: char) {
+        if self.read_only {
+            return say!("[canvas] {}", ChatError::ReadOnlyMode);
+        }
+        let local = self.local_peer_id();
+        let delta = match self.canvas.draw(x, y, ch, local, clock::unix_time()) {
+            Ok(delta) => delta,
+            Err(e) => return say!("[canvas] {e}"),
+        };
+        if let Err(e) = self.publish_canvas(&delta) {
+            say!("[canvas] failed to publish the stroke: {e}");
+        }
+    }
+
+    /// Verify and merge a stroke from the canvas topic. Returns false if it was invalid.
+    fn handle_canvas(&mut self, data: &[u8]) -> bool {
+        let data = match &self.room_key {
+            Some(key) => match key.open(data) {
+                Ok(data) => Cow::Owned(data),
+                Err(e) => {
+                    warn!("[canvas] dropped a stroke: {e}");
+                    return false;
+                }
+            },
+            None => Cow::Borrowed(data),
+        };
+        let verified = serde_json::from_slice::<SignedDelta>(&data)
+            .map_err(|e| e.to_string())
+            .and_then(|signed| signed.verify().map_err(|e| e.to_string()))
+            // Only the author may sign its strokes, or someone could win cells in its name
+            .and_then(|(author, delta)| {
+                if delta.author == author.to_string() {
+                    Ok((author, delta))
+                } else {
+                    Err(format!("{author} signed a stroke of {}", delta.author))
+                }
+            });
+        let (author, delta) = match verified {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!("[canvas] dropped invalid stroke: {e}");
+                return false;
+            }
+        };
+        let room = self.topic.hash().into_string();
+        if self
+            .rooms
+            .is_ignored(&room, &author, self.config.rooms.get(&room))
+        {
+            return true;
+        }
+        match self.canvas.apply(&delta) {
+            Ok(changed) => {
+                if changed {
+                    debug!("[canvas] {} drew at ({}, {})", author, delta.x, delta.y);
+                }
+                true
+            }
+            Err(e) => {
+                warn!("[canvas] dropped invalid stroke from {author}: {e}");
+                false
+            }
+        }
+    }
+
+    /// Take in a report or a proof from the spam topic. Returns false if it was invalid.
+    fn handle_spam(&mut self, data: &[u8]) -> bool {
+        let data = match &self.room_key {
+            Some(key) => match key.open(data) {
+                Ok(data) => Cow::Owned(data),
+                Err(e) => {
+                    warn!("[spam] dropped a report: {e}");
+                    return false;
+                }
+            },
+            None => Cow::Borrowed(data),
+        };
+        let message = match serde_json::from_slice::<SpamMessage>(&data) {
+            Ok(message) => message,
+            Err(e) => {
+                warn!("[spam] dropped invalid report: {e}");
+                return false;
+            }
+        };
+        let id = match &message {
+            SpamMessage::Report(report) => report.message_id.clone(),
+            SpamMessage::Proof(proof) => proof.message_id.clone(),
+        };
+        let hidden = self.spam.is_suppressed(&id);
+        let added = match message {
+            SpamMessage::Report(report) => {
+                let reporter = report.reporter_peer_id;
+                self.spam
+                    .add_report(report)
+                    .map_err(|e| format!("dropped a report from {reporter}: {e}"))
+                    .map(usize::from)
+            }
+            SpamMessage::Proof(proof) => self
+                .spam
+                .add_proof(proof)
+                .map_err(|e| format!("dropped invalid proof: {e}")),
+        };
+        if let Err(e) = added {
+            warn!("[spam] {e}");
+            return false;
+        }
+        // Only the report or proof that takes the message over the threshold hides it
+        if !hidden && self.spam.is_suppressed(&id) {
+            self.hide_spam(&id);
+        }
+        true
+    }
+
+    /// Take in a share dealt to us, a request for the shares we hold or a share handed back.
+    /// Returns false if it was invalid.
+    fn handle_keyshare(&mut self, data: &[u8]) -> bool {
+        let verified = serde_json::from_slice::<SignedKeyShare>(data)
+            .map_err(|e| e.to_string())
+            .and_then(|signed| signed.verify().map_err(|e| e.to_string()));
+        let (signer, message) = match verified {
+            Ok(verified) => verified,
+            Err(e) => {
+                warn!("[keyshare] dropped invalid message: {e}");
+                return false;
+            }
+        };
+        let me = self.local_peer_id();
+        match message {
+            KeyShareMessage::Deal { holder, share } if holder == me => {
+                self.keep_share(signer, share)
+            }
+            KeyShareMessage::Request { timestamp } => {
+                self.answer_key_request(signer, timestamp);
+                true
+            }
+            KeyShareMessage::Answer { owner, share } if owner == me => {
+                self.take_key_share(signer, share)
+            }
+            // Shares dealt to or handed back to someone else
+            _ => true,
+        }
+    }
+
+    // Keep the share of its room key `owner` dealt us, if it is sealed to us.
+    fn keep_share(&mut self, owner: PeerId, share: HeldShare) -> bool {
+        if let Err(e) = share.sealed.open(&self.keypair) {
+            warn!("[keyshare] dropped a share from {owner}: {e}");
+            return false;
+        }
+        let (room, threshold) = (share.room.clone(), share.threshold);
+        let replaced = keyshare::keep(&mut self.config.key_shares, owner, share);
+        self.save_config();
+        let what = if replaced { "a new share" } else { "a share" };
+        say!(
+            "[keyshare] {} gave you {what} of the key of room {room}; {threshold} holders can give \
+             it back",
+            self.display_name(&owner)
+        );
+        true
+    }
+
+    // Hand `owner` back the shares of its keys we hold, opened with our key and sealed anew to
+    // its own. Requests are signed, and answered only while fresh, so a replayed one goes
+    // unanswered.
+    fn answer_key_request(&mut self, owner: PeerId, timestamp: u64) {
+        if clock::unix_time().abs_diff(timestamp) > keyshare::REQUEST_MAX_AGE {
+            return debug!("[keyshare] ignored a stale request from {owner}");
+        }
+        let held: Vec<HeldShare> = self
+            .config
+            .key_shares
+            .iter()
+            .filter(|kept| kept.owner == owner)
+            .map(|kept| kept.share.clone())
+            .collect();
+        for share in held {
+            let resealed = share
+                .sealed
+                .open(&self.keypair)
+                .and_then(|opened| SealedShare::seal(&opened, &owner));
+            let sealed = match resealed {
+                Ok(sealed) => sealed,
+                Err(e) => {
+                    warn!("[keyshare] can't hand {owner} back a share: {e}");
+                    continue;
+                }
+            };
+            let room = share.room.clone();
+            let answer = KeyShareMessage::Answer {
+                owner,
+                share: HeldShare { sealed, ..share },
+            };
+            if let Err(e) = self.publish_keyshare(&answer) {
+                return say!("[keyshare] failed to hand a share back: {e}");
+            }
+            say!(
+                "[keyshare] handed {} back their share of the key of room {room}",
+                self.display_name(&owner)
+            );
+        }
+    }
+
+    // Take a share `holder` handed back after `/recover-key`, saving the key once enough came.
+    fn take_key_share(&mut self, holder: PeerId, share: HeldShare) -> bool {
+        let Some((recovery, path)) = &mut self.recovery else {
+            debug!("[keyshare] {holder} handed back a share nobody asked for");
+            return true;
+        };
+        let (set, threshold) = (share.set, share.threshold);
+        let (room, secret) = match recovery.add(&self.keypair, share) {
+            Ok(Some(recovered)) => recovered,
+            Ok(None) => {
+                let count = recovery.count(&set);
+                say!(
+                    "[keyshare] {} handed back a share: {count} of the {threshold} it takes",
+                    self.display_name(&holder)
+                );
+                return true;
+            }
+            Err(e) => {
+                warn!("[keyshare] dropped a share from {holder}: {e}");
+                return false;
+            }
+        };
+        // A share that was tampered with rebuilds some other key, which names another room
+        let key = <[u8; SECRET_LEN]>::try_from(secret).map(RoomKey::from_secret);
+        let names_room = |key: &RoomKey| {
+            let topic = node::chat_topic(&self.topic_prefix, key.topic());
+            topic.hash().as_str() == room
+        };
+        let key = match key {
+            Ok(key) if names_room(&key) => key,
+            _ => {
+                say!("[keyshare] the shares handed back don't rebuild the key of {room}");
+                return true;
+            }
+        };
+        let path = path.clone();
+        self.recovery = None;
+        match key.save(&path) {
+            Ok(()) => say!(
+                "[keyshare] recovered the key of room {room} and saved it to {0}; join the room \
+                 with --room-key-file {0}",
+                path.display()
+            ),
+            Err(e) => say!("[keyshare] recovered the key of room {room}, but {e}"),
+        }
+        true
+    }
+
+    // Split the room's key into a share for each of `holders`, any `threshold` of whom can hand
+    // it back with `/recover-key`. Every share is sealed before any is sent, so a holder that
+    // can't take one doesn't leave the split short.
+    fn share_key(&mut self, threshold: usize, holders: Vec<String>) {
+        let Some(key) = &self.room_key else {
+            return say!("[keyshare] only passphrase rooms have a key to share");
+        };
+        let secret = *key.secret();
+        let mut peers = Vec::new();
+        for holder in &holders {
+            match self.resolve_peer(holder) {
+                Ok(peer) if peer == self.local_peer_id() => {
+                    return say!("[keyshare] a share of your own wouldn't help you recover the key")
+                }
+                Ok(peer) if peers.contains(&peer) => {
+                    return say!("[keyshare] {holder} is named twice")
+                }
+                Ok(peer) => peers.push(peer),
+                Err(e) => return say!("[keyshare] {e}"),
+            }
+        }
+        let shares = match keyshare::split(&secret, threshold, peers.len()) {
+            Ok(shares) => shares,
+            Err(e) => return say!("[keyshare] can't split the key: {e}"),
+        };
+        let (set, room) = (Uuid::new_v4(), self.topic.hash().into_string());
+        let mut deals = Vec::new();
+        for (holder, share) in peers.iter().zip(&shares) {
+            match SealedShare::seal(share, holder) {
+                Ok(sealed) => deals.push(KeyShareMessage::Deal {
+                    holder: *holder,
+                    share: HeldShare {
+                        set,
+                        room: room.clone(),
+                        threshold: threshold as u8,
+                        sealed,
+                    },
+                }),
+                Err(e) => return say!("[keyshare] can't seal a share: {e}"),
+            }
+        }
+        for deal in &deals {
+            if let Err(e) = self.publish_keyshare(deal) {
+                return say!("[keyshare] failed to send the shares: {e}");
+            }
+        }
+        say!(
+            "[keyshare] sent a share of the room key to each of {} peers; any {threshold} of them \
+             can give it back",
+            peers.len()
+        );
+    }
+
+    // Ask the holders of our shares for them back. The key is saved at `path`, or beside the
+    // config file, once enough came.
+    fn recover_key(&mut self, path: Option<PathBuf>) {
+        let Some(path) = path.or_else(|| self.config_path.as_deref().map(keyshare::path_beside))
+        else {
+            return say!("[keyshare] there is no config file to save the key beside, give a path");
+        };
+        let request = KeyShareMessage::Request {
+            timestamp: clock::unix_time(),
+        };
+        if let Err(e) = self.publish_keyshare(&request) {
+            return say!("[keyshare] failed to ask for the shares: {e}");
+        }
+        say!(
+            "[keyshare] asked the holders of your shares for them; the key is saved to {} once \
+             enough are back",
+            path.display()
+        );
+        self.recovery = Some((Recovery::default(), path));
+    }
+
+    // Hide a message enough members reported as spam, saying so if it was shown.
+    fn hide_spam(&mut self, id: &gossipsub::MessageId) {
+        let count = self.spam.count(id);
+        let Some(stored) = self
+            .history
+            .iter_mut()
+            .rev()
+            .find(|m| message_id_of(&m.id) == *id)
+        else {
+            return debug!("[spam] {id} reported by {count} members before it arrived");
+        };
+        if std::mem::take(&mut stored.shown) {
+            let nick = sanitize::nick(&stored.message.nick);
+            say!("[spam] hid {nick}'s message {id}: reported by {count} members");
+        }
+    }
+
+    // Send proofs of the spam we hid when a newcomer subscribes to the spam topic, so it hides
+    // those messages too should they reach it. Like roster snapshots, only a few members answer
+    // on average.
+    fn send_spam_proofs(&mut self) {
+        if self.read_only || self.spam.suppressed().next().is_none() {
+            return;
+        }
+        let room = self.topic.hash().into_string();
+        if !snapshot::should_answer(self.roster.online(&room).count()) {
+            return;
+        }
+        let proofs: Vec<_> = self
+            .spam
+            .suppressed()
+            .take(spam::MAX_PROOFS_SENT)
+            .filter_map(|id| self.spam.generate_proof(id))
+            .collect();
+        for proof in proofs {
+            if let Err(e) = self.publish_spam(&SpamMessage::Proof(proof)) {
+                return debug!("[spam] proofs not sent: {e}");
+            }
+        }
+    }
+
+    fn print_contacts(&self) {
+        if self.config.contacts.is_empty() {
+            return say!("[contacts] none yet, add one with /contact add <peer|nick>");
+        }
+        let now = clock::unix_time();
+        let room = self.topic.hash().into_string();
+        for contact in self.config.contacts.sorted() {
+            let peer = &contact.peer;
+            let star = if contact.favorite { "★ " } else { "" };
+            let nick = match self.nicks.get(peer) {
+                Some(nick) if !nick.eq_ignore_ascii_case(&contact.label) => format!("{nick}, "),
+                _ => String::new(),
+            };
+            let (status, seen_at) = match self.presence.status(&room, peer, now) {
+                Some((PresenceStatus::Online, at)) => ("online in this room", Some(at)),
+                Some((PresenceStatus::Stale, at)) => ("stale in this room", Some(at)),
+                Some((PresenceStatus::Offline, at)) => ("offline", Some(at)),
+                None => ("offline", None),
+            };
+            let last_seen = match seen_at.max(contact.last_seen) {
+                Some(at) => format!(
+                    "last seen {} ago",
+                    clock::format_duration(now.saturating_sub(at))
+                ),
+                None => "never seen".to_string(),
+            };
+            say!(
+                "[contacts] {star}{} ({nick}{peer}) {status}, {last_seen}",
+                contact.label
+            );
+            if !contact.notes.is_empty() {
+                say!("[contacts]   {}", contact.notes);
+            }
+        }
+    }
+
+    // Save when contacts were last heard from in this room, so `/contacts` still knows once
+    // they are gone. Returns whether that changed anything.
+    fn remember_contacts(&mut self) -> bool {
+        let now = clock::unix_time();
+        let room = self.topic.hash().into_string();
+        let seen: Vec<(PeerId, u64)> = self
+            .config
+            .contacts
+            .iter()
+            .filter_map(|contact| {
+                let (_, seen_at) = self.presence.status(&room, &contact.peer, now)?;
+                Some((contact.peer, seen_at))
+            })
+            .collect();
+        let mut changed = false;
+        for (peer, seen_at) in seen {
+            changed |= self.config.contacts.seen(&peer, seen_at);
+        }
+        changed
+    }
+
+    fn describe_auto_away(&self) -> String {
+        match self.away_after {
+            Some(after) => format!(
+                "online, away after {} without input",
+                clock::format_duration(after.as_secs())
+            ),
+            None => "online, auto-away is off".to_string(),
+        }
+    }
+
+    fn run_blocklist_command(&mut self, command: BlocklistCommand) {
+        match command {
+            BlocklistCommand::Show => self.print_updates(),
+            BlocklistCommand::Apply(id) => match self.blocklist.apply(id, clock::unix_time()) {
+                Ok(change) => {
+                    self.enforce(change);
+                    self.audit_update(self.local_peer_id(), id);
+                    say!("[blocklist] applied #{id}");
+                }
+                Err(e) => say!("[blocklist] {e}"),
+            },
+            BlocklistCommand::Revert(id) => match self.blocklist.revert(id) {
+                Ok(change) => {
+                    self.enforce(change);
+                    say!("[blocklist] reverted #{id}");
+                }
+                Err(e) => say!("[blocklist] {e}"),
+            },
+            BlocklistCommand::Add { peer, reason } => {
+                self.block(peer, reason.clone());
+                self.publish_blocklist_update(BlockAction::Add, peer, reason);
+            }
+            BlocklistCommand::Remove(peer) => {
+                self.unblock(peer);
+                self.publish_blocklist_update(BlockAction::Remove, peer, String::new());
+            }
+        }
+    }
+
+    fn run_filter_command(&mut self, command: FilterCommand) {
+        let topic = self.topic.hash().into_string();
+        match command {
+            FilterCommand::Show => {
+                match self.filter() {
+                    Some(filter) => say!("[filter] {topic}: {filter}"),
+                    None => say!("[filter] no filter on {topic}"),
+                }
+                say!("[{} messages filtered]", self.filtered_count());
+                return;
+            }
+            FilterCommand::Set(filter) => {
+                say!("[filter] {topic}: {filter}");
+                self.config.filters.insert(topic.clone(), filter);
+            }
+            FilterCommand::Clear => {
+                if self.config.filters.remove(&topic).is_none() {
+                    say!("[filter] no filter on {topic}");
+                    return;
+                }
+                say!("[filter] cleared for {topic}");
+            }
+        }
+        // The counter describes the filter in use, so start over whenever it changes
+        self.filtered.remove(&topic);
+        self.save_config();
+    }
+
+    /// Print the status line for messages hidden since the last time it was shown.
+    fn report_filtered(&mut self, topic: &str) {
+        if let Some(count) = self.filtered.get_mut(topic) {
+            if count.hidden > count.reported {
+                say!("[{} messages filtered]", count.hidden);
+                count.reported = count.hidden;
+            }
+        }
+    }
+
+    /// Store a received message, dropping the oldest once [`MAX_HISTORY`] is reached.
+    fn remember(&mut self, message: StoredMessage) {
+        log_transcript(message.source.as_ref(), &message.topic, &message.message);
+        if self.history.len() >= MAX_HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(message);
+    }
+
+    fn save_config(&self) {
+        let Some(path) = self.config_path.clone() else {
+            return;
+        };
+        let config = self.config.clone();
+        self.disk.submit(move || {
+            if let Err(e) = config.save(&path) {
+                say!("Failed to save config to {}: {e}", path.display());
+            }
+        });
+    }
+
+    fn audit(&self, actor: PeerId, event: AuditEvent) {
+        let audit = self.audit.clone();
+        self.disk.submit(move || {
+            if let Err(e) = audit.record(actor, event) {
+                say!("[audit] {e}");
+            }
+        });
+    }
+
+    // Record that the received blocklist update `id` was applied.
+    fn audit_update(&self, actor: PeerId, id: u64) {
+        if let Some(received) = self.blocklist.updates().find(|u| u.id == id) {
+            let event = AuditEvent::blocklist_applied(received.author, id, &received.update);
+            self.audit(actor, event);
+        }
+    }
+
+    // Read on the disk thread, after the entries still being written.
+    fn print_audit(&self, n: usize) {
+        let audit = self.audit.clone();
+        self.disk.submit(move || match audit.tail(n) {
+            Ok(entries) if entries.is_empty() => say!("[audit] no entries"),
+            Ok(entries) => {
+                for entry in entries {
+                    say!("[audit] {entry}");
+                }
+            }
+            Err(e) => say!("[audit] {e}"),
+        });
+    }
+
+    /// Print the last `n` messages that passed the display filter, oldest first.
+    fn print_history(&self, n: usize) {
+        let shown: Vec<_> = self.history.iter().filter(|m| m.shown).collect();
+        if shown.is_empty() {
+            say!("[history] no messages yet");
+            return;
+        }
+        for stored in &shown[shown.len().saturating_sub(n)..] {
+            let (body, _) = sanitize::body(&stored.message.body);
+            let unsigned = stored.source.map_or(" (unsigned)", |_| "");
+            say!(
+                "[history] {}{unsigned}: {body} (id {})",
+                sanitize::nick(&stored.message.nick),
+                stored.id
+            );
+        }
+    }
+
+    fn block(&mut self, peer: PeerId, reason: String) {
+        match self
+            .blocklist
+            .block(peer, reason, BlockOrigin::Manual, clock::unix_time())
+        {
+            Ok(_) => {
+                self.enforce(Change::Blocked(peer));
+                say!("Blocked {peer}");
+            }
+            Err(_) => say!("Blocklist is full, unblock someone first"),
+        }
+    }
+
+    fn unblock(&mut self, peer: PeerId) {
+        let blocked = self.blocklist.unblock(&peer).is_some();
+        let banned = self.bans.unban(&peer).is_some();
+        // Room bans are lifted in every room, kicks only in the current one
+        let removed = self.config.banlist.remove(&peer, |_| true);
+        let mut rooms: Vec<String> = removed
+            .iter()
+            .filter_map(|record| match &record.scope {
+                BanScope::Room(room) => Some(room.clone()),
+                BanScope::Global => None,
+            })
+            .collect();
+        rooms.push(self.topic.hash().into_string());
+        let mut readmitted = false;
+        let now = clock::unix_time();
+        for room in rooms {
+            let mut settings = self.config.rooms.get(&room).cloned().unwrap_or_default();
+            if self.rooms.readmit(&room, &peer, &mut settings) {
+                settings.lift(peer, now);
+                self.config.rooms.insert(room, settings);
+                readmitted = true;
+            }
+        }
+        if !removed.is_empty() || readmitted {
+            self.save_config();
+        }
+        if blocked || banned || readmitted {
+            self.enforce(Change::Unblocked(peer));
+            say!("Unblocked {peer}");
+        } else {
+            say!("{peer} is not blocked");
+        }
+    }
+
+    /// Disconnect a peer that crossed an automatic ban threshold.
+    fn start_ban(&mut self, ban: TempBan) {
+        self.enforce(Change::Blocked(ban.peer));
+        self.config.banlist.insert(BanRecord::auto(&ban));
+        self.save_config();
+        self.audit(
+            self.local_peer_id(),
+            AuditEvent::AutoBan {
+                peer: ban.peer,
+                violation: ban.violation,
+                offense: ban.offense,
+                expires_at: ban.expires_at,
+            },
+        );
+        warn!(
+            "[ban] {} {}: banned for {} (offense #{})",
+            ban.peer,
+            ban.violation,
+            clock::format_duration(ban.expires_at - ban.started_at),
+            ban.offense
+        );
+    }
+
+    /// Lift automatic bans that have run out, send a heartbeat when one is due, report changes
+    /// to the rosters and disconnect the least valuable peers when there are too many. Call
+    /// this periodically.
+    pub fn tick(&mut self) {
+        self.retry_publishes(None);
+        // Authenticators waiting on something other than the exchange, or out of time
+        for peer in self.pending_auth.pending() {
+            self.poll_auth(peer);
+        }
+        self.poll_answers();
+        self.send_receipts();
+        let connected = self.swarm.connected_peers().count();
+        if self.stall.check(Instant::now(), connected) {
+            self.publish_stalled();
+        }
+        let now = clock::unix_time();
+        self.outbound_mark = self.counters.bytes_sent;
+        for run in self.floods.finish(now) {
+            self.report_run(run);
+        }
+        let expired = self.fragments.expire(now);
+        if expired > 0 {
+            debug!("[fragment] dropped {expired} incomplete messages");
+        }
+        for usage in self.memory().structures {
+            self.evictions.check(&usage, now);
+        }
+        self.check_empty_room();
+        if self.rotation.is_some()
+            && self
+                .rotation_announced
+                .is_none_or(|announced| now >= announced + ROTATION_INTERVAL)
+        {
+            self.announce_rotation(now);
+        }
+        if !self.idle_away
+            && self
+                .away_after
+                .is_some_and(|after| self.last_input.elapsed() >= after)
+        {
+            let was_away = self.is_away();
+            self.idle_away = true;
+            self.announce_away(was_away);
+        }
+        if self.config.dnd.is_some_and(|dnd| dnd.is_over(now)) {
+            self.set_dnd(None, now);
+            say!("[dnd] do not disturb is over");
+        }
+        // What came in a burst before do not disturb started isn't summed up after it
+        let dnd = self.is_dnd();
+        if let Some(notifier) = &mut self.notifier {
+            match dnd {
+                true => notifier.clear(),
+                false => notifier.flush(Instant::now()),
+            }
+        }
+        if self.next_heartbeat.is_none_or(|due| now >= due) {
+            self.send_heartbeat(now);
+        }
+        if self.topic_alias_due(now) {
+            self.rotate_topic_alias(now);
+        }
+        self.update_roster(now);
+        if now >= self.next_ping_score {
+            self.next_ping_score = now + 60;
+            self.score_pings();
+        }
+        self.trim_connections();
+        self.close_dead_connections();
+        if self.relay_retry.is_some_and(|retry| now >= retry) {
+            if let Err(e) = self.listen_on_relay() {
+                say!("[relay] can't listen on the relay: {e}, retrying in {RELAY_RETRY}s");
+                self.relay_retry = Some(now + RELAY_RETRY);
+            }
+        }
+        self.listen_again(now);
+        self.expire_bans(now);
+        self.expire_proposals();
+        if let Some(result) = self.bootstrap.poll_lookup() {
+            self.handle_bootstrap_lookup(result);
+        }
+        self.bootstrap.start_lookup(now);
+        self.report_health();
+    }
+
+    // Tell the HTTP server how the node is doing, for `GET /health`.
+    fn report_health(&self) {
+        if let Some(server) = &self.hook_server {
+            server.report_health(self.health());
+        }
+    }
+
+    // Listen again on the addresses whose listener closed a while ago.
+    fn listen_again(&mut self, now: u64) {
+        let (due, waiting) = mem::take(&mut self.relisten)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(_, at)| now >= *at);
+        self.relisten = waiting;
+        for (address, _) in due {
+            match self.listen_on(address.clone()) {
+                Ok(_) => say!("[listen] listening on {address} again"),
+                Err(e) => {
+                    say!("[listen] can't listen on {address}: {e}, retrying in {LISTEN_RETRY}s");
+                    self.relisten.push((address, now + LISTEN_RETRY));
+                }
+            }
+        }
+    }
+
+    // Apply the roster changes since the last tick. Joins and leaves are printed as one summary
+    // per room once its batch window is over, or one line each with `--verbose-presence`.
+    fn update_roster(&mut self, now: u64) {
+        let events = self.roster.update(&self.presence, now);
+        if !events.is_empty() {
+            self.refresh_collisions();
+            self.roster_listeners.retain(|listener| {
+                events
+                    .iter()
+                    .all(|event| listener.send(event.clone()).is_ok())
+            });
+            for event in &events {
+                self.emit(event.clone().into());
+            }
+            self.announce_on_irc(&events);
+        }
+        // A contact leaving is when its last sighting is worth saving
+        let contact_left = events.iter().any(|event| match event {
+            RosterEvent::Left { peer, .. } => self.config.contacts.get(peer).is_some(),
+            _ => false,
+        });
+        if contact_left && self.remember_contacts() {
+            self.save_config();
+        }
+        for event in &events {
+            if self.verbose_presence {
+                self.print_roster_event(event);
+            } else {
+                self.membership.push(event, now);
+            }
+        }
+        for (room, batch) in self.membership.due(now) {
+            let names = |peers: &[PeerId]| -> Vec<String> {
+                peers
+                    .iter()
+                    .map(|peer| self.roster_name(&room, peer))
+                    .collect()
+            };
+            let line = membership::summary(
+                &sanitize::line(&room),
+                &names(&batch.joined),
+                &names(&batch.left),
+                self.roster.online(&room).count(),
+            );
+            say!("[roster] {line}");
+        }
+    }
+
+    // Tell the IRC channel who joined or left the room, if the bridge announces them.
+    fn announce_on_irc(&self, events: &[RosterEvent]) {
+        let Some(bridge) = &self.irc else {
+            return;
+        };
+        if !bridge.settings().announce {
+            return;
+        }
+        let room = self.topic.hash().into_string();
+        for event in events.iter().filter(|event| event.room() == room) {
+            let (peer, what) = match event {
+                RosterEvent::Joined { peer, .. } => (peer, "joined"),
+                RosterEvent::Left { peer, .. } => (peer, "left"),
+                _ => continue,
+            };
+            let nick = self.nicks.get(peer).map_or("", String::as_str);
+            let text = format!("{} {what} the room", self.irc_name(peer, nick));
+            if let Err(e) = bridge.announce(&text) {
+                debug!("[irc] announcement not sent: {e}");
+            }
+        }
+    }
+
+    // The name a peer goes by on IRC: its nick, told apart from others like it, and never our
+    // alias for it, which stays private.
+    fn irc_name(&self, peer: &PeerId, nick: &str) -> String {
+        match nick {
+            "" => {
+                let id = peer.to_base58();
+                id[id.len() - 6..].to_string()
+            }
+            nick if self.nick_clashes(peer, nick) => collision::disambiguate(nick, peer),
+            nick => nick.to_string(),
+        }
+    }
+
+    fn print_roster_event(&self, event: &RosterEvent) {
+        let room = event.room();
+        let (peer, what) = match event {
+            RosterEvent::Joined { peer, .. } => (peer, "joined"),
+            RosterEvent::Left { peer, .. } => (peer, "left"),
+            RosterEvent::Away { peer, .. } => (peer, "is away in"),
+            RosterEvent::Back { peer, .. } => (peer, "is back in"),
+        };
+        say!(
+            "[roster] {} {what} {} — {} online",
+            self.display_name(peer),
+            sanitize::line(room),
+            self.roster.online(room).count()
+        );
+    }
+
+    // A peer's name in a roster summary, marked when its user is away or doesn't want to be
+    // disturbed.
+    fn roster_name(&self, room: &str, peer: &PeerId) -> String {
+        let name = self.display_name(peer);
+        if self.presence.is_dnd(room, peer) {
+            format!("{name} (dnd)")
+        } else if self.roster.is_away(room, peer) {
+            format!("{name} (away)")
+        } else {
+            name
+        }
+    }
+
+    // Find the nicks several peers online in the room use, with a notice for each new clash.
+    fn refresh_collisions(&mut self) {
+        let room = self.topic.hash().into_string();
+        let online = self
+            .roster
+            .online(&room)
+            .filter_map(|peer| Some((*peer, self.nicks.get(peer)?.as_str())));
+        for clash in self.collisions.update(online) {
+            let names: Vec<String> = clash
+                .peers
+                .iter()
+                .map(|peer| self.display_name(peer))
+                .collect();
+            say!(
+                "[nick] {} peers online use the nick '{}', shown as {}",
+                clash.peers.len(),
+                clash.nick,
+                names.join(", ")
+            );
+        }
+    }
+
+    // Queue a receipt for a chat message we accepted. Only a few of the peers in the room
+    // confirm each message, and a read-only node never does.
+    fn confirm(&mut self, id: &gossipsub::MessageId) {
+        if self.read_only || self.receipts.len() >= stall::MAX_RECEIPT_IDS {
+            return;
+        }
+        let topic = self.wire_topic.hash();
+        let subscribers = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic))
+            .count();
+        if stall::should_confirm(subscribers) {
+            self.receipts.push(id.to_string());
+        }
+    }
+
+    // Confirm the chat messages accepted since the last tick to their authors.
+    fn send_receipts(&mut self) {
+        if self.receipts.is_empty() {
+            return;
+        }
+        let message = ControlMessage::Receipt {
+            room: self.topic.hash().into_string(),
+            ids: mem::take(&mut self.receipts),
+        };
+        if let Err(e) = self.publish_control(&message) {
+            debug!("[receipt] receipt not sent: {e}");
+        }
+    }
+
+    /// Why published messages may not be reaching anyone: how many peers are connected,
+    /// subscribed to the chat topic and in its mesh, and how our recent messages fared.
+    pub fn diagnose_publish(&self) -> PublishDiagnosis {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let (room, topic) = (self.topic.hash(), self.wire_topic.hash());
+        let now = clock::unix_time();
+        // Mesh peers that stopped answering pings, or whose heartbeats stopped
+        let stale = gossipsub
+            .mesh_peers(&topic)
+            .filter(|peer| {
+                self.pings.is_failing(peer)
+                    || self
+                        .presence
+                        .status(room.as_str(), peer, now)
+                        .is_some_and(|(status, _)| status != PresenceStatus::Online)
+            })
+            .count();
+        PublishDiagnosis {
+            connected: self.swarm.connected_peers().count(),
+            subscribed: gossipsub
+                .all_peers()
+                .filter(|(peer, topics)| topics.contains(&&topic) && self.swarm.is_connected(peer))
+                .count(),
+            mesh: gossipsub.mesh_peers(&topic).count(),
+            stale,
+            unconfirmed: self.stall.unconfirmed(),
+            last_receipt: self.stall.last_receipt().map(|at| at.elapsed()),
+        }
+    }
+
+    /// Our chat messages awaiting a receipt, and how many in a row never got one.
+    pub fn stall(&self) -> &StallWatch {
+        &self.stall
+    }
+
+    // Several messages in a row went unconfirmed with peers connected: say why once, rather
+    // than failing silently, and rejoin the mesh.
+    fn publish_stalled(&mut self) {
+        if let Some(problem) = self.diagnose_publish().problem() {
+            say!("messages may not be reaching anyone: {problem} — reconnecting");
+            self.regraft();
+        }
+    }
+
+    // `/doctor`: run the environment checks on a thread of their own, as the probes wait for
+    // answers, and print the report once done. The clock is compared with peers' when enough
+    // of their messages were seen.
+    fn doctor(&mut self) {
+        let settings = doctor::Settings {
+            config_dir: self.config_path.as_deref().map(doctor::config_dir),
+            relay: self.relay_server.clone(),
+            mdns: self.swarm.behaviour().mdns.is_enabled(),
+            peer_offset: self.clock_samples.offset(),
+            ntp_server: Some(doctor::NTP_SERVER.to_string()),
+        };
+        say!("[doctor] checking...");
+        let started = thread::Builder::new()
+            .name("doctor".to_string())
+            .spawn(move || say!("{}", doctor::run(&settings)));
+        if let Err(e) = started {
+            say!("[doctor] can't run the checks: {e}");
+        }
+    }
+
+    // `/doctor publish`: print the diagnosis, and rejoin the mesh when something is wrong.
+    fn doctor_publish(&mut self) {
+        let diagnosis = self.diagnose_publish();
+        say!("{diagnosis}");
+        if diagnosis.problem().is_some() && diagnosis.connected > 0 {
+            self.regraft();
+            say!("[doctor] rejoined the mesh, run /doctor publish again in a few seconds");
+        }
+    }
+
+    // Leave and rejoin the chat topic, so Gossipsub grafts a fresh mesh from the peers
+    // subscribed to it instead of keeping one that stopped delivering.
+    fn regraft(&mut self) {
+        let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+        if let Err(e) = gossipsub.unsubscribe(&self.wire_topic) {
+            debug!("[doctor] can't leave the mesh: {e}");
+        }
+        if let Err(e) = gossipsub.subscribe(&self.wire_topic) {
+            say!("[doctor] can't rejoin the mesh: {e}");
+        }
+    }
+
+    // `/topic-alias`: show what the room's chat messages travel under.
+    fn print_topic_alias(&self) {
+        let room = self.topic.hash().into_string();
+        match self.config.topic_aliases.get(&room) {
+            Some(alias) => say!(
+                "[alias] {room} travels under {}, picked {} s ago",
+                self.wire_topic,
+                clock::unix_time().saturating_sub(alias.issued_at)
+            ),
+            None => say!(
+                "[alias] {room} travels under its own name; a moderator can give it an alias with \
+                 /topic-alias rotate"
+            ),
+        }
+    }
+
+    // Whether we are a moderator of the room and its alias is old enough to rotate.
+    fn topic_alias_due(&self, now: u64) -> bool {
+        let room = self.topic.hash();
+        !self.read_only
+            && self
+                .config
+                .topic_aliases
+                .get(room.as_str())
+                .is_some_and(|alias| now >= alias.issued_at + topic_alias::ROTATION_INTERVAL)
+            && self.is_moderator(room.as_str(), &self.local_peer_id())
+    }
+
+    // `/topic-alias rotate`, and each rotation once one is due: pick a fresh alias for the
+    // room, tell the room and move to it.
+    fn rotate_topic_alias(&mut self, now: u64) {
+        let room = self.topic.hash().into_string();
+        if !self.is_moderator(&room, &self.local_peer_id()) {
+            return say!("[alias] only moderators of {room} can give it an alias");
+        }
+        // A second rotation within a second still replaces the first
+        let known = self.config.topic_aliases.get(&room);
+        let now = known.map_or(now, |known| now.max(known.issued_at + 1));
+        let alias = TopicAlias::generate(&room, now);
+        if let Err(e) = self.publish_control(&ControlMessage::TopicAlias(alias.clone())) {
+            return say!("[alias] can't announce the alias: {e}");
+        }
+        self.config.topic_aliases.learn(alias);
+        self.save_config();
+        self.follow_topic_alias();
+    }
+
+    // Tell a newcomer which alias the room's chat messages travel under, if there is one and
+    // we are one of the moderators who can.
+    fn announce_topic_alias(&mut self) {
+        let room = self.topic.hash().into_string();
+        let Some(alias) = self.config.topic_aliases.get(&room).cloned() else {
+            return;
+        };
+        if !self.is_moderator(&room, &self.local_peer_id()) {
+            return;
+        }
+        if let Err(e) = self.publish_control(&ControlMessage::TopicAlias(alias)) {
+            debug!("[alias] alias not announced: {e}");
+        }
+    }
+
+    // An alias for the room from `author`. Returns false if it was invalid.
+    fn receive_topic_alias(&mut self, author: PeerId, alias: TopicAlias) -> bool {
+        let room = self.topic.hash().into_string();
+        // Aliases of other rooms are none of our business
+        if alias.room != room {
+            return true;
+        }
+        if !self.is_moderator(&room, &author) {
+            say!("[alias] ignored an alias for {room} from {author}, who is not a moderator of it");
+            return true;
+        }
+        if let Err(e) = alias.validate(clock::unix_time()) {
+            warn!("[alias] dropped an alias from {author}: {e}");
+            return false;
+        }
+        if self.config.topic_aliases.learn(alias) {
+            self.save_config();
+            self.follow_topic_alias();
+        }
+        true
+    }
+
+    // Move the room's chat messages to the topic its alias names, if that changed.
+    fn follow_topic_alias(&mut self) {
+        let room = self.topic.hash();
+        let topic = self.config.topic_aliases.resolve(room.as_str());
+        if topic == self.wire_topic.hash() {
+            return;
+        }
+        let topic = gossipsub::IdentTopic::new(topic.into_string());
+        let gossipsub = &mut self.swarm.behaviour_mut().gossipsub;
+        if let Err(e) = gossipsub.subscribe(&topic) {
+            return say!("[alias] can't move to the room's new alias: {e}");
+        }
+        if let Err(e) = gossipsub.unsubscribe(&self.wire_topic) {
+            debug!("[alias] can't leave {}: {e}", self.wire_topic);
+        }
+        self.wire_topic = topic;
+        say!("[alias] {room} now travels under {}", self.wire_topic);
+    }
+
+    // Tell the room we are still here, unless heartbeats are off, and schedule the next one.
+    fn send_heartbeat(&mut self, now: u64) {
+        if self.presence_interval == 0 || self.read_only {
+            return;
+        }
+        let message = ControlMessage::Presence {
+            room: self.topic.hash().into_string(),
+            interval: self.presence_interval,
+            away: self.is_away(),
+            dnd: self.is_dnd(),
+            status: self.status_line.clone(),
+        };
+        // Nobody to tell is common right after startup; the next heartbeat will try again
+        if let Err(e) = self.publish_control(&message) {
+            debug!("[presence] heartbeat not sent: {e}");
+        }
+        self.next_heartbeat = Some(presence::next_heartbeat(now, self.presence_interval));
+    }
+
+    // Apply a minute of ping score adjustments. With scoring on, they become the peers'
+    // application scores, so Gossipsub prunes slow peers from the mesh before fast ones.
+    fn score_pings(&mut self) {
+        for peer in self.pings.minute() {
+            let adjustment = self.pings.adjustment(&peer);
+            self.swarm
+                .behaviour_mut()
+                .gossipsub
+                .set_application_score(&peer, adjustment);
+        }
+    }
+
+    // Close the connections that look dead rather than waiting for the idle timeout, and mark
+    // their peers offline unless another connection to them still works. Peers we had dialed
+    // are dialed again once the connection is closed, in case only the connection died.
+    fn close_dead_connections(&mut self) {
+        let room = self.topic.hash().into_string();
+        let now = clock::unix_time();
+        for dead in self.liveness.dead(Instant::now()) {
+            say!(
+                "[liveness] the connection to {} stopped answering, closing it",
+                self.display_name(&dead.peer)
+            );
+            self.swarm.close_connection(dead.connection);
+            if !self.liveness.is_connected(&dead.peer)
+                && self.presence.status(&room, &dead.peer, now).is_some()
+            {
+                self.presence.depart(&room, dead.peer, now);
+            }
+            if let Some(address) = dead.address {
+                self.redial.insert(dead.peer, address);
+            }
+        }
+    }
+
+    // Dial a peer whose dead connection was closed at the address it was reached at.
+    fn dial_again(&mut self, peer: PeerId, address: Multiaddr) {
+        let opts = DialOpts::peer_id(peer)
+            .addresses(vec![address])
+            .condition(PeerCondition::DisconnectedAndNotDialing)
+            .build();
+        match self.swarm.dial(opts) {
+            Ok(()) => debug!("[liveness] dialing {peer} again"),
+            Err(e) => debug!("[liveness] can't dial {peer} again: {e}"),
+        }
+    }
+
+    // Disconnect the peers the connection manager picks when we have nearly too many.
+    fn trim_connections(&mut self) {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let mesh: HashSet<&PeerId> = gossipsub.all_mesh_peers().collect();
+        let connected: Vec<ConnectedPeer> = self
+            .swarm
+            .connected_peers()
+            .map(|peer| ConnectedPeer {
+                peer: *peer,
+                in_mesh: mesh.contains(peer),
+                score: gossipsub
+                    .peer_score(peer)
+                    .unwrap_or_else(|| self.pings.adjustment(peer)),
+            })
+            .collect();
+        for peer in self.connections.evictions(&connected) {
+            info!(
+                "[peers] disconnected {peer} to stay below {} peers",
+                self.connections.max_peers()
+            );
+            let _ = self.swarm.disconnect_peer_id(peer);
+            self.counters.evicted += 1;
+        }
+    }
+
+    // Count connections refused because the peer is missing from `--allowlist-file`.
+    fn count_not_allowlisted(&mut self, cause: &ConnectionDenied) {
+        if cause
+            .downcast_ref::<allow_block_list::NotAllowed>()
+            .is_some()
+        {
+            self.counters.not_allowlisted += 1;
+        }
+    }
+
+    // Tell the room right away when we went away or came back, rather than at the next heartbeat.
+    fn announce_away(&mut self, was_away: bool) {
+        if self.is_away() != was_away {
+            self.send_heartbeat(clock::unix_time());
+        }
+    }
+
+    // Lift the automatic bans that have run out, returning how many there were.
+    fn expire_bans(&mut self, now: u64) -> usize {
+        let expired = self.bans.expire(now);
+        if expired.is_empty() {
+            return 0;
+        }
+        for ban in &expired {
+            self.config
+                .banlist
+                .remove(&ban.peer, |record| record.temp_ban().is_some());
+            self.enforce(Change::Unblocked(ban.peer));
+            say!("[ban] ban on {} has ended", ban.peer);
+        }
+        self.save_config();
+        expired.len()
+    }
+
+    // Mirror the blocklist entry of `peer` in the saved ban list.
+    fn sync_block(&mut self, peer: PeerId) {
+        let is_block = |record: &BanRecord| {
+            record.peer == peer
+                && record.scope == BanScope::Global
+                && matches!(
+                    record.origin,
+                    BanOrigin::Manual | BanOrigin::Blocklist { .. }
+                )
+        };
+        let record = self.blocklist.entry(&peer).map(|entry| BanRecord {
+            peer,
+            scope: BanScope::Global,
+            origin: match entry.origin {
+                BlockOrigin::Manual => BanOrigin::Manual,
+                BlockOrigin::Shared { via, .. } => BanOrigin::Blocklist { via },
+            },
+            created_at: entry.added_at,
+            expires_at: None,
+            reason: entry.reason.clone(),
+        });
+        if self.config.banlist.iter().find(|r| is_block(r)) == record.as_ref() {
+            return;
+        }
+        self.config.banlist.remove(&peer, is_block);
+        if let Some(record) = record {
+            self.config.banlist.insert(record);
+        }
+        self.save_config();
+    }
+
+    // Save a room ban honored from, or issued by, the moderator `by`.
+    fn record_room_ban(&mut self, by: PeerId, moderation: &Moderation) {
+        self.config.banlist.insert(BanRecord {
+            peer: moderation.target,
+            scope: BanScope::Room(moderation.room.clone()),
+            origin: BanOrigin::Moderator { by },
+            created_at: clock::unix_time(),
+            expires_at: None,
+            reason: moderation.reason.clone(),
+        });
+    }
+
+    /// Make the swarm, and the saved ban list, match a change to the blocklist or the
+    /// automatic bans.
+    fn enforce(&mut self, change: Change) {
+        if let Change::Blocked(peer) | Change::Unblocked(peer) = change {
+            self.sync_block(peer);
+        }
+        // A peer stays blocked while either the blocklist or a ban still holds it
+        let still_blocked = matches!(change, Change::Unblocked(peer) if self.is_blocked(&peer));
+        let blocked = &mut self.swarm.behaviour_mut().blocked;
+        match change {
+            // Blocking closes existing connections and refuses new ones
+            Change::Blocked(peer) => blocked.block_peer(peer),
+            Change::Unblocked(peer) if !still_blocked => blocked.unblock_peer(peer),
+            Change::Unblocked(_) | Change::Unchanged => {}
+        }
+    }
+
+    /// Timestamp for a control message about `target`. Receivers drop messages that aren't
+    /// newer than the last one, so never reuse a timestamp.
+    fn next_timestamp(&mut self, target: PeerId) -> u64 {
+        let last = self.last_published.get(&target).copied().unwrap_or(0);
+        let timestamp = clock::unix_time().max(last + 1);
+        self.last_published.insert(target, timestamp);
+        timestamp
+    }
+
+    /// Sign and publish a blocklist update on the control topic.
+    fn publish_blocklist_update(&mut self, action: BlockAction, target: PeerId, reason: String) {
+        let timestamp = self.next_timestamp(target);
+        let message = ControlMessage::BlocklistUpdate(BlocklistUpdate {
+            action,
+            target,
+            reason,
+            timestamp,
+        });
+        if let Err(e) = self.publish_control(&message) {
+            say!("[blocklist] failed to share update: {e}");
+        }
+    }
+
+    /// Sign a board message and publish it on the board topic, sealed with the room key in
+    /// passphrase rooms.
+    fn publish_board(&mut self, message: &BoardMessage) -> Result<(), ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        let signed = SignedBoard::sign(&self.keypair, message)?;
+        let data = serde_json::to_vec(&signed).map_err(CryptoError::from)?;
+        self.publish_sealed(self.board_topic.clone(), data)
+    }
+
+    /// Sign a change to the task list and publish it on the tasks topic, sealed with the room
+    /// key in passphrase rooms.
+    fn publish_tasks(&mut self, delta: &TaskList) -> Result<(), ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        let signed = SignedTasks::sign(&self.keypair, delta)?;
+        let data = serde_json::to_vec(&signed).map_err(CryptoError::from)?;
+        self.publish_sealed(self.tasks_topic.clone(), data)
+    }
+
+    /// Sign a Wordle move and publish it on the Wordle topic, sealed with the room key in
+    /// passphrase rooms.
+    fn publish_move(&mut self, wordle_move: &WordleMove) -> Result<(), ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        let signed = SignedMove::sign(&self.keypair, wordle_move)?;
+        let data = serde_json::to_vec(&signed).map_err(CryptoError::from)?;
+        self.publish_sealed(self.wordle_topic.clone(), data)
+    }
+
+    /// Sign a stroke and publish it on the canvas topic, sealed with the room key if there is
+    /// one.
+    fn publish_canvas(&mut self, delta: &CanvasDelta) -> Result<(), ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        let signed = SignedDelta::sign(&self.keypair, delta)?;
+        let data = serde_json::to_vec(&signed).map_err(CryptoError::from)?;
+        self.publish_sealed(self.canvas_topic.clone(), data)
+    }
+
+    /// Publish a spam report or proof on the spam topic, sealed with the room key if there is
+    /// one.
+    fn publish_spam(&mut self, message: &SpamMessage) -> Result<(), ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        let data = serde_json::to_vec(message).map_err(CryptoError::from)?;
+        self.publish_sealed(self.spam_topic.clone(), data)
+    }
+
+    /// Sign a key share message and publish it on the key share topic. It isn't sealed with
+    /// the room key: whoever recovers a key doesn't have it.
+    fn publish_keyshare(&mut self, message: &KeyShareMessage) -> Result<(), ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        let signed = SignedKeyShare::sign(&self.keypair, message)?;
+        let data = serde_json::to_vec(&signed).map_err(CryptoError::from)?;
+        let len = data.len() as u64;
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.keyshare_topic.clone(), data)?;
+        self.counters.published += 1;
+        self.counters.bytes_sent += len;
+        Ok(())
+    }
+
+    // Publish `data` on `topic`, sealed with the room key if there is one.
+    fn publish_sealed(
+        &mut self,
+        topic: gossipsub::IdentTopic,
+        mut data: Vec<u8>,
+    ) -> Result<(), ChatError> {
+        if let Some(key) = &self.room_key {
+            data = key.seal(&data);
+        }
+        let len = data.len() as u64;
+        self.swarm.behaviour_mut().gossipsub.publish(topic, data)?;
+        self.counters.published += 1;
+        self.counters.bytes_sent += len;
+        Ok(())
+    }
+
+    /// Sign a control message and publish it on the control topic.
+    fn publish_control(&mut self, message: &ControlMessage) -> Result<(), ChatError> {
+        if self.read_only {
+            return Err(ChatError::ReadOnlyMode);
+        }
+        let signed = SignedControl::sign(&self.keypair, message)?;
+        let data = serde_json::to_vec(&signed).map_err(CryptoError::from)?;
+        let len = data.len() as u64;
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.control_topic.clone(), data)?;
+        self.counters.published += 1;
+        self.counters.bytes_sent += len;
+        Ok(())
+    }
+
+    fn print_blocked(&self) {
+        let mut entries: Vec<_> = self.blocklist.entries().collect();
+        let mut bans: Vec<_> = self.bans.bans().collect();
+        if entries.is_empty() && bans.is_empty() {
+            say!("No blocked peers");
+        }
+        entries.sort_by_key(|(_, entry)| entry.added_at);
+        for (peer, entry) in entries {
+            let origin = match entry.origin {
+                BlockOrigin::Manual => "manual".to_string(),
+                BlockOrigin::Shared { via, update: 0 } => format!("via {via}"),
+                BlockOrigin::Shared { via, update } => format!("via {via}, #{update}"),
+            };
+            say!(
+                "Blocked {peer} ({origin}) {}",
+                sanitize::line(&entry.reason)
+            );
+        }
+        let now = clock::unix_time();
+        bans.sort_by_key(|ban| ban.expires_at);
+        for ban in bans {
+            say!(
+                "Banned {} (automatic, {} left) {}",
+                ban.peer,
+                clock::format_duration(ban.expires_at.saturating_sub(now)),
+                ban.violation
+            );
+        }
+    }
+
+    fn run_bans_command(&mut self, command: BansCommand) {
+        match command {
+            BansCommand::List => self.print_bans(),
+            BansCommand::Remove(peer) => self.unblock(peer),
+            BansCommand::ClearExpired => {
+                let expired = self.expire_bans(clock::unix_time());
+                say!("[ban] cleared {expired} expired bans");
+            }
+            BansCommand::ClearAuto => {
+                let auto: Vec<PeerId> = self.bans.bans().map(|ban| ban.peer).collect();
+                for peer in &auto {
+                    self.bans.unban(peer);
+                    self.config
+                        .banlist
+                        .remove(peer, |record| record.temp_ban().is_some());
+                    self.enforce(Change::Unblocked(*peer));
+                }
+                self.save_config();
+                say!("[ban] lifted {} automatic bans", auto.len());
+            }
+        }
+    }
+
+    fn print_bans(&self) {
+        if self.config.banlist.is_empty() {
+            return say!("[ban] no blocks or bans");
+        }
+        let now = clock::unix_time();
+        for group in ["manual", "automatic", "shared blocklist", "moderator"] {
+            let mut records: Vec<_> = self
+                .config
+                .banlist
+                .iter()
+                .filter(|record| record.origin.group() == group)
+                .collect();
+            if records.is_empty() {
+                continue;
+            }
+            records.sort_by_key(|record| record.created_at);
+            say!("[ban] {group}:");
+            for record in records {
+                let left = match record.expires_at {
+                    Some(expires_at) => format!(
+                        "{} left",
+                        clock::format_duration(expires_at.saturating_sub(now))
+                    ),
+                    None => "permanent".to_string(),
+                };
+                let by = match record.origin {
+                    BanOrigin::Blocklist { via } => format!(", via {via}"),
+                    BanOrigin::Moderator { by } => format!(", by {by}"),
+                    BanOrigin::Manual | BanOrigin::Auto { .. } => String::new(),
+                };
+                say!(
+                    "[ban]   {} {} ({left}{by}) {}",
+                    record.peer,
+                    sanitize::line(&record.scope.to_string()),
+                    sanitize::line(&record.reason)
+                );
+            }
+        }
+    }
+
+    fn print_updates(&self) {
+        let mut any = false;
+        for received in self.blocklist.updates() {
+            any = true;
+            let status = match received.status {
+                UpdateStatus::Pending => "pending",
+                UpdateStatus::Applied => "applied",
+                UpdateStatus::Reverted => "reverted",
+            };
+            say!(
+                "#{} [{status}] via {}: {}",
+                received.id,
+                received.author,
+                describe_update(&received.update)
+            );
+        }
+        if !any {
+            say!("No blocklist updates received");
+        }
+    }
+}
+
+// The kind of a swarm event, for the watchdog.
+fn event_kind(event: &SwarmEvent<MyBehaviourEvent>) -> &'static str {
+    match event {
+        SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(_)) => "gossipsub",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(_)) => "mdns",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Relay(_)) => "relay",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Ping(_)) => "ping",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Identify(_)) => "identify",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Dcutr(_)) => "dcutr",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Snapshot(_)) => "snapshot",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Content(_)) => "content",
+        SwarmEvent::Behaviour(MyBehaviourEvent::Auth(_)) => "auth",
+        SwarmEvent::Behaviour(_) => "behaviour",
+        SwarmEvent::ConnectionEstablished { .. } => "connection established",
+        SwarmEvent::ConnectionClosed { .. } => "connection closed",
+        SwarmEvent::IncomingConnection { .. } => "incoming connection",
+        SwarmEvent::IncomingConnectionError { .. } => "incoming connection error",
+        SwarmEvent::OutgoingConnectionError { .. } => "outgoing connection error",
+        SwarmEvent::NewListenAddr { .. } => "new listen address",
+        _ => "swarm",
+    }
+}
+
+// The first eight characters of an id, enough to tell posts and tasks apart in commands.
+// The next news from the Nostr relay, or never without one.
+async fn next_relay_event(relay: &mut Option<Relay>) -> Option<RelayEvent> {
+    match relay {
+        Some(relay) => relay.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_irc_event(bridge: &mut Option<IrcBridge>) -> Option<IrcEvent> {
+    match bridge {
+        Some(bridge) => bridge.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_matrix_event(bridge: &mut Option<MatrixBridge>) -> Option<MatrixEvent> {
+    match bridge {
+        Some(bridge) => bridge.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_xmpp_event(bridge: &mut Option<XmppBridge>) -> Option<XmppEvent> {
+    match bridge {
+        Some(bridge) => bridge.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_mqtt_event(bridge: &mut Option<MqttBridge>) -> Option<MqttEvent> {
+    match bridge {
+        Some(bridge) => bridge.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_fediverse_event(fediverse: &mut Option<Fediverse>) -> Option<FediverseEvent> {
+    match fediverse {
+        Some(fediverse) => fediverse.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_dtn_event(radio: &mut Option<Radio>) -> Option<DtnEvent> {
+    match radio {
+        Some(radio) => radio.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_gateway_event(gateway: &mut Option<Gateway>) -> Option<ClientEvent> {
+    match gateway {
+        Some(gateway) => gateway.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_hook_event(server: &mut Option<HookServer>) -> Option<HookEvent> {
+    match server {
+        Some(server) => server.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Make `room` invite-only under the owner who signed the invite `token`, which lets the local
+// peer in, taking on the moderators it names.
+fn accept_invite(
+    config: &mut Config,
+    room: &str,
+    token: &str,
+    local_peer_id: PeerId,
+) -> Result<(), ChatError> {
+    let signed = invite::decode_token(token)?;
+    let (owner, invite) = invite::check(&signed, room, None, local_peer_id, clock::unix_time())?;
+    let settings = config.rooms.entry(room.to_string()).or_default();
+    settings.owner = Some(owner);
+    settings.invite = Some(token.trim().to_string());
+    for moderator in invite.moderators {
+        if !settings.moderators.contains(&moderator) {
+            settings.moderators.push(moderator);
+        }
+    }
+    Ok(())
+}
+
+// The start of a Nostr id or public key, enough to tell them apart on screen.
+fn short_note_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
+
+// The Gossipsub id of a message in the history, whose id is the hex of it. Messages carried over
+// the simulated Bluetooth link have ids of their own, taken as they are.
+fn message_id_of(id: &str) -> gossipsub::MessageId {
+    hex::decode(id).map_or_else(
+        |_| gossipsub::MessageId::new(id.as_bytes()),
+        gossipsub::MessageId::from,
+    )
+}
+
+fn short_id(id: &Uuid) -> String {
+    id.to_string()[..8].to_string()
+}
+
+// Log a chat message under the transcript target, which only `--log-transcripts` lets through.
+fn log_transcript(author: Option<&PeerId>, topic: &str, message: &ChatMessage) {
+    info!(
+        target: TRANSCRIPT,
+        peer = author.map(tracing::field::display),
+        topic,
+        nick = message.nick.as_str(),
+        "{}",
+        message.body
+    );
+}
+
+fn describe_action(action: ModAction) -> &'static str {
+    match action {
+        ModAction::Kick => "kick",
+        ModAction::RoomBan => "room ban",
+    }
+}
+
+/// One-line description of a blocklist update.
+fn describe_update(update: &BlocklistUpdate) -> String {
+    let action = match update.action {
+        BlockAction::Add => "block",
+        BlockAction::Remove => "unblock",
+    };
+    if update.reason.is_empty() {
+        format!("{action} {}", update.target)
+    } else {
+        format!(
+            "{action} {} ({})",
+            update.target,
+            sanitize::line(&update.reason)
+        )
+    }
+}
@@ -0,0 +1,580 @@
+// Command line flags for the chat node.
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{builder::RangedU64ValueParser, Parser, Subcommand, ValueEnum};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+
+use url::Url;
+
+use crate::{
+    autoban, batch, bootstrap, chat, dtn, fragment, http, matrix, node, nostr,
+    proxy::{self, Proxy},
+    spam, validator, xmpp,
+};
+
+/// Command line options accepted by the chat node.
+#[derive(Parser, Debug, Clone)]
+#[command(name = "p2p-chat", about = "Peer-to-peer chat over Gossipsub and mDNS")]
+pub struct Cli {
+    /// Optional subcommand; without one the node starts chatting.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Name shown to other peers [default: the end of the local PeerId]
+    #[arg(long, value_name = "NAME")]
+    pub nick: Option<String>,
+
+    /// Config file holding settings such as message filters
+    /// [default: $XDG_CONFIG_HOME/p2p-chat/config.json]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Keep the node's identity key in this file, creating it if missing, so the PeerId stays
+    /// the same across runs [default: a fresh identity every run]
+    #[arg(long, value_name = "PATH")]
+    pub identity: Option<PathBuf>,
+
+    /// Preferred cipher for encrypting TCP connections.
+    #[arg(long, value_enum, default_value_t = NoiseCipher::Chacha20)]
+    pub noise_cipher: NoiseCipher,
+
+    /// Disable mDNS peer discovery on the local network.
+    #[arg(long)]
+    pub no_mdns: bool,
+
+    /// Join a private network using the pre-shared key in this `swarm.key` file.
+    #[arg(long, value_name = "PATH")]
+    pub swarm_key: Option<PathBuf>,
+
+    /// Reserve a slot on this circuit relay (an address ending in /p2p/<relay peer id>), so peers
+    /// that can't reach this node directly can dial it through the relay.
+    #[arg(
+        long,
+        value_name = "MULTIADDR",
+        value_parser = relay_address,
+        conflicts_with = "swarm_key"
+    )]
+    pub relay_server: Option<Multiaddr>,
+
+    /// Dial this bootstrap peer at startup (an address ending in /p2p/<peer id>). Repeat it for
+    /// more; with `--bootstrap-domain`, they are only dialed until its records are found.
+    #[arg(long, value_name = "MULTIADDR", value_parser = bootstrap::parse_address)]
+    pub bootstrap: Vec<Multiaddr>,
+
+    /// Dial the bootstrap peers listed in the DNS TXT records of `_p2pchat._tcp.<DOMAIN>` or
+    /// `_dnsaddr.<DOMAIN>`, each `dnsaddr=<multiaddr>` or a bare multiaddr. They are looked up
+    /// at startup and every five minutes, so changes are picked up.
+    #[arg(long, value_name = "DOMAIN")]
+    pub bootstrap_domain: Option<String>,
+
+    /// Prepend this to every topic name, as `<prefix>/<topic>`, so separate deployments on the
+    /// same network keep apart: peers with another prefix are disconnected once identified.
+    /// Letters, digits and hyphens, at most 32.
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        default_value = node::DEFAULT_TOPIC_PREFIX,
+        value_parser = topic_prefix
+    )]
+    pub topic_prefix: String,
+
+    /// Join the private room of this passphrase: its topic and message key are both derived
+    /// from the phrase, so only peers who know it can find the room or read its messages.
+    #[arg(long, value_name = "PHRASE")]
+    pub room_pass: Option<String>,
+
+    /// Join the private room whose key `/recover-key` saved in this file, as if with its
+    /// passphrase.
+    #[arg(long, value_name = "PATH", conflicts_with = "room_pass")]
+    pub room_key_file: Option<PathBuf>,
+
+    /// Also publish chat messages to this Nostr relay (a ws:// or wss:// URL) as text notes
+    /// tagged with the room's hashtag, and show the notes others tag with it. Notes are signed
+    /// with a Nostr key derived from the identity key. Messages of passphrase rooms are private,
+    /// so they can't be bridged.
+    #[arg(
+        long,
+        value_name = "URL",
+        value_parser = nostr::relay_url,
+        conflicts_with_all = ["room_pass", "room_key_file"]
+    )]
+    pub nostr_relay: Option<Url>,
+
+    /// Publish chat messages only to the Nostr relay, not over Gossipsub.
+    #[arg(long, requires = "nostr_relay")]
+    pub nostr_only: bool,
+
+    /// Run an IRC server on this address, e.g. 127.0.0.1:6667, for IRC clients to chat in the
+    /// room through this node by joining `#<topic>`.
+    #[arg(long, value_name = "ADDR")]
+    pub irc_gateway: Option<SocketAddr>,
+
+    /// Bridge the room to a Matrix room on this homeserver, e.g. https://matrix.org. Needs
+    /// `--matrix-room`, and `--matrix-user` with `--matrix-password` or `--matrix-token`.
+    #[arg(
+        long,
+        value_name = "URL",
+        value_parser = http::service_url,
+        conflicts_with_all = ["room_pass", "room_key_file"],
+        requires_all = ["matrix_room", "matrix_login"]
+    )]
+    pub matrix_homeserver: Option<Url>,
+
+    /// The Matrix room to bridge, by id (`!id:server`) or alias (`#alias:server`).
+    #[arg(
+        long,
+        value_name = "ROOM",
+        value_parser = matrix::room_name,
+        requires = "matrix_homeserver"
+    )]
+    pub matrix_room: Option<String>,
+
+    /// The Matrix user the bridge logs in as, e.g. `@bridge:matrix.org`.
+    #[arg(
+        long,
+        value_name = "USER",
+        group = "matrix_login",
+        requires_all = ["matrix_homeserver", "matrix_password"]
+    )]
+    pub matrix_user: Option<String>,
+
+    /// The password of `--matrix-user`.
+    #[arg(long, value_name = "PASSWORD", requires = "matrix_user")]
+    pub matrix_password: Option<String>,
+
+    /// An access token of the bridge's Matrix user, instead of logging in with a password.
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        group = "matrix_login",
+        requires = "matrix_homeserver"
+    )]
+    pub matrix_token: Option<String>,
+
+    /// Bridge the room to a multi-user chat room on this XMPP server, as `host` or
+    /// `host:port` (port 5222 by default). Needs `--xmpp-jid`, `--xmpp-password` and
+    /// `--xmpp-room`.
+    #[arg(
+        long,
+        value_name = "HOST",
+        conflicts_with_all = ["room_pass", "room_key_file"],
+        requires_all = ["xmpp_jid", "xmpp_password", "xmpp_room"]
+    )]
+    pub xmpp_server: Option<String>,
+
+    /// The XMPP account the bridge logs in as, e.g. `bridge@example.com`. Its user part is
+    /// the bridge's nick in the room.
+    #[arg(
+        long,
+        value_name = "JID",
+        value_parser = xmpp::bare_jid,
+        requires = "xmpp_server"
+    )]
+    pub xmpp_jid: Option<String>,
+
+    /// The password of `--xmpp-jid`.
+    #[arg(long, value_name = "PASSWORD", requires = "xmpp_server")]
+    pub xmpp_password: Option<String>,
+
+    /// The multi-user chat room to bridge, e.g. `team@conference.example.com`.
+    #[arg(
+        long,
+        value_name = "ROOM",
+        value_parser = xmpp::bare_jid,
+        requires = "xmpp_server"
+    )]
+    pub xmpp_room: Option<String>,
+
+    /// Cross-post the room to the fediverse as this ActivityPub actor, e.g.
+    /// https://chat.example.com/ops, served by `--http` behind a proxy. Needs
+    /// `--activitypub-key`.
+    #[arg(
+        long,
+        value_name = "URL",
+        value_parser = http::service_url,
+        conflicts_with_all = ["room_pass", "room_key_file"],
+        requires = "activitypub_key"
+    )]
+    pub activitypub_actor: Option<Url>,
+
+    /// An inbox the room's messages are delivered to as notes, e.g.
+    /// https://mastodon.social/users/alice/inbox. Only the actors of these inboxes may post
+    /// notes to the room. Can be repeated.
+    #[arg(
+        long,
+        value_name = "URL",
+        value_parser = http::service_url,
+        requires = "activitypub_actor"
+    )]
+    pub activitypub_inbox: Vec<Url>,
+
+    /// The PEM file with the actor's RSA or Ed25519 private key. An Ed25519 key is generated
+    /// if the file doesn't exist.
+    #[arg(long, value_name = "PATH", requires = "activitypub_actor")]
+    pub activitypub_key: Option<PathBuf>,
+
+    /// Carry the room's messages from node to node over a simulated Bluetooth link, handing
+    /// them on to every neighbour met until they expire, for when there is no internet.
+    #[arg(long, conflicts_with_all = ["room_pass", "room_key_file"])]
+    pub dtn_mode: bool,
+
+    /// The directory standing in for the Bluetooth range: nodes advertising in the same one are
+    /// in range of each other. [default: p2p-chat-bluetooth in the temporary directory]
+    #[arg(long, value_name = "DIR", requires = "dtn_mode")]
+    pub dtn_range: Option<PathBuf>,
+
+    /// Most messages carried for others; the oldest make room for new ones.
+    #[arg(long, value_name = "MESSAGES", default_value_t = dtn::MAX_CARRIED, requires = "dtn_mode")]
+    pub dtn_buffer: usize,
+
+    /// Run an HTTP server on this address, e.g. 127.0.0.1:8080, where the hooks in the config
+    /// file post messages for the room to `/hooks/<token>`, and the fediverse posts notes to
+    /// `/inbox`.
+    #[arg(long, value_name = "ADDR", conflicts_with = "no_publish")]
+    pub http: Option<SocketAddr>,
+
+    /// Authenticate chat messages with the shared hex key in this file. Messages without a
+    /// valid tag are rejected, which lowers the peer score of whoever forwarded them
+    #[arg(long, value_name = "PATH")]
+    pub hmac_key: Option<PathBuf>,
+
+    /// Longest message body sent or passed on, in bytes. Messages over 512 KiB are sent in
+    /// fragments, so raise this to exchange them.
+    #[arg(long, value_name = "BYTES", default_value_t = validator::MAX_BODY_BYTES)]
+    pub max_body: usize,
+
+    /// Also offer mplex on TCP connections, for peers that don't support Yamux. Peers that do
+    /// still get Yamux, which has flow control and performs better.
+    #[arg(long)]
+    pub allow_mplex: bool,
+
+    /// Yamux receive window per stream in bytes [default: 262144 (256 KiB), minimum]
+    #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(262144..))]
+    pub yamux_window_size: Option<u32>,
+
+    /// Yamux receive buffer limit per stream in bytes [default: 1048576 (1 MiB)]
+    #[arg(long, value_name = "BYTES")]
+    pub yamux_max_buffer: Option<usize>,
+
+    /// Cap outbound bandwidth on TCP connections to this many kilobits per second, for metered
+    /// connections. Writes wait while the cap is used up; QUIC is turned off while it is set.
+    #[arg(long, value_name = "KBPS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_upload_kbps: Option<u32>,
+
+    /// Dial every outbound TCP connection through this SOCKS5 proxy, such as Tor's at
+    /// socks5://127.0.0.1:9050. Host names in addresses are resolved by the proxy. Listening is
+    /// unchanged; QUIC is turned off while it is set, since it can't go through the proxy.
+    #[arg(long, value_name = "URL", value_parser = proxy::proxy_url)]
+    pub proxy: Option<Proxy>,
+
+    /// Only connect to the peers listed in this file (one PeerId per line), so no one else
+    /// reaches the Gossipsub mesh. The `--relay-server` is always allowed.
+    #[arg(long, value_name = "PATH")]
+    pub allowlist_file: Option<PathBuf>,
+
+    /// Trust blocklist updates shared by this peer (repeatable).
+    #[arg(long, value_name = "PEER_ID")]
+    pub trust: Vec<PeerId>,
+
+    /// Most connections kept open at once. Nearing it, peers outside the mesh or with a
+    /// negative score are disconnected first; mesh peers in good standing never are.
+    #[arg(long, value_name = "PEERS", default_value_t = 50)]
+    pub max_peers: u32,
+
+    /// Most peers whose presence is tracked, counting each room a peer is in; further ones
+    /// evict offline peers first, then those heard from longest ago.
+    #[arg(long, value_name = "PEERS", default_value_t = autoban::MAX_TRACKED_PEERS)]
+    pub max_tracked_peers: usize,
+
+    /// Most peers whose nick is remembered for naming them in notices; further ones evict the
+    /// nick learned or changed longest ago.
+    #[arg(long, value_name = "NICKS", default_value_t = chat::MAX_KNOWN_NICKS)]
+    pub max_known_nicks: usize,
+
+    /// Bytes of incomplete fragmented messages held at once; further fragments evict the
+    /// message started longest ago.
+    #[arg(long, value_name = "BYTES", default_value_t = fragment::DEFAULT_REASSEMBLY_BYTES)]
+    pub max_reassembly_bytes: usize,
+
+    /// Bytes of incomplete fragmented messages held for one sender, so a single peer can't take
+    /// the whole budget. The default fits one message of the largest size.
+    #[arg(long, value_name = "BYTES", default_value_t = fragment::DEFAULT_REASSEMBLY_PEER_BYTES)]
+    pub max_reassembly_peer_bytes: usize,
+
+    /// How long `ChatNode::connect_to` waits for a connection, in seconds.
+    #[arg(long, value_name = "SECONDS", default_value_t = 10)]
+    pub dial_timeout: u64,
+
+    /// Join an invite-only room with a token from `/invite create`.
+    #[arg(long, value_name = "TOKEN")]
+    pub join_with: Option<String>,
+
+    /// Show invites from `/invite create` as QR codes in the terminal, as `/invite qr` does.
+    #[arg(long)]
+    pub qr: bool,
+
+    /// Honor kicks and room bans from this peer in the chat room (repeatable).
+    #[arg(long, value_name = "PEER_ID")]
+    pub moderator: Vec<PeerId>,
+
+    /// Apply blocklist updates from trusted peers without waiting for `/blocklist apply`.
+    #[arg(long)]
+    pub auto_apply: bool,
+
+    /// Messages a peer may send per 10 seconds before it is temporarily banned.
+    #[arg(long, value_name = "MESSAGES", default_value_t = 30)]
+    pub rate_limit: usize,
+
+    /// Invalid messages tolerated from a peer per minute before it is temporarily banned.
+    #[arg(long, value_name = "MESSAGES", default_value_t = 5)]
+    pub invalid_limit: usize,
+
+    /// Copies of one message a peer may send within the repeat window before further copies
+    /// are collapsed, not forwarded and counted towards an automatic ban.
+    #[arg(long, value_name = "COPIES", default_value_t = 5)]
+    pub repeat_threshold: usize,
+
+    /// Sliding window in seconds over which copies of a message are counted.
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    pub repeat_window: u64,
+
+    /// Messages with fewer letters and digits than this (like "ok" or "+1") may be repeated freely.
+    #[arg(long, value_name = "CHARS", default_value_t = 8)]
+    pub repeat_min_length: usize,
+
+    /// Peers that have to report a message with `/spam` before it is hidden.
+    #[arg(
+        long,
+        value_name = "PEERS",
+        default_value_t = spam::DEFAULT_THRESHOLD,
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub spam_threshold: usize,
+
+    /// Drop messages that aren't signed by their author instead of marking them as unsigned.
+    #[arg(long)]
+    pub require_signed: bool,
+
+    /// Show peers' profile display names on their messages instead of their nicks.
+    #[arg(long)]
+    pub display_names: bool,
+
+    /// Don't show desktop notifications of mentions and of messages from favorite contacts.
+    #[arg(long)]
+    pub no_notify: bool,
+
+    /// Drop messages for topics this node isn't subscribed to, even if a peer relays them.
+    #[arg(long)]
+    pub strict_topic: bool,
+
+    /// Observe without publishing anything: typed lines are only shown locally, and no
+    /// heartbeats or control messages are sent. The node still relays other peers' messages.
+    #[arg(long)]
+    pub no_publish: bool,
+
+    /// Length of a first automatic ban in seconds; repeat offenses double it.
+    #[arg(long, value_name = "SECONDS", default_value_t = 600)]
+    pub ban_duration: u64,
+
+    /// Seconds between presence heartbeats, varied by up to a fifth each time; 0 turns them off.
+    /// A room's `presence_interval` in the config file takes precedence.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    pub presence_interval: u64,
+
+    /// Seconds over which peers joining and leaving a room are collected into one summary line.
+    #[arg(long, value_name = "SECONDS", default_value_t = 2)]
+    pub presence_batch: u64,
+
+    /// Print a line for every peer joining, leaving, going away or coming back instead of
+    /// batched summaries.
+    #[arg(long)]
+    pub verbose_presence: bool,
+
+    /// Show as away to other peers after this many seconds without typing anything, and as back
+    /// on the next line typed; 0 turns it off. Only applies when stdin is a terminal.
+    #[arg(long, value_name = "SECONDS", default_value_t = 0)]
+    pub away_after: u64,
+
+    /// What to do once stdin closes: exit after sending what is queued, or keep receiving
+    /// messages. Defaults to exit when stdin is a pipe or file, and to listen at a terminal.
+    #[arg(long, value_enum, value_name = "ACTION")]
+    pub on_stdin_eof: Option<StdinEof>,
+
+    /// How stdin and stdout are spoken: lines for people, or JSON for programs, one command
+    /// object per line in and one event object per line out, with everything else on stderr.
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = IoMode::Text)]
+    pub io: IoMode,
+
+    /// Where log notices go: stderr, the systemd journal with the level as its priority and
+    /// fields such as the peer and topic as journal fields, syslog at `/dev/log`, or a file.
+    #[arg(long, value_enum, value_name = "TARGET", default_value_t = LogTarget::Stderr)]
+    pub log_target: LogTarget,
+
+    /// The file log notices are appended to with `--log-target file`.
+    #[arg(long, value_name = "PATH", required_if_eq("log_target", "file"))]
+    pub log_file: Option<PathBuf>,
+
+    /// Also log each chat message sent and received. Off by default, so private chats aren't
+    /// shipped to a central log server along with the notices.
+    #[arg(long)]
+    pub log_transcripts: bool,
+
+    /// Collect our chat messages for up to this many milliseconds and send them as one
+    /// Gossipsub message, for feeds of many small messages; 0 sends each at once. A room's
+    /// `batch_ms` in the config file takes precedence.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub batch_ms: u64,
+
+    /// Send a batch early once its messages add up to this many bytes. A room's `batch_bytes`
+    /// in the config file takes precedence.
+    #[arg(long, value_name = "BYTES", default_value_t = batch::DEFAULT_BATCH_BYTES)]
+    pub batch_bytes: usize,
+
+    /// Lose this percentage of reads and writes on TCP connections, each stalling like a
+    /// retransmission. Debug builds only; QUIC is turned off while it is set.
+    #[cfg(debug_assertions)]
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(..=100))]
+    pub simulate_packet_loss: Option<u8>,
+
+    /// Delay every write on TCP connections by a log-normally distributed number of
+    /// milliseconds with this mean and standard deviation. Debug builds only; QUIC is turned
+    /// off while it is set.
+    #[cfg(debug_assertions)]
+    #[arg(long, num_args = 2, value_names = ["MEAN", "STDDEV"])]
+    pub simulate_latency_ms: Option<Vec<u64>>,
+}
+
+/// Subcommands that run instead of the chat node.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Generate a new pre-shared swarm key for a private network.
+    Genkey {
+        /// Write the key to this file instead of printing it.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Manage the identity key given with `--identity`.
+    Identity {
+        #[command(subcommand)]
+        action: IdentityCommand,
+    },
+    /// Measure message throughput and latency between nodes started in this process, and print
+    /// a table comparing transports and payload sizes.
+    Bench {
+        /// Nodes per run: one publishes and the others receive.
+        #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u64).range(2..=16))]
+        nodes: u64,
+        /// Transports to compare.
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            default_values_t = [BenchTransport::Tcp, BenchTransport::Quic, BenchTransport::Memory]
+        )]
+        transport: Vec<BenchTransport>,
+        /// Message body sizes in bytes.
+        #[arg(
+            long,
+            value_name = "BYTES",
+            value_delimiter = ',',
+            default_values_t = [64, 1024, 16384]
+        )]
+        sizes: Vec<usize>,
+        /// Messages published per run.
+        #[arg(long, default_value_t = 1000)]
+        messages: u64,
+    },
+    /// Check the environment without joining a room: sockets, UDP for QUIC, mDNS multicast,
+    /// the clock, the config directory and the relay. Exits with status 1 if a check fails.
+    Doctor,
+}
+
+/// Transports `p2p-chat bench` runs nodes over, all on this machine.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchTransport {
+    /// TCP on the loopback interface, with Noise and Yamux.
+    Tcp,
+    /// QUIC on the loopback interface.
+    Quic,
+    /// The in-process memory transport, with Noise and Yamux.
+    Memory,
+}
+
+/// Actions on the identity key.
+#[derive(Subcommand, Debug, Clone)]
+pub enum IdentityCommand {
+    /// Replace the key with a new one, signing a statement with the old key that peers use to
+    /// move their trust to the new one.
+    Rotate,
+}
+
+/// What the node does once stdin closes.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StdinEof {
+    /// Send the messages still waiting for a peer, then leave the room and exit.
+    Exit,
+    /// Keep running and receiving messages, with nothing more to send.
+    #[default]
+    Listen,
+}
+
+/// How the node reads stdin and writes stdout.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IoMode {
+    /// Chat messages and slash commands in, what happens out, as text.
+    #[default]
+    Text,
+    /// [`ChatCommand`](crate::stdio::ChatCommand)s in and
+    /// [`ChatEvent`](crate::stdio::ChatEvent)s out, one JSON object per line.
+    Json,
+}
+
+/// Where the node's log notices go.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogTarget {
+    /// Standard error, as text.
+    #[default]
+    Stderr,
+    /// The systemd journal, over its native protocol.
+    Journald,
+    /// The local syslog daemon, at `/dev/log`.
+    Syslog,
+    /// The file given with `--log-file`, as text with timestamps.
+    File,
+}
+
+/// Parse a topic prefix, which has to be short and leave the topic's `/` separators alone.
+fn topic_prefix(s: &str) -> Result<String, String> {
+    node::check_topic_prefix(s).map(|()| s.to_string())
+}
+
+/// Parse a relay address, which has to name the relay's peer id.
+fn relay_address(s: &str) -> Result<Multiaddr, String> {
+    let addr = s.parse::<Multiaddr>().map_err(|e| e.to_string())?;
+    match addr.iter().last() {
+        Some(Protocol::P2p(_)) => Ok(addr),
+        _ => Err("the address must end in /p2p/<relay peer id>".to_string()),
+    }
+}
+
+impl Default for Cli {
+    fn default() -> Self {
+        // Parsing an empty argument list gives us every flag at its default value.
+        Cli::parse_from(["p2p-chat"])
+    }
+}
+
+/// Cipher preference used when securing a TCP connection.
+///
+/// `libp2p-noise` only implements the `Noise_XX_25519_ChaChaPoly_SHA256` handshake, so the
+/// AES-GCM preference is served by the TLS 1.3 security upgrade instead. Every node offers
+/// both upgrades; the preference only decides which one is proposed first, and the dialer's
+/// first choice that the listener also supports is the one that gets used.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoiseCipher {
+    /// ChaCha20-Poly1305 via Noise (fast on mobile and embedded hardware).
+    #[default]
+    Chacha20,
+    /// AES-GCM capable TLS 1.3 (fast on server hardware with AES-NI).
+    Aesgcm,
+}
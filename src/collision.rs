@@ -0,0 +1,99 @@
+// Telling apart peers online under the same nick.
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use libp2p::PeerId;
+
+use crate::sanitize::MAX_NICK_CHARS;
+
+/// Characters of the PeerId appended to a nick that several online peers use.
+pub const SUFFIX_CHARS: usize = 4;
+
+/// Separates a shared nick from the PeerId suffix, as in `alex·b3f9`.
+pub const SEPARATOR: char = '·';
+
+/// The end of `peer`'s id that tells it apart from others using the same nick.
+pub fn suffix(peer: &PeerId) -> String {
+    let id = peer.to_base58();
+    id[id.len() - SUFFIX_CHARS..].to_string()
+}
+
+/// `nick` with `peer`'s suffix appended, shortening the nick if needed so the result still
+/// fits the nick length limit.
+pub fn disambiguate(nick: &str, peer: &PeerId) -> String {
+    let nick: String = nick
+        .chars()
+        .take(MAX_NICK_CHARS - SUFFIX_CHARS - 1)
+        .collect();
+    format!("{nick}{SEPARATOR}{}", suffix(peer))
+}
+
+/// Split a disambiguated name like `alex·b3f9` into the nick and the suffix.
+pub fn split(name: &str) -> Option<(&str, &str)> {
+    let (nick, suffix) = name.rsplit_once(SEPARATOR)?;
+    (!nick.is_empty() && suffix.len() == SUFFIX_CHARS).then_some((nick, suffix))
+}
+
+/// Nicks shared by more than one online peer of a room.
+///
+/// Nicks are compared ignoring case, like nick arguments to commands.
+#[derive(Debug, Default)]
+pub struct Collisions {
+    // The peers sharing each ambiguous nick, keyed by the lowercased nick
+    groups: BTreeMap<String, BTreeSet<PeerId>>,
+    // Every peer in one of the groups
+    ambiguous: HashSet<PeerId>,
+}
+
+/// Peers that started sharing a nick with another online peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    /// The nick as the first of the peers uses it.
+    pub nick: String,
+    /// Every online peer using it, the newcomers included.
+    pub peers: Vec<PeerId>,
+}
+
+impl Collisions {
+    /// Recompute the ambiguous nicks from the online peers and their nicks. Returns the nicks
+    /// that became ambiguous or gained a peer, for a notice; nicks only one peer uses any more
+    /// stop being ambiguous without one.
+    pub fn update<'a>(
+        &mut self,
+        online: impl IntoIterator<Item = (PeerId, &'a str)>,
+    ) -> Vec<Collision> {
+        let mut by_nick: BTreeMap<String, (String, BTreeSet<PeerId>)> = BTreeMap::new();
+        for (peer, nick) in online {
+            if nick.is_empty() {
+                continue;
+            }
+            by_nick
+                .entry(nick.to_lowercase())
+                .or_insert_with(|| (nick.to_string(), BTreeSet::new()))
+                .1
+                .insert(peer);
+        }
+        by_nick.retain(|_, (_, peers)| peers.len() > 1);
+
+        let mut collisions = Vec::new();
+        for (key, (nick, peers)) in &by_nick {
+            let known = self.groups.get(key);
+            if known.is_none_or(|known| !peers.is_subset(known)) {
+                collisions.push(Collision {
+                    nick: nick.clone(),
+                    peers: peers.iter().copied().collect(),
+                });
+            }
+        }
+        self.groups = by_nick
+            .into_iter()
+            .map(|(key, (_, peers))| (key, peers))
+            .collect();
+        self.ambiguous = self.groups.values().flatten().copied().collect();
+        collisions
+    }
+
+    /// Whether `peer` shares its nick with another online peer.
+    pub fn is_ambiguous(&self, peer: &PeerId) -> bool {
+        self.ambiguous.contains(peer)
+    }
+}
@@ -0,0 +1,836 @@
+// Slash commands typed on stdin (anything that isn't a command is sent as a chat message).
+use std::path::PathBuf;
+
+use libp2p::PeerId;
+
+use crate::{
+    audit::DEFAULT_TAIL,
+    canvas,
+    clock,
+    filter::TopicFilter,
+    invite,
+    profile::ProfileField,
+};
+
+/// A command entered by the local user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UserCommand {
+    /// `/help`
+    Help,
+    /// `/md <text>`: send a chat message written in Markdown.
+    Markdown(String),
+    /// `/trust [peer]`: trust a peer's shared blocklist updates, or list trusted peers.
+    Trust(Option<PeerId>),
+    /// `/untrust <peer>`
+    Untrust(PeerId),
+    /// `/block <peer> [reason]`
+    Block { peer: PeerId, reason: String },
+    /// `/block list`
+    BlockList,
+    /// `/unblock <peer>`
+    Unblock(PeerId),
+    /// `/blocklist ...`
+    Blocklist(BlocklistCommand),
+    /// `/filter ...`
+    Filter(FilterCommand),
+    /// `/stats`: Gossipsub mesh state and message counters.
+    Stats,
+    /// `/stats memory`: sizes of bounded in-memory state against their limits.
+    MemoryStats,
+    /// `/doctor`: check sockets, multicast, the clock, the config directory and the relay.
+    Doctor,
+    /// `/doctor publish`: check why published messages may not be reaching anyone, and
+    /// rejoin the mesh if something is wrong.
+    DoctorPublish,
+    /// `/kick <peer> [reason]`: as a moderator, remove a peer from the room for this session.
+    Kick { peer: PeerId, reason: String },
+    /// `/roomban <peer> [reason]`: as a moderator, remove a peer from the room for good.
+    RoomBan { peer: PeerId, reason: String },
+    /// `/modlist`: list the room's moderators.
+    ModList,
+    /// `/quorum [n|off]`: show, or set, how many moderators must sign a room ban in this room.
+    /// `off` is a quorum of one.
+    Quorum(Option<usize>),
+    /// `/proposals`: list the room bans waiting for moderators' signatures.
+    Proposals,
+    /// `/topic-alias`: show the alias the room's chat messages travel under.
+    TopicAlias,
+    /// `/topic-alias rotate`: as a moderator, move the room's chat messages to a fresh alias.
+    TopicAliasRotate,
+    /// `/approve <id>`: as a moderator, sign a proposed room ban.
+    Approve(String),
+    /// `/invite create [ttl] [peer]`: as the room owner, issue a signed invite. `/invite qr`
+    /// shows it as a QR code too.
+    InviteCreate {
+        ttl: u64,
+        invitee: Option<PeerId>,
+        qr: bool,
+    },
+    /// `/whois <peer>`: show a peer's nick and the key that has been signing its messages.
+    Whois(PeerId),
+    /// `/verify [peer|nick] [confirm]`: show a fingerprint (our own without a target), and with
+    /// `confirm` mark the peer verified.
+    Verify {
+        target: Option<String>,
+        confirm: bool,
+    },
+    /// `/unverify <peer|nick>`: forget a verification.
+    Unverify(String),
+    /// `/link <n>`: make a held-back hyperlink from an untrusted peer clickable.
+    Link(u64),
+    /// `/audit tail [n]`: show the last entries of the audit log.
+    AuditTail(usize),
+    /// `/history [n]`: show the last messages received, as printed when they arrived.
+    History(usize),
+    /// `/report <message id|last from <nick>> [reason]`: report a message to the moderators.
+    Report { target: ReportTarget, reason: String },
+    /// `/reports`: as a moderator, list reports nobody has acted on yet.
+    Reports,
+    /// `/spam <message id|last from <nick>>`: report a message as spam to the room, which
+    /// hides it once enough members did.
+    Spam(ReportTarget),
+    /// `/share-key <threshold> <peer|nick>...`: split the room's key into a share for each
+    /// holder, any `threshold` of whom can hand it back.
+    ShareKey {
+        threshold: usize,
+        holders: Vec<String>,
+    },
+    /// `/recover-key [path]`: ask the holders of our shares for them, and save the room key
+    /// rebuilt from them.
+    RecoverKey(Option<PathBuf>),
+    /// `/bans ...`
+    Bans(BansCommand),
+    /// `/peers`: list the peers seen in the room, how recently and whether they are online.
+    Peers,
+    /// `/net`: listen addresses, connected peers and the bootstrap peers, with where they came
+    /// from.
+    Net,
+    /// `/status ...`
+    Status(StatusCommand),
+    /// `/profile ...`
+    Profile(ProfileCommand),
+    /// `/dnd ...`
+    Dnd(DndCommand),
+    /// `/contacts`: list our contacts, favorites first, with whether they are around.
+    Contacts,
+    /// `/contact ...`
+    Contact(ContactCommand),
+    /// `/alias ...`
+    Alias(AliasCommand),
+    /// `/post <title> | <body>`: publish a post on the room's board.
+    Post { title: String, body: String },
+    /// `/board`: list the board's posts, pinned ones first.
+    Board,
+    /// `/pin <post id>` and `/unpin <post id>`: as a moderator, pin a post or unpin it. The
+    /// id may be shortened to any unique prefix.
+    Pin { post: String, pinned: bool },
+    /// `/task ...`
+    Task(TaskCommand),
+    /// `/wordle ...`
+    Wordle(WordleCommand),
+    /// `/draw <x> <y> [char]`: set a cell of the room's canvas, or clear it without a char.
+    Draw { x: u8, y: u8, ch: char },
+    /// `/canvas`: show the room's canvas.
+    Canvas,
+    /// `/export-topology <path.dot>`: write the peers we know and our connections to them as a
+    /// Graphviz graph.
+    ExportTopology(PathBuf),
+    /// `/save <message id> <path>`: write a received message to a file, byte for byte when it
+    /// wasn't text.
+    Save { id: String, path: PathBuf },
+    /// `/attach <path>`: send a file along with the next chat message, by reference.
+    Attach(PathBuf),
+    /// `/fetch <message id|content id> <path>`: fetch the attachment of a message from the
+    /// peer that sent it and save it to a file.
+    Fetch { target: String, path: PathBuf },
+    /// `/transfers ...`
+    Transfers(TransfersCommand),
+}
+
+/// Subcommands of `/task`, which edits the room's shared task list. Tasks are given by any
+/// unique prefix of their id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskCommand {
+    /// `/task list` (or just `/task`)
+    List,
+    /// `/task add <title>`
+    Add(String),
+    /// `/task done <id>` and `/task undone <id>`
+    Done { task: String, done: bool },
+    /// `/task assign <id> [nick]`: assign a task, or unassign it without a nick.
+    Assign {
+        task: String,
+        assignee: Option<String>,
+    },
+    /// `/task remove <id>`
+    Remove(String),
+}
+
+/// Subcommands of `/transfers`, which manages interrupted fetches of attachments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransfersCommand {
+    /// `/transfers`: fetches in progress or paused, with how far they got.
+    List,
+    /// `/transfers discard <content id>`: stop a fetch and delete what arrived. The id may be
+    /// shortened to any unique prefix.
+    Discard(String),
+}
+
+/// Subcommands of `/wordle`, which plays Wordle with the room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WordleCommand {
+    /// `/wordle` (or `/wordle show`): the current game.
+    Show,
+    /// `/wordle start <word>`: host a game with a five-letter word for the others to guess.
+    Start(String),
+    /// `/wordle guess <word>`
+    Guess(String),
+    /// `/wordle end`: as the host, end the game and reveal the word.
+    End,
+}
+
+/// Subcommands of `/alias`, which names peers locally instead of by the nicks they send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AliasCommand {
+    /// `/alias`: list our aliases.
+    List,
+    /// `/alias <peer|nick> <name>`: show the peer as `name` from now on.
+    Set { target: String, alias: String },
+    /// `/alias remove <peer|nick|alias>`: show the peer by its nick again.
+    Remove(String),
+}
+
+/// Subcommands of `/contact`, which manages the contacts saved in the config file. Contacts
+/// are given by PeerId, nick or label.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContactCommand {
+    /// `/contact add <peer|nick> [label]`: save a peer, labeled with its nick by default, or
+    /// relabel a contact.
+    Add {
+        target: String,
+        label: Option<String>,
+    },
+    /// `/contact remove <contact>`
+    Remove(String),
+    /// `/contact note <contact> [notes]`: set or, without notes, clear the notes about a contact.
+    Note { target: String, notes: String },
+    /// `/contact fav|unfav <contact>`: mark a contact as a favorite or not.
+    Favorite { target: String, favorite: bool },
+}
+
+/// Subcommands of `/dnd`, which tells peers not to disturb us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DndCommand {
+    /// `/dnd`: show whether do not disturb is on and for how long.
+    Show,
+    /// `/dnd on`: turn it on until `/dnd off`.
+    On,
+    /// `/dnd <duration>`: turn it on for this many seconds.
+    For(u64),
+    /// `/dnd off`
+    Off,
+}
+
+/// Subcommands of `/profile`, which manages the profile we publish and shows those of peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileCommand {
+    /// `/profile [show <peer|nick>]`: show a peer's profile, or our own without a target.
+    Show(Option<String>),
+    /// `/profile set <field> <value>`: set a field of our profile and publish it. For the
+    /// avatar, the value is the path of the image.
+    Set { field: ProfileField, value: String },
+    /// `/profile clear <field>`: clear a field of our profile and publish it.
+    Clear(ProfileField),
+}
+
+/// Subcommands of `/status`, which sets the away state and status line peers see in their
+/// rosters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatusCommand {
+    /// `/status`: show whether we are away and why.
+    Show,
+    /// `/status away`: show as away until `/status auto`, even while typing.
+    Away,
+    /// `/status online`: never show as away until `/status auto`, even when idle.
+    Online,
+    /// `/status auto`: drop a manual status and go back to `--away-after`.
+    Auto,
+    /// `/status set <text>`: broadcast a free-form status line, such as "reviewing PRs".
+    Set(String),
+    /// `/status clear`: drop the status line.
+    Clear,
+}
+
+/// Subcommands of `/bans`, which reviews every saved block and ban.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BansCommand {
+    /// `/bans`: list blocks and bans grouped by origin, with the time left.
+    List,
+    /// `/bans remove <peer>`: lift every block and ban of a peer.
+    Remove(PeerId),
+    /// `/bans clear expired`: drop bans that have run out right away.
+    ClearExpired,
+    /// `/bans clear auto`: lift all automatic bans.
+    ClearAuto,
+}
+
+/// The message a `/report` is about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReportTarget {
+    /// The message with this Gossipsub id.
+    Id(String),
+    /// The latest message from a peer, given by nick or peer id.
+    LastFrom(String),
+}
+
+/// Subcommands of `/blocklist`, which manages blocklists shared between trusted peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlocklistCommand {
+    /// `/blocklist show`: list received updates and their status.
+    Show,
+    /// `/blocklist apply <entry>`: apply a pending update.
+    Apply(u64),
+    /// `/blocklist revert <entry>`: undo an applied update or discard a pending one.
+    Revert(u64),
+    /// `/blocklist add <peer> [reason]`: block a peer and share it with peers who trust us.
+    Add { peer: PeerId, reason: String },
+    /// `/blocklist remove <peer>`: unblock a peer and share the removal.
+    Remove(PeerId),
+}
+
+/// Subcommands of `/filter`, which controls which messages of the current topic are shown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterCommand {
+    /// `/filter show`: print the current filter and how many messages it hid.
+    Show,
+    /// `/filter set <criteria>`: only show messages matching the criteria.
+    Set(TopicFilter),
+    /// `/filter clear`: show every message again.
+    Clear,
+}
+
+/// Help text listing every command.
+pub const HELP: &str = "\
+Commands:
+  /help                          Show this help
+  /md <text>                     Send a message written in Markdown; peers that don't read it get it
+                                 as plain text
+  /trust [peer]                  Trust a peer's shared blocklist updates (no argument: list)
+  /untrust <peer>                Stop trusting a peer
+  /block <peer> [reason]         Block a peer locally
+  /block list                    List blocked peers and automatic bans
+  /unblock <peer>                Lift a block, automatic ban or room ban
+  /blocklist show                List blocklist updates received from trusted peers
+  /blocklist apply <entry>       Apply a pending update
+  /blocklist revert <entry>      Undo an applied update or discard a pending one
+  /blocklist add <peer> [reason] Block a peer and share the block with peers who trust you
+  /blocklist remove <peer>       Unblock a peer and share the removal
+  /filter show                   Show the filter for this topic
+  /filter set <criteria>         Only show matching messages, e.g. nick:alice,bob since:<unix time>
+                                 body:<regex> (body: takes the rest of the line)
+  /filter clear                  Show every message again
+  /stats                         Show Gossipsub mesh and message statistics
+  /stats memory                  Show the peer registry, nick cache and fragment buffers against
+                                 their limits
+  /doctor                        Check sockets, multicast, the clock, config directory and relay
+  /doctor publish                Check whether messages reach anyone, and rejoin the mesh if not
+  /kick <peer> [reason]          Remove a peer from the room (moderators only)
+  /roomban <peer> [reason]       Ban a peer from the room (moderators only)
+  /modlist                       List the room's moderators
+  /quorum [n|off]                Show or set how many moderators must sign a room ban here
+  /proposals                     List room bans waiting for moderators' signatures
+  /topic-alias [rotate]          Show, or as a moderator rotate, the room's topic alias
+  /approve <id>                  Sign a proposed room ban (moderators only)
+  /invite create [ttl] [peer]    Create an invite to this room (ttl like 30m, 2h, 7d; default 1d),
+                                 optionally only valid for one peer
+  /invite qr [ttl] [peer]        Create an invite, shown as a QR code to scan from the screen
+  /share-key <threshold> <peer|nick>...
+                                 Split the room's key among peers, any threshold of whom can
+                                 give it back
+  /recover-key [path]            Ask for the shares of your room key back and save the key
+  /whois <peer>                  Show a peer's nick and the key verified on its messages
+  /verify [peer|nick] [confirm]  Show a fingerprint to compare out of band (no argument: yours);
+                                 confirm marks the peer verified
+  /unverify <peer|nick>          Forget a verification
+  /link <n>                      Show link #n from an untrusted peer as a clickable link
+  /audit tail [n]                Show the last n entries of the audit log (default 20)
+  /history [n]                   Show the last n messages received (default 20), including those
+                                 skipped when too many arrived at once
+  /report <id|last from <nick>> [reason]
+                                 Report a message to the room's moderators
+  /reports                       List reports nobody has acted on (moderators)
+  /spam <id|last from <nick>>    Report a message as spam, hiding it once enough members did
+  /bans                          List all blocks and bans by origin, with the time left
+  /bans remove <peer>            Lift every block and ban of a peer
+  /bans clear expired|auto       Drop bans that have run out, or lift all automatic bans
+  /peers                         List peers seen in the room: online, stale or offline
+  /net                           Show listen addresses, connections and the bootstrap peers found
+  /status [away|online|auto]     Show or set your away status; auto follows --away-after
+  /status set <text> | clear     Set or clear a status line peers see, e.g. /status set reviewing PRs
+  /profile [show <peer|nick>]    Show a peer's profile (no argument: yours)
+  /profile set <field> <value>   Set and publish name, pronouns, bio or avatar (an image path)
+  /profile clear <field>         Clear a field of your profile
+  /dnd [on|off|<duration>]       Show or set do not disturb, e.g. /dnd 45m; peers see you as dnd
+  /contacts                      List your contacts, favorites first, and when they were last seen
+  /contact add <peer|nick> [label]
+                                 Save a peer as a contact (labeled with its nick by default)
+  /contact remove <contact>      Forget a contact
+  /contact note <contact> [text] Set or clear your notes about a contact
+  /contact fav|unfav <contact>   Mark a contact as a favorite or not
+  /alias [<peer|nick> <name>]    Show a peer under a name of your choosing (no argument: list)
+  /alias remove <peer|name>      Show a peer by its own nick again
+  /post <title> | <body>         Post an announcement on the room's board
+  /board                         List the board's posts, pinned ones first
+  /pin|unpin <post id>           Pin a post to the top of the board or unpin it (moderators only)
+  /task [list]                   List the room's shared tasks
+  /task add <title>              Add a task to the list
+  /task done|undone <id>         Mark a task done, or not done again
+  /task assign <id> [nick]       Assign a task to someone, or to nobody without a nick
+  /task remove <id>              Remove a task from the list
+  /wordle [show]                 Show the current Wordle game
+  /wordle start <word>           Host a Wordle game with a five-letter word
+  /wordle guess <word>           Guess the word of the current game (six tries)
+  /wordle end                    End the game you host and reveal the word
+  /draw <x> <y> [char]           Draw a character on the room's 80×24 canvas, or clear the cell
+  /canvas                        Show the room's canvas
+  /export-topology <path.dot>    Write our peers and connections as a Graphviz graph, e.g. to
+                                 render with dot -Tsvg
+  /save <id> <path>              Write a received message to a file, the original bytes when it
+                                 was binary
+  /attach <path>                 Send a file along with your next message (up to 1 GiB); peers
+                                 fetch it from you
+  /fetch <id> <path>             Fetch the file attached to a message and save it to a new file;
+                                 an interrupted fetch resumes where it stopped
+  /transfers                     List fetches in progress or paused
+  /transfers discard <id>        Stop a fetch and delete what arrived of it";
+
+/// Parse a line of input. Returns `None` when the line is a chat message rather than a command.
+pub fn parse(line: &str) -> Option<Result<UserCommand, String>> {
+    let line = line.trim();
+    let rest = line.strip_prefix('/')?;
+    let (name, args) = split_word(rest);
+    Some(match name {
+        "help" => Ok(UserCommand::Help),
+        "md" if args.is_empty() => Err("usage: /md <text>".to_string()),
+        "md" => Ok(UserCommand::Markdown(args.to_string())),
+        "trust" if args.is_empty() => Ok(UserCommand::Trust(None)),
+        "trust" => peer_arg(args).map(|(peer, _)| UserCommand::Trust(Some(peer))),
+        "untrust" => peer_arg(args).map(|(peer, _)| UserCommand::Untrust(peer)),
+        "block" if args == "list" => Ok(UserCommand::BlockList),
+        "block" => peer_arg(args).map(|(peer, reason)| UserCommand::Block { peer, reason }),
+        "unblock" => peer_arg(args).map(|(peer, _)| UserCommand::Unblock(peer)),
+        "blocklist" => parse_blocklist(args).map(UserCommand::Blocklist),
+        "filter" => parse_filter(args).map(UserCommand::Filter),
+        "stats" if args == "memory" => Ok(UserCommand::MemoryStats),
+        "stats" => Ok(UserCommand::Stats),
+        "doctor" if args == "publish" => Ok(UserCommand::DoctorPublish),
+        "doctor" if args.is_empty() => Ok(UserCommand::Doctor),
+        "doctor" => Err("usage: /doctor [publish]".to_string()),
+        "kick" => peer_arg(args).map(|(peer, reason)| UserCommand::Kick { peer, reason }),
+        "roomban" => peer_arg(args).map(|(peer, reason)| UserCommand::RoomBan { peer, reason }),
+        "modlist" => Ok(UserCommand::ModList),
+        "quorum" => parse_quorum(args),
+        "proposals" => Ok(UserCommand::Proposals),
+        "topic-alias" if args == "rotate" => Ok(UserCommand::TopicAliasRotate),
+        "topic-alias" if args.is_empty() => Ok(UserCommand::TopicAlias),
+        "topic-alias" => Err("usage: /topic-alias [rotate]".to_string()),
+        "approve" => match split_word(args).0 {
+            "" => Err("usage: /approve <id>".to_string()),
+            id => Ok(UserCommand::Approve(id.to_string())),
+        },
+        "invite" => parse_invite(args),
+        "whois" => peer_arg(args).map(|(peer, _)| UserCommand::Whois(peer)),
+        "verify" => parse_verify(args),
+        "unverify" => match split_word(args) {
+            ("", _) => Err("usage: /unverify <peer|nick>".to_string()),
+            (target, _) => Ok(UserCommand::Unverify(target.to_string())),
+        },
+        "link" => entry_arg(args).map(UserCommand::Link),
+        "audit" => parse_audit(args),
+        "history" if args.is_empty() => Ok(UserCommand::History(DEFAULT_TAIL)),
+        "history" => args
+            .parse()
+            .map(UserCommand::History)
+            .map_err(|_| format!("invalid count {args:?}")),
+        "report" => parse_report(args),
+        "reports" => Ok(UserCommand::Reports),
+        "spam" => parse_spam(args),
+        "share-key" => parse_share_key(args),
+        "recover-key" if args.is_empty() => Ok(UserCommand::RecoverKey(None)),
+        "recover-key" => Ok(UserCommand::RecoverKey(Some(args.into()))),
+        "bans" => parse_bans(args).map(UserCommand::Bans),
+        "peers" => Ok(UserCommand::Peers),
+        "net" => Ok(UserCommand::Net),
+        "status" => parse_status(args).map(UserCommand::Status),
+        "profile" => parse_profile(args).map(UserCommand::Profile),
+        "dnd" => parse_dnd(args).map(UserCommand::Dnd),
+        "contacts" => Ok(UserCommand::Contacts),
+        "contact" => parse_contact(args).map(UserCommand::Contact),
+        "alias" => parse_alias(args).map(UserCommand::Alias),
+        "post" => match args.split_once('|') {
+            Some((title, body)) if !title.trim().is_empty() && !body.trim().is_empty() => {
+                Ok(UserCommand::Post {
+                    title: title.trim().to_string(),
+                    body: body.trim().to_string(),
+                })
+            }
+            _ => Err("usage: /post <title> | <body>".to_string()),
+        },
+        "board" => Ok(UserCommand::Board),
+        "task" => parse_task(args).map(UserCommand::Task),
+        "wordle" => parse_wordle(args).map(UserCommand::Wordle),
+        "draw" => parse_draw(args),
+        "canvas" => Ok(UserCommand::Canvas),
+        "export-topology" if !args.is_empty() => Ok(UserCommand::ExportTopology(args.into())),
+        "export-topology" => Err("usage: /export-topology <path.dot>".to_string()),
+        "save" => match split_word(args) {
+            (id, path) if !id.is_empty() && !path.is_empty() => Ok(UserCommand::Save {
+                id: id.to_string(),
+                path: path.into(),
+            }),
+            _ => Err("usage: /save <message id> <path>".to_string()),
+        },
+        "attach" if !args.is_empty() => Ok(UserCommand::Attach(args.into())),
+        "attach" => Err("usage: /attach <path>".to_string()),
+        "fetch" => match split_word(args) {
+            (target, path) if !target.is_empty() && !path.is_empty() => Ok(UserCommand::Fetch {
+                target: target.to_string(),
+                path: path.into(),
+            }),
+            _ => Err("usage: /fetch <message id|content id> <path>".to_string()),
+        },
+        "transfers" => match split_word(args) {
+            ("" | "list", "") => Ok(UserCommand::Transfers(TransfersCommand::List)),
+            ("discard", cid) if !cid.is_empty() && !cid.contains(' ') => Ok(
+                UserCommand::Transfers(TransfersCommand::Discard(cid.to_string())),
+            ),
+            _ => Err("usage: /transfers [list | discard <content id>]".to_string()),
+        },
+        "pin" | "unpin" => match split_word(args) {
+            (post, "") if !post.is_empty() => Ok(UserCommand::Pin {
+                post: post.to_string(),
+                pinned: name == "pin",
+            }),
+            _ => Err(format!("usage: /{name} <post id>")),
+        },
+        _ => Err(format!("unknown command /{name}, try /help")),
+    })
+}
+
+fn parse_blocklist(args: &str) -> Result<BlocklistCommand, String> {
+    let (sub, rest) = split_word(args);
+    match sub {
+        "show" | "" => Ok(BlocklistCommand::Show),
+        "apply" => entry_arg(rest).map(BlocklistCommand::Apply),
+        "revert" => entry_arg(rest).map(BlocklistCommand::Revert),
+        "add" => peer_arg(rest).map(|(peer, reason)| BlocklistCommand::Add { peer, reason }),
+        "remove" => peer_arg(rest).map(|(peer, _)| BlocklistCommand::Remove(peer)),
+        _ => Err(format!("unknown /blocklist subcommand {sub:?}, try /help")),
+    }
+}
+
+fn parse_filter(args: &str) -> Result<FilterCommand, String> {
+    let (sub, rest) = split_word(args);
+    match sub {
+        "show" | "" => Ok(FilterCommand::Show),
+        "set" => TopicFilter::parse(rest).map(FilterCommand::Set),
+        "clear" => Ok(FilterCommand::Clear),
+        _ => Err(format!("unknown /filter subcommand {sub:?}, try /help")),
+    }
+}
+
+fn parse_quorum(args: &str) -> Result<UserCommand, String> {
+    match args.trim() {
+        "" => Ok(UserCommand::Quorum(None)),
+        "off" => Ok(UserCommand::Quorum(Some(1))),
+        n => match n.parse() {
+            Ok(n) if n > 0 => Ok(UserCommand::Quorum(Some(n))),
+            _ => Err("usage: /quorum [n|off]".to_string()),
+        },
+    }
+}
+
+fn parse_invite(args: &str) -> Result<UserCommand, String> {
+    let (sub, rest) = split_word(args);
+    if sub != "create" && sub != "qr" {
+        return Err("usage: /invite create|qr [ttl] [peer]".to_string());
+    }
+    let (mut ttl, mut invitee) = (invite::DEFAULT_TTL, None);
+    for word in rest.split_whitespace() {
+        match clock::parse_duration(word) {
+            Some(seconds) => ttl = seconds,
+            None => {
+                let peer = word
+                    .parse()
+                    .map_err(|_| format!("invalid ttl or peer id {word:?}"))?;
+                invitee = Some(peer);
+            }
+        }
+    }
+    Ok(UserCommand::InviteCreate {
+        ttl,
+        invitee,
+        qr: sub == "qr",
+    })
+}
+
+fn parse_verify(args: &str) -> Result<UserCommand, String> {
+    let (target, rest) = split_word(args);
+    let confirm = match rest {
+        "" => false,
+        "confirm" => true,
+        _ => return Err("usage: /verify [peer|nick] [confirm]".to_string()),
+    };
+    Ok(UserCommand::Verify {
+        target: (!target.is_empty()).then(|| target.to_string()),
+        confirm,
+    })
+}
+
+fn parse_audit(args: &str) -> Result<UserCommand, String> {
+    match split_word(args) {
+        ("tail", "") => Ok(UserCommand::AuditTail(DEFAULT_TAIL)),
+        ("tail", n) => n
+            .parse()
+            .map(UserCommand::AuditTail)
+            .map_err(|_| format!("invalid count {n:?}")),
+        _ => Err("usage: /audit tail [n]".to_string()),
+    }
+}
+
+fn parse_bans(args: &str) -> Result<BansCommand, String> {
+    match split_word(args) {
+        ("", _) => Ok(BansCommand::List),
+        ("remove", rest) => peer_arg(rest).map(|(peer, _)| BansCommand::Remove(peer)),
+        ("clear", "expired") => Ok(BansCommand::ClearExpired),
+        ("clear", "auto") => Ok(BansCommand::ClearAuto),
+        _ => Err("usage: /bans [remove <peer> | clear expired|auto]".to_string()),
+    }
+}
+
+fn parse_status(args: &str) -> Result<StatusCommand, String> {
+    match args {
+        "" => Ok(StatusCommand::Show),
+        "away" => Ok(StatusCommand::Away),
+        "online" => Ok(StatusCommand::Online),
+        "auto" => Ok(StatusCommand::Auto),
+        "clear" => Ok(StatusCommand::Clear),
+        _ => match args.split_once(' ') {
+            Some(("set", text)) if !text.trim().is_empty() => {
+                Ok(StatusCommand::Set(text.trim().to_string()))
+            }
+            _ => Err("usage: /status [away|online|auto | set <text> | clear]".to_string()),
+        },
+    }
+}
+
+fn parse_dnd(args: &str) -> Result<DndCommand, String> {
+    match args {
+        "" => Ok(DndCommand::Show),
+        "on" => Ok(DndCommand::On),
+        "off" => Ok(DndCommand::Off),
+        _ => match clock::parse_duration(args) {
+            Some(secs) if secs > 0 => Ok(DndCommand::For(secs)),
+            _ => Err("usage: /dnd [on|off|<duration like 45m>]".to_string()),
+        },
+    }
+}
+
+fn parse_contact(args: &str) -> Result<ContactCommand, String> {
+    let usage = || {
+        "usage: /contact add <peer|nick> [label] | remove <contact> | note <contact> [text] \
+         | fav|unfav <contact>"
+            .to_string()
+    };
+    let (sub, rest) = split_word(args);
+    let (target, rest) = split_word(rest);
+    if target.is_empty() {
+        return Err(usage());
+    }
+    let target = target.to_string();
+    match sub {
+        "add" => Ok(ContactCommand::Add {
+            target,
+            label: (!rest.is_empty()).then(|| unquote(rest).to_string()),
+        }),
+        "remove" if rest.is_empty() => Ok(ContactCommand::Remove(target)),
+        "note" => Ok(ContactCommand::Note {
+            target,
+            notes: unquote(rest).to_string(),
+        }),
+        "fav" | "unfav" if rest.is_empty() => Ok(ContactCommand::Favorite {
+            target,
+            favorite: sub == "fav",
+        }),
+        _ => Err(usage()),
+    }
+}
+
+fn parse_alias(args: &str) -> Result<AliasCommand, String> {
+    match split_word(args) {
+        ("", _) => Ok(AliasCommand::List),
+        ("remove", target) if !target.is_empty() => Ok(AliasCommand::Remove(target.to_string())),
+        (_, "") => Err("usage: /alias [<peer|nick> <name> | remove <peer|name>]".to_string()),
+        (target, alias) => Ok(AliasCommand::Set {
+            target: target.to_string(),
+            alias: unquote(alias).to_string(),
+        }),
+    }
+}
+
+fn parse_task(args: &str) -> Result<TaskCommand, String> {
+    let usage = "usage: /task [list | add <title> | done|undone|remove <id> | assign <id> [nick]]";
+    match split_word(args) {
+        ("" | "list", "") => Ok(TaskCommand::List),
+        ("add", title) if !title.is_empty() => Ok(TaskCommand::Add(title.to_string())),
+        ("done" | "undone", task) if !task.is_empty() && !task.contains(' ') => {
+            Ok(TaskCommand::Done {
+                task: task.to_string(),
+                done: args.starts_with("done"),
+            })
+        }
+        ("assign", rest) if !rest.is_empty() => match split_word(rest) {
+            (task, assignee) if !assignee.contains(' ') => Ok(TaskCommand::Assign {
+                task: task.to_string(),
+                assignee: Some(assignee.to_string()).filter(|nick| !nick.is_empty()),
+            }),
+            _ => Err(usage.to_string()),
+        },
+        ("remove", task) if !task.is_empty() && !task.contains(' ') => {
+            Ok(TaskCommand::Remove(task.to_string()))
+        }
+        _ => Err(usage.to_string()),
+    }
+}
+
+fn parse_wordle(args: &str) -> Result<WordleCommand, String> {
+    match split_word(args) {
+        ("" | "show", "") => Ok(WordleCommand::Show),
+        ("start", word) if !word.is_empty() && !word.contains(' ') => {
+            Ok(WordleCommand::Start(word.to_string()))
+        }
+        ("guess", word) if !word.is_empty() && !word.contains(' ') => {
+            Ok(WordleCommand::Guess(word.to_string()))
+        }
+        ("end", "") => Ok(WordleCommand::End),
+        _ => Err("usage: /wordle [show | start <word> | guess <word> | end]".to_string()),
+    }
+}
+
+fn parse_draw(args: &str) -> Result<UserCommand, String> {
+    let usage = || "usage: /draw <x> <y> [char]".to_string();
+    let (x, rest) = split_word(args);
+    let (y, ch) = split_word(rest);
+    let mut chars = ch.chars();
+    let ch = match (chars.next(), chars.next()) {
+        (None, _) => canvas::BLANK,
+        (Some(ch), None) => ch,
+        (Some(_), Some(_)) => return Err(usage()),
+    };
+    match (x.parse(), y.parse()) {
+        (Ok(x), Ok(y)) => Ok(UserCommand::Draw { x, y, ch }),
+        _ => Err(usage()),
+    }
+}
+
+fn parse_profile(args: &str) -> Result<ProfileCommand, String> {
+    let usage = || "usage: /profile [show <peer|nick> | set <field> <value> | clear <field>]";
+    let field = |name: &str| {
+        ProfileField::parse(name)
+            .ok_or_else(|| format!("unknown field {name:?}, try name, pronouns, bio or avatar"))
+    };
+    match split_word(args) {
+        ("", _) => Ok(ProfileCommand::Show(None)),
+        ("show", "") => Ok(ProfileCommand::Show(None)),
+        ("show", target) => Ok(ProfileCommand::Show(Some(target.to_string()))),
+        ("set", rest) => match split_word(rest) {
+            ("", _) | (_, "") => Err(usage().to_string()),
+            (name, value) => Ok(ProfileCommand::Set {
+                field: field(name)?,
+                value: unquote(value).to_string(),
+            }),
+        },
+        ("clear", name) if !name.is_empty() => field(name).map(ProfileCommand::Clear),
+        _ => Err(usage().to_string()),
+    }
+}
+
+/// Strip one pair of surrounding double quotes, so `/profile set bio "a b"` sets `a b`.
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+fn parse_report(args: &str) -> Result<UserCommand, String> {
+    let usage = "usage: /report <message id|last from <nick>> [reason]";
+    let (target, rest) = parse_report_target(args).ok_or(usage)?;
+    Ok(UserCommand::Report {
+        target,
+        reason: rest.to_string(),
+    })
+}
+
+fn parse_spam(args: &str) -> Result<UserCommand, String> {
+    let usage = "usage: /spam <message id|last from <nick>>";
+    match parse_report_target(args) {
+        Some((target, "")) => Ok(UserCommand::Spam(target)),
+        _ => Err(usage.to_string()),
+    }
+}
+
+fn parse_share_key(args: &str) -> Result<UserCommand, String> {
+    let usage = "usage: /share-key <threshold> <peer|nick>...";
+    let (threshold, rest) = split_word(args);
+    let threshold = threshold.parse().map_err(|_| usage.to_string())?;
+    let holders: Vec<String> = rest.split_whitespace().map(str::to_string).collect();
+    if holders.is_empty() {
+        return Err(usage.to_string());
+    }
+    Ok(UserCommand::ShareKey { threshold, holders })
+}
+
+// The message a `/report` or `/spam` is about, and what follows it.
+fn parse_report_target(args: &str) -> Option<(ReportTarget, &str)> {
+    match split_word(args) {
+        ("", _) => None,
+        ("last", rest) => match split_word(rest) {
+            ("from", rest) => match split_word(rest) {
+                ("", _) => None,
+                (peer, rest) => Some((ReportTarget::LastFrom(peer.to_string()), rest)),
+            },
+            _ => None,
+        },
+        (id, rest) => Some((ReportTarget::Id(id.to_string()), rest)),
+    }
+}
+
+/// Split off the first whitespace-separated word.
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (s, ""),
+    }
+}
+
+/// Parse a PeerId followed by optional free text.
+fn peer_arg(args: &str) -> Result<(PeerId, String), String> {
+    let (peer, rest) = split_word(args);
+    if peer.is_empty() {
+        return Err("missing peer id".to_string());
+    }
+    let peer = peer
+        .parse()
+        .map_err(|_| format!("invalid peer id {peer:?}"))?;
+    Ok((peer, rest.to_string()))
+}
+
+/// Parse a numeric entry id (an optional leading `#` is accepted).
+fn entry_arg(args: &str) -> Result<u64, String> {
+    let (entry, _) = split_word(args);
+    entry
+        .trim_start_matches('#')
+        .parse()
+        .map_err(|_| format!("invalid entry {entry:?}"))
+}
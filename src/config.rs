@@ -0,0 +1,140 @@
+// Settings persisted between runs in a JSON config file.
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    autoban::TempBan,
+    bans::{BanList, BanOrigin, BanRecord, BanScope},
+    contacts::Contacts,
+    dnd::DoNotDisturb,
+    error::ConfigError,
+    filter::TopicFilter,
+    hooks::HookSettings,
+    irc::IrcSettings,
+    keyshare::KeptShare,
+    mqtt::MqttSettings,
+    profile::Profile,
+    room::RoomSettings,
+    topic_alias::AliasResolver,
+    verify::VerifiedPeer,
+    webhook::WebhookSettings,
+};
+
+/// Everything stored in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Display filters, keyed by topic name.
+    #[serde(default)]
+    pub filters: HashMap<String, TopicFilter>,
+    /// Moderators and banned peers, keyed by topic name.
+    #[serde(default)]
+    pub rooms: HashMap<String, RoomSettings>,
+    /// Blocks and bans of every kind, with where and until when they apply.
+    #[serde(default, skip_serializing_if = "BanList::is_empty")]
+    pub banlist: BanList,
+    /// Automatic bans saved by older versions, moved into `banlist` on startup.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bans: Vec<TempBan>,
+    /// Peers whose fingerprint the user confirmed with `/verify`.
+    #[serde(default)]
+    pub verified: Vec<VerifiedPeer>,
+    /// Our own profile, published to every room we join.
+    #[serde(default, skip_serializing_if = "Profile::is_empty")]
+    pub profile: Profile,
+    /// Do not disturb, while it is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dnd: Option<DoNotDisturb>,
+    /// Peers saved with `/contact add`.
+    #[serde(default, skip_serializing_if = "Contacts::is_empty")]
+    pub contacts: Contacts,
+    /// Shares of other peers' room keys dealt to us with `/share-key`, sealed to our identity.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_shares: Vec<KeptShare>,
+    /// Names the user gave peers with `/alias`, shown instead of their nicks. Never sent.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<PeerId, String>,
+    /// The IRC channel the node mirrors into the room, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub irc: Option<IrcSettings>,
+    /// Incoming webhooks rooms' messages are posted to, keyed by topic name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub webhooks: BTreeMap<String, WebhookSettings>,
+    /// Hooks posting messages to rooms over `--http`, keyed by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hooks: BTreeMap<String, HookSettings>,
+    /// The MQTT broker whose topics the node mirrors, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttSettings>,
+    /// The alias each room's chat topic travels under, as its moderators announced.
+    #[serde(default, skip_serializing_if = "AliasResolver::is_empty")]
+    pub topic_aliases: AliasResolver,
+}
+
+impl Config {
+    /// Read the config file, or start from defaults if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|source| ConfigError::Parse {
+                path: path.to_path_buf(),
+                source,
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(source) => Err(ConfigError::Read {
+                path: path.to_path_buf(),
+                source,
+            }),
+        }
+    }
+
+    /// Move bans saved by older versions into the ban list. Returns whether any moved.
+    pub fn migrate_bans(&mut self, now: u64) -> bool {
+        let mut moved = false;
+        for ban in self.bans.drain(..) {
+            self.banlist.insert(BanRecord::auto(&ban));
+            moved = true;
+        }
+        for (room, settings) in &self.rooms {
+            let scope = BanScope::Room(room.clone());
+            for peer in &settings.banned {
+                if !self.banlist.iter().any(|r| r.peer == *peer && r.scope == scope) {
+                    self.banlist.insert(BanRecord {
+                        peer: *peer,
+                        scope: scope.clone(),
+                        origin: BanOrigin::Manual,
+                        created_at: now,
+                        expires_at: None,
+                        reason: String::new(),
+                    });
+                    moved = true;
+                }
+            }
+        }
+        moved
+    }
+
+    /// Write the config file, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let contents = serde_json::to_string_pretty(self).expect("config always serializes");
+        path.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(path, contents))
+            .map_err(|source| ConfigError::Write {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/p2p-chat/config.json`, falling back to `~/.config`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("p2p-chat").join("config.json"))
+}
@@ -0,0 +1,94 @@
+// Which peers to disconnect first when the node nears its `--max-peers` limit.
+use std::cmp::Ordering;
+
+use libp2p::PeerId;
+
+/// Share of `--max-peers` above which low priority peers start being disconnected, in percent.
+pub const HIGH_WATER_PERCENT: usize = 90;
+
+/// Share of `--max-peers` that trimming brings the connection count back down to, in percent,
+/// so that every new connection doesn't trigger another eviction.
+pub const LOW_WATER_PERCENT: usize = 80;
+
+/// How much a connected peer is worth keeping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Outside our mesh with a negative score: the first to go.
+    Low,
+    /// Neither low nor high: only disconnected once no low priority peer is left.
+    Normal,
+    /// In our mesh and not scored negatively: never disconnected to make room.
+    High,
+}
+
+impl Priority {
+    /// The priority of a peer from its Gossipsub mesh membership and its peer score.
+    pub fn of(in_mesh: bool, score: f64) -> Self {
+        match (in_mesh, score < 0.0) {
+            (true, false) => Priority::High,
+            (false, true) => Priority::Low,
+            _ => Priority::Normal,
+        }
+    }
+}
+
+/// A connected peer as the connection manager sees it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectedPeer {
+    pub peer: PeerId,
+    /// Whether the peer is in our mesh for any topic.
+    pub in_mesh: bool,
+    /// The peer's Gossipsub score, or its ping score adjustment when scoring is off.
+    pub score: f64,
+}
+
+impl ConnectedPeer {
+    pub fn priority(&self) -> Priority {
+        Priority::of(self.in_mesh, self.score)
+    }
+}
+
+/// Picks the peers to disconnect when the node has nearly as many as `--max-peers` allows.
+///
+/// New connections beyond the limit itself are refused by `libp2p::connection_limits`. Once
+/// more than [`HIGH_WATER_PERCENT`] of the limit is connected, peers are disconnected down to
+/// [`LOW_WATER_PERCENT`], lowest priority and then lowest score first, so there is always
+/// room for a newcomer that may turn out more useful. High priority peers are never picked,
+/// even if that leaves the node above the low water mark.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionManager {
+    max_peers: usize,
+}
+
+impl ConnectionManager {
+    pub fn new(max_peers: usize) -> Self {
+        ConnectionManager { max_peers }
+    }
+
+    /// The most peers connected at once.
+    pub fn max_peers(&self) -> usize {
+        self.max_peers
+    }
+
+    /// The peers among `connected` to disconnect, if there are too many.
+    pub fn evictions(&self, connected: &[ConnectedPeer]) -> Vec<PeerId> {
+        if connected.len() * 100 <= self.max_peers * HIGH_WATER_PERCENT {
+            return Vec::new();
+        }
+        let target = self.max_peers * LOW_WATER_PERCENT / 100;
+        let mut candidates: Vec<&ConnectedPeer> = connected
+            .iter()
+            .filter(|peer| peer.priority() != Priority::High)
+            .collect();
+        candidates.sort_by(|a, b| {
+            a.priority()
+                .cmp(&b.priority())
+                .then(a.score.partial_cmp(&b.score).unwrap_or(Ordering::Equal))
+        });
+        candidates
+            .into_iter()
+            .take(connected.len().saturating_sub(target))
+            .map(|peer| peer.peer)
+            .collect()
+    }
+}
@@ -0,0 +1,127 @@
+// The user's contacts: peers saved with a label, notes and a favorite mark.
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::sanitize;
+
+/// Most contacts kept; `/contact add` refuses more.
+pub const MAX_CONTACTS: usize = 256;
+
+/// Longest note about a contact, in characters.
+pub const MAX_NOTES_CHARS: usize = 200;
+
+/// A peer the user saved with `/contact add`, stored in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub peer: PeerId,
+    /// Name the user gave the contact, usable wherever a peer or nick is.
+    pub label: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub notes: String,
+    /// Favorites are listed first.
+    #[serde(default)]
+    pub favorite: bool,
+    /// Unix time (seconds) we last heard from the contact, kept for when it is nowhere to be
+    /// seen this session.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<u64>,
+}
+
+/// The contacts saved in the config file, one per peer and label.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Contacts(Vec<Contact>);
+
+impl Contacts {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Add `peer` as `label`, or relabel it if it already is a contact. Labels are sanitized
+    /// and shortened like nicks.
+    pub fn add(&mut self, peer: PeerId, label: &str) -> Result<&Contact, String> {
+        let label = sanitize::nick(label);
+        if label.is_empty() {
+            return Err("the label is empty".to_string());
+        }
+        if self
+            .0
+            .iter()
+            .any(|c| c.peer != peer && c.label.eq_ignore_ascii_case(&label))
+        {
+            return Err(format!("another contact is already labeled {label:?}"));
+        }
+        let index = match self.0.iter().position(|c| c.peer == peer) {
+            Some(index) => {
+                self.0[index].label = label;
+                index
+            }
+            None if self.0.len() >= MAX_CONTACTS => {
+                return Err(format!("you already have {MAX_CONTACTS} contacts"));
+            }
+            None => {
+                self.0.push(Contact {
+                    peer,
+                    label,
+                    notes: String::new(),
+                    favorite: false,
+                    last_seen: None,
+                });
+                self.0.len() - 1
+            }
+        };
+        Ok(&self.0[index])
+    }
+
+    /// Remove `peer`, returning its contact if it was one.
+    pub fn remove(&mut self, peer: &PeerId) -> Option<Contact> {
+        let index = self.0.iter().position(|c| c.peer == *peer)?;
+        Some(self.0.remove(index))
+    }
+
+    pub fn get(&self, peer: &PeerId) -> Option<&Contact> {
+        self.0.iter().find(|c| c.peer == *peer)
+    }
+
+    pub fn get_mut(&mut self, peer: &PeerId) -> Option<&mut Contact> {
+        self.0.iter_mut().find(|c| c.peer == *peer)
+    }
+
+    /// The contact labeled `label`, ignoring case.
+    pub fn by_label(&self, label: &str) -> Option<&Contact> {
+        self.0.iter().find(|c| c.label.eq_ignore_ascii_case(label))
+    }
+
+    /// Set the notes about `peer`, or clear them with an empty string.
+    pub fn set_notes(&mut self, peer: &PeerId, notes: &str) -> Result<(), String> {
+        let notes = sanitize::line(notes);
+        if notes.chars().count() > MAX_NOTES_CHARS {
+            return Err(format!("notes are at most {MAX_NOTES_CHARS} characters"));
+        }
+        let contact = self.get_mut(peer).ok_or("not a contact")?;
+        contact.notes = notes;
+        Ok(())
+    }
+
+    /// Record that `peer` was heard from at `at`, returning whether that is news.
+    pub fn seen(&mut self, peer: &PeerId, at: u64) -> bool {
+        match self.get_mut(peer) {
+            Some(contact) if contact.last_seen.is_none_or(|seen| seen < at) => {
+                contact.last_seen = Some(at);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Favorites first, then by label.
+    pub fn sorted(&self) -> Vec<&Contact> {
+        let mut contacts: Vec<&Contact> = self.0.iter().collect();
+        contacts.sort_by_key(|c| (!c.favorite, c.label.to_lowercase()));
+        contacts
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Contact> {
+        self.0.iter()
+    }
+}
@@ -0,0 +1,184 @@
+// Message formats peers accept, advertised in Identify's agent version, and the one picked for
+// what we send them.
+use std::{collections::HashMap, fmt, sync::OnceLock};
+
+use libp2p::PeerId;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Peers whose accepted formats are remembered. Beyond this, a newly identified peer is left
+/// out and gets the default.
+pub const MAX_PEERS: usize = 4096;
+
+/// How a chat message is published, from the plainest to the richest.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ContentType {
+    /// The body alone, as the first versions published it. The nick and anything else the
+    /// message carries are lost.
+    #[serde(rename = "text/plain")]
+    PlainText,
+    /// The JSON [`ChatMessage`](crate::message::ChatMessage) with a plain text body.
+    #[serde(rename = "application/x-p2pchat-v1")]
+    Envelope,
+    /// The JSON envelope with a Markdown body.
+    #[serde(rename = "text/markdown")]
+    Markdown,
+}
+
+/// Formats this node reads, advertised to every peer.
+pub const ACCEPTED: [ContentType; 3] = [
+    ContentType::PlainText,
+    ContentType::Envelope,
+    ContentType::Markdown,
+];
+
+/// What peers that advertise nothing get: every p2p-chat node reads the envelope, while older
+/// ones don't advertise what they accept.
+pub const DEFAULT: ContentType = ContentType::Envelope;
+
+impl ContentType {
+    /// The media type naming the format.
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ContentType::PlainText => "text/plain",
+            ContentType::Envelope => "application/x-p2pchat-v1",
+            ContentType::Markdown => "text/markdown",
+        }
+    }
+
+    /// The format named by `mime`, if it is one of ours.
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        ACCEPTED.into_iter().find(|format| format.mime() == mime)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mime())
+    }
+}
+
+/// How our agent version starts, telling other chat nodes apart from other libp2p agents.
+pub const AGENT: &str = "p2p-chat/";
+
+/// The agent version announced with Identify, listing the formats we accept, as in
+/// `p2p-chat/0.1.0 Accept: [text/plain, application/x-p2pchat-v1, text/markdown]`.
+pub fn agent_version() -> String {
+    let accepted: Vec<&str> = ACCEPTED.iter().map(ContentType::mime).collect();
+    format!(
+        "{AGENT}{} Accept: [{}]",
+        env!("CARGO_PKG_VERSION"),
+        accepted.join(", ")
+    )
+}
+
+/// The formats a peer's agent version says it accepts, leaving out those we don't know. None
+/// if it says nothing about them.
+pub fn parse_accept(agent_version: &str) -> Option<Vec<ContentType>> {
+    let (_, list) = agent_version.split_once("Accept: [")?;
+    let (list, _) = list.split_once(']')?;
+    Some(
+        list.split(',')
+            .filter_map(|mime| ContentType::from_mime(mime.trim()))
+            .collect(),
+    )
+}
+
+/// The formats each identified peer accepts, to pick what to send them.
+#[derive(Debug, Default)]
+pub struct ContentTypeNegotiator {
+    peers: HashMap<PeerId, Vec<ContentType>>,
+}
+
+impl ContentTypeNegotiator {
+    /// Remember what `peer` accepts, from the agent version it announced. A peer naming none
+    /// of our formats is taken as saying nothing, and a chat node always reads the envelope,
+    /// whatever it says.
+    pub fn learn(&mut self, peer: PeerId, agent_version: &str) {
+        let accepted = parse_accept(agent_version).filter(|accepted| !accepted.is_empty());
+        match accepted {
+            Some(mut accepted)
+                if self.peers.len() < MAX_PEERS || self.peers.contains_key(&peer) =>
+            {
+                if agent_version.starts_with(AGENT) && !accepted.contains(&DEFAULT) {
+                    accepted.push(DEFAULT);
+                }
+                self.peers.insert(peer, accepted);
+            }
+            Some(_) => {}
+            None => {
+                self.peers.remove(&peer);
+            }
+        }
+    }
+
+    /// Forget a peer we are no longer connected to.
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    /// The formats `peer` advertised, if it did.
+    pub fn accepted(&self, peer: &PeerId) -> Option<&[ContentType]> {
+        self.peers.get(peer).map(Vec::as_slice)
+    }
+
+    /// The richest format `peer_id` reads: [`DEFAULT`] if it didn't say.
+    pub fn pick_format(&self, peer_id: PeerId) -> ContentType {
+        self.peers
+            .get(&peer_id)
+            .and_then(|accepted| accepted.iter().copied().max())
+            .unwrap_or(DEFAULT)
+    }
+
+    /// The format for a message published to all of `peers`: the richest all of them read.
+    /// Plain text loses the nick, attachments and everything else the message carries, so it
+    /// is only picked when none of them reads more; peers that read only plain text are
+    /// otherwise left out, rather than the others losing all that.
+    pub fn pick_for_all(&self, peers: impl IntoIterator<Item = PeerId>) -> ContentType {
+        let formats: Vec<ContentType> = peers
+            .into_iter()
+            .map(|peer| self.pick_format(peer))
+            .collect();
+        let richer = formats
+            .iter()
+            .copied()
+            .filter(|format| *format != ContentType::PlainText)
+            .min();
+        match richer {
+            Some(format) => format,
+            None if formats.is_empty() => ContentType::Markdown,
+            None => ContentType::PlainText,
+        }
+    }
+}
+
+/// Markdown turned into plain text, for peers that don't read it and for showing it in the
+/// terminal: emphasis and code marks and heading hashes are dropped, and links are written
+/// as `text (url)`.
+pub fn to_plain(markdown: &str) -> String {
+    static RULES: OnceLock<Vec<(Regex, &str)>> = OnceLock::new();
+    let rules = RULES.get_or_init(|| {
+        [
+            (r"(?m)^#{1,6}[ \t]+", ""),
+            (r"\[([^\]\n]+)\]\(([^)\s]+)\)", "$1 ($2)"),
+            (r"`([^`\n]+)`", "$1"),
+            (r"\*\*([^*\n]+)\*\*", "$1"),
+            (r"__([^_\n]+)__", "$1"),
+            (r"~~([^~\n]+)~~", "$1"),
+            (r"\*([^*\s](?:[^*\n]*[^*\s])?)\*", "$1"),
+        ]
+        .into_iter()
+        .map(|(pattern, replacement)| {
+            (
+                Regex::new(pattern).expect("the Markdown patterns are valid"),
+                replacement,
+            )
+        })
+        .collect()
+    });
+    rules
+        .iter()
+        .fold(markdown.to_string(), |text, (regex, replacement)| {
+            regex.replace_all(&text, *replacement).into_owned()
+        })
+}
@@ -0,0 +1,81 @@
+// Control messages exchanged between nodes on a dedicated Gossipsub topic.
+use libp2p::gossipsub;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blocklist::BlocklistUpdate,
+    decision::{AggregateDecision, PartialSig},
+    identity::SignedRotation,
+    invite::Join,
+    node,
+    profile::Profile,
+    report::Report,
+    room::Moderation,
+    signed::Signed,
+    topic_alias::TopicAlias,
+};
+
+/// Every control message is signed by the node that authored it.
+pub type SignedControl = Signed<ControlMessage>;
+
+/// Messages that configure or moderate the chat rather than being displayed as chat.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlMessage {
+    /// A trusted peer added or removed a blocklist entry.
+    BlocklistUpdate(BlocklistUpdate),
+    /// A room moderator kicked or banned a peer.
+    Moderation(Moderation),
+    /// A moderator proposes a room ban that needs a quorum of moderators, signed by the
+    /// proposer.
+    Proposal {
+        moderation: Moderation,
+        partial: PartialSig,
+    },
+    /// A moderator signs a proposal.
+    Approval(PartialSig),
+    /// A quorum of moderators signed a proposal.
+    Decision {
+        moderation: Moderation,
+        decision: AggregateDecision,
+    },
+    /// A peer presents its invite to an invite-only room.
+    Join(Join),
+    /// A peer moved to a new identity key; the statement is signed by its old key.
+    Rotation(SignedRotation),
+    /// A member reports a message to the room's moderators.
+    Report(Box<Report>),
+    /// A peer is shutting down and leaving the room; its final presence.
+    Leave { room: String },
+    /// A peer's periodic heartbeat, saying it is still in the room, when to expect the next,
+    /// whether its user is away or doesn't want to be disturbed, and the user's status line.
+    /// Sent right away when any of those changes.
+    Presence {
+        room: String,
+        interval: u64,
+        #[serde(default)]
+        away: bool,
+        #[serde(default)]
+        dnd: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        status: Option<String>,
+    },
+    /// A peer's profile, sent to the room when it joins and whenever it changes.
+    Profile { room: String, profile: Profile },
+    /// A room moderator moved the room's chat messages to a new alias, or tells a newcomer
+    /// which one they travel under.
+    TopicAlias(TopicAlias),
+    /// A peer received these chat messages, so their authors know they got through. A few of
+    /// the peers in the room send one for each message.
+    Receipt { room: String, ids: Vec<String> },
+}
+
+/// The topic that carries control messages for the default chat topic.
+pub fn control_topic() -> gossipsub::IdentTopic {
+    control_topic_for(node::default_topic().hash().as_str())
+}
+
+/// The topic that carries control messages for the room on `topic`.
+pub fn control_topic_for(topic: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{topic}/_control"))
+}
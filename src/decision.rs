@@ -0,0 +1,333 @@
+// Room bans decided by a quorum of moderators. Each moderator signs the proposal's hash on its
+// own, and once enough have, their signatures are put together in one decision that members
+// check against the room's moderators before honoring it.
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+};
+
+use libp2p::{identity::Keypair, PeerId};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{error::CryptoError, room::Moderation, signed};
+
+/// Proposals waiting for signatures. A proposal beyond this is refused until
+/// [`Decisions::expire`] drops some.
+pub const MAX_PENDING: usize = 256;
+
+/// Decided proposals remembered, so a decision isn't applied twice. Beyond this, the oldest
+/// is forgotten.
+pub const MAX_DECIDED: usize = 4096;
+
+/// Seconds after an action is proposed that a decision on it is still honored. Decided
+/// proposals aren't remembered between runs, so this is what keeps an old decision from being
+/// replayed after a restart.
+pub const MAX_AGE: u64 = 7 * 24 * 60 * 60;
+
+/// Hex digits of a proposal's hash shown as its id, as in `/approve <id>`.
+pub const ID_LEN: usize = 8;
+
+/// Prefix of what moderators sign, so a partial signature can't be replayed as anything else.
+const SIGNING_PREFIX: &[u8] = b"p2p-chat-decision:";
+
+/// The hash of a proposed action, which each moderator signs.
+pub fn proposal_hash(moderation: &Moderation) -> [u8; 32] {
+    let json = serde_json::to_vec(moderation).expect("moderation actions always serialize");
+    Sha256::new()
+        .chain_update(SIGNING_PREFIX)
+        .chain_update(json)
+        .finalize()
+        .into()
+}
+
+/// Check that a decision on `moderation` may still be honored at `now`: the action is at
+/// most [`MAX_AGE`] old, and was taken after `lifted_at`, when its target's ban was last
+/// lifted in the room.
+pub fn check_fresh(
+    moderation: &Moderation,
+    lifted_at: Option<u64>,
+    now: u64,
+) -> Result<(), DecisionError> {
+    let lifted = lifted_at.is_some_and(|lifted_at| moderation.timestamp <= lifted_at);
+    if lifted || now.saturating_sub(moderation.timestamp) > MAX_AGE {
+        return Err(DecisionError::Stale);
+    }
+    Ok(())
+}
+
+/// The id a proposal is shown with: the start of its hash in hex.
+pub fn short_id(proposal_hash: &[u8; 32]) -> String {
+    hex::encode(proposal_hash)[..ID_LEN].to_string()
+}
+
+/// One moderator's signature of a proposal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PartialSig {
+    #[serde(with = "hex")]
+    pub proposal_hash: [u8; 32],
+    #[serde(with = "hex")]
+    pub sig: Vec<u8>,
+}
+
+impl PartialSig {
+    /// Sign `proposal_hash` with `keypair`.
+    pub fn sign(keypair: &Keypair, proposal_hash: [u8; 32]) -> Result<Self, CryptoError> {
+        Ok(PartialSig {
+            proposal_hash,
+            sig: keypair.sign(&signing_input(&proposal_hash))?,
+        })
+    }
+
+    /// Check that `signer` signed the proposal.
+    pub fn verify(&self, signer: &PeerId) -> Result<(), DecisionError> {
+        check_signature(&self.proposal_hash, signer, &self.sig)
+    }
+}
+
+/// Partial signatures of a quorum of moderators, put together: members check it instead of
+/// each signature arriving on its own.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AggregateDecision {
+    #[serde(with = "hex")]
+    pub proposal_hash: [u8; 32],
+    /// Each signer's signature in the order of `signers`, after its length in two bytes.
+    #[serde(with = "hex")]
+    pub agg_sig: Vec<u8>,
+    pub signers: Vec<PeerId>,
+}
+
+impl AggregateDecision {
+    /// Put the partial signatures of `signers` together.
+    pub fn aggregate(proposal_hash: [u8; 32], signatures: &[(PeerId, Vec<u8>)]) -> Self {
+        let mut agg_sig = Vec::new();
+        for (_, sig) in signatures {
+            let len = u16::try_from(sig.len()).expect("signatures are short");
+            agg_sig.extend_from_slice(&len.to_be_bytes());
+            agg_sig.extend_from_slice(sig);
+        }
+        AggregateDecision {
+            proposal_hash,
+            agg_sig,
+            signers: signatures.iter().map(|(signer, _)| *signer).collect(),
+        }
+    }
+
+    /// Check that the decision is on `moderation` and signed by at least `quorum` of
+    /// `moderators`, each once.
+    pub fn verify(
+        &self,
+        moderation: &Moderation,
+        moderators: &[PeerId],
+        quorum: usize,
+    ) -> Result<(), DecisionError> {
+        if self.proposal_hash != proposal_hash(moderation) {
+            return Err(DecisionError::WrongProposal);
+        }
+        let signatures = self.signatures().ok_or(DecisionError::Malformed)?;
+        let mut seen = HashSet::new();
+        for (signer, sig) in self.signers.iter().zip(signatures) {
+            if !seen.insert(signer) {
+                return Err(DecisionError::DuplicateSigner(*signer));
+            }
+            if !moderators.contains(signer) {
+                return Err(DecisionError::NotModerator(*signer));
+            }
+            check_signature(&self.proposal_hash, signer, sig)?;
+        }
+        if self.signers.len() < quorum {
+            return Err(DecisionError::TooFewSigners {
+                signers: self.signers.len(),
+                quorum,
+            });
+        }
+        Ok(())
+    }
+
+    // The signatures in `agg_sig`, if there is exactly one for each signer
+    fn signatures(&self) -> Option<Vec<&[u8]>> {
+        let mut signatures = Vec::with_capacity(self.signers.len());
+        let mut rest = self.agg_sig.as_slice();
+        while !rest.is_empty() {
+            let (len, tail) = rest.split_at_checked(2)?;
+            let len = u16::from_be_bytes([len[0], len[1]]);
+            let (sig, tail) = tail.split_at_checked(len.into())?;
+            signatures.push(sig);
+            rest = tail;
+        }
+        (signatures.len() == self.signers.len()).then_some(signatures)
+    }
+}
+
+/// Reasons a partial signature or a decision is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecisionError {
+    /// The decision's hash isn't that of the action it came with.
+    WrongProposal,
+    /// There isn't one signature for each signer.
+    Malformed,
+    /// No proposal with this hash is waiting for signatures.
+    UnknownProposal,
+    /// Too many proposals are waiting for signatures already.
+    Full,
+    /// The action is older than [`MAX_AGE`], or than the last time its target's ban was
+    /// lifted.
+    Stale,
+    NotModerator(PeerId),
+    DuplicateSigner(PeerId),
+    /// The signer's public key can't be told from its PeerId (RSA keys).
+    UnknownKey(PeerId),
+    BadSignature(PeerId),
+    TooFewSigners {
+        signers: usize,
+        quorum: usize,
+    },
+}
+
+impl fmt::Display for DecisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecisionError::WrongProposal => write!(f, "the decision is on another proposal"),
+            DecisionError::Malformed => write!(f, "malformed aggregate signature"),
+            DecisionError::UnknownProposal => write!(f, "no such proposal"),
+            DecisionError::Full => write!(f, "too many proposals are open"),
+            DecisionError::Stale => write!(f, "the action is too old to honor"),
+            DecisionError::NotModerator(peer) => write!(f, "{peer} is not a moderator"),
+            DecisionError::DuplicateSigner(peer) => write!(f, "{peer} signed twice"),
+            DecisionError::UnknownKey(peer) => write!(f, "the key of {peer} is unknown"),
+            DecisionError::BadSignature(peer) => write!(f, "the signature of {peer} is invalid"),
+            DecisionError::TooFewSigners { signers, quorum } => {
+                write!(f, "signed by {signers} moderators, {quorum} needed")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecisionError {}
+
+/// A proposal waiting for signatures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pending {
+    pub moderation: Moderation,
+    pub proposer: PeerId,
+    /// Partial signatures collected so far, in the order they came.
+    pub signatures: Vec<(PeerId, Vec<u8>)>,
+}
+
+/// Proposals waiting for a quorum, and the hashes of those decided.
+#[derive(Debug, Default)]
+pub struct Decisions {
+    pending: HashMap<[u8; 32], Pending>,
+    decided: HashSet<[u8; 32]>,
+    // The decided hashes, oldest first
+    decided_order: VecDeque<[u8; 32]>,
+}
+
+impl Decisions {
+    /// Wait for signatures on `moderation`, proposed by `proposer`, and return its hash. A
+    /// proposal already waiting is kept as it is.
+    pub fn propose(
+        &mut self,
+        proposer: PeerId,
+        moderation: Moderation,
+    ) -> Result<[u8; 32], DecisionError> {
+        let hash = proposal_hash(&moderation);
+        if self.pending.contains_key(&hash) || self.decided.contains(&hash) {
+            return Ok(hash);
+        }
+        if self.pending.len() >= MAX_PENDING {
+            return Err(DecisionError::Full);
+        }
+        self.pending.insert(
+            hash,
+            Pending {
+                moderation,
+                proposer,
+                signatures: Vec::new(),
+            },
+        );
+        Ok(hash)
+    }
+
+    /// Add `signer`'s partial signature to its proposal, returning how many it has. The caller
+    /// checks that the signer is a moderator.
+    pub fn sign(&mut self, signer: PeerId, partial: &PartialSig) -> Result<usize, DecisionError> {
+        let pending = self
+            .pending
+            .get_mut(&partial.proposal_hash)
+            .ok_or(DecisionError::UnknownProposal)?;
+        partial.verify(&signer)?;
+        if !pending.signatures.iter().any(|(s, _)| *s == signer) {
+            pending.signatures.push((signer, partial.sig.clone()));
+        }
+        Ok(pending.signatures.len())
+    }
+
+    /// The proposal with this hash, if it is waiting for signatures.
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&Pending> {
+        self.pending.get(hash)
+    }
+
+    /// The proposal whose id starts with `id`, if exactly one does.
+    pub fn find(&self, id: &str) -> Option<[u8; 32]> {
+        let id = id.to_ascii_lowercase();
+        let mut matches = self
+            .pending
+            .keys()
+            .filter(|hash| hex::encode(hash).starts_with(&id));
+        match (matches.next(), matches.next()) {
+            (Some(hash), None) if !id.is_empty() => Some(*hash),
+            _ => None,
+        }
+    }
+
+    /// Drop the proposals that could no longer be honored at `now`: those older than
+    /// [`MAX_AGE`], and those whose target was let back into the room since, as `lifted_at`
+    /// tells. Returns how many were dropped.
+    pub fn expire(&mut self, now: u64, lifted_at: impl Fn(&Moderation) -> Option<u64>) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|_, pending| {
+            check_fresh(&pending.moderation, lifted_at(&pending.moderation), now).is_ok()
+        });
+        before - self.pending.len()
+    }
+
+    /// Proposals waiting for signatures.
+    pub fn pending(&self) -> impl Iterator<Item = (&[u8; 32], &Pending)> {
+        self.pending.iter()
+    }
+
+    pub fn is_decided(&self, hash: &[u8; 32]) -> bool {
+        self.decided.contains(hash)
+    }
+
+    /// Record the proposal with this hash as decided, returning it if it was waiting.
+    pub fn decide(&mut self, hash: [u8; 32]) -> Option<Pending> {
+        if self.decided.insert(hash) {
+            self.decided_order.push_back(hash);
+            if self.decided_order.len() > MAX_DECIDED {
+                if let Some(oldest) = self.decided_order.pop_front() {
+                    self.decided.remove(&oldest);
+                }
+            }
+        }
+        self.pending.remove(&hash)
+    }
+}
+
+/// The exact bytes a moderator signs.
+fn signing_input(proposal_hash: &[u8; 32]) -> Vec<u8> {
+    [SIGNING_PREFIX, proposal_hash].concat()
+}
+
+fn check_signature(
+    proposal_hash: &[u8; 32],
+    signer: &PeerId,
+    sig: &[u8],
+) -> Result<(), DecisionError> {
+    let key = signed::peer_public_key(signer).ok_or(DecisionError::UnknownKey(*signer))?;
+    if !key.verify(&signing_input(proposal_hash), sig) {
+        return Err(DecisionError::BadSignature(*signer));
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+// File writes done on a thread of their own, so a slow disk can't stall the event loop.
+use std::{sync::mpsc, thread};
+
+use tokio::sync::oneshot;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs file writes one at a time, in the order they were queued, on a background thread.
+/// Dropping it waits for the writes still queued.
+#[derive(Debug)]
+pub struct DiskWriter {
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DiskWriter {
+    pub fn spawn() -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let thread = thread::Builder::new()
+            .name("disk".to_string())
+            .spawn(move || {
+                for job in queue {
+                    job();
+                }
+            })
+            .expect("the disk thread starts");
+        DiskWriter {
+            jobs: Some(jobs),
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue a write, or anything else that must come after the writes queued before it, like
+    /// reading the file back. It reports its own errors.
+    pub fn submit(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send(Box::new(job));
+        }
+    }
+
+    /// Wait until the writes queued so far are done.
+    pub async fn flush(&self) {
+        let (done, written) = oneshot::channel();
+        self.submit(move || {
+            let _ = done.send(());
+        });
+        let _ = written.await;
+    }
+}
+
+impl Drop for DiskWriter {
+    fn drop(&mut self) {
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,42 @@
+// Do not disturb: connected and logging, but shown to peers as not to be disturbed.
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+
+/// Do not disturb as set with `/dnd`, saved in the config so it outlives restarts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DoNotDisturb {
+    /// Unix time at which it ends by itself, or `None` to last until `/dnd off`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+}
+
+impl DoNotDisturb {
+    /// On until `/dnd off`.
+    pub fn indefinite() -> Self {
+        DoNotDisturb { until: None }
+    }
+
+    /// On for `secs` seconds from `now`.
+    pub fn for_secs(secs: u64, now: u64) -> Self {
+        DoNotDisturb {
+            until: Some(now.saturating_add(secs)),
+        }
+    }
+
+    /// Whether a timed window has run out by `now`.
+    pub fn is_over(&self, now: u64) -> bool {
+        self.until.is_some_and(|until| now >= until)
+    }
+
+    /// How long it lasts, such as `until /dnd off` or `for another 44m`.
+    pub fn describe(&self, now: u64) -> String {
+        match self.until {
+            Some(until) => format!(
+                "for another {}",
+                clock::format_duration(until.saturating_sub(now))
+            ),
+            None => "until /dnd off".to_string(),
+        }
+    }
+}
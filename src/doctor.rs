@@ -0,0 +1,444 @@
+// Checks of the environment the node runs in, from `p2p-chat doctor` and `/doctor`: sockets,
+// multicast, the clock, the config directory and the relay.
+use std::{
+    collections::VecDeque,
+    fmt, fs,
+    io::{self, Read},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use libp2p::{multiaddr::Protocol, Multiaddr};
+
+use crate::{cli::Cli, clock, config};
+
+/// Longest a probe waits for an answer.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where the clock is checked when no peers' clocks are at hand.
+pub const NTP_SERVER: &str = "pool.ntp.org:123";
+
+/// The multicast group mDNS announces peers on.
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// Clock offset, in seconds, worth a warning: messages show up with odd times.
+pub const CLOCK_WARN_SECS: i64 = 5;
+
+/// Clock offset, in seconds, that breaks things: invites, bans and heartbeats are judged by
+/// the clock.
+pub const CLOCK_FAIL_SECS: i64 = 60;
+
+/// Peers' message timestamps kept to compare our clock with.
+pub const MAX_CLOCK_SAMPLES: usize = 64;
+
+/// Timestamps needed before peers' clocks are trusted over an NTP server.
+pub const MIN_CLOCK_SAMPLES: usize = 3;
+
+// Seconds from the NTP epoch (1900) to the Unix epoch
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// How a check went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    /// Something works less well than it could, but the node can still chat.
+    Warn,
+    /// Something the node needs is broken.
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Status::Pass => "pass",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        })
+    }
+}
+
+/// The outcome of one check, with what to do about it when it didn't pass.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    pub hint: Option<&'static str>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Check {
+            name,
+            status: Status::Warn,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>, hint: &'static str) -> Self {
+        Check {
+            name,
+            status: Status::Fail,
+            detail: detail.into(),
+            hint: Some(hint),
+        }
+    }
+}
+
+/// Every check that was run, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn count(&self, status: Status) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == status)
+            .count()
+    }
+
+    /// Whether any check failed.
+    pub fn has_failures(&self) -> bool {
+        self.count(Status::Fail) > 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(
+                f,
+                "[doctor] {}  {}: {}",
+                check.status, check.name, check.detail
+            )?;
+            if let Some(hint) = check.hint {
+                writeln!(f, "[doctor]       {hint}")?;
+            }
+        }
+        write!(
+            f,
+            "[doctor] {} passed, {} warnings, {} failed",
+            self.count(Status::Pass),
+            self.count(Status::Warn),
+            self.count(Status::Fail)
+        )
+    }
+}
+
+/// What to check.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// The directory of the config file, where the board, tasks and audit log go as well.
+    pub config_dir: Option<PathBuf>,
+    /// The relay of `--relay-server`, to dial.
+    pub relay: Option<Multiaddr>,
+    /// Whether mDNS discovery is on.
+    pub mdns: bool,
+    /// How far our clock is behind peers' clocks, in seconds, when enough of them were seen.
+    pub peer_offset: Option<i64>,
+    /// The server to ask for the time otherwise, if any.
+    pub ntp_server: Option<String>,
+}
+
+impl Settings {
+    /// The checks for a node started with `cli`, with the clock checked against
+    /// [`NTP_SERVER`].
+    pub fn from_cli(cli: &Cli) -> Self {
+        Settings {
+            config_dir: cli
+                .config
+                .clone()
+                .or_else(config::default_path)
+                .map(|path| config_dir(&path)),
+            relay: cli.relay_server.clone(),
+            mdns: !cli.no_mdns,
+            peer_offset: None,
+            ntp_server: Some(NTP_SERVER.to_string()),
+        }
+    }
+}
+
+/// The directory a config file at `path` is in.
+pub fn config_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Run every check. Probes block for up to [`PROBE_TIMEOUT`] each, so this belongs on a
+/// thread of its own rather than the event loop.
+pub fn run(settings: &Settings) -> Report {
+    let mut checks = vec![check_tcp(), check_udp()];
+    checks.push(match settings.mdns {
+        true => check_multicast(),
+        false => Check::pass("mDNS", "not used (--no-mdns)"),
+    });
+    checks.push(check_clock(settings));
+    if let Some(dir) = &settings.config_dir {
+        checks.push(check_config_dir(dir));
+    }
+    if let Some(relay) = &settings.relay {
+        checks.push(check_relay(relay));
+    }
+    Report { checks }
+}
+
+/// Whether a TCP listener can be bound and accepts a connection over loopback.
+pub fn check_tcp() -> Check {
+    const HINT: &str = "the node can't accept TCP connections; check that this process may \
+                        open sockets and that no firewall rule blocks listening";
+    let probe = || -> io::Result<u16> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let port = listener.local_addr()?.port();
+        TcpStream::connect_timeout(
+            &SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            PROBE_TIMEOUT,
+        )?;
+        listener.accept()?;
+        Ok(port)
+    };
+    match probe() {
+        Ok(port) => Check::pass(
+            "TCP",
+            format!("listened on port {port} and accepted a connection"),
+        ),
+        Err(e) => Check::fail("TCP", format!("listener test failed: {e}"), HINT),
+    }
+}
+
+/// Whether UDP sockets, which QUIC runs on, can be bound and exchange a datagram.
+pub fn check_udp() -> Check {
+    const HINT: &str = "QUIC needs UDP; the node still listens on TCP, so peers can connect \
+                        over that instead";
+    let probe = || -> io::Result<u16> {
+        let listener = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        let port = listener.local_addr()?.port();
+        listener.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        sender.send_to(b"p2p-chat doctor", (Ipv4Addr::LOCALHOST, port))?;
+        let mut buf = [0; 64];
+        listener.recv_from(&mut buf)?;
+        Ok(port)
+    };
+    match probe() {
+        Ok(port) => Check::pass("UDP (QUIC)", format!("port {port} received a datagram")),
+        Err(e) => Check::warn("UDP (QUIC)", format!("UDP test failed: {e}"), HINT),
+    }
+}
+
+/// Whether a probe sent to the mDNS multicast group comes back to us.
+pub fn check_multicast() -> Check {
+    const HINT: &str = "mDNS won't find peers on this network; allow UDP multicast to \
+                        224.0.0.251:5353, or connect through --relay-server";
+    let nonce = format!("p2p-chat doctor {}", process::id());
+    let probe = || -> io::Result<()> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+        let port = socket.local_addr()?.port();
+        socket.send_to(nonce.as_bytes(), (MDNS_GROUP, port))?;
+        let mut buf = [0; 64];
+        loop {
+            let (len, _) = socket.recv_from(&mut buf)?;
+            if buf[..len] == *nonce.as_bytes() {
+                return Ok(());
+            }
+        }
+    };
+    match probe() {
+        Ok(()) => Check::pass("mDNS", format!("a probe to {MDNS_GROUP} came back")),
+        Err(e) => Check::warn("mDNS", format!("multicast probe failed: {e}"), HINT),
+    }
+}
+
+/// Judge the clock by peers' timestamps when enough were seen, or else by an NTP server.
+pub fn check_clock(settings: &Settings) -> Check {
+    if let Some(offset) = settings.peer_offset {
+        return judge_clock(offset, "peers' clocks");
+    }
+    let Some(server) = &settings.ntp_server else {
+        return Check::pass(
+            "clock",
+            "not checked: no peers' clocks or NTP server to compare with",
+        );
+    };
+    match sntp_offset(server) {
+        Ok(offset) => judge_clock(offset, server),
+        Err(e) => Check::warn(
+            "clock",
+            format!("couldn't ask {server} for the time: {e}"),
+            "run /doctor once connected to peers to compare with their clocks instead",
+        ),
+    }
+}
+
+/// The clock check for a clock `offset` seconds behind `source` (ahead when negative).
+pub fn judge_clock(offset: i64, source: &str) -> Check {
+    const HINT: &str = "set the clock right or enable time sync (NTP); invites, bans and \
+                        heartbeats expire by it";
+    let detail = match offset {
+        0 => format!("in step with {source}"),
+        offset if offset > 0 => format!("{offset} s behind {source}"),
+        offset => format!("{} s ahead of {source}", -offset),
+    };
+    match offset.abs() {
+        skew if skew >= CLOCK_FAIL_SECS => Check::fail("clock", detail, HINT),
+        skew if skew >= CLOCK_WARN_SECS => Check::warn("clock", detail, HINT),
+        _ => Check::pass("clock", detail),
+    }
+}
+
+/// How many seconds our clock is behind the SNTP `server`'s.
+pub fn sntp_offset(server: &str) -> io::Result<i64> {
+    let address = server
+        .to_socket_addrs()?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no IPv4 address"))?;
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    // Version 4, client mode
+    let mut request = [0u8; 48];
+    request[0] = 0x23;
+    socket.send_to(&request, address)?;
+    let mut response = [0u8; 48];
+    let (len, _) = socket.recv_from(&mut response)?;
+    if len < 48 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "short answer"));
+    }
+    // The transmit timestamp's seconds
+    let seconds = u32::from_be_bytes([response[40], response[41], response[42], response[43]]);
+    let server_time = u64::from(seconds).saturating_sub(NTP_UNIX_OFFSET);
+    Ok(server_time as i64 - clock::unix_time() as i64)
+}
+
+/// Whether the config directory exists, or can be created, and only we can write to it.
+pub fn check_config_dir(dir: &Path) -> Check {
+    const HINT: &str = "the config, board, tasks and audit log can't be saved; fix the \
+                        directory's permissions or point --config somewhere writable";
+    let existing = dir.ancestors().find(|ancestor| ancestor.exists());
+    let Some(existing) = existing else {
+        return Check::fail(
+            "config directory",
+            format!("{} can't be created", dir.display()),
+            HINT,
+        );
+    };
+    let probe = existing.join(format!(".p2p-chat-doctor-{}", process::id()));
+    if let Err(e) = fs::write(&probe, b"").and_then(|()| fs::remove_file(&probe)) {
+        return Check::fail(
+            "config directory",
+            format!("{} isn't writable: {e}", existing.display()),
+            HINT,
+        );
+    }
+    // A directory still to be created gets our umask, whatever its parent allows
+    if existing == dir && is_writable_by_others(dir) {
+        return Check::warn(
+            "config directory",
+            format!("{} is writable by other users", existing.display()),
+            "others could change your config or identity key; run chmod go-w on it",
+        );
+    }
+    match existing == dir {
+        true => Check::pass("config directory", format!("{} is writable", dir.display())),
+        false => Check::pass(
+            "config directory",
+            format!(
+                "{} will be created in {}",
+                dir.display(),
+                existing.display()
+            ),
+        ),
+    }
+}
+
+#[cfg(unix)]
+fn is_writable_by_others(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o022 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_writable_by_others(_: &Path) -> bool {
+    false
+}
+
+/// Whether the relay answers on its TCP address. Other addresses aren't checked.
+pub fn check_relay(relay: &Multiaddr) -> Check {
+    const HINT: &str = "peers that can't reach you directly won't get through; check the \
+                        relay's address and that it is running";
+    let mut host = None;
+    let mut port = None;
+    for protocol in relay.iter() {
+        match protocol {
+            Protocol::Ip4(ip) => host = Some(ip.to_string()),
+            Protocol::Ip6(ip) => host = Some(ip.to_string()),
+            Protocol::Dns(name) | Protocol::Dns4(name) | Protocol::Dns6(name) => {
+                host = Some(name.to_string())
+            }
+            Protocol::Tcp(number) => port = Some(number),
+            _ => {}
+        }
+    }
+    let (Some(host), Some(port)) = (host, port) else {
+        return Check::pass("relay", format!("not checked: {relay} isn't a TCP address"));
+    };
+    let dial = || -> io::Result<()> {
+        let address = (host.as_str(), port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+        let mut stream = TcpStream::connect_timeout(&address, PROBE_TIMEOUT)?;
+        // A listening libp2p node opens with multistream-select; anything that accepted is enough
+        stream.set_read_timeout(Some(Duration::from_millis(200)))?;
+        let _ = stream.read(&mut [0; 1]);
+        Ok(())
+    };
+    match dial() {
+        Ok(()) => Check::pass("relay", format!("{host}:{port} accepted a connection")),
+        Err(e) => Check::fail("relay", format!("can't reach {host}:{port}: {e}"), HINT),
+    }
+}
+
+/// How far our clock is from peers', from the timestamps on their messages.
+#[derive(Debug, Default)]
+pub struct ClockSamples {
+    offsets: VecDeque<i64>,
+}
+
+impl ClockSamples {
+    /// Record a message written at `sent_at` that arrived at `received_at`, both Unix times.
+    pub fn record(&mut self, sent_at: u64, received_at: u64) {
+        if self.offsets.len() >= MAX_CLOCK_SAMPLES {
+            self.offsets.pop_front();
+        }
+        self.offsets.push_back(sent_at as i64 - received_at as i64);
+    }
+
+    /// The median offset, once [`MIN_CLOCK_SAMPLES`] were recorded: how many seconds our clock
+    /// is behind peers' clocks.
+    pub fn offset(&self) -> Option<i64> {
+        if self.offsets.len() < MIN_CLOCK_SAMPLES {
+            return None;
+        }
+        let mut sorted: Vec<i64> = self.offsets.iter().copied().collect();
+        sorted.sort_unstable();
+        Some(sorted[sorted.len() / 2])
+    }
+}
@@ -0,0 +1,483 @@
+// Delay-tolerant delivery for when there is no internet to gossip over: chat messages are carried
+// from node to node over short-range links and handed on at every encounter, the way Briar does
+// over Bluetooth, until they expire.
+//
+// Bluetooth is simulated. A directory stands in for the radio range: each node advertises itself
+// there with a Unix domain socket named after its PeerId, and nodes sharing the directory are in
+// range of each other. Meeting a neighbour, both sides swap the ids of the bundles they carry and
+// hand over the ones the other lacks.
+use std::{
+    collections::{HashSet, VecDeque},
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use libp2p::{
+    futures::{
+        future,
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        AsyncRead, AsyncWrite,
+    },
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{cli::Cli, clock, message::ChatMessage, runtime, signed::Signed};
+
+/// Bundles and events waiting between the node and the radio.
+pub const QUEUE: usize = 256;
+
+/// How many bundles a node carries by default; the oldest make room for new ones.
+pub const MAX_CARRIED: usize = 1024;
+
+/// How long a bundle is carried after it was written, at most.
+pub const BUNDLE_TTL: u64 = 24 * 60 * 60;
+
+/// How often the range is scanned for neighbours to meet.
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Longest an encounter may take before the link is dropped.
+pub const ENCOUNTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest line of an encounter: the ids of every bundle carried, or one bundle.
+pub const MAX_FRAME_BYTES: usize = 256 * 1024;
+
+/// A chat message as carried between nodes, signed by its author so carriers can't alter it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    /// Random, and the same at every carrier.
+    pub id: String,
+    /// Name of the room's topic.
+    pub room: String,
+    pub message: ChatMessage,
+    /// Unix time after which nobody carries it any more.
+    pub expires: u64,
+}
+
+impl Bundle {
+    /// A bundle of `message` for `room`, written at `now`. Attachments are left behind, since
+    /// they are fetched from their sender over the swarm.
+    pub fn new(room: &str, message: &ChatMessage, now: u64) -> Self {
+        Bundle {
+            id: Uuid::new_v4().to_string(),
+            room: room.to_string(),
+            message: ChatMessage {
+                attachment: None,
+                ..message.clone()
+            },
+            expires: now + BUNDLE_TTL,
+        }
+    }
+}
+
+/// Bundles are signed by their author.
+pub type SignedBundle = Signed<Bundle>;
+
+/// Where the simulated radio range is and how much a node carries, from `--dtn-mode`,
+/// `--dtn-range` and `--dtn-buffer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DtnSettings {
+    pub range: PathBuf,
+    pub capacity: usize,
+}
+
+impl DtnSettings {
+    pub fn from_cli(cli: &Cli) -> Option<Self> {
+        cli.dtn_mode.then(|| DtnSettings {
+            range: cli.dtn_range.clone().unwrap_or_else(default_range),
+            capacity: cli.dtn_buffer,
+        })
+    }
+}
+
+/// The range nodes on one machine share unless told otherwise.
+pub fn default_range() -> PathBuf {
+    std::env::temp_dir().join("p2p-chat-bluetooth")
+}
+
+/// The bundles a node carries, oldest first. Expired ones are dropped, then the oldest once
+/// there are as many as it can carry.
+#[derive(Debug)]
+pub struct Carrier {
+    bundles: VecDeque<(String, u64, SignedBundle)>,
+    ids: HashSet<String>,
+    capacity: usize,
+}
+
+impl Carrier {
+    pub fn new(capacity: usize) -> Self {
+        Carrier {
+            bundles: VecDeque::new(),
+            ids: HashSet::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Carry `signed`, whose content is `bundle`, unless it is carried already or expired at
+    /// `now`. An expiry further out than [`BUNDLE_TTL`] is cut short. Returns whether it is new.
+    pub fn carry(&mut self, signed: SignedBundle, bundle: &Bundle, now: u64) -> bool {
+        self.expire(now);
+        if bundle.expires <= now || self.ids.contains(&bundle.id) {
+            return false;
+        }
+        if self.bundles.len() >= self.capacity {
+            if let Some((oldest, _, _)) = self.bundles.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        let expires = bundle.expires.min(now + BUNDLE_TTL);
+        self.ids.insert(bundle.id.clone());
+        self.bundles.push_back((bundle.id.clone(), expires, signed));
+        true
+    }
+
+    /// Drop the bundles expired at `now`.
+    pub fn expire(&mut self, now: u64) {
+        let ids = &mut self.ids;
+        self.bundles.retain(|(id, expires, _)| {
+            let keep = *expires > now;
+            if !keep {
+                ids.remove(id);
+            }
+            keep
+        });
+    }
+
+    /// The ids of the bundles carried, oldest first.
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.bundles.iter().map(|(id, _, _)| id.as_str())
+    }
+
+    /// The bundles carried that aren't in `have`, oldest first.
+    pub fn missing(&self, have: &HashSet<String>) -> Vec<SignedBundle> {
+        self.bundles
+            .iter()
+            .filter(|(id, _, _)| !have.contains(id))
+            .map(|(_, _, signed)| signed.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bundles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bundles.is_empty()
+    }
+}
+
+// A line of an encounter: first each side's hello, then the bundles the other lacks, then done.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    Hello { peer: PeerId, ids: Vec<String> },
+    Bundle { bundle: SignedBundle },
+    Done,
+}
+
+/// What came of meeting a neighbour.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encounter {
+    /// The PeerId the neighbour gave. Nothing proves it, but every bundle is signed.
+    pub peer: PeerId,
+    /// Bundles handed to the neighbour.
+    pub sent: usize,
+    /// Bundles new to us, with their authors, now carried.
+    pub received: Vec<(PeerId, Bundle)>,
+    /// Bundles whose signature didn't check out, dropped.
+    pub forged: usize,
+}
+
+/// Meet a neighbour on `stream` as `local`: swap the ids of the bundles carried, hand over those
+/// it lacks and carry those it has that are new, at `now`. Both sides run the same exchange.
+pub async fn encounter<S>(
+    stream: S,
+    local: PeerId,
+    carrier: &mut Carrier,
+    now: u64,
+) -> Result<Encounter, String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = stream.split();
+    let mut reader = BufReader::new(reader.take(MAX_FRAME_BYTES as u64));
+    carrier.expire(now);
+    let ids = carrier.ids().map(str::to_string).collect();
+    send(&mut writer, &Frame::Hello { peer: local, ids }).await?;
+    let Frame::Hello { peer, ids } = receive(&mut reader).await? else {
+        return Err("the neighbour didn't say hello".to_string());
+    };
+    let outgoing = carrier.missing(&ids.into_iter().collect());
+    let sent = outgoing.len();
+
+    // Both sides write while they read, so neither waits on a buffer the other doesn't empty
+    let write = async {
+        for bundle in outgoing {
+            send(&mut writer, &Frame::Bundle { bundle }).await?;
+        }
+        send(&mut writer, &Frame::Done).await?;
+        writer.close().await.map_err(|e| e.to_string())
+    };
+    let limit = carrier.capacity;
+    let read = async {
+        let mut taken = Vec::new();
+        loop {
+            match receive(&mut reader).await? {
+                Frame::Bundle { bundle } if taken.len() < limit => taken.push(bundle),
+                Frame::Bundle { .. } => return Err(format!("over {limit} bundles")),
+                Frame::Done => return Ok(taken),
+                Frame::Hello { .. } => return Err("the neighbour said hello twice".to_string()),
+            }
+        }
+    };
+    let (written, taken) = future::join(write, read).await;
+    written?;
+
+    let mut received = Vec::new();
+    let mut forged = 0;
+    for signed in taken? {
+        match signed.verify() {
+            Ok((author, bundle)) => {
+                if carrier.carry(signed, &bundle, now) {
+                    received.push((author, bundle));
+                }
+            }
+            Err(_) => forged += 1,
+        }
+    }
+    Ok(Encounter {
+        peer,
+        sent,
+        received,
+        forged,
+    })
+}
+
+async fn send<W: AsyncWrite + Unpin>(writer: &mut W, frame: &Frame) -> Result<(), String> {
+    let mut line = serde_json::to_vec(frame).map_err(|e| e.to_string())?;
+    line.push(b'\n');
+    writer.write_all(&line).await.map_err(|e| e.to_string())
+}
+
+async fn receive<R>(reader: &mut BufReader<libp2p::futures::io::Take<R>>) -> Result<Frame, String>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = Vec::new();
+    reader
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(|e| e.to_string())?;
+    if !line.ends_with(b"\n") {
+        return Err(if reader.get_ref().limit() == 0 {
+            format!("a line over {MAX_FRAME_BYTES} bytes")
+        } else {
+            "the link closed".to_string()
+        });
+    }
+    // The limit is put back after every line, so no line runs on for ever
+    reader.get_mut().set_limit(MAX_FRAME_BYTES as u64);
+    serde_json::from_slice(&line).map_err(|e| format!("not a frame: {e}"))
+}
+
+/// What happened on the radio.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DtnEvent {
+    /// A neighbour was met: `sent` bundles handed on, `received` taken, `forged` dropped.
+    Met {
+        peer: PeerId,
+        sent: usize,
+        received: usize,
+        forged: usize,
+    },
+    /// A bundle new to us arrived, signed by `author`.
+    Received { author: PeerId, bundle: Bundle },
+    /// Meeting a neighbour failed.
+    Failed { peer: PeerId, error: String },
+}
+
+/// The simulated Bluetooth radio: advertises the node in the range, meets the neighbours found
+/// there and carries bundles between them, on a task of its own. Dropping it stops advertising.
+#[derive(Debug)]
+pub struct Radio {
+    settings: DtnSettings,
+    advertisement: PathBuf,
+    outgoing: mpsc::Sender<SignedBundle>,
+    incoming: mpsc::Receiver<DtnEvent>,
+    task: runtime::Task<()>,
+}
+
+impl Radio {
+    /// Advertise `local` in the range of `settings` and start meeting neighbours.
+    pub fn spawn(settings: DtnSettings, local: PeerId) -> io::Result<Self> {
+        let advertisement = advertisement(&settings.range, &local);
+        let listener = fs::create_dir_all(&settings.range)
+            // Left behind by an earlier run that didn't stop advertising
+            .and_then(|()| match fs::remove_file(&advertisement) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            })
+            .and_then(|()| runtime::listen_unix(&advertisement))
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("can't advertise at {}: {e}", advertisement.display()),
+                )
+            })?;
+        let (outgoing, queue) = mpsc::channel(QUEUE);
+        let (events, incoming) = mpsc::channel(QUEUE);
+        let task = runtime::spawn(run(listener, local, settings.clone(), queue, events));
+        Ok(Radio {
+            settings,
+            advertisement,
+            outgoing,
+            incoming,
+            task,
+        })
+    }
+
+    pub fn settings(&self) -> &DtnSettings {
+        &self.settings
+    }
+
+    /// The socket the node advertises itself with.
+    pub fn advertisement(&self) -> &Path {
+        &self.advertisement
+    }
+
+    /// Carry `bundle` to the neighbours met from now on. Fails while [`QUEUE`] bundles are
+    /// waiting for the radio.
+    pub fn carry(&self, bundle: SignedBundle) -> Result<(), String> {
+        self.outgoing
+            .try_send(bundle)
+            .map_err(|_| format!("{QUEUE} bundles are already waiting for the radio"))
+    }
+
+    /// The next thing that happened on the radio.
+    pub async fn next(&mut self) -> Option<DtnEvent> {
+        self.incoming.recv().await
+    }
+}
+
+impl Drop for Radio {
+    fn drop(&mut self) {
+        self.task.abort();
+        let _ = fs::remove_file(&self.advertisement);
+    }
+}
+
+/// The socket `peer` advertises itself with in `range`.
+pub fn advertisement(range: &Path, peer: &PeerId) -> PathBuf {
+    range.join(format!("{peer}.sock"))
+}
+
+// The neighbours advertising in `range` that `local` dials: those whose PeerId sorts after its
+// own, so two neighbours never dial each other at once and wait on each other.
+fn neighbours(range: &Path, local: &PeerId) -> Vec<(PeerId, PathBuf)> {
+    let Ok(entries) = fs::read_dir(range) else {
+        return Vec::new();
+    };
+    let local = local.to_string();
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path.file_name()?.to_str()?.strip_suffix(".sock")?;
+            let peer = name.parse().ok()?;
+            (*name > *local).then_some((peer, path))
+        })
+        .collect()
+}
+
+async fn run(
+    listener: runtime::UnixListener,
+    local: PeerId,
+    settings: DtnSettings,
+    mut queue: mpsc::Receiver<SignedBundle>,
+    events: mpsc::Sender<DtnEvent>,
+) {
+    let mut carrier = Carrier::new(settings.capacity);
+    let mut scan = runtime::interval(SCAN_INTERVAL);
+    loop {
+        tokio::select! {
+            Some(signed) = queue.recv() => {
+                if let Ok((_, bundle)) = signed.verify() {
+                    carrier.carry(signed, &bundle, clock::unix_time());
+                }
+            }
+            accepted = runtime::accept_unix(&listener) => match accepted {
+                Ok(stream) => {
+                    if meet(stream, None, local, &mut carrier, &events).await.is_err() {
+                        return;
+                    }
+                }
+                Err(e) => debug!("[dtn] a neighbour couldn't connect: {e}"),
+            },
+            _ = scan.tick() => {
+                for (peer, path) in neighbours(&settings.range, &local) {
+                    let stream = match runtime::connect_unix(&path).await {
+                        Ok(stream) => stream,
+                        // Nobody listens there any more
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            let _ = fs::remove_file(&path);
+                            continue;
+                        }
+                        Err(e) => {
+                            debug!("[dtn] can't reach {peer}: {e}");
+                            continue;
+                        }
+                    };
+                    if meet(stream, Some(peer), local, &mut carrier, &events).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Meet the neighbour on `stream`, expected to be `peer` when we dialed it, and tell the node.
+// Fails once the node is gone.
+async fn meet(
+    stream: runtime::UnixStream,
+    peer: Option<PeerId>,
+    local: PeerId,
+    carrier: &mut Carrier,
+    events: &mpsc::Sender<DtnEvent>,
+) -> Result<(), mpsc::error::SendError<DtnEvent>> {
+    let met = runtime::timeout(
+        ENCOUNTER_TIMEOUT,
+        encounter(stream, local, carrier, clock::unix_time()),
+    )
+    .await
+    .unwrap_or_else(|_| Err(format!("no encounter within {ENCOUNTER_TIMEOUT:?}")));
+    let encounter = match met {
+        Ok(encounter) => encounter,
+        Err(error) => {
+            // Who dialed us is only known from its hello
+            return match peer {
+                Some(peer) => events.send(DtnEvent::Failed { peer, error }).await,
+                None => {
+                    debug!("[dtn] a neighbour's encounter failed: {error}");
+                    Ok(())
+                }
+            };
+        }
+    };
+    events
+        .send(DtnEvent::Met {
+            peer: encounter.peer,
+            sent: encounter.sent,
+            received: encounter.received.len(),
+            forged: encounter.forged,
+        })
+        .await?;
+    for (author, bundle) in encounter.received {
+        events.send(DtnEvent::Received { author, bundle }).await?;
+    }
+    Ok(())
+}
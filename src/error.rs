@@ -0,0 +1,224 @@
+// Error types returned by the public API.
+use std::{convert::Infallible, error::Error, io, path::PathBuf, time::Duration};
+
+use libp2p::{gossipsub, identity, multiaddr, noise, swarm, tls, TransportError};
+use thiserror::Error;
+
+use crate::{invite::InviteError, proxy::ProxyError, psk::PskMismatch};
+
+/// Everything that can go wrong while setting up or running a chat node.
+#[derive(Debug, Error)]
+pub enum ChatError {
+    /// Listening on or dialing an address failed.
+    #[error("transport error: {0}")]
+    Transport(#[from] TransportError<io::Error>),
+    /// The swarm's network behaviour could not be built.
+    #[error("failed to set up the network behaviour: {0}")]
+    Behaviour(#[source] Box<dyn Error + Send + Sync>),
+    /// A message could not be published.
+    #[error("{}", describe_publish_error(.0))]
+    Gossipsub(#[from] gossipsub::PublishError),
+    /// Subscribing to a topic failed.
+    #[error("subscribe failed: {0}")]
+    Subscription(#[from] gossipsub::SubscriptionError),
+    #[error("invalid address: {0}")]
+    Multiaddr(#[from] multiaddr::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    /// The invite given with `--join-with` was refused.
+    #[error("cannot join: {0}")]
+    Invite(#[from] InviteError),
+    /// The node was started with `--no-publish` and publishes nothing.
+    #[error("read-only mode: nothing is published with --no-publish")]
+    ReadOnlyMode,
+    /// No peer to publish a queued message to turned up in time.
+    #[error("not sent, nobody joined the room within {} s. Send it again later", .0.as_secs())]
+    PublishTimedOut(Duration),
+    /// The node shut down before a queued message could be published.
+    #[error("not sent, the node shut down first")]
+    Shutdown,
+}
+
+// Lets `?` pass through builder steps that cannot fail.
+impl From<Infallible> for ChatError {
+    fn from(never: Infallible) -> Self {
+        match never {}
+    }
+}
+
+/// Why a message wasn't published, and what to do about it.
+pub fn describe_publish_error(error: &gossipsub::PublishError) -> String {
+    match error {
+        gossipsub::PublishError::InsufficientPeers => {
+            "not sent, no peers in the room yet. Wait for someone to join (see /peers) and send \
+             it again"
+                .to_string()
+        }
+        gossipsub::PublishError::MessageTooLarge => {
+            "not sent, the message is too large for the network. Shorten it or send it in parts"
+                .to_string()
+        }
+        gossipsub::PublishError::Duplicate => {
+            "not sent, the same message was just published. Change it to send it again".to_string()
+        }
+        gossipsub::PublishError::SigningError(e) => format!("not sent, signing failed: {e}"),
+        gossipsub::PublishError::TransformFailed(e) => format!("not sent, encoding failed: {e}"),
+    }
+}
+
+/// Why a dial failed, in words: for each address tried, whether the connection was refused,
+/// timed out or reached the wrong peer.
+pub fn describe_dial_error(error: &swarm::DialError) -> String {
+    match error {
+        swarm::DialError::Transport(attempts) => attempts
+            .iter()
+            .map(|(address, e)| format!("{address}: {}", describe_transport_error(e)))
+            .collect::<Vec<_>>()
+            .join("; "),
+        swarm::DialError::WrongPeerId { obtained, endpoint } => format!(
+            "{}: wrong peer id, the node there is {obtained}",
+            endpoint.get_remote_address()
+        ),
+        swarm::DialError::LocalPeerId { endpoint } => {
+            format!("{}: that is this node", endpoint.get_remote_address())
+        }
+        swarm::DialError::NoAddresses => "no known address for the peer".to_string(),
+        swarm::DialError::Denied { cause } => format!("refused by this node: {cause}"),
+        swarm::DialError::Aborted => "the attempt was aborted".to_string(),
+        swarm::DialError::DialPeerConditionFalse(_) => {
+            "already connected or connecting".to_string()
+        }
+    }
+}
+
+fn describe_transport_error(error: &TransportError<io::Error>) -> String {
+    match error {
+        TransportError::MultiaddrNotSupported(_) => {
+            "no enabled transport supports this address".to_string()
+        }
+        TransportError::Other(e) => describe_io_error(e),
+    }
+}
+
+// The reason at the bottom of a connection error. Transports wrap each other's errors, so
+// walk the chain for a refused connection, a timeout, a PSK mismatch or a failing proxy, and
+// fall back to the innermost message.
+fn describe_io_error(error: &io::Error) -> String {
+    let mut current: &(dyn Error + 'static) = error;
+    loop {
+        if current.is::<PskMismatch>() || current.is::<ProxyError>() {
+            return current.to_string();
+        }
+        // `io::Error::source` skips over a wrapped custom error, so look inside it explicitly
+        let next = match current.downcast_ref::<io::Error>() {
+            Some(e) => {
+                match e.kind() {
+                    io::ErrorKind::ConnectionRefused => {
+                        return "connection refused, nothing is listening there".to_string()
+                    }
+                    io::ErrorKind::TimedOut => return "timed out".to_string(),
+                    io::ErrorKind::ConnectionReset => {
+                        return "the connection was reset by the other side".to_string()
+                    }
+                    io::ErrorKind::HostUnreachable | io::ErrorKind::NetworkUnreachable => {
+                        return "unreachable from this network".to_string()
+                    }
+                    _ => {}
+                }
+                e.get_ref()
+                    .map(|inner| inner as &(dyn Error + 'static))
+                    .or_else(|| e.source())
+            }
+            None => current.source(),
+        };
+        match next {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+    // Some layers pass the reason on as text only
+    let message = current.to_string();
+    let lowercase = message.to_lowercase();
+    if lowercase.contains("connection refused") {
+        "connection refused, nothing is listening there".to_string()
+    } else if lowercase.contains("timeout") || lowercase.contains("timed out") {
+        "timed out".to_string()
+    } else {
+        message
+    }
+}
+
+/// [`ChatNode::connect_to`](crate::chat::ChatNode::connect_to) did not end in a connection.
+#[derive(Debug, Error)]
+pub enum DialError {
+    /// The address points at the local node.
+    #[error("refusing to dial our own peer id")]
+    SelfDial,
+    /// The swarm refused the dial or the connection attempt failed.
+    #[error(transparent)]
+    Swarm(#[from] swarm::DialError),
+    /// Neither a connection nor an error was reported in time.
+    #[error("no connection after {0:?}")]
+    Timeout(Duration),
+}
+
+/// Why a peer failed the challenge of a
+/// [`ConnectionAuthenticator`](crate::auth::ConnectionAuthenticator).
+#[derive(Debug, Error)]
+pub enum AuthError {
+    /// The authenticator didn't accept the peer's answer.
+    #[error("{0}")]
+    Rejected(String),
+    /// Reading or writing the exchange failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The challenge or its answer didn't get through.
+    #[error("no answer: {0}")]
+    NoAnswer(String),
+    /// The exchange wasn't over in time.
+    #[error("not authenticated within {} s", .0.as_secs())]
+    TimedOut(Duration),
+}
+
+/// A settings file (config file, swarm key or identity key) could not be read, parsed or written.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("cannot read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("cannot write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+    #[error("invalid config file {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("invalid swarm key {}: {reason}", path.display())]
+    SwarmKey { path: PathBuf, reason: String },
+    #[error("invalid identity key {}: {reason}", path.display())]
+    Identity { path: PathBuf, reason: String },
+    #[error("invalid HMAC key {}: {reason}", path.display())]
+    HmacKey { path: PathBuf, reason: String },
+    #[error("invalid allowlist {}: {reason}", path.display())]
+    Allowlist { path: PathBuf, reason: String },
+    #[error("invalid ActivityPub key {}: {reason}", path.display())]
+    ActivityPubKey { path: PathBuf, reason: String },
+    #[error("invalid room key {}: {reason}", path.display())]
+    RoomKey { path: PathBuf, reason: String },
+}
+
+/// Keys, certificates or signatures could not be produced.
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("signing failed: {0}")]
+    Signing(#[from] identity::SigningError),
+    #[error("cannot encode signed payload: {0}")]
+    Encoding(#[from] serde_json::Error),
+    #[error("noise setup failed: {0}")]
+    Noise(#[from] noise::Error),
+    #[error("TLS certificate generation failed: {0}")]
+    Certificate(#[from] tls::certificate::GenError),
+}
@@ -0,0 +1,139 @@
+// Client-side filters deciding which chat messages are displayed.
+use std::fmt;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::message::ChatMessage;
+
+/// A predicate over chat messages, applied per topic. Messages that don't match are still
+/// received and stored, just not displayed. Every criterion that is set has to match.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TopicFilter {
+    /// Only show messages from one of these nicks (compared case-insensitively).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nick_includes: Option<Vec<String>>,
+    /// Only show messages whose body matches this regular expression.
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "regex_serde")]
+    pub body_regex: Option<Regex>,
+    /// Only show messages written at or after this Unix time (seconds).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_timestamp: Option<u64>,
+}
+
+impl TopicFilter {
+    /// Parse a filter spec of space-separated criteria: `nick:alice,bob`, `since:<unix time>`
+    /// and `body:<regex>`. Since a regex may contain spaces, `body:` takes the rest of the line.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut filter = TopicFilter::default();
+        let mut rest = spec.trim();
+        while !rest.is_empty() {
+            if let Some(pattern) = rest.strip_prefix("body:") {
+                let regex = Regex::new(pattern).map_err(|e| format!("invalid body regex: {e}"))?;
+                filter.body_regex = Some(regex);
+                break;
+            }
+            let (criterion, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            rest = tail.trim_start();
+            match criterion.split_once(':') {
+                Some(("nick", nicks)) => {
+                    let nicks: Vec<String> = nicks
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|nick| !nick.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    if nicks.is_empty() {
+                        return Err("nick: needs at least one nick".to_string());
+                    }
+                    filter.nick_includes = Some(nicks);
+                }
+                Some(("since", time)) => {
+                    let time = time
+                        .parse()
+                        .map_err(|_| format!("invalid since: time {time:?}"))?;
+                    filter.min_timestamp = Some(time);
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown filter {criterion:?}, expected nick:, since: or body:"
+                    ))
+                }
+            }
+        }
+        if filter.is_empty() {
+            return Err("empty filter, use /filter clear to remove it".to_string());
+        }
+        Ok(filter)
+    }
+
+    /// Whether no criterion is set, so every message matches.
+    pub fn is_empty(&self) -> bool {
+        self.nick_includes.is_none() && self.body_regex.is_none() && self.min_timestamp.is_none()
+    }
+
+    /// Whether `message` should be displayed.
+    pub fn matches(&self, message: &ChatMessage) -> bool {
+        let nick_ok = self.nick_includes.as_ref().is_none_or(|nicks| {
+            nicks
+                .iter()
+                .any(|nick| nick.eq_ignore_ascii_case(&message.nick))
+        });
+        let body_ok = self
+            .body_regex
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(&message.body));
+        let time_ok = self
+            .min_timestamp
+            .is_none_or(|min| message.timestamp >= min);
+        nick_ok && body_ok && time_ok
+    }
+}
+
+// `Regex` has no notion of equality, so compare filters by their source pattern.
+impl PartialEq for TopicFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.nick_includes == other.nick_includes
+            && self.body_regex.as_ref().map(Regex::as_str)
+                == other.body_regex.as_ref().map(Regex::as_str)
+            && self.min_timestamp == other.min_timestamp
+    }
+}
+
+impl Eq for TopicFilter {}
+
+/// Formats the filter in the same syntax accepted by [`TopicFilter::parse`].
+impl fmt::Display for TopicFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut criteria = Vec::new();
+        if let Some(nicks) = &self.nick_includes {
+            criteria.push(format!("nick:{}", nicks.join(",")));
+        }
+        if let Some(time) = self.min_timestamp {
+            criteria.push(format!("since:{time}"));
+        }
+        if let Some(regex) = &self.body_regex {
+            criteria.push(format!("body:{}", regex.as_str()));
+        }
+        write!(f, "{}", criteria.join(" "))
+    }
+}
+
+/// Stores a regex as its source pattern.
+mod regex_serde {
+    use regex::Regex;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(regex: &Option<Regex>, s: S) -> Result<S::Ok, S::Error> {
+        match regex {
+            Some(regex) => s.serialize_some(regex.as_str()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Regex>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|pattern| Regex::new(&pattern).map_err(D::Error::custom))
+            .transpose()
+    }
+}
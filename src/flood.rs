@@ -0,0 +1,176 @@
+// Detection of peers that flood a room with the same message over and over.
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+};
+
+use libp2p::PeerId;
+
+use crate::autoban::MAX_TRACKED_PEERS;
+
+/// Seconds without a further copy after which a collapsed run is summarized.
+pub const SUMMARY_DELAY: u64 = 5;
+
+// Fingerprints remembered per peer, however long the window.
+const MAX_FINGERPRINTS: usize = 256;
+
+/// When repeated messages count as a flood.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodSettings {
+    /// Copies of one message a peer may send within `window` before further copies are hidden.
+    pub threshold: usize,
+    /// Sliding window (seconds) over which copies are counted.
+    pub window: u64,
+    /// Messages shorter than this, once normalized, are never flagged ("ok", "+1").
+    pub min_length: usize,
+}
+
+/// What to do with a received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Show,
+    /// The message repeats one the peer has sent too often; hide it and don't forward it.
+    Collapse,
+}
+
+/// Copies of one message hidden in a row, reported as a single line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub peer: PeerId,
+    /// The repeated message.
+    pub body: String,
+    /// Copies hidden since the message was last shown.
+    pub hidden: usize,
+}
+
+// Recent messages of one peer and the run of hidden copies, if any.
+#[derive(Debug, Default)]
+struct PeerHistory {
+    // (received at, fingerprint), oldest first
+    recent: VecDeque<(u64, u64)>,
+    run: Option<OpenRun>,
+}
+
+#[derive(Debug)]
+struct OpenRun {
+    fingerprint: u64,
+    body: String,
+    hidden: usize,
+    last_seen: u64,
+}
+
+/// Counts normalized copies of each peer's recent messages.
+#[derive(Debug)]
+pub struct FloodDetector {
+    settings: FloodSettings,
+    peers: HashMap<PeerId, PeerHistory>,
+}
+
+/// Reduce a body to the part that matters for spotting repeats: lowercase letters and digits.
+/// Changing case, punctuation or spacing doesn't make a copy new.
+pub fn normalize(body: &str) -> String {
+    body.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+impl FloodDetector {
+    pub fn new(settings: FloodSettings) -> Self {
+        FloodDetector {
+            settings,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Count a message from `peer`. A different message ends the peer's current run, which is
+    /// returned so it can be summarized before the new message is shown.
+    pub fn check(&mut self, peer: PeerId, body: &str, now: u64) -> (Verdict, Option<Run>) {
+        let normalized = normalize(body);
+        if normalized.chars().count() < self.settings.min_length {
+            return (Verdict::Show, self.end_run(peer));
+        }
+        if !self.peers.contains_key(&peer) && self.peers.len() >= MAX_TRACKED_PEERS {
+            let window = self.settings.window;
+            self.peers.retain(|_, history| {
+                history.run.is_some()
+                    || history
+                        .recent
+                        .back()
+                        .is_some_and(|&(t, _)| t + window > now)
+            });
+            if self.peers.len() >= MAX_TRACKED_PEERS {
+                return (Verdict::Show, None);
+            }
+        }
+
+        let mut hasher = DefaultHasher::new();
+        normalized.hash(&mut hasher);
+        let fingerprint = hasher.finish();
+
+        let window = self.settings.window;
+        let history = self.peers.entry(peer).or_default();
+        while history
+            .recent
+            .front()
+            .is_some_and(|&(t, _)| t + window <= now)
+            || history.recent.len() >= MAX_FINGERPRINTS
+        {
+            history.recent.pop_front();
+        }
+        history.recent.push_back((now, fingerprint));
+        let copies = history
+            .recent
+            .iter()
+            .filter(|&&(_, f)| f == fingerprint)
+            .count();
+        if copies <= self.settings.threshold {
+            return (Verdict::Show, self.end_run(peer));
+        }
+
+        if let Some(run) = history
+            .run
+            .as_mut()
+            .filter(|run| run.fingerprint == fingerprint)
+        {
+            run.hidden += 1;
+            run.last_seen = now;
+            return (Verdict::Collapse, None);
+        }
+        let ended = self.end_run(peer);
+        self.peers.entry(peer).or_default().run = Some(OpenRun {
+            fingerprint,
+            body: body.to_string(),
+            hidden: 1,
+            last_seen: now,
+        });
+        (Verdict::Collapse, ended)
+    }
+
+    /// End the runs that have seen no copy for [`SUMMARY_DELAY`] seconds.
+    pub fn finish(&mut self, now: u64) -> Vec<Run> {
+        let idle: Vec<PeerId> = self
+            .peers
+            .iter()
+            .filter(|(_, history)| {
+                history
+                    .run
+                    .as_ref()
+                    .is_some_and(|run| run.last_seen + SUMMARY_DELAY <= now)
+            })
+            .map(|(peer, _)| *peer)
+            .collect();
+        idle.into_iter()
+            .filter_map(|peer| self.end_run(peer))
+            .collect()
+    }
+
+    fn end_run(&mut self, peer: PeerId) -> Option<Run> {
+        let run = self.peers.get_mut(&peer)?.run.take()?;
+        Some(Run {
+            peer,
+            body: run.body,
+            hidden: run.hidden,
+        })
+    }
+}
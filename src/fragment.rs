@@ -0,0 +1,304 @@
+// Chat messages too large for one Gossipsub message, split into fragments and put back together.
+use std::collections::{BTreeMap, HashMap};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::message::ChatMessage;
+
+/// Encoded messages larger than this are sent in fragments.
+pub const FRAGMENT_THRESHOLD: usize = 512 * 1024;
+
+/// Payload bytes per fragment. Hex encoding doubles them, which keeps a fragment well within
+/// [`MAX_TRANSMIT_SIZE`](crate::node::MAX_TRANSMIT_SIZE).
+pub const MAX_CHUNK: usize = 256 * 1024;
+
+/// Most fragments a message may be split into, which makes the largest message 4 MiB.
+pub const MAX_FRAGMENTS: u32 = 16;
+
+/// Seconds after its first fragment arrived at which an incomplete message is dropped.
+pub const REASSEMBLY_TIMEOUT: u64 = 30;
+
+/// Incomplete messages held at once; a further one evicts the one started longest ago.
+pub const MAX_BUFFERS: usize = 8;
+
+/// Incomplete messages held at once for one sender; a further one evicts its oldest.
+pub const MAX_PEER_BUFFERS: usize = 2;
+
+/// Default of `--max-reassembly-bytes`.
+pub const DEFAULT_REASSEMBLY_BYTES: usize = 16 * 1024 * 1024;
+
+/// Default of `--max-reassembly-peer-bytes`: one message of the largest size.
+pub const DEFAULT_REASSEMBLY_PEER_BYTES: usize = MAX_FRAGMENTS as usize * MAX_CHUNK;
+
+/// One piece of a fragmented chat message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    pub msg_id: Uuid,
+    pub index: u32,
+    pub total: u32,
+    #[serde(with = "hex::serde")]
+    pub data: Vec<u8>,
+}
+
+impl Fragment {
+    /// JSON encoding published on the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("fragments always serialize")
+    }
+
+    /// Decode received data, if it is a fragment rather than a whole message.
+    pub fn decode(data: &[u8]) -> Option<Fragment> {
+        serde_json::from_slice(data).ok()
+    }
+}
+
+/// Why fragments couldn't be put back together into a message.
+#[derive(Debug, Error)]
+pub enum FragmentError {
+    #[error("no fragments")]
+    Empty,
+    #[error("fragments of different messages or with conflicting counts")]
+    Mismatched,
+    #[error("fragment {0} is missing")]
+    Missing(u32),
+    #[error("a message can have at most {MAX_FRAGMENTS} fragments, not {0}")]
+    TooMany(u32),
+    #[error("reassembled message is invalid: {0}")]
+    Decode(#[from] serde_json::Error),
+}
+
+/// Split a message into fragments of at most `max_chunk` encoded bytes each.
+pub fn fragment(msg: &ChatMessage, max_chunk: usize) -> Vec<Fragment> {
+    let encoded = msg.encode();
+    let msg_id = Uuid::new_v4();
+    let chunks: Vec<&[u8]> = encoded.chunks(max_chunk.max(1)).collect();
+    let total = chunks.len() as u32;
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| Fragment {
+            msg_id,
+            index: index as u32,
+            total,
+            data: data.to_vec(),
+        })
+        .collect()
+}
+
+/// Put a message back together from all of its fragments, in any order.
+pub fn reassemble(mut frags: Vec<Fragment>) -> Result<ChatMessage, FragmentError> {
+    let first = frags.first().ok_or(FragmentError::Empty)?;
+    let (msg_id, total) = (first.msg_id, first.total);
+    if total > MAX_FRAGMENTS {
+        return Err(FragmentError::TooMany(total));
+    }
+    if frags.iter().any(|f| f.msg_id != msg_id || f.total != total) {
+        return Err(FragmentError::Mismatched);
+    }
+    frags.sort_by_key(|f| f.index);
+    frags.dedup_by_key(|f| f.index);
+    for index in 0..total {
+        if frags.get(index as usize).is_none_or(|f| f.index != index) {
+            return Err(FragmentError::Missing(index));
+        }
+    }
+    if frags.len() != total as usize {
+        return Err(FragmentError::Mismatched);
+    }
+    let data: Vec<u8> = frags.into_iter().flat_map(|f| f.data).collect();
+    Ok(serde_json::from_slice(&data)?)
+}
+
+// Fragments of one message received so far.
+#[derive(Debug)]
+struct FragmentBuffer {
+    sender: PeerId,
+    total: u32,
+    parts: BTreeMap<u32, Fragment>,
+    started_at: u64,
+}
+
+impl FragmentBuffer {
+    fn bytes(&self) -> usize {
+        self.parts.values().map(|part| part.data.len()).sum()
+    }
+}
+
+/// What became of a received fragment.
+#[derive(Debug)]
+pub enum Assembly {
+    /// More fragments are needed.
+    Pending,
+    /// That was the last fragment; here is the whole message.
+    Complete(Result<ChatMessage, FragmentError>),
+    /// The fragment was dropped: a bad count, another sender using the same message id, or a
+    /// message too large for the byte limits, which is dropped with it.
+    Dropped,
+}
+
+/// Bytes of incomplete messages the [`Reassembler`] holds, in all and per sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReassemblyLimits {
+    pub bytes: usize,
+    pub peer_bytes: usize,
+}
+
+impl Default for ReassemblyLimits {
+    fn default() -> Self {
+        ReassemblyLimits {
+            bytes: DEFAULT_REASSEMBLY_BYTES,
+            peer_bytes: DEFAULT_REASSEMBLY_PEER_BYTES,
+        }
+    }
+}
+
+/// Fragments waiting for the rest of their message, per message id.
+///
+/// Each sender gets at most [`MAX_PEER_BUFFERS`] messages and `peer_bytes` of the budget, so
+/// one peer can't crowd out the others; going over evicts its own oldest message. Over the
+/// overall limits, the oldest message of anyone goes.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    buffers: HashMap<Uuid, FragmentBuffer>,
+    limits: ReassemblyLimits,
+    evictions: u64,
+}
+
+impl Reassembler {
+    pub fn new(limits: ReassemblyLimits) -> Self {
+        Reassembler {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    /// Add a fragment `sender` published.
+    pub fn add(&mut self, sender: PeerId, fragment: Fragment, now: u64) -> Assembly {
+        if fragment.total == 0 || fragment.total > MAX_FRAGMENTS || fragment.index >= fragment.total
+        {
+            return Assembly::Dropped;
+        }
+        if !self.buffers.contains_key(&fragment.msg_id) {
+            if self.peer_buffers(&sender) >= MAX_PEER_BUFFERS {
+                self.evict_oldest(|_, buffer| buffer.sender == sender);
+            }
+            if self.buffers.len() >= MAX_BUFFERS {
+                self.evict_oldest(|_, _| true);
+            }
+        }
+        let buffer = self
+            .buffers
+            .entry(fragment.msg_id)
+            .or_insert_with(|| FragmentBuffer {
+                sender,
+                total: fragment.total,
+                parts: BTreeMap::new(),
+                started_at: now,
+            });
+        if buffer.sender != sender || buffer.total != fragment.total {
+            return Assembly::Dropped;
+        }
+        let msg_id = fragment.msg_id;
+        buffer.parts.insert(fragment.index, fragment);
+        if buffer.parts.len() == buffer.total as usize {
+            let buffer = self
+                .buffers
+                .remove(&msg_id)
+                .expect("the buffer was just filled");
+            return Assembly::Complete(reassemble(buffer.parts.into_values().collect()));
+        }
+
+        // Over budget, older messages go first: the sender's own, then anyone's
+        while self.peer_bytes(&sender) > self.limits.peer_bytes {
+            if !self.evict_oldest(|id, buffer| buffer.sender == sender && *id != msg_id) {
+                return self.drop_buffer(&msg_id);
+            }
+        }
+        while self.bytes() > self.limits.bytes {
+            if !self.evict_oldest(|id, _| *id != msg_id) {
+                return self.drop_buffer(&msg_id);
+            }
+        }
+        Assembly::Pending
+    }
+
+    // Evict the message started longest ago among those matching `evictable`. Returns
+    // whether there was one.
+    fn evict_oldest(&mut self, evictable: impl Fn(&Uuid, &FragmentBuffer) -> bool) -> bool {
+        let oldest = self
+            .buffers
+            .iter()
+            .filter(|(id, buffer)| evictable(id, buffer))
+            .min_by_key(|(_, buffer)| buffer.started_at)
+            .map(|(id, _)| *id);
+        match oldest {
+            Some(id) => {
+                self.buffers.remove(&id);
+                self.evictions += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn drop_buffer(&mut self, msg_id: &Uuid) -> Assembly {
+        self.buffers.remove(msg_id);
+        self.evictions += 1;
+        Assembly::Dropped
+    }
+
+    fn peer_buffers(&self, peer: &PeerId) -> usize {
+        self.buffers
+            .values()
+            .filter(|buffer| buffer.sender == *peer)
+            .count()
+    }
+
+    fn peer_bytes(&self, peer: &PeerId) -> usize {
+        self.buffers
+            .values()
+            .filter(|buffer| buffer.sender == *peer)
+            .map(FragmentBuffer::bytes)
+            .sum()
+    }
+
+    /// Drop the messages still incomplete [`REASSEMBLY_TIMEOUT`] seconds after their first
+    /// fragment, returning how many there were.
+    pub fn expire(&mut self, now: u64) -> usize {
+        let before = self.buffers.len();
+        self.buffers
+            .retain(|_, buffer| now < buffer.started_at + REASSEMBLY_TIMEOUT);
+        before - self.buffers.len()
+    }
+
+    /// Number of messages waiting for more fragments.
+    pub fn pending(&self) -> usize {
+        self.buffers.len()
+    }
+
+    /// Bytes of fragments held.
+    pub fn bytes(&self) -> usize {
+        self.buffers.values().map(FragmentBuffer::bytes).sum()
+    }
+
+    /// Bytes of fragments held for the sender with the most.
+    pub fn largest_peer_bytes(&self) -> usize {
+        let mut per_peer: HashMap<PeerId, usize> = HashMap::new();
+        for buffer in self.buffers.values() {
+            *per_peer.entry(buffer.sender).or_default() += buffer.bytes();
+        }
+        per_peer.into_values().max().unwrap_or(0)
+    }
+
+    pub fn limits(&self) -> ReassemblyLimits {
+        self.limits
+    }
+
+    /// Incomplete messages evicted to stay within the limits so far.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+}
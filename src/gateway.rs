@@ -0,0 +1,513 @@
+// An IRC server for local IRC clients. Each client joins the room's channel, `#<topic>`, and
+// chats in the room through this node under a nick of its own.
+use std::{collections::BTreeMap, io, net::SocketAddr};
+
+use libp2p::futures::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    AsyncReadExt,
+};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::{
+    irc::{self, Message},
+    runtime,
+};
+
+/// The name the gateway goes by as an IRC server.
+pub const SERVER_NAME: &str = "p2p-chat";
+
+/// Messages waiting to be written to one client. A client that falls that far behind is
+/// disconnected.
+pub const CLIENT_QUEUE: usize = 256;
+
+/// Most clients connected at once. More are turned away.
+pub const MAX_CLIENTS: usize = 64;
+
+/// Longest line taken from a client, message tags included.
+pub const MAX_LINE_BYTES: usize = 8192;
+
+/// The channel IRC clients join to chat in the room on `topic`.
+pub fn channel_for(topic: &str) -> String {
+    format!("#{topic}")
+}
+
+/// What happens on a client's connection, passed to [`Gateway::handle`].
+#[derive(Debug)]
+pub enum ClientEvent {
+    /// A client connected from `address`.
+    Connected {
+        id: u64,
+        address: SocketAddr,
+        sender: mpsc::Sender<Message>,
+    },
+    /// A client sent a message.
+    Message { id: u64, message: Message },
+    /// A client's connection closed or failed.
+    Closed { id: u64, reason: String },
+}
+
+/// What a client did that the node has to act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GatewayEvent {
+    /// A client connected from `address` picked its nick.
+    Registered { nick: String, address: SocketAddr },
+    /// A client in the channel said `text`, to be published to the room.
+    Said { nick: String, text: String },
+    /// A client left with `/quit` or lost its connection.
+    Left { nick: String, reason: String },
+}
+
+#[derive(Debug)]
+struct Client {
+    address: SocketAddr,
+    nick: Option<String>,
+    joined: bool,
+    sender: mpsc::Sender<Message>,
+}
+
+impl Client {
+    // The `nick!user@host` its messages come from.
+    fn source(&self) -> String {
+        let nick = self.nick.as_deref().unwrap_or("*");
+        format!("{nick}!{nick}@{SERVER_NAME}")
+    }
+}
+
+/// The IRC server, listening for clients on a task of its own. The node passes what the
+/// clients send to [`Gateway::handle`] and the room's messages to [`Gateway::deliver`].
+/// Dropping it disconnects every client.
+#[derive(Debug)]
+pub struct Gateway {
+    address: SocketAddr,
+    channel: String,
+    clients: BTreeMap<u64, Client>,
+    // Clients whose queue overflowed, disconnected once the message being handled is done
+    lagging: Vec<u64>,
+    events: mpsc::Receiver<ClientEvent>,
+    task: runtime::Task<()>,
+}
+
+impl Gateway {
+    /// Listen for IRC clients on `address`, for the room on `topic`.
+    pub fn bind(address: SocketAddr, topic: &str) -> io::Result<Self> {
+        let listener = runtime::listen_tcp(address)?;
+        let address = listener.local_addr()?;
+        let (sender, events) = mpsc::channel(MAX_CLIENTS * 4);
+        let task = runtime::spawn(accept(listener, sender));
+        Ok(Gateway {
+            address,
+            channel: channel_for(topic),
+            clients: BTreeMap::new(),
+            lagging: Vec::new(),
+            events,
+            task,
+        })
+    }
+
+    /// The address clients connect to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// The nicks of the clients that picked one.
+    pub fn nicks(&self) -> impl Iterator<Item = &str> {
+        self.clients
+            .values()
+            .filter_map(|client| client.nick.as_deref())
+    }
+
+    /// The next thing that happened on a client's connection.
+    pub async fn next(&mut self) -> Option<ClientEvent> {
+        self.events.recv().await
+    }
+
+    /// Answer a client, and tell the node what it did.
+    pub fn handle(&mut self, event: ClientEvent) -> Option<GatewayEvent> {
+        let done = match event {
+            ClientEvent::Connected {
+                id,
+                address,
+                sender,
+            } => {
+                if self.clients.len() >= MAX_CLIENTS {
+                    let full = Message::new("ERROR", &["Too many connections"]);
+                    let _ = sender.try_send(full);
+                    return None;
+                }
+                let client = Client {
+                    address,
+                    nick: None,
+                    joined: false,
+                    sender,
+                };
+                self.clients.insert(id, client);
+                None
+            }
+            ClientEvent::Message { id, message } => self.take(id, message),
+            ClientEvent::Closed { id, reason } => self.remove(id, &reason),
+        };
+        self.drop_lagging();
+        done
+    }
+
+    /// Say `text` on the channel from `nick`, to every client in it.
+    pub fn deliver(&mut self, nick: &str, text: &str) {
+        let nick = irc_nick(nick);
+        let source = format!("{nick}!{nick}@{SERVER_NAME}");
+        let joined: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| client.joined)
+            .map(|(id, _)| *id)
+            .collect();
+        for message in irc::say("PRIVMSG", &self.channel, "", text) {
+            let message = Message {
+                source: Some(source.clone()),
+                ..message
+            };
+            for id in &joined {
+                self.send(*id, message.clone());
+            }
+        }
+        self.drop_lagging();
+    }
+
+    // Act on a message from client `id`.
+    fn take(&mut self, id: u64, message: Message) -> Option<GatewayEvent> {
+        let client = self.clients.get(&id)?;
+        let registered = client.nick.is_some();
+        match message.command.as_str() {
+            "NICK" => return self.set_nick(id, message.param(0)),
+            "CAP" if message.param(0).eq_ignore_ascii_case("LS") => {
+                // No capabilities: the client goes on registering without any
+                self.reply(id, "CAP", &["LS", ""]);
+            }
+            "CAP" | "PASS" | "USER" | "PONG" => {}
+            "PING" => {
+                let pong = Message {
+                    source: Some(SERVER_NAME.to_string()),
+                    ..Message::new("PONG", &[SERVER_NAME, message.param(0)])
+                };
+                self.send(id, pong);
+            }
+            "QUIT" => {
+                let reason = match message.param(0) {
+                    "" => "Quit".to_string(),
+                    reason => format!("Quit: {reason}"),
+                };
+                let closing = Message::new("ERROR", &[&format!("Closing link ({reason})")]);
+                self.send(id, closing);
+                return self.remove(id, &reason);
+            }
+            _ if !registered => self.reply(id, "451", &["You have not registered"]),
+            "JOIN" => {
+                for channel in message.param(0).split(',') {
+                    self.join(id, channel);
+                }
+            }
+            "PART" => {
+                if message
+                    .param(0)
+                    .split(',')
+                    .any(|channel| channel.eq_ignore_ascii_case(&self.channel))
+                {
+                    self.part(id, message.param(1));
+                }
+            }
+            "PRIVMSG" => return self.privmsg(id, &message),
+            command => self.reply(id, "421", &[command, "Unknown command"]),
+        }
+        None
+    }
+
+    fn set_nick(&mut self, id: u64, nick: &str) -> Option<GatewayEvent> {
+        if nick.is_empty() {
+            self.reply(id, "431", &["No nickname given"]);
+            return None;
+        }
+        if !irc::valid_nick(nick) {
+            self.reply(id, "432", &[nick, "Erroneous nickname"]);
+            return None;
+        }
+        let taken = self.clients.iter().any(|(other, client)| {
+            *other != id
+                && client
+                    .nick
+                    .as_deref()
+                    .is_some_and(|taken| taken.eq_ignore_ascii_case(nick))
+        });
+        if taken {
+            self.reply(id, "433", &[nick, "Nickname is already in use"]);
+            return None;
+        }
+        let client = self.clients.get_mut(&id)?;
+        let Some(old) = client.nick.replace(nick.to_string()) else {
+            let address = client.address;
+            self.reply(
+                id,
+                "001",
+                &[&format!("Welcome to the p2p-chat IRC gateway, {nick}")],
+            );
+            self.reply(
+                id,
+                "002",
+                &[&format!("Join {} to chat in the room", self.channel)],
+            );
+            self.reply(id, "422", &["MOTD File is missing"]);
+            return Some(GatewayEvent::Registered {
+                nick: nick.to_string(),
+                address,
+            });
+        };
+        let changed = Message {
+            source: Some(format!("{old}!{old}@{SERVER_NAME}")),
+            ..Message::new("NICK", &[nick])
+        };
+        let joined = client.joined;
+        self.send(id, changed.clone());
+        if joined {
+            self.send_to_channel(id, &changed);
+        }
+        None
+    }
+
+    fn join(&mut self, id: u64, channel: &str) {
+        if !channel.eq_ignore_ascii_case(&self.channel) {
+            self.reply(id, "403", &[channel, "No such channel"]);
+            return;
+        }
+        let Some(client) = self.clients.get_mut(&id) else {
+            return;
+        };
+        if client.joined {
+            return;
+        }
+        client.joined = true;
+        let joined = Message {
+            source: Some(client.source()),
+            ..Message::new("JOIN", &[&self.channel])
+        };
+        self.send(id, joined.clone());
+        self.send_to_channel(id, &joined);
+        let names: Vec<&str> = self
+            .clients
+            .values()
+            .filter(|client| client.joined)
+            .filter_map(|client| client.nick.as_deref())
+            .collect();
+        let names = names.join(" ");
+        let channel = self.channel.clone();
+        self.reply(id, "331", &[&channel, "No topic is set"]);
+        self.reply(id, "353", &["=", &channel, &names]);
+        self.reply(id, "366", &[&channel, "End of /NAMES list"]);
+    }
+
+    fn part(&mut self, id: u64, reason: &str) {
+        let Some(client) = self.clients.get_mut(&id).filter(|client| client.joined) else {
+            return;
+        };
+        client.joined = false;
+        let parted = Message {
+            source: Some(client.source()),
+            ..Message::new("PART", &[&self.channel, reason])
+        };
+        self.send(id, parted.clone());
+        self.send_to_channel(id, &parted);
+    }
+
+    fn privmsg(&mut self, id: u64, message: &Message) -> Option<GatewayEvent> {
+        let (target, text) = (message.param(0), message.param(1));
+        if text.is_empty() {
+            self.reply(id, "412", &["No text to send"]);
+            return None;
+        }
+        if !target.eq_ignore_ascii_case(&self.channel) {
+            self.reply(id, "401", &[target, "No such nick/channel"]);
+            return None;
+        }
+        let client = self.clients.get(&id)?;
+        if !client.joined {
+            self.reply(id, "404", &[target, "Cannot send to channel"]);
+            return None;
+        }
+        let nick = client.nick.clone()?;
+        let said = Message {
+            source: Some(client.source()),
+            ..Message::new("PRIVMSG", &[&self.channel, text])
+        };
+        self.send_to_channel(id, &said);
+        // Only actions among the client-to-client requests are chat
+        let text = match text.strip_prefix('\x01') {
+            Some(ctcp) => format!(
+                "* {nick} {}",
+                ctcp.trim_end_matches('\x01').strip_prefix("ACTION ")?
+            ),
+            None => irc::strip_formatting(text),
+        };
+        Some(GatewayEvent::Said { nick, text })
+    }
+
+    // Forget client `id`, telling the channel it quit.
+    fn remove(&mut self, id: u64, reason: &str) -> Option<GatewayEvent> {
+        let client = self.clients.remove(&id)?;
+        let nick = client.nick.clone()?;
+        if client.joined {
+            let quit = Message {
+                source: Some(client.source()),
+                ..Message::new("QUIT", &[reason])
+            };
+            self.send_to_channel(id, &quit);
+        }
+        Some(GatewayEvent::Left {
+            nick,
+            reason: reason.to_string(),
+        })
+    }
+
+    // Send `message` to the clients in the channel other than `from`.
+    fn send_to_channel(&mut self, from: u64, message: &Message) {
+        let others: Vec<u64> = self
+            .clients
+            .iter()
+            .filter(|(id, client)| **id != from && client.joined)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in others {
+            self.send(id, message.clone());
+        }
+    }
+
+    // A numeric reply to client `id`, addressed to its nick.
+    fn reply(&mut self, id: u64, code: &str, params: &[&str]) {
+        let Some(client) = self.clients.get(&id) else {
+            return;
+        };
+        let nick = client.nick.as_deref().unwrap_or("*");
+        let params: Vec<&str> = [nick].into_iter().chain(params.iter().copied()).collect();
+        let reply = Message {
+            source: Some(SERVER_NAME.to_string()),
+            ..Message::new(code, &params)
+        };
+        self.send(id, reply);
+    }
+
+    fn send(&mut self, id: u64, message: Message) {
+        let Some(client) = self.clients.get(&id) else {
+            return;
+        };
+        if client.sender.try_send(message).is_err() && !self.lagging.contains(&id) {
+            self.lagging.push(id);
+        }
+    }
+
+    fn drop_lagging(&mut self) {
+        for id in std::mem::take(&mut self.lagging) {
+            debug!("[gateway] disconnected IRC client {id}, which fell behind");
+            self.remove(id, "Send queue exceeded");
+        }
+        // Clients the quits overflowed are dropped the next time something for them doesn't fit
+        self.lagging.clear();
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// `name` with what IRC doesn't allow in a nick replaced, to stand for a peer in the channel.
+fn irc_nick(name: &str) -> String {
+    let nick: String = name
+        .chars()
+        .map(|c| match c {
+            ' ' | ',' | '*' | '?' | '!' | '@' | ':' | '#' | '&' | '$' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match nick.as_str() {
+        "" => "_".to_string(),
+        _ => nick,
+    }
+}
+
+// Take clients until the node goes away, each served on a task of its own.
+async fn accept(listener: runtime::TcpListener, events: mpsc::Sender<ClientEvent>) {
+    let mut next_id = 0;
+    loop {
+        let (socket, address) = match runtime::accept_tcp(&listener).await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                debug!("[gateway] accepting a client failed: {e}");
+                runtime::sleep(std::time::Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let (sender, outgoing) = mpsc::channel(CLIENT_QUEUE);
+        let id = next_id;
+        next_id += 1;
+        let connected = ClientEvent::Connected {
+            id,
+            address,
+            sender,
+        };
+        if events.send(connected).await.is_err() {
+            return;
+        }
+        // The task ends once the node drops the client, or the client its connection
+        runtime::spawn(serve(id, socket, outgoing, events.clone()));
+    }
+}
+
+// Pass the lines client `id` sends on to the node and write what the node has for it, until
+// either closes the connection.
+async fn serve(
+    id: u64,
+    socket: runtime::TcpStream,
+    mut outgoing: mpsc::Receiver<Message>,
+    events: mpsc::Sender<ClientEvent>,
+) {
+    let (reader, mut writer) = socket.split();
+    // The limit is put back after every line, so no line runs on for ever
+    let mut reader = BufReader::new(reader.take(MAX_LINE_BYTES as u64));
+    let mut line = Vec::new();
+    let reason = loop {
+        tokio::select! {
+            // A line cut off here stays in the buffer, to be finished next time round
+            read = reader.read_until(b'\n', &mut line) => {
+                match read {
+                    Ok(_) if line.ends_with(b"\n") => {}
+                    Ok(_) if reader.get_ref().limit() == 0 => break "Line too long".to_string(),
+                    Ok(_) => break "Connection closed".to_string(),
+                    Err(e) => break e.to_string(),
+                }
+                reader.get_mut().set_limit(MAX_LINE_BYTES as u64);
+                let text = String::from_utf8_lossy(&line).into_owned();
+                line.clear();
+                let Some(message) = Message::parse(&text) else {
+                    continue;
+                };
+                if events.send(ClientEvent::Message { id, message }).await.is_err() {
+                    return;
+                }
+            }
+            message = outgoing.recv() => {
+                // The node is done with the client once everything for it is written
+                let Some(message) = message else {
+                    return;
+                };
+                let written = writer.write_all(format!("{message}\r\n").as_bytes()).await;
+                if let Err(e) = written.and(writer.flush().await) {
+                    break e.to_string();
+                }
+            }
+        }
+    };
+    let _ = events.send(ClientEvent::Closed { id, reason }).await;
+}
@@ -0,0 +1,90 @@
+// The part of Gossipsub that the chat node's event handlers talk back to.
+use libp2p::{
+    gossipsub::{self, MessageAcceptance, MessageId, TopicSubscriptionFilter},
+    mdns, PeerId,
+};
+
+use crate::say;
+
+/// Gossipsub operations used while handling its events, so the handlers can be driven by a
+/// mock in tests instead of a real swarm.
+pub trait Gossip {
+    /// Report whether a received message is valid and may be forwarded. Returns false if the
+    /// message is no longer in the cache.
+    fn report_validation(
+        &mut self,
+        message_id: &MessageId,
+        propagation_source: &PeerId,
+        acceptance: MessageAcceptance,
+    ) -> bool;
+
+    /// Always send messages to `peer`, whether or not it is in the mesh.
+    fn add_explicit_peer(&mut self, peer: &PeerId);
+
+    /// Stop treating `peer` as explicit; it is still reached through the mesh while connected.
+    fn remove_explicit_peer(&mut self, peer: &PeerId);
+}
+
+impl<D, F> Gossip for gossipsub::Behaviour<D, F>
+where
+    D: gossipsub::DataTransform + Send + 'static,
+    F: TopicSubscriptionFilter + Send + 'static,
+{
+    fn report_validation(
+        &mut self,
+        message_id: &MessageId,
+        propagation_source: &PeerId,
+        acceptance: MessageAcceptance,
+    ) -> bool {
+        // Forwarding fails harmlessly when no other peer is left to forward the message to
+        self.report_message_validation_result(message_id, propagation_source, acceptance)
+            .unwrap_or(false)
+    }
+
+    fn add_explicit_peer(&mut self, peer: &PeerId) {
+        gossipsub::Behaviour::add_explicit_peer(self, peer);
+    }
+
+    fn remove_explicit_peer(&mut self, peer: &PeerId) {
+        gossipsub::Behaviour::remove_explicit_peer(self, peer);
+    }
+}
+
+/// Make peers found by mDNS explicit Gossipsub peers, and drop them again once their
+/// announcement expires.
+pub fn discovery(event: mdns::Event, gossip: &mut impl Gossip) {
+    match event {
+        // When mDNS discovers a new peer on the local network
+        mdns::Event::Discovered(list) => {
+            // For each discovered peer, print the peer ID and add them to Gossipsub explicitly
+            for (peer_id, _multiaddr) in list {
+                say!("mDNS discovered a new peer: {peer_id}");
+                gossip.add_explicit_peer(&peer_id);
+                say!("Added explicit peer: {:?}", peer_id);
+            }
+        }
+        // When a previously discovered peer's mDNS announcement has expired
+        mdns::Event::Expired(list) => {
+            // For each expired peer, remove them from the Gossipsub peer list
+            for (peer_id, _multiaddr) in list {
+                say!("mDNS discover peer has expired: {peer_id}");
+                gossip.remove_explicit_peer(&peer_id);
+            }
+        }
+    }
+}
+
+/// The verdict on a received message, to be reported back to Gossipsub.
+#[derive(Debug)]
+pub struct Validation {
+    pub message_id: MessageId,
+    pub propagation_source: PeerId,
+    pub acceptance: MessageAcceptance,
+}
+
+impl Validation {
+    /// Report the verdict, so Gossipsub forwards or drops the message.
+    pub fn report(self, gossip: &mut impl Gossip) -> bool {
+        gossip.report_validation(&self.message_id, &self.propagation_source, self.acceptance)
+    }
+}
@@ -0,0 +1,505 @@
+// The node's HTTP server, where CI and alerting systems post messages for the room: JSON sent
+// to `POST /hooks/<token>` is published for the hook in the `hooks` section of the config file
+// that goes by the token. With an ActivityPub actor, the server also serves the actor and takes
+// notes from the fediverse at `POST /inbox`. `GET /health` answers with the node's health, for
+// readiness checks.
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use libp2p::futures::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    AsyncRead,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tokio::sync::{mpsc, Semaphore};
+use tracing::debug;
+
+use crate::{
+    activitypub::{self, Inbox},
+    http::{self, RequestHead},
+    runtime, sanitize,
+    stats::HealthStatus,
+};
+
+/// The `origin` of messages posted through a hook, which peers show them with.
+pub const ORIGIN: &str = "hook";
+
+/// Posts waiting for the node. More are answered with 503 until it catches up.
+pub const QUEUE: usize = 256;
+
+/// Most connections served at once. More are answered with 503.
+pub const MAX_CONNECTIONS: usize = 64;
+
+/// How long a client has to send its request.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shortest token a hook may have.
+pub const MIN_TOKEN_CHARS: usize = 16;
+
+/// The window posts are counted over for a hook's `per_minute` limit.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// A hook: who may post to which rooms, and how much.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HookSettings {
+    /// The secret posted to, as `/hooks/<token>`. Letters, digits, `-` and `_`.
+    pub token: String,
+    /// The rooms, topic names without the prefix, the hook may post to.
+    pub rooms: Vec<String>,
+    /// Largest JSON body taken, in bytes.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+    /// Most posts taken in a minute.
+    #[serde(default = "default_per_minute")]
+    pub per_minute: u32,
+}
+
+fn default_max_bytes() -> usize {
+    4096
+}
+
+fn default_per_minute() -> u32 {
+    30
+}
+
+impl HookSettings {
+    /// Settings letting `token` post to `rooms`, with the defaults for the rest.
+    pub fn new(token: &str, rooms: &[&str]) -> Self {
+        HookSettings {
+            token: token.to_string(),
+            rooms: rooms.iter().map(|room| room.to_string()).collect(),
+            max_bytes: default_max_bytes(),
+            per_minute: default_per_minute(),
+        }
+    }
+
+    /// Whether the settings make sense.
+    pub fn check(&self) -> Result<(), String> {
+        if self.token.chars().count() < MIN_TOKEN_CHARS {
+            return Err(format!(
+                "the token is shorter than {MIN_TOKEN_CHARS} characters"
+            ));
+        }
+        let url_safe = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if !self.token.chars().all(url_safe) {
+            return Err("the token may only have letters, digits, - and _".to_string());
+        }
+        if self.rooms.is_empty() {
+            return Err("the hook may post to no room".to_string());
+        }
+        if self.max_bytes == 0 || self.per_minute == 0 {
+            return Err("max_bytes and per_minute must be over 0".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What a hook posts.
+#[derive(Deserialize, Debug)]
+struct Post {
+    room: String,
+    text: String,
+    #[serde(default)]
+    nick: Option<String>,
+}
+
+/// What happened on the HTTP server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookEvent {
+    /// Hook `hook` posted `text` to the room as `nick`, to be published.
+    Posted {
+        hook: String,
+        nick: String,
+        text: String,
+    },
+    /// `actor`, as `user@host`, posted a note saying `text` to the inbox, to be published.
+    Note { actor: String, text: String },
+    /// A request from `address` was turned away with `status`.
+    Rejected {
+        address: SocketAddr,
+        status: u16,
+        error: String,
+    },
+}
+
+// Why a request was turned away.
+struct Rejection {
+    status: u16,
+    error: String,
+    retry_after: Option<u64>,
+    // The method a 405 names as allowed
+    allow: &'static str,
+}
+
+impl Rejection {
+    fn new(status: u16, error: impl Into<String>) -> Self {
+        Rejection {
+            status,
+            error: error.into(),
+            retry_after: None,
+            allow: "POST",
+        }
+    }
+}
+
+// How a request that was taken is answered.
+enum Taken {
+    // Queued for the node
+    Queued,
+    // With an ActivityPub document
+    Document(String),
+    // With the node's health, 200 when it is ready and 503 otherwise
+    Health(u16, String),
+}
+
+// The hooks, by name, and when each last posted, the actor's inbox if there is one, and the
+// node's health as it last reported it.
+struct Routes {
+    room: String,
+    max_body: usize,
+    hooks: BTreeMap<String, HookSettings>,
+    posted: Mutex<BTreeMap<String, VecDeque<Instant>>>,
+    inbox: Option<Inbox>,
+    health: Arc<Mutex<Option<HealthStatus>>>,
+}
+
+impl Routes {
+    // The hook going by `token`, by name. Tokens are compared by their digests, in constant
+    // time and against every hook, so how long a guess takes tells nothing about how close it
+    // came or how long the tokens are.
+    fn hook(&self, token: &str) -> Option<(&str, &HookSettings)> {
+        let guess = Sha256::digest(token.as_bytes());
+        let mut found = None;
+        for (name, settings) in &self.hooks {
+            let matches = Sha256::digest(settings.token.as_bytes()).ct_eq(&guess);
+            if bool::from(matches) && found.is_none() {
+                found = Some((name.as_str(), settings));
+            }
+        }
+        found
+    }
+
+    // Check what hook `name` posted, and count it against the hook's limit.
+    fn admit(&self, name: &str, settings: &HookSettings, body: &[u8]) -> Result<Post, Rejection> {
+        let post: Post = serde_json::from_slice(body).map_err(|_| {
+            Rejection::new(400, "the body must be a JSON object with room and text")
+        })?;
+        if post.text.trim().is_empty() {
+            return Err(Rejection::new(400, "the text is empty"));
+        }
+        if post.text.len() > self.max_body {
+            return Err(Rejection::new(
+                413,
+                format!("the text is over {} bytes", self.max_body),
+            ));
+        }
+        if post.room != self.room {
+            return Err(Rejection::new(404, format!("unknown room {}", post.room)));
+        }
+        if !settings.rooms.contains(&post.room) {
+            return Err(Rejection::new(
+                403,
+                format!("the hook may not post to {}", post.room),
+            ));
+        }
+        if let Some(nick) = &post.nick {
+            if sanitize::nick(nick) != *nick || nick.is_empty() {
+                return Err(Rejection::new(400, "the nick is empty or malformed"));
+            }
+        }
+        let mut posted = self.posted.lock().expect("the rate limits aren't poisoned");
+        let times = posted.entry(name.to_string()).or_default();
+        let now = Instant::now();
+        while times
+            .front()
+            .is_some_and(|&time| now.duration_since(time) >= RATE_WINDOW)
+        {
+            times.pop_front();
+        }
+        if times.len() >= settings.per_minute as usize {
+            let oldest = times.front().copied().unwrap_or(now);
+            let wait = RATE_WINDOW.saturating_sub(now.duration_since(oldest));
+            return Err(Rejection {
+                retry_after: Some(wait.as_secs().max(1)),
+                ..Rejection::new(429, format!("over {} posts a minute", settings.per_minute))
+            });
+        }
+        times.push_back(now);
+        Ok(post)
+    }
+}
+
+/// The HTTP server, taking requests on a task of its own. The node publishes the posts it
+/// passes on. Dropping it stops the server.
+#[derive(Debug)]
+pub struct HookServer {
+    address: SocketAddr,
+    events: mpsc::Receiver<HookEvent>,
+    health: Arc<Mutex<Option<HealthStatus>>>,
+    task: runtime::Task<()>,
+}
+
+impl Drop for HookServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl HookServer {
+    /// Listen on `address` for posts to the room `room`, a topic name without the prefix, by
+    /// `hooks`, keyed by name, and for notes to `inbox` if there is one. Texts longer than
+    /// `max_body` bytes are refused.
+    pub fn bind(
+        address: SocketAddr,
+        room: &str,
+        max_body: usize,
+        hooks: BTreeMap<String, HookSettings>,
+        inbox: Option<Inbox>,
+    ) -> io::Result<Self> {
+        let listener = runtime::listen_tcp(address)?;
+        let address = listener.local_addr()?;
+        let (sender, events) = mpsc::channel(QUEUE);
+        let health = Arc::new(Mutex::new(None));
+        let routes = Routes {
+            room: room.to_string(),
+            max_body,
+            hooks,
+            posted: Mutex::new(BTreeMap::new()),
+            inbox,
+            health: health.clone(),
+        };
+        let task = runtime::spawn(accept(listener, Arc::new(routes), sender));
+        Ok(HookServer {
+            address,
+            events,
+            health,
+            task,
+        })
+    }
+
+    /// The address hooks post to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Serve `health` at `GET /health` until the next report. Until the first, the node is
+    /// reported as not ready.
+    pub fn report_health(&self, health: HealthStatus) {
+        *self.health.lock().expect("the health isn't poisoned") = Some(health);
+    }
+
+    /// The next thing that happened on the server.
+    pub async fn next(&mut self) -> Option<HookEvent> {
+        self.events.recv().await
+    }
+}
+
+// Take connections until the node goes away, each served on a task of its own.
+async fn accept(
+    listener: runtime::TcpListener,
+    routes: Arc<Routes>,
+    events: mpsc::Sender<HookEvent>,
+) {
+    let slots = Arc::new(Semaphore::new(MAX_CONNECTIONS));
+    loop {
+        let (mut socket, address) = match runtime::accept_tcp(&listener).await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                debug!("[hooks] accepting a connection failed: {e}");
+                runtime::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        if events.is_closed() {
+            return;
+        }
+        let Ok(slot) = slots.clone().try_acquire_owned() else {
+            let busy = json!({ "error": "too many connections" }).to_string();
+            let _ = socket
+                .write_all(http::response(503, &[], &busy).as_bytes())
+                .await;
+            continue;
+        };
+        let (routes, events) = (routes.clone(), events.clone());
+        runtime::spawn(async move {
+            serve(socket, address, &routes, &events).await;
+            drop(slot);
+        });
+    }
+}
+
+// Answer the request on `socket`, passing what it posted on to the node.
+async fn serve(
+    mut socket: runtime::TcpStream,
+    address: SocketAddr,
+    routes: &Routes,
+    events: &mpsc::Sender<HookEvent>,
+) {
+    let answer = match runtime::timeout(READ_TIMEOUT, take(&mut socket, routes, events)).await {
+        Ok(answer) => answer,
+        Err(_) => Err(Rejection::new(400, "the request took too long")),
+    };
+    let response = match answer {
+        Ok(Taken::Queued) => http::response(202, &[], &json!({ "status": "accepted" }).to_string()),
+        Ok(Taken::Document(document)) => {
+            let headers = [("Content-Type", activitypub::CONTENT_TYPE.to_string())];
+            http::response(200, &headers, &document)
+        }
+        Ok(Taken::Health(status, body)) => http::response(status, &[], &body),
+        Err(rejection) => {
+            let body = json!({ "error": rejection.error }).to_string();
+            let mut headers = Vec::new();
+            if let Some(seconds) = rejection.retry_after {
+                headers.push(("Retry-After", seconds.to_string()));
+            }
+            if rejection.status == 405 {
+                headers.push(("Allow", rejection.allow.to_string()));
+            }
+            let rejected = HookEvent::Rejected {
+                address,
+                status: rejection.status,
+                error: rejection.error,
+            };
+            let _ = events.try_send(rejected);
+            http::response(rejection.status, &headers, &body)
+        }
+    };
+    let written = socket.write_all(response.as_bytes()).await;
+    if let Err(e) = written.and(socket.flush().await) {
+        debug!("[hooks] answering {address} failed: {e}");
+    }
+}
+
+// Read a request, and pass it on to the node if a hook posted it as it may, or it is a note
+// for the inbox. Health checks are answered here.
+async fn take(
+    socket: &mut runtime::TcpStream,
+    routes: &Routes,
+    events: &mpsc::Sender<HookEvent>,
+) -> Result<Taken, Rejection> {
+    let mut reader = BufReader::new(socket);
+    let head = http::read_head(&mut reader)
+        .await
+        .map_err(|e| Rejection::new(400, e))?;
+    if head.path == "/health" {
+        return health(&head, routes);
+    }
+    if let Some(inbox) = &routes.inbox {
+        let actor = inbox.actor();
+        if head.path == actor.id().path() && head.method == "GET" {
+            return Ok(Taken::Document(actor.document()));
+        }
+        if head.path == actor.inbox().path() {
+            return take_note(&mut reader, &head, inbox, routes.max_body, events).await;
+        }
+    }
+    let Some(token) = head.path.strip_prefix("/hooks/") else {
+        return Err(Rejection::new(404, format!("nothing at {}", head.path)));
+    };
+    if head.method != "POST" {
+        return Err(Rejection::new(405, "hooks are posted to"));
+    }
+    let Some((name, settings)) = routes.hook(token) else {
+        return Err(Rejection::new(401, "unknown token"));
+    };
+    let Some(length) = head.content_length else {
+        return Err(Rejection::new(411, "the body needs a Content-Length"));
+    };
+    if length > settings.max_bytes {
+        return Err(Rejection::new(
+            413,
+            format!("the body is over {} bytes", settings.max_bytes),
+        ));
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| Rejection::new(400, e.to_string()))?;
+    let post = routes.admit(name, settings, &body)?;
+    let posted = HookEvent::Posted {
+        hook: name.to_string(),
+        nick: post.nick.unwrap_or_else(|| name.to_string()),
+        text: post.text,
+    };
+    events
+        .try_send(posted)
+        .map_err(|_| Rejection::new(503, "the node is busy, try again"))?;
+    Ok(Taken::Queued)
+}
+
+// The node's health as it last reported it: 200 when it is ready, 503 when it isn't or hasn't
+// reported yet.
+fn health(head: &RequestHead, routes: &Routes) -> Result<Taken, Rejection> {
+    if head.method != "GET" {
+        return Err(Rejection {
+            allow: "GET",
+            ..Rejection::new(405, "the health is fetched with GET")
+        });
+    }
+    let health = routes.health.lock().expect("the health isn't poisoned");
+    Ok(match &*health {
+        Some(health) => {
+            let status = if health.is_ready() { 200 } else { 503 };
+            Taken::Health(status, health.to_json().to_string())
+        }
+        None => Taken::Health(503, json!({ "ready": false }).to_string()),
+    })
+}
+
+// Read an activity posted to the inbox, and pass the note it creates on to the node if it was
+// signed by an actor that may post.
+async fn take_note(
+    reader: &mut (impl AsyncRead + Unpin),
+    head: &RequestHead,
+    inbox: &Inbox,
+    max_body: usize,
+    events: &mpsc::Sender<HookEvent>,
+) -> Result<Taken, Rejection> {
+    if head.method != "POST" {
+        return Err(Rejection::new(405, "notes are posted to the inbox"));
+    }
+    let Some(length) = head.content_length else {
+        return Err(Rejection::new(411, "the body needs a Content-Length"));
+    };
+    if length > activitypub::MAX_ACTIVITY_BYTES {
+        return Err(Rejection::new(
+            413,
+            format!("the body is over {} bytes", activitypub::MAX_ACTIVITY_BYTES),
+        ));
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| Rejection::new(400, e.to_string()))?;
+    let received = inbox
+        .receive(head, &body)
+        .await
+        .map_err(|refusal| Rejection::new(refusal.status, refusal.error))?;
+    let Some(received) = received else {
+        return Ok(Taken::Queued);
+    };
+    if received.text.len() > max_body {
+        return Err(Rejection::new(
+            413,
+            format!("the note is over {max_body} bytes"),
+        ));
+    }
+    let note = HookEvent::Note {
+        actor: received.actor,
+        text: received.text,
+    };
+    events
+        .try_send(note)
+        .map_err(|_| Rejection::new(503, "the node is busy, try again"))?;
+    Ok(Taken::Queued)
+}
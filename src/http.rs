@@ -0,0 +1,333 @@
+// Just enough HTTP/1.1 to talk JSON to web services outside the swarm, over TLS for https://
+// URLs, and to take JSON from them. One request per connection.
+use std::time::Duration;
+
+use libp2p::futures::{
+    future::Either,
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    AsyncBufRead,
+};
+use url::{Host, Url};
+
+use crate::{runtime, tls};
+
+/// How long a request may take by default, from connecting to reading the whole answer.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest answer read from a server, headers included.
+pub const MAX_RESPONSE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Longest request line and headers taken from a client.
+pub const MAX_HEAD_BYTES: u64 = 8192;
+
+/// Parse the URL of a web service, which has to be an `http://` or `https://` URL with a host.
+pub fn service_url(s: &str) -> Result<Url, String> {
+    let url = Url::parse(s).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("the URL must start with http:// or https://".to_string());
+    }
+    if url.host().is_none() {
+        return Err("the URL has no host".to_string());
+    }
+    Ok(url)
+}
+
+/// What a server answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// POST `body` as JSON to `url`. Returns the status code of the response, whatever it is.
+pub async fn post_json(url: &Url, body: &str) -> Result<u16, String> {
+    let response = request("POST", url, None, Some(body), REQUEST_TIMEOUT).await?;
+    Ok(response.status)
+}
+
+/// Send a request with an optional bearer `token` and JSON `body`, and read the answer,
+/// whatever its status, within `timeout`.
+pub async fn request(
+    method: &str,
+    url: &Url,
+    token: Option<&str>,
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<Response, String> {
+    let headers: Vec<_> = token
+        .map(|token| ("Authorization", format!("Bearer {token}")))
+        .into_iter()
+        .collect();
+    send(method, url, &headers, body, timeout).await
+}
+
+/// Send a request with extra `headers` and a `body`, JSON unless the headers give a
+/// `Content-Type`, and read the answer, whatever its status, within `timeout`.
+pub async fn send(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, String)],
+    body: Option<&str>,
+    timeout: Duration,
+) -> Result<Response, String> {
+    runtime::timeout(timeout, exchange(method, url, headers, body))
+        .await
+        .map_err(|_| "the request timed out".to_string())
+        .and_then(|response| response)
+}
+
+/// The `Host` a request for `url` is sent with: its host, and its port unless that is the
+/// scheme's default.
+pub fn authority(url: &Url) -> String {
+    match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    }
+}
+
+/// The path and query asked for in a request for `url`.
+pub fn resource(url: &Url) -> String {
+    match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    }
+}
+
+async fn exchange(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, String)],
+    body: Option<&str>,
+) -> Result<Response, String> {
+    let host = match url.host() {
+        Some(Host::Domain(domain)) => domain.to_string(),
+        Some(Host::Ipv4(ip)) => ip.to_string(),
+        Some(Host::Ipv6(ip)) => ip.to_string(),
+        None => return Err("the URL has no host".to_string()),
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+    let tcp = runtime::connect_tcp(&host, port)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut socket = if url.scheme() == "https" {
+        Either::Left(tls::connect(&host, tcp).await?)
+    } else {
+        Either::Right(tcp)
+    };
+    let mut request = format!(
+        "{method} {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         User-Agent: p2p-chat\r\n\
+         Connection: close\r\n",
+        resource(url),
+        authority(url)
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+    let body = body.unwrap_or_default();
+    if !body.is_empty() || method != "GET" {
+        let typed = headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("content-type"));
+        if !typed {
+            request.push_str("Content-Type: application/json\r\n");
+        }
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    request.push_str(body);
+    socket
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    socket.flush().await.map_err(|e| e.to_string())?;
+    read_response(&mut BufReader::new(socket.take(MAX_RESPONSE_BYTES))).await
+}
+
+/// The request line and headers of a request a client sent, its body still to be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestHead {
+    pub method: String,
+    /// The path asked for, without the query.
+    pub path: String,
+    /// The length of the body, if the client said. Bodies sent in chunks have none.
+    pub content_length: Option<usize>,
+    /// The headers, their names in lower case, in the order sent.
+    pub headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// The value of the first header called `name`, in lower case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read the head of a request, up to [`MAX_HEAD_BYTES`].
+pub async fn read_head(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<RequestHead, String> {
+    let mut reader = reader.take(MAX_HEAD_BYTES);
+    let request_line = read_line(&mut reader).await?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err("the request line is malformed".to_string());
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err("only HTTP/1 is spoken here".to_string());
+    }
+    let (mut content_length, mut headers) = (None, Vec::new());
+    loop {
+        let header = read_line(&mut reader).await?;
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err("a header is malformed".to_string());
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            let length = value
+                .trim()
+                .parse()
+                .map_err(|_| "the length is malformed")?;
+            content_length = Some(length);
+        }
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    let path = target.split('?').next().unwrap_or_default();
+    Ok(RequestHead {
+        method: method.to_string(),
+        path: path.to_string(),
+        content_length,
+        headers,
+    })
+}
+
+/// An answer with a `body`, JSON unless the extra `headers` give a `Content-Type`, after which
+/// the connection closes.
+pub fn response(status: u16, headers: &[(&str, String)], body: &str) -> String {
+    let reason = match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        503 => "Service Unavailable",
+        _ => "",
+    };
+    let mut response = format!(
+        "HTTP/1.1 {status} {reason}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n",
+        body.len()
+    );
+    let typed = headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("content-type"));
+    if !typed {
+        response.push_str("Content-Type: application/json\r\n");
+    }
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    response.push_str(body);
+    response
+}
+
+// The status line, headers and body of an answer, sent with a length, in chunks, or up to
+// the end of the connection.
+async fn read_response(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Response, String> {
+    let status_line = read_line(reader).await?;
+    let status = status_code(&status_line).ok_or("the server didn't answer with HTTP")?;
+    let (mut length, mut chunked) = (None, false);
+    loop {
+        let header = read_line(reader).await?;
+        if header.is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            length = Some(value.parse::<usize>().map_err(|e| e.to_string())?);
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        }
+    }
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            let size = read_line(reader).await?;
+            let size = size.split(';').next().unwrap_or_default().trim();
+            let size = usize::from_str_radix(size, 16).map_err(|e| e.to_string())?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            // The sizes come from the server, so they are held to the cap before allocating
+            let end = start
+                .checked_add(size)
+                .filter(|end| *end as u64 <= MAX_RESPONSE_BYTES)
+                .ok_or_else(|| format!("the answer is over {MAX_RESPONSE_BYTES} bytes"))?;
+            body.resize(end, 0);
+            reader
+                .read_exact(&mut body[start..])
+                .await
+                .map_err(|e| e.to_string())?;
+            read_line(reader).await?;
+        }
+    } else if let Some(length) = length {
+        if length as u64 > MAX_RESPONSE_BYTES {
+            return Err(format!("the answer is over {MAX_RESPONSE_BYTES} bytes"));
+        }
+        body.resize(length, 0);
+        reader
+            .read_exact(&mut body)
+            .await
+            .map_err(|e| e.to_string())?;
+    } else {
+        reader
+            .read_to_end(&mut body)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(Response { status, body })
+}
+
+// A line of the answer without its line ending. The end of the connection ends the answer.
+async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<String, String> {
+    let mut line = Vec::new();
+    if reader
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(|e| e.to_string())?
+        == 0
+    {
+        return Err("the server closed the connection".to_string());
+    }
+    let line = String::from_utf8_lossy(&line);
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// The code of a status line such as `HTTP/1.1 200 OK`.
+fn status_code(line: &str) -> Option<u16> {
+    let mut parts = line.split_whitespace();
+    parts.next()?.strip_prefix("HTTP/1.")?;
+    parts.next()?.parse().ok()
+}
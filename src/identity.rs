@@ -0,0 +1,186 @@
+// Identity keys kept on disk, and rotating them without losing the trust peers placed in them.
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use libp2p::{identity::Keypair, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{ChatError, ConfigError},
+    signed::{Signed, VerifyError},
+};
+
+/// How long after a rotation the node keeps announcing it, in seconds (a week).
+pub const ROTATION_GRACE: u64 = 7 * 24 * 60 * 60;
+
+/// Seconds between announcements of a rotation during the grace period.
+pub const ROTATION_INTERVAL: u64 = 60;
+
+/// A statement by an old identity that its owner has moved to a new one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rotation {
+    pub old: PeerId,
+    pub new: PeerId,
+    /// Unix time (seconds) of the rotation.
+    pub timestamp: u64,
+}
+
+/// A rotation, signed with the old key.
+pub type SignedRotation = Signed<Rotation>;
+
+/// Reasons an announced rotation is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RotationError {
+    BadSignature(VerifyError),
+    /// The statement was signed by a key other than the old identity it names.
+    WrongSigner(PeerId),
+    /// The statement was announced by a peer other than the new identity it names.
+    WrongAnnouncer(PeerId),
+    /// The old and new identities are the same.
+    Unchanged,
+    /// The grace period is over.
+    Expired,
+}
+
+impl fmt::Display for RotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RotationError::BadSignature(e) => write!(f, "invalid rotation signature: {e}"),
+            RotationError::WrongSigner(signer) => {
+                write!(f, "rotation was signed by {signer}, not the old key")
+            }
+            RotationError::WrongAnnouncer(announcer) => {
+                write!(f, "rotation was announced by {announcer}, not the new key")
+            }
+            RotationError::Unchanged => write!(f, "rotation keeps the same key"),
+            RotationError::Expired => write!(f, "rotation is past its grace period"),
+        }
+    }
+}
+
+impl std::error::Error for RotationError {}
+
+/// Read the identity key at `path`, generating and saving a new one if the file doesn't exist.
+pub fn load_or_create(path: &Path) -> Result<Keypair, ConfigError> {
+    if path.exists() {
+        return load(path);
+    }
+    let keypair = Keypair::generate_ed25519();
+    save(path, &keypair)?;
+    Ok(keypair)
+}
+
+/// Read the identity key at `path`.
+pub fn load(path: &Path) -> Result<Keypair, ConfigError> {
+    let bytes = fs::read(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Keypair::from_protobuf_encoding(&bytes).map_err(|e| ConfigError::Identity {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })
+}
+
+/// Replace the identity key at `path` with a fresh one. The continuity statement, signed by
+/// the old key, is saved next to the key so the node can announce it during the grace period.
+pub fn rotate(path: &Path, now: u64) -> Result<Rotation, ChatError> {
+    let old = load(path)?;
+    let new = Keypair::generate_ed25519();
+    let rotation = Rotation {
+        old: old.public().to_peer_id(),
+        new: new.public().to_peer_id(),
+        timestamp: now,
+    };
+    let signed = SignedRotation::sign(&old, &rotation)?;
+
+    // Save the statement first: if the key isn't replaced after all, the statement names a key
+    // the node doesn't have and is never announced
+    let statement = rotation_path(path);
+    let json = serde_json::to_vec_pretty(&signed).expect("rotations always serialize");
+    fs::write(&statement, json).map_err(|source| ConfigError::Write {
+        path: statement,
+        source,
+    })?;
+    save(path, &new)?;
+    Ok(rotation)
+}
+
+/// The rotation to announce for the identity at `path`: one that moved to `current` no longer
+/// than [`ROTATION_GRACE`] ago.
+pub fn pending_rotation(path: &Path, current: PeerId, now: u64) -> Option<SignedRotation> {
+    let json = fs::read(rotation_path(path)).ok()?;
+    let signed: SignedRotation = serde_json::from_slice(&json).ok()?;
+    check(&signed, current, now).ok()?;
+    Some(signed)
+}
+
+/// Check a rotation announced by `announcer` at `now`: it must be signed by the old key it
+/// names and move to the announcer itself.
+pub fn check(
+    signed: &SignedRotation,
+    announcer: PeerId,
+    now: u64,
+) -> Result<Rotation, RotationError> {
+    let (signer, rotation) = signed.verify().map_err(RotationError::BadSignature)?;
+    if signer != rotation.old {
+        return Err(RotationError::WrongSigner(signer));
+    }
+    if announcer != rotation.new {
+        return Err(RotationError::WrongAnnouncer(announcer));
+    }
+    if rotation.old == rotation.new {
+        return Err(RotationError::Unchanged);
+    }
+    if now >= rotation.timestamp.saturating_add(ROTATION_GRACE) {
+        return Err(RotationError::Expired);
+    }
+    Ok(rotation)
+}
+
+/// Where the continuity statement for the key at `path` is kept: `<path>.rotation`.
+pub fn rotation_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".rotation");
+    PathBuf::from(name)
+}
+
+// Write a key through a temporary file, so a crash never leaves a half written key behind.
+fn save(path: &Path, keypair: &Keypair) -> Result<(), ConfigError> {
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .expect("ed25519 keys always encode");
+    let mut temporary = OsString::from(path.as_os_str());
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| write_private(&temporary, &bytes))
+        .and_then(|_| fs::rename(&temporary, path))
+        .map_err(|source| ConfigError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+// Only the owner may read a secret key.
+#[cfg(unix)]
+pub(crate) fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    use std::{io::Write, os::unix::fs::OpenOptionsExt};
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(bytes)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn write_private(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    fs::write(path, bytes)
+}
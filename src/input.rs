@@ -0,0 +1,56 @@
+// User input read on a task of its own, so a flood of lines can't hold up the event loop.
+use libp2p::futures::{stream, Stream, StreamExt};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt},
+    sync::mpsc,
+};
+
+use crate::runtime;
+
+/// Lines read ahead of the event loop. Once that many are waiting, reading stops until the
+/// loop takes some, so a large file piped in is only read as fast as it is sent.
+pub const INPUT_QUEUE: usize = 64;
+
+/// Read lines from `reader` on a task of their own. The stream ends with the input or at the
+/// first read error.
+pub fn spawn_lines<R>(reader: R) -> impl Stream<Item = String>
+where
+    R: AsyncBufRead + Unpin + Send + 'static,
+{
+    let lines = stream::unfold(reader.lines(), |mut lines| async move {
+        let line = lines.next_line().await.ok().flatten()?;
+        Some((line, lines))
+    });
+    forward(lines)
+}
+
+/// Read the lines typed on stdin on a task of their own, the way the runtime reads stdin.
+pub fn spawn_stdin() -> impl Stream<Item = String> {
+    #[cfg(not(feature = "async-std"))]
+    return spawn_lines(tokio::io::BufReader::new(tokio::io::stdin()));
+    #[cfg(feature = "async-std")]
+    {
+        use async_std::io::{prelude::BufReadExt, stdin, BufReader};
+        let lines = stream::unfold(BufReader::new(stdin()).lines(), |mut lines| async move {
+            let line = lines.next().await?.ok()?;
+            Some((line, lines))
+        });
+        forward(lines)
+    }
+}
+
+fn forward(lines: impl Stream<Item = String> + Send + 'static) -> impl Stream<Item = String> {
+    let (sender, receiver) = mpsc::channel(INPUT_QUEUE);
+    runtime::spawn(async move {
+        let mut lines = std::pin::pin!(lines);
+        while let Some(line) = lines.next().await {
+            // The node is gone, nobody reads what's left
+            if sender.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|line| (line, receiver))
+    })
+}
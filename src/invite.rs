@@ -0,0 +1,132 @@
+// Signed, expiring invites to invite-only rooms.
+use std::fmt;
+
+use data_encoding::BASE32_NOPAD;
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::signed::{Signed, VerifyError};
+
+/// Prefix of invite tokens, so they are recognizable when pasted around.
+pub const TOKEN_PREFIX: &str = "p2pchat-invite:";
+
+/// Invite lifetime when `/invite create` is given none (one day).
+pub const DEFAULT_TTL: u64 = 24 * 60 * 60;
+
+/// Permission to join a room, issued and signed by the room's owner.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    /// Name of the room's topic.
+    pub room: String,
+    /// The room's moderators at the time of issue, so new members honor them right away.
+    pub moderators: Vec<PeerId>,
+    /// Only this peer may use the invite, if set.
+    pub invitee: Option<PeerId>,
+    /// Unix time (seconds) at which the invite was issued and at which it expires.
+    pub issued_at: u64,
+    pub expires_at: u64,
+}
+
+/// An invite together with the owner's signature. The signer is the room owner.
+pub type SignedInvite = Signed<Invite>;
+
+/// A peer presents its invite to the members of a room.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Join {
+    pub room: String,
+    pub invite: SignedInvite,
+}
+
+/// Reasons an invite is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InviteError {
+    /// The token is not a hex encoded invite.
+    Malformed,
+    /// The owner's signature doesn't check out.
+    BadSignature(VerifyError),
+    /// The invite is for another room.
+    WrongRoom(String),
+    /// The invite was signed by someone other than the room's owner.
+    NotOwner(PeerId),
+    /// The invite is restricted to another peer.
+    WrongInvitee,
+    Expired,
+}
+
+impl fmt::Display for InviteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InviteError::Malformed => write!(f, "malformed invite token"),
+            InviteError::BadSignature(e) => write!(f, "invalid invite signature: {e}"),
+            InviteError::WrongRoom(room) => write!(f, "invite is for room {room}"),
+            InviteError::NotOwner(signer) => {
+                write!(f, "invite was signed by {signer}, not the room owner")
+            }
+            InviteError::WrongInvitee => write!(f, "invite was issued to another peer"),
+            InviteError::Expired => write!(f, "invite has expired"),
+        }
+    }
+}
+
+impl std::error::Error for InviteError {}
+
+/// Encode a signed invite as a token that can be passed to `--join-with`.
+pub fn encode_token(invite: &SignedInvite) -> String {
+    let json = serde_json::to_vec(invite).expect("invites always serialize");
+    format!("{TOKEN_PREFIX}{}", hex::encode(json))
+}
+
+/// Encode a signed invite as the token of a QR code, shorter than [`encode_token`]'s: the
+/// prefix in upper case and the invite in base32 of its binary form, which fit the QR code's
+/// denser alphanumeric mode.
+pub fn encode_qr_token(invite: &SignedInvite) -> String {
+    format!(
+        "{}{}",
+        TOKEN_PREFIX.to_ascii_uppercase(),
+        BASE32_NOPAD.encode(&invite.to_bytes())
+    )
+}
+
+/// Decode a token produced by [`encode_token`] or [`encode_qr_token`]. The signature is not
+/// checked here.
+pub fn decode_token(token: &str) -> Result<SignedInvite, InviteError> {
+    let token = token.trim();
+    if let Some(base32) = token.strip_prefix(&TOKEN_PREFIX.to_ascii_uppercase()) {
+        let bytes = BASE32_NOPAD
+            .decode(base32.as_bytes())
+            .map_err(|_| InviteError::Malformed)?;
+        return SignedInvite::from_bytes(&bytes).ok_or(InviteError::Malformed);
+    }
+    let hex = token
+        .strip_prefix(TOKEN_PREFIX)
+        .ok_or(InviteError::Malformed)?;
+    let json = hex::decode(hex).map_err(|_| InviteError::Malformed)?;
+    serde_json::from_slice(&json).map_err(|_| InviteError::Malformed)
+}
+
+/// Check that `invite` lets `presenter` into `room` at `now`, returning the owner who signed it.
+///
+/// With `owner` set, the invite must be signed by that peer; without one (a new member that
+/// doesn't know the room yet) the signer becomes the owner.
+pub fn check(
+    invite: &SignedInvite,
+    room: &str,
+    owner: Option<PeerId>,
+    presenter: PeerId,
+    now: u64,
+) -> Result<(PeerId, Invite), InviteError> {
+    let (signer, invite) = invite.verify().map_err(InviteError::BadSignature)?;
+    if invite.room != room {
+        return Err(InviteError::WrongRoom(invite.room));
+    }
+    if owner.is_some_and(|owner| owner != signer) {
+        return Err(InviteError::NotOwner(signer));
+    }
+    if invite.invitee.is_some_and(|invitee| invitee != presenter) {
+        return Err(InviteError::WrongInvitee);
+    }
+    if now >= invite.expires_at {
+        return Err(InviteError::Expired);
+    }
+    Ok((signer, invite))
+}
@@ -0,0 +1,628 @@
+// A bridge mirroring an IRC channel: the node joins it as an IRC client, publishes what is said
+// there to the room and says there what is said in the room.
+//
+// IRC lines carry nothing but text, so the chat messages the bridge publishes are marked with
+// an origin instead, and no bridge copies a marked message back out.
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use futures_rustls::client::TlsStream;
+use libp2p::futures::{
+    future::Either,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, WriteHalf},
+    AsyncReadExt,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::{runtime, tls};
+
+/// The origin of the chat messages copied in from IRC.
+pub const ORIGIN: &str = "irc";
+
+/// Longest IRC line, with its CR LF.
+pub const MAX_LINE: usize = 512;
+
+/// Room left in every line for the `:nick!user@host ` source the server puts in front of it
+/// when passing it on.
+pub const SOURCE_RESERVE: usize = 128;
+
+/// Lines waiting for the server. Once that many are, more are refused until some go out.
+pub const QUEUE: usize = 256;
+
+/// First wait before connecting again after losing the server, doubled after each failure.
+pub const RECONNECT_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait before connecting again.
+pub const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// How long connecting, including the TLS handshake, may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval of the pings checking the server is still there. It is given up on when nothing
+/// came from it for two intervals.
+pub const PING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Port of IRC servers without TLS.
+pub const PORT: u16 = 6667;
+
+/// Port of IRC servers with TLS.
+pub const TLS_PORT: u16 = 6697;
+
+/// The IRC channel a node mirrors, saved in the `irc` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IrcSettings {
+    /// The server, as `host` or `host:port`.
+    pub server: String,
+    /// Whether to connect over TLS.
+    #[serde(default)]
+    pub tls: bool,
+    /// The channel, with its `#`.
+    pub channel: String,
+    /// The nick the bridge asks for. An `_` is added to it for as long as it is taken.
+    pub nick: String,
+    /// Whether joins and parts on either side are announced on the other.
+    #[serde(default)]
+    pub announce: bool,
+}
+
+impl IrcSettings {
+    /// The host and port of the server, [`TLS_PORT`] or [`PORT`] when none is given.
+    pub fn address(&self) -> Result<(String, u16), String> {
+        let default = if self.tls { TLS_PORT } else { PORT };
+        let (host, port) = match self.server.rsplit_once(':') {
+            // A bare IPv6 address has colons but no port
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("{port} isn't a port number"))?;
+                (host, port)
+            }
+            _ => (self.server.as_str(), default),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err("the IRC server has no host".to_string());
+        }
+        Ok((host.to_string(), port))
+    }
+
+    /// Check the settings before connecting with them.
+    pub fn check(&self) -> Result<(), String> {
+        self.address()?;
+        let valid_channel = self.channel.starts_with(['#', '&'])
+            && self.channel.len() > 1
+            && !self.channel.contains([' ', ',', '\x07', '\r', '\n']);
+        if !valid_channel {
+            return Err(format!("{} isn't a channel name", self.channel));
+        }
+        if !valid_nick(&self.nick) {
+            return Err(format!("{} isn't a valid IRC nick", self.nick));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `nick` is a nick IRC servers take: letters, digits and `-_[]\\`^{}|`, not starting
+/// with a digit or `-`.
+pub fn valid_nick(nick: &str) -> bool {
+    !nick.is_empty()
+        && !nick.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+        && nick
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_[]\\`^{}|".contains(c))
+}
+
+/// An IRC message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Who sent it, as `nick!user@host` or a server name.
+    pub source: Option<String>,
+    /// The command or three digit reply, in upper case.
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl Message {
+    pub fn new(command: &str, params: &[&str]) -> Self {
+        Message {
+            source: None,
+            command: command.to_string(),
+            params: params.iter().map(|param| param.to_string()).collect(),
+        }
+    }
+
+    /// Parse one line, with or without its line ending. Message tags are skipped.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']);
+        if rest.starts_with('@') {
+            rest = rest.split_once(' ')?.1;
+        }
+        rest = rest.trim_start_matches(' ');
+        let source = match rest.strip_prefix(':') {
+            Some(prefixed) => {
+                let (source, after) = prefixed.split_once(' ')?;
+                rest = after;
+                Some(source.to_string())
+            }
+            None => None,
+        };
+        rest = rest.trim_start_matches(' ');
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return None;
+        }
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+            let (param, after) = rest.split_once(' ').unwrap_or((rest, ""));
+            params.push(param.to_string());
+            rest = after;
+        }
+        Some(Message {
+            source,
+            command: command.to_ascii_uppercase(),
+            params,
+        })
+    }
+
+    /// The nick of the sender, when a user sent it.
+    pub fn nick(&self) -> Option<&str> {
+        let source = self.source.as_deref()?;
+        Some(source.split(['!', '@']).next().unwrap_or(source))
+    }
+
+    /// Parameter `n`, or nothing when there aren't that many.
+    pub fn param(&self, n: usize) -> &str {
+        self.params.get(n).map_or("", String::as_str)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(source) = &self.source {
+            write!(f, ":{source} ")?;
+        }
+        write!(f, "{}", self.command)?;
+        if let Some((last, params)) = self.params.split_last() {
+            for param in params {
+                write!(f, " {param}")?;
+            }
+            if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+                write!(f, " :{last}")?;
+            } else {
+                write!(f, " {last}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `text` without the mIRC bold, color, italics, underline and reverse codes.
+pub fn strip_formatting(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x02' | '\x0f' | '\x11' | '\x16' | '\x1d' | '\x1e' | '\x1f' => {}
+            // A color code is followed by up to two digits, then maybe a comma and two more
+            '\x03' => {
+                for _ in 0..2 {
+                    chars.next_if(char::is_ascii_digit);
+                }
+                let mut ahead = chars.clone();
+                if ahead.next() == Some(',') && ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+                    chars.next();
+                    for _ in 0..2 {
+                        chars.next_if(char::is_ascii_digit);
+                    }
+                }
+            }
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Split one line of text into pieces of at most `max` bytes, breaking at spaces where it
+/// can and between characters where it can't.
+pub fn split(text: &str, max: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        if rest.len() <= max {
+            pieces.push(rest);
+            break;
+        }
+        let mut end = max;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        // A character longer than `max` still goes out on its own
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let piece = match rest[..end].rfind(' ') {
+            Some(space) if space > 0 => &rest[..space],
+            _ => &rest[..end],
+        };
+        pieces.push(piece.trim_end());
+        rest = rest[piece.len()..].trim_start();
+    }
+    pieces
+}
+
+/// The `command` (`PRIVMSG` or `NOTICE`) messages saying `text` on `channel`, every line of
+/// it starting with `prefix`. Long lines are split so none is over [`MAX_LINE`] once the
+/// server has put the sender in front of it.
+pub fn say(command: &str, channel: &str, prefix: &str, text: &str) -> Vec<Message> {
+    let overhead = SOURCE_RESERVE + command.len() + channel.len() + prefix.len() + 5;
+    let max = MAX_LINE.saturating_sub(overhead).max(1);
+    text.lines()
+        .map(|line| line.replace(['\r', '\0'], ""))
+        .flat_map(|line| {
+            split(&line, max)
+                .into_iter()
+                .map(|piece| Message::new(command, &[channel, &format!("{prefix}{piece}")]))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// What happens on the connection to the IRC server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IrcEvent {
+    /// Connected, or connected again, and joined the channel as `nick`.
+    Connected { nick: String },
+    /// The nick asked for is taken, so `trying` is asked for instead.
+    NickInUse { taken: String, trying: String },
+    /// The connection was lost or couldn't be made. Connecting is tried again with backoff,
+    /// and this isn't repeated until a connection was made.
+    Disconnected(String),
+    /// Someone said `text` on the channel.
+    Message { nick: String, text: String },
+    /// Someone did something on the channel with `/me`.
+    Action { nick: String, text: String },
+    /// Someone joined the channel.
+    Joined(String),
+    /// Someone left the channel or IRC, or was kicked out.
+    Left { nick: String, reason: String },
+}
+
+// Text to say on the channel once joined.
+#[derive(Debug)]
+struct Outgoing {
+    command: &'static str,
+    prefix: String,
+    text: String,
+}
+
+/// The connection to an IRC channel, kept on a task of its own that reconnects whenever it
+/// is lost. Dropping it closes the connection.
+#[derive(Debug)]
+pub struct IrcBridge {
+    settings: IrcSettings,
+    outgoing: mpsc::Sender<Outgoing>,
+    incoming: mpsc::Receiver<IrcEvent>,
+    task: runtime::Task<()>,
+}
+
+impl IrcBridge {
+    /// Connect to the server and channel of `settings`.
+    pub fn spawn(settings: IrcSettings) -> Self {
+        let (outgoing, queue) = mpsc::channel(QUEUE);
+        let (events, incoming) = mpsc::channel(QUEUE);
+        let task = runtime::spawn(run(settings.clone(), queue, events));
+        IrcBridge {
+            settings,
+            outgoing,
+            incoming,
+            task,
+        }
+    }
+
+    pub fn settings(&self) -> &IrcSettings {
+        &self.settings
+    }
+
+    /// Say `text` on the channel for `nick`, each line starting with `<nick>`. It goes out as
+    /// soon as the channel is joined. Fails while [`QUEUE`] messages are waiting.
+    pub fn relay(&self, nick: &str, text: &str) -> Result<(), String> {
+        self.queue("PRIVMSG", format!("<{nick}> "), text)
+    }
+
+    /// Tell the channel something happened in the room, with a notice rather than a message
+    /// as IRC asks of bots.
+    pub fn announce(&self, text: &str) -> Result<(), String> {
+        self.queue("NOTICE", "* ".to_string(), text)
+    }
+
+    fn queue(&self, command: &'static str, prefix: String, text: &str) -> Result<(), String> {
+        let outgoing = Outgoing {
+            command,
+            prefix,
+            text: text.to_string(),
+        };
+        self.outgoing
+            .try_send(outgoing)
+            .map_err(|_| format!("{QUEUE} messages are already waiting for the IRC server"))
+    }
+
+    /// The next thing that happened on the connection.
+    pub async fn next(&mut self) -> Option<IrcEvent> {
+        self.incoming.recv().await
+    }
+}
+
+impl Drop for IrcBridge {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+type Socket = Either<TlsStream<runtime::TcpStream>, runtime::TcpStream>;
+
+// Keep connected to the channel until the node goes away.
+async fn run(
+    settings: IrcSettings,
+    mut queue: mpsc::Receiver<Outgoing>,
+    events: mpsc::Sender<IrcEvent>,
+) {
+    let mut backoff = RECONNECT_MIN;
+    let mut reported = false;
+    loop {
+        let connected = runtime::timeout(CONNECT_TIMEOUT, connect(&settings))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|connected| connected);
+        let error = match connected {
+            Ok(socket) => {
+                let session = Session {
+                    settings: &settings,
+                    events: &events,
+                    nick: settings.nick.clone(),
+                    joined: false,
+                };
+                let (error, joined) = session.run(socket, &mut queue).await;
+                if joined {
+                    backoff = RECONNECT_MIN;
+                    reported = false;
+                }
+                error
+            }
+            Err(e) => e,
+        };
+        if events.is_closed() {
+            return;
+        }
+        debug!(
+            "[irc] {}: {error}, connecting again in {backoff:?}",
+            settings.server
+        );
+        if !reported {
+            reported = true;
+            let _ = events.send(IrcEvent::Disconnected(error)).await;
+        }
+        runtime::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+async fn connect(settings: &IrcSettings) -> Result<Socket, String> {
+    let (host, port) = settings.address()?;
+    let tcp = runtime::connect_tcp(&host, port)
+        .await
+        .map_err(|e| e.to_string())?;
+    if settings.tls {
+        Ok(Either::Left(tls::connect(&host, tcp).await?))
+    } else {
+        Ok(Either::Right(tcp))
+    }
+}
+
+// One connection to the server.
+struct Session<'a> {
+    settings: &'a IrcSettings,
+    events: &'a mpsc::Sender<IrcEvent>,
+    // Our nick on the server, which may have `_` added
+    nick: String,
+    joined: bool,
+}
+
+impl Session<'_> {
+    // Register and join the channel, then pass what is said there on to the node and what the
+    // node has to say to the channel, until the connection fails. Returns why it did, and
+    // whether the channel was joined.
+    async fn run(mut self, socket: Socket, queue: &mut mpsc::Receiver<Outgoing>) -> (String, bool) {
+        let (reader, mut writer) = socket.split();
+        let mut reader = BufReader::new(reader);
+        let register = [
+            Message::new("NICK", &[&self.nick]),
+            Message::new("USER", &[&self.settings.nick, "0", "*", "p2p-chat bridge"]),
+        ];
+        if let Err(e) = write(&mut writer, &register).await {
+            return (e, false);
+        }
+        let mut line = Vec::new();
+        let mut ping = runtime::interval(PING_INTERVAL);
+        // The first tick is straight away, before the server could have been silent
+        ping.tick().await;
+        let mut heard = Instant::now();
+        let error = loop {
+            tokio::select! {
+                // A line cut off here stays in the buffer, to be finished next time round
+                read = reader.read_until(b'\n', &mut line) => {
+                    match read {
+                        Ok(0) => break "the server closed the connection".to_string(),
+                        Ok(_) => {}
+                        Err(e) => break e.to_string(),
+                    }
+                    heard = Instant::now();
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    line.clear();
+                    let Some(message) = Message::parse(&text) else {
+                        continue;
+                    };
+                    if let Err(e) = self.handle(message, &mut writer).await {
+                        break e;
+                    }
+                }
+                outgoing = queue.recv(), if self.joined => {
+                    let Some(outgoing) = outgoing else {
+                        break "the node went away".to_string();
+                    };
+                    let messages = say(
+                        outgoing.command,
+                        &self.settings.channel,
+                        &outgoing.prefix,
+                        &outgoing.text,
+                    );
+                    if let Err(e) = write(&mut writer, &messages).await {
+                        break e;
+                    }
+                }
+                _ = ping.tick() => {
+                    if heard.elapsed() > 2 * PING_INTERVAL {
+                        break "the server stopped answering".to_string();
+                    }
+                    if let Err(e) = write(&mut writer, &[Message::new("PING", &["p2p-chat"])]).await {
+                        break e;
+                    }
+                }
+            }
+        };
+        (error, self.joined)
+    }
+
+    // Answer a message from the server, and tell the node about what happened on the channel.
+    // Fails when the connection has to be given up.
+    async fn handle(
+        &mut self,
+        message: Message,
+        writer: &mut WriteHalf<Socket>,
+    ) -> Result<(), String> {
+        let channel = self.settings.channel.as_str();
+        let from = message.nick().unwrap_or_default().to_string();
+        let ours = from.eq_ignore_ascii_case(&self.nick);
+        let here = message.param(0).eq_ignore_ascii_case(channel);
+        let event = match message.command.as_str() {
+            "PING" => {
+                let mut pong = message.clone();
+                pong.source = None;
+                pong.command = "PONG".to_string();
+                write(writer, &[pong]).await?;
+                None
+            }
+            // Welcome: registered, under the nick the server confirms
+            "001" => {
+                self.nick = message.param(0).to_string();
+                write(writer, &[Message::new("JOIN", &[channel])]).await?;
+                None
+            }
+            // The nick is taken or held back for now
+            "433" | "436" | "437" if !self.joined => {
+                let taken = std::mem::take(&mut self.nick);
+                self.nick = format!("{taken}_");
+                write(writer, &[Message::new("NICK", &[&self.nick])]).await?;
+                Some(IrcEvent::NickInUse {
+                    taken,
+                    trying: self.nick.clone(),
+                })
+            }
+            "432" => {
+                return Err(format!(
+                    "the server refuses the nick {}: {}",
+                    self.nick,
+                    message.param(2)
+                ));
+            }
+            // The channel is full, invite-only, banning us, keyed or doesn't exist
+            "403" | "405" | "471" | "473" | "474" | "475" => {
+                let reason = message.params.last().map_or("", String::as_str);
+                return Err(format!("can't join {channel}: {reason}"));
+            }
+            "ERROR" => {
+                return Err(format!(
+                    "the server closed the connection: {}",
+                    message.param(0)
+                ))
+            }
+            "NICK" if ours => {
+                self.nick = message.param(0).to_string();
+                None
+            }
+            "JOIN" if here && ours => {
+                self.joined = true;
+                Some(IrcEvent::Connected {
+                    nick: self.nick.clone(),
+                })
+            }
+            "JOIN" if here => Some(IrcEvent::Joined(from)),
+            "PART" if here && !ours => Some(IrcEvent::Left {
+                nick: from,
+                reason: message.param(1).to_string(),
+            }),
+            "QUIT" if !ours => Some(IrcEvent::Left {
+                nick: from,
+                reason: message.param(0).to_string(),
+            }),
+            "KICK" if here => {
+                let kicked = message.param(1);
+                let reason = format!("kicked by {from}: {}", message.param(2));
+                if kicked.eq_ignore_ascii_case(&self.nick) {
+                    return Err(reason);
+                }
+                Some(IrcEvent::Left {
+                    nick: kicked.to_string(),
+                    reason,
+                })
+            }
+            "PRIVMSG" if here && !ours => {
+                let text = message.param(1);
+                match text.strip_prefix('\x01') {
+                    // Only actions among the client-to-client requests are chat
+                    Some(ctcp) => {
+                        ctcp.trim_end_matches('\x01')
+                            .strip_prefix("ACTION ")
+                            .map(|action| IrcEvent::Action {
+                                nick: from,
+                                text: strip_formatting(action),
+                            })
+                    }
+                    None => Some(IrcEvent::Message {
+                        nick: from,
+                        text: strip_formatting(text),
+                    }),
+                }
+            }
+            _ => None,
+        };
+        if let Some(event) = event {
+            self.events
+                .send(event)
+                .await
+                .map_err(|_| "the node went away".to_string())?;
+        }
+        Ok(())
+    }
+}
+
+async fn write(writer: &mut WriteHalf<Socket>, messages: &[Message]) -> Result<(), String> {
+    for message in messages {
+        writer
+            .write_all(format!("{message}\r\n").as_bytes())
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    writer.flush().await.map_err(|e| e.to_string())
+}
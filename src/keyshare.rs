@@ -0,0 +1,346 @@
+// Recovery of a passphrase room's key from shares held by trusted peers. The key is split with
+// Shamir's Secret Sharing over GF(256), so any `threshold` of the shares rebuild it and fewer
+// tell nothing about it. Each share travels, and is kept, sealed to its holder's identity key:
+// the Ed25519 key is taken as an X25519 one, and the share encrypted with XChaCha20-Poly1305
+// under a key agreed with a fresh ephemeral key.
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use curve25519_dalek::{edwards::CompressedEdwardsY, montgomery::MontgomeryPoint};
+use hkdf::Hkdf;
+use std::path::{Path, PathBuf};
+
+use libp2p::{gossipsub, identity::Keypair, PeerId};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use uuid::Uuid;
+
+use crate::{
+    node,
+    signed::{self, Signed},
+};
+
+/// Most shares a key is split into; each needs its own nonzero point of GF(256).
+pub const MAX_SHARES: usize = 255;
+
+/// Shares of other peers' keys kept at most; the oldest go first.
+pub const MAX_HELD: usize = 256;
+
+/// How far a recovery request's timestamp may be from our clock, in seconds, before it is
+/// taken for a replay and left unanswered.
+pub const REQUEST_MAX_AGE: u64 = 300;
+
+/// Context mixed into the key agreed for a sealed share.
+const KDF_INFO: &[u8] = b"p2p-chat keyshare v1";
+
+/// One share of a secret: the point `x` and the value of each byte's polynomial there.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub x: u8,
+    #[serde(with = "hex")]
+    pub y: Vec<u8>,
+}
+
+/// Split `secret` into `count` shares, any `threshold` of which rebuild it.
+pub fn split(secret: &[u8], threshold: usize, count: usize) -> Result<Vec<Share>, String> {
+    if threshold == 0 || threshold > count {
+        return Err(format!("a threshold of {threshold} with {count} shares"));
+    }
+    if count > MAX_SHARES {
+        return Err(format!("at most {MAX_SHARES} shares"));
+    }
+    let mut shares: Vec<Share> = (1..=count as u8)
+        .map(|x| Share {
+            x,
+            y: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+    // A random polynomial per byte, the byte being its constant term
+    let mut coefficients = vec![0; threshold];
+    for &byte in secret {
+        coefficients[0] = byte;
+        OsRng.fill_bytes(&mut coefficients[1..]);
+        for share in &mut shares {
+            let y = coefficients
+                .iter()
+                .rev()
+                .fold(0, |acc, &c| gf_mul(acc, share.x) ^ c);
+            share.y.push(y);
+        }
+    }
+    Ok(shares)
+}
+
+/// Rebuild a secret from `shares` by interpolating each byte's polynomial at zero. Fewer shares
+/// than the threshold give a wrong secret rather than an error, so check what comes out.
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>, String> {
+    let len = match shares {
+        [] => return Err("no shares".to_string()),
+        [first, ..] => first.y.len(),
+    };
+    for (i, share) in shares.iter().enumerate() {
+        if share.x == 0 || share.y.len() != len {
+            return Err(format!("a malformed share at {}", share.x));
+        }
+        if shares[..i].iter().any(|earlier| earlier.x == share.x) {
+            return Err(format!("the share at {} twice", share.x));
+        }
+    }
+    // The Lagrange basis polynomials at zero, one per share
+    let basis: Vec<u8> = shares
+        .iter()
+        .map(|share| {
+            shares
+                .iter()
+                .filter(|other| other.x != share.x)
+                .fold(1, |acc, other| {
+                    gf_mul(acc, gf_mul(other.x, gf_inv(other.x ^ share.x)))
+                })
+        })
+        .collect();
+    Ok((0..len)
+        .map(|i| {
+            shares
+                .iter()
+                .zip(&basis)
+                .fold(0, |acc, (share, &l)| acc ^ gf_mul(share.y[i], l))
+        })
+        .collect())
+}
+
+// Multiplication in GF(256) modulo x⁸ + x⁴ + x³ + x + 1, as AES has it.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+// The inverse of a nonzero element: a²⁵⁴, as a²⁵⁵ is one.
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    for _ in 0..254 {
+        result = gf_mul(result, a);
+    }
+    result
+}
+
+/// A share sealed to one peer's identity key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SealedShare {
+    /// The ephemeral X25519 key the sealing key was agreed with.
+    #[serde(with = "hex")]
+    pub ephemeral: [u8; 32],
+    #[serde(with = "hex")]
+    pub nonce: Vec<u8>,
+    #[serde(with = "hex")]
+    pub ciphertext: Vec<u8>,
+}
+
+impl SealedShare {
+    /// Seal `share` so only `recipient` can open it. Only peers whose id inlines an Ed25519 key
+    /// can be sealed to.
+    pub fn seal(share: &Share, recipient: &PeerId) -> Result<Self, String> {
+        let public = x25519_public(recipient)?;
+        let mut secret = [0; 32];
+        OsRng.fill_bytes(&mut secret);
+        let ephemeral = MontgomeryPoint::mul_base_clamped(secret).to_bytes();
+        let shared = public.mul_clamped(secret).to_bytes();
+        let cipher = sealing_cipher(&shared, &ephemeral, &public.to_bytes());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_vec(share).expect("shares always serialize");
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .expect("shares are far below the cipher's length limit");
+        Ok(SealedShare {
+            ephemeral,
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+    }
+
+    /// Open a share sealed to the owner of `keypair`.
+    pub fn open(&self, keypair: &Keypair) -> Result<Share, String> {
+        let secret = x25519_secret(keypair)?;
+        let public = MontgomeryPoint::mul_base_clamped(secret).to_bytes();
+        let shared = MontgomeryPoint(self.ephemeral)
+            .mul_clamped(secret)
+            .to_bytes();
+        if self.nonce.len() != 24 {
+            return Err("a malformed nonce".to_string());
+        }
+        let plaintext = sealing_cipher(&shared, &self.ephemeral, &public)
+            .decrypt(XNonce::from_slice(&self.nonce), self.ciphertext.as_slice())
+            .map_err(|_| "the share isn't sealed to us".to_string())?;
+        serde_json::from_slice(&plaintext).map_err(|e| format!("a malformed share: {e}"))
+    }
+}
+
+// The cipher of a share sealed with the agreed secret `shared`, bound to both public keys.
+fn sealing_cipher(
+    shared: &[u8; 32],
+    ephemeral: &[u8; 32],
+    recipient: &[u8; 32],
+) -> XChaCha20Poly1305 {
+    let hkdf = Hkdf::<Sha256>::new(Some(&[ephemeral.as_slice(), recipient].concat()), shared);
+    let mut key = [0; 32];
+    hkdf.expand(KDF_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF output length");
+    XChaCha20Poly1305::new(&key.into())
+}
+
+// A peer's Ed25519 identity key as the X25519 key on the same curve.
+fn x25519_public(peer: &PeerId) -> Result<MontgomeryPoint, String> {
+    let key = signed::peer_public_key(peer)
+        .and_then(|key| key.try_into_ed25519().ok())
+        .ok_or_else(|| format!("{peer} has no Ed25519 key in its id"))?;
+    CompressedEdwardsY(key.to_bytes())
+        .decompress()
+        .map(|point| point.to_montgomery())
+        .ok_or_else(|| format!("{peer} has an invalid key"))
+}
+
+// The X25519 scalar of an Ed25519 identity: the first half of the hashed seed, as Ed25519 signs
+// with it.
+fn x25519_secret(keypair: &Keypair) -> Result<[u8; 32], String> {
+    let keypair = keypair
+        .clone()
+        .try_into_ed25519()
+        .map_err(|_| "only Ed25519 identities can hold shares".to_string())?;
+    let hash = Sha512::digest(keypair.secret().as_ref());
+    let mut secret = [0; 32];
+    secret.copy_from_slice(&hash[..32]);
+    Ok(secret)
+}
+
+/// What goes on the key share topic, signed by its sender.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyShareMessage {
+    /// The signer hands `holder` a share of the key of `room`.
+    Deal {
+        holder: PeerId,
+        #[serde(flatten)]
+        share: HeldShare,
+    },
+    /// The signer asks the holders of its shares for them back.
+    Request { timestamp: u64 },
+    /// The signer, a holder, hands `owner` back its share.
+    Answer {
+        owner: PeerId,
+        #[serde(flatten)]
+        share: HeldShare,
+    },
+}
+
+/// A key share message, signed by its sender.
+pub type SignedKeyShare = Signed<KeyShareMessage>;
+
+/// A share as it is dealt, kept and handed back: sealed to whoever is meant to open it, with
+/// the room it is of and how many shares it takes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HeldShare {
+    /// The split the share is from, so shares of different splits aren't combined.
+    pub set: Uuid,
+    /// The topic of the room whose key was split.
+    pub room: String,
+    pub threshold: u8,
+    pub sealed: SealedShare,
+}
+
+/// A share of someone else's key, kept in the config file until its owner asks for it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct KeptShare {
+    pub owner: PeerId,
+    #[serde(flatten)]
+    pub share: HeldShare,
+}
+
+/// Keep the share `owner` dealt us, replacing any earlier one of the same room. Returns whether
+/// it replaced one.
+pub fn keep(kept: &mut Vec<KeptShare>, owner: PeerId, share: HeldShare) -> bool {
+    let earlier = kept
+        .iter()
+        .position(|k| k.owner == owner && k.share.room == share.room);
+    let replaced = earlier.map(|at| kept.remove(at)).is_some();
+    if kept.len() >= MAX_HELD {
+        kept.remove(0);
+    }
+    kept.push(KeptShare { owner, share });
+    replaced
+}
+
+/// Shares handed back to us during a recovery, by split, until one has enough of them.
+#[derive(Debug, Default)]
+pub struct Recovery {
+    sets: Vec<(HeldShare, Vec<Share>)>,
+}
+
+impl Recovery {
+    /// Take in a share handed back, opened with our `keypair`. Returns the room and its secret
+    /// once a split has as many shares as it takes.
+    pub fn add(
+        &mut self,
+        keypair: &Keypair,
+        held: HeldShare,
+    ) -> Result<Option<(String, Vec<u8>)>, String> {
+        let share = held.sealed.open(keypair)?;
+        let at = match self
+            .sets
+            .iter()
+            .position(|(first, _)| first.set == held.set)
+        {
+            Some(at) => at,
+            None if self.sets.len() >= MAX_SHARES => return Err("too many splits".to_string()),
+            None => {
+                self.sets.push((held, Vec::new()));
+                self.sets.len() - 1
+            }
+        };
+        let (first, shares) = &mut self.sets[at];
+        if shares.iter().any(|s| s.x == share.x) {
+            return Ok(None);
+        }
+        shares.push(share);
+        if shares.len() < first.threshold as usize {
+            return Ok(None);
+        }
+        let secret = combine(shares)?;
+        Ok(Some((first.room.clone(), secret)))
+    }
+
+    /// How many shares of the split `set` came back.
+    pub fn count(&self, set: &Uuid) -> usize {
+        self.sets
+            .iter()
+            .find(|(first, _)| first.set == *set)
+            .map_or(0, |(_, shares)| shares.len())
+    }
+}
+
+/// Where `/recover-key` saves a key by default: beside the config file.
+pub fn path_beside(config_path: &Path) -> PathBuf {
+    config_path.with_extension("room.key")
+}
+
+/// The topic that carries key shares in the deployment whose lobby is `topic`. Its messages
+/// aren't sealed with a room key, as the peer recovering one doesn't have it.
+pub fn keyshare_topic_for(topic: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{topic}/_keyshare"))
+}
+
+/// The topic that carries key shares of the default deployment.
+pub fn keyshare_topic() -> gossipsub::IdentTopic {
+    keyshare_topic_for(node::default_topic().hash().as_str())
+}
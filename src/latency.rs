@@ -0,0 +1,107 @@
+// Peer score adjustments from ping round-trip times.
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use libp2p::{ping, PeerId};
+use tracing::trace;
+
+use crate::autoban::MAX_TRACKED_PEERS;
+
+/// Round-trip times below this earn a peer [`FAST_BONUS`] per minute.
+pub const FAST_RTT: Duration = Duration::from_millis(50);
+
+/// Round-trip times above this cost a peer [`SLOW_PENALTY`] per minute.
+pub const SLOW_RTT: Duration = Duration::from_millis(500);
+
+/// Score added per minute to a peer with a fast connection.
+pub const FAST_BONUS: f64 = 0.1;
+
+/// Score taken per minute from a peer with a slow connection.
+pub const SLOW_PENALTY: f64 = 0.05;
+
+/// Bound on a peer's accumulated adjustment either way, so that a long session can't make a
+/// peer untouchable or a permanently slow link outweigh everything else.
+pub const MAX_ADJUSTMENT: f64 = 5.0;
+
+/// Accumulates score adjustments from the latest ping round-trip time of each peer.
+///
+/// Latency isn't misbehavior, so slow peers are only ranked lower, never banned: the
+/// adjustment feeds Gossipsub's application score when scoring is on, which makes mesh
+/// maintenance prefer faster peers, and the connection manager's ranking otherwise.
+#[derive(Debug, Default)]
+pub struct PingScorer {
+    rtt: HashMap<PeerId, Duration>,
+    adjustments: HashMap<PeerId, f64>,
+    // Peers whose latest ping failed
+    failing: HashSet<PeerId>,
+}
+
+impl PingScorer {
+    /// Record the outcome of a ping; failed pings leave the last round-trip time in place.
+    pub fn handle(&mut self, event: &ping::Event) {
+        match &event.result {
+            Ok(rtt) => self.record(event.peer, *rtt),
+            Err(_) if self.failing.len() < MAX_TRACKED_PEERS => {
+                self.failing.insert(event.peer);
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Record a successful ping of `peer`.
+    pub fn record(&mut self, peer: PeerId, rtt: Duration) {
+        if self.rtt.len() < MAX_TRACKED_PEERS || self.rtt.contains_key(&peer) {
+            self.rtt.insert(peer, rtt);
+        }
+        self.failing.remove(&peer);
+    }
+
+    /// Forget a peer that disconnected.
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.rtt.remove(peer);
+        self.adjustments.remove(peer);
+        self.failing.remove(peer);
+    }
+
+    /// Apply one minute's worth of adjustments from the latest round-trip times, returning the
+    /// peers whose adjustment changed.
+    pub fn minute(&mut self) -> Vec<PeerId> {
+        let mut changed = Vec::new();
+        for (peer, rtt) in &self.rtt {
+            let delta = if *rtt < FAST_RTT {
+                FAST_BONUS
+            } else if *rtt > SLOW_RTT {
+                -SLOW_PENALTY
+            } else {
+                continue;
+            };
+            let adjustment = self.adjustments.entry(*peer).or_default();
+            let updated = (*adjustment + delta).clamp(-MAX_ADJUSTMENT, MAX_ADJUSTMENT);
+            if updated != *adjustment {
+                trace!(
+                    "[ping] {peer}: rtt {rtt:?}, score adjustment {adjustment:.2} -> {updated:.2}"
+                );
+                *adjustment = updated;
+                changed.push(*peer);
+            }
+        }
+        changed
+    }
+
+    /// The latest round-trip time to `peer`.
+    pub fn rtt(&self, peer: &PeerId) -> Option<Duration> {
+        self.rtt.get(peer).copied()
+    }
+
+    /// Whether the latest ping of `peer` failed.
+    pub fn is_failing(&self, peer: &PeerId) -> bool {
+        self.failing.contains(peer)
+    }
+
+    /// The score adjustment accumulated for `peer`, zero if it has none.
+    pub fn adjustment(&self, peer: &PeerId) -> f64 {
+        self.adjustments.get(peer).copied().unwrap_or_default()
+    }
+}
@@ -0,0 +1,173 @@
+//! Peer-to-peer chat built on libp2p Gossipsub and mDNS.
+
+// Cross-posting the room to the fediverse over ActivityPub, and notes from it to the room.
+pub mod activitypub;
+// Peers allowed to connect, read from `--allowlist-file`.
+pub mod allowlist;
+// Files attached to chat messages by content id, and the protocol fetching them.
+pub mod attachment;
+// Append-only log of security-relevant events.
+pub mod audit;
+// Custom authentication of peers before their messages are accepted.
+pub mod auth;
+// Temporary bans for peers that flood or send invalid messages.
+pub mod autoban;
+// The cap on outbound bandwidth from `--max-upload-kbps`.
+pub mod bandwidth;
+// Small chat messages batched into one Gossipsub message.
+pub mod batch;
+// The persisted list of every block and ban.
+pub mod bans;
+// Throughput and latency benchmarks between local nodes.
+pub mod bench;
+// The room's shared drawing canvas, merged cell by cell.
+pub mod canvas;
+// Each room's bulletin board of long-lived posts.
+pub mod board;
+// Local block list and blocklists shared between trusted peers.
+pub mod blocklist;
+// Peers dialed at startup, from `--bootstrap` or DNS TXT records of `--bootstrap-domain`.
+pub mod bootstrap;
+// The chat node driving the swarm from user input and swarm events.
+pub mod chat;
+// Telling apart peers online under the same nick.
+pub mod collision;
+// Command line flags.
+pub mod cli;
+// Wall clock helpers.
+pub mod clock;
+// Settings persisted in the config file.
+pub mod config;
+// Slash commands typed on stdin.
+pub mod commands;
+// Which peers to disconnect first when there are too many.
+pub mod connections;
+// The user's saved contacts.
+pub mod contacts;
+// Message formats peers accept, and the one each is sent.
+pub mod content_type;
+// Signed control messages exchanged on a dedicated topic.
+pub mod control;
+// Room bans decided by a quorum of moderators.
+pub mod decision;
+// File writes done off the event loop.
+pub mod disk;
+// Do not disturb mode, saved between runs.
+pub mod dnd;
+// Checks of sockets, multicast, the clock and the config directory, for `doctor` and `/doctor`.
+pub mod doctor;
+// Messages carried from node to node over a simulated Bluetooth link when there is no internet.
+pub mod dtn;
+// Error types of the public API.
+pub mod error;
+// Client-side display filters for chat messages.
+pub mod filter;
+// Collapsing of messages a peer keeps repeating.
+pub mod flood;
+// Chat messages split into fragments and put back together.
+pub mod fragment;
+// An IRC server letting local IRC clients chat in the room.
+pub mod gateway;
+// The Gossipsub operations event handlers use, mockable in tests.
+pub mod gossip;
+// An HTTP server where CI and alerting systems post messages for the room.
+pub mod hooks;
+// Just enough HTTP to post JSON to web services and take JSON from them.
+pub mod http;
+// Identity keys on disk and signed key rotations.
+pub mod identity;
+// User input read ahead of the event loop on a task of its own.
+pub mod input;
+// Signed invites to invite-only rooms.
+pub mod invite;
+// A bridge mirroring an IRC channel into the room and the room into the channel.
+pub mod irc;
+// Shares of a passphrase room's key, dealt to trusted peers to recover it from.
+pub mod keyshare;
+// Peer score adjustments from ping round-trip times.
+pub mod latency;
+// Ceilings on in-memory state and how close to them it is.
+pub mod limits;
+// Where log notices go: stderr, the systemd journal, syslog or a file.
+pub mod logging;
+// Connections that died without closing, judged from pings, silence and timeouts.
+pub mod liveness;
+// Simulated packet loss and latency, in debug builds.
+#[cfg(debug_assertions)]
+pub mod lossy;
+// A bridge mirroring a Matrix room into the room and the room into the Matrix room.
+pub mod matrix;
+// Joins and leaves summarized per room for the terminal.
+pub mod membership;
+// A bridge mirroring MQTT topics into the room and the room into MQTT topics.
+pub mod mqtt;
+// Chat messages as they travel over the chat topic.
+pub mod message;
+// Swarm construction and the combined network behaviour.
+pub mod node;
+// Desktop notifications of mentions and of messages from favorite contacts.
+pub mod notify;
+// A bridge publishing chat messages to a Nostr relay and showing its notes.
+pub mod nostr;
+// Messages waiting for a peer to publish them to.
+pub mod outbox;
+// Terminal output that can't stall the event loop.
+pub mod output;
+// Passphrase rooms: topics and message keys derived with Argon2id.
+pub mod passphrase;
+// Presence heartbeats and when peers were last seen.
+pub mod presence;
+// Profiles peers publish about themselves.
+pub mod profile;
+// Outbound connections through a SOCKS5 proxy.
+pub mod proxy;
+// Pre-shared swarm keys for private networks.
+pub mod psk;
+// QR codes of invites drawn in the terminal.
+pub mod qr;
+// Abuse reports sent to room moderators.
+pub mod report;
+// Room settings and moderation.
+pub mod room;
+// The peers online in each room.
+pub mod roster;
+// The async runtime the node runs on.
+pub mod runtime;
+// Making text from untrusted peers safe to print.
+pub mod sanitize;
+// Payloads signed with a node's identity key.
+pub mod signed;
+// Membership snapshots sent to peers joining a room.
+pub mod snapshot;
+// Spam reported by members together, and Merkle proofs of the reports.
+pub mod spam;
+// Detection of published messages that reach nobody.
+pub mod stall;
+// Session counters and Gossipsub diagnostics.
+pub mod stats;
+// The JSON commands and events spoken on stdin and stdout with `--io json`.
+pub mod stdio;
+// Each room's shared task list, merged as a CRDT.
+pub mod tasks;
+// TLS for connections to servers outside the swarm.
+pub mod tls;
+// Extended validation of chat messages: HMAC tags and content checks.
+pub mod validator;
+// Key fingerprints for verifying peers out of band.
+pub mod verify;
+// Aliases rooms' chat topics travel under, announced and rotated by moderators.
+pub mod topic_alias;
+// The network as this node sees it, exported as a Graphviz graph.
+pub mod topology;
+// Resumable fetches of attachments, kept in partial files between runs.
+pub mod transfer;
+// Transport stack (security and multiplexing upgrades).
+pub mod transport;
+// Warnings about slow event loop iterations.
+pub mod watchdog;
+// Rooms' messages posted to incoming webhooks.
+pub mod webhook;
+// Wordle games played with the room.
+pub mod wordle;
+// A bridge mirroring an XMPP multi-user chat room into the room and the room into it.
+pub mod xmpp;
@@ -0,0 +1,218 @@
+// Ceilings on the in-memory state peers can make grow, shown by `/stats memory`.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    hash::Hash,
+};
+
+use tracing::warn;
+
+/// Seconds over which evictions are counted for [`EvictionWatch`].
+pub const EVICTION_WINDOW: u64 = 60;
+
+/// Evictions from one structure within [`EVICTION_WINDOW`] that get a warning: its limit is
+/// probably too low for the network.
+pub const EVICTION_WARNING: u64 = 100;
+
+/// A map holding at most `limit` entries. Inserting a new key into a full map evicts the entry
+/// inserted or updated longest ago.
+#[derive(Debug)]
+pub struct LruMap<K, V> {
+    entries: HashMap<K, (V, u64)>,
+    // Keys by when they were last inserted or updated, oldest first
+    order: BTreeMap<u64, K>,
+    next: u64,
+    limit: usize,
+    evictions: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> LruMap<K, V> {
+    pub fn new(limit: usize) -> Self {
+        LruMap {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            next: 0,
+            limit: limit.max(1),
+            evictions: 0,
+        }
+    }
+
+    /// Insert or update `key`, returning its previous value.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let old = match self.entries.remove(&key) {
+            Some((old, stamp)) => {
+                self.order.remove(&stamp);
+                Some(old)
+            }
+            None => {
+                if self.entries.len() >= self.limit {
+                    if let Some((_, oldest)) = self.order.pop_first() {
+                        self.entries.remove(&oldest);
+                        self.evictions += 1;
+                    }
+                }
+                None
+            }
+        };
+        self.order.insert(self.next, key.clone());
+        self.entries.insert(key, (value, self.next));
+        self.next += 1;
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let (value, stamp) = self.entries.remove(key)?;
+        self.order.remove(&stamp);
+        Some(value)
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, (value, _))| (key, value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Entries evicted to make room so far.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+}
+
+/// How full one bounded structure is, for `/stats memory`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// What the structure holds, e.g. "nick cache".
+    pub name: &'static str,
+    /// The flag raising its limit.
+    pub flag: &'static str,
+    pub entries: usize,
+    pub max_entries: usize,
+    /// Bytes held and the limit on them, for structures limited by size.
+    pub bytes: Option<(usize, usize)>,
+    /// Bytes held for the peer holding the most and the limit per peer, for structures
+    /// shared between peers.
+    pub peer_bytes: Option<(usize, usize)>,
+    /// Entries evicted to make room this session.
+    pub evictions: u64,
+}
+
+impl Usage {
+    /// Whether the structure is at or over one of its limits.
+    pub fn is_full(&self) -> bool {
+        self.entries >= self.max_entries
+            || self.bytes.is_some_and(|(bytes, max)| bytes >= max)
+            || self.peer_bytes.is_some_and(|(bytes, max)| bytes >= max)
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<20} {} / {} entries",
+            self.name, self.entries, self.max_entries
+        )?;
+        if let Some((bytes, max)) = self.bytes {
+            write!(f, ", {} / {}", format_bytes(bytes), format_bytes(max))?;
+        }
+        if let Some((bytes, max)) = self.peer_bytes {
+            write!(
+                f,
+                ", largest peer {} / {}",
+                format_bytes(bytes),
+                format_bytes(max)
+            )?;
+        }
+        write!(f, ", {} evicted ({})", self.evictions, self.flag)
+    }
+}
+
+/// The bounded structures of a node, as shown by `/stats memory`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub structures: Vec<Usage>,
+}
+
+impl MemoryReport {
+    /// The structure called `name`.
+    pub fn get(&self, name: &str) -> Option<&Usage> {
+        self.structures.iter().find(|usage| usage.name == name)
+    }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Memory (current / limit, evicted this session):")?;
+        for usage in &self.structures {
+            write!(f, "\n  {usage}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Warns when a structure keeps evicting, more than [`EVICTION_WARNING`] entries within
+/// [`EVICTION_WINDOW`] seconds.
+#[derive(Debug, Default)]
+pub struct EvictionWatch {
+    // Per structure, when its window started and its evictions by then
+    windows: HashMap<&'static str, (u64, u64)>,
+}
+
+impl EvictionWatch {
+    /// Look at `usage` at `now`, warning once its window is over if it evicted too much
+    /// during it. Returns whether it did.
+    pub fn check(&mut self, usage: &Usage, now: u64) -> bool {
+        let (started, evictions) = *self
+            .windows
+            .entry(usage.name)
+            .or_insert((now, usage.evictions));
+        if now < started + EVICTION_WINDOW {
+            return false;
+        }
+        self.windows.insert(usage.name, (now, usage.evictions));
+        let evicted = usage.evictions.saturating_sub(evictions);
+        if evicted < EVICTION_WARNING {
+            return false;
+        }
+        warn!(
+            "[limits] the {} evicted {evicted} entries in the last {} s; raise {} if this keeps \
+             happening",
+            usage.name,
+            now - started,
+            usage.flag
+        );
+        true
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    const KIB: usize = 1024;
+    const MIB: usize = 1024 * KIB;
+    match bytes {
+        bytes if bytes >= MIB => format!("{:.1} MiB", bytes as f64 / MIB as f64),
+        bytes if bytes >= KIB => format!("{:.1} KiB", bytes as f64 / KIB as f64),
+        bytes => format!("{bytes} B"),
+    }
+}
@@ -0,0 +1,167 @@
+// Connections to peers that vanished without closing them, judged from failed pings, silence
+// and requests that time out.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use libp2p::{ping, swarm::ConnectionId, Multiaddr, PeerId};
+
+/// Failed pings in a row a connection needs before it can be judged dead. With a ping every
+/// 15 seconds and 20 seconds to answer each, that is well over a minute without an answer,
+/// which no working link, however slow, comes close to.
+pub const DEAD_PINGS: u32 = 3;
+
+/// Silence from a peer, without a message or an answered ping, that counts against its
+/// connections.
+pub const SILENT_AFTER: Duration = Duration::from_secs(90);
+
+/// Most requests timing out that count against a peer's connections.
+pub const MAX_TIMEOUTS: u32 = 2;
+
+/// Score at which a connection with [`DEAD_PINGS`] failed pings is judged dead: each failed
+/// ping, the peer's silence and each request to it that timed out count one.
+pub const DEAD_SCORE: u32 = 4;
+
+#[derive(Debug)]
+struct Tracked {
+    peer: PeerId,
+    // Where we dialed the peer, to dial it again; connections it dialed have none
+    address: Option<Multiaddr>,
+    failed_pings: u32,
+}
+
+#[derive(Debug)]
+struct Signals {
+    heard: Instant,
+    timeouts: u32,
+}
+
+/// A connection judged dead, to close.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dead {
+    pub connection: ConnectionId,
+    pub peer: PeerId,
+    /// Where we dialed the peer, if we did.
+    pub address: Option<Multiaddr>,
+}
+
+/// How healthy each open connection looks.
+#[derive(Debug, Default)]
+pub struct Liveness {
+    connections: HashMap<ConnectionId, Tracked>,
+    peers: HashMap<PeerId, Signals>,
+}
+
+impl Liveness {
+    /// Track a connection to `peer` opened at `now`, dialed at `address` if we dialed it.
+    pub fn connected(
+        &mut self,
+        connection: ConnectionId,
+        peer: PeerId,
+        address: Option<Multiaddr>,
+        now: Instant,
+    ) {
+        self.connections.insert(
+            connection,
+            Tracked {
+                peer,
+                address,
+                failed_pings: 0,
+            },
+        );
+        self.peers.entry(peer).or_insert(Signals {
+            heard: now,
+            timeouts: 0,
+        });
+    }
+
+    /// Stop tracking a connection to `peer` that closed.
+    pub fn closed(&mut self, connection: &ConnectionId, peer: &PeerId) {
+        self.connections.remove(connection);
+        if !self.is_connected(peer) {
+            self.peers.remove(peer);
+        }
+    }
+
+    /// Record the outcome of a ping. An answer clears the connection's failures and counts as
+    /// hearing from the peer; peers that don't support pings are judged on the rest.
+    pub fn ping(&mut self, event: &ping::Event, now: Instant) {
+        let Some(tracked) = self.connections.get_mut(&event.connection) else {
+            return;
+        };
+        match &event.result {
+            Ok(_) => {
+                tracked.failed_pings = 0;
+                self.heard(&event.peer, now);
+            }
+            Err(ping::Failure::Unsupported) => {}
+            Err(_) => tracked.failed_pings += 1,
+        }
+    }
+
+    /// Record hearing from `peer` at `now`, as when it relays a message.
+    pub fn heard(&mut self, peer: &PeerId, now: Instant) {
+        if let Some(signals) = self.peers.get_mut(peer) {
+            signals.heard = now;
+            signals.timeouts = 0;
+        }
+    }
+
+    /// Record a request to `peer` that timed out: it couldn't be written or wasn't answered.
+    pub fn timed_out(&mut self, peer: &PeerId) {
+        if let Some(signals) = self.peers.get_mut(peer) {
+            signals.timeouts = (signals.timeouts + 1).min(MAX_TIMEOUTS);
+        }
+    }
+
+    /// The score of `connection` at `now`, higher the deader it looks, or none if it isn't
+    /// tracked.
+    pub fn score(&self, connection: &ConnectionId, now: Instant) -> Option<u32> {
+        let tracked = self.connections.get(connection)?;
+        let signals = self.peers.get(&tracked.peer)?;
+        let silent = now.duration_since(signals.heard) >= SILENT_AFTER;
+        Some(tracked.failed_pings + u32::from(silent) + signals.timeouts)
+    }
+
+    /// Take the connections judged dead at `now`: those with [`DEAD_PINGS`] failed pings in a
+    /// row and a score of at least [`DEAD_SCORE`]. They are no longer tracked.
+    pub fn dead(&mut self, now: Instant) -> Vec<Dead> {
+        let dead: Vec<ConnectionId> = self
+            .connections
+            .iter()
+            .filter(|(connection, tracked)| {
+                tracked.failed_pings >= DEAD_PINGS
+                    && self
+                        .score(connection, now)
+                        .is_some_and(|score| score >= DEAD_SCORE)
+            })
+            .map(|(connection, _)| *connection)
+            .collect();
+        dead.into_iter()
+            .filter_map(|connection| {
+                let tracked = self.connections.remove(&connection)?;
+                Some(Dead {
+                    connection,
+                    peer: tracked.peer,
+                    address: tracked.address,
+                })
+            })
+            .collect()
+    }
+
+    /// Whether a connection to `peer` is tracked and not judged dead.
+    pub fn is_connected(&self, peer: &PeerId) -> bool {
+        self.connections
+            .values()
+            .any(|tracked| tracked.peer == *peer)
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+}
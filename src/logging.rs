@@ -0,0 +1,252 @@
+// Where the node's log notices go: stderr, the systemd journal, syslog or a file. The journal
+// and syslog are spoken to directly over their Unix datagram sockets, with tracing levels
+// mapped to syslog severities.
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, IsTerminal},
+    path::Path,
+    sync::Mutex,
+};
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    layer::{Context, SubscriberExt},
+    util::SubscriberInitExt,
+    Layer,
+};
+
+use crate::{
+    cli::{Cli, LogTarget},
+    output,
+};
+
+/// Target of the chat messages sent and received, logged only with `--log-transcripts`.
+pub const TRANSCRIPT: &str = "concurrent_chat_server::transcript";
+
+/// The name the node logs under in the journal and syslog.
+pub const IDENTIFIER: &str = "p2p-chat";
+
+/// Where journald takes entries in its native protocol.
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Where the syslog daemon takes lines.
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+// The daemon facility: the node runs as a system service when it logs to syslog.
+const FACILITY: u8 = 3;
+
+/// Send the node's log notices to the target picked on the command line, from now on.
+pub fn init(cli: &Cli) -> io::Result<()> {
+    let (mut stderr, mut file, mut daemon) = (None, None, None);
+    match cli.log_target {
+        LogTarget::Stderr => {
+            stderr = Some(
+                tracing_subscriber::fmt::layer()
+                    .without_time()
+                    .with_level(false)
+                    .with_target(false)
+                    // Escape codes only mean something to a terminal, not to a script driving us
+                    .with_ansi(io::stdin().is_terminal() && io::stderr().is_terminal())
+                    .with_writer(output::stderr),
+            )
+        }
+        LogTarget::File => {
+            let path = cli.log_file.as_deref().expect("clap requires --log-file");
+            let log = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| cannot_log(path, e))?;
+            file = Some(
+                tracing_subscriber::fmt::layer()
+                    .with_target(false)
+                    .with_ansi(false)
+                    .with_writer(Mutex::new(log)),
+            )
+        }
+        LogTarget::Journald => daemon = Some(journald(Path::new(JOURNALD_SOCKET))?),
+        LogTarget::Syslog => daemon = Some(syslog(Path::new(SYSLOG_SOCKET))?),
+    }
+    tracing_subscriber::registry()
+        .with(stderr)
+        .with(file)
+        .with(daemon)
+        .with(filter(cli.log_transcripts))
+        .init();
+    Ok(())
+}
+
+/// The node's notices from info up, and its chat messages only if `transcripts` is set.
+pub fn filter(transcripts: bool) -> Targets {
+    let transcript_level = match transcripts {
+        true => LevelFilter::INFO,
+        false => LevelFilter::OFF,
+    };
+    Targets::new()
+        .with_target("concurrent_chat_server", Level::INFO)
+        .with_target(TRANSCRIPT, transcript_level)
+}
+
+fn cannot_log(path: &Path, e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), format!("cannot log to {}: {e}", path.display()))
+}
+
+/// The syslog severity of a tracing level: error is `err`, warn is `warning`, info is `info`,
+/// and debug and trace are both `debug`.
+pub fn severity(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+/// What an event logged: its message and its other fields, in order.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub message: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl Record {
+    /// The fields `event` was logged with.
+    pub fn of(event: &Event<'_>) -> Self {
+        let mut record = Record::default();
+        event.record(&mut record);
+        record
+    }
+
+    fn add(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = value,
+            name => self.fields.push((name.to_string(), value)),
+        }
+    }
+}
+
+impl Visit for Record {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.add(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.add(field, format!("{value:?}"));
+    }
+}
+
+/// A journal entry in journald's native protocol: the priority, identifier, target and message,
+/// then each field under its name in capitals.
+pub fn journal_entry(level: &Level, target: &str, record: &Record) -> Vec<u8> {
+    let mut entry = Vec::new();
+    journal_field(&mut entry, "PRIORITY", &severity(level).to_string());
+    journal_field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+    journal_field(&mut entry, "TARGET", target);
+    journal_field(&mut entry, "MESSAGE", &record.message);
+    for (name, value) in &record.fields {
+        if let Some(name) = journal_field_name(name) {
+            journal_field(&mut entry, &name, value);
+        }
+    }
+    entry
+}
+
+/// A field name as the journal takes it: capitals, digits and underscores, not starting with an
+/// underscore or a digit, at most 64 long. Names of nothing but such characters have none.
+pub fn journal_field_name(name: &str) -> Option<String> {
+    let name: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_uppercase(),
+            false => '_',
+        })
+        .skip_while(|c| *c == '_' || c.is_ascii_digit())
+        .take(64)
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+// `NAME=value` on a line, or the name, the length and the value when it spans lines.
+fn journal_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// A syslog line as RFC 3164 has it, less the timestamp and host the daemon adds: the priority,
+/// the identifier with `pid`, and the message on one line followed by the fields as `name=value`.
+pub fn syslog_line(level: &Level, record: &Record, pid: u32) -> String {
+    let priority = FACILITY * 8 + severity(level);
+    let mut line = format!("<{priority}>{IDENTIFIER}[{pid}]: {}", record.message);
+    for (name, value) in &record.fields {
+        line.push_str(&format!(" {name}={value}"));
+    }
+    line.replace(['\r', '\n'], " ")
+}
+
+/// A layer sending each event as a datagram to a log daemon, as journald or syslog takes it.
+/// Events the daemon can't take, such as ones too long for a datagram, are dropped.
+#[cfg(unix)]
+pub struct DaemonLayer {
+    socket: std::os::unix::net::UnixDatagram,
+    encode: fn(&Level, &str, &Record) -> Vec<u8>,
+}
+
+/// A layer logging to the journal listening at `path`.
+#[cfg(unix)]
+pub fn journald(path: &Path) -> io::Result<DaemonLayer> {
+    DaemonLayer::connect(path, journal_entry)
+}
+
+/// A layer logging to the syslog daemon listening at `path`.
+#[cfg(unix)]
+pub fn syslog(path: &Path) -> io::Result<DaemonLayer> {
+    DaemonLayer::connect(path, |level, _, record| {
+        syslog_line(level, record, std::process::id()).into_bytes()
+    })
+}
+
+#[cfg(unix)]
+impl DaemonLayer {
+    fn connect(path: &Path, encode: fn(&Level, &str, &Record) -> Vec<u8>) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(path).map_err(|e| cannot_log(path, e))?;
+        Ok(DaemonLayer { socket, encode })
+    }
+}
+
+#[cfg(unix)]
+impl<S: Subscriber> Layer<S> for DaemonLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        let datagram = (self.encode)(metadata.level(), metadata.target(), &Record::of(event));
+        let _ = self.socket.send(&datagram);
+    }
+}
+
+/// Only Unix has the journal and syslog sockets.
+#[cfg(not(unix))]
+pub type DaemonLayer = tracing_subscriber::layer::Identity;
+
+/// Only Unix has the journal's socket.
+#[cfg(not(unix))]
+pub fn journald(path: &Path) -> io::Result<DaemonLayer> {
+    Err(cannot_log(path, io::ErrorKind::Unsupported.into()))
+}
+
+/// Only Unix has the syslog socket.
+#[cfg(not(unix))]
+pub fn syslog(path: &Path) -> io::Result<DaemonLayer> {
+    Err(cannot_log(path, io::ErrorKind::Unsupported.into()))
+}
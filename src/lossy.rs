@@ -0,0 +1,348 @@
+// Simulated packet loss and latency, for trying the chat on a bad link without having one.
+use std::{
+    future::Future,
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    core::transport::{DialOpts, ListenerId, TransportError, TransportEvent},
+    futures::{AsyncRead, AsyncWrite},
+    Multiaddr, Transport,
+};
+use rand::Rng;
+
+use crate::{
+    cli::Cli,
+    runtime::{self, Sleep},
+};
+
+/// How long a lost read or write stalls before it goes through, like a TCP retransmission
+/// after the minimum retransmission timeout.
+pub const RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Loss and latency to add to every connection, from `--simulate-packet-loss` and
+/// `--simulate-latency-ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Impairment {
+    /// Chance of each read or write being lost, from 0 to 1.
+    pub loss: f64,
+    /// Mean and standard deviation of the delay added to each write.
+    pub latency: Option<(Duration, Duration)>,
+}
+
+impl Impairment {
+    /// The impairment asked for on the command line, if any.
+    pub fn from_cli(cli: &Cli) -> Option<Self> {
+        let latency = cli.simulate_latency_ms.as_deref().map(|ms| {
+            let (mean, stddev) = (ms[0], ms.get(1).copied().unwrap_or(0));
+            (Duration::from_millis(mean), Duration::from_millis(stddev))
+        });
+        let impairment = Impairment {
+            loss: f64::from(cli.simulate_packet_loss.unwrap_or(0)) / 100.0,
+            latency,
+        };
+        (impairment != Impairment::default()).then_some(impairment)
+    }
+
+    /// How long the next read or write stalls: [`RETRANSMIT_TIMEOUT`] if it is lost, otherwise
+    /// not at all.
+    pub fn loss_delay(&self) -> Duration {
+        if self.loss > 0.0 && rand::thread_rng().gen_bool(self.loss.min(1.0)) {
+            RETRANSMIT_TIMEOUT
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// How long the next write waits, sampled from a log-normal distribution with the
+    /// configured mean and standard deviation. Like real round trips, delays are never
+    /// negative and have a long tail of slow ones.
+    pub fn latency_delay(&self) -> Duration {
+        let Some((mean, stddev)) = self.latency else {
+            return Duration::ZERO;
+        };
+        let (mean, stddev) = (mean.as_secs_f64(), stddev.as_secs_f64());
+        if mean <= 0.0 {
+            return Duration::ZERO;
+        }
+        // The normal distribution whose exponent has that mean and standard deviation
+        let sigma = (1.0 + (stddev / mean).powi(2)).ln().sqrt();
+        let mu = mean.ln() - sigma * sigma / 2.0;
+        // Box-Muller transform of two uniform samples into a standard normal one
+        let mut rng = rand::thread_rng();
+        let (u1, u2): (f64, f64) = (1.0 - rng.gen::<f64>(), rng.gen());
+        let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+        Duration::from_secs_f64((mu + sigma * z).exp())
+    }
+}
+
+/// How an [`ImpairedTransport`] wraps the byte streams of its connections.
+pub trait Impair {
+    type Stream<S>;
+
+    fn wrap<S>(inner: S, impairment: Impairment) -> Self::Stream<S>;
+}
+
+/// Loses reads and writes, see [`LossyStream`].
+#[derive(Debug)]
+pub enum Loss {}
+
+impl Impair for Loss {
+    type Stream<S> = LossyStream<S>;
+
+    fn wrap<S>(inner: S, impairment: Impairment) -> LossyStream<S> {
+        LossyStream::new(inner, impairment)
+    }
+}
+
+/// Delays writes, see [`LatencyStream`].
+#[derive(Debug)]
+pub enum Latency {}
+
+impl Impair for Latency {
+    type Stream<S> = LatencyStream<S>;
+
+    fn wrap<S>(inner: S, impairment: Impairment) -> LatencyStream<S> {
+        LatencyStream::new(inner, impairment)
+    }
+}
+
+/// Wraps a transport whose connections are byte streams, impairing every connection it makes
+/// or accepts in the way `I` does.
+#[derive(Debug)]
+pub struct ImpairedTransport<T, I> {
+    inner: T,
+    impairment: Impairment,
+    impair: PhantomData<fn() -> I>,
+}
+
+/// A transport losing reads and writes with the configured probability.
+pub type LossyTransport<T> = ImpairedTransport<T, Loss>;
+
+/// A transport delaying writes by the configured latency.
+pub type LatencyTransport<T> = ImpairedTransport<T, Latency>;
+
+impl<T, I> ImpairedTransport<T, I> {
+    pub fn new(inner: T, impairment: Impairment) -> Self {
+        ImpairedTransport {
+            inner,
+            impairment,
+            impair: PhantomData,
+        }
+    }
+}
+
+impl<T, I> Transport for ImpairedTransport<T, I>
+where
+    T: Transport + Unpin,
+    T::Dial: Unpin,
+    T::ListenerUpgrade: Unpin,
+    I: Impair,
+{
+    type Output = I::Stream<T::Output>;
+    type Error = T::Error;
+    type ListenerUpgrade = Impaired<T::ListenerUpgrade, I>;
+    type Dial = Impaired<T::Dial, I>;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        self.inner.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.inner.remove_listener(id)
+    }
+
+    fn dial(
+        &mut self,
+        addr: Multiaddr,
+        opts: DialOpts,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let dial = self.inner.dial(addr, opts)?;
+        Ok(Impaired::new(dial, self.impairment))
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        let impairment = self.impairment;
+        let event = ready!(Pin::new(&mut self.inner).poll(cx));
+        Poll::Ready(event.map_upgrade(|upgrade| Impaired::new(upgrade, impairment)))
+    }
+}
+
+/// A connection being made or accepted by an [`ImpairedTransport`].
+pub struct Impaired<F, I> {
+    inner: F,
+    impairment: Impairment,
+    impair: PhantomData<fn() -> I>,
+}
+
+impl<F, I> Impaired<F, I> {
+    fn new(inner: F, impairment: Impairment) -> Self {
+        Impaired {
+            inner,
+            impairment,
+            impair: PhantomData,
+        }
+    }
+}
+
+impl<F, S, E, I> Future for Impaired<F, I>
+where
+    F: Future<Output = Result<S, E>> + Unpin,
+    I: Impair,
+{
+    type Output = Result<I::Stream<S>, E>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let stream = ready!(Pin::new(&mut self.inner).poll(cx))?;
+        Poll::Ready(Ok(I::wrap(stream, self.impairment)))
+    }
+}
+
+/// A byte stream whose lost reads and writes are held back for [`RETRANSMIT_TIMEOUT`].
+///
+/// The wrapper sits below the security upgrade, as a lossy network sits below TCP. There, a
+/// lost segment is sent again rather than gone, so losses show up as stalls. Dropping the
+/// bytes instead would only break the encryption and close the connection.
+pub struct LossyStream<S> {
+    inner: S,
+    impairment: Impairment,
+    read: Gate,
+    write: Gate,
+}
+
+impl<S> LossyStream<S> {
+    pub fn new(inner: S, impairment: Impairment) -> Self {
+        LossyStream {
+            inner,
+            impairment,
+            read: Gate::Closed,
+            write: Gate::Closed,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LossyStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.read.poll_open(cx, || this.impairment.loss_delay()));
+        let read = ready!(Pin::new(&mut this.inner).poll_read(cx, buf));
+        this.read = Gate::Closed;
+        Poll::Ready(read)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LossyStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.write.poll_open(cx, || this.impairment.loss_delay()));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf));
+        this.write = Gate::Closed;
+        Poll::Ready(written)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// A byte stream whose writes each wait out a sampled latency first. Reads pass straight
+/// through, so a connection between two impaired nodes sees the latency once each way.
+pub struct LatencyStream<S> {
+    inner: S,
+    impairment: Impairment,
+    write: Gate,
+}
+
+impl<S> LatencyStream<S> {
+    pub fn new(inner: S, impairment: Impairment) -> Self {
+        LatencyStream {
+            inner,
+            impairment,
+            write: Gate::Closed,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LatencyStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LatencyStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.write.poll_open(cx, || this.impairment.latency_delay()));
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, buf));
+        this.write = Gate::Closed;
+        Poll::Ready(written)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+// Whether a read or write may go through: each one waits until a fresh deadline first, and
+// the gate stays open until the inner stream has completed it.
+enum Gate {
+    Closed,
+    Waiting(Sleep),
+    Open,
+}
+
+impl Gate {
+    fn poll_open(&mut self, cx: &mut Context<'_>, delay: impl Fn() -> Duration) -> Poll<()> {
+        loop {
+            match self {
+                Gate::Closed => match delay() {
+                    Duration::ZERO => *self = Gate::Open,
+                    delay => {
+                        let deadline = Instant::now() + delay;
+                        *self = Gate::Waiting(runtime::sleep_until(deadline));
+                    }
+                },
+                Gate::Waiting(sleep) => {
+                    ready!(sleep.as_mut().poll(cx));
+                    *self = Gate::Open;
+                }
+                Gate::Open => return Poll::Ready(()),
+            }
+        }
+    }
+}
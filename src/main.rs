@@ -1,148 +1,170 @@
 // Required libraries and modules from the Rust standard library and libp2p crate.
-use std::{error::Error, time::Duration};
+use std::io::IsTerminal;
 
-use libp2p::{
-    // StreamExt provides utilities for working with asynchronous streams.
-    futures::StreamExt,
-    // Gossipsub is a pub/sub messaging protocol used for decentralized communication.
-    gossipsub, 
-    // mDNS (Multicast DNS) helps discover peers in the local network.
-    mdns, 
-    // Noise is a cryptographic protocol for encrypted peer communications.
-    noise, 
-    // NetworkBehaviour defines the behavior of a node in the network (combining Gossipsub and mDNS).
-    swarm::{NetworkBehaviour, SwarmEvent}, 
-    // TCP transport protocol, using for peer-to-peer connection.
-    tcp, 
-    // Yamux is a multiplexing protocol that allows multiple streams over a single connection.
-    yamux, 
-    // SwarmBuilder is used to create and configure the swarm (the core of peer-to-peer networking).
-    SwarmBuilder,
-};
+use clap::Parser;
 
-// Tokio is an asynchronous runtime that allows the code to run asynchronously.
-use tokio::{io, io::AsyncBufReadExt, select};
+use concurrent_chat_server::{
+    bench::{self, BenchSpec},
+    chat::ChatNode,
+    cli::{Cli, Command, IdentityCommand, IoMode, StdinEof},
+    clock,
+    error::ChatError,
+    doctor, identity, input, logging, output, psk, runtime, say,
+};
+#[cfg(debug_assertions)]
+use concurrent_chat_server::lossy;
 
-// Define a custom network behavior by combining Gossipsub and mDNS.
-// This macro derives the necessary code to combine the two protocols.
-#[derive(NetworkBehaviour)]
-pub(crate) struct MyBehaviour {
-    // Gossipsub for pub-sub message passing
-    gossipsub: gossipsub::Behaviour,
-    // mDNS for peer discovery in a local network
-    mdns: mdns::tokio::Behaviour,
+// Runs the node on the runtime picked at build time, Tokio unless built with async-std.
+fn main() -> Result<(), ChatError> {
+    runtime::block_on(run())
 }
 
-#[tokio::main]
 // The main asynchronous function that starts the P2P node and manages message passing.
-async fn main() -> Result<(), Box<dyn Error>> {
-    // Create the swarm (P2P node) by building the transport stack and network behaviour.
-    let mut swarm = SwarmBuilder::with_new_identity()
-        // Use Tokio runtime for asynchronous networking
-        .with_tokio()
-        // Set up a TCP transport layer with Noise encryption and Yamux multiplexing
-        .with_tcp(
-            tcp::Config::default(),         // Default TCP transport configuration
-            noise::Config::new,             // Secure communication using Noise encryption
-            yamux::Config::default,         // Multiplexing using Yamux
-        )?
-        // Optionally, use QUIC transport (faster, encrypted transport protocol)
-        .with_quic()
-        // Define the custom behavior (Gossipsub + mDNS) for the P2P node
-        .with_behaviour(|key| {
-            // Create a default Gossipsub configuration
-            let gossipsub_config = gossipsub::Config::default();
-            
-            // Create a Gossipsub behavior with message signing using the local node's identity key.
-            let mut gossipsub = gossipsub::Behaviour::new(
-                gossipsub::MessageAuthenticity::Signed(key.clone()), // Ensure authenticity
-                gossipsub_config,                                    // Gossipsub configuration
-            )
-            .expect("error");
+async fn run() -> Result<(), ChatError> {
+    // Parse the command line flags
+    let cli = Cli::parse();
+    // A program reading JSON events gets nothing else on stdout
+    if cli.io == IoMode::Json && cli.command.is_none() {
+        output::reserve_stdout();
+    }
+    logging::init(&cli)?;
 
-            // Create an mDNS behavior for local peer discovery
-            let mdns = mdns::tokio::Behaviour::new(
-                mdns::Config::default(),          // Default mDNS configuration
-                key.public().to_peer_id()         // Peer ID is derived from the node's public key
-            )?;
-            
-            // Return the combined behavior for use in the swarm.
-            Ok(MyBehaviour { gossipsub, mdns })
-        })?
-        // Set the swarm configuration with an idle connection timeout of 60 seconds
-        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
-        // Build and return the fully configured swarm object
-        .build();
+    // Run a subcommand instead of the chat node if one was given
+    if let Some(Command::Genkey { output }) = &cli.command {
+        match output {
+            Some(path) => {
+                let key = psk::write_new(path)?;
+                println!("Wrote swarm key {} to {}", key.fingerprint(), path.display());
+            }
+            None => print!("{}", psk::generate()),
+        }
+        return Ok(());
+    }
+    if let Some(Command::Identity {
+        action: IdentityCommand::Rotate,
+    }) = &cli.command
+    {
+        let Some(path) = &cli.identity else {
+            eprintln!("identity rotate needs --identity <PATH>");
+            std::process::exit(2);
+        };
+        let rotation = identity::rotate(path, clock::unix_time())?;
+        println!("Rotated identity {} to {}", rotation.old, rotation.new);
+        println!(
+            "The node announces the change for {}; peers that trusted the old key move that trust over",
+            clock::format_duration(identity::ROTATION_GRACE)
+        );
+        return Ok(());
+    }
+    if let Some(Command::Bench {
+        nodes,
+        transport,
+        sizes,
+        messages,
+    }) = &cli.command
+    {
+        let specs: Vec<BenchSpec> = transport
+            .iter()
+            .flat_map(|&transport| {
+                sizes.iter().map(move |&payload| BenchSpec {
+                    transport,
+                    nodes: *nodes,
+                    payload,
+                    messages: *messages,
+                })
+            })
+            .collect();
+        let results = bench::run_all(&specs).await;
+        output::flush();
+        let results = results?;
+        print!("{}", bench::table(&results));
+        return Ok(());
+    }
+    if let Some(Command::Doctor) = &cli.command {
+        let report = doctor::run(&doctor::Settings::from_cli(&cli));
+        println!("{report}");
+        if report.has_failures() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    // Create a Gossipsub topic that all peers will subscribe to
-    let topic = gossipsub::IdentTopic::new("xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx");
+    // The node prints from a thread of its own, so let it finish before exiting
+    let ran = chat(&cli).await;
+    output::flush();
+    ran
+}
 
-    // Subscribe to the created topic so that this node can receive and publish messages on it
-    swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+// Run the chat node until Ctrl-C.
+async fn chat(cli: &Cli) -> Result<(), ChatError> {
+    // Create the chat node: the swarm (transport stack and network behaviour) plus chat state.
+    let mut chat = ChatNode::new(cli)?;
+    // With `--io json`, what happens is written to stdout as JSON events, one per line
+    let events = (cli.io == IoMode::Json).then(|| {
+        let mut events = chat.subscribe_events();
+        runtime::spawn(async move {
+            while let Some(event) = events.recv().await {
+                output::data(event.encode());
+            }
+        })
+    });
+    say!("Local peer id: {}", chat.local_peer_id());
+    if cli.room_pass.is_some() || cli.room_key_file.is_some() {
+        say!(
+            "Joined private room {}, messages are encrypted with the passphrase's key",
+            chat.topic().hash()
+        );
+    }
 
-    // Create an asynchronous stdin reader to capture user input
-    let mut stdin = io::BufReader::new(io::stdin()).lines();
+    // Nobody idles at a pipe or a script, so only go away automatically at a terminal, and
+    // nobody types more once the pipe ends, so exit then unless told otherwise
+    let interactive = std::io::stdin().is_terminal() && cli.io == IoMode::Text;
+    if !interactive {
+        chat.set_away_after(None);
+        if cli.on_stdin_eof.is_none() {
+            chat.set_on_input_end(StdinEof::Exit);
+        }
+    }
 
-    // Instruct the swarm to listen for incoming connections on all interfaces (IP4 over QUIC)
-    swarm.listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)?;
+    // Simulated loss and latency only apply to TCP, so QUIC would get around them
+    #[cfg(debug_assertions)]
+    let impaired = lossy::Impairment::from_cli(cli).is_some();
+    #[cfg(not(debug_assertions))]
+    let impaired = false;
+    if let Some(path) = &cli.swarm_key {
+        // QUIC is not available on private networks, since pnet can only wrap TCP streams
+        say!("Private network enabled with swarm key {}, QUIC disabled", path.display());
+    } else if impaired {
+        say!("Simulating a lossy link on TCP connections, QUIC disabled");
+    } else if let Some(kbps) = cli.max_upload_kbps {
+        // QUIC connections couldn't be held to the cap
+        say!("Upload capped at {kbps} kbps on TCP connections, QUIC disabled");
+    } else if let Some(proxy) = &cli.proxy {
+        // UDP can't go through a SOCKS5 proxy's CONNECT
+        let address = proxy.address;
+        say!("Dialing through the SOCKS5 proxy at {address}, QUIC disabled");
+    } else {
+        // Instruct the swarm to listen for incoming connections on all interfaces (IP4 over QUIC)
+        chat.listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)?;
+    }
     // Instruct the swarm to listen for incoming connections over TCP as well
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
-    println!("Enter messages via STDIN and they will be sent to connected peers using Gossipsub");
+    chat.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    // Be reachable through a relay as well, for peers that can't dial us directly
+    chat.listen_on_relay()?;
+    // Reach the network through the bootstrap peers, given or published in DNS
+    chat.bootstrap();
+    if interactive {
+        say!("Enter messages via STDIN and they will be sent to connected peers using Gossipsub");
+    }
 
-    // Main event loop: Listen for events and handle them (user input and network events).
-    loop {
-        // Select between different asynchronous tasks: reading stdin or receiving swarm events
-        select! {
-            // If there's user input (a line of text from stdin)
-            Ok(Some(line)) = stdin.next_line() => {
-                // Delay for 2 seconds to give peers time to connect before sending the first message
-                tokio::time::sleep(Duration::from_secs(2)).await;
-                
-                // Publish the input line as a Gossipsub message to the subscribed topic
-                if let Err(e) = swarm
-                    .behaviour_mut().gossipsub
-                    .publish(topic.clone(), line.as_bytes()) {
-                    // If an error occurs while publishing the message, print the error.
-                    println!("Publish error: {e:?}");
-                }
-            }
-            // Handle events from the swarm (e.g., peer discovery, message receipt)
-            event = swarm.select_next_some() => match event {
-                // When mDNS discovers a new peer on the local network
-                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(list))) => {
-                    // For each discovered peer, print the peer ID and add them to Gossipsub explicitly
-                    for (peer_id, _multiaddr) in list {
-                        println!("mDNS discovered a new peer: {peer_id}");
-                        swarm.behaviour_mut().gossipsub.add_explicit_peer(&peer_id);
-                        println!("Added explicit peer: {:?}", peer_id);
-                    }
-                },
-                // When a previously discovered peer's mDNS announcement has expired
-                SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(list))) => {
-                    // For each expired peer, remove them from the Gossipsub peer list
-                    for (peer_id, _multiaddr) in list {
-                        println!("mDNS discover peer has expired: {peer_id}");
-                        swarm.behaviour_mut().gossipsub.remove_explicit_peer(&peer_id);
-                    }
-                },
-                // When a Gossipsub message is received from a peer
-                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
-                    propagation_source: peer_id,   // The peer that sent the message
-                    message_id: id,                // Unique ID of the message
-                    message,                       // The actual message content (bytes)
-                })) => println!(
-                        "Got message: '{}' with id: {id} from peer: {peer_id}",
-                        // Convert the message from bytes to a readable string and print it
-                        String::from_utf8_lossy(&message.data),
-                    ),
-                // When the local node starts listening on a new network address
-                SwarmEvent::NewListenAddr { address, .. } => {
-                    // Print the address the local node is listening on
-                    println!("Local node is listening on {address}");
-                }
-                // Catch all other events (not handled explicitly)
-                _ => {}
-            }
-        }
+    // Main event loop: run commands and send messages typed on stdin, handle network events,
+    // and leave gracefully on Ctrl-C. Stdin is read on its own task, a few lines ahead.
+    let input = input::spawn_stdin();
+    chat.run(input, runtime::ctrl_c()).await;
+    // The events end with the node, so the last replies are written before exiting
+    drop(chat);
+    if let Some(events) = events {
+        events.await;
     }
+    Ok(())
 }
@@ -0,0 +1,531 @@
+// A bridge to a Matrix room, speaking the client-server API to the homeserver: the room's
+// messages are posted to the Matrix room by the bridge's user, and what others say there is
+// published to the room.
+//
+// The bridge long-polls `/sync` for the room's timeline and marks what it passed on as read.
+// The sync token is saved beside the config file, so messages said on Matrix while the node
+// was down are bridged once it is back.
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+    pin::pin,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tracing::debug;
+use url::Url;
+
+use crate::{cli::Cli, clock, error::ConfigError, http, runtime};
+
+/// The `origin` of messages copied in from Matrix.
+pub const ORIGIN: &str = "matrix";
+
+/// Messages waiting for the Matrix room. Once that many are, more are refused until some go
+/// out.
+pub const QUEUE: usize = 256;
+
+/// First wait before connecting again after losing the homeserver, doubled after each failure.
+pub const RECONNECT_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait before connecting again.
+pub const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// How long the homeserver may hold a `/sync` request open when there is nothing new.
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most timeline events taken from one `/sync`.
+pub const SYNC_LIMIT: usize = 50;
+
+/// Whether `s` names a Matrix room, by id (`!id:server`) or alias (`#alias:server`).
+pub fn room_name(s: &str) -> Result<String, String> {
+    let valid = (s.starts_with('!') || s.starts_with('#'))
+        && s.split_once(':').is_some_and(|(local, server)| {
+            local.len() > 1 && !server.is_empty() && !s.contains(char::is_whitespace)
+        });
+    if !valid {
+        return Err("a Matrix room is `!id:server` or `#alias:server`".to_string());
+    }
+    Ok(s.to_string())
+}
+
+/// How the bridge logs in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatrixLogin {
+    Password { user: String, password: String },
+    Token(String),
+}
+
+/// The homeserver, the room on it, and how to log in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatrixSettings {
+    pub homeserver: Url,
+    pub room: String,
+    pub login: MatrixLogin,
+}
+
+impl MatrixSettings {
+    /// The settings of `--matrix-homeserver` and the flags going with it, if it was given.
+    pub fn from_cli(cli: &Cli) -> Option<Self> {
+        let login = match (&cli.matrix_user, &cli.matrix_password, &cli.matrix_token) {
+            (Some(user), Some(password), _) => MatrixLogin::Password {
+                user: user.clone(),
+                password: password.clone(),
+            },
+            (_, _, Some(token)) => MatrixLogin::Token(token.clone()),
+            _ => return None,
+        };
+        Some(MatrixSettings {
+            homeserver: cli.matrix_homeserver.clone()?,
+            room: cli.matrix_room.clone()?,
+            login,
+        })
+    }
+}
+
+/// Where the bridge is up to on the homeserver, kept between runs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MatrixState {
+    /// The token of the next `/sync`.
+    pub next_batch: String,
+    /// The last event marked as read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_up_to: Option<String>,
+}
+
+impl MatrixState {
+    /// The state saved at `path`, if any.
+    pub fn load(path: &Path) -> Result<Option<Self>, ConfigError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents)
+                    .map(Some)
+                    .map_err(|source| ConfigError::Parse {
+                        path: path.to_path_buf(),
+                        source,
+                    })
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(source) => Err(ConfigError::Read {
+                path: path.to_path_buf(),
+                source,
+            }),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let contents = serde_json::to_string_pretty(self).expect("the state always serializes");
+        fs::write(path, contents).map_err(|source| ConfigError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// The file kept next to the config file with the bridge's sync token: `config.json` keeps it
+/// in `config.matrix.json`.
+pub fn path_beside(config_path: &Path) -> PathBuf {
+    config_path.with_extension("matrix.json")
+}
+
+/// What happens on the bridge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatrixEvent {
+    /// Logged in as `user_id` and in `room_id`, the room's id.
+    Connected { user_id: String, room_id: String },
+    /// The homeserver was lost or refused us. Connecting is tried again with backoff, and this
+    /// isn't repeated until a connection was made.
+    Disconnected(String),
+    /// `sender`, a Matrix user id, said `body` in the room.
+    Message { sender: String, body: String },
+    /// `sender` did something, with `/me`.
+    Emote { sender: String, body: String },
+    /// A sync went through: the state to save.
+    Synced(MatrixState),
+}
+
+/// The bridge to a Matrix room, kept on a task of its own that reconnects whenever the
+/// homeserver is lost. Dropping it stops bridging.
+#[derive(Debug)]
+pub struct MatrixBridge {
+    settings: MatrixSettings,
+    outgoing: mpsc::Sender<String>,
+    incoming: mpsc::Receiver<MatrixEvent>,
+    task: runtime::Task<()>,
+}
+
+impl MatrixBridge {
+    /// Log in and join the room of `settings`, syncing from `state` if a previous run saved
+    /// one, or from now if not.
+    pub fn spawn(settings: MatrixSettings, state: Option<MatrixState>) -> Self {
+        let (outgoing, queue) = mpsc::channel(QUEUE);
+        let (events, incoming) = mpsc::channel(QUEUE);
+        let task = runtime::spawn(run(settings.clone(), state, queue, events));
+        MatrixBridge {
+            settings,
+            outgoing,
+            incoming,
+            task,
+        }
+    }
+
+    pub fn settings(&self) -> &MatrixSettings {
+        &self.settings
+    }
+
+    /// Post `text` to the room for `nick`, as `<nick> text`. It goes out as soon as the room
+    /// is joined. Fails while [`QUEUE`] messages are waiting.
+    pub fn relay(&self, nick: &str, text: &str) -> Result<(), String> {
+        self.outgoing
+            .try_send(format!("<{nick}> {text}"))
+            .map_err(|_| format!("{QUEUE} messages are already waiting for Matrix"))
+    }
+
+    /// The next thing that happened on the bridge.
+    pub async fn next(&mut self) -> Option<MatrixEvent> {
+        self.incoming.recv().await
+    }
+}
+
+impl Drop for MatrixBridge {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Keep logged in and syncing until the node goes away.
+async fn run(
+    settings: MatrixSettings,
+    state: Option<MatrixState>,
+    mut queue: mpsc::Receiver<String>,
+    events: mpsc::Sender<MatrixEvent>,
+) {
+    let mut state = state.unwrap_or_default();
+    // A token we were given is kept; one from logging in is dropped when it stops working
+    let mut token = match &settings.login {
+        MatrixLogin::Token(token) => Some(token.clone()),
+        MatrixLogin::Password { .. } => None,
+    };
+    // Messages are numbered from when the bridge started, so one sent again after a lost
+    // answer isn't posted twice
+    let mut transactions = Transactions {
+        prefix: format!("p2p-chat-{}", clock::unix_time()),
+        next: 0,
+        unsent: VecDeque::new(),
+    };
+    let mut backoff = RECONNECT_MIN;
+    let mut reported = false;
+    loop {
+        let error = match connect(&settings, &mut token).await {
+            Ok(client) => {
+                backoff = RECONNECT_MIN;
+                reported = false;
+                let connected = MatrixEvent::Connected {
+                    user_id: client.user_id.clone(),
+                    room_id: client.room_id.clone(),
+                };
+                if events.send(connected).await.is_err() {
+                    return;
+                }
+                match client
+                    .serve(&mut state, &mut queue, &mut transactions, &events)
+                    .await
+                {
+                    Ok(()) => return,
+                    Err(Failure::Unauthorized(e)) => {
+                        if matches!(settings.login, MatrixLogin::Password { .. }) {
+                            token = None;
+                        }
+                        e
+                    }
+                    Err(Failure::Other(e)) => e,
+                }
+            }
+            Err(Failure::Unauthorized(e) | Failure::Other(e)) => e,
+        };
+        if events.is_closed() {
+            return;
+        }
+        debug!(
+            "[matrix] {}: {error}, connecting again in {backoff:?}",
+            settings.homeserver
+        );
+        if !reported {
+            reported = true;
+            let _ = events.send(MatrixEvent::Disconnected(error)).await;
+        }
+        runtime::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+// Transaction ids for the messages posted, and the messages whose answer was lost.
+struct Transactions {
+    prefix: String,
+    next: u64,
+    unsent: VecDeque<(String, String)>,
+}
+
+impl Transactions {
+    fn next(&mut self) -> String {
+        self.next += 1;
+        format!("{}-{}", self.prefix, self.next)
+    }
+}
+
+// Why talking to the homeserver failed.
+enum Failure {
+    // The access token was refused
+    Unauthorized(String),
+    Other(String),
+}
+
+impl From<String> for Failure {
+    fn from(e: String) -> Self {
+        Failure::Other(e)
+    }
+}
+
+// Log in if there is no token yet, then join the room.
+async fn connect(settings: &MatrixSettings, token: &mut Option<String>) -> Result<Client, Failure> {
+    let mut client = Client {
+        homeserver: settings.homeserver.clone(),
+        token: token.clone().unwrap_or_default(),
+        user_id: String::new(),
+        room_id: String::new(),
+    };
+    let answer = match &settings.login {
+        MatrixLogin::Password { user, password } if token.is_none() => {
+            let login = json!({
+                "type": "m.login.password",
+                "identifier": { "type": "m.id.user", "user": user },
+                "password": password,
+                "initial_device_display_name": "p2p-chat bridge",
+            });
+            let answer = client
+                .call("POST", &["login"], &[], Some(login), http::REQUEST_TIMEOUT)
+                .await?;
+            let access_token = string(&answer, "access_token")?;
+            client.token = access_token.clone();
+            *token = Some(access_token);
+            answer
+        }
+        _ => {
+            client
+                .call(
+                    "GET",
+                    &["account", "whoami"],
+                    &[],
+                    None,
+                    http::REQUEST_TIMEOUT,
+                )
+                .await?
+        }
+    };
+    client.user_id = string(&answer, "user_id")?;
+    let joined = client
+        .call(
+            "POST",
+            &["join", &settings.room],
+            &[],
+            Some(json!({})),
+            http::REQUEST_TIMEOUT,
+        )
+        .await?;
+    client.room_id = string(&joined, "room_id")?;
+    Ok(client)
+}
+
+// A string field of an answer.
+fn string(answer: &Value, field: &str) -> Result<String, Failure> {
+    answer[field]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| Failure::Other(format!("the homeserver's answer has no {field}")))
+}
+
+// The bridge's session with the homeserver.
+struct Client {
+    homeserver: Url,
+    token: String,
+    user_id: String,
+    room_id: String,
+}
+
+impl Client {
+    // Call the client-server API at `/_matrix/client/v3/<path>`.
+    async fn call(
+        &self,
+        method: &str,
+        path: &[&str],
+        query: &[(&str, &str)],
+        body: Option<Value>,
+        timeout: Duration,
+    ) -> Result<Value, Failure> {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|()| "the homeserver URL can't have a path".to_string())?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(path);
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        let token = (!self.token.is_empty()).then_some(self.token.as_str());
+        let body = body.map(|body| body.to_string());
+        let response = http::request(method, &url, token, body.as_deref(), timeout).await?;
+        let answer: Value = serde_json::from_slice(&response.body).unwrap_or(Value::Null);
+        if response.is_success() {
+            return Ok(answer);
+        }
+        let reason = match answer["errcode"].as_str() {
+            Some(code) => format!(
+                "the homeserver answered {} ({code}: {})",
+                response.status,
+                answer["error"].as_str().unwrap_or_default()
+            ),
+            None => format!("the homeserver answered {}", response.status),
+        };
+        match response.status {
+            401 => Err(Failure::Unauthorized(reason)),
+            _ => Err(Failure::Other(reason)),
+        }
+    }
+
+    // Post `text` to the room, waiting as long as the homeserver asks if it limits our rate.
+    async fn send(&self, text: &str, transaction: &str) -> Result<(), Failure> {
+        let path = [
+            "rooms",
+            &self.room_id,
+            "send",
+            "m.room.message",
+            transaction,
+        ];
+        loop {
+            let content = json!({ "msgtype": "m.text", "body": text });
+            match self
+                .call("PUT", &path, &[], Some(content), http::REQUEST_TIMEOUT)
+                .await
+            {
+                Err(Failure::Other(e)) if e.contains("M_LIMIT_EXCEEDED") => {
+                    debug!("[matrix] rate limited, sending again in a second");
+                    runtime::sleep(Duration::from_secs(1)).await;
+                }
+                sent => return sent.map(|_| ()),
+            }
+        }
+    }
+
+    // One long-poll of the room's timeline.
+    async fn sync(&self, since: &str) -> Result<Value, Failure> {
+        // The first sync only finds out where the room is up to
+        let limit = if since.is_empty() { 1 } else { SYNC_LIMIT };
+        let filter = json!({
+            "room": {
+                "rooms": [self.room_id],
+                "timeline": { "limit": limit },
+                "state": { "types": [] },
+                "ephemeral": { "types": [] },
+                "account_data": { "types": [] },
+            },
+            "presence": { "types": [] },
+            "account_data": { "types": [] },
+        })
+        .to_string();
+        let wait = SYNC_TIMEOUT.as_millis().to_string();
+        let mut query = vec![("timeout", wait.as_str()), ("filter", filter.as_str())];
+        if !since.is_empty() {
+            query.push(("since", since));
+        }
+        let timeout = SYNC_TIMEOUT + http::REQUEST_TIMEOUT;
+        self.call("GET", &["sync"], &query, None, timeout).await
+    }
+
+    // Sync the timeline, passing messages on to the node and posting queued ones to the room,
+    // until something fails. Returns `Ok` once the node goes away.
+    async fn serve(
+        &self,
+        state: &mut MatrixState,
+        queue: &mut mpsc::Receiver<String>,
+        transactions: &mut Transactions,
+        events: &mpsc::Sender<MatrixEvent>,
+    ) -> Result<(), Failure> {
+        loop {
+            // Messages whose answer was lost go first, under the same transaction
+            while let Some((text, transaction)) = transactions.unsent.front() {
+                self.send(text, transaction).await?;
+                transactions.unsent.pop_front();
+            }
+            let since = state.next_batch.clone();
+            let mut sync = pin!(self.sync(&since));
+            let answer = loop {
+                tokio::select! {
+                    answer = &mut sync => break answer?,
+                    text = queue.recv() => {
+                        let Some(text) = text else {
+                            return Ok(());
+                        };
+                        let transaction = transactions.next();
+                        if let Err(e) = self.send(&text, &transaction).await {
+                            transactions.unsent.push_back((text, transaction));
+                            return Err(e);
+                        }
+                    }
+                }
+            };
+            let first = state.next_batch.is_empty();
+            let next_batch = string(&answer, "next_batch")?;
+            let timeline = &answer["rooms"]["join"][&self.room_id]["timeline"]["events"];
+            let mut last = None;
+            for event in timeline.as_array().into_iter().flatten() {
+                last = event["event_id"].as_str().or(last);
+                if first {
+                    continue;
+                }
+                let Some(message) = self.message(event) else {
+                    continue;
+                };
+                if events.send(message).await.is_err() {
+                    return Ok(());
+                }
+            }
+            state.next_batch = next_batch;
+            if let Some(last) = last.filter(|last| state.read_up_to.as_deref() != Some(last)) {
+                if !first {
+                    let path = ["rooms", &self.room_id, "receipt", "m.read", last];
+                    self.call("POST", &path, &[], Some(json!({})), http::REQUEST_TIMEOUT)
+                        .await?;
+                }
+                state.read_up_to = Some(last.to_string());
+            }
+            if events
+                .send(MatrixEvent::Synced(state.clone()))
+                .await
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    // What someone else said in a timeline event, if it is a message. Notices are left out,
+    // since that is how bots, other bridges among them, post.
+    fn message(&self, event: &Value) -> Option<MatrixEvent> {
+        if event["type"] != "m.room.message" {
+            return None;
+        }
+        let sender = event["sender"].as_str()?.to_string();
+        if sender == self.user_id {
+            return None;
+        }
+        let body = event["content"]["body"].as_str()?.to_string();
+        match event["content"]["msgtype"].as_str()? {
+            "m.text" => Some(MatrixEvent::Message { sender, body }),
+            "m.emote" => Some(MatrixEvent::Emote { sender, body }),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// Joins and leaves collected into one summary line per room for the terminal.
+use std::collections::BTreeMap;
+
+use libp2p::PeerId;
+
+use crate::roster::RosterEvent;
+
+/// Names listed per direction in a summary before the rest are left out.
+pub const MAX_NAMES: usize = 5;
+
+/// Peers that joined and left a room since its batch was opened, net of peers that did both.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Batch {
+    pub joined: Vec<PeerId>,
+    pub left: Vec<PeerId>,
+    opened_at: u64,
+}
+
+impl Batch {
+    pub fn is_empty(&self) -> bool {
+        self.joined.is_empty() && self.left.is_empty()
+    }
+}
+
+/// Collects roster arrivals and departures per room for `window` seconds before they are
+/// printed, so that a network blip makes one line instead of a screenful.
+///
+/// Only what the user sees is batched; embedders still get every [`RosterEvent`] as it happens.
+#[derive(Debug)]
+pub struct MembershipBatcher {
+    window: u64,
+    pending: BTreeMap<String, Batch>,
+}
+
+impl MembershipBatcher {
+    pub fn new(window: u64) -> Self {
+        MembershipBatcher {
+            window,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Add a roster change. A peer leaving after joining within the same batch (or the other way
+    /// round) cancels out. Peers going away or coming back aren't summarized.
+    pub fn push(&mut self, event: &RosterEvent, now: u64) {
+        let (peer, joined) = match event {
+            RosterEvent::Joined { peer, .. } => (peer, true),
+            RosterEvent::Left { peer, .. } => (peer, false),
+            RosterEvent::Away { .. } | RosterEvent::Back { .. } => return,
+        };
+        let batch = self
+            .pending
+            .entry(event.room().to_string())
+            .or_insert_with(|| Batch {
+                opened_at: now,
+                ..Batch::default()
+            });
+        let (add, cancel) = if joined {
+            (&mut batch.joined, &mut batch.left)
+        } else {
+            (&mut batch.left, &mut batch.joined)
+        };
+        match cancel.iter().position(|p| p == peer) {
+            Some(index) => {
+                cancel.remove(index);
+            }
+            None if !add.contains(peer) => add.push(*peer),
+            None => {}
+        }
+    }
+
+    /// Take the batches that have been open for the whole window, leaving out those whose
+    /// changes all cancelled out.
+    pub fn due(&mut self, now: u64) -> Vec<(String, Batch)> {
+        let rooms: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, batch)| now >= batch.opened_at + self.window)
+            .map(|(room, _)| room.clone())
+            .collect();
+        rooms
+            .into_iter()
+            .filter_map(|room| {
+                let batch = self.pending.remove(&room)?;
+                (!batch.is_empty()).then_some((room, batch))
+            })
+            .collect()
+    }
+}
+
+/// One line summing up a room's batch, such as
+/// `lobby: +3 joined (alice, bob, carol), −1 left (dave) — 12 online`.
+pub fn summary(room: &str, joined: &[String], left: &[String], online: usize) -> String {
+    let mut parts = Vec::new();
+    if !joined.is_empty() {
+        parts.push(format!("+{} joined ({})", joined.len(), names(joined)));
+    }
+    if !left.is_empty() {
+        parts.push(format!("−{} left ({})", left.len(), names(left)));
+    }
+    format!("{room}: {} — {online} online", parts.join(", "))
+}
+
+fn names(names: &[String]) -> String {
+    let mut listed = names[..names.len().min(MAX_NAMES)].join(", ");
+    if names.len() > MAX_NAMES {
+        listed.push_str(&format!(" and {} more", names.len() - MAX_NAMES));
+    }
+    listed
+}
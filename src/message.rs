@@ -0,0 +1,216 @@
+// Chat messages as they travel over the chat topic.
+use std::{borrow::Cow, fmt::Write, io, str, sync::Arc};
+
+use libp2p::{gossipsub::MessageId, PeerId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attachment::AttachmentRef,
+    content_type::{self, ContentType},
+    hooks,
+    sanitize::{self, Link},
+};
+
+/// Bytes of a binary payload shown in its preview.
+pub const PREVIEW_BYTES: usize = 8;
+
+/// A chat message published on the chat topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    /// Display name chosen by the sender.
+    pub nick: String,
+    /// Shared rather than copied as the message moves from the receive handler to the history
+    /// and on to reports, which matters at high message rates.
+    pub body: Arc<str>,
+    /// Unix time (seconds) at which the sender wrote the message.
+    pub timestamp: u64,
+    /// A file sent along by reference, for peers to fetch from the sender.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<AttachmentRef>,
+    /// Where a bridge copied the message from, such as `irc`. Bridges don't copy such
+    /// messages back out, so none of them goes round in a loop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// How the body is written, when not as plain text: Markdown, sent with `/md`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<ContentType>,
+}
+
+impl ChatMessage {
+    /// A plain text message with nothing attached, written by `nick` at `timestamp`.
+    pub fn new(nick: impl Into<String>, body: impl Into<Arc<str>>, timestamp: u64) -> Self {
+        ChatMessage {
+            nick: nick.into(),
+            body: body.into(),
+            timestamp,
+            attachment: None,
+            origin: None,
+            content_type: None,
+        }
+    }
+
+    /// JSON encoding published on the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("chat messages always serialize")
+    }
+
+    /// The message as peers reading at most `format` take it: a Markdown body is turned into
+    /// plain text for those that don't read Markdown.
+    pub fn fit(&self, format: ContentType) -> Cow<'_, ChatMessage> {
+        match self.content_type {
+            Some(ContentType::Markdown) if format < ContentType::Markdown => {
+                Cow::Owned(ChatMessage {
+                    body: self.plain_text().into(),
+                    content_type: None,
+                    ..self.clone()
+                })
+            }
+            _ => Cow::Borrowed(self),
+        }
+    }
+
+    /// The body as plain text, which is all peers reading only `text/plain` get.
+    pub fn plain_text(&self) -> Cow<'_, str> {
+        match self.content_type {
+            Some(ContentType::Markdown) => Cow::Owned(content_type::to_plain(&self.body)),
+            _ => Cow::Borrowed(&self.body),
+        }
+    }
+
+    /// Length of [`ChatMessage::encode`], without encoding into a buffer.
+    pub fn encoded_len(&self) -> usize {
+        let mut counter = ByteCounter(0);
+        serde_json::to_writer(&mut counter, self).expect("chat messages always serialize");
+        counter.0
+    }
+
+    /// Decode a received message. Peers running older versions publish plain text, which is
+    /// kept as the body with an empty nick and the time of receipt. A payload that isn't text
+    /// gets a [`binary_preview`] as its body rather than a mangled copy.
+    pub fn decode(data: &[u8], received_at: u64) -> Self {
+        serde_json::from_slice(data).unwrap_or_else(|_| ChatMessage {
+            nick: String::new(),
+            // Valid text is only copied into the body
+            body: match str::from_utf8(data) {
+                Ok(text) => Arc::from(text),
+                Err(_) => Arc::from(binary_preview(data)),
+            },
+            timestamp: received_at,
+            attachment: None,
+            origin: None,
+            content_type: None,
+        })
+    }
+}
+
+/// A received chat message, with the payload it came in when that wasn't UTF-8 and the body
+/// only describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Incoming {
+    pub chat: ChatMessage,
+    pub binary: Option<Arc<[u8]>>,
+}
+
+impl Incoming {
+    /// Decode a received message like [`ChatMessage::decode`], keeping a binary payload.
+    pub fn decode(data: &[u8], received_at: u64) -> Self {
+        Incoming {
+            chat: ChatMessage::decode(data, received_at),
+            binary: str::from_utf8(data).is_err().then(|| Arc::from(data)),
+        }
+    }
+}
+
+impl From<ChatMessage> for Incoming {
+    fn from(chat: ChatMessage) -> Self {
+        Incoming { chat, binary: None }
+    }
+}
+
+/// A short description of a payload that isn't text: its length and first bytes in hex, like
+/// `⟨binary, 512 bytes: 1f8b0800…⟩`.
+pub fn binary_preview(data: &[u8]) -> String {
+    let mut hex = String::new();
+    for byte in data.iter().take(PREVIEW_BYTES) {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    let more = if data.len() > PREVIEW_BYTES {
+        "…"
+    } else {
+        ""
+    };
+    format!("⟨binary, {} bytes: {hex}{more}⟩", data.len())
+}
+
+// Writer counting the bytes written to it.
+struct ByteCounter(usize);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A received chat message as kept in the node's history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMessage {
+    /// Gossipsub id of the message, as printed when it arrived.
+    pub id: String,
+    /// The original author, if the message was signed.
+    pub source: Option<PeerId>,
+    /// Name of the topic the message arrived on.
+    pub topic: String,
+    pub message: ChatMessage,
+    /// The payload of a message that wasn't UTF-8, whose body is only a preview.
+    pub binary: Option<Arc<[u8]>>,
+    /// Whether the message passed the topic's filter and was displayed.
+    pub shown: bool,
+}
+
+/// How a message's author relates to the peers the user has verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Identity {
+    Unverified,
+    /// The author's fingerprint was confirmed with `/verify`.
+    Verified,
+    /// The author uses the nick of a verified peer but has a different key.
+    Impostor,
+}
+
+/// The line printed for a received message, relayed by `via`, along with the hyperlinks held
+/// back from its sanitized body.
+pub fn render(
+    chat: &ChatMessage,
+    signed: bool,
+    identity: Identity,
+    id: &MessageId,
+    via: &PeerId,
+) -> (String, Vec<Link>) {
+    let (body, links) = sanitize::body(&chat.plain_text());
+    let nick = match chat.origin.as_deref() {
+        // Posted by a hook, so tell it from anyone going by the same nick
+        Some(hooks::ORIGIN) => format!("[hook] {}", sanitize::nick(&chat.nick)),
+        _ => sanitize::nick(&chat.nick),
+    };
+    let badge = match identity {
+        Identity::Unverified => "",
+        Identity::Verified => " ✓",
+        Identity::Impostor => " (NOT the verified peer using this nick)",
+    };
+    let unsigned = if signed { "" } else { " (unsigned)" };
+    let attachment = chat
+        .attachment
+        .as_ref()
+        .map(|attachment| format!(" {attachment}"))
+        .unwrap_or_default();
+    let line = format!(
+        "Got message: '{body}'{attachment} from {nick}{badge}{unsigned} with id: {id} from peer: \
+         {via}"
+    );
+    (line, links)
+}
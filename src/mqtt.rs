@@ -0,0 +1,1144 @@
+// A bridge between an MQTT broker and the room: the node connects to the broker as a client,
+// publishes what arrives on the mapped MQTT topics to the room, and publishes the room's
+// messages to the MQTT topics mapped the other way, such as a command topic.
+//
+// Only MQTT 3.1.1 is spoken, with clean sessions: publishes not yet acknowledged are sent again
+// after a reconnect, but what was published while the node was away is lost. Payloads that
+// aren't text are attached to the chat message as a file rather than mangled into its body.
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use futures_rustls::client::TlsStream;
+use libp2p::futures::{
+    future::Either,
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf},
+    AsyncRead,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::{
+    attachment::{self, AttachmentRef, Cid},
+    message, runtime, tls,
+};
+
+/// The origin of the chat messages copied in from MQTT.
+pub const ORIGIN: &str = "mqtt";
+
+/// Publishes waiting for the broker. Once that many are, more are refused until some go out.
+pub const QUEUE: usize = 256;
+
+/// Publishes of QoS 1 and 2 sent but not yet acknowledged. No more are sent until some are.
+pub const MAX_INFLIGHT: usize = 64;
+
+/// First wait before connecting again after losing the broker, doubled after each failure.
+pub const RECONNECT_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait before connecting again.
+pub const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// How long connecting, TLS handshake and CONNACK included, may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Largest packet taken from the broker. A larger one ends the connection.
+pub const MAX_PACKET_BYTES: usize = 16 * 1024 * 1024;
+
+/// Port of MQTT brokers without TLS.
+pub const PORT: u16 = 1883;
+
+/// Port of MQTT brokers with TLS.
+pub const TLS_PORT: u16 = 8883;
+
+/// Our own publishes remembered, so they aren't mirrored back when a mapping brings them in
+/// again.
+const ECHOES: usize = 64;
+
+/// Which way a mapping mirrors.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// From the MQTT topic to the room.
+    #[default]
+    In,
+    /// From the room to the MQTT topic.
+    Out,
+    Both,
+}
+
+/// An MQTT topic mirrored into a room, or a room mirrored to an MQTT topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MqttMapping {
+    /// The MQTT topic. Mappings into the room may use the `+` and `#` wildcards.
+    pub mqtt_topic: String,
+    /// The room, a topic name without the prefix.
+    pub room: String,
+    #[serde(default)]
+    pub direction: Direction,
+    /// The QoS subscribed and published with, 0, 1 or 2.
+    #[serde(default)]
+    pub qos: u8,
+    /// What is published. Into the room, `{topic}`, `{payload}` and `{payload.field}` of a
+    /// JSON payload are filled in; out of it, `{nick}`, `{room}` and `{text}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Only the room's messages starting with this are published to the MQTT topic, without
+    /// it, such as `!` for commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+}
+
+impl MqttMapping {
+    /// A mapping of `mqtt_topic` and `room` with QoS 0 and no template or prefix.
+    pub fn new(mqtt_topic: &str, room: &str, direction: Direction) -> Self {
+        MqttMapping {
+            mqtt_topic: mqtt_topic.to_string(),
+            room: room.to_string(),
+            direction,
+            qos: 0,
+            template: None,
+            prefix: None,
+        }
+    }
+
+    fn inbound(&self) -> bool {
+        self.direction != Direction::Out
+    }
+
+    fn outbound(&self) -> bool {
+        self.direction != Direction::In
+    }
+
+    /// The body of a chat message for `payload`, published on `topic`.
+    pub fn render_in(&self, topic: &str, payload: &str) -> String {
+        let template = self.template.as_deref().unwrap_or("{topic}: {payload}");
+        let mut json = None;
+        fill(template, |name| match name {
+            "topic" => Some(topic.to_string()),
+            "payload" => Some(payload.to_string()),
+            name => {
+                let path = name.strip_prefix("payload.")?;
+                let json = json.get_or_insert_with(|| {
+                    serde_json::from_str::<Value>(payload).unwrap_or(Value::Null)
+                });
+                Some(field(json, path))
+            }
+        })
+    }
+
+    /// The payload published for `text`, said by `nick`. `None` when it lacks the prefix.
+    pub fn render_out(&self, nick: &str, text: &str) -> Option<String> {
+        let text = match &self.prefix {
+            Some(prefix) => text.strip_prefix(prefix.as_str())?,
+            None => text,
+        };
+        let template = self.template.as_deref().unwrap_or("{text}");
+        Some(fill(template, |name| match name {
+            "nick" => Some(nick.to_string()),
+            "room" => Some(self.room.clone()),
+            "text" => Some(text.to_string()),
+            _ => None,
+        }))
+    }
+}
+
+// `template` with the `{name}`s `value` knows replaced. Others are left as they are.
+fn fill(template: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after
+            .find('}')
+            .and_then(|end| Some((end, value(&after[..end])?)))
+        {
+            Some((end, replacement)) => {
+                filled.push_str(&replacement);
+                rest = &after[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+// The field at the dotted `path` of `json`, as text. Empty when there is none.
+fn field(json: &Value, path: &str) -> String {
+    let found = path.split('.').try_fold(json, |value, key| match value {
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        value => value.get(key),
+    });
+    match found {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Null) | None => String::new(),
+        Some(value) => value.to_string(),
+    }
+}
+
+/// Whether `topic` is matched by the topic filter `filter`, which may have wildcards: `+` for
+/// one level and `#` for all the levels left.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    // Wildcards at the start don't match the broker's own $ topics
+    if topic.starts_with('$') && filter.starts_with(['+', '#']) {
+        return false;
+    }
+    let (mut filter, mut topic) = (filter.split('/'), topic.split('/'));
+    loop {
+        match (filter.next(), topic.next()) {
+            (Some("#"), _) => return true,
+            (Some("+"), Some(_)) => {}
+            (Some(expected), Some(level)) if expected == level => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+// Check an MQTT topic, or a topic filter when `wildcards` are allowed.
+fn check_topic(topic: &str, wildcards: bool) -> Result<(), String> {
+    if topic.is_empty() || topic.len() > usize::from(u16::MAX) || topic.contains('\0') {
+        return Err(format!("{topic:?} isn't an MQTT topic"));
+    }
+    let levels: Vec<_> = topic.split('/').collect();
+    for (at, level) in levels.iter().enumerate() {
+        let wild = level.contains(['+', '#']);
+        if wild && !wildcards {
+            return Err(format!(
+                "{topic} has wildcards, so it can't be published to"
+            ));
+        }
+        let valid = !wild || *level == "+" || (*level == "#" && at == levels.len() - 1);
+        if !valid {
+            return Err(format!("{topic} has a misplaced wildcard"));
+        }
+    }
+    Ok(())
+}
+
+/// The broker a node mirrors topics of, saved in the `mqtt` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MqttSettings {
+    /// The broker, as `host` or `host:port`.
+    pub broker: String,
+    /// Whether to connect over TLS.
+    #[serde(default)]
+    pub tls: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// The client id the broker knows the node by. A random one when none is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// Seconds between pings when nothing else is sent.
+    #[serde(default = "default_keep_alive")]
+    pub keep_alive: u16,
+    pub mappings: Vec<MqttMapping>,
+}
+
+fn default_keep_alive() -> u16 {
+    60
+}
+
+impl MqttSettings {
+    /// Settings for `broker` without TLS or a login, and with the default keep-alive.
+    pub fn new(broker: &str, mappings: Vec<MqttMapping>) -> Self {
+        MqttSettings {
+            broker: broker.to_string(),
+            tls: false,
+            username: None,
+            password: None,
+            client_id: None,
+            keep_alive: default_keep_alive(),
+            mappings,
+        }
+    }
+
+    /// The host and port of the broker, [`TLS_PORT`] or [`PORT`] when none is given.
+    pub fn address(&self) -> Result<(String, u16), String> {
+        let default = if self.tls { TLS_PORT } else { PORT };
+        let (host, port) = match self.broker.rsplit_once(':') {
+            // A bare IPv6 address has colons but no port
+            Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("{port} isn't a port number"))?;
+                (host, port)
+            }
+            _ => (self.broker.as_str(), default),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() {
+            return Err("the MQTT broker has no host".to_string());
+        }
+        Ok((host.to_string(), port))
+    }
+
+    /// Check the settings before connecting with them.
+    pub fn check(&self) -> Result<(), String> {
+        self.address()?;
+        if self.password.is_some() && self.username.is_none() {
+            return Err("a password needs a username".to_string());
+        }
+        if self.client_id.as_deref() == Some("") {
+            return Err("the client id is empty".to_string());
+        }
+        if self.keep_alive == 0 {
+            return Err("keep_alive must be over 0".to_string());
+        }
+        if self.mappings.is_empty() {
+            return Err("there are no mappings".to_string());
+        }
+        for mapping in &self.mappings {
+            check_topic(&mapping.mqtt_topic, mapping.direction == Direction::In)?;
+            if mapping.qos > 2 {
+                return Err(format!(
+                    "{} has QoS {}, not 0 to 2",
+                    mapping.mqtt_topic, mapping.qos
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The directory kept next to the config file for payloads attached to chat messages: `config.json`
+/// keeps them in `config.mqtt`.
+pub fn dir_beside(config_path: &Path) -> PathBuf {
+    config_path.with_extension("mqtt")
+}
+
+/// What happens on the connection to the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MqttEvent {
+    /// Connected, or again, and subscribed to `subscriptions` topics.
+    Connected { subscriptions: usize },
+    /// The connection was lost or couldn't be made. Connecting is tried again with backoff,
+    /// and this isn't repeated until a connection was made.
+    Disconnected(String),
+    /// The broker refused the subscription to this topic filter.
+    Refused(String),
+    /// `payload` arrived on `topic`. `body` is what a mapping makes of it. A payload that isn't
+    /// text was written to the file attached, and `body` only describes it.
+    Message {
+        topic: String,
+        body: String,
+        attachment: Option<(AttachmentRef, PathBuf)>,
+    },
+}
+
+/// An MQTT control packet, as far as a client needs them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Connect {
+        client_id: String,
+        username: Option<String>,
+        password: Option<String>,
+        keep_alive: u16,
+    },
+    ConnAck {
+        session_present: bool,
+        code: u8,
+    },
+    Publish {
+        topic: String,
+        /// The packet id, which QoS 1 and 2 publishes have.
+        id: Option<u16>,
+        qos: u8,
+        retain: bool,
+        dup: bool,
+        payload: Vec<u8>,
+    },
+    PubAck(u16),
+    PubRec(u16),
+    PubRel(u16),
+    PubComp(u16),
+    Subscribe {
+        id: u16,
+        filters: Vec<(String, u8)>,
+    },
+    SubAck {
+        id: u16,
+        codes: Vec<u8>,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+impl Packet {
+    /// The packet as sent.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let first = match self {
+            Packet::Connect {
+                client_id,
+                username,
+                password,
+                keep_alive,
+            } => {
+                put_bytes(&mut body, b"MQTT");
+                body.push(4);
+                // A clean session, with the login flags
+                let mut flags = 0x02;
+                if username.is_some() {
+                    flags |= 0x80;
+                }
+                if password.is_some() {
+                    flags |= 0x40;
+                }
+                body.push(flags);
+                body.extend_from_slice(&keep_alive.to_be_bytes());
+                put_bytes(&mut body, client_id.as_bytes());
+                for field in [username, password].into_iter().flatten() {
+                    put_bytes(&mut body, field.as_bytes());
+                }
+                0x10
+            }
+            Packet::ConnAck {
+                session_present,
+                code,
+            } => {
+                body.extend_from_slice(&[u8::from(*session_present), *code]);
+                0x20
+            }
+            Packet::Publish {
+                topic,
+                id,
+                qos,
+                retain,
+                dup,
+                payload,
+            } => {
+                put_bytes(&mut body, topic.as_bytes());
+                if let Some(id) = id {
+                    body.extend_from_slice(&id.to_be_bytes());
+                }
+                body.extend_from_slice(payload);
+                0x30 | u8::from(*dup) << 3 | qos << 1 | u8::from(*retain)
+            }
+            Packet::PubAck(id) => return with_id(0x40, *id),
+            Packet::PubRec(id) => return with_id(0x50, *id),
+            Packet::PubRel(id) => return with_id(0x62, *id),
+            Packet::PubComp(id) => return with_id(0x70, *id),
+            Packet::Subscribe { id, filters } => {
+                body.extend_from_slice(&id.to_be_bytes());
+                for (filter, qos) in filters {
+                    put_bytes(&mut body, filter.as_bytes());
+                    body.push(*qos);
+                }
+                0x82
+            }
+            Packet::SubAck { id, codes } => {
+                body.extend_from_slice(&id.to_be_bytes());
+                body.extend_from_slice(codes);
+                0x90
+            }
+            Packet::PingReq => 0xc0,
+            Packet::PingResp => 0xd0,
+            Packet::Disconnect => 0xe0,
+        };
+        frame(first, &body)
+    }
+
+    /// The packet at the start of `buffer` and its length, or `None` if it isn't all there
+    /// yet.
+    pub fn decode(buffer: &[u8]) -> Result<Option<(Packet, usize)>, String> {
+        let Some((len, header)) = header(buffer)? else {
+            return Ok(None);
+        };
+        if len > MAX_PACKET_BYTES {
+            return Err(format!("a packet is over {MAX_PACKET_BYTES} bytes"));
+        }
+        let Some(body) = buffer.get(header..header + len) else {
+            return Ok(None);
+        };
+        let first = buffer[0];
+        let mut body = Body(body);
+        let packet = match first >> 4 {
+            1 => {
+                if body.bytes()? != b"MQTT" || body.byte()? != 4 {
+                    return Err("only MQTT 3.1.1 is spoken".to_string());
+                }
+                let flags = body.byte()?;
+                let keep_alive = body.id()?;
+                let client_id = body.string()?;
+                // A will, which this client never sends
+                if flags & 0x04 != 0 {
+                    body.bytes()?;
+                    body.bytes()?;
+                }
+                let username = (flags & 0x80 != 0).then(|| body.string()).transpose()?;
+                let password = (flags & 0x40 != 0).then(|| body.string()).transpose()?;
+                Packet::Connect {
+                    client_id,
+                    username,
+                    password,
+                    keep_alive,
+                }
+            }
+            2 => Packet::ConnAck {
+                session_present: body.byte()? & 1 != 0,
+                code: body.byte()?,
+            },
+            3 => {
+                let qos = (first >> 1) & 3;
+                if qos > 2 {
+                    return Err("a publish has QoS 3".to_string());
+                }
+                let topic = body.string()?;
+                let id = (qos > 0).then(|| body.id()).transpose()?;
+                Packet::Publish {
+                    topic,
+                    id,
+                    qos,
+                    retain: first & 1 != 0,
+                    dup: first & 8 != 0,
+                    payload: body.0.to_vec(),
+                }
+            }
+            4 => Packet::PubAck(body.id()?),
+            5 => Packet::PubRec(body.id()?),
+            6 => Packet::PubRel(body.id()?),
+            7 => Packet::PubComp(body.id()?),
+            8 => {
+                let id = body.id()?;
+                let mut filters = Vec::new();
+                while !body.0.is_empty() {
+                    filters.push((body.string()?, body.byte()?));
+                }
+                Packet::Subscribe { id, filters }
+            }
+            9 => Packet::SubAck {
+                id: body.id()?,
+                codes: body.0.to_vec(),
+            },
+            12 => Packet::PingReq,
+            13 => Packet::PingResp,
+            14 => Packet::Disconnect,
+            kind => return Err(format!("unexpected packet of type {kind}")),
+        };
+        Ok(Some((packet, header + len)))
+    }
+}
+
+fn put_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    // Topics and logins are checked to fit in the length
+    let len = u16::try_from(bytes.len()).unwrap_or(u16::MAX);
+    buffer.extend_from_slice(&len.to_be_bytes());
+    buffer.extend_from_slice(&bytes[..usize::from(len)]);
+}
+
+fn with_id(first: u8, id: u16) -> Vec<u8> {
+    frame(first, &id.to_be_bytes())
+}
+
+// A packet of `body`, after the fixed header with its remaining length.
+fn frame(first: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![first];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+// The remaining length of the packet at the start of `buffer`, and the length of its fixed
+// header, once they are there.
+fn header(buffer: &[u8]) -> Result<Option<(usize, usize)>, String> {
+    let mut len = 0;
+    for at in 1..=4 {
+        let Some(&byte) = buffer.get(at) else {
+            return Ok(None);
+        };
+        len |= usize::from(byte & 0x7f) << (7 * (at - 1));
+        if byte & 0x80 == 0 {
+            return Ok(Some((len, at + 1)));
+        }
+    }
+    Err("a packet's length is malformed".to_string())
+}
+
+// The rest of a packet being decoded.
+struct Body<'a>(&'a [u8]);
+
+impl<'a> Body<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("a packet ends early".to_string());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn id(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.id()?;
+        self.take(usize::from(len))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| "a string isn't UTF-8".to_string())
+    }
+}
+
+// Why the broker refused to connect us, by CONNACK return code.
+fn refusal(code: u8) -> &'static str {
+    match code {
+        1 => "it doesn't speak MQTT 3.1.1",
+        2 => "it refused the client id",
+        3 => "it is unavailable",
+        4 => "bad username or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
+
+// A publish waiting for the broker.
+#[derive(Debug)]
+struct Outgoing {
+    topic: String,
+    qos: u8,
+    payload: Vec<u8>,
+}
+
+/// The connection to an MQTT broker, kept on a task of its own that reconnects whenever it is
+/// lost. Dropping it closes the connection.
+#[derive(Debug)]
+pub struct MqttBridge {
+    settings: MqttSettings,
+    outgoing: mpsc::Sender<Outgoing>,
+    incoming: mpsc::Receiver<MqttEvent>,
+    task: runtime::Task<()>,
+}
+
+impl MqttBridge {
+    /// Connect to the broker of `settings` and subscribe to the topics mapped into the room.
+    /// Payloads that aren't text are written to `spool` to be attached.
+    pub fn spawn(settings: MqttSettings, spool: PathBuf) -> Self {
+        let (outgoing, queue) = mpsc::channel(QUEUE);
+        let (events, incoming) = mpsc::channel(QUEUE);
+        let task = runtime::spawn(run(settings.clone(), spool, queue, events));
+        MqttBridge {
+            settings,
+            outgoing,
+            incoming,
+            task,
+        }
+    }
+
+    pub fn settings(&self) -> &MqttSettings {
+        &self.settings
+    }
+
+    /// Publish `text`, said by `nick` in the room, to the MQTT topics mapped out of the room.
+    /// Returns how many it goes to. Fails while [`QUEUE`] publishes are waiting.
+    pub fn relay(&self, nick: &str, text: &str) -> Result<usize, String> {
+        let mut relayed = 0;
+        for mapping in self.settings.mappings.iter().filter(|m| m.outbound()) {
+            let Some(payload) = mapping.render_out(nick, text) else {
+                continue;
+            };
+            let outgoing = Outgoing {
+                topic: mapping.mqtt_topic.clone(),
+                qos: mapping.qos,
+                payload: payload.into_bytes(),
+            };
+            self.outgoing
+                .try_send(outgoing)
+                .map_err(|_| format!("{QUEUE} publishes are already waiting for the broker"))?;
+            relayed += 1;
+        }
+        Ok(relayed)
+    }
+
+    /// The next thing that happened on the connection.
+    pub async fn next(&mut self) -> Option<MqttEvent> {
+        self.incoming.recv().await
+    }
+}
+
+impl Drop for MqttBridge {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// A publish of ours the broker hasn't finished acknowledging.
+#[derive(Debug)]
+enum Inflight {
+    // Waiting for PUBACK, or PUBREC for QoS 2
+    Sent(Packet),
+    // QoS 2, released and waiting for PUBCOMP
+    Released,
+}
+
+// What outlives a connection.
+struct State {
+    settings: MqttSettings,
+    client_id: String,
+    spool: PathBuf,
+    inflight: BTreeMap<u16, Inflight>,
+    next_id: u16,
+    // Our publishes to topics mapped into the room, to skip when they come back
+    echoes: VecDeque<(String, Vec<u8>)>,
+    // Payloads written to the spool, the oldest removed beyond what peers are served
+    spooled: VecDeque<PathBuf>,
+}
+
+impl State {
+    // A packet id not in use.
+    fn id(&mut self) -> u16 {
+        loop {
+            self.next_id = self.next_id.wrapping_add(1);
+            if self.next_id != 0 && !self.inflight.contains_key(&self.next_id) {
+                return self.next_id;
+            }
+        }
+    }
+
+    // Write `payload` to the spool to be attached, as the content of its id.
+    fn spool(&mut self, topic: &str, payload: &[u8]) -> Result<(AttachmentRef, PathBuf), String> {
+        let cid = Cid::of(payload);
+        let path = self.spool.join(cid.to_string());
+        if !path.exists() {
+            fs::create_dir_all(&self.spool)
+                .and_then(|()| fs::write(&path, payload))
+                .map_err(|e| format!("can't write {}: {e}", path.display()))?;
+            self.spooled.push_back(path.clone());
+            if self.spooled.len() > attachment::MAX_SHARED_FILES {
+                if let Some(oldest) = self.spooled.pop_front() {
+                    let _ = fs::remove_file(oldest);
+                }
+            }
+        }
+        let filename = topic.rsplit('/').next().unwrap_or(topic);
+        let attachment = AttachmentRef {
+            cid,
+            size: payload.len() as u64,
+            mime_type: attachment::sniff_mime_type(payload).to_string(),
+            filename: if filename.is_empty() {
+                "payload"
+            } else {
+                filename
+            }
+            .to_string(),
+        };
+        Ok((attachment, path))
+    }
+}
+
+type Socket = Either<TlsStream<runtime::TcpStream>, runtime::TcpStream>;
+
+// Keep connected to the broker until the node goes away.
+async fn run(
+    settings: MqttSettings,
+    spool: PathBuf,
+    mut queue: mpsc::Receiver<Outgoing>,
+    events: mpsc::Sender<MqttEvent>,
+) {
+    let client_id = settings
+        .client_id
+        .clone()
+        .unwrap_or_else(|| format!("p2p-chat-{:08x}", rand::random::<u32>()));
+    let mut state = State {
+        settings,
+        client_id,
+        spool,
+        inflight: BTreeMap::new(),
+        next_id: 0,
+        echoes: VecDeque::new(),
+        spooled: VecDeque::new(),
+    };
+    let mut backoff = RECONNECT_MIN;
+    let mut reported = false;
+    loop {
+        let connected = runtime::timeout(CONNECT_TIMEOUT, connect(&state))
+            .await
+            .map_err(|_| "connecting timed out".to_string())
+            .and_then(|connected| connected);
+        let error = match connected {
+            Ok(reader) => {
+                let session = Session {
+                    state: &mut state,
+                    events: &events,
+                    subscribed: false,
+                    releasing: HashSet::new(),
+                };
+                let (error, subscribed) = session.run(reader, &mut queue).await;
+                if subscribed {
+                    backoff = RECONNECT_MIN;
+                    reported = false;
+                }
+                error
+            }
+            Err(e) => e,
+        };
+        if events.is_closed() {
+            return;
+        }
+        debug!(
+            "[mqtt] {}: {error}, connecting again in {backoff:?}",
+            state.settings.broker
+        );
+        if !reported {
+            reported = true;
+            let _ = events.send(MqttEvent::Disconnected(error)).await;
+        }
+        runtime::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+// Packets read from one side of a connection.
+struct Reader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    // The next packet. A malformed one or the end of the stream fails.
+    async fn packet(&mut self) -> Result<Packet, String> {
+        loop {
+            if let Some((packet, len)) = Packet::decode(&self.buffer)? {
+                self.buffer.drain(..len);
+                return Ok(packet);
+            }
+            // Only what a read returned in full is added, so a read given up on loses nothing
+            let mut chunk = [0; 8192];
+            let read = self
+                .inner
+                .read(&mut chunk)
+                .await
+                .map_err(|e| e.to_string())?;
+            if read == 0 {
+                return Err("the broker closed the connection".to_string());
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+async fn send(writer: &mut (impl AsyncWrite + Unpin), packet: &Packet) -> Result<(), String> {
+    writer
+        .write_all(&packet.encode())
+        .await
+        .map_err(|e| e.to_string())?;
+    writer.flush().await.map_err(|e| e.to_string())
+}
+
+// Connect, over TLS if asked to, and have the broker accept us.
+async fn connect(state: &State) -> Result<Reader<Socket>, String> {
+    let settings = &state.settings;
+    let (host, port) = settings.address()?;
+    let tcp = runtime::connect_tcp(&host, port)
+        .await
+        .map_err(|e| e.to_string())?;
+    let socket = if settings.tls {
+        Either::Left(tls::connect(&host, tcp).await?)
+    } else {
+        Either::Right(tcp)
+    };
+    let mut reader = Reader {
+        inner: socket,
+        buffer: Vec::new(),
+    };
+    let connect = Packet::Connect {
+        client_id: state.client_id.clone(),
+        username: settings.username.clone(),
+        password: settings.password.clone(),
+        keep_alive: settings.keep_alive,
+    };
+    send(&mut reader.inner, &connect).await?;
+    match reader.packet().await? {
+        Packet::ConnAck { code: 0, .. } => Ok(reader),
+        Packet::ConnAck { code, .. } => Err(format!("the broker refused us: {}", refusal(code))),
+        packet => Err(format!("the broker sent {packet:?} instead of CONNACK")),
+    }
+}
+
+// One connection to the broker, after it accepted us.
+struct Session<'a> {
+    state: &'a mut State,
+    events: &'a mpsc::Sender<MqttEvent>,
+    subscribed: bool,
+    // QoS 2 publishes received and not yet released, so a repeat isn't passed on twice
+    releasing: HashSet<u16>,
+}
+
+impl Session<'_> {
+    // Subscribe, send again what the broker hadn't acknowledged, then pass what arrives on to
+    // the node and what the node publishes to the broker, until the connection fails. Returns
+    // why it did, and whether the subscriptions were made.
+    async fn run(
+        mut self,
+        reader: Reader<Socket>,
+        queue: &mut mpsc::Receiver<Outgoing>,
+    ) -> (String, bool) {
+        let (inner, mut writer) = reader.inner.split();
+        let mut reader = Reader {
+            inner,
+            buffer: reader.buffer,
+        };
+        if let Err(e) = self.start(&mut writer).await {
+            return (e, false);
+        }
+        let keep_alive = Duration::from_secs(self.state.settings.keep_alive.into());
+        let mut ping = runtime::interval(keep_alive);
+        // The first tick is straight away, before the broker could have been silent
+        ping.tick().await;
+        let mut heard = Instant::now();
+        let error = loop {
+            let room = self.state.inflight.len() < MAX_INFLIGHT;
+            tokio::select! {
+                packet = reader.packet() => {
+                    let packet = match packet {
+                        Ok(packet) => packet,
+                        Err(e) => break e,
+                    };
+                    heard = Instant::now();
+                    if let Err(e) = self.handle(packet, &mut writer).await {
+                        break e;
+                    }
+                }
+                outgoing = queue.recv(), if room => {
+                    let Some(outgoing) = outgoing else {
+                        break "the node went away".to_string();
+                    };
+                    if let Err(e) = self.publish(outgoing, &mut writer).await {
+                        break e;
+                    }
+                }
+                _ = ping.tick() => {
+                    if heard.elapsed() > 2 * keep_alive {
+                        break "the broker stopped answering".to_string();
+                    }
+                    if let Err(e) = send(&mut writer, &Packet::PingReq).await {
+                        break e;
+                    }
+                }
+            }
+        };
+        (error, self.subscribed)
+    }
+
+    // Subscribe to the topics mapped into the room, and send again what the broker hadn't
+    // acknowledged when the last connection was lost.
+    async fn start(&mut self, writer: &mut WriteHalf<Socket>) -> Result<(), String> {
+        let filters = self.filters();
+        if filters.is_empty() {
+            self.subscribed = true;
+            self.emit(MqttEvent::Connected { subscriptions: 0 }).await?;
+        } else {
+            let id = self.state.id();
+            send(writer, &Packet::Subscribe { id, filters }).await?;
+        }
+        for (&id, inflight) in &mut self.state.inflight {
+            let packet = match inflight {
+                Inflight::Sent(packet) => {
+                    if let Packet::Publish { dup, .. } = packet {
+                        *dup = true;
+                    }
+                    packet.clone()
+                }
+                Inflight::Released => Packet::PubRel(id),
+            };
+            send(writer, &packet).await?;
+        }
+        Ok(())
+    }
+
+    // The topic filters mapped into the room, each with the highest QoS asked for it.
+    fn filters(&self) -> Vec<(String, u8)> {
+        let mut filters: BTreeMap<&str, u8> = BTreeMap::new();
+        for mapping in self.state.settings.mappings.iter().filter(|m| m.inbound()) {
+            let qos = filters.entry(&mapping.mqtt_topic).or_default();
+            *qos = (*qos).max(mapping.qos);
+        }
+        filters
+            .into_iter()
+            .map(|(filter, qos)| (filter.to_string(), qos))
+            .collect()
+    }
+
+    async fn publish(
+        &mut self,
+        outgoing: Outgoing,
+        writer: &mut WriteHalf<Socket>,
+    ) -> Result<(), String> {
+        let state = &mut *self.state;
+        let mapped_in = state
+            .settings
+            .mappings
+            .iter()
+            .any(|m| m.inbound() && topic_matches(&m.mqtt_topic, &outgoing.topic));
+        if mapped_in {
+            if state.echoes.len() >= ECHOES {
+                state.echoes.pop_front();
+            }
+            state
+                .echoes
+                .push_back((outgoing.topic.clone(), outgoing.payload.clone()));
+        }
+        let id = (outgoing.qos > 0).then(|| state.id());
+        let packet = Packet::Publish {
+            topic: outgoing.topic,
+            id,
+            qos: outgoing.qos,
+            retain: false,
+            dup: false,
+            payload: outgoing.payload,
+        };
+        send(writer, &packet).await?;
+        if let Some(id) = id {
+            state.inflight.insert(id, Inflight::Sent(packet));
+        }
+        Ok(())
+    }
+
+    // Acknowledge a packet from the broker, and pass what was published on to the node.
+    // Fails when the connection has to be given up.
+    async fn handle(
+        &mut self,
+        packet: Packet,
+        writer: &mut WriteHalf<Socket>,
+    ) -> Result<(), String> {
+        match packet {
+            Packet::Publish {
+                topic,
+                id,
+                qos,
+                retain,
+                payload,
+                ..
+            } => {
+                let repeated = match (qos, id) {
+                    (1, Some(id)) => {
+                        send(writer, &Packet::PubAck(id)).await?;
+                        false
+                    }
+                    (2, Some(id)) => {
+                        send(writer, &Packet::PubRec(id)).await?;
+                        !self.releasing.insert(id)
+                    }
+                    _ => false,
+                };
+                // Retained payloads were published before we came, like history
+                if !repeated && !retain {
+                    self.received(topic, payload).await?;
+                }
+            }
+            Packet::PubAck(id) | Packet::PubComp(id) => {
+                self.state.inflight.remove(&id);
+            }
+            Packet::PubRec(id) => {
+                self.state.inflight.insert(id, Inflight::Released);
+                send(writer, &Packet::PubRel(id)).await?;
+            }
+            Packet::PubRel(id) => {
+                self.releasing.remove(&id);
+                send(writer, &Packet::PubComp(id)).await?;
+            }
+            Packet::SubAck { codes, .. } => {
+                let filters = self.filters();
+                for ((filter, _), code) in filters.iter().zip(&codes) {
+                    if *code == 0x80 {
+                        self.emit(MqttEvent::Refused(filter.clone())).await?;
+                    }
+                }
+                if !self.subscribed {
+                    self.subscribed = true;
+                    let subscriptions = codes.iter().filter(|&&code| code != 0x80).count();
+                    self.emit(MqttEvent::Connected { subscriptions }).await?;
+                }
+            }
+            Packet::Disconnect => return Err("the broker disconnected us".to_string()),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Pass a payload published on `topic` on to the node, made into a chat message by the
+    // first mapping of the topic.
+    async fn received(&mut self, topic: String, payload: Vec<u8>) -> Result<(), String> {
+        let state = &mut *self.state;
+        if let Some(at) = state
+            .echoes
+            .iter()
+            .position(|(echo, sent)| *echo == topic && *sent == payload)
+        {
+            state.echoes.remove(at);
+            return Ok(());
+        }
+        let Some(mapping) = state
+            .settings
+            .mappings
+            .iter()
+            .find(|m| m.inbound() && topic_matches(&m.mqtt_topic, &topic))
+        else {
+            return Ok(());
+        };
+        if payload.is_empty() {
+            return Ok(());
+        }
+        let event = match String::from_utf8(payload) {
+            Ok(text) => MqttEvent::Message {
+                body: mapping.render_in(&topic, &text),
+                topic,
+                attachment: None,
+            },
+            Err(e) => {
+                let payload = e.into_bytes();
+                let body = mapping.render_in(&topic, &message::binary_preview(&payload));
+                let attachment = match state.spool(&topic, &payload) {
+                    Ok(attachment) => Some(attachment),
+                    Err(e) => {
+                        debug!("[mqtt] not attaching a payload from {topic}: {e}");
+                        None
+                    }
+                };
+                MqttEvent::Message {
+                    topic,
+                    body,
+                    attachment,
+                }
+            }
+        };
+        self.emit(event).await
+    }
+
+    async fn emit(&self, event: MqttEvent) -> Result<(), String> {
+        self.events
+            .send(event)
+            .await
+            .map_err(|_| "the node went away".to_string())
+    }
+}
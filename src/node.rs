@@ -0,0 +1,272 @@
+// Construction of the swarm (the P2P node) and its network behaviour.
+use std::{io, time::Duration};
+
+use libp2p::{
+    // Connection gating for blocked peers, and for peers missing from the allowlist.
+    allow_block_list,
+    // A hard cap on open connections (`--max-peers`).
+    connection_limits::{self, ConnectionLimits},
+    // Direct Connection Upgrade through Relay: hole punching over TCP and QUIC.
+    dcutr,
+    // Gossipsub is a pub/sub messaging protocol used for decentralized communication.
+    gossipsub,
+    // Identify tells peers the address they see us at, our candidates for hole punching.
+    identify,
+    // Identity keypairs are used to sign messages and derive the node's PeerId.
+    identity::Keypair,
+    // mDNS (Multicast DNS) helps discover peers in the local network.
+    mdns,
+    // Multiaddresses, whose last protocol may name the peer to dial.
+    multiaddr::Protocol,
+    // Noise secures connections relayed over a circuit.
+    noise,
+    // Ping measures round-trip times, which adjust peer scores.
+    ping,
+    // Circuit Relay v2 client, for reservations on a relay and relayed connections.
+    relay,
+    // NetworkBehaviour defines the behavior of a node in the network (combining Gossipsub and mDNS).
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour},
+    // SwarmBuilder is used to create and configure the swarm (the core of peer-to-peer networking).
+    Swarm,
+    SwarmBuilder,
+};
+
+use crate::{
+    allowlist, attachment, auth,
+    cli::Cli,
+    content_type,
+    error::{ChatError, CryptoError},
+    psk, snapshot,
+    transport::{self, MuxerCounts},
+};
+
+/// Name of the Gossipsub topic that all peers subscribe to, under their topic prefix.
+pub const TOPIC: &str = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx";
+
+/// Prefix of every topic name unless `--topic-prefix` names another deployment.
+pub const DEFAULT_TOPIC_PREFIX: &str = "p2pchat";
+
+/// Longest topic prefix, in characters.
+pub const MAX_TOPIC_PREFIX_LEN: usize = 32;
+
+/// Largest Gossipsub message sent or accepted, in bytes. Larger chat messages are fragmented.
+pub const MAX_TRANSMIT_SIZE: usize = 1024 * 1024;
+
+/// Protocol version announced to peers with Identify, followed by the topic prefix.
+pub const PROTOCOL_VERSION: &str = "/p2p-chat/1.0.0";
+
+/// The chat topic named `name` in the deployment of `prefix`: `<prefix>/<name>`.
+pub fn chat_topic(prefix: &str, name: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{prefix}/{name}"))
+}
+
+/// The chat topic of nodes started without `--topic-prefix` or `--room-pass`.
+pub fn default_topic() -> gossipsub::IdentTopic {
+    chat_topic(DEFAULT_TOPIC_PREFIX, TOPIC)
+}
+
+/// The protocol version announced with Identify by nodes of the deployment of `prefix`.
+pub fn protocol_version(prefix: &str) -> String {
+    format!("{PROTOCOL_VERSION}/{prefix}")
+}
+
+/// Whether a peer announcing `version` with Identify runs the chat under another topic prefix
+/// than `prefix`, or without one. Peers that aren't chat nodes, such as relays, don't.
+pub fn is_other_deployment(version: &str, prefix: &str) -> bool {
+    version.starts_with("/p2p-chat/") && version != protocol_version(prefix)
+}
+
+/// Check a topic prefix: 1 to [`MAX_TOPIC_PREFIX_LEN`] ASCII letters, digits and hyphens, so
+/// every topic name stays well formed.
+pub fn check_topic_prefix(prefix: &str) -> Result<(), String> {
+    if prefix.is_empty() || prefix.len() > MAX_TOPIC_PREFIX_LEN {
+        return Err(format!(
+            "the topic prefix must be 1 to {MAX_TOPIC_PREFIX_LEN} characters long"
+        ));
+    }
+    if !prefix
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err("the topic prefix may only contain letters, digits and hyphens".to_string());
+    }
+    Ok(())
+}
+
+/// mDNS discovery on the runtime picked at build time.
+#[cfg(not(feature = "async-std"))]
+pub type Mdns = mdns::tokio::Behaviour;
+#[cfg(feature = "async-std")]
+pub type Mdns = mdns::async_io::Behaviour;
+
+/// Keeps a message's source only when the message was signed.
+///
+/// Gossipsub runs in permissive mode so that unsigned messages reach the chat node instead of
+/// vanishing silently. Signed messages are still verified before they get here, but an unsigned
+/// message may claim any source, so that claim is dropped and unsigned messages arrive
+/// without one.
+#[derive(Debug, Default, Clone)]
+pub struct SignedSourceOnly;
+
+impl gossipsub::DataTransform for SignedSourceOnly {
+    fn inbound_transform(
+        &self,
+        raw_message: gossipsub::RawMessage,
+    ) -> Result<gossipsub::Message, io::Error> {
+        let signed = raw_message.signature.is_some();
+        Ok(gossipsub::Message {
+            source: raw_message.source.filter(|_| signed),
+            data: raw_message.data,
+            sequence_number: raw_message.sequence_number,
+            topic: raw_message.topic,
+        })
+    }
+
+    fn outbound_transform(
+        &self,
+        _topic: &gossipsub::TopicHash,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, io::Error> {
+        Ok(data)
+    }
+}
+
+// Define a custom network behavior by combining Gossipsub and mDNS.
+// This macro derives the necessary code to combine the two protocols.
+#[derive(NetworkBehaviour)]
+pub struct MyBehaviour {
+    // Gossipsub for pub-sub message passing
+    pub gossipsub: gossipsub::Behaviour<SignedSourceOnly>,
+    // mDNS for peer discovery in a local network (disabled with `--no-mdns`)
+    pub mdns: Toggle<Mdns>,
+    // Refuses and closes connections to blocked peers
+    pub blocked: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+    // Refuses connections to peers missing from `--allowlist-file` (disabled without one)
+    pub allowed: Toggle<allow_block_list::Behaviour<allow_block_list::AllowedPeers>>,
+    // Reservations on a relay (`--relay-server`) and connections relayed through one
+    pub relay: relay::client::Behaviour,
+    // Refuses connections beyond `--max-peers`; the chat node trims before it gets there
+    pub limits: connection_limits::Behaviour,
+    // Round-trip times to connected peers, for ranking them by latency
+    pub ping: ping::Behaviour,
+    // Observed addresses, which hole punching needs to know where to dial us
+    pub identify: identify::Behaviour,
+    // Replaces connections relayed to us with direct ones by hole punching, over QUIC or TCP
+    pub dcutr: dcutr::Behaviour,
+    // Snapshots of a room's members, sent to peers joining it
+    pub snapshot: snapshot::Behaviour,
+    // Challenges of a `ConnectionAuthenticator`, and our answers to peers' challenges
+    pub auth: auth::Behaviour,
+    // Attachments fetched by content id from the peers that sent them
+    pub content: attachment::Behaviour,
+}
+
+/// Create the swarm (P2P node) with a fresh identity.
+pub fn build_swarm(cli: &Cli) -> Result<Swarm<MyBehaviour>, ChatError> {
+    build_swarm_with_identity(Keypair::generate_ed25519(), cli, &MuxerCounts::default())
+}
+
+/// Create the swarm (P2P node) by building the transport stack and network behaviour,
+/// counting the multiplexer of every TCP connection in `muxers`.
+pub fn build_swarm_with_identity(
+    keypair: Keypair,
+    cli: &Cli,
+    muxers: &MuxerCounts,
+) -> Result<Swarm<MyBehaviour>, ChatError> {
+    // Load the pre-shared key when the node is part of a private network
+    let swarm_key = cli.swarm_key.as_deref().map(psk::load).transpose()?;
+    let transport = transport::build_transport(&keypair, cli, swarm_key, muxers)?;
+    // Only peers on the allowlist may connect, and always the relay we reserve a slot on
+    let allowed = cli
+        .allowlist_file
+        .as_deref()
+        .map(allowlist::load)
+        .transpose()?
+        .map(|peers| {
+            let mut allowed =
+                allow_block_list::Behaviour::<allow_block_list::AllowedPeers>::default();
+            let relay = cli
+                .relay_server
+                .as_ref()
+                .and_then(|address| match address.iter().last() {
+                    Some(Protocol::P2p(peer)) => Some(peer),
+                    _ => None,
+                });
+            for peer in peers.into_iter().chain(relay) {
+                allowed.allow_peer(peer);
+            }
+            allowed
+        });
+
+    let builder = SwarmBuilder::with_existing_identity(keypair);
+    // Run connections on the runtime picked at build time
+    #[cfg(not(feature = "async-std"))]
+    let builder = builder.with_tokio();
+    #[cfg(feature = "async-std")]
+    let builder = builder.with_async_std();
+    let swarm = builder
+        // Set up TCP (Noise/TLS encryption, Yamux multiplexing) and, unless private, QUIC
+        .with_other_transport(|_| transport)?
+        // Relayed connections are secured with Noise inside the circuit
+        .with_relay_client(noise::Config::new, || transport::yamux_config(cli))
+        .map_err(CryptoError::from)?
+        // Define the custom behavior (Gossipsub + mDNS) for the P2P node
+        .with_behaviour(|key, relay| {
+            // Messages are only forwarded once the chat node has validated them. Unsigned
+            // messages are let through so the chat node can mark or reject them itself.
+            let gossipsub_config = gossipsub::ConfigBuilder::default()
+                .validate_messages()
+                .validation_mode(gossipsub::ValidationMode::Permissive)
+                .max_transmit_size(MAX_TRANSMIT_SIZE)
+                .build()
+                .map_err(|e| format!("invalid Gossipsub config: {e}"))?;
+
+            // Create a Gossipsub behavior with message signing using the local node's identity key.
+            let gossipsub = gossipsub::Behaviour::new_with_transform(
+                gossipsub::MessageAuthenticity::Signed(key.clone()), // Ensure authenticity
+                gossipsub_config,                                    // Gossipsub configuration
+                None,
+                SignedSourceOnly,
+            )
+            .map_err(|e| format!("cannot create the Gossipsub behaviour: {e}"))?;
+
+            // Create an mDNS behavior for local peer discovery, unless it was disabled
+            let mdns = if cli.no_mdns {
+                None
+            } else {
+                Some(Mdns::new(
+                    mdns::Config::default(),   // Default mDNS configuration
+                    key.public().to_peer_id(), // Peer ID is derived from the node's public key
+                )?)
+            };
+
+            // Return the combined behavior for use in the swarm.
+            Ok(MyBehaviour {
+                gossipsub,
+                mdns: mdns.into(),
+                blocked: Default::default(),
+                allowed: allowed.into(),
+                relay,
+                limits: connection_limits::Behaviour::new(
+                    ConnectionLimits::default().with_max_established(Some(cli.max_peers)),
+                ),
+                ping: ping::Behaviour::new(ping::Config::new()),
+                // The agent version lists the message formats we accept
+                identify: identify::Behaviour::new(
+                    identify::Config::new(protocol_version(&cli.topic_prefix), key.public())
+                        .with_agent_version(content_type::agent_version()),
+                ),
+                dcutr: dcutr::Behaviour::new(key.public().to_peer_id()),
+                snapshot: snapshot::behaviour(),
+                auth: auth::behaviour(),
+                content: attachment::behaviour(),
+            })
+        })
+        .map_err(|e| ChatError::Behaviour(e.into()))?
+        // Set the swarm configuration with an idle connection timeout of 60 seconds
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        // Build and return the fully configured swarm object
+        .build();
+
+    Ok(swarm)
+}
@@ -0,0 +1,611 @@
+// A bridge to a Nostr relay: chat messages are also published as NIP-01 text notes, and notes
+// tagged with the room's hashtag are shown like chat.
+//
+// Nostr signs events with BIP-340 Schnorr signatures over secp256k1, which libp2p's Ed25519
+// identity keys can't make, so the Nostr key is derived from the identity key: the same
+// identity always has the same Nostr public key.
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures_rustls::client::TlsStream;
+use k256::schnorr::{Signature, SigningKey, VerifyingKey};
+use libp2p::{futures::future::Either, identity::Keypair};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use soketto::{
+    connection::{Receiver, Sender},
+    handshake::{Client, ServerResponse},
+};
+use tokio::sync::mpsc;
+use tracing::debug;
+use url::{Host, Url};
+
+use crate::{clock, runtime, tls};
+
+/// Kind of NIP-01 text notes.
+pub const TEXT_NOTE: u32 = 1;
+
+/// Notes waiting for the relay. Once that many are, more are refused until some go out.
+pub const QUEUE: usize = 256;
+
+/// First wait before connecting again after losing the relay, doubled after each failure.
+pub const RECONNECT_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait before connecting again.
+pub const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// How long connecting, including the TLS and websocket handshakes, may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval of the pings that keep an idle connection from being closed.
+pub const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Largest message taken from the relay.
+pub const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+// Ids of the notes seen lately, so one delivered again after reconnecting isn't shown twice
+const SEEN_IDS: usize = 1024;
+
+// The one subscription the bridge makes on the relay
+const SUBSCRIPTION: &str = "p2p-chat";
+
+/// A Nostr key pair.
+#[derive(Clone)]
+pub struct NostrKeys {
+    signing: SigningKey,
+}
+
+impl NostrKeys {
+    /// The Nostr keys of an identity, derived from its secret key.
+    pub fn derive(keypair: &Keypair) -> Self {
+        let secret = keypair
+            .to_protobuf_encoding()
+            .expect("identity keys encode");
+        // A hash that isn't a valid scalar is all but impossible, but hash again if it isn't
+        let mut counter = 0u8;
+        loop {
+            let bytes = Sha256::new()
+                .chain_update(b"p2p-chat nostr key")
+                .chain_update(&secret)
+                .chain_update([counter])
+                .finalize();
+            if let Ok(signing) = SigningKey::from_bytes(&bytes) {
+                return NostrKeys { signing };
+            }
+            counter += 1;
+        }
+    }
+
+    /// The public key as Nostr writes it: the hex of its x coordinate.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.signing.verifying_key().to_bytes())
+    }
+}
+
+impl std::fmt::Debug for NostrKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NostrKeys")
+            .field("public_key", &self.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The hashtag that marks the notes of the room on `topic`.
+pub fn hashtag_for(topic: &str) -> String {
+    topic.to_lowercase()
+}
+
+/// A NIP-01 event.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+impl Event {
+    /// A text note saying `content`, tagged with `hashtag` and signed with `keys`.
+    pub fn text_note(keys: &NostrKeys, content: &str, hashtag: &str, created_at: u64) -> Self {
+        let mut event = Event {
+            id: String::new(),
+            pubkey: keys.public_key(),
+            created_at,
+            kind: TEXT_NOTE,
+            tags: vec![vec!["t".to_string(), hashtag.to_string()]],
+            content: content.to_string(),
+            sig: String::new(),
+        };
+        let id = event.compute_id();
+        let signature = keys
+            .signing
+            .sign_prehash_with_aux_rand(&id, &rand::random())
+            .expect("a hash signs");
+        event.id = hex::encode(id);
+        event.sig = hex::encode(signature.to_bytes());
+        event
+    }
+
+    /// The id of the event: the SHA-256 of its fields serialized the way NIP-01 lays down.
+    pub fn compute_id(&self) -> [u8; 32] {
+        let serialized = json!([
+            0,
+            self.pubkey,
+            self.created_at,
+            self.kind,
+            self.tags,
+            self.content
+        ]);
+        Sha256::digest(serialized.to_string()).into()
+    }
+
+    /// Check that the id matches the fields and that the author's key signed it.
+    pub fn verify(&self) -> Result<(), String> {
+        let id = self.compute_id();
+        if hex::encode(id) != self.id {
+            return Err("its id doesn't match its content".to_string());
+        }
+        let key = hex::decode(&self.pubkey)
+            .ok()
+            .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+            .ok_or("its public key is invalid")?;
+        let signature = hex::decode(&self.sig)
+            .ok()
+            .and_then(|bytes| Signature::try_from(bytes.as_slice()).ok())
+            .ok_or("its signature is malformed")?;
+        key.verify_raw(&id, &signature)
+            .map_err(|_| "its signature is invalid".to_string())
+    }
+
+    /// Whether the event is tagged with `hashtag`.
+    pub fn has_hashtag(&self, hashtag: &str) -> bool {
+        self.tags
+            .iter()
+            .any(|tag| tag.len() >= 2 && tag[0] == "t" && tag[1].eq_ignore_ascii_case(hashtag))
+    }
+}
+
+/// A NIP-01 subscription filter.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    pub kinds: Vec<u32>,
+    #[serde(rename = "#t")]
+    pub hashtags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+}
+
+/// The message publishing `event`.
+pub fn event_message(event: &Event) -> String {
+    json!(["EVENT", event]).to_string()
+}
+
+/// The message subscribing to the events `filter` matches as `subscription`.
+pub fn req_message(subscription: &str, filter: &Filter) -> String {
+    json!(["REQ", subscription, filter]).to_string()
+}
+
+/// A message from a relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayMessage {
+    /// An event matching the subscription.
+    Event { subscription: String, event: Event },
+    /// Whether the relay took an event we published.
+    Ok {
+        id: String,
+        accepted: bool,
+        message: String,
+    },
+    /// The stored events matching the subscription were all sent; new ones follow.
+    EndOfStored(String),
+    /// The relay ended the subscription.
+    Closed {
+        subscription: String,
+        message: String,
+    },
+    /// A human readable message.
+    Notice(String),
+}
+
+impl RelayMessage {
+    /// Parse a message from a relay.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let fields: Vec<Value> = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let string = |n: usize| {
+            fields
+                .get(n)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| format!("field {n} isn't a string"))
+        };
+        match string(0)?.as_str() {
+            "EVENT" => {
+                let event = fields.get(2).cloned().ok_or("no event")?;
+                Ok(RelayMessage::Event {
+                    subscription: string(1)?,
+                    event: serde_json::from_value(event).map_err(|e| e.to_string())?,
+                })
+            }
+            "OK" => Ok(RelayMessage::Ok {
+                id: string(1)?,
+                accepted: fields
+                    .get(2)
+                    .and_then(Value::as_bool)
+                    .ok_or("field 2 isn't a boolean")?,
+                message: string(3).unwrap_or_default(),
+            }),
+            "EOSE" => Ok(RelayMessage::EndOfStored(string(1)?)),
+            "CLOSED" => Ok(RelayMessage::Closed {
+                subscription: string(1)?,
+                message: string(2).unwrap_or_default(),
+            }),
+            "NOTICE" => Ok(RelayMessage::Notice(string(1)?)),
+            other => Err(format!("unknown message {other}")),
+        }
+    }
+}
+
+/// Parse a relay URL, which has to be a `ws://` or `wss://` URL with a host.
+pub fn relay_url(s: &str) -> Result<Url, String> {
+    let url = Url::parse(s).map_err(|e| e.to_string())?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        return Err("the relay URL must start with ws:// or wss://".to_string());
+    }
+    if url.host().is_none() {
+        return Err("the relay URL has no host".to_string());
+    }
+    Ok(url)
+}
+
+/// What happens on the connection to the relay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayEvent {
+    /// Connected, or connected again, and subscribed to the room's hashtag.
+    Connected,
+    /// The connection was lost or couldn't be made. Connecting is tried again with backoff,
+    /// and this isn't repeated until a connection was made.
+    Disconnected(String),
+    /// A note someone else published with the room's hashtag. Its signature was checked.
+    Note(Event),
+    /// The relay refused a note we published, or it was lost with the connection.
+    Rejected { id: String, reason: String },
+    /// A message from the relay's operator.
+    Notice(String),
+}
+
+/// The connection to a Nostr relay, kept on a task of its own that reconnects whenever it is
+/// lost. Dropping it closes the connection.
+#[derive(Debug)]
+pub struct Relay {
+    url: Url,
+    keys: NostrKeys,
+    hashtag: String,
+    outgoing: mpsc::Sender<Event>,
+    // Notes published and not yet written to the relay or lost
+    pending: Arc<AtomicUsize>,
+    incoming: mpsc::Receiver<RelayEvent>,
+    task: runtime::Task<()>,
+}
+
+impl Relay {
+    /// Connect to the relay at `url` and follow the notes tagged with `hashtag`, publishing
+    /// with `keys`.
+    pub fn spawn(url: Url, keys: NostrKeys, hashtag: String) -> Self {
+        let (outgoing, queue) = mpsc::channel(QUEUE);
+        let (events, incoming) = mpsc::channel(QUEUE);
+        let pending = Arc::new(AtomicUsize::new(0));
+        let task = runtime::spawn(run(
+            url.clone(),
+            hashtag.clone(),
+            keys.public_key(),
+            (queue, pending.clone()),
+            events,
+        ));
+        Relay {
+            url,
+            keys,
+            hashtag,
+            outgoing,
+            pending,
+            incoming,
+            task,
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn hashtag(&self) -> &str {
+        &self.hashtag
+    }
+
+    /// Our public key on Nostr.
+    pub fn public_key(&self) -> String {
+        self.keys.public_key()
+    }
+
+    /// Sign a note saying `content` and queue it for the relay, which gets it as soon as it
+    /// is connected. Fails while [`QUEUE`] notes are waiting.
+    pub fn publish(&self, content: &str, created_at: u64) -> Result<Event, String> {
+        let event = Event::text_note(&self.keys, content, &self.hashtag, created_at);
+        self.outgoing
+            .try_send(event.clone())
+            .map_err(|_| format!("{QUEUE} notes are already waiting for the relay"))?;
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        Ok(event)
+    }
+
+    /// Notes published and not yet sent to the relay.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// The next thing that happened on the connection.
+    pub async fn next(&mut self) -> Option<RelayEvent> {
+        self.incoming.recv().await
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// The ids of the notes seen lately.
+#[derive(Default)]
+struct Seen {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl Seen {
+    // Whether `id` is new, remembering it if it is.
+    fn insert(&mut self, id: &str) -> bool {
+        if !self.ids.insert(id.to_string()) {
+            return false;
+        }
+        self.order.push_back(id.to_string());
+        if self.order.len() > SEEN_IDS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+type Socket = Either<TlsStream<runtime::TcpStream>, runtime::TcpStream>;
+
+// The notes to send, and how many of them haven't been yet.
+type Queue = (mpsc::Receiver<Event>, Arc<AtomicUsize>);
+
+// Keep connected to the relay, resubscribing after every reconnection from the newest note
+// seen, until the node goes away.
+async fn run(
+    url: Url,
+    hashtag: String,
+    own: String,
+    mut queue: Queue,
+    events: mpsc::Sender<RelayEvent>,
+) {
+    let mut since = clock::unix_time();
+    let mut seen = Seen::default();
+    let mut backoff = RECONNECT_MIN;
+    let mut reported = false;
+    loop {
+        let connected = runtime::timeout(CONNECT_TIMEOUT, connect(&url))
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|connected| connected);
+        let error = match connected {
+            Ok((sender, receiver)) => {
+                backoff = RECONNECT_MIN;
+                reported = false;
+                if events.send(RelayEvent::Connected).await.is_err() {
+                    return;
+                }
+                let session = Session {
+                    hashtag: &hashtag,
+                    own: &own,
+                    since: &mut since,
+                    seen: &mut seen,
+                    events: &events,
+                };
+                session.run(sender, receiver, &mut queue).await
+            }
+            Err(e) => e,
+        };
+        if events.is_closed() {
+            return;
+        }
+        debug!("[nostr] {url}: {error}, connecting again in {backoff:?}");
+        if !reported {
+            reported = true;
+            let _ = events.send(RelayEvent::Disconnected(error)).await;
+        }
+        runtime::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+// One connection to the relay.
+struct Session<'a> {
+    hashtag: &'a str,
+    own: &'a str,
+    since: &'a mut u64,
+    seen: &'a mut Seen,
+    events: &'a mpsc::Sender<RelayEvent>,
+}
+
+impl Session<'_> {
+    // Subscribe, then pass notes from the relay on to the node and queued notes to the relay,
+    // until the connection fails. Returns why it did.
+    async fn run(
+        self,
+        mut sender: Sender<Socket>,
+        mut receiver: Receiver<Socket>,
+        (queue, pending): &mut Queue,
+    ) -> String {
+        let filter = Filter {
+            kinds: vec![TEXT_NOTE],
+            hashtags: vec![self.hashtag.to_string()],
+            since: Some(*self.since),
+        };
+        if let Err(e) = send(&mut sender, &req_message(SUBSCRIPTION, &filter)).await {
+            return e;
+        }
+        let events = self.events;
+        // Both run until the connection fails, so neither is dropped halfway through a frame
+        let reading = self.read(&mut receiver);
+        let writing = async {
+            const EMPTY: &[u8] = &[];
+            let mut ping = runtime::interval(PING_INTERVAL);
+            loop {
+                tokio::select! {
+                    event = queue.recv() => {
+                        let Some(event) = event else {
+                            return "the node went away".to_string();
+                        };
+                        let sent = send(&mut sender, &event_message(&event)).await;
+                        pending.fetch_sub(1, Ordering::Relaxed);
+                        if let Err(e) = sent {
+                            let lost = RelayEvent::Rejected {
+                                id: event.id,
+                                reason: "the connection was lost".to_string(),
+                            };
+                            let _ = events.try_send(lost);
+                            return e;
+                        }
+                    }
+                    _ = ping.tick() => {
+                        let ping = sender.send_ping(EMPTY.try_into().expect("fits")).await;
+                        if let Err(e) = ping.and(sender.flush().await) {
+                            return e.to_string();
+                        }
+                    }
+                }
+            }
+        };
+        tokio::select! {
+            e = reading => e,
+            e = writing => e,
+        }
+    }
+
+    async fn read(mut self, receiver: &mut Receiver<Socket>) -> String {
+        let mut data = Vec::new();
+        loop {
+            data.clear();
+            if let Err(e) = receiver.receive_data(&mut data).await {
+                return e.to_string();
+            }
+            let message = match std::str::from_utf8(&data).map_err(|e| e.to_string()) {
+                Ok(text) => RelayMessage::parse(text),
+                Err(e) => Err(e),
+            };
+            let event = match message {
+                Ok(RelayMessage::Event {
+                    subscription,
+                    event,
+                }) if subscription == SUBSCRIPTION => self.take_note(event),
+                Ok(RelayMessage::Ok {
+                    id,
+                    accepted: false,
+                    message,
+                }) => Some(RelayEvent::Rejected {
+                    id,
+                    reason: message,
+                }),
+                Ok(RelayMessage::Closed { message, .. }) => {
+                    return format!("the relay ended the subscription: {message}");
+                }
+                Ok(RelayMessage::Notice(notice)) => Some(RelayEvent::Notice(notice)),
+                Ok(_) => None,
+                Err(e) => {
+                    debug!("[nostr] ignored a message from the relay: {e}");
+                    None
+                }
+            };
+            if let Some(event) = event {
+                if self.events.send(event).await.is_err() {
+                    return "the node went away".to_string();
+                }
+            }
+        }
+    }
+
+    // A note from the subscription, unless it is ours, seen already, not for the room or not
+    // signed by its author.
+    fn take_note(&mut self, event: Event) -> Option<RelayEvent> {
+        if event.pubkey == self.own || !self.seen.insert(&event.id) {
+            return None;
+        }
+        if event.kind != TEXT_NOTE || !event.has_hashtag(self.hashtag) {
+            return None;
+        }
+        if let Err(e) = event.verify() {
+            debug!("[nostr] ignored note {}: {e}", event.id);
+            return None;
+        }
+        // A note stamped in the future mustn't hide the ones after it on reconnecting
+        *self.since = (*self.since).max(event.created_at.min(clock::unix_time()));
+        Some(RelayEvent::Note(event))
+    }
+}
+
+async fn send(sender: &mut Sender<Socket>, text: &str) -> Result<(), String> {
+    sender.send_text(text).await.map_err(|e| e.to_string())?;
+    sender.flush().await.map_err(|e| e.to_string())
+}
+
+// Open a websocket to the relay, over TLS for wss:// URLs.
+async fn connect(url: &Url) -> Result<(Sender<Socket>, Receiver<Socket>), String> {
+    let host = match url.host() {
+        Some(Host::Domain(domain)) => domain.to_string(),
+        Some(Host::Ipv4(ip)) => ip.to_string(),
+        Some(Host::Ipv6(ip)) => ip.to_string(),
+        None => return Err("the relay URL has no host".to_string()),
+    };
+    let port = url.port_or_known_default().unwrap_or(443);
+    let tcp = runtime::connect_tcp(&host, port)
+        .await
+        .map_err(|e| e.to_string())?;
+    let socket = if url.scheme() == "wss" {
+        Either::Left(tls::connect(&host, tcp).await?)
+    } else {
+        Either::Right(tcp)
+    };
+    let resource = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    let authority = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let mut client = Client::new(socket, &authority, &resource);
+    match client.handshake().await.map_err(|e| e.to_string())? {
+        ServerResponse::Accepted { .. } => {}
+        ServerResponse::Redirect { location, .. } => {
+            return Err(format!("the relay redirects to {location}"));
+        }
+        ServerResponse::Rejected { status_code } => {
+            return Err(format!("the relay refused the connection ({status_code})"));
+        }
+    }
+    let mut builder = client.into_builder();
+    builder.set_max_message_size(MAX_MESSAGE_BYTES);
+    Ok(builder.finish())
+}
@@ -0,0 +1,256 @@
+// Desktop notifications of mentions and of messages from favorite contacts, for when the
+// terminal is out of sight. Each platform's own notifier shows them: `notify-send` on Linux and
+// the BSDs, `osascript` on macOS and a PowerShell toast on Windows. A burst of messages gets one
+// notification summing it up.
+use std::{
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::debug;
+
+use crate::{presence, sanitize};
+
+/// After a notification, how long further ones are held back to be summed up in one.
+pub const BURST_WINDOW: Duration = Duration::from_secs(10);
+
+/// Longest message body shown in a notification, in characters.
+pub const MAX_BODY_CHARS: usize = 120;
+
+/// Senders named in the summary of a burst; any more are "others".
+pub const MAX_SUMMARY_SENDERS: usize = 3;
+
+/// The name notifications are shown under.
+pub const APP_NAME: &str = "p2p-chat";
+
+/// Why a message warrants a notification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The message mentions our nick.
+    Mention,
+    /// The message comes from a favorite contact.
+    Favorite,
+}
+
+/// What a notification says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+}
+
+impl Notification {
+    /// A notification of one message from `sender`, its body cut to [`MAX_BODY_CHARS`].
+    pub fn of(sender: &str, body: &str, reason: Reason) -> Self {
+        let title = match reason {
+            Reason::Mention => format!("{sender} mentioned you"),
+            Reason::Favorite => sender.to_string(),
+        };
+        Notification {
+            title,
+            body: presence::shorten(&sanitize::line(body), MAX_BODY_CHARS),
+        }
+    }
+
+    /// A notification summing up `messages` held back from `senders`, who are named in the
+    /// order they first wrote, up to [`MAX_SUMMARY_SENDERS`] of them.
+    pub fn summary(messages: usize, senders: &[String]) -> Self {
+        let title = match messages {
+            1 => "1 more message".to_string(),
+            n => format!("{n} more messages"),
+        };
+        let named = &senders[..senders.len().min(MAX_SUMMARY_SENDERS)];
+        let body = match (named, senders.len() > named.len()) {
+            ([only], false) => format!("from {only}"),
+            ([first @ .., last], false) => format!("from {} and {last}", first.join(", ")),
+            (named, true) => format!("from {} and others", named.join(", ")),
+            ([], false) => String::new(),
+        };
+        Notification { title, body }
+    }
+}
+
+/// Where notifications are shown: the desktop, or a list in tests.
+pub trait Backend: Send {
+    fn show(&mut self, notification: Notification);
+}
+
+/// The desktop's notifications, each shown by the platform's notifier from a thread of its own
+/// so a slow notifier doesn't hold up the node. Nothing is shown while the terminal's window
+/// has the focus, where that can be told.
+#[derive(Debug, Default)]
+pub struct Desktop;
+
+impl Backend for Desktop {
+    fn show(&mut self, notification: Notification) {
+        thread::spawn(move || {
+            if terminal_focused() {
+                return;
+            }
+            let shown = command(&notification)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+            match shown {
+                Ok(status) if !status.success() => debug!("[notify] the notifier failed: {status}"),
+                Err(e) => debug!("[notify] can't run the notifier: {e}"),
+                Ok(_) => {}
+            }
+        });
+    }
+}
+
+/// The command showing `notification` on this platform.
+#[cfg(target_os = "macos")]
+pub fn command(notification: &Notification) -> Command {
+    let mut command = Command::new("osascript");
+    command.args(["-e", &applescript(notification)]);
+    command
+}
+
+/// The command showing `notification` on this platform.
+#[cfg(windows)]
+pub fn command(notification: &Notification) -> Command {
+    let mut command = Command::new("powershell");
+    command.args([
+        "-NoProfile",
+        "-NonInteractive",
+        "-Command",
+        &powershell(notification),
+    ]);
+    command
+}
+
+/// The command showing `notification` on this platform.
+#[cfg(not(any(target_os = "macos", windows)))]
+pub fn command(notification: &Notification) -> Command {
+    let mut command = Command::new("notify-send");
+    command
+        .arg(format!("--app-name={APP_NAME}"))
+        .arg("--")
+        .args([&notification.title, &notification.body]);
+    command
+}
+
+/// The AppleScript showing `notification` through macOS's Notification Center.
+pub fn applescript(notification: &Notification) -> String {
+    let quote = |text: &str| format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""));
+    format!(
+        "display notification {} with title {}",
+        quote(&notification.body),
+        quote(&notification.title)
+    )
+}
+
+/// The PowerShell script showing `notification` as a Windows toast. The text is set on the
+/// toast's XML as text nodes, so it needs no XML escaping.
+pub fn powershell(notification: &Notification) -> String {
+    let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+    format!(
+        "$manager = [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, \
+         ContentType = WindowsRuntime]\n\
+         $xml = $manager::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)\n\
+         $text = $xml.GetElementsByTagName('text')\n\
+         $text.Item(0).AppendChild($xml.CreateTextNode({})) > $null\n\
+         $text.Item(1).AppendChild($xml.CreateTextNode({})) > $null\n\
+         $manager::CreateToastNotifier({}).Show([Windows.UI.Notifications.ToastNotification]::new($xml))",
+        quote(&notification.title),
+        quote(&notification.body),
+        quote(APP_NAME)
+    )
+}
+
+/// Whether the terminal's window has the focus. Only X11 terminals say which window is theirs,
+/// in `WINDOWID`, so elsewhere the terminal is taken to be out of sight.
+pub fn terminal_focused() -> bool {
+    let Some(ours) = std::env::var("WINDOWID")
+        .ok()
+        .and_then(|id| id.trim().parse::<u64>().ok())
+    else {
+        return false;
+    };
+    Command::new("xprop")
+        .args(["-root", "_NET_ACTIVE_WINDOW"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .and_then(|output| active_window(&String::from_utf8_lossy(&output.stdout)))
+        == Some(ours)
+}
+
+/// The window `xprop -root _NET_ACTIVE_WINDOW` says has the focus, as in
+/// `_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007`.
+pub fn active_window(xprop: &str) -> Option<u64> {
+    let (_, id) = xprop.trim().rsplit_once("# ")?;
+    u64::from_str_radix(id.strip_prefix("0x")?, 16).ok()
+}
+
+/// Notifications as messages come, a burst of them summed up: the first is shown at once, and
+/// the ones coming within [`BURST_WINDOW`] of it are held back and shown as one once it is over.
+pub struct Notifier {
+    backend: Box<dyn Backend>,
+    window: Duration,
+    // Until when notifications are held back
+    quiet_until: Option<Instant>,
+    held: usize,
+    // Senders of the held messages, in the order they first wrote
+    senders: Vec<String>,
+}
+
+impl Notifier {
+    /// Notifications shown by `backend`, with bursts summed up after `window`.
+    pub fn new(backend: Box<dyn Backend>, window: Duration) -> Self {
+        Notifier {
+            backend,
+            window,
+            quiet_until: None,
+            held: 0,
+            senders: Vec::new(),
+        }
+    }
+
+    /// Notifications on the desktop.
+    pub fn desktop() -> Self {
+        Notifier::new(Box::new(Desktop), BURST_WINDOW)
+    }
+
+    /// Notify of a message from `sender` at `now`, unless it is part of a burst.
+    pub fn notify(&mut self, sender: &str, body: &str, reason: Reason, now: Instant) {
+        if self.quiet_until.is_some_and(|until| now < until) {
+            self.held += 1;
+            // Only the first few are named, but one more tells there are others
+            if self.senders.len() <= MAX_SUMMARY_SENDERS
+                && !self.senders.iter().any(|s| s == sender)
+            {
+                self.senders.push(sender.to_string());
+            }
+            return;
+        }
+        self.backend.show(Notification::of(sender, body, reason));
+        self.quiet_until = Some(now + self.window);
+    }
+
+    /// Sum up the messages held back once the burst is over at `now`. The summary starts a
+    /// window of its own.
+    pub fn flush(&mut self, now: Instant) {
+        if self.quiet_until.is_none_or(|until| now < until) {
+            return;
+        }
+        if self.held == 0 {
+            self.quiet_until = None;
+            return;
+        }
+        let summary = Notification::summary(self.held, &self.senders);
+        self.backend.show(summary);
+        self.clear();
+        self.quiet_until = Some(now + self.window);
+    }
+
+    /// Forget the messages held back, as when do not disturb starts.
+    pub fn clear(&mut self) {
+        self.held = 0;
+        self.senders.clear();
+    }
+}
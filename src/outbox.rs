@@ -0,0 +1,143 @@
+// Messages waiting for a peer to publish them to, retried with exponential backoff.
+use std::time::{Duration, Instant};
+
+use libp2p::gossipsub::{MessageId, TopicHash};
+use tokio::sync::oneshot;
+
+use crate::{error::ChatError, message::ChatMessage};
+
+/// How long a message waits for a peer before it is given up on.
+pub const PUBLISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wait before the first retry; each further one waits twice as long, up to [`MAX_RETRY`].
+pub const FIRST_RETRY: Duration = Duration::from_millis(100);
+
+/// Longest wait between two retries.
+pub const MAX_RETRY: Duration = Duration::from_secs(5);
+
+/// Wait after the `attempt`th failed try, counting from 0.
+pub fn backoff(attempt: u32) -> Duration {
+    FIRST_RETRY
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_RETRY)
+}
+
+/// Where the outcome of a queued message goes.
+pub type Reply = oneshot::Sender<Result<MessageId, ChatError>>;
+
+/// A message waiting for a peer.
+#[derive(Debug)]
+pub struct Queued {
+    pub topic: TopicHash,
+    pub data: Vec<u8>,
+    /// A line typed while the room was empty, encoded only when it is sent, for the peers in
+    /// the room by then. Its `data` is empty.
+    pub message: Option<ChatMessage>,
+    // The caller awaiting the outcome, or none for chat lines, whose errors are printed
+    reply: Option<Reply>,
+    attempts: u32,
+    retry_at: Instant,
+    deadline: Instant,
+}
+
+impl Queued {
+    /// Whether the caller stopped waiting for the outcome, which cancels the message.
+    pub fn is_cancelled(&self) -> bool {
+        self.reply.as_ref().is_some_and(Reply::is_closed)
+    }
+
+    /// Whether this is a line typed while the room was empty.
+    pub fn is_held(&self) -> bool {
+        self.message.is_some()
+    }
+
+    /// Hand `outcome` to whoever awaits it. Returns the error when nobody does, to print.
+    pub fn resolve(self, outcome: Result<MessageId, ChatError>) -> Option<ChatError> {
+        match self.reply {
+            Some(reply) => {
+                let _ = reply.send(outcome);
+                None
+            }
+            None => outcome.err(),
+        }
+    }
+}
+
+/// The messages waiting for a peer, in the order they were published.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    queue: Vec<Queued>,
+}
+
+impl Outbox {
+    /// Queue `data` for `topic`, due for a try straight away.
+    pub fn push(&mut self, topic: TopicHash, data: Vec<u8>, reply: Option<Reply>, now: Instant) {
+        self.enqueue(topic, data, None, reply, now);
+    }
+
+    /// Queue a chat line for `topic`, to be encoded once it is sent. Nobody awaits the outcome.
+    pub fn hold(&mut self, topic: TopicHash, message: ChatMessage, now: Instant) {
+        self.enqueue(topic, Vec::new(), Some(message), None, now);
+    }
+
+    fn enqueue(
+        &mut self,
+        topic: TopicHash,
+        data: Vec<u8>,
+        message: Option<ChatMessage>,
+        reply: Option<Reply>,
+        now: Instant,
+    ) {
+        self.queue.push(Queued {
+            topic,
+            data,
+            message,
+            reply,
+            attempts: 0,
+            retry_at: now,
+            deadline: now + PUBLISH_TIMEOUT,
+        });
+    }
+
+    /// Take the messages due for another try at `now`, and those for `subscribed`, a topic a
+    /// peer just subscribed to, whenever they are due. Cancelled messages are dropped.
+    pub fn take_due(&mut self, now: Instant, subscribed: Option<&TopicHash>) -> Vec<Queued> {
+        self.queue.retain(|queued| !queued.is_cancelled());
+        let (due, waiting) = self
+            .queue
+            .drain(..)
+            .partition(|queued| queued.retry_at <= now || Some(&queued.topic) == subscribed);
+        self.queue = waiting;
+        due
+    }
+
+    /// Put back a message that found no peer again, to wait longer before the next try.
+    /// Returns it instead once its time is up.
+    pub fn retry_later(&mut self, mut queued: Queued, now: Instant) -> Option<Queued> {
+        if now >= queued.deadline {
+            return Some(queued);
+        }
+        queued.retry_at = (now + backoff(queued.attempts)).min(queued.deadline);
+        queued.attempts += 1;
+        self.queue.push(queued);
+        None
+    }
+
+    /// When the next message is due for a try.
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.queue.iter().map(|queued| queued.retry_at).min()
+    }
+
+    /// Lines typed while the room was empty that are still waiting.
+    pub fn held(&self) -> usize {
+        self.queue.iter().filter(|queued| queued.is_held()).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
@@ -0,0 +1,263 @@
+// Terminal output written on a thread of its own, so a terminal that stops reading, for example
+// one paused with Ctrl-S, can't stall the event loop.
+use std::{
+    collections::VecDeque,
+    io::{self, IsTerminal, Write},
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Condvar, Mutex, Once, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Lines waiting for the terminal before the oldest are dropped to make room. Written to a pipe
+/// or a file, the node waits for room instead.
+pub const OUTPUT_BUFFER: usize = 4096;
+
+/// How long the printer collects output before writing it in one go.
+pub const FRAME: Duration = Duration::from_millis(20);
+
+/// Lines that waited longer than this for a terminal are skipped, so a storm of messages
+/// doesn't leave the screen minutes behind.
+pub const MAX_LAG: Duration = Duration::from_secs(2);
+
+/// Something for the printer to do.
+#[derive(Debug)]
+pub enum Output {
+    Stdout(String),
+    Stderr(String),
+    Flushed(mpsc::Sender<()>),
+}
+
+impl Output {
+    fn is_text(&self) -> bool {
+        !matches!(self, Output::Flushed(_))
+    }
+}
+
+/// Output waiting for the printer, and how it is laid out for a write.
+#[derive(Debug)]
+pub struct Backlog {
+    queue: VecDeque<(Instant, Output)>,
+    // Lines dropped or skipped since the marker last said so
+    skipped: u64,
+    lossless: bool,
+}
+
+impl Backlog {
+    /// A backlog for a terminal, or a `lossless` one for a pipe or a file, which never drops,
+    /// skips or merges lines.
+    pub fn new(lossless: bool) -> Self {
+        Backlog {
+            queue: VecDeque::new(),
+            skipped: 0,
+            lossless,
+        }
+    }
+
+    /// Queue `output` at `now`. A full backlog drops its oldest line to make room, unless it is
+    /// lossless: then `output` is handed back, to queue again once the printer catches up.
+    pub fn push(&mut self, output: Output, now: Instant) -> Result<(), Output> {
+        if self.queue.len() >= OUTPUT_BUFFER {
+            if self.lossless {
+                return Err(output);
+            }
+            if let Some(oldest) = self.queue.iter().position(|(_, output)| output.is_text()) {
+                self.queue.remove(oldest);
+                self.skipped += 1;
+            }
+        }
+        self.queue.push_back((now, output));
+        Ok(())
+    }
+
+    /// Take everything waiting at `now`, laid out for one write. For a terminal, runs of the
+    /// same line become one with a "×N" suffix, and lines that waited over [`MAX_LAG`] are
+    /// skipped, as are any dropped for room, with a marker saying how many.
+    pub fn take(&mut self, now: Instant) -> Vec<Output> {
+        let mut runs: Vec<(Output, usize)> = Vec::new();
+        for (queued, output) in mem::take(&mut self.queue) {
+            if !self.lossless && output.is_text() && now.duration_since(queued) > MAX_LAG {
+                self.skipped += 1;
+                continue;
+            }
+            match (runs.last_mut(), &output) {
+                (Some((Output::Stdout(last), count)), Output::Stdout(line))
+                    if !self.lossless && last == line =>
+                {
+                    *count += 1
+                }
+                _ => runs.push((output, 1)),
+            }
+        }
+
+        let mut batch = Vec::new();
+        if self.skipped > 0 {
+            batch.push(Output::Stdout(format!(
+                "… {} messages not shown, see /history …\n",
+                mem::take(&mut self.skipped)
+            )));
+        }
+        for (output, count) in runs {
+            let output = match output {
+                Output::Stdout(line) if count > 1 => Output::Stdout(format!("{line} ×{count}\n")),
+                Output::Stdout(line) => Output::Stdout(line + "\n"),
+                output => output,
+            };
+            // Consecutive text for the same stream goes out in one write
+            match (batch.last_mut(), output) {
+                (Some(Output::Stdout(text)), Output::Stdout(more))
+                | (Some(Output::Stderr(text)), Output::Stderr(more)) => text.push_str(&more),
+                (_, output) => batch.push(output),
+            }
+        }
+        batch
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+struct Printer {
+    backlog: Mutex<Backlog>,
+    // Signaled when output is queued, and when the printer takes it
+    queued: Condvar,
+    taken: Condvar,
+}
+
+static PRINTER: OnceLock<Printer> = OnceLock::new();
+static STARTED: Once = Once::new();
+// Set once stdout is kept for data, when lines go to stderr instead
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Print a line to stdout like `println!`, without waiting for the terminal.
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        $crate::output::line(format!($($arg)*))
+    };
+}
+
+/// Queue a line for stdout. At a terminal, lines the printer can't keep up with are skipped
+/// with a marker pointing to `/history`; a pipe or a file gets every line. Once stdout is
+/// reserved, the line goes to stderr.
+pub fn line(text: String) {
+    if STDOUT_RESERVED.load(Ordering::Relaxed) {
+        return send(Output::Stderr(text + "\n"));
+    }
+    send(Output::Stdout(text));
+}
+
+/// Keep stdout for [`data`] from now on, as for a program reading it: [`line`] writes to stderr
+/// instead, and nothing is dropped, skipped or merged, even at a terminal.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+    let mut backlog = printer().backlog.lock().expect("the printer doesn't panic");
+    backlog.lossless = true;
+}
+
+/// Queue a line for stdout, reserved or not.
+pub fn data(text: String) {
+    send(Output::Stdout(text));
+}
+
+/// Wait until the lines queued so far are written, as before exiting.
+pub fn flush() {
+    let (done, written) = mpsc::channel();
+    send(Output::Flushed(done));
+    let _ = written.recv();
+}
+
+/// A writer for log output that queues it for stderr like [`line`] does for stdout, for
+/// `tracing_subscriber::fmt::Layer::with_writer`.
+pub fn stderr() -> LogWriter {
+    LogWriter
+}
+
+/// See [`stderr`].
+#[derive(Debug)]
+pub struct LogWriter;
+
+impl io::Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        send(Output::Stderr(String::from_utf8_lossy(buf).into_owned()));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn send(mut output: Output) {
+    let printer = printer();
+    let mut backlog = printer.backlog.lock().expect("the printer doesn't panic");
+    // Only a lossless backlog hands output back: a pipe that isn't read holds the node up
+    while let Err(back) = backlog.push(output, Instant::now()) {
+        output = back;
+        backlog = printer
+            .taken
+            .wait(backlog)
+            .expect("the printer doesn't panic");
+    }
+    printer.queued.notify_one();
+}
+
+// Started on first use. The print macros are used so that tests capture the output.
+fn printer() -> &'static Printer {
+    let printer = PRINTER.get_or_init(|| Printer {
+        backlog: Mutex::new(Backlog::new(!io::stdout().is_terminal())),
+        queued: Condvar::new(),
+        taken: Condvar::new(),
+    });
+    STARTED.call_once(|| {
+        thread::Builder::new()
+            .name("output".to_string())
+            .spawn(move || print_forever(printer))
+            .expect("the output thread starts");
+    });
+    printer
+}
+
+fn print_forever(printer: &Printer) {
+    let mut written = Instant::now();
+    loop {
+        let mut backlog = printer.backlog.lock().expect("the printer doesn't panic");
+        while backlog.is_empty() {
+            backlog = printer
+                .queued
+                .wait(backlog)
+                .expect("the printer doesn't panic");
+        }
+        // Let a burst collect, so it is written at once rather than line by line
+        if let Some(rest) = FRAME.checked_sub(written.elapsed()) {
+            drop(backlog);
+            thread::sleep(rest);
+            backlog = printer.backlog.lock().expect("the printer doesn't panic");
+        }
+        let batch = backlog.take(Instant::now());
+        drop(backlog);
+        printer.taken.notify_all();
+
+        for output in batch {
+            match output {
+                Output::Stdout(text) => {
+                    print!("{text}");
+                    let _ = io::stdout().flush();
+                }
+                Output::Stderr(text) => eprint!("{text}"),
+                Output::Flushed(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+        written = Instant::now();
+    }
+}
@@ -0,0 +1,153 @@
+// Private rooms whose topic and message key are both derived from a shared passphrase.
+use std::{error::Error, fmt, fs, path::Path};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+
+use crate::{error::ConfigError, identity};
+
+/// Salt for every derivation. It is public and the same for everyone, so all holders of a
+/// phrase arrive at the same room; the Argon2id cost is what makes guessing phrases expensive.
+pub const SALT: &[u8] = b"p2p-chat room passphrase v1";
+
+/// Argon2id memory cost in KiB (19 MiB).
+pub const MEMORY_KIB: u32 = 19 * 1024;
+
+/// Argon2id passes over the memory.
+pub const ITERATIONS: u32 = 2;
+
+/// Argon2id lanes.
+pub const PARALLELISM: u32 = 1;
+
+/// Length of the secret a phrase is derived into: the topic id, then the message key.
+pub const SECRET_LEN: usize = 64;
+
+// Sealed payloads: this prefix, then the nonce, then the ciphertext and its tag
+const MAGIC: &[u8] = b"p2p-chat-sealed1:";
+const NONCE_LEN: usize = 24;
+
+/// The topic and message key of a passphrase room.
+pub struct RoomKey {
+    topic: String,
+    cipher: XChaCha20Poly1305,
+    secret: [u8; SECRET_LEN],
+}
+
+/// Why a payload in a passphrase room couldn't be opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenError {
+    /// The payload is plaintext, not sealed for any room.
+    NotSealed,
+    /// The payload is sealed, but not with this room's key, or it was altered.
+    WrongKey,
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenError::NotSealed => write!(f, "message is not encrypted"),
+            OpenError::WrongKey => write!(f, "message was encrypted with a different key"),
+        }
+    }
+}
+
+impl Error for OpenError {}
+
+impl RoomKey {
+    /// Derive the room for `phrase`: 64 bytes of Argon2id output, the first half naming the
+    /// topic and the second half keying XChaCha20-Poly1305.
+    pub fn derive(phrase: &str) -> Self {
+        let params = Params::new(MEMORY_KIB, ITERATIONS, PARALLELISM, Some(64))
+            .expect("the Argon2 parameters are valid");
+        let mut output = [0u8; SECRET_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(phrase.as_bytes(), SALT, &mut output)
+            .expect("the salt and output lengths are valid");
+        RoomKey::from_secret(output)
+    }
+
+    /// The room of a secret a phrase was derived into, as rebuilt from shares with
+    /// `/recover-key`.
+    pub fn from_secret(secret: [u8; SECRET_LEN]) -> Self {
+        let (id, key) = secret.split_at(32);
+        RoomKey {
+            topic: format!("p2p-chat/room/{}", hex::encode(id)),
+            cipher: XChaCha20Poly1305::new(key.into()),
+            secret,
+        }
+    }
+
+    /// The secret the room was derived into, which `/share-key` splits into shares.
+    pub fn secret(&self) -> &[u8; SECRET_LEN] {
+        &self.secret
+    }
+
+    /// Read a room's secret saved in hex with [`RoomKey::save`].
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let invalid = |reason: String| ConfigError::RoomKey {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let secret = hex::decode(text.trim()).map_err(|e| invalid(e.to_string()))?;
+        let secret = secret
+            .try_into()
+            .map_err(|_| invalid(format!("expected {SECRET_LEN} bytes in hex")))?;
+        Ok(RoomKey::from_secret(secret))
+    }
+
+    /// Save the room's secret in hex at `path`, readable only by its owner.
+    pub fn save(&self, path: &Path) -> Result<(), ConfigError> {
+        let text = format!("{}\n", hex::encode(self.secret));
+        path.parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| identity::write_private(path, text.as_bytes()))
+            .map_err(|source| ConfigError::Write {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+
+    /// The Gossipsub topic of the room. It reveals nothing about the phrase.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Encrypt a payload for the room under a fresh random nonce.
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .expect("payloads are far below the cipher's length limit");
+        [MAGIC, nonce.as_slice(), &ciphertext].concat()
+    }
+
+    /// Decrypt a payload sealed with [`RoomKey::seal`].
+    pub fn open(&self, data: &[u8]) -> Result<Vec<u8>, OpenError> {
+        let sealed = data.strip_prefix(MAGIC).ok_or(OpenError::NotSealed)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(OpenError::WrongKey);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| OpenError::WrongKey)
+    }
+}
+
+// Never print the key.
+impl fmt::Debug for RoomKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RoomKey")
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
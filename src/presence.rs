@@ -0,0 +1,278 @@
+// Presence heartbeats and when each peer was last seen in each room.
+use std::{collections::HashMap, fmt};
+
+use libp2p::PeerId;
+use rand::Rng;
+
+use crate::{autoban::MAX_TRACKED_PEERS, sanitize};
+
+/// Heartbeat interval (seconds) assumed for peers that haven't announced theirs when our own
+/// heartbeats are off.
+pub const DEFAULT_INTERVAL: u64 = 30;
+
+/// Heartbeat intervals without news after which a peer counts as stale.
+pub const STALE_AFTER: u64 = 2;
+
+/// Heartbeat intervals without news after which a peer counts as offline.
+pub const OFFLINE_AFTER: u64 = 5;
+
+/// Longest status line, in characters; longer ones from peers are cut off.
+pub const MAX_STATUS_CHARS: usize = 80;
+
+/// Characters of a status line shown in `/peers`; `/whois` shows all of it.
+pub const ROSTER_STATUS_CHARS: usize = 32;
+
+/// How current a peer's presence is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PresenceStatus {
+    /// Heard from within the last [`STALE_AFTER`] intervals.
+    Online,
+    /// Missed a heartbeat or two; it may have crashed or lost its connection.
+    Stale,
+    /// Silent for [`OFFLINE_AFTER`] intervals, or it said it was leaving.
+    Offline,
+}
+
+impl fmt::Display for PresenceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresenceStatus::Online => write!(f, "online"),
+            PresenceStatus::Stale => write!(f, "stale"),
+            PresenceStatus::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Seen {
+    at: u64,
+    interval: u64,
+    away: bool,
+    dnd: bool,
+    status: Option<String>,
+    departed: bool,
+}
+
+/// Last-seen times per room and peer, from heartbeats and any other message.
+///
+/// Each peer is judged by the interval its own heartbeats announce; peers only heard from
+/// through chat messages are judged by the interval they were created with. At most
+/// [`MAX_TRACKED_PEERS`] peers are tracked unless set otherwise; once full, offline peers are
+/// forgotten first, then those heard from longest ago.
+#[derive(Debug)]
+pub struct Presence {
+    interval: u64,
+    seen: HashMap<(String, PeerId), Seen>,
+    limit: usize,
+    evictions: u64,
+}
+
+impl Presence {
+    /// Track peers, assuming a heartbeat every `interval` seconds from those that haven't
+    /// announced their own.
+    pub fn new(interval: u64) -> Self {
+        Presence {
+            interval,
+            seen: HashMap::new(),
+            limit: MAX_TRACKED_PEERS,
+            evictions: 0,
+        }
+    }
+
+    /// Track at most `limit` room and peer pairs (`--max-tracked-peers`).
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Room and peer pairs tracked.
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Peers forgotten to make room so far.
+    pub fn evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    /// Record that `peer` was heard from in `room`.
+    pub fn seen(&mut self, room: &str, peer: PeerId, now: u64) {
+        let (interval, away) = self
+            .seen
+            .get(&(room.to_string(), peer))
+            .map_or((self.interval, false), |seen| (seen.interval, seen.away));
+        self.record(room, peer, now, interval, away, false);
+    }
+
+    /// Record a heartbeat from `peer`, which sends one every `interval` seconds and says
+    /// whether its user is away.
+    pub fn heartbeat(&mut self, room: &str, peer: PeerId, interval: u64, away: bool, now: u64) {
+        self.record(room, peer, now, interval, away, false);
+    }
+
+    /// Record whether the user of `peer` doesn't want to be disturbed, as its latest heartbeat
+    /// in `room` said.
+    pub fn set_dnd(&mut self, room: &str, peer: &PeerId, dnd: bool) {
+        if let Some(seen) = self.seen.get_mut(&(room.to_string(), *peer)) {
+            seen.dnd = dnd;
+        }
+    }
+
+    /// Record the status line of `peer`, as its latest heartbeat in `room` said. Only the
+    /// latest one is kept.
+    pub fn set_status_line(&mut self, room: &str, peer: &PeerId, status: Option<String>) {
+        if let Some(seen) = self.seen.get_mut(&(room.to_string(), *peer)) {
+            seen.status = status;
+        }
+    }
+
+    /// Record what another member said about `peer`: that it was heard from at `at` and
+    /// whether its user was away. Ignored when we heard from `peer` more recently ourselves;
+    /// if we never do, it goes stale and offline as usual.
+    pub fn hint(&mut self, room: &str, peer: PeerId, at: u64, away: bool) {
+        let known = self.seen.get(&(room.to_string(), peer));
+        if known.is_some_and(|seen| seen.at >= at) {
+            return;
+        }
+        let interval = known.map_or(self.interval, |seen| seen.interval);
+        self.record(room, peer, at, interval, away, false);
+    }
+
+    /// Record that `peer` announced it is leaving `room`.
+    pub fn depart(&mut self, room: &str, peer: PeerId, now: u64) {
+        self.record(room, peer, now, self.interval, false, true);
+    }
+
+    /// Whether the last heartbeat of `peer` in `room` said its user is away.
+    pub fn is_away(&self, room: &str, peer: &PeerId) -> bool {
+        self.seen
+            .get(&(room.to_string(), *peer))
+            .is_some_and(|seen| seen.away)
+    }
+
+    /// Whether the last heartbeat of `peer` in `room` said its user doesn't want to be disturbed.
+    pub fn is_dnd(&self, room: &str, peer: &PeerId) -> bool {
+        self.seen
+            .get(&(room.to_string(), *peer))
+            .is_some_and(|seen| seen.dnd)
+    }
+
+    /// The status line of `peer` in `room`, until it goes offline.
+    pub fn status_line(&self, room: &str, peer: &PeerId, now: u64) -> Option<&str> {
+        self.seen
+            .get(&(room.to_string(), *peer))
+            .filter(|seen| classify(seen, now) != PresenceStatus::Offline)
+            .and_then(|seen| seen.status.as_deref())
+    }
+
+    /// The status of `peer` in `room` and when it was last heard from.
+    pub fn status(&self, room: &str, peer: &PeerId, now: u64) -> Option<(PresenceStatus, u64)> {
+        let seen = self.seen.get(&(room.to_string(), *peer))?;
+        Some((classify(seen, now), seen.at))
+    }
+
+    /// Everyone seen in `room`, most recently heard from first.
+    pub fn room(&self, room: &str, now: u64) -> Vec<(PeerId, PresenceStatus, u64)> {
+        let mut peers: Vec<_> = self
+            .seen
+            .iter()
+            .filter(|((r, _), _)| r == room)
+            .map(|((_, peer), seen)| (*peer, classify(seen, now), seen.at))
+            .collect();
+        peers.sort_by_key(|&(_, _, at)| std::cmp::Reverse(at));
+        peers
+    }
+
+    fn record(
+        &mut self,
+        room: &str,
+        peer: PeerId,
+        now: u64,
+        interval: u64,
+        away: bool,
+        departed: bool,
+    ) {
+        let key = (room.to_string(), peer);
+        if !self.seen.contains_key(&key) && self.seen.len() >= self.limit {
+            // Make room by forgetting peers that are offline anyway, or else the one heard
+            // from longest ago
+            let before = self.seen.len();
+            self.seen
+                .retain(|_, seen| classify(seen, now) != PresenceStatus::Offline);
+            if self.seen.len() >= self.limit {
+                let oldest = self
+                    .seen
+                    .iter()
+                    .min_by_key(|(_, seen)| seen.at)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    self.seen.remove(&oldest);
+                }
+            }
+            self.evictions += (before - self.seen.len()) as u64;
+        }
+        // Only heartbeats say whether the user doesn't want to be disturbed and set the status
+        // line, and both end when the peer leaves
+        let (dnd, status) = match self.seen.remove(&key) {
+            Some(seen) if !departed => (seen.dnd, seen.status),
+            _ => (false, None),
+        };
+        self.seen.insert(
+            key,
+            Seen {
+                at: now,
+                interval,
+                away,
+                dnd,
+                status,
+                departed,
+            },
+        );
+    }
+}
+
+/// A status line fit for sending and display: on one line, without control, bidi or
+/// zero-width characters and at most [`MAX_STATUS_CHARS`] long. `None` when nothing is left.
+pub fn clean_status_line(text: &str) -> Option<String> {
+    let cleaned: String = sanitize::line(text)
+        .trim()
+        .chars()
+        .take(MAX_STATUS_CHARS)
+        .collect();
+    Some(cleaned.trim_end().to_string()).filter(|status| !status.is_empty())
+}
+
+/// `status` cut to `max` characters, ending in `…` when anything was cut.
+pub fn shorten(status: &str, max: usize) -> String {
+    if status.chars().count() <= max {
+        return status.to_string();
+    }
+    let kept: String = status.chars().take(max.saturating_sub(1)).collect();
+    format!("{}…", kept.trim_end())
+}
+
+/// When to send the next heartbeat: `interval` seconds after `now`, give or take a fifth, so
+/// peers that started together don't keep sending at the same moment.
+pub fn next_heartbeat(now: u64, interval: u64) -> u64 {
+    let jitter = interval / 5;
+    now + rand::thread_rng().gen_range(interval - jitter..=interval + jitter)
+}
+
+fn classify(seen: &Seen, now: u64) -> PresenceStatus {
+    let age = now.saturating_sub(seen.at);
+    if seen.departed || age >= seen.interval.saturating_mul(OFFLINE_AFTER) {
+        PresenceStatus::Offline
+    } else if age >= seen.interval.saturating_mul(STALE_AFTER) {
+        PresenceStatus::Stale
+    } else {
+        PresenceStatus::Online
+    }
+}
@@ -0,0 +1,158 @@
+// Profiles peers publish about themselves: display name, pronouns, bio and avatar hash.
+use std::{fmt, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::sanitize;
+
+/// Longest display name accepted, in characters.
+pub const MAX_DISPLAY_NAME_CHARS: usize = sanitize::MAX_NICK_CHARS;
+
+/// Longest pronouns accepted, in characters.
+pub const MAX_PRONOUNS_CHARS: usize = 24;
+
+/// Longest bio accepted, in characters.
+pub const MAX_BIO_CHARS: usize = 160;
+
+/// A peer's self-description. Every field is optional; empty ones aren't sent.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub display_name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub pronouns: String,
+    /// A single line about the peer.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub bio: String,
+    /// Hex SHA-256 of the avatar image, for fetching it from the peer by content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+}
+
+/// A field of a profile, as named in `/profile set`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileField {
+    DisplayName,
+    Pronouns,
+    Bio,
+    Avatar,
+}
+
+impl ProfileField {
+    /// The field called `name`, like `bio` or `display-name`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "name" | "display-name" | "display_name" => Some(ProfileField::DisplayName),
+            "pronouns" => Some(ProfileField::Pronouns),
+            "bio" => Some(ProfileField::Bio),
+            "avatar" => Some(ProfileField::Avatar),
+            _ => None,
+        }
+    }
+
+    fn limit(self) -> usize {
+        match self {
+            ProfileField::DisplayName => MAX_DISPLAY_NAME_CHARS,
+            ProfileField::Pronouns => MAX_PRONOUNS_CHARS,
+            ProfileField::Bio => MAX_BIO_CHARS,
+            ProfileField::Avatar => 64,
+        }
+    }
+}
+
+impl fmt::Display for ProfileField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileField::DisplayName => write!(f, "display name"),
+            ProfileField::Pronouns => write!(f, "pronouns"),
+            ProfileField::Bio => write!(f, "bio"),
+            ProfileField::Avatar => write!(f, "avatar"),
+        }
+    }
+}
+
+impl Profile {
+    /// Whether no field is set, in which case there is nothing to publish.
+    pub fn is_empty(&self) -> bool {
+        *self == Profile::default()
+    }
+
+    /// Set a text field, after checking it fits. Setting it to an empty string clears it.
+    pub fn set(&mut self, field: ProfileField, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let chars = value.chars().count();
+        if chars > field.limit() {
+            return Err(format!(
+                "the {field} is {chars} characters long, at most {} are allowed",
+                field.limit()
+            ));
+        }
+        match field {
+            ProfileField::DisplayName => self.display_name = sanitize::nick(value),
+            ProfileField::Pronouns => self.pronouns = sanitize::nick(value),
+            ProfileField::Bio => self.bio = sanitize::line(value),
+            ProfileField::Avatar => self.avatar = (!value.is_empty()).then(|| value.to_string()),
+        }
+        self.check()
+    }
+
+    /// Check a profile received from a peer: every field within its limit and the avatar a
+    /// hex SHA-256 digest.
+    pub fn check(&self) -> Result<(), String> {
+        let fields = [
+            (ProfileField::DisplayName, &self.display_name),
+            (ProfileField::Pronouns, &self.pronouns),
+            (ProfileField::Bio, &self.bio),
+        ];
+        for (field, value) in fields {
+            if value.chars().count() > field.limit() {
+                return Err(format!("{field} too long"));
+            }
+        }
+        match &self.avatar {
+            Some(hash) if hash.len() != 64 || hex::decode(hash).is_err() => {
+                Err("avatar is not a SHA-256 digest".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The profile with every field made safe to print, since profiles come from untrusted
+    /// peers. Expects a profile that passed [`Profile::check`].
+    pub fn sanitized(&self) -> Self {
+        Profile {
+            display_name: sanitize::nick(&self.display_name),
+            pronouns: sanitize::nick(&self.pronouns),
+            bio: sanitize::line(&self.bio),
+            avatar: self.avatar.as_ref().map(|hash| hash.to_ascii_lowercase()),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "no profile");
+        }
+        let mut parts = Vec::new();
+        if !self.display_name.is_empty() {
+            parts.push(self.display_name.clone());
+        }
+        if !self.pronouns.is_empty() {
+            parts.push(format!("({})", self.pronouns));
+        }
+        if !self.bio.is_empty() {
+            parts.push(format!("\"{}\"", self.bio));
+        }
+        if let Some(hash) = &self.avatar {
+            parts.push(format!("avatar sha256:{hash}"));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Hex SHA-256 of the avatar image in `path`.
+pub fn avatar_hash(path: &Path) -> io::Result<String> {
+    Ok(hex::encode(Sha256::digest(fs::read(path)?)))
+}
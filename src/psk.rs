@@ -0,0 +1,107 @@
+// Pre-shared swarm keys for running a private network (libp2p pnet).
+use std::{error::Error, fmt, fs, io, path::Path};
+
+use libp2p::{
+    core::upgrade::NegotiationError,
+    pnet::{KeyParseError, PnetError, PreSharedKey},
+};
+use rand::RngCore;
+
+use crate::error::ConfigError;
+
+/// Read a swarm key in the standard `swarm.key` format (shared with go-libp2p and IPFS).
+pub fn load(path: &Path) -> Result<PreSharedKey, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    contents
+        .parse()
+        .map_err(|e: KeyParseError| ConfigError::SwarmKey {
+            path: path.to_path_buf(),
+            reason: e.to_string(),
+        })
+}
+
+/// Generate a fresh random swarm key.
+pub fn generate() -> PreSharedKey {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    PreSharedKey::new(bytes)
+}
+
+/// Generate a swarm key and write it to `path`, refusing to overwrite an existing file.
+pub fn write_new(path: &Path) -> Result<PreSharedKey, ConfigError> {
+    let key = generate();
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .and_then(|mut file| io::Write::write_all(&mut file, key.to_string().as_bytes()))
+        .map_err(|source| ConfigError::Write {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(key)
+}
+
+/// Connection failure caused by the remote peer not sharing our swarm key.
+///
+/// Without the key, the remote's bytes decrypt to garbage, so the failure shows up either in
+/// the nonce exchange or as an unreadable protocol negotiation right after it.
+#[derive(Debug)]
+pub struct PskMismatch {
+    detail: String,
+}
+
+impl PskMismatch {
+    pub(crate) fn new(detail: String) -> Self {
+        PskMismatch { detail }
+    }
+}
+
+impl fmt::Display for PskMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PSK mismatch: the remote peer is not using our swarm key ({})",
+            self.detail
+        )
+    }
+}
+
+impl Error for PskMismatch {}
+
+impl From<PnetError> for PskMismatch {
+    fn from(err: PnetError) -> Self {
+        PskMismatch {
+            detail: err.to_string(),
+        }
+    }
+}
+
+/// Turn a connection error on a private network into a [`PskMismatch`] where that is the cause.
+pub fn explain_connection_error<E>(err: E) -> io::Error
+where
+    E: Error + Send + Sync + 'static,
+{
+    // Walk the error chain looking for a failure that only a key mismatch produces here.
+    let mut cause: Option<&(dyn Error + 'static)> = Some(&err);
+    while let Some(current) = cause {
+        let detail = if current.is::<NegotiationError>() {
+            Some(current.to_string())
+        } else {
+            // `io::Error::source` skips over a wrapped custom error, so look inside it explicitly.
+            current
+                .downcast_ref::<io::Error>()
+                .and_then(|io_err| io_err.get_ref())
+                .and_then(|inner| inner.downcast_ref::<PskMismatch>())
+                .map(|mismatch| mismatch.detail.clone())
+        };
+        if let Some(detail) = detail {
+            return io::Error::new(io::ErrorKind::PermissionDenied, PskMismatch { detail });
+        }
+        cause = current.source();
+    }
+    io::Error::other(err)
+}
@@ -0,0 +1,508 @@
+// QR codes drawn in the terminal with Unicode half blocks, so an invite can be scanned from the
+// screen instead of typed in. The encoder covers what invites need: byte and alphanumeric
+// segments at error correction level L, the densest, in the smallest version that holds them.
+use std::env;
+
+/// Light modules drawn around the code, which scanners need to find it. The standard asks for
+/// four, but two are enough for phone cameras and leave room for larger codes.
+pub const QUIET_ZONE: usize = 2;
+
+/// Terminal width assumed when `COLUMNS` doesn't say.
+pub const DEFAULT_COLUMNS: usize = 80;
+
+/// The characters of alphanumeric mode, in the order of their values.
+const ALPHANUMERIC: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+/// Error correction codewords per block at level L, by version.
+const ECC_CODEWORDS_PER_BLOCK: [usize; 41] = [
+    0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28, 30,
+    30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30,
+];
+
+/// Error correction blocks at level L, by version.
+const ERROR_CORRECTION_BLOCKS: [usize; 41] = [
+    0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13, 14,
+    15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25,
+];
+
+/// A QR code: a square of dark and light modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    version: usize,
+    size: usize,
+    modules: Vec<bool>,
+    // Modules of the finder, timing and alignment patterns and of the format and version
+    // information, which data and masks leave alone
+    function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `text` in the smallest version up to `max_version` (at most 40) that holds it,
+    /// in alphanumeric mode if all its characters have one, or as bytes otherwise. None if it
+    /// doesn't fit.
+    pub fn encode(text: &str, max_version: usize) -> Option<Self> {
+        let alphanumeric = text.chars().all(|c| ALPHANUMERIC.contains(c));
+        let version = (1..=max_version.min(40)).find(|&version| {
+            segment_bits(text.len(), alphanumeric, version)
+                .is_some_and(|bits| bits <= data_codewords(version) * 8)
+        })?;
+        let mut bits = Bits::default();
+        if alphanumeric {
+            bits.push(0b0010, 4);
+            bits.push(text.len() as u32, count_bits(true, version));
+            let values: Vec<u32> = text
+                .chars()
+                .map(|c| ALPHANUMERIC.find(c).expect("checked above") as u32)
+                .collect();
+            for pair in values.chunks(2) {
+                match pair {
+                    [first, second] => bits.push(first * 45 + second, 11),
+                    [last] => bits.push(*last, 6),
+                    _ => unreachable!(),
+                }
+            }
+        } else {
+            bits.push(0b0100, 4);
+            bits.push(text.len() as u32, count_bits(false, version));
+            for byte in text.bytes() {
+                bits.push(byte.into(), 8);
+            }
+        }
+
+        // The terminator, then zeros to a whole byte, then alternating pad bytes
+        let capacity = data_codewords(version) * 8;
+        bits.push(0, (capacity - bits.0.len()).min(4));
+        bits.push(0, (8 - bits.0.len() % 8) % 8);
+        for pad in [0xec, 0x11].into_iter().cycle() {
+            if bits.0.len() >= capacity {
+                break;
+            }
+            bits.push(pad, 8);
+        }
+        let data: Vec<u8> = bits
+            .0
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | u8::from(bit)))
+            .collect();
+
+        let mut code = QrCode::blank(version);
+        code.draw_codewords(&interleave(version, &data));
+        // Keep the mask that leaves the fewest patterns confusing scanners
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                let mut masked = code.clone();
+                masked.apply_mask(mask);
+                masked.draw_format(mask);
+                masked.penalty()
+            })
+            .expect("there are masks");
+        code.apply_mask(mask);
+        code.draw_format(mask);
+        Some(code)
+    }
+
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// Modules on each side.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Whether the module `x` from the left and `y` from the top is dark.
+    pub fn is_dark(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    /// A code with the function patterns drawn and no data.
+    fn blank(version: usize) -> Self {
+        let size = version * 4 + 17;
+        let mut code = QrCode {
+            version,
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        };
+        for i in 0..size {
+            code.set_function(6, i, i % 2 == 0);
+            code.set_function(i, 6, i % 2 == 0);
+        }
+        for (x, y) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4..=4isize {
+                for dx in -4..=4isize {
+                    let (Some(x), Some(y)) = (x.checked_add_signed(dx), y.checked_add_signed(dy))
+                    else {
+                        continue;
+                    };
+                    let distance = dx.abs().max(dy.abs());
+                    if x < size && y < size {
+                        code.set_function(x, y, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+        let positions = alignment_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // Those at three corners would overlap the finder patterns
+                if (i, j) == (0, 0) || (i, j) == (0, last) || (i, j) == (last, 0) {
+                    continue;
+                }
+                for dy in -2..=2isize {
+                    for dx in -2..=2isize {
+                        let dark = dx.abs().max(dy.abs()) != 1;
+                        code.set_function(
+                            x.wrapping_add_signed(dx),
+                            y.wrapping_add_signed(dy),
+                            dark,
+                        );
+                    }
+                }
+            }
+        }
+        // Reserve the format information, drawn once the mask is chosen
+        code.draw_format(0);
+        if version >= 7 {
+            let mut remainder = version as u32;
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+            }
+            let bits = (version as u32) << 12 | remainder;
+            for i in 0..18 {
+                let dark = bits >> i & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                code.set_function(a, b, dark);
+                code.set_function(b, a, dark);
+            }
+        }
+        code
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    /// Draw the format information: the error correction level and `mask`.
+    fn draw_format(&mut self, mask: u32) {
+        // Level L is 01
+        let data = 0b01 << 3 | mask;
+        let mut remainder = data;
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| bits >> i & 1 == 1;
+        let size = self.size;
+        for i in 0..6 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        // The module that is always dark
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Lay the codewords out in the zigzag of two-module columns, from the bottom right.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+        loop {
+            // The vertical timing pattern's column is skipped
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for x in [right, right - 1] {
+                    if !self.function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = codewords[i / 8] >> (7 - i % 8) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let i = y * self.size + x;
+                self.modules[i] ^= flip && !self.function[i];
+            }
+        }
+    }
+
+    /// How hard the code is to scan, by the standard's rules: long runs of one color, 2×2
+    /// blocks, lookalikes of the finder pattern and an imbalance of dark and light.
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut penalty = 0;
+        let lines = (0..size).flat_map(|i| {
+            [
+                (0..size).map(|j| self.is_dark(j, i)).collect::<Vec<_>>(),
+                (0..size).map(|j| self.is_dark(i, j)).collect::<Vec<_>>(),
+            ]
+        });
+        const FINDER: [bool; 11] = [
+            true, false, true, true, true, false, true, false, false, false, false,
+        ];
+        for line in lines {
+            for run in line.chunk_by(|a, b| a == b) {
+                if run.len() >= 5 {
+                    penalty += run.len() - 2;
+                }
+            }
+            for window in line.windows(FINDER.len()) {
+                if window == FINDER || window.iter().rev().eq(FINDER.iter()) {
+                    penalty += 40;
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.is_dark(x, y);
+                if [(1, 0), (0, 1), (1, 1)]
+                    .iter()
+                    .all(|&(dx, dy)| self.is_dark(x + dx, y + dy) == dark)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = self.modules.len();
+        // Ten points for each 5% the dark modules are away from half
+        let steps = (dark * 20).abs_diff(total * 10).div_ceil(total);
+        penalty + steps.saturating_sub(1) * 10
+    }
+
+    /// The code drawn with half blocks, two rows of modules to a line, inside its quiet zone.
+    /// Light modules are drawn as blocks, so the code comes out right on the usual light text
+    /// on a dark background.
+    pub fn render(&self) -> String {
+        let side = self.size + QUIET_ZONE * 2;
+        let light = |x: usize, y: usize| {
+            let (Some(x), Some(y)) = (x.checked_sub(QUIET_ZONE), y.checked_sub(QUIET_ZONE)) else {
+                return true;
+            };
+            x >= self.size || y >= self.size || !self.is_dark(x, y)
+        };
+        let mut out = String::new();
+        for y in (0..side).step_by(2) {
+            for x in 0..side {
+                let bottom = y + 1 < side && light(x, y + 1);
+                out.push(match (light(x, y), bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Columns the rendered code takes.
+    pub fn width(&self) -> usize {
+        self.size + QUIET_ZONE * 2
+    }
+}
+
+/// The largest version whose code, quiet zone included, fits in `columns`.
+pub fn max_version(columns: usize) -> usize {
+    (columns.saturating_sub(QUIET_ZONE * 2 + 17) / 4).min(40)
+}
+
+/// The terminal's width, as `COLUMNS` says, or [`DEFAULT_COLUMNS`].
+pub fn terminal_columns() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.trim().parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+/// Whether the terminal takes UTF-8, and so can draw the half blocks, as the locale says.
+pub fn utf8_terminal() -> bool {
+    if cfg!(windows) {
+        return true;
+    }
+    // The first of these that is set decides, as for the C library
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    locale.contains("utf-8") || locale.contains("utf8")
+}
+
+/// Bits being packed into codewords.
+#[derive(Default)]
+struct Bits(Vec<bool>);
+
+impl Bits {
+    fn push(&mut self, value: u32, count: usize) {
+        self.0.extend((0..count).rev().map(|i| value >> i & 1 == 1));
+    }
+}
+
+/// Width of the character count of a segment.
+fn count_bits(alphanumeric: bool, version: usize) -> usize {
+    match (alphanumeric, version) {
+        (true, ..=9) => 9,
+        (true, ..=26) => 11,
+        (true, _) => 13,
+        (false, ..=9) => 8,
+        (false, _) => 16,
+    }
+}
+
+/// Bits a segment of `len` characters takes in `version`, None if its count doesn't fit.
+fn segment_bits(len: usize, alphanumeric: bool, version: usize) -> Option<usize> {
+    let count_bits = count_bits(alphanumeric, version);
+    if len >= 1 << count_bits {
+        return None;
+    }
+    let data = match alphanumeric {
+        true => len / 2 * 11 + len % 2 * 6,
+        false => len * 8,
+    };
+    Some(4 + count_bits + data)
+}
+
+/// Modules left for codewords once the function patterns are drawn.
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+    modules
+}
+
+fn data_codewords(version: usize) -> usize {
+    raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[version] * ERROR_CORRECTION_BLOCKS[version]
+}
+
+/// Centers of the alignment patterns along each axis.
+fn alignment_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version / 7 + 2;
+    let step = match version {
+        32 => 26,
+        _ => (version * 4 + count * 2 + 1) / (count * 2 - 2) * 2,
+    };
+    let size = version * 4 + 17;
+    let mut positions: Vec<usize> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+/// Split `data` into blocks, add each one's error correction codewords, and interleave them.
+fn interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let blocks = ERROR_CORRECTION_BLOCKS[version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw = raw_data_modules(version) / 8;
+    // The last blocks are a codeword longer than the first
+    let short_blocks = blocks - raw % blocks;
+    let short_len = raw / blocks - ecc_len;
+    let divisor = reed_solomon_divisor(ecc_len);
+
+    let mut chunks = Vec::with_capacity(blocks);
+    let mut rest = data;
+    for i in 0..blocks {
+        let len = short_len + usize::from(i >= short_blocks);
+        let (chunk, tail) = rest.split_at(len);
+        chunks.push((chunk, reed_solomon_remainder(chunk, &divisor)));
+        rest = tail;
+    }
+    let mut out = Vec::with_capacity(raw);
+    for i in 0..=short_len {
+        out.extend(chunks.iter().filter_map(|(chunk, _)| chunk.get(i)));
+    }
+    for i in 0..ecc_len {
+        out.extend(chunks.iter().map(|(_, ecc)| ecc[i]));
+    }
+    out
+}
+
+/// Multiplication in GF(2⁸) modulo x⁸ + x⁴ + x³ + x² + 1, QR codes' field.
+fn gf_mul(a: u8, b: u8) -> u8 {
+    let mut product: u16 = 0;
+    for i in (0..8).rev() {
+        product = (product << 1) ^ ((product >> 7) * 0x11d);
+        product ^= u16::from(b >> i & 1) * u16::from(a);
+    }
+    product as u8
+}
+
+/// Coefficients of the generator polynomial of `degree`, highest first, the leading 1 left out.
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+    let mut root = 1;
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_mul(divisor[j], root);
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+        root = gf_mul(root, 2);
+    }
+    divisor
+}
+
+/// The error correction codewords of `data`.
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+        for (r, &coefficient) in remainder.iter_mut().zip(divisor) {
+            *r ^= gf_mul(coefficient, factor);
+        }
+    }
+    remainder
+}
@@ -0,0 +1,148 @@
+// Abuse reports that room members send to the room's moderators.
+use std::collections::{HashMap, VecDeque};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::message::ChatMessage;
+
+/// Reports accepted from one reporter per [`REPORT_WINDOW`]; more are dropped.
+pub const MAX_REPORTS_PER_WINDOW: usize = 5;
+
+/// Window (seconds) over which reports per reporter are counted.
+pub const REPORT_WINDOW: u64 = 600;
+
+/// Number of received reports kept, pending or handled.
+pub const MAX_REPORTS: usize = 256;
+
+// Reporters whose recent reports are counted
+const MAX_REPORTERS: usize = 1024;
+
+/// A member's report of a message, published on the control topic for the room's moderators.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Name of the room's topic.
+    pub room: String,
+    /// The moderators the report is meant for; everyone else ignores it.
+    pub moderators: Vec<PeerId>,
+    /// Gossipsub id of the reported message.
+    pub message_id: String,
+    /// Author of the reported message, if it was signed.
+    pub author: Option<PeerId>,
+    /// A copy of the reported message as the reporter received it.
+    pub message: ChatMessage,
+    pub reason: String,
+    /// Unix time (seconds) at which the report was made.
+    pub timestamp: u64,
+}
+
+/// A report as kept by a moderator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceivedReport {
+    /// Local number, used to refer to it in `/reports`.
+    pub id: u64,
+    pub reporter: PeerId,
+    pub report: Report,
+    /// Whether a kick or ban of the author has dealt with it.
+    pub handled: bool,
+}
+
+/// How a received report was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportOutcome {
+    /// We are not one of the moderators it is addressed to.
+    NotAddressed,
+    /// The reporter already reported this message; the new reason replaced the old one.
+    Duplicate(u64),
+    /// The reporter sent too many reports recently.
+    RateLimited,
+    /// A new pending report.
+    New(u64),
+}
+
+/// Reports received as a moderator.
+#[derive(Debug)]
+pub struct Reports {
+    local: PeerId,
+    reports: VecDeque<ReceivedReport>,
+    next_id: u64,
+    // When each reporter's recent reports arrived
+    recent: HashMap<PeerId, VecDeque<u64>>,
+}
+
+impl Reports {
+    pub fn new(local: PeerId) -> Self {
+        Reports {
+            local,
+            reports: VecDeque::new(),
+            next_id: 1,
+            recent: HashMap::new(),
+        }
+    }
+
+    /// Every report kept, oldest first.
+    pub fn all(&self) -> impl Iterator<Item = &ReceivedReport> {
+        self.reports.iter()
+    }
+
+    /// Reports no kick or ban has dealt with yet, oldest first.
+    pub fn pending(&self) -> impl Iterator<Item = &ReceivedReport> {
+        self.reports.iter().filter(|r| !r.handled)
+    }
+
+    /// Take in a report signed by `reporter`.
+    pub fn receive(&mut self, reporter: PeerId, report: Report, now: u64) -> ReportOutcome {
+        if !report.moderators.contains(&self.local) {
+            return ReportOutcome::NotAddressed;
+        }
+        // Reporting the same message again only updates the reason
+        if let Some(existing) = self.reports.iter_mut().find(|r| {
+            r.reporter == reporter && !r.handled && r.report.message_id == report.message_id
+        }) {
+            existing.report.reason = report.reason;
+            return ReportOutcome::Duplicate(existing.id);
+        }
+
+        if !self.recent.contains_key(&reporter) && self.recent.len() >= MAX_REPORTERS {
+            self.recent
+                .retain(|_, times| times.back().is_some_and(|&t| t + REPORT_WINDOW > now));
+            if self.recent.len() >= MAX_REPORTERS {
+                return ReportOutcome::RateLimited;
+            }
+        }
+        let times = self.recent.entry(reporter).or_default();
+        while times.front().is_some_and(|&t| t + REPORT_WINDOW <= now) {
+            times.pop_front();
+        }
+        if times.len() >= MAX_REPORTS_PER_WINDOW {
+            return ReportOutcome::RateLimited;
+        }
+        times.push_back(now);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.reports.push_back(ReceivedReport {
+            id,
+            reporter,
+            report,
+            handled: false,
+        });
+        if self.reports.len() > MAX_REPORTS {
+            self.reports.pop_front();
+        }
+        ReportOutcome::New(id)
+    }
+
+    /// Mark the pending reports about messages `target` wrote in `room` as handled, returning
+    /// them.
+    pub fn resolve(&mut self, room: &str, target: &PeerId) -> Vec<ReceivedReport> {
+        let mut resolved = Vec::new();
+        for received in self.reports.iter_mut().filter(|r| {
+            !r.handled && r.report.room == room && r.report.author.as_ref() == Some(target)
+        }) {
+            received.handled = true;
+            resolved.push(received.clone());
+        }
+        resolved
+    }
+}
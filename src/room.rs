@@ -0,0 +1,229 @@
+// Room settings and the moderation actions moderators can take in a room.
+use std::collections::{BTreeMap, HashMap};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::decision;
+
+/// Maximum number of (moderator, target) pairs remembered for replay protection.
+pub const MAX_SEEN_ACTIONS: usize = 4096;
+
+/// Maximum number of admitted members remembered per invite-only room.
+pub const MAX_ROOM_MEMBERS: usize = 4096;
+
+/// Settings of one room (a chat topic), stored in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoomSettings {
+    /// Peers whose signed kick and room-ban messages are honored in this room.
+    #[serde(default)]
+    pub moderators: Vec<PeerId>,
+    /// Peers banned from this room by a moderator, ignored until unbanned. Filled from the
+    /// config's ban list on startup; only read from the file as written by older versions.
+    #[serde(default, skip_serializing)]
+    pub banned: Vec<PeerId>,
+    /// Owner of an invite-only room, who signs its invites. Open rooms have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<PeerId>,
+    /// Peers that presented a valid invite to an invite-only room.
+    #[serde(default)]
+    pub members: Vec<PeerId>,
+    /// Moderators who must sign a room ban before it is honored; without one, any moderator's
+    /// ban is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quorum: Option<usize>,
+    /// Our own invite token, presented to members we haven't met yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite: Option<String>,
+    /// When each peer's ban or kick here was last lifted, so quorum decisions taken before
+    /// can't remove it again.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub lifted: BTreeMap<PeerId, u64>,
+    /// Seconds between our presence heartbeats in this room, instead of `--presence-interval`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_interval: Option<u64>,
+    /// Milliseconds our chat messages may wait to be sent together in this room, instead of
+    /// `--batch-ms`; 0 turns batching off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_ms: Option<u64>,
+    /// Size in bytes at which a batch goes out early in this room, instead of `--batch-bytes`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch_bytes: Option<usize>,
+}
+
+impl RoomSettings {
+    /// Remember that `peer` was let back in at `now`. Lifts older than [`decision::MAX_AGE`]
+    /// are forgotten, since decisions that old are refused anyway.
+    pub fn lift(&mut self, peer: PeerId, now: u64) {
+        self.lifted
+            .retain(|_, at| now.saturating_sub(*at) <= decision::MAX_AGE);
+        self.lifted.insert(peer, now);
+    }
+
+    /// Whether messages from `peer` are accepted: anyone in an open room, otherwise the owner,
+    /// moderators and members that joined with an invite.
+    pub fn admits(&self, peer: &PeerId) -> bool {
+        self.owner.is_none_or(|owner| owner == *peer)
+            || self.moderators.contains(peer)
+            || self.members.contains(peer)
+    }
+
+    /// Record `peer` as a member, returning false if the member list is full.
+    pub fn admit(&mut self, peer: PeerId) -> bool {
+        if self.members.contains(&peer) {
+            return true;
+        }
+        if self.members.len() >= MAX_ROOM_MEMBERS {
+            return false;
+        }
+        self.members.push(peer);
+        true
+    }
+}
+
+/// What a moderator does to a peer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ModAction {
+    /// Ignore the peer in the room until the node restarts.
+    Kick,
+    /// Ignore the peer in the room for good.
+    RoomBan,
+}
+
+/// A kick or room-ban published by a moderator on the control topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Moderation {
+    pub action: ModAction,
+    /// Name of the room's topic, so an action can't be replayed in another room.
+    pub room: String,
+    pub target: PeerId,
+    pub reason: String,
+    /// Unix time (seconds) at which the moderator took the action.
+    pub timestamp: u64,
+}
+
+/// How a received moderation action was handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationOutcome {
+    /// The author is not a moderator of the room.
+    NotModerator,
+    /// The action is not newer than one already seen from this moderator, or too many
+    /// actions are tracked already.
+    Stale,
+    /// The action targets the local node, which never ignores itself.
+    TargetsSelf,
+    /// The target is now ignored in the room.
+    Honored,
+}
+
+/// Moderators and ignored peers of every room, checked against incoming actions.
+#[derive(Debug)]
+pub struct Rooms {
+    local_peer: PeerId,
+    // Moderators given on the command line, on top of the configured ones, per room
+    extra_moderators: HashMap<String, Vec<PeerId>>,
+    // Peers kicked this session, per room
+    kicked: HashMap<String, Vec<PeerId>>,
+    // Newest timestamp seen per (moderator, target), so replays are dropped
+    latest: HashMap<(PeerId, PeerId), u64>,
+}
+
+impl Rooms {
+    pub fn new(local_peer: PeerId) -> Self {
+        Rooms {
+            local_peer,
+            extra_moderators: HashMap::new(),
+            kicked: HashMap::new(),
+            latest: HashMap::new(),
+        }
+    }
+
+    /// Treat `moderator` as a moderator of `room` for this session.
+    pub fn add_moderator(&mut self, room: &str, moderator: PeerId) {
+        let moderators = self.extra_moderators.entry(room.to_string()).or_default();
+        if !moderators.contains(&moderator) {
+            moderators.push(moderator);
+        }
+    }
+
+    /// Moderators of `room`: the configured ones followed by those added for this session.
+    pub fn moderators(&self, room: &str, settings: Option<&RoomSettings>) -> Vec<PeerId> {
+        let mut moderators = settings.map(|s| s.moderators.clone()).unwrap_or_default();
+        for moderator in self.extra_moderators.get(room).into_iter().flatten() {
+            if !moderators.contains(moderator) {
+                moderators.push(*moderator);
+            }
+        }
+        moderators
+    }
+
+    /// Whether messages from `peer` in `room` are ignored.
+    pub fn is_ignored(&self, room: &str, peer: &PeerId, settings: Option<&RoomSettings>) -> bool {
+        settings.is_some_and(|s| s.banned.contains(peer))
+            || self
+                .kicked
+                .get(room)
+                .is_some_and(|kicked| kicked.contains(peer))
+    }
+
+    /// Check a moderation action from `author` and apply it. Room bans are written to
+    /// `settings`, which the caller should persist.
+    pub fn receive(
+        &mut self,
+        author: PeerId,
+        moderation: &Moderation,
+        settings: &mut RoomSettings,
+    ) -> ModerationOutcome {
+        if !self
+            .moderators(&moderation.room, Some(settings))
+            .contains(&author)
+        {
+            return ModerationOutcome::NotModerator;
+        }
+        if moderation.target == self.local_peer {
+            return ModerationOutcome::TargetsSelf;
+        }
+        let key = (author, moderation.target);
+        if self.latest.get(&key) >= Some(&moderation.timestamp)
+            || (!self.latest.contains_key(&key) && self.latest.len() >= MAX_SEEN_ACTIONS)
+        {
+            return ModerationOutcome::Stale;
+        }
+        self.latest.insert(key, moderation.timestamp);
+        self.apply(moderation, settings);
+        ModerationOutcome::Honored
+    }
+
+    /// Apply a moderation action without checking who took it, for actions of the local user.
+    pub fn apply(&mut self, moderation: &Moderation, settings: &mut RoomSettings) {
+        match moderation.action {
+            ModAction::Kick => {
+                let kicked = self.kicked.entry(moderation.room.clone()).or_default();
+                if !kicked.contains(&moderation.target) {
+                    kicked.push(moderation.target);
+                }
+            }
+            ModAction::RoomBan => {
+                if !settings.banned.contains(&moderation.target) {
+                    settings.banned.push(moderation.target);
+                }
+            }
+        }
+    }
+
+    /// Stop ignoring `peer` in `room`, returning whether it was ignored.
+    pub fn readmit(&mut self, room: &str, peer: &PeerId, settings: &mut RoomSettings) -> bool {
+        let kicked = self
+            .kicked
+            .get_mut(room)
+            .is_some_and(|kicked| remove(kicked, peer));
+        remove(&mut settings.banned, peer) || kicked
+    }
+}
+
+fn remove(peers: &mut Vec<PeerId>, peer: &PeerId) -> bool {
+    let before = peers.len();
+    peers.retain(|p| p != peer);
+    peers.len() != before
+}
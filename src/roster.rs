@@ -0,0 +1,161 @@
+// Who is online in each room, from subscriptions and presence.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use libp2p::PeerId;
+
+use crate::{
+    autoban::MAX_TRACKED_PEERS,
+    presence::{Presence, PresenceStatus},
+};
+
+/// A peer arriving in or leaving a room's roster, or its user going away or coming back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RosterEvent {
+    Joined { room: String, peer: PeerId },
+    Left { room: String, peer: PeerId },
+    Away { room: String, peer: PeerId },
+    Back { room: String, peer: PeerId },
+}
+
+impl RosterEvent {
+    pub fn room(&self) -> &str {
+        match self {
+            RosterEvent::Joined { room, .. }
+            | RosterEvent::Left { room, .. }
+            | RosterEvent::Away { room, .. }
+            | RosterEvent::Back { room, .. } => room,
+        }
+    }
+}
+
+/// The peers online in each room: subscribed to its topic and recently heard from.
+///
+/// Subscriptions are recorded as they happen, but the roster itself only changes when
+/// [`Roster::update`] is called, so that changes arriving together are reported together.
+/// Peers other members vouched for in a snapshot count as subscribed until they go offline.
+#[derive(Debug, Default)]
+pub struct Roster {
+    subscribed: HashMap<String, HashSet<PeerId>>,
+    vouched: HashMap<String, HashSet<PeerId>>,
+    // Whether each online peer's user is away
+    online: HashMap<String, BTreeMap<PeerId, bool>>,
+}
+
+impl Roster {
+    /// Record that `peer` subscribed to the topic of `room`.
+    pub fn subscribe(&mut self, room: &str, peer: PeerId) {
+        let tracked: usize = self.subscribed.values().map(HashSet::len).sum();
+        if tracked < MAX_TRACKED_PEERS {
+            self.subscribed
+                .entry(room.to_string())
+                .or_default()
+                .insert(peer);
+        }
+    }
+
+    /// Record that another member vouched for `peer` being in `room`, although we can't see
+    /// its subscription ourselves.
+    pub fn vouch(&mut self, room: &str, peer: PeerId) {
+        let tracked: usize = self.vouched.values().map(HashSet::len).sum();
+        if tracked < MAX_TRACKED_PEERS {
+            self.vouched
+                .entry(room.to_string())
+                .or_default()
+                .insert(peer);
+        }
+    }
+
+    /// Record that `peer` unsubscribed from the topic of `room`.
+    pub fn unsubscribe(&mut self, room: &str, peer: &PeerId) {
+        for peers in [&mut self.subscribed, &mut self.vouched] {
+            if let Some(room_peers) = peers.get_mut(room) {
+                room_peers.remove(peer);
+                if room_peers.is_empty() {
+                    peers.remove(room);
+                }
+            }
+        }
+    }
+
+    /// Whether `peer` was seen subscribing to the topic of `room`, online or not.
+    pub fn is_subscribed(&self, room: &str, peer: &PeerId) -> bool {
+        self.subscribed
+            .get(room)
+            .is_some_and(|peers| peers.contains(peer))
+    }
+
+    /// The peers online in `room`, as of the last update.
+    pub fn online(&self, room: &str) -> impl Iterator<Item = &PeerId> {
+        self.online.get(room).into_iter().flat_map(BTreeMap::keys)
+    }
+
+    /// Whether the user of `peer` was away in `room` as of the last update.
+    pub fn is_away(&self, room: &str, peer: &PeerId) -> bool {
+        self.online
+            .get(room)
+            .and_then(|peers| peers.get(peer))
+            .is_some_and(|&away| away)
+    }
+
+    /// Bring every room up to date with the subscriptions and with `presence`, returning the
+    /// changes.
+    pub fn update(&mut self, presence: &Presence, now: u64) -> Vec<RosterEvent> {
+        let rooms: HashSet<String> = self
+            .subscribed
+            .keys()
+            .chain(self.vouched.keys())
+            .chain(self.online.keys())
+            .cloned()
+            .collect();
+        let mut events = Vec::new();
+        for room in rooms {
+            // Hearsay is only believed while the peer looks alive
+            if let Some(vouched) = self.vouched.get_mut(&room) {
+                vouched.retain(|peer| {
+                    presence
+                        .status(&room, peer, now)
+                        .is_some_and(|(status, _)| status != PresenceStatus::Offline)
+                });
+                if vouched.is_empty() {
+                    self.vouched.remove(&room);
+                }
+            }
+            let current: BTreeMap<PeerId, bool> = self
+                .subscribed
+                .get(&room)
+                .into_iter()
+                .flatten()
+                .chain(self.vouched.get(&room).into_iter().flatten())
+                .filter(|peer| {
+                    presence
+                        .status(&room, peer, now)
+                        .is_some_and(|(status, _)| status == PresenceStatus::Online)
+                })
+                .map(|peer| (*peer, presence.is_away(&room, peer)))
+                .collect();
+            let previous = self.online.remove(&room).unwrap_or_default();
+            for (&peer, &away) in &current {
+                let room = room.clone();
+                match previous.get(&peer) {
+                    None => events.push(RosterEvent::Joined { room, peer }),
+                    Some(&was_away) if was_away != away => events.push(if away {
+                        RosterEvent::Away { room, peer }
+                    } else {
+                        RosterEvent::Back { room, peer }
+                    }),
+                    Some(_) => {}
+                }
+            }
+            for peer in previous.keys().filter(|peer| !current.contains_key(peer)) {
+                events.push(RosterEvent::Left {
+                    room: room.clone(),
+                    peer: *peer,
+                });
+            }
+            if !current.is_empty() {
+                self.online.insert(room, current);
+            }
+        }
+        events
+    }
+}
@@ -0,0 +1,302 @@
+// The async runtime the node runs on, picked when building: Tokio by default, async-std with
+// `--no-default-features --features async-std`.
+//
+// Only timers, spawning, signals, stdin and sockets opened outside the swarm depend on the
+// runtime. Tokio's channels, semaphore and `select!` work under any executor, so they are used
+// with either.
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use libp2p::futures::{
+    future::{AbortHandle, Abortable},
+    FutureExt,
+};
+
+#[cfg(not(any(feature = "tokio", feature = "async-std")))]
+compile_error!("enable one of the `tokio` or `async-std` features to pick a runtime");
+
+/// Name of the runtime this build uses.
+#[cfg(not(feature = "async-std"))]
+pub const NAME: &str = "tokio";
+#[cfg(feature = "async-std")]
+pub const NAME: &str = "async-std";
+
+/// A timer started by [`sleep`] or [`sleep_until`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Run `future` to completion on a new runtime, blocking the thread until it's done.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    #[cfg(not(feature = "async-std"))]
+    return tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("the tokio runtime starts")
+        .block_on(future);
+    #[cfg(feature = "async-std")]
+    return async_std::task::block_on(future);
+}
+
+/// Wait for `duration`.
+pub fn sleep(duration: Duration) -> Sleep {
+    #[cfg(not(feature = "async-std"))]
+    return Box::pin(tokio::time::sleep(duration));
+    #[cfg(feature = "async-std")]
+    return Box::pin(async_std::task::sleep(duration));
+}
+
+/// Wait until `deadline`, or not at all when it has passed.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    #[cfg(not(feature = "async-std"))]
+    return Box::pin(tokio::time::sleep_until(deadline.into()));
+    #[cfg(feature = "async-std")]
+    return sleep(deadline.saturating_duration_since(Instant::now()));
+}
+
+/// A [`timeout`] ran out before its future finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+/// Run `future` for at most `duration`.
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    #[cfg(not(feature = "async-std"))]
+    return tokio::time::timeout(duration, future)
+        .await
+        .map_err(|_| Elapsed);
+    #[cfg(feature = "async-std")]
+    return async_std::future::timeout(duration, future)
+        .await
+        .map_err(|_| Elapsed);
+}
+
+/// Ticks once a period, the first one straight away. A tick that comes late pushes the later
+/// ones back rather than having them catch up in a burst.
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+}
+
+/// An [`Interval`] ticking every `period`.
+pub fn interval(period: Duration) -> Interval {
+    Interval {
+        period,
+        next: Instant::now(),
+    }
+}
+
+impl Interval {
+    /// Wait for the next tick. Dropping the future before it's done skips nothing.
+    pub async fn tick(&mut self) -> Instant {
+        let tick = self.next;
+        sleep_until(tick).await;
+        let now = Instant::now();
+        self.next = if now >= tick + self.period {
+            now + self.period
+        } else {
+            tick + self.period
+        };
+        tick
+    }
+}
+
+/// A task started with [`spawn`]. It keeps running when this is dropped; awaiting it gives
+/// its output, or `None` if it was aborted.
+#[derive(Debug)]
+pub struct Task<T> {
+    #[cfg(not(feature = "async-std"))]
+    handle: tokio::task::JoinHandle<Result<T, libp2p::futures::future::Aborted>>,
+    #[cfg(feature = "async-std")]
+    handle: async_std::task::JoinHandle<Result<T, libp2p::futures::future::Aborted>>,
+    abort: AbortHandle,
+}
+
+/// Run `future` on a task of its own.
+pub fn spawn<F>(future: F) -> Task<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (abort, registration) = AbortHandle::new_pair();
+    let future = Abortable::new(future, registration);
+    #[cfg(not(feature = "async-std"))]
+    let handle = tokio::spawn(future);
+    #[cfg(feature = "async-std")]
+    let handle = async_std::task::spawn(future);
+    Task { handle, abort }
+}
+
+impl<T> Task<T> {
+    /// Stop the task at its next await.
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        #[cfg(not(feature = "async-std"))]
+        return self.handle.poll_unpin(cx).map(|joined| match joined {
+            Ok(output) => output.ok(),
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => None,
+        });
+        #[cfg(feature = "async-std")]
+        return self.handle.poll_unpin(cx).map(Result::ok);
+    }
+}
+
+/// A TCP connection opened with [`connect_tcp`], as futures I/O.
+#[cfg(not(feature = "async-std"))]
+pub type TcpStream = tokio_util::compat::Compat<tokio::net::TcpStream>;
+#[cfg(feature = "async-std")]
+pub type TcpStream = async_std::net::TcpStream;
+
+/// Connect to `port` on `host`, a name or an address, outside the swarm.
+pub async fn connect_tcp(host: &str, port: u16) -> std::io::Result<TcpStream> {
+    #[cfg(not(feature = "async-std"))]
+    {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        return tokio::net::TcpStream::connect((host, port))
+            .await
+            .map(TokioAsyncReadCompatExt::compat);
+    }
+    #[cfg(feature = "async-std")]
+    return async_std::net::TcpStream::connect((host, port)).await;
+}
+
+/// A TCP listener opened with [`listen_tcp`].
+#[cfg(not(feature = "async-std"))]
+pub type TcpListener = tokio::net::TcpListener;
+#[cfg(feature = "async-std")]
+pub type TcpListener = async_std::net::TcpListener;
+
+/// Listen for TCP connections on `address`, outside the swarm. Binding doesn't wait, so a
+/// taken port is known straight away.
+pub fn listen_tcp(address: std::net::SocketAddr) -> std::io::Result<TcpListener> {
+    let listener = std::net::TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    #[cfg(not(feature = "async-std"))]
+    return TcpListener::from_std(listener);
+    #[cfg(feature = "async-std")]
+    return Ok(TcpListener::from(listener));
+}
+
+/// Take the next connection to `listener`.
+pub async fn accept_tcp(
+    listener: &TcpListener,
+) -> std::io::Result<(TcpStream, std::net::SocketAddr)> {
+    #[cfg(not(feature = "async-std"))]
+    {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        return listener
+            .accept()
+            .await
+            .map(|(stream, address)| (stream.compat(), address));
+    }
+    #[cfg(feature = "async-std")]
+    return listener.accept().await;
+}
+
+/// A Unix domain socket connection opened with [`connect_unix`], as futures I/O.
+#[cfg(all(unix, not(feature = "async-std")))]
+pub type UnixStream = tokio_util::compat::Compat<tokio::net::UnixStream>;
+#[cfg(all(unix, feature = "async-std"))]
+pub type UnixStream = async_std::os::unix::net::UnixStream;
+// Never opened where there are no Unix domain sockets
+#[cfg(not(unix))]
+pub type UnixStream = TcpStream;
+
+/// A Unix domain socket listener opened with [`listen_unix`].
+#[cfg(all(unix, not(feature = "async-std")))]
+pub type UnixListener = tokio::net::UnixListener;
+#[cfg(all(unix, feature = "async-std"))]
+pub type UnixListener = async_std::os::unix::net::UnixListener;
+#[cfg(not(unix))]
+pub type UnixListener = TcpListener;
+
+/// Connect to the Unix domain socket at `path`.
+#[cfg(unix)]
+pub async fn connect_unix(path: &std::path::Path) -> std::io::Result<UnixStream> {
+    #[cfg(not(feature = "async-std"))]
+    {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        return tokio::net::UnixStream::connect(path)
+            .await
+            .map(TokioAsyncReadCompatExt::compat);
+    }
+    #[cfg(feature = "async-std")]
+    return UnixStream::connect(path).await;
+}
+
+#[cfg(not(unix))]
+pub async fn connect_unix(_: &std::path::Path) -> std::io::Result<UnixStream> {
+    Err(no_unix_sockets())
+}
+
+/// Listen for connections on a Unix domain socket at `path`. Binding doesn't wait, so a path
+/// in use is known straight away.
+#[cfg(unix)]
+pub fn listen_unix(path: &std::path::Path) -> std::io::Result<UnixListener> {
+    let listener = std::os::unix::net::UnixListener::bind(path)?;
+    listener.set_nonblocking(true)?;
+    #[cfg(not(feature = "async-std"))]
+    return UnixListener::from_std(listener);
+    #[cfg(feature = "async-std")]
+    return Ok(UnixListener::from(listener));
+}
+
+#[cfg(not(unix))]
+pub fn listen_unix(_: &std::path::Path) -> std::io::Result<UnixListener> {
+    Err(no_unix_sockets())
+}
+
+/// Take the next connection to `listener`.
+pub async fn accept_unix(listener: &UnixListener) -> std::io::Result<UnixStream> {
+    #[cfg(all(unix, not(feature = "async-std")))]
+    {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+        return listener.accept().await.map(|(stream, _)| stream.compat());
+    }
+    #[cfg(all(unix, feature = "async-std"))]
+    return listener.accept().await.map(|(stream, _)| stream);
+    #[cfg(not(unix))]
+    return accept_tcp(listener).await.map(|(stream, _)| stream);
+}
+
+#[cfg(not(unix))]
+fn no_unix_sockets() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix domain sockets aren't available on this platform",
+    )
+}
+
+/// Wait for Ctrl-C. Returns straight away if it can't be listened for.
+pub async fn ctrl_c() {
+    #[cfg(not(feature = "async-std"))]
+    let _ = tokio::signal::ctrl_c().await;
+    #[cfg(feature = "async-std")]
+    {
+        use async_signal::{Signal, Signals};
+        use libp2p::futures::StreamExt;
+
+        if let Ok(mut signals) = Signals::new([Signal::Int]) {
+            signals.next().await;
+        }
+    }
+}
@@ -0,0 +1,163 @@
+// Making text from untrusted peers safe to print to the terminal.
+use std::fmt::Write;
+
+/// Longest message body shown; the rest is cut off with a note of how much is missing.
+pub const MAX_BODY_CHARS: usize = 2000;
+
+/// Longest run of characters without a space before one is inserted, so a single endless
+/// word can still wrap.
+pub const MAX_WORD_CHARS: usize = 80;
+
+/// Longest nick shown.
+pub const MAX_NICK_CHARS: usize = 32;
+
+// OSC 8 hyperlinks: ESC ] 8 ; params ; URI ST, where ST is ESC \ or BEL
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{7}';
+
+/// A hyperlink embedded in a message, held back until the user asks for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The text the link was attached to.
+    pub text: String,
+    pub uri: String,
+}
+
+/// Whether `c` reorders or hides text: bidi controls and zero-width characters.
+pub fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{061c}'
+            | '\u{200b}'..='\u{200f}'
+            | '\u{202a}'..='\u{202e}'
+            | '\u{2060}'..='\u{2064}'
+            | '\u{2066}'..='\u{2069}'
+            | '\u{180e}'
+            | '\u{feff}'
+    )
+}
+
+/// A nick fit for display: no control, bidi or zero-width characters, and bounded in length.
+pub fn nick(nick: &str) -> String {
+    let cleaned: String = nick
+        .chars()
+        .filter(|&c| !c.is_control() && !is_invisible(c))
+        .take(MAX_NICK_CHARS)
+        .collect();
+    cleaned.trim().to_string()
+}
+
+/// A single line of untrusted text, such as a ban reason: line breaks become spaces and other
+/// control, bidi and zero-width characters are dropped.
+pub fn line(text: &str) -> String {
+    let (text, _) = body(text);
+    text
+}
+
+/// A message body fit for display, along with the hyperlinks it embedded.
+///
+/// Escape sequences are removed, so the body can neither move the cursor nor recolor the
+/// terminal. OSC 8 hyperlinks are replaced by their text and a `[link]` marker; showing them
+/// is up to the caller. Long words are broken up and long bodies truncated.
+pub fn body(text: &str) -> (String, Vec<Link>) {
+    let mut out = String::new();
+    let mut links = Vec::new();
+    let mut link: Option<(String, String)> = None;
+    let mut chars = text.chars().peekable();
+    let mut word = 0;
+    let mut shown = 0;
+    let mut total = 0;
+
+    while let Some(c) = chars.next() {
+        let c = match c {
+            ESC => {
+                match chars.next() {
+                    // Operating system command, possibly a hyperlink
+                    Some(']') => {
+                        let command = take_until_terminator(&mut chars);
+                        if let Some(rest) = command.strip_prefix("8;") {
+                            let uri = rest.split_once(';').map_or("", |(_, uri)| uri);
+                            // Any OSC 8 ends the open link; one with an empty URI only does that
+                            if let Some((uri, text)) = link.take() {
+                                out.push_str(" [link]");
+                                links.push(Link {
+                                    text: line(&text),
+                                    uri: line(&uri),
+                                });
+                            }
+                            if !uri.is_empty() {
+                                link = Some((uri.to_string(), String::new()));
+                            }
+                        }
+                    }
+                    // Control sequence: parameters up to a final byte in @..~
+                    Some('[') => {
+                        for c in chars.by_ref() {
+                            if ('@'..='~').contains(&c) {
+                                break;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            '\n' | '\r' | '\t' => ' ',
+            c if c.is_control() || is_invisible(c) => continue,
+            c => c,
+        };
+
+        total += 1;
+        if shown >= MAX_BODY_CHARS {
+            continue;
+        }
+        if c == ' ' {
+            word = 0;
+        } else if word >= MAX_WORD_CHARS {
+            out.push(' ');
+            word = 1;
+        } else {
+            word += 1;
+        }
+        out.push(c);
+        shown += 1;
+        if let Some((_, text)) = link.as_mut() {
+            text.push(c);
+        }
+    }
+    if let Some((uri, text)) = link {
+        out.push_str(" [link]");
+        links.push(Link {
+            text: line(&text),
+            uri: line(&uri),
+        });
+    }
+    if total > shown {
+        let _ = write!(out, "… ({} more characters)", total - shown);
+    }
+    (out, links)
+}
+
+/// Render a link the terminal can open (OSC 8), with its target spelled out.
+pub fn clickable(link: &Link) -> String {
+    format!(
+        "{ESC}]8;;{uri}{ESC}\\{text}{ESC}]8;;{ESC}\\ -> {uri}",
+        uri = link.uri,
+        text = link.text
+    )
+}
+
+fn take_until_terminator(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut command = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            BEL => break,
+            ESC if chars.peek() == Some(&'\\') => {
+                chars.next();
+                break;
+            }
+            c => command.push(c),
+        }
+    }
+    command
+}
@@ -0,0 +1,134 @@
+// Payloads signed with a node's identity key, verifiable by anyone who receives them.
+use std::{error::Error, fmt, marker::PhantomData};
+
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::CryptoError;
+
+/// Prefix mixed into every signature so a chat signature can't be replayed in another protocol.
+const SIGNING_PREFIX: &[u8] = b"p2p-chat-signed:";
+
+/// A JSON payload together with its author's public key and signature.
+///
+/// Gossipsub already signs whole messages, but an application level signature stays verifiable
+/// after the payload has been stored, forwarded or embedded in another message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Signed<T> {
+    // JSON encoding of the signed value, kept verbatim so the signature can be checked.
+    payload: String,
+    // Protobuf encoding of the author's public key.
+    #[serde(with = "hex")]
+    public_key: Vec<u8>,
+    // Signature over `SIGNING_PREFIX` followed by the payload.
+    #[serde(with = "hex")]
+    signature: Vec<u8>,
+    #[serde(skip)]
+    _marker: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> Signed<T> {
+    /// Serialize `value` and sign it with `keypair`.
+    pub fn sign(keypair: &Keypair, value: &T) -> Result<Self, CryptoError> {
+        let payload = serde_json::to_string(value)?;
+        let signature = keypair.sign(&signing_input(&payload))?;
+        Ok(Signed {
+            payload,
+            public_key: keypair.public().encode_protobuf(),
+            signature,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Check the signature and return the author's PeerId along with the decoded value.
+    pub fn verify(&self) -> Result<(PeerId, T), VerifyError> {
+        let public_key = PublicKey::try_decode_protobuf(&self.public_key)
+            .map_err(|_| VerifyError::InvalidPublicKey)?;
+        if !public_key.verify(&signing_input(&self.payload), &self.signature) {
+            return Err(VerifyError::BadSignature);
+        }
+        let value = serde_json::from_str(&self.payload)
+            .map_err(|e| VerifyError::InvalidPayload(e.to_string()))?;
+        Ok((public_key.to_peer_id(), value))
+    }
+
+    /// A compact binary form, for where every byte counts: the public key and the signature,
+    /// each after its length in one byte, then the payload.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for field in [&self.public_key, &self.signature] {
+            bytes.push(u8::try_from(field.len()).expect("keys and signatures are short"));
+            bytes.extend_from_slice(field);
+        }
+        bytes.extend_from_slice(self.payload.as_bytes());
+        bytes
+    }
+
+    /// Decode the form [`Signed::to_bytes`] produces. The signature is not checked here.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&key_len, rest) = bytes.split_first()?;
+        let (public_key, rest) = rest.split_at_checked(key_len.into())?;
+        let (&signature_len, rest) = rest.split_first()?;
+        let (signature, payload) = rest.split_at_checked(signature_len.into())?;
+        Some(Signed {
+            payload: String::from_utf8(payload.to_vec()).ok()?,
+            public_key: public_key.to_vec(),
+            signature: signature.to_vec(),
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// The public key a PeerId was derived from, if it is inlined in the id (Ed25519 and
+/// secp256k1 keys are, RSA keys are too long).
+pub fn peer_public_key(peer: &PeerId) -> Option<PublicKey> {
+    let multihash: &libp2p::multihash::Multihash<64> = peer.as_ref();
+    // The identity multihash (code 0) carries the protobuf encoded key itself
+    if multihash.code() != 0 {
+        return None;
+    }
+    PublicKey::try_decode_protobuf(multihash.digest()).ok()
+}
+
+/// A public key as shown to the user: its type and raw bytes in hex.
+pub fn describe_key(key: &PublicKey) -> String {
+    match key.clone().try_into_ed25519() {
+        Ok(ed25519) => format!("ed25519 {}", hex::encode(ed25519.to_bytes())),
+        Err(_) => format!(
+            "{:?} {}",
+            key.key_type(),
+            hex::encode(key.encode_protobuf())
+        ),
+    }
+}
+
+/// The exact bytes covered by a signature.
+fn signing_input(payload: &str) -> Vec<u8> {
+    [SIGNING_PREFIX, payload.as_bytes()].concat()
+}
+
+/// Reasons a signed payload can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The embedded public key could not be decoded.
+    InvalidPublicKey,
+    /// The signature does not match the payload and public key.
+    BadSignature,
+    /// The signature is valid but the payload is not the expected type.
+    InvalidPayload(String),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::InvalidPublicKey => write!(f, "invalid public key"),
+            VerifyError::BadSignature => write!(f, "signature does not match"),
+            VerifyError::InvalidPayload(e) => write!(f, "invalid signed payload: {e}"),
+        }
+    }
+}
+
+impl Error for VerifyError {}
@@ -0,0 +1,78 @@
+// Membership snapshots sent to peers joining a room, so their roster fills in right away.
+use libp2p::{
+    request_response::{self, ProtocolSupport},
+    PeerId, StreamProtocol,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::sanitize;
+
+/// Protocol name of the snapshot exchange.
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p-chat/roster-snapshot/1");
+
+/// Most members a snapshot carries; the rest of a larger one is dropped.
+pub const MAX_MEMBERS: usize = 200;
+
+/// Members expected to answer a newcomer, however many are online. Each one answers with a
+/// probability that makes this the average.
+pub const RESPONDERS: usize = 3;
+
+/// Seconds after startup during which received snapshots are applied.
+pub const ACCEPT_FOR: u64 = 60;
+
+/// Snapshots applied per session; further ones are acknowledged but ignored.
+pub const MAX_APPLIED: usize = 8;
+
+/// Request-response carrying snapshots as JSON, acknowledged with an empty response.
+pub type Behaviour = request_response::json::Behaviour<Snapshot, ()>;
+
+/// The members of a room as its sender knows them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub room: String,
+    pub members: Vec<Member>,
+}
+
+/// A member of the room, as last heard from by the sender.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub peer: PeerId,
+    pub nick: String,
+    pub away: bool,
+    /// Unix time the sender last heard from the member.
+    pub last_seen: u64,
+}
+
+impl Snapshot {
+    /// The snapshot fit to apply: at most [`MAX_MEMBERS`] members, each listed once and never
+    /// seen later than `now`, with their nicks sanitized.
+    pub fn sanitized(mut self, now: u64) -> Snapshot {
+        self.members.truncate(MAX_MEMBERS);
+        let mut seen = Vec::with_capacity(self.members.len());
+        self.members.retain(|member| {
+            let first = !seen.contains(&member.peer);
+            seen.push(member.peer);
+            first
+        });
+        for member in &mut self.members {
+            member.nick = sanitize::nick(&member.nick);
+            member.last_seen = member.last_seen.min(now);
+        }
+        self
+    }
+}
+
+/// The snapshot behaviour, answering and sending on [`PROTOCOL`].
+pub fn behaviour() -> Behaviour {
+    Behaviour::new(
+        [(PROTOCOL, ProtocolSupport::Full)],
+        request_response::Config::default(),
+    )
+}
+
+/// Whether to answer a newcomer while `online` other members are in the room, so that about
+/// [`RESPONDERS`] of them do rather than everyone.
+pub fn should_answer(online: usize) -> bool {
+    online <= RESPONDERS || rand::thread_rng().gen_ratio(RESPONDERS as u32, online as u32)
+}
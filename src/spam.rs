@@ -0,0 +1,333 @@
+// Spam flagged by the room's members together. Each report is signed by its reporter, and a
+// message reported by enough of them is hidden. The reports are kept in a Merkle tree with a
+// leaf per reported message, so a newcomer can be handed a compact proof that one was reported.
+use std::collections::{BTreeMap, VecDeque};
+
+use libp2p::{
+    gossipsub::{self, MessageId},
+    identity::Keypair,
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::CryptoError,
+    node,
+    signed::{self, VerifyError},
+};
+
+/// Reports from distinct peers it takes to hide a message, unless `--spam-threshold` says
+/// otherwise.
+pub const DEFAULT_THRESHOLD: usize = 3;
+
+/// Reported messages whose reports are kept; the first reported go first.
+pub const MAX_REPORTED: usize = 1024;
+
+/// Reports kept per message, and so carried in a proof. Past this many nobody doubts it.
+pub const MAX_REPORTS_PER_MESSAGE: usize = 64;
+
+/// Proofs a member sends a newcomer, for the messages reported last.
+pub const MAX_PROOFS_SENT: usize = 32;
+
+/// Longest path a proof may carry, from the leaf to the root.
+pub const MAX_PROOF_DEPTH: usize = 64;
+
+/// Prefix mixed into report signatures so they can't be replayed as anything else.
+const SIGNING_PREFIX: &[u8] = b"p2p-chat-spam:";
+
+// Prefixes telling leaves from inner nodes, so a leaf can't pass for a subtree
+const LEAF: u8 = 0;
+const NODE: u8 = 1;
+
+/// A SHA-256 hash in the tree.
+pub type Hash = [u8; 32];
+
+/// One peer's report of a message as spam, signed with its identity key.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SpamReport {
+    pub message_id: MessageId,
+    pub reporter_peer_id: PeerId,
+    /// Signature over [`SIGNING_PREFIX`] followed by the message id, checked against the key
+    /// inlined in the reporter's PeerId.
+    #[serde(with = "hex")]
+    pub signature: Vec<u8>,
+}
+
+impl SpamReport {
+    /// Report `message_id` as the owner of `keypair`.
+    pub fn sign(keypair: &Keypair, message_id: MessageId) -> Result<Self, CryptoError> {
+        let signature = keypair.sign(&signing_input(&message_id))?;
+        Ok(SpamReport {
+            message_id,
+            reporter_peer_id: keypair.public().to_peer_id(),
+            signature,
+        })
+    }
+
+    /// Check that the reporter signed the report.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        let key =
+            signed::peer_public_key(&self.reporter_peer_id).ok_or(VerifyError::InvalidPublicKey)?;
+        match key.verify(&signing_input(&self.message_id), &self.signature) {
+            true => Ok(()),
+            false => Err(VerifyError::BadSignature),
+        }
+    }
+}
+
+fn signing_input(message_id: &MessageId) -> Vec<u8> {
+    [SIGNING_PREFIX, &message_id.0].concat()
+}
+
+/// What goes on the spam topic.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SpamMessage {
+    /// A member reported a message.
+    Report(SpamReport),
+    /// A member vouches that a message was reported, for newcomers who missed the reports.
+    Proof(SpamProof),
+}
+
+/// A step from a node of the tree up to its parent: the sibling's hash, and which side the
+/// sibling is on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofStep {
+    #[serde(with = "hex")]
+    pub sibling: Hash,
+    pub left: bool,
+}
+
+/// Proof that a message was reported: every report of it, and the path from their leaf to the
+/// root of the sender's tree.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SpamProof {
+    pub message_id: MessageId,
+    pub reports: Vec<SpamReport>,
+    pub path: Vec<ProofStep>,
+    #[serde(with = "hex")]
+    pub root: Hash,
+}
+
+impl SpamProof {
+    /// Check that each report is of the message and signed by its reporter, that no reporter
+    /// comes twice, and that the path leads from the reports to the root. Returns how many
+    /// peers reported the message.
+    pub fn verify(&self) -> Result<usize, String> {
+        if self.reports.is_empty() || self.reports.len() > MAX_REPORTS_PER_MESSAGE {
+            return Err(format!("{} reports", self.reports.len()));
+        }
+        if self.path.len() > MAX_PROOF_DEPTH {
+            return Err(format!("a path {} long", self.path.len()));
+        }
+        for (i, report) in self.reports.iter().enumerate() {
+            if report.message_id != self.message_id {
+                return Err(format!("a report of {}", report.message_id));
+            }
+            if self.reports[..i]
+                .iter()
+                .any(|earlier| earlier.reporter_peer_id == report.reporter_peer_id)
+            {
+                return Err(format!("{} reported twice", report.reporter_peer_id));
+            }
+            report
+                .verify()
+                .map_err(|e| format!("report of {}: {e}", report.reporter_peer_id))?;
+        }
+        let mut root = leaf_hash(&self.message_id, &self.reports);
+        for step in &self.path {
+            root = match step.left {
+                true => node_hash(&step.sibling, &root),
+                false => node_hash(&root, &step.sibling),
+            };
+        }
+        match root == self.root {
+            true => Ok(self.reports.len()),
+            false => Err("the path doesn't lead to the root".to_string()),
+        }
+    }
+}
+
+// The leaf of a message: its id and every report of it, in the order they are kept.
+fn leaf_hash(message_id: &MessageId, reports: &[SpamReport]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF]);
+    hasher.update((message_id.0.len() as u64).to_be_bytes());
+    hasher.update(&message_id.0);
+    for report in reports {
+        let reporter = report.reporter_peer_id.to_bytes();
+        hasher.update((reporter.len() as u64).to_be_bytes());
+        hasher.update(&reporter);
+        hasher.update((report.signature.len() as u64).to_be_bytes());
+        hasher.update(&report.signature);
+    }
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// The level above `level`. An odd node out moves up as it is.
+fn parents(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!("chunks of two"),
+        })
+        .collect()
+}
+
+/// The spam reports of the room, by message, and the Merkle tree over them. Leaves are in
+/// message id order and each message's reports in reporter order, so peers holding the same
+/// reports have the same root.
+#[derive(Debug)]
+pub struct SpamReportStore {
+    threshold: usize,
+    reports: BTreeMap<MessageId, Vec<SpamReport>>,
+    // Reported messages, first reported first
+    order: VecDeque<MessageId>,
+}
+
+impl SpamReportStore {
+    /// A store hiding messages once `threshold` peers reported them.
+    pub fn new(threshold: usize) -> Self {
+        SpamReportStore {
+            threshold: threshold.max(1),
+            reports: BTreeMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Add a report after checking its signature. Returns whether it was new; a reporter
+    /// reporting the same message again isn't.
+    pub fn add_report(&mut self, report: SpamReport) -> Result<bool, VerifyError> {
+        report.verify()?;
+        Ok(self.insert(report))
+    }
+
+    // Add a report whose signature was checked.
+    fn insert(&mut self, report: SpamReport) -> bool {
+        if !self.reports.contains_key(&report.message_id) {
+            if self.order.len() >= MAX_REPORTED {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.reports.remove(&oldest);
+                }
+            }
+            self.order.push_back(report.message_id.clone());
+        }
+        let reports = self.reports.entry(report.message_id.clone()).or_default();
+        let at = match reports
+            .binary_search_by(|kept| kept.reporter_peer_id.cmp(&report.reporter_peer_id))
+        {
+            Ok(_) => return false,
+            Err(at) => at,
+        };
+        if reports.len() >= MAX_REPORTS_PER_MESSAGE {
+            return false;
+        }
+        reports.insert(at, report);
+        true
+    }
+
+    /// Add the reports a proof carries once it checks out, returning how many were new.
+    pub fn add_proof(&mut self, proof: SpamProof) -> Result<usize, String> {
+        proof.verify()?;
+        Ok(proof
+            .reports
+            .into_iter()
+            .filter(|report| self.insert(report.clone()))
+            .count())
+    }
+
+    /// How many peers reported `message_id`.
+    pub fn count(&self, message_id: &MessageId) -> usize {
+        self.reports.get(message_id).map_or(0, Vec::len)
+    }
+
+    /// Whether `reporter` reported `message_id`.
+    pub fn reported_by(&self, message_id: &MessageId, reporter: &PeerId) -> bool {
+        self.reports.get(message_id).is_some_and(|reports| {
+            reports
+                .iter()
+                .any(|report| report.reporter_peer_id == *reporter)
+        })
+    }
+
+    /// Whether enough peers reported `message_id` to hide it.
+    pub fn is_suppressed(&self, message_id: &MessageId) -> bool {
+        self.count(message_id) >= self.threshold
+    }
+
+    /// The hidden messages, last reported first.
+    pub fn suppressed(&self) -> impl Iterator<Item = &MessageId> {
+        self.order.iter().rev().filter(|id| self.is_suppressed(id))
+    }
+
+    // Leaves of the tree, in message id order.
+    fn leaves(&self) -> Vec<Hash> {
+        self.reports
+            .iter()
+            .map(|(id, reports)| leaf_hash(id, reports))
+            .collect()
+    }
+
+    /// The root of the tree, all zeros while nothing was reported.
+    pub fn root(&self) -> Hash {
+        let mut level = self.leaves();
+        if level.is_empty() {
+            return [0; 32];
+        }
+        while level.len() > 1 {
+            level = parents(&level);
+        }
+        level[0]
+    }
+
+    /// Proof that `message_id` was reported, with every report of it and the path to the root.
+    pub fn generate_proof(&self, message_id: &MessageId) -> Option<SpamProof> {
+        let reports = self.reports.get(message_id)?;
+        let mut index = self.reports.keys().position(|id| id == message_id)?;
+        let mut level = self.leaves();
+        let mut path = Vec::new();
+        while level.len() > 1 {
+            let sibling = index ^ 1;
+            // The odd node out has no sibling at this level
+            if let Some(hash) = level.get(sibling) {
+                path.push(ProofStep {
+                    sibling: *hash,
+                    left: sibling < index,
+                });
+            }
+            level = parents(&level);
+            index /= 2;
+        }
+        Some(SpamProof {
+            message_id: message_id.clone(),
+            reports: reports.clone(),
+            path,
+            root: level[0],
+        })
+    }
+}
+
+/// The topic that carries the spam reports of the room on `topic`.
+pub fn spam_topic_for(topic: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{topic}/_spam"))
+}
+
+/// The topic that carries the spam reports of the default chat topic.
+pub fn spam_topic() -> gossipsub::IdentTopic {
+    spam_topic_for(node::default_topic().hash().as_str())
+}
@@ -0,0 +1,179 @@
+// Detection of chat messages that are published but reach nobody, from the receipts peers send.
+use std::{
+    collections::VecDeque,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use libp2p::gossipsub::MessageId;
+use rand::Rng;
+
+/// How long a published message waits for a receipt before it counts as unconfirmed.
+pub const RECEIPT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Consecutive unconfirmed messages, despite connected peers, that count as a stall.
+pub const STALL_MESSAGES: usize = 3;
+
+/// Receivers expected to confirm each message, however many are in the room. Each one
+/// confirms with a probability that makes this the average.
+pub const RECEIPT_RESPONDERS: usize = 3;
+
+/// Most message ids a receipt carries; ids beyond it wait for the next one.
+pub const MAX_RECEIPT_IDS: usize = 64;
+
+/// Most published messages awaiting a receipt; the oldest are forgotten beyond it.
+pub const MAX_AWAITING: usize = 256;
+
+/// Whether to confirm a message while `subscribers` peers are in the room, so that about
+/// [`RECEIPT_RESPONDERS`] of them do rather than everyone.
+pub fn should_confirm(subscribers: usize) -> bool {
+    subscribers <= RECEIPT_RESPONDERS
+        || rand::thread_rng().gen_ratio(RECEIPT_RESPONDERS as u32, subscribers as u32)
+}
+
+/// Published messages awaiting a receipt, and how many in a row never got one.
+#[derive(Debug, Default)]
+pub struct StallWatch {
+    awaiting: VecDeque<(String, Instant)>,
+    unconfirmed: usize,
+    last_receipt: Option<Instant>,
+    warned: bool,
+}
+
+impl StallWatch {
+    /// Record a message published at `now`.
+    pub fn sent(&mut self, id: &MessageId, now: Instant) {
+        if self.awaiting.len() >= MAX_AWAITING {
+            self.awaiting.pop_front();
+        }
+        self.awaiting.push_back((id.to_string(), now));
+    }
+
+    /// Record a receipt for `ids` at `now`. Returns whether it confirmed any message we
+    /// published, which ends a stall.
+    pub fn confirmed(&mut self, ids: &[String], now: Instant) -> bool {
+        let before = self.awaiting.len();
+        self.awaiting.retain(|(id, _)| !ids.contains(id));
+        if self.awaiting.len() == before {
+            return false;
+        }
+        self.unconfirmed = 0;
+        self.last_receipt = Some(now);
+        self.warned = false;
+        true
+    }
+
+    /// Count the messages whose window is over at `now`. Returns true once per stall: when
+    /// [`STALL_MESSAGES`] in a row went unconfirmed while `connected` peers could have
+    /// confirmed them.
+    pub fn check(&mut self, now: Instant, connected: usize) -> bool {
+        while self
+            .awaiting
+            .front()
+            .is_some_and(|(_, sent)| now.duration_since(*sent) >= RECEIPT_WINDOW)
+        {
+            self.awaiting.pop_front();
+            self.unconfirmed += 1;
+        }
+        if connected == 0 || self.warned || self.unconfirmed < STALL_MESSAGES {
+            return false;
+        }
+        self.warned = true;
+        true
+    }
+
+    /// Messages in a row whose window ended without a receipt.
+    pub fn unconfirmed(&self) -> usize {
+        self.unconfirmed
+    }
+
+    /// Messages still within their window.
+    pub fn awaiting(&self) -> usize {
+        self.awaiting.len()
+    }
+
+    /// When a receipt last confirmed one of our messages.
+    pub fn last_receipt(&self) -> Option<Instant> {
+        self.last_receipt
+    }
+
+    /// Whether at least [`STALL_MESSAGES`] messages in a row went unconfirmed.
+    pub fn is_stalled(&self) -> bool {
+        self.unconfirmed >= STALL_MESSAGES
+    }
+}
+
+/// Why published messages might not be reaching anyone, from `/doctor publish`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishDiagnosis {
+    /// Peers with an open connection.
+    pub connected: usize,
+    /// Connected peers subscribed to the chat topic.
+    pub subscribed: usize,
+    /// Peers in our mesh for the chat topic, the ones messages are pushed to.
+    pub mesh: usize,
+    /// Mesh peers that stopped answering pings or sending heartbeats.
+    pub stale: usize,
+    /// Messages in a row whose window ended without a receipt.
+    pub unconfirmed: usize,
+    /// How long ago a receipt last confirmed one of our messages.
+    pub last_receipt: Option<Duration>,
+}
+
+impl PublishDiagnosis {
+    /// What is wrong, or none when messages look like they get through.
+    pub fn problem(&self) -> Option<String> {
+        if self.connected == 0 {
+            return Some("no peers connected".to_string());
+        }
+        if self.subscribed == 0 {
+            return Some(format!(
+                "{} peers connected but none in the room",
+                self.connected
+            ));
+        }
+        if self.mesh == 0 {
+            return Some(format!(
+                "{} peers connected but none in mesh",
+                self.connected
+            ));
+        }
+        if self.stale == self.mesh {
+            return Some(format!("all {} peers in mesh stopped answering", self.mesh));
+        }
+        if self.unconfirmed >= STALL_MESSAGES {
+            return Some(format!(
+                "{} peers in mesh but none confirmed the last {} messages",
+                self.mesh, self.unconfirmed
+            ));
+        }
+        None
+    }
+}
+
+impl fmt::Display for PublishDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "[doctor] {} peers connected, {} in the room, {} in mesh ({} stale)",
+            self.connected, self.subscribed, self.mesh, self.stale
+        )?;
+        match self.last_receipt {
+            Some(ago) => writeln!(
+                f,
+                "[doctor] {} messages unconfirmed, last receipt {}s ago",
+                self.unconfirmed,
+                ago.as_secs()
+            )?,
+            None => writeln!(
+                f,
+                "[doctor] {} messages unconfirmed, no receipts yet",
+                self.unconfirmed
+            )?,
+        }
+        match self.problem() {
+            Some(problem) => write!(f, "[doctor] messages may not be reaching anyone: {problem}"),
+            None => write!(f, "[doctor] publishing looks healthy"),
+        }
+    }
+}
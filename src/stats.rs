@@ -0,0 +1,328 @@
+// Session counters and Gossipsub diagnostics shown by `/stats`.
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    gossipsub::{MessageId, TopicHash},
+    PeerId,
+};
+use serde_json::{json, Value};
+
+/// How long message fingerprints are remembered for duplicate estimation.
+pub const DEDUP_TTL: Duration = Duration::from_secs(5 * 60);
+
+// Fingerprints are kept in buckets spanning this long, and expire a bucket at a time
+const DEDUP_BUCKET: Duration = Duration::from_secs(60);
+
+/// Remembers fingerprints of recent messages to spot the same content arriving twice.
+///
+/// Gossipsub drops messages whose id it has already seen, so this only catches copies that
+/// were published again (a new sequence number with the same author and content). Fingerprints
+/// are forgotten once older than the TTL, rounded up to the minute, so memory follows the
+/// message rate rather than a fixed count.
+#[derive(Debug)]
+pub struct TimedDedup {
+    ttl: Duration,
+    // Fingerprints by the start of the minute they arrived in
+    buckets: BTreeMap<Instant, HashSet<MessageId>>,
+}
+
+impl Default for TimedDedup {
+    fn default() -> Self {
+        TimedDedup::new(DEDUP_TTL)
+    }
+}
+
+impl TimedDedup {
+    pub fn new(ttl: Duration) -> Self {
+        TimedDedup {
+            ttl,
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// The fingerprint of `data` sent by `author`, which is the same for republished copies.
+    pub fn fingerprint(author: &PeerId, data: &[u8]) -> MessageId {
+        let mut hasher = DefaultHasher::new();
+        author.hash(&mut hasher);
+        data.hash(&mut hasher);
+        MessageId::new(&hasher.finish().to_be_bytes())
+    }
+
+    /// Remember `id` as seen at `now`, forgetting the expired ones. Returns false if it was
+    /// already remembered.
+    pub fn insert(&mut self, id: MessageId, now: Instant) -> bool {
+        self.expire(now);
+        if self.contains(&id, now) {
+            return false;
+        }
+        let bucket = match self.buckets.last_key_value() {
+            Some((&start, _)) if now < start + DEDUP_BUCKET => start,
+            _ => now,
+        };
+        self.buckets.entry(bucket).or_default().insert(id)
+    }
+
+    /// Whether `id` was seen within the TTL before `now`.
+    pub fn contains(&self, id: &MessageId, now: Instant) -> bool {
+        let live = match now.checked_sub(self.ttl + DEDUP_BUCKET) {
+            Some(cutoff) => self.buckets.range(cutoff..),
+            None => self.buckets.range(..),
+        };
+        live.into_iter().any(|(_, ids)| ids.contains(id))
+    }
+
+    /// Record a message, returning true if the same author already sent the same bytes.
+    pub fn check(&mut self, author: &PeerId, data: &[u8], now: Instant) -> bool {
+        !self.insert(Self::fingerprint(author, data), now)
+    }
+
+    /// Number of fingerprints remembered, expired ones included until the next insert.
+    pub fn len(&self) -> usize {
+        self.buckets.values().map(HashSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    // Drop the buckets whose every fingerprint is older than the TTL
+    fn expire(&mut self, now: Instant) {
+        if let Some(cutoff) = now.checked_sub(self.ttl + DEDUP_BUCKET) {
+            self.buckets = self.buckets.split_off(&cutoff);
+        }
+    }
+}
+
+/// Message counters for the current session.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SessionCounters {
+    pub published: u64,
+    pub received: u64,
+    /// Received messages whose content the dedup cache had already seen.
+    pub duplicates: u64,
+    /// Received messages that weren't UTF-8 text, shown as a preview of their bytes.
+    pub binary: u64,
+    /// Received messages dropped by `--strict-topic` for a topic we aren't subscribed to.
+    pub out_of_topic: u64,
+    /// Peers disconnected to stay below `--max-peers`.
+    pub evicted: u64,
+    /// Connections refused to peers missing from `--allowlist-file`.
+    pub not_allowlisted: u64,
+    /// TCP connections that negotiated Yamux and mplex.
+    pub yamux_connections: u64,
+    pub mplex_connections: u64,
+    /// Relayed connections replaced by a direct one through hole punching, over QUIC and TCP,
+    /// and hole punches that failed, leaving the connection on the relay.
+    pub quic_hole_punches: u64,
+    pub tcp_hole_punches: u64,
+    pub failed_hole_punches: u64,
+    /// Gossipsub payload bytes published and received; protocol overhead is not counted.
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Times input was held back because too much went out since the last tick.
+    pub input_pauses: u64,
+    /// Event loop iterations that ran longer than the watchdog allows.
+    pub slow_iterations: u64,
+    /// Chat messages published to the Nostr relay, and notes from it shown as chat.
+    pub notes_published: u64,
+    pub notes_received: u64,
+    /// Chat messages said on the IRC channel, and lines from it published to the room.
+    pub irc_sent: u64,
+    pub irc_received: u64,
+    /// Chat messages posted to the Matrix room, and messages from it published to the room.
+    pub matrix_sent: u64,
+    pub matrix_received: u64,
+    /// Chat messages said in the XMPP room, and messages and presence from it published to
+    /// the room.
+    pub xmpp_sent: u64,
+    pub xmpp_received: u64,
+    /// Chat messages published to MQTT topics, and MQTT payloads published to the room.
+    pub mqtt_sent: u64,
+    pub mqtt_received: u64,
+    /// Chat messages delivered to fediverse inboxes as notes, given up on or dropped, and
+    /// notes from the fediverse published to the room.
+    pub fediverse_sent: u64,
+    pub fediverse_failed: u64,
+    pub fediverse_received: u64,
+    /// Neighbours met over the simulated Bluetooth link, messages handed on to them, and
+    /// messages for the room taken from them.
+    pub dtn_encounters: u64,
+    pub dtn_sent: u64,
+    pub dtn_received: u64,
+    /// Messages posted to webhooks, given up on, and refused because a webhook was resting
+    /// or behind.
+    pub webhook_delivered: u64,
+    pub webhook_failed: u64,
+    pub webhook_dropped: u64,
+    /// Messages hooks posted over HTTP, and requests turned away.
+    pub hook_posted: u64,
+    pub hook_rejected: u64,
+}
+
+/// Mesh state of one subscribed topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicStats {
+    pub topic: TopicHash,
+    /// Peers in our mesh for the topic, which receive full messages from us.
+    pub mesh_peers: usize,
+    /// All known peers subscribed to the topic.
+    pub subscribed_peers: usize,
+}
+
+/// Snapshot of the node's Gossipsub state, as printed by `/stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkStats {
+    pub topics: Vec<TopicStats>,
+    pub counters: SessionCounters,
+    /// Peer scores, empty unless peer scoring is enabled.
+    pub peer_scores: Vec<(PeerId, f64)>,
+}
+
+impl fmt::Display for NetworkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[stats] subscribed topics: {}", self.topics.len())?;
+        for topic in &self.topics {
+            writeln!(
+                f,
+                "[stats]   {}: {} mesh peers, {} subscribed peers",
+                topic.topic, topic.mesh_peers, topic.subscribed_peers
+            )?;
+        }
+        let counters = &self.counters;
+        writeln!(
+            f,
+            "[stats] messages published: {}, received: {}, estimated duplicates: {}, \
+             out of topic: {}, binary: {}",
+            counters.published,
+            counters.received,
+            counters.duplicates,
+            counters.out_of_topic,
+            counters.binary
+        )?;
+        writeln!(
+            f,
+            "[stats] payload bytes sent: {}, received: {}, input paused at the high-water \
+             mark: {}, slow event loop iterations: {}",
+            counters.bytes_sent,
+            counters.bytes_received,
+            counters.input_pauses,
+            counters.slow_iterations
+        )?;
+        writeln!(
+            f,
+            "[stats] peers disconnected to make room: {}, kept out by the allowlist: {}",
+            counters.evicted, counters.not_allowlisted
+        )?;
+        writeln!(
+            f,
+            "[stats] tcp connections over yamux: {}, mplex: {}",
+            counters.yamux_connections, counters.mplex_connections
+        )?;
+        writeln!(
+            f,
+            "[stats] hole punches over quic: {}, tcp: {}, failed: {}",
+            counters.quic_hole_punches, counters.tcp_hole_punches, counters.failed_hole_punches
+        )?;
+        writeln!(
+            f,
+            "[stats] nostr notes published: {}, received: {}",
+            counters.notes_published, counters.notes_received
+        )?;
+        writeln!(
+            f,
+            "[stats] irc messages sent: {}, received: {}",
+            counters.irc_sent, counters.irc_received
+        )?;
+        writeln!(
+            f,
+            "[stats] matrix messages sent: {}, received: {}",
+            counters.matrix_sent, counters.matrix_received
+        )?;
+        writeln!(
+            f,
+            "[stats] xmpp messages sent: {}, received: {}",
+            counters.xmpp_sent, counters.xmpp_received
+        )?;
+        writeln!(
+            f,
+            "[stats] mqtt messages sent: {}, received: {}",
+            counters.mqtt_sent, counters.mqtt_received
+        )?;
+        writeln!(
+            f,
+            "[stats] fediverse notes sent: {}, failed: {}, received: {}",
+            counters.fediverse_sent, counters.fediverse_failed, counters.fediverse_received
+        )?;
+        writeln!(
+            f,
+            "[stats] dtn encounters: {}, messages handed on: {}, received: {}",
+            counters.dtn_encounters, counters.dtn_sent, counters.dtn_received
+        )?;
+        writeln!(
+            f,
+            "[stats] webhook messages delivered: {}, failed: {}, dropped: {}",
+            counters.webhook_delivered, counters.webhook_failed, counters.webhook_dropped
+        )?;
+        writeln!(
+            f,
+            "[stats] hook posts published: {}, refused: {}",
+            counters.hook_posted, counters.hook_rejected
+        )?;
+        // libp2p-gossipsub 0.47 keeps its per-peer send queues private
+        writeln!(f, "[stats] queue depth: not exposed by gossipsub")?;
+        if self.peer_scores.is_empty() {
+            write!(f, "[stats] peer scores: scoring disabled")?;
+        } else {
+            write!(f, "[stats] peer scores:")?;
+            for (peer, score) in &self.peer_scores {
+                write!(f, "\n[stats]   {peer}: {score:.2}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the node is listening and connected, for readiness checks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthStatus {
+    /// The node has at least one listen address.
+    pub listening: bool,
+    /// Peers with an open connection.
+    pub peer_count: usize,
+    /// Mesh peers of every subscribed topic, by topic name.
+    pub mesh_peer_count_per_topic: HashMap<String, usize>,
+    /// Gossipsub payload bytes received and sent this session.
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    /// Time since the node was created.
+    pub uptime: Duration,
+    /// When the last Gossipsub message arrived, if any did.
+    pub last_message_received: Option<Instant>,
+}
+
+impl HealthStatus {
+    /// A node is ready when it listens and has at least one peer; otherwise it is isolated.
+    pub fn is_ready(&self) -> bool {
+        self.listening && self.peer_count > 0
+    }
+
+    /// The status as JSON, as `GET /health` serves it. Times are in seconds, the last message's
+    /// as how long ago it arrived.
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ready": self.is_ready(),
+            "listening": self.listening,
+            "peer_count": self.peer_count,
+            "mesh_peer_count_per_topic": self.mesh_peer_count_per_topic,
+            "bytes_received": self.bytes_received,
+            "bytes_sent": self.bytes_sent,
+            "uptime": self.uptime.as_secs(),
+            "last_message_received": self.last_message_received.map(|at| at.elapsed().as_secs()),
+        })
+    }
+}
@@ -0,0 +1,127 @@
+// The JSON lines spoken on stdin and stdout with `--io json`, for programs that run the node as a
+// subprocess instead of a person typing at it.
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{message::StoredMessage, roster::RosterEvent};
+
+/// A command read from stdin as a JSON object, named by its `command` field. The object may also
+/// carry an `id` of any JSON type, echoed on the reply so replies can be matched to commands.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ChatCommand {
+    /// Send `text` to the room as a chat message. Slash commands in it aren't run.
+    Publish { text: String },
+    /// Join the invite-only room with a token from `/invite create`, like `--join-with`.
+    Join { invite: String },
+    /// Dial `address`, replying once connected or once the dial failed.
+    Dial { address: Multiaddr },
+    /// Ask for the node's state, answered in the reply's `result`.
+    Query { what: Query },
+}
+
+/// What a [`ChatCommand::Query`] asks for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Query {
+    /// The peers seen in the room, as `/peers` lists them.
+    Peers,
+    /// The messages received, oldest first, as `message` events.
+    History,
+    /// Connections, mesh peers and traffic, as the health check sees them.
+    Stats,
+}
+
+/// An event written to stdout as a JSON object, named by its `event` field.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChatEvent {
+    /// The node runs, as `peer` with `nick` in `room`.
+    Ready {
+        peer: PeerId,
+        nick: String,
+        room: String,
+    },
+    /// The node listens on a new address, which peers can dial.
+    Listening { address: Multiaddr },
+    /// The first connection to a peer opened.
+    Connected { peer: PeerId },
+    /// The last connection to a peer closed.
+    Disconnected { peer: PeerId },
+    /// A peer came online in a room.
+    Joined { room: String, peer: PeerId },
+    /// A peer left a room or stopped being heard from.
+    Left { room: String, peer: PeerId },
+    /// A peer in a room went away.
+    Away { room: String, peer: PeerId },
+    /// A peer in a room came back.
+    Back { room: String, peer: PeerId },
+    /// A chat message arrived. `peer` is its author, or null when it wasn't signed.
+    Message {
+        id: String,
+        room: String,
+        peer: Option<PeerId>,
+        nick: String,
+        text: String,
+        timestamp: u64,
+    },
+    /// A command was carried out, with what a query asked for.
+    Done {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+    },
+    /// A line was not a command, or the command failed.
+    Error {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<Value>,
+        error: String,
+    },
+}
+
+impl ChatEvent {
+    /// The event for a received message.
+    pub fn message(stored: &StoredMessage) -> Self {
+        ChatEvent::Message {
+            id: stored.id.clone(),
+            room: stored.topic.clone(),
+            peer: stored.source,
+            nick: stored.message.nick.clone(),
+            text: stored.message.body.to_string(),
+            timestamp: stored.message.timestamp,
+        }
+    }
+
+    /// The line written to stdout for the event.
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).expect("events always serialize")
+    }
+}
+
+impl From<RosterEvent> for ChatEvent {
+    fn from(event: RosterEvent) -> Self {
+        match event {
+            RosterEvent::Joined { room, peer } => ChatEvent::Joined { room, peer },
+            RosterEvent::Left { room, peer } => ChatEvent::Left { room, peer },
+            RosterEvent::Away { room, peer } => ChatEvent::Away { room, peer },
+            RosterEvent::Back { room, peer } => ChatEvent::Back { room, peer },
+        }
+    }
+}
+
+/// Parse a line of stdin into the command's `id`, when one could be read, and the command, or
+/// why the line isn't one.
+pub fn parse(line: &str) -> (Option<Value>, Result<ChatCommand, String>) {
+    let mut value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return (None, Err(format!("not JSON: {e}"))),
+    };
+    let Some(object) = value.as_object_mut() else {
+        return (None, Err("not a JSON object".to_string()));
+    };
+    let id = object.remove("id");
+    let command = serde_json::from_value(value).map_err(|e| format!("not a command: {e}"));
+    (id, command)
+}
@@ -0,0 +1,297 @@
+// A room's shared task list, kept as a CRDT so concurrent edits merge without conflicts.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use libp2p::{gossipsub, PeerId};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{error::ConfigError, node, sanitize, signed::Signed};
+
+/// Most tasks kept, removed ones included; deltas adding more are dropped.
+pub const MAX_TASKS: usize = 500;
+
+/// Longest task title, in characters.
+pub const MAX_TASK_TITLE_CHARS: usize = 120;
+
+/// Every delta is signed by the node that published it.
+pub type SignedTasks = Signed<TaskList>;
+
+/// A task as shown in `/task list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    pub id: Uuid,
+    pub title: String,
+    pub assignee: Option<String>,
+    pub done: bool,
+}
+
+/// When a value was written and by whom. Later stamps win, and the peer breaks ties.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    pub at: u64,
+    pub peer: PeerId,
+}
+
+/// A value where the last write wins.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Register<T> {
+    pub value: T,
+    /// `None` until the value is first written.
+    pub stamp: Option<Stamp>,
+}
+
+impl<T: Clone> Register<T> {
+    // Write `value` as `peer`, stamped after `now` and after the current value, so a write
+    // made after seeing another always wins even if clocks disagree.
+    fn write(&mut self, value: T, peer: PeerId, now: u64) {
+        let at = self.stamp.map_or(now, |stamp| now.max(stamp.at + 1));
+        self.value = value;
+        self.stamp = Some(Stamp { at, peer });
+    }
+
+    fn merge(&mut self, other: &Register<T>) -> bool {
+        if other.stamp > self.stamp {
+            *self = other.clone();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// A task in the set: the tags of its adds, with its fields.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+struct Entry {
+    title: String,
+    created_at: u64,
+    tags: BTreeSet<Uuid>,
+    assignee: Register<Option<String>>,
+    done: Register<bool>,
+}
+
+/// The tasks of a room as an observed-remove set: each add is tagged, and a remove only
+/// tombstones the tags it has seen, so a concurrent add survives it. Whether a task is done
+/// and who it is assigned to are last-writer-wins registers.
+///
+/// A delta is a task list holding just what changed, and merging is idempotent, commutative
+/// and associative, so every peer ends up with the same list once it has every delta, in
+/// whatever order they came.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskList {
+    #[serde(default)]
+    entries: BTreeMap<Uuid, Entry>,
+    #[serde(default)]
+    removed: BTreeSet<Uuid>,
+}
+
+impl TaskList {
+    /// Add a task, returning its id and the delta to publish.
+    pub fn add(&mut self, title: &str, now: u64) -> (Uuid, TaskList) {
+        let id = Uuid::new_v4();
+        let entry = Entry {
+            title: title.to_string(),
+            created_at: now,
+            tags: BTreeSet::from([Uuid::new_v4()]),
+            ..Entry::default()
+        };
+        let delta = TaskList::single(id, entry, BTreeSet::new());
+        self.merge(&delta);
+        (id, delta)
+    }
+
+    /// Remove task `id`, returning the delta to publish. `None` if there is no such task.
+    pub fn remove(&mut self, id: &Uuid) -> Option<TaskList> {
+        let tags = self.live_tags(id)?;
+        let delta = TaskList {
+            entries: BTreeMap::new(),
+            removed: tags,
+        };
+        self.merge(&delta);
+        Some(delta)
+    }
+
+    /// Mark task `id` done or not done as `peer`, returning the delta to publish.
+    pub fn set_done(&mut self, id: &Uuid, done: bool, peer: PeerId, now: u64) -> Option<TaskList> {
+        self.live_tags(id)?;
+        let mut entry = Entry {
+            done: self.entries[id].done.clone(),
+            ..Entry::default()
+        };
+        entry.done.write(done, peer, now);
+        let delta = TaskList::single(*id, entry, BTreeSet::new());
+        self.merge(&delta);
+        Some(delta)
+    }
+
+    /// Assign task `id` to `assignee`, or nobody, as `peer`, returning the delta to publish.
+    pub fn assign(
+        &mut self,
+        id: &Uuid,
+        assignee: Option<&str>,
+        peer: PeerId,
+        now: u64,
+    ) -> Option<TaskList> {
+        self.live_tags(id)?;
+        let mut entry = Entry {
+            assignee: self.entries[id].assignee.clone(),
+            ..Entry::default()
+        };
+        entry
+            .assignee
+            .write(assignee.map(sanitize::nick), peer, now);
+        let delta = TaskList::single(*id, entry, BTreeSet::new());
+        self.merge(&delta);
+        Some(delta)
+    }
+
+    /// Merge a delta, or another peer's whole list. Returns whether anything changed.
+    /// Entries beyond [`MAX_TASKS`] are dropped.
+    pub fn merge(&mut self, other: &TaskList) -> bool {
+        let mut changed = false;
+        for tag in &other.removed {
+            changed |= self.removed.insert(*tag);
+        }
+        for (id, theirs) in &other.entries {
+            if !self.entries.contains_key(id) && self.entries.len() >= MAX_TASKS {
+                continue;
+            }
+            let ours = self.entries.entry(*id).or_default();
+            if ours.title.is_empty() && !theirs.title.is_empty() {
+                ours.title = sanitize::line(&theirs.title)
+                    .chars()
+                    .take(MAX_TASK_TITLE_CHARS)
+                    .collect();
+                ours.created_at = theirs.created_at;
+                changed = true;
+            }
+            for tag in &theirs.tags {
+                changed |= ours.tags.insert(*tag);
+            }
+            changed |= ours.done.merge(&theirs.done);
+            if ours.assignee.merge(&theirs.assignee) {
+                ours.assignee.value = ours.assignee.value.as_deref().map(sanitize::nick);
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// The tasks in the list, oldest first.
+    pub fn tasks(&self) -> Vec<Task> {
+        let mut entries: Vec<(&Uuid, &Entry)> = self
+            .entries
+            .iter()
+            .filter(|(id, entry)| !entry.title.is_empty() && self.live_tags(id).is_some())
+            .collect();
+        entries.sort_by_key(|(id, entry)| (entry.created_at, **id));
+        entries
+            .into_iter()
+            .map(|(id, entry)| Task {
+                id: *id,
+                title: entry.title.clone(),
+                assignee: entry.assignee.value.clone(),
+                done: entry.done.value,
+            })
+            .collect()
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<Task> {
+        self.tasks().into_iter().find(|task| task.id == *id)
+    }
+
+    /// The task whose id starts with `prefix`, if exactly one does.
+    pub fn find(&self, prefix: &str) -> Result<Task, String> {
+        let prefix = prefix.to_lowercase();
+        let mut matches = self
+            .tasks()
+            .into_iter()
+            .filter(|task| task.id.to_string().starts_with(&prefix));
+        match (matches.next(), matches.next()) {
+            (Some(task), None) => Ok(task),
+            (None, _) => Err(format!("no task {prefix}")),
+            (Some(_), Some(_)) => Err(format!("more than one task starts with {prefix}")),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.removed.is_empty()
+    }
+
+    fn single(id: Uuid, entry: Entry, removed: BTreeSet<Uuid>) -> TaskList {
+        TaskList {
+            entries: BTreeMap::from([(id, entry)]),
+            removed,
+        }
+    }
+
+    // The tags of task `id` that haven't been removed, if any are left.
+    fn live_tags(&self, id: &Uuid) -> Option<BTreeSet<Uuid>> {
+        let tags: BTreeSet<Uuid> = self
+            .entries
+            .get(id)?
+            .tags
+            .difference(&self.removed)
+            .copied()
+            .collect();
+        (!tags.is_empty()).then_some(tags)
+    }
+}
+
+/// The topic that carries the task list of the room on `topic`.
+pub fn tasks_topic_for(topic: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{topic}/_tasks"))
+}
+
+/// The topic that carries the task list of the default chat topic.
+pub fn tasks_topic() -> gossipsub::IdentTopic {
+    tasks_topic_for(node::default_topic().hash().as_str())
+}
+
+/// The file kept next to the config file with the task lists of every room: `config.json`
+/// keeps them in `config.tasks.json`.
+pub fn path_beside(config_path: &Path) -> PathBuf {
+    config_path.with_extension("tasks.json")
+}
+
+/// The task list of `room` saved at `path`, or an empty one if there is none yet.
+pub fn load(path: &Path, room: &str) -> Result<TaskList, ConfigError> {
+    let mut lists = read_lists(path)?;
+    let mut tasks = TaskList::default();
+    // Merged rather than taken as is, so saved titles and nicks are checked like received ones
+    if let Some(saved) = lists.remove(room) {
+        tasks.merge(&saved);
+    }
+    Ok(tasks)
+}
+
+/// Save the task list of `room` at `path`, keeping the lists of other rooms there.
+pub fn save(path: &Path, room: &str, tasks: &TaskList) -> Result<(), ConfigError> {
+    let mut lists = read_lists(path)?;
+    lists.insert(room.to_string(), tasks.clone());
+    let contents = serde_json::to_string_pretty(&lists).expect("task lists serialize");
+    path.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(path, contents))
+        .map_err(|source| ConfigError::Write {
+            path: path.to_path_buf(),
+            source,
+        })
+}
+
+fn read_lists(path: &Path) -> Result<BTreeMap<String, TaskList>, ConfigError> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(source) => Err(ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        }),
+    }
+}
@@ -0,0 +1,39 @@
+// TLS for the connections bridges open to servers outside the swarm, checked against the
+// Mozilla root certificates.
+use std::sync::Arc;
+
+use futures_rustls::{
+    client::TlsStream,
+    rustls::{self, pki_types},
+    TlsConnector,
+};
+
+use crate::runtime;
+
+/// Start TLS on `tcp`, a connection to `host`, checking the server's certificate for it.
+pub async fn connect(
+    host: &str,
+    tcp: runtime::TcpStream,
+) -> Result<TlsStream<runtime::TcpStream>, String> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(
+        webpki_roots::TLS_SERVER_ROOTS
+            .iter()
+            .map(|anchor| pki_types::TrustAnchor {
+                subject: anchor.subject.into(),
+                subject_public_key_info: anchor.spki.into(),
+                name_constraints: anchor.name_constraints.map(Into::into),
+            }),
+    );
+    let provider = rustls::crypto::ring::default_provider();
+    let config = rustls::ClientConfig::builder_with_provider(provider.into())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = pki_types::ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,104 @@
+// Aliases a room's chat messages travel under, so a network blocking the room's topic name
+// doesn't see them. A moderator of the room picks a random alias and announces it, signed, on
+// the control topic; members move to it and keep using the room's own name everywhere else.
+use std::collections::BTreeMap;
+
+use libp2p::gossipsub::TopicHash;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+/// Seconds a moderator keeps an alias before rotating it.
+pub const ROTATION_INTERVAL: u64 = 24 * 60 * 60;
+
+/// Random bytes in an alias, written as twice as many hex digits.
+pub const ALIAS_BYTES: usize = 6;
+
+/// Seconds an alias may be dated ahead of our clock.
+pub const MAX_CLOCK_SKEW: u64 = 5 * 60;
+
+/// The chat topic of `room` travels under `alias` since `issued_at`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TopicAlias {
+    /// Name of the room's topic.
+    pub room: String,
+    /// Hex digits replacing the last part of the room's topic name.
+    pub alias: String,
+    /// Unix time (seconds) at which the moderator picked the alias.
+    pub issued_at: u64,
+}
+
+impl TopicAlias {
+    /// A fresh random alias for `room`, picked at `now`.
+    pub fn generate(room: &str, now: u64) -> Self {
+        let mut bytes = [0u8; ALIAS_BYTES];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        TopicAlias {
+            room: room.to_string(),
+            alias: hex::encode(bytes),
+            issued_at: now,
+        }
+    }
+
+    /// Check an alias received at `now`.
+    pub fn validate(&self, now: u64) -> Result<(), String> {
+        let hex = |c: char| c.is_ascii_digit() || ('a'..='f').contains(&c);
+        if self.alias.len() != 2 * ALIAS_BYTES || !self.alias.chars().all(hex) {
+            return Err(format!(
+                "an alias is {} lowercase hex digits",
+                2 * ALIAS_BYTES
+            ));
+        }
+        if self.issued_at > now + MAX_CLOCK_SKEW {
+            return Err("the alias is dated in the future".to_string());
+        }
+        Ok(())
+    }
+
+    /// The topic the room's chat messages travel under: the room's topic name with its last
+    /// part replaced by the alias, so the deployment's prefix stays.
+    pub fn topic(&self) -> TopicHash {
+        let name = match self.room.rsplit_once('/') {
+            Some((prefix, _)) => format!("{prefix}/{}", self.alias),
+            None => self.alias.clone(),
+        };
+        TopicHash::from_raw(name)
+    }
+}
+
+/// The newest alias of each room, kept in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct AliasResolver(BTreeMap<String, TopicAlias>);
+
+impl AliasResolver {
+    /// The topic the room named `name` is carried on: its alias, or its own name without one.
+    pub fn resolve(&self, name: &str) -> TopicHash {
+        self.0
+            .get(name)
+            .map_or_else(|| TopicHash::from_raw(name), TopicAlias::topic)
+    }
+
+    /// The alias of `room`, if it has one.
+    pub fn get(&self, room: &str) -> Option<&TopicAlias> {
+        self.0.get(room)
+    }
+
+    /// Keep `alias` unless its room has a newer one. Of two picked in the same second, the
+    /// greater alias wins, so every member settles on the same one. Returns whether it was kept.
+    pub fn learn(&mut self, alias: TopicAlias) -> bool {
+        if self
+            .0
+            .get(&alias.room)
+            .is_some_and(|known| (known.issued_at, &known.alias) >= (alias.issued_at, &alias.alias))
+        {
+            return false;
+        }
+        self.0.insert(alias.room.clone(), alias);
+        true
+    }
+
+    /// Whether no room has an alias.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
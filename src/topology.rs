@@ -0,0 +1,86 @@
+// The network as this node sees it, written out as a Graphviz graph for `/export-topology`.
+use std::fmt::Write;
+
+use libp2p::PeerId;
+
+/// How this node is connected to a peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    /// In the Gossipsub mesh of one of our topics, so full messages flow over the connection.
+    Mesh,
+    /// Connected outside the mesh, so only gossip about message ids and subscriptions flows.
+    /// The chat subscribes to every topic it publishes on, so it has no fanout peers; they
+    /// would show up here.
+    Metadata,
+}
+
+/// A peer in the topology.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopologyPeer {
+    pub peer: PeerId,
+    /// The name the peer is shown by, as in `/peers`.
+    pub name: String,
+    /// Gossipsub score, when scoring is on.
+    pub score: Option<f64>,
+    /// How we are connected to the peer. Peers known from their messages may not be connected
+    /// at all.
+    pub link: Option<Link>,
+}
+
+/// This node and the peers it knows of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Topology {
+    pub local: TopologyPeer,
+    pub peers: Vec<TopologyPeer>,
+}
+
+impl Topology {
+    /// The topology as a Graphviz DOT graph, to render with `dot -Tsvg`. Mesh links are solid
+    /// and other connections dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("graph topology {\n    node [shape=box];\n");
+        let _ = writeln!(
+            dot,
+            "    \"{}\" [label=\"{}\", style=bold];",
+            self.local.peer,
+            label(&self.local)
+        );
+        for peer in &self.peers {
+            let _ = writeln!(dot, "    \"{}\" [label=\"{}\"];", peer.peer, label(peer));
+        }
+        for peer in &self.peers {
+            let style = match peer.link {
+                Some(Link::Mesh) => "solid",
+                Some(Link::Metadata) => "dashed",
+                None => continue,
+            };
+            let _ = writeln!(
+                dot,
+                "    \"{}\" -- \"{}\" [style={style}];",
+                self.local.peer, peer.peer
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// A node's label: its name, and its score when it has one.
+fn label(peer: &TopologyPeer) -> String {
+    let name = escape(&peer.name);
+    match peer.score {
+        Some(score) => format!("{name}\\nscore {score:.2}"),
+        None => name,
+    }
+}
+
+// Quote a name for a DOT string. Names come from peers, so nothing in them may end the string.
+fn escape(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control())
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
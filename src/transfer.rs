@@ -0,0 +1,391 @@
+// Resumable fetches of attachments: content is written to a partial file chunk by chunk, next
+// to a manifest of the ranges already there, so an interrupted fetch picks up where it stopped.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::attachment::{AttachmentRef, Cid, CHUNK_BYTES};
+
+/// Chunks requested from the sender at once.
+pub const MAX_IN_FLIGHT: usize = 4;
+
+/// Byte ranges of a file, as sorted half-open `(start, end)` pairs that neither overlap nor
+/// touch.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(from = "Vec<(u64, u64)>")]
+pub struct Ranges(Vec<(u64, u64)>);
+
+// Ranges read back from a manifest are merged again, so a damaged one keeps them sorted and
+// apart, and empty or reversed ones are dropped
+impl From<Vec<(u64, u64)>> for Ranges {
+    fn from(pairs: Vec<(u64, u64)>) -> Self {
+        let mut ranges = Ranges::default();
+        for (start, end) in pairs {
+            ranges.insert(start, end);
+        }
+        ranges
+    }
+}
+
+impl Ranges {
+    /// Add the bytes from `start` up to `end`, merging with the ranges they overlap or touch.
+    pub fn insert(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+        let (mut start, mut end) = (start, end);
+        self.0.retain(|&(s, e)| {
+            if e < start || s > end {
+                return true;
+            }
+            start = start.min(s);
+            end = end.max(e);
+            false
+        });
+        let at = self.0.partition_point(|&(s, _)| s < start);
+        self.0.insert(at, (start, end));
+    }
+
+    /// Whether every byte from `start` up to `end` is in a range.
+    pub fn contains(&self, start: u64, end: u64) -> bool {
+        self.0.iter().any(|&(s, e)| s <= start && end <= e)
+    }
+
+    /// Bytes in the ranges.
+    pub fn covered(&self) -> u64 {
+        self.0.iter().map(|(s, e)| e - s).sum()
+    }
+
+    /// The chunks of a file of `size` bytes that aren't all in a range, as `(offset, len)`.
+    pub fn missing(&self, size: u64) -> impl Iterator<Item = (u64, u64)> + '_ {
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            while offset < size {
+                let len = CHUNK_BYTES.min(size - offset);
+                let range = self
+                    .0
+                    .iter()
+                    .find(|&&(s, e)| s <= offset && offset + len <= e);
+                match range {
+                    Some(&(_, end)) if end >= size => offset = size,
+                    // Skip the chunks the range holds at once, up to the one its end falls in
+                    Some(&(_, end)) => offset = end - end % CHUNK_BYTES,
+                    None => {
+                        offset += len;
+                        return Some((offset - len, len));
+                    }
+                }
+            }
+            None
+        })
+    }
+}
+
+/// What is saved beside a partial file: what it will be once complete, who has it and what
+/// has arrived so far.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub attachment: AttachmentRef,
+    /// The peer the chunks are fetched from: the sender of the latest offer.
+    pub sender: PeerId,
+    /// Where the file goes once complete.
+    pub destination: PathBuf,
+    pub done: Ranges,
+}
+
+/// Whether chunks of a transfer are being fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Active,
+    /// Stopped after a request failed, or from a previous run, until the sender reconnects or
+    /// the file is offered or fetched again. The partial file is kept.
+    Parked,
+}
+
+/// A fetch of an attachment in progress.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub manifest: Manifest,
+    pub state: State,
+    // Offsets of the chunks requested and not answered yet
+    in_flight: BTreeSet<u64>,
+}
+
+impl Transfer {
+    pub fn new(manifest: Manifest, state: State) -> Self {
+        Transfer {
+            manifest,
+            state,
+            in_flight: BTreeSet::new(),
+        }
+    }
+
+    /// Bytes received so far.
+    pub fn received(&self) -> u64 {
+        self.manifest.done.covered()
+    }
+
+    /// Whether every chunk has arrived.
+    pub fn is_complete(&self) -> bool {
+        self.manifest
+            .done
+            .missing(self.manifest.attachment.size)
+            .next()
+            .is_none()
+    }
+
+    /// Chunks to request now, as `(offset, len)`, keeping at most [`MAX_IN_FLIGHT`] requested.
+    /// They count as requested from here on. None while parked.
+    pub fn next_chunks(&mut self) -> Vec<(u64, u64)> {
+        if self.state == State::Parked {
+            return Vec::new();
+        }
+        let room = MAX_IN_FLIGHT.saturating_sub(self.in_flight.len());
+        let chunks: Vec<(u64, u64)> = self
+            .manifest
+            .done
+            .missing(self.manifest.attachment.size)
+            .filter(|(offset, _)| !self.in_flight.contains(offset))
+            .take(room)
+            .collect();
+        self.in_flight
+            .extend(chunks.iter().map(|&(offset, _)| offset));
+        chunks
+    }
+
+    /// Record the chunk at `offset` as arrived, `len` bytes long.
+    pub fn arrived(&mut self, offset: u64, len: u64) {
+        self.in_flight.remove(&offset);
+        self.manifest.done.insert(offset, offset + len);
+    }
+
+    /// Park the transfer, forgetting the chunks requested: any that still arrive are taken
+    /// all the same. Returns whether it was active.
+    pub fn park(&mut self) -> bool {
+        self.in_flight.clear();
+        std::mem::replace(&mut self.state, State::Parked) == State::Active
+    }
+
+    /// Chunks requested and not answered yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+/// The transfers of a node, with their partial files in a directory of their own.
+#[derive(Debug)]
+pub struct Transfers {
+    dir: PathBuf,
+    transfers: BTreeMap<Cid, Transfer>,
+}
+
+impl Transfers {
+    /// The transfers whose manifests are in `dir`, all parked, as left by a previous run.
+    /// Manifests that can't be read are skipped.
+    pub fn load(dir: PathBuf) -> Self {
+        let mut transfers = BTreeMap::new();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Transfers { dir, transfers },
+            Err(e) => {
+                warn!("[transfer] can't read {}: {e}", dir.display());
+                return Transfers { dir, transfers };
+            }
+        };
+        for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let manifest = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| {
+                    serde_json::from_str::<Manifest>(&text).map_err(|e| e.to_string())
+                });
+            match manifest {
+                Ok(manifest) => {
+                    let cid = manifest.attachment.cid;
+                    transfers.insert(cid, Transfer::new(manifest, State::Parked));
+                }
+                Err(e) => warn!("[transfer] skipped {}: {e}", path.display()),
+            }
+        }
+        Transfers { dir, transfers }
+    }
+
+    /// The directory holding partial files and manifests.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The partial file of the content with id `cid`.
+    pub fn part_path(&self, cid: &Cid) -> PathBuf {
+        self.dir.join(format!("{cid}.part"))
+    }
+
+    /// The manifest of the content with id `cid`.
+    pub fn manifest_path(&self, cid: &Cid) -> PathBuf {
+        self.dir.join(format!("{cid}.json"))
+    }
+
+    /// Start fetching `attachment` from `sender` into `destination`, or resume the transfer
+    /// of the same content with them.
+    pub fn start(
+        &mut self,
+        attachment: AttachmentRef,
+        sender: PeerId,
+        destination: PathBuf,
+    ) -> &mut Transfer {
+        let transfer = self.transfers.entry(attachment.cid).or_insert_with(|| {
+            Transfer::new(
+                Manifest {
+                    attachment,
+                    sender,
+                    destination: destination.clone(),
+                    done: Ranges::default(),
+                },
+                State::Active,
+            )
+        });
+        transfer.manifest.sender = sender;
+        transfer.manifest.destination = destination;
+        transfer.state = State::Active;
+        transfer
+    }
+
+    pub fn get(&self, cid: &Cid) -> Option<&Transfer> {
+        self.transfers.get(cid)
+    }
+
+    pub fn get_mut(&mut self, cid: &Cid) -> Option<&mut Transfer> {
+        self.transfers.get_mut(cid)
+    }
+
+    pub fn remove(&mut self, cid: &Cid) -> Option<Transfer> {
+        self.transfers.remove(cid)
+    }
+
+    /// The transfers, by content id.
+    pub fn iter(&self) -> impl Iterator<Item = (&Cid, &Transfer)> {
+        self.transfers.iter()
+    }
+
+    /// The parked transfers fetching from `peer`.
+    pub fn parked_from(&self, peer: &PeerId) -> Vec<Cid> {
+        self.transfers
+            .iter()
+            .filter(|(_, transfer)| {
+                transfer.state == State::Parked && transfer.manifest.sender == *peer
+            })
+            .map(|(cid, _)| *cid)
+            .collect()
+    }
+
+    /// The transfer whose content id starts with `prefix`, if exactly one does.
+    pub fn find(&self, prefix: &str) -> Result<Cid, String> {
+        let prefix = prefix.to_lowercase();
+        let mut matches = self
+            .transfers
+            .keys()
+            .filter(|cid| cid.to_string().starts_with(&prefix));
+        match (matches.next(), matches.next()) {
+            (Some(cid), None) => Ok(*cid),
+            (None, _) => Err(format!("no transfer {prefix}")),
+            (Some(_), Some(_)) => Err(format!("more than one transfer starts with {prefix}")),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.transfers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transfers.is_empty()
+    }
+}
+
+/// The directory kept next to the config file for partial fetches: `config.json` keeps them
+/// in `config.transfers`.
+pub fn dir_beside(config_path: &Path) -> PathBuf {
+    config_path.with_extension("transfers")
+}
+
+/// Write a chunk into the partial file at `part`, creating it and its directory if needed.
+pub fn write_chunk(part: &Path, offset: u64, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = part.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(part)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(data)
+}
+
+/// Save `manifest` at `path`. Written after the chunks it lists, so it never claims more than
+/// the partial file holds.
+pub fn save_manifest(path: &Path, manifest: &Manifest) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let contents = serde_json::to_string_pretty(manifest).expect("manifests serialize");
+    let temporary = path.with_extension("json.tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+/// Check the complete partial file at `part` against its content id and move it to the
+/// destination, never over an existing file. The partial file and manifest are removed
+/// unless the destination couldn't be written, so that it can be fetched again.
+pub fn finish(part: &Path, manifest_path: &Path, manifest: &Manifest) -> Result<(), String> {
+    let attachment = &manifest.attachment;
+    let destination = &manifest.destination;
+    // Nothing was fetched for an empty file
+    if attachment.size == 0 {
+        write_chunk(part, 0, &[]).map_err(|e| format!("can't write {}: {e}", part.display()))?;
+    }
+    let checked = Cid::of_file(part).map_err(|e| format!("can't read {}: {e}", part.display()));
+    match checked {
+        Ok((cid, size)) if cid == attachment.cid && size == attachment.size => {}
+        Ok(_) => {
+            discard(part, manifest_path);
+            return Err(format!(
+                "{attachment} didn't match its content id and was discarded"
+            ));
+        }
+        Err(e) => return Err(e),
+    }
+    if destination.exists() {
+        return Err(format!(
+            "can't write {}: it already exists",
+            destination.display()
+        ));
+    }
+    // A rename can't cross file systems, so copy then
+    if fs::rename(part, destination).is_err() {
+        fs::copy(part, destination)
+            .map_err(|e| format!("can't write {}: {e}", destination.display()))?;
+        let _ = fs::remove_file(part);
+    }
+    let _ = fs::remove_file(manifest_path);
+    Ok(())
+}
+
+/// Remove the partial file and manifest of a transfer.
+pub fn discard(part: &Path, manifest_path: &Path) {
+    for path in [part, manifest_path] {
+        if let Err(e) = fs::remove_file(path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("[transfer] can't remove {}: {e}", path.display());
+            }
+        }
+    }
+}
@@ -0,0 +1,373 @@
+// Construction of the TCP transport stack (TCP -> security upgrade -> Yamux, or mplex).
+use std::{
+    error::Error,
+    io,
+    iter::Once,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use either::Either;
+use libp2p::{
+    core::{
+        either::EitherFuture,
+        muxing::StreamMuxerBox,
+        transport::{timeout::TransportTimeoutError, Boxed, MemoryTransport},
+        upgrade::{
+            InboundConnectionUpgrade, OutboundConnectionUpgrade, SelectUpgrade, UpgradeInfo,
+            Version,
+        },
+    },
+    futures::{future, future::MapOk, TryFutureExt},
+    identity::Keypair,
+    noise,
+    pnet::{PnetConfig, PreSharedKey},
+    quic, tcp, tls, yamux, PeerId, Transport,
+};
+use libp2p_mplex::MplexConfig;
+
+#[cfg(debug_assertions)]
+use crate::lossy::{Impairment, LatencyTransport, LossyTransport};
+
+use crate::{
+    bandwidth::{BandwidthLimiter, ThrottledStream},
+    cli::{Cli, NoiseCipher},
+    error::{ChatError, CryptoError},
+    proxy::ProxyTransport,
+    psk::{self, PskMismatch},
+};
+
+/// How long the security and multiplexing handshakes may take before a connection is dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Shorter handshake limit on private networks, where a stall almost always means a key mismatch.
+const PSK_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// TCP and QUIC on the runtime picked at build time
+#[cfg(not(feature = "async-std"))]
+type Tcp = tcp::tokio::Transport;
+#[cfg(feature = "async-std")]
+type Tcp = tcp::async_io::Transport;
+#[cfg(not(feature = "async-std"))]
+type Quic = quic::tokio::Transport;
+#[cfg(feature = "async-std")]
+type Quic = quic::async_std::Transport;
+
+/// How many TCP connections negotiated each stream multiplexer this session.
+#[derive(Debug, Clone, Default)]
+pub struct MuxerCounts {
+    yamux: Arc<AtomicU64>,
+    mplex: Arc<AtomicU64>,
+}
+
+impl MuxerCounts {
+    /// Connections multiplexed with Yamux.
+    pub fn yamux(&self) -> u64 {
+        self.yamux.load(Ordering::Relaxed)
+    }
+
+    /// Connections multiplexed with mplex, only ever negotiated with `--allow-mplex`.
+    pub fn mplex(&self) -> u64 {
+        self.mplex.load(Ordering::Relaxed)
+    }
+
+    fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Build the full transport: TCP and, outside private networks, QUIC and the in-process memory
+/// transport.
+///
+/// With a pre-shared key every TCP connection is wrapped in the pnet handshake first. QUIC
+/// brings its own encryption that pnet cannot wrap, so it is left out of private networks, and
+/// so is the memory transport, which only `p2p-chat bench` listens on.
+/// With `--max-upload-kbps` QUIC is left out as well, since only TCP connections are throttled,
+/// and with `--proxy`, since a SOCKS5 proxy only carries TCP.
+pub fn build_transport(
+    key: &Keypair,
+    cli: &Cli,
+    psk: Option<PreSharedKey>,
+    muxers: &MuxerCounts,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, ChatError> {
+    let tcp = build_tcp_transport(key, cli, psk, muxers)?;
+    if psk.is_some() {
+        return Ok(tcp);
+    }
+
+    let memory = build_memory_transport(key, cli)?;
+    // QUIC has no byte stream to throttle, so it would get around a bandwidth cap, and it
+    // would get around the proxy as well
+    if cli.max_upload_kbps.is_some() || cli.proxy.is_some() {
+        return Ok(tcp
+            .or_transport(memory)
+            .map(|either, _| either.into_inner())
+            .boxed());
+    }
+    let quic = Quic::new(quic::Config::new(key))
+        .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)));
+    Ok(tcp
+        .or_transport(quic)
+        .map(|either, _| either.into_inner())
+        .or_transport(memory)
+        .map(|either, _| either.into_inner())
+        .boxed())
+}
+
+/// Build the memory transport for nodes in the same process, secured with Noise and
+/// multiplexed with Yamux like TCP.
+pub fn build_memory_transport(
+    key: &Keypair,
+    cli: &Cli,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, ChatError> {
+    let memory = MemoryTransport::default();
+    #[cfg(debug_assertions)]
+    let memory = impaired(memory, cli);
+    Ok(memory
+        .upgrade(Version::V1Lazy)
+        .authenticate(noise::Config::new(key).map_err(CryptoError::from)?)
+        .multiplex(yamux_config(cli))
+        .timeout(HANDSHAKE_TIMEOUT)
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+        .boxed())
+}
+
+/// Build the authenticated and multiplexed TCP transport described by the command line flags,
+/// counting the multiplexer each connection negotiates in `muxers`.
+///
+/// Yamux is always offered first. With `--allow-mplex` mplex is offered as well, for peers
+/// that only implement mplex; two nodes that both support Yamux still use it.
+pub fn build_tcp_transport(
+    key: &Keypair,
+    cli: &Cli,
+    psk: Option<PreSharedKey>,
+    muxers: &MuxerCounts,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, ChatError> {
+    // Offer both security upgrades, ordered by the configured cipher preference.
+    let security = SelectSecurity::new(
+        noise::Config::new(key).map_err(CryptoError::from)?,
+        tls::Config::new(key).map_err(CryptoError::from)?,
+        cli.noise_cipher == NoiseCipher::Aesgcm,
+    );
+
+    // Dials go through the SOCKS5 proxy, if there is one
+    let tcp = ProxyTransport::new(Tcp::new(tcp::Config::default()), cli.proxy.clone());
+    // Debug builds may impair the connections to show how the chat copes with a bad link
+    #[cfg(debug_assertions)]
+    let tcp = impaired(tcp, cli);
+    // Every connection's writes share the one bandwidth cap, if there is one
+    let limiter = cli.max_upload_kbps.map(BandwidthLimiter::from_kbps);
+    let tcp = tcp.map(move |socket, _| ThrottledStream::new(socket, limiter.clone()));
+    let authenticated = tcp
+        // Wrap the raw socket in the private network handshake when a swarm key is set
+        .and_then(move |socket, _| async move {
+            match psk {
+                Some(psk) => PnetConfig::new(psk)
+                    .handshake(socket)
+                    .await
+                    .map(future::Either::Left)
+                    .map_err(PskMismatch::from),
+                None => Ok(future::Either::Right(socket)),
+            }
+        })
+        .map_err(|err| match err {
+            Either::Left(err) => err,
+            Either::Right(mismatch) => io::Error::new(io::ErrorKind::PermissionDenied, mismatch),
+        })
+        .upgrade(Version::V1Lazy)
+        // Authenticate the remote peer with whichever security protocol was negotiated
+        .authenticate(security);
+    // Never let a stalled handshake hang a connection attempt
+    let timeout = if psk.is_some() {
+        PSK_HANDSHAKE_TIMEOUT
+    } else {
+        HANDSHAKE_TIMEOUT
+    };
+
+    // Multiplex streams over the secured connection using Yamux, or mplex if allowed
+    let (yamux, mplex) = (muxers.yamux.clone(), muxers.mplex.clone());
+    if cli.allow_mplex {
+        let transport = authenticated
+            .multiplex(SelectUpgrade::new(
+                yamux_config(cli),
+                MplexConfig::default(),
+            ))
+            .timeout(timeout)
+            .map(move |(peer_id, muxer), _| {
+                match &muxer {
+                    future::Either::Left(_) => MuxerCounts::count(&yamux),
+                    future::Either::Right(_) => MuxerCounts::count(&mplex),
+                }
+                (peer_id, StreamMuxerBox::new(muxer))
+            });
+        Ok(explain_errors(transport, psk.is_some()))
+    } else {
+        let transport = authenticated
+            .multiplex(yamux_config(cli))
+            .timeout(timeout)
+            .map(move |(peer_id, muxer), _| {
+                MuxerCounts::count(&yamux);
+                (peer_id, StreamMuxerBox::new(muxer))
+            });
+        Ok(explain_errors(transport, psk.is_some()))
+    }
+}
+
+// Box the finished TCP transport, explaining failed handshakes on private networks.
+fn explain_errors<T, E>(transport: T, private: bool) -> Boxed<(PeerId, StreamMuxerBox)>
+where
+    T: Transport<Output = (PeerId, StreamMuxerBox), Error = TransportTimeoutError<E>>
+        + Send
+        + Unpin
+        + 'static,
+    T::Dial: Send + 'static,
+    T::ListenerUpgrade: Send + 'static,
+    E: Error + Send + Sync + 'static,
+{
+    if private {
+        // With mismatched keys both sides may sit waiting on garbage that looks like a partial
+        // negotiation message, so a stalled handshake is reported as a mismatch as well.
+        transport
+            .map_err(|err| match err {
+                TransportTimeoutError::Timeout => io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    PskMismatch::new("no readable handshake from the remote peer".to_string()),
+                ),
+                err => psk::explain_connection_error(err),
+            })
+            .boxed()
+    } else {
+        transport.boxed()
+    }
+}
+
+// Wrap `transport` in the loss and latency asked for with `--simulate-packet-loss` and
+// `--simulate-latency-ms`, which do nothing when not given.
+#[cfg(debug_assertions)]
+fn impaired<T>(transport: T, cli: &Cli) -> LatencyTransport<LossyTransport<T>> {
+    let impairment = Impairment::from_cli(cli).unwrap_or_default();
+    LatencyTransport::new(LossyTransport::new(transport, impairment), impairment)
+}
+
+/// Yamux configuration with the window and buffer sizes from the command line.
+///
+/// The flags are only applied when given: setting either of them switches libp2p-yamux to its
+/// older (but wire compatible) implementation, which is the one that still exposes these knobs.
+#[allow(deprecated)]
+pub fn yamux_config(cli: &Cli) -> yamux::Config {
+    let mut config = yamux::Config::default();
+    if let Some(window) = cli.yamux_window_size {
+        config.set_receive_window_size(window);
+    }
+    if let Some(max_buffer) = cli.yamux_max_buffer {
+        config.set_max_buffer_size(max_buffer);
+    }
+    config
+}
+
+/// Security upgrade offering Noise and TLS, in an order chosen at runtime.
+///
+/// Whatever the order, both protocols stay supported, so two nodes with different preferences
+/// still settle on a protocol they have in common.
+#[derive(Clone)]
+pub struct SelectSecurity {
+    noise: noise::Config,
+    tls: tls::Config,
+    prefer_tls: bool,
+}
+
+impl SelectSecurity {
+    /// Combine the two upgrades, proposing TLS first when `prefer_tls` is set.
+    pub fn new(noise: noise::Config, tls: tls::Config, prefer_tls: bool) -> Self {
+        Self {
+            noise,
+            tls,
+            prefer_tls,
+        }
+    }
+}
+
+/// Protocol name tagged with the upgrade it belongs to: `Left` is Noise, `Right` is TLS.
+type SecurityInfo = Either<&'static str, &'static str>;
+
+impl UpgradeInfo for SelectSecurity {
+    type Info = SecurityInfo;
+    type InfoIter = std::array::IntoIter<SecurityInfo, 2>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        let noise = Either::Left(protocol_name(&self.noise));
+        let tls = Either::Right(protocol_name(&self.tls));
+
+        // The listener accepts the first protocol it supports, so order expresses preference.
+        if self.prefer_tls {
+            [tls, noise].into_iter()
+        } else {
+            [noise, tls].into_iter()
+        }
+    }
+}
+
+/// The single protocol name advertised by a security upgrade.
+fn protocol_name<U>(upgrade: &U) -> &'static str
+where
+    U: UpgradeInfo<Info = &'static str, InfoIter = Once<&'static str>>,
+{
+    upgrade
+        .protocol_info()
+        .next()
+        .expect("security upgrades advertise exactly one protocol")
+}
+
+type SecurityFuture<A, B, TA, TB> = MapOk<
+    EitherFuture<A, B>,
+    fn(future::Either<(PeerId, TA), (PeerId, TB)>) -> (PeerId, future::Either<TA, TB>),
+>;
+
+impl<C, TA, TB, EA, EB> InboundConnectionUpgrade<C> for SelectSecurity
+where
+    noise::Config: InboundConnectionUpgrade<C, Output = (PeerId, TA), Error = EA>,
+    tls::Config: InboundConnectionUpgrade<C, Output = (PeerId, TB), Error = EB>,
+{
+    type Output = (PeerId, future::Either<TA, TB>);
+    type Error = Either<EA, EB>;
+    type Future = SecurityFuture<
+        <noise::Config as InboundConnectionUpgrade<C>>::Future,
+        <tls::Config as InboundConnectionUpgrade<C>>::Future,
+        TA,
+        TB,
+    >;
+
+    fn upgrade_inbound(self, socket: C, info: Self::Info) -> Self::Future {
+        match info {
+            Either::Left(info) => EitherFuture::First(self.noise.upgrade_inbound(socket, info)),
+            Either::Right(info) => EitherFuture::Second(self.tls.upgrade_inbound(socket, info)),
+        }
+        .map_ok(future::Either::factor_first)
+    }
+}
+
+impl<C, TA, TB, EA, EB> OutboundConnectionUpgrade<C> for SelectSecurity
+where
+    noise::Config: OutboundConnectionUpgrade<C, Output = (PeerId, TA), Error = EA>,
+    tls::Config: OutboundConnectionUpgrade<C, Output = (PeerId, TB), Error = EB>,
+{
+    type Output = (PeerId, future::Either<TA, TB>);
+    type Error = Either<EA, EB>;
+    type Future = SecurityFuture<
+        <noise::Config as OutboundConnectionUpgrade<C>>::Future,
+        <tls::Config as OutboundConnectionUpgrade<C>>::Future,
+        TA,
+        TB,
+    >;
+
+    fn upgrade_outbound(self, socket: C, info: Self::Info) -> Self::Future {
+        match info {
+            Either::Left(info) => EitherFuture::First(self.noise.upgrade_outbound(socket, info)),
+            Either::Right(info) => EitherFuture::Second(self.tls.upgrade_outbound(socket, info)),
+        }
+        .map_ok(future::Either::factor_first)
+    }
+}
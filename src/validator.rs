@@ -0,0 +1,115 @@
+// Application checks deciding whether Gossipsub accepts, rejects or ignores a chat message.
+use std::{fs, path::Path};
+
+use hmac::{Hmac, Mac};
+use libp2p::gossipsub::MessageAcceptance;
+use sha2::Sha256;
+
+use crate::{error::ConfigError, message::ChatMessage};
+
+/// Length of the HMAC-SHA256 tag appended to chat messages.
+pub const TAG_LEN: usize = 32;
+
+/// Shortest HMAC key accepted, in bytes.
+pub const MIN_KEY_LEN: usize = 16;
+
+/// Longest message body passed on by default, in bytes (`--max-body`). Larger ones are ignored.
+pub const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// Extended validation for the chat topic.
+///
+/// With a shared key, every chat message carries an HMAC tag. A missing or wrong tag is
+/// rejected, which costs the peer that forwarded it score; messages that are authentic but
+/// fail the content checks are only ignored, so nobody is penalized for relaying them.
+#[derive(Debug, Clone)]
+pub struct AppValidator {
+    key: Option<Vec<u8>>,
+    max_body: usize,
+}
+
+impl Default for AppValidator {
+    fn default() -> Self {
+        AppValidator::new(None)
+    }
+}
+
+impl AppValidator {
+    /// A validator checking tags made with `key`, or none at all without a key.
+    pub fn new(key: Option<Vec<u8>>) -> Self {
+        AppValidator {
+            key,
+            max_body: MAX_BODY_BYTES,
+        }
+    }
+
+    /// Pass on bodies of up to `bytes` instead of [`MAX_BODY_BYTES`].
+    pub fn with_max_body(mut self, bytes: usize) -> Self {
+        self.max_body = bytes;
+        self
+    }
+
+    /// Longest body passed on, in bytes.
+    pub fn max_body(&self) -> usize {
+        self.max_body
+    }
+
+    /// Read a hex-encoded key from `path`.
+    pub fn load_key(path: &Path) -> Result<Vec<u8>, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let invalid = |reason: String| ConfigError::HmacKey {
+            path: path.to_path_buf(),
+            reason,
+        };
+        let key = hex::decode(contents.trim()).map_err(|e| invalid(e.to_string()))?;
+        if key.len() < MIN_KEY_LEN {
+            return Err(invalid(format!("key must be at least {MIN_KEY_LEN} bytes")));
+        }
+        Ok(key)
+    }
+
+    /// Whether messages are authenticated with a shared key.
+    pub fn has_key(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Append the tag to an outgoing payload.
+    pub fn tag(&self, mut payload: Vec<u8>) -> Vec<u8> {
+        if let Some(mac) = self.mac() {
+            let tag = mac.chain_update(&payload).finalize().into_bytes();
+            payload.extend_from_slice(&tag);
+        }
+        payload
+    }
+
+    /// Check the tag of a received payload and strip it off.
+    pub fn verify<'a>(&self, data: &'a [u8]) -> Result<&'a [u8], MessageAcceptance> {
+        let Some(mac) = self.mac() else {
+            return Ok(data);
+        };
+        let split = data
+            .len()
+            .checked_sub(TAG_LEN)
+            .ok_or(MessageAcceptance::Reject)?;
+        let (payload, tag) = data.split_at(split);
+        mac.chain_update(payload)
+            .verify_slice(tag)
+            .map_err(|_| MessageAcceptance::Reject)?;
+        Ok(payload)
+    }
+
+    /// Content checks: messages with an empty or oversized body are ignored.
+    pub fn check_content(&self, chat: &ChatMessage) -> Result<(), MessageAcceptance> {
+        if chat.body.trim().is_empty() || chat.body.len() > self.max_body {
+            return Err(MessageAcceptance::Ignore);
+        }
+        Ok(())
+    }
+
+    fn mac(&self) -> Option<Hmac<Sha256>> {
+        let key = self.key.as_ref()?;
+        Some(Hmac::new_from_slice(key).expect("HMAC takes keys of any length"))
+    }
+}
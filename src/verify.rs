@@ -0,0 +1,87 @@
+// Out-of-band verification of peer identities with comparable key fingerprints.
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Bytes of the SHA-256 digest shown as a fingerprint.
+pub const FINGERPRINT_LEN: usize = 8;
+
+/// One word per byte value, for reading a fingerprint out loud.
+#[rustfmt::skip]
+pub const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alley",
+    "amber", "angle", "ankle", "apple", "apron", "arena", "armor", "arrow",
+    "aspen", "atlas", "attic", "audio", "autumn", "award", "bacon", "badge",
+    "bagel", "baker", "bamboo", "banjo", "barn", "basil", "basin", "beach",
+    "beard", "beetle", "bell", "bench", "berry", "birch", "bison", "blade",
+    "blanket", "bonus", "boot", "bottle", "boulder", "bracket", "branch", "bread",
+    "brick", "bridge", "broom", "bucket", "buffalo", "bugle", "button", "cabin",
+    "cactus", "camel", "candle", "canoe", "canyon", "carbon", "carpet", "carrot",
+    "castle", "cedar", "cello", "chalk", "cherry", "circus", "clay", "cliff",
+    "clock", "cloud", "clover", "cobalt", "cocoa", "comet", "copper", "coral",
+    "cotton", "cougar", "crane", "crater", "cricket", "crystal", "cup", "daisy",
+    "dancer", "delta", "desert", "diamond", "dinner", "dolphin", "donkey", "dragon",
+    "drum", "eagle", "echo", "elbow", "ember", "engine", "falcon", "feather",
+    "fence", "fern", "ferry", "fiddle", "finch", "flame", "flute", "forest",
+    "fossil", "fox", "garden", "garlic", "gecko", "geyser", "ginger", "glacier",
+    "globe", "goose", "granite", "grape", "gravel", "guitar", "hammer", "harbor",
+    "harp", "hawk", "hazel", "helmet", "heron", "honey", "hornet", "igloo",
+    "iris", "island", "ivory", "jacket", "jaguar", "jelly", "jungle", "kayak",
+    "kettle", "kiwi", "koala", "ladder", "lagoon", "lantern", "lemon", "lentil",
+    "lily", "lion", "lizard", "lobster", "locket", "lotus", "magnet", "mango",
+    "maple", "marble", "meadow", "melon", "meteor", "mint", "mirror", "monkey",
+    "moose", "mosaic", "muffin", "nectar", "needle", "nickel", "noodle", "oasis",
+    "ocean", "olive", "onion", "orchid", "otter", "owl", "paddle", "panda",
+    "parrot", "peach", "pebble", "pepper", "piano", "pigeon", "pillow", "pine",
+    "planet", "plum", "pocket", "pony", "poppy", "prism", "puzzle", "quail",
+    "quartz", "quill", "rabbit", "radar", "raven", "reef", "ribbon", "river",
+    "robin", "rocket", "ruby", "saddle", "salmon", "scarf", "shell", "silver",
+    "sketch", "sparrow", "spider", "spruce", "squid", "stamp", "statue", "storm",
+    "sugar", "summit", "swan", "tablet", "tango", "teapot", "thistle", "thunder",
+    "tiger", "timber", "tomato", "topaz", "torch", "tulip", "tundra", "turtle",
+    "valley", "velvet", "violin", "volcano", "wagon", "walnut", "walrus", "willow",
+    "window", "wizard", "yacht", "yarn", "zebra", "zephyr", "zinc", "zipper",
+];
+
+/// A short digest of a peer's identity that two people can compare over another channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; FINGERPRINT_LEN]);
+
+impl Fingerprint {
+    /// Fingerprint of `peer`. The PeerId is derived from the public key, so this fingerprints
+    /// the key as well.
+    pub fn of(peer: &PeerId) -> Self {
+        let digest = Sha256::digest(peer.to_bytes());
+        let mut bytes = [0; FINGERPRINT_LEN];
+        bytes.copy_from_slice(&digest[..FINGERPRINT_LEN]);
+        Fingerprint(bytes)
+    }
+
+    /// Upper case hex in groups of four, like `1A2B 3C4D 5E6F 7081`.
+    pub fn hex(&self) -> String {
+        self.0
+            .chunks(2)
+            .map(hex::encode_upper)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// One word per byte, like `coral lion harp ...`.
+    pub fn words(&self) -> String {
+        self.0
+            .iter()
+            .map(|&b| WORDS[b as usize])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// A peer whose fingerprint the user confirmed, stored in the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedPeer {
+    pub peer: PeerId,
+    /// The nick the peer used when it was verified. Another peer using it is an impostor.
+    pub nick: String,
+    /// Unix time (seconds) of the verification.
+    pub verified_at: u64,
+}
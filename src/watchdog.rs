@@ -0,0 +1,94 @@
+// Warnings about event loop iterations that run long, since networking stalls meanwhile.
+use std::{fmt, time::Duration};
+
+use tracing::warn;
+
+/// Longest one iteration of the event loop may take before the watchdog warns.
+pub const SLOW_ITERATION: Duration = Duration::from_millis(100);
+
+/// What an iteration of the event loop was doing, for the watchdog's warning.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Activity {
+    /// The once a second housekeeping.
+    Tick,
+    /// Publishing the batched messages.
+    BatchFlush,
+    /// Trying again to publish messages that had no peer to go to.
+    PublishRetry,
+    /// Handling a swarm event of this kind.
+    Event(&'static str),
+    /// Running a slash command typed by the user.
+    Command(String),
+    /// Sending a line typed by the user.
+    Chat,
+    /// Handling news from the Nostr relay.
+    Nostr,
+    /// Handling news from the IRC channel.
+    Irc,
+    /// Handling news from the Matrix room.
+    Matrix,
+    /// Handling news from the XMPP room.
+    Xmpp,
+    /// Handling news from the MQTT broker.
+    Mqtt,
+    /// Handling news of notes delivered to the fediverse.
+    Fediverse,
+    /// Handling an encounter over the simulated Bluetooth link.
+    Dtn,
+    /// Handling a line from a client of the IRC gateway.
+    Gateway,
+    /// Handling news of messages forwarded to webhooks.
+    Webhook,
+    /// Handling a post from a hook or a note from the fediverse, or a request the HTTP server
+    /// turned away.
+    Hook,
+}
+
+impl Activity {
+    /// The activity of handling `line`, naming the command but not the text of a message.
+    pub fn input(line: &str) -> Self {
+        match line.trim().strip_prefix('/') {
+            Some(command) => {
+                let name = command.split_whitespace().next().unwrap_or_default();
+                Activity::Command(name.chars().take(32).collect())
+            }
+            None => Activity::Chat,
+        }
+    }
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Activity::Tick => write!(f, "the once a second tick"),
+            Activity::BatchFlush => write!(f, "publishing a batch"),
+            Activity::PublishRetry => write!(f, "retrying queued messages"),
+            Activity::Event(kind) => write!(f, "a swarm event ({kind})"),
+            Activity::Command(name) => write!(f, "the command /{name}"),
+            Activity::Chat => write!(f, "sending a chat message"),
+            Activity::Nostr => write!(f, "a note from the Nostr relay"),
+            Activity::Irc => write!(f, "a line from the IRC channel"),
+            Activity::Matrix => write!(f, "a message from the Matrix room"),
+            Activity::Xmpp => write!(f, "a message from the XMPP room"),
+            Activity::Mqtt => write!(f, "a payload from the MQTT broker"),
+            Activity::Fediverse => write!(f, "news of a note for the fediverse"),
+            Activity::Dtn => write!(f, "an encounter over the simulated Bluetooth link"),
+            Activity::Gateway => write!(f, "a line from an IRC gateway client"),
+            Activity::Hook => write!(f, "a post from a hook or the fediverse"),
+            Activity::Webhook => write!(f, "news of a webhook"),
+        }
+    }
+}
+
+/// Warn when an iteration spent `elapsed` on `activity`, more than [`SLOW_ITERATION`].
+/// Returns whether it did.
+pub fn check(activity: &Activity, elapsed: Duration) -> bool {
+    if elapsed <= SLOW_ITERATION {
+        return false;
+    }
+    warn!(
+        "[watchdog] the event loop spent {} ms on {activity}, stalling the network meanwhile",
+        elapsed.as_millis()
+    );
+    true
+}
@@ -0,0 +1,387 @@
+// Rooms' messages forwarded to incoming webhooks, such as Slack's or Mattermost's: each room in
+// the `webhooks` section of the config file has its messages posted as JSON to a URL, several
+// at a time when they come in quick succession.
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::debug;
+use url::Url;
+
+use crate::{clock, http, runtime};
+
+/// Messages waiting for one webhook. Once that many are, more are dropped until some go out.
+pub const QUEUE: usize = 256;
+
+/// Most messages posted in one request.
+pub const MAX_BATCH: usize = 50;
+
+/// First wait before posting a batch again, doubled after each failure.
+pub const RETRY_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait before posting a batch again.
+pub const RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Batches in a row a webhook may fail to take before it is given a rest.
+pub const BREAKER_FAILURES: u32 = 3;
+
+/// How long messages for a webhook that keeps failing are dropped instead of queued.
+pub const BREAKER_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Where a room's messages go, and which of them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WebhookSettings {
+    /// The incoming webhook's `http://` or `https://` URL.
+    pub url: String,
+    /// The `text` of each message, with `{nick}`, `{room}`, `{timestamp}` and `{body}`
+    /// replaced. The lines of a batch are joined with newlines.
+    #[serde(default = "default_template")]
+    pub template: String,
+    /// How long to wait for more messages before posting, in milliseconds.
+    #[serde(default = "default_batch_ms")]
+    pub batch_ms: u64,
+    /// Times a batch is posted again after a server error or a lost connection.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Forward only messages that mention our nick.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mentions_only: bool,
+    /// Forward only messages from these nicks or peer ids. All senders if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub senders: Vec<String>,
+}
+
+fn default_template() -> String {
+    "{nick}: {body}".to_string()
+}
+
+fn default_batch_ms() -> u64 {
+    2000
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+impl WebhookSettings {
+    /// Settings posting every message to `url`, with the defaults for the rest.
+    pub fn new(url: &str) -> Self {
+        WebhookSettings {
+            url: url.to_string(),
+            template: default_template(),
+            batch_ms: default_batch_ms(),
+            retries: default_retries(),
+            mentions_only: false,
+            senders: Vec::new(),
+        }
+    }
+
+    /// The webhook's URL, if the settings make sense.
+    pub fn check(&self) -> Result<Url, String> {
+        if self.template.trim().is_empty() {
+            return Err("the template is empty".to_string());
+        }
+        http::service_url(&self.url)
+    }
+
+    /// Whether a message `sender` sent as `nick` should be forwarded, for a node going by
+    /// `own_nick`.
+    pub fn wants(&self, sender: &PeerId, nick: &str, body: &str, own_nick: &str) -> bool {
+        let from_sender = self.senders.is_empty()
+            || self
+                .senders
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(nick) || *wanted == sender.to_string());
+        from_sender && (!self.mentions_only || mentions(body, own_nick))
+    }
+}
+
+/// Whether `body` mentions `nick`, as a word of its own in any case.
+pub fn mentions(body: &str, nick: &str) -> bool {
+    if nick.is_empty() {
+        return false;
+    }
+    let (body, nick) = (body.to_ascii_lowercase(), nick.to_ascii_lowercase());
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    body.match_indices(&nick).any(|(start, _)| {
+        let before = body[..start].chars().next_back();
+        let after = body[start + nick.len()..].chars().next();
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    })
+}
+
+/// A message on its way to a webhook.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Forwarded {
+    pub nick: String,
+    pub room: String,
+    pub timestamp: u64,
+    pub body: String,
+}
+
+/// `template` with the placeholders filled in from `message`.
+pub fn render(template: &str, message: &Forwarded) -> String {
+    // The body goes last, so placeholders in it are left alone
+    template
+        .replace("{nick}", &message.nick)
+        .replace("{room}", &message.room)
+        .replace("{timestamp}", &message.timestamp.to_string())
+        .replace("{body}", &message.body)
+}
+
+/// The JSON posted for `batch`: its rendered lines as `text`, which Slack and Mattermost show,
+/// and the messages themselves.
+pub fn payload(template: &str, batch: &[Forwarded]) -> String {
+    let text: Vec<String> = batch
+        .iter()
+        .map(|message| render(template, message))
+        .collect();
+    json!({ "text": text.join("\n"), "messages": batch }).to_string()
+}
+
+/// What happened to the messages forwarded to a room's webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookEvent {
+    /// The webhook took a batch of `messages`.
+    Delivered { room: String, messages: usize },
+    /// A batch of `messages` was given up on.
+    Failed {
+        room: String,
+        messages: usize,
+        error: String,
+    },
+    /// The webhook failed [`BREAKER_FAILURES`] batches in a row. Its messages are dropped for
+    /// [`BREAKER_COOLDOWN`], after which the next batch tries it again.
+    Opened { room: String },
+    /// The webhook took a batch again after being given a rest.
+    Closed { room: String },
+}
+
+// One room's webhook, posting from a task of its own.
+#[derive(Debug)]
+struct Webhook {
+    settings: WebhookSettings,
+    queue: mpsc::Sender<Forwarded>,
+    // Until when, in Unix seconds, the webhook is resting; 0 while it isn't
+    open_until: Arc<AtomicU64>,
+    task: runtime::Task<()>,
+}
+
+impl Drop for Webhook {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The webhooks rooms' messages are forwarded to. Dropping it stops posting.
+#[derive(Debug)]
+pub struct Webhooks {
+    hooks: BTreeMap<String, Webhook>,
+    sender: mpsc::Sender<WebhookEvent>,
+    events: mpsc::Receiver<WebhookEvent>,
+}
+
+impl Default for Webhooks {
+    fn default() -> Self {
+        let (sender, events) = mpsc::channel(QUEUE);
+        Webhooks {
+            hooks: BTreeMap::new(),
+            sender,
+            events,
+        }
+    }
+}
+
+impl Webhooks {
+    /// Forward the messages of `room`, a topic name, as `settings` say.
+    pub fn add(&mut self, room: &str, settings: WebhookSettings) -> Result<(), String> {
+        let url = settings.check()?;
+        let (queue, outgoing) = mpsc::channel(QUEUE);
+        let open_until = Arc::new(AtomicU64::new(0));
+        let task = runtime::spawn(run(
+            Endpoint {
+                room: room.to_string(),
+                url,
+                settings: settings.clone(),
+            },
+            outgoing,
+            open_until.clone(),
+            self.sender.clone(),
+        ));
+        let webhook = Webhook {
+            settings,
+            queue,
+            open_until,
+            task,
+        };
+        self.hooks.insert(room.to_string(), webhook);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    /// The rooms with a webhook.
+    pub fn rooms(&self) -> impl Iterator<Item = &str> {
+        self.hooks.keys().map(String::as_str)
+    }
+
+    /// Queue `message` for its room's webhook if the webhook wants it, for a node going by
+    /// `own_nick`. Returns whether it was queued. Fails while the webhook is resting or
+    /// [`QUEUE`] messages are already waiting for it.
+    pub fn forward(
+        &self,
+        sender: &PeerId,
+        message: Forwarded,
+        own_nick: &str,
+    ) -> Result<bool, String> {
+        let Some(webhook) = self.hooks.get(&message.room) else {
+            return Ok(false);
+        };
+        if !webhook
+            .settings
+            .wants(sender, &message.nick, &message.body, own_nick)
+        {
+            return Ok(false);
+        }
+        let open_until = webhook.open_until.load(Ordering::Relaxed);
+        let now = clock::unix_time();
+        if open_until > now {
+            return Err(format!(
+                "the webhook keeps failing, resting for another {}s",
+                open_until - now
+            ));
+        }
+        webhook
+            .queue
+            .try_send(message)
+            .map_err(|_| format!("{QUEUE} messages are already waiting for the webhook"))?;
+        Ok(true)
+    }
+
+    /// The next thing that happened to forwarded messages.
+    pub async fn next(&mut self) -> Option<WebhookEvent> {
+        self.events.recv().await
+    }
+}
+
+// Where one room's messages are posted.
+struct Endpoint {
+    room: String,
+    url: Url,
+    settings: WebhookSettings,
+}
+
+impl Endpoint {
+    // Post `batch`, again after server errors and lost connections as often as the settings
+    // allow. Other answers aren't tried again.
+    async fn post(&self, batch: &[Forwarded]) -> Result<(), String> {
+        let body = payload(&self.settings.template, batch);
+        let mut backoff = RETRY_MIN;
+        let mut retries = 0;
+        loop {
+            let error = match http::post_json(&self.url, &body).await {
+                Ok(status) if (200..300).contains(&status) => return Ok(()),
+                Ok(status) if status >= 500 || status == 429 => {
+                    format!("the webhook answered {status}")
+                }
+                Ok(status) => return Err(format!("the webhook answered {status}")),
+                Err(e) => e,
+            };
+            if retries >= self.settings.retries {
+                return Err(error);
+            }
+            retries += 1;
+            debug!(
+                "[webhook] {}: {error}, posting again in {backoff:?}",
+                self.room
+            );
+            runtime::sleep(backoff).await;
+            backoff = (backoff * 2).min(RETRY_MAX);
+        }
+    }
+}
+
+// Post the queued messages in batches until the node goes away, resting the webhook once it
+// keeps failing.
+async fn run(
+    endpoint: Endpoint,
+    mut queue: mpsc::Receiver<Forwarded>,
+    open_until: Arc<AtomicU64>,
+    events: mpsc::Sender<WebhookEvent>,
+) {
+    let room = endpoint.room.clone();
+    let window = Duration::from_millis(endpoint.settings.batch_ms);
+    let mut failures = 0;
+    while let Some(first) = queue.recv().await {
+        // Messages coming in quick succession go out together
+        let mut batch = vec![first];
+        let deadline = Instant::now() + window;
+        while batch.len() < MAX_BATCH {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match runtime::timeout(left, queue.recv()).await {
+                Ok(Some(message)) => batch.push(message),
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+        let messages = batch.len();
+        let event = match endpoint.post(&batch).await {
+            Ok(()) => {
+                if failures >= BREAKER_FAILURES {
+                    open_until.store(0, Ordering::Relaxed);
+                    let _ = events
+                        .send(WebhookEvent::Closed { room: room.clone() })
+                        .await;
+                }
+                failures = 0;
+                WebhookEvent::Delivered {
+                    room: room.clone(),
+                    messages,
+                }
+            }
+            Err(error) => {
+                failures += 1;
+                WebhookEvent::Failed {
+                    room: room.clone(),
+                    messages,
+                    error,
+                }
+            }
+        };
+        if events.send(event).await.is_err() {
+            return;
+        }
+        if failures < BREAKER_FAILURES {
+            continue;
+        }
+        // Rest the webhook, dropping what was queued for it meanwhile
+        let until = clock::unix_time() + BREAKER_COOLDOWN.as_secs();
+        open_until.store(until, Ordering::Relaxed);
+        let _ = events
+            .send(WebhookEvent::Opened { room: room.clone() })
+            .await;
+        let mut dropped = 0;
+        while queue.try_recv().is_ok() {
+            dropped += 1;
+        }
+        if dropped > 0 {
+            let failed = WebhookEvent::Failed {
+                room: room.clone(),
+                messages: dropped,
+                error: "the webhook keeps failing".to_string(),
+            };
+            let _ = events.send(failed).await;
+        }
+    }
+}
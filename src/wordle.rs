@@ -0,0 +1,439 @@
+// Wordle played over Gossipsub: one peer picks a word, sealed until the game ends, and the
+// others guess it.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Key, XChaCha20Poly1305, XNonce,
+};
+use libp2p::{gossipsub, PeerId};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{node, signed::Signed};
+
+/// Letters in a word.
+pub const WORD_LEN: usize = 5;
+
+/// Guesses each player gets per game.
+pub const MAX_GUESSES: usize = 6;
+
+/// Most moves kept, of every game together; the oldest are dropped to make room.
+pub const MAX_MOVES: usize = 1000;
+
+// A sealed word: the nonce, then the ciphertext and its tag
+const NONCE_LEN: usize = 24;
+
+/// Every move is signed by the peer that made it.
+pub type SignedMove = Signed<WordleMove>;
+
+/// How a letter of a guess matches the word.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Mark {
+    /// Not in the word, or not as many times as guessed.
+    Gray,
+    /// In the word, somewhere else.
+    Yellow,
+    /// In the word at this place.
+    Green,
+}
+
+/// The marks of every letter of a guess.
+pub type Marks = [Mark; WORD_LEN];
+
+/// Moves of a game, published on the room's Wordle topic. Each carries a Lamport clock, so
+/// every peer puts the moves of a game in the same order, one that never puts a move before
+/// one its author had seen.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WordleMove {
+    /// The host starts a game with its word sealed under a key only the host has.
+    Start {
+        game: Uuid,
+        #[serde(with = "hex")]
+        sealed_word: Vec<u8>,
+        clock: u64,
+    },
+    /// A player's `n`th guess, counting from one. A player guesses again only once the host
+    /// has scored the previous guess.
+    Guess {
+        game: Uuid,
+        n: usize,
+        word: String,
+        clock: u64,
+    },
+    /// The host's marks for a player's `n`th guess.
+    Score {
+        game: Uuid,
+        player: PeerId,
+        n: usize,
+        word: String,
+        marks: Marks,
+        clock: u64,
+    },
+    /// The game is over: the host publishes the key its word was sealed with.
+    Reveal {
+        game: Uuid,
+        #[serde(with = "hex")]
+        key: Vec<u8>,
+        clock: u64,
+    },
+}
+
+impl WordleMove {
+    /// The game the move belongs to.
+    pub fn game(&self) -> Uuid {
+        match self {
+            WordleMove::Start { game, .. }
+            | WordleMove::Guess { game, .. }
+            | WordleMove::Score { game, .. }
+            | WordleMove::Reveal { game, .. } => *game,
+        }
+    }
+
+    /// The move's Lamport clock.
+    pub fn clock(&self) -> u64 {
+        match self {
+            WordleMove::Start { clock, .. }
+            | WordleMove::Guess { clock, .. }
+            | WordleMove::Score { clock, .. }
+            | WordleMove::Reveal { clock, .. } => *clock,
+        }
+    }
+}
+
+/// A guess and, once the host scored it, its marks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Guess {
+    pub word: String,
+    pub marks: Option<Marks>,
+}
+
+impl Guess {
+    /// Whether every letter is in its place.
+    pub fn is_solved(&self) -> bool {
+        self.marks
+            .is_some_and(|marks| marks.iter().all(|mark| *mark == Mark::Green))
+    }
+}
+
+/// A game as it stands after every move received so far.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub id: Uuid,
+    pub host: PeerId,
+    /// Each player's guesses, in order.
+    pub guesses: BTreeMap<PeerId, Vec<Guess>>,
+    /// The word, once the host revealed it.
+    pub word: Option<String>,
+    /// Scores that turned out not to match the revealed word.
+    pub wrong_scores: usize,
+}
+
+impl Game {
+    pub fn is_over(&self) -> bool {
+        self.word.is_some()
+    }
+
+    /// Players whose last guess has every letter in its place.
+    pub fn solvers(&self) -> impl Iterator<Item = &PeerId> {
+        self.guesses
+            .iter()
+            .filter(|(_, guesses)| guesses.last().is_some_and(Guess::is_solved))
+            .map(|(player, _)| player)
+    }
+
+    // The number of `player`'s next guess, if it is the player's turn.
+    fn next_guess(&self, player: &PeerId) -> Result<usize, String> {
+        if self.is_over() {
+            return Err("the game is over".to_string());
+        }
+        if *player == self.host {
+            return Err("you can't guess the word of a game you host".to_string());
+        }
+        let guesses = self.guesses.get(player).map_or(&[][..], Vec::as_slice);
+        match guesses.last() {
+            Some(last) if last.marks.is_none() => {
+                Err("wait for the host to score your last guess".to_string())
+            }
+            Some(last) if last.is_solved() => Err("you already found the word".to_string()),
+            _ if guesses.len() >= MAX_GUESSES => Err(format!("you used all {MAX_GUESSES} guesses")),
+            _ => Ok(guesses.len() + 1),
+        }
+    }
+}
+
+/// The Wordle games of a room. The moves form a grow-only set, so every peer that received
+/// the same moves sees the same games, whatever order they came in. A move that breaks the
+/// rules, such as a second guess made before the first was scored, is ignored by everyone
+/// alike.
+#[derive(Debug, Default)]
+pub struct Wordle {
+    // The highest clock seen, so our next move comes after it
+    clock: u64,
+    moves: BTreeSet<(u64, PeerId, WordleMove)>,
+    // The words of the games we host, with the keys they were sealed with
+    hosted: HashMap<Uuid, (String, Vec<u8>)>,
+}
+
+impl Wordle {
+    /// Add a move by `author`. Returns false if we already had it.
+    pub fn insert(&mut self, author: PeerId, wordle_move: WordleMove) -> bool {
+        let clock = wordle_move.clock();
+        if !self.moves.insert((clock, author, wordle_move)) {
+            return false;
+        }
+        self.clock = self.clock.max(clock);
+        while self.moves.len() > MAX_MOVES {
+            self.moves.pop_first();
+        }
+        true
+    }
+
+    /// Start a game as `host` with `word`, returning the move to publish.
+    pub fn start(&mut self, host: PeerId, word: &str) -> Result<WordleMove, String> {
+        let word = check_word(word)?;
+        let (key, sealed_word) = seal_word(&word);
+        let game = Uuid::new_v4();
+        self.hosted.insert(game, (word, key));
+        let start = WordleMove::Start {
+            game,
+            sealed_word,
+            clock: self.next_clock(),
+        };
+        self.insert(host, start.clone());
+        Ok(start)
+    }
+
+    /// Guess `word` in the current game as `player`, returning the move to publish.
+    pub fn guess(&mut self, player: PeerId, word: &str) -> Result<WordleMove, String> {
+        let game = self
+            .game()
+            .ok_or("no game yet, start one with /wordle start <word>")?;
+        let n = game.next_guess(&player)?;
+        let guess = WordleMove::Guess {
+            game: game.id,
+            n,
+            word: check_word(word)?,
+            clock: self.next_clock(),
+        };
+        self.insert(player, guess.clone());
+        Ok(guess)
+    }
+
+    /// Score the guesses of the current game still waiting for it, if `host` hosts it,
+    /// returning the moves to publish.
+    pub fn score_waiting(&mut self, host: PeerId) -> Vec<WordleMove> {
+        let Some(game) = self
+            .game()
+            .filter(|game| game.host == host && !game.is_over())
+        else {
+            return Vec::new();
+        };
+        let Some((word, _)) = self.hosted.get(&game.id) else {
+            return Vec::new();
+        };
+        let scores: Vec<WordleMove> = game
+            .guesses
+            .iter()
+            .filter_map(|(player, guesses)| {
+                let last = guesses.last().filter(|guess| guess.marks.is_none())?;
+                Some((*player, guesses.len(), last.word.clone()))
+            })
+            .enumerate()
+            .map(|(i, (player, n, guess))| WordleMove::Score {
+                game: game.id,
+                player,
+                n,
+                marks: score(word, &guess),
+                word: guess,
+                clock: self.next_clock().saturating_add(i as u64),
+            })
+            .collect();
+        for score in &scores {
+            self.insert(host, score.clone());
+        }
+        scores
+    }
+
+    /// End the current game as its `host` by revealing the word, returning the move to
+    /// publish.
+    pub fn reveal(&mut self, host: PeerId) -> Result<WordleMove, String> {
+        let game = self.game().ok_or("no game to end")?;
+        if game.host != host {
+            return Err("only the host can end the game".to_string());
+        }
+        if game.is_over() {
+            return Err("the game is already over".to_string());
+        }
+        let (_, key) = self
+            .hosted
+            .remove(&game.id)
+            .ok_or("the word of this game was lost when the node restarted")?;
+        let reveal = WordleMove::Reveal {
+            game: game.id,
+            key,
+            clock: self.next_clock(),
+        };
+        self.insert(host, reveal.clone());
+        Ok(reveal)
+    }
+
+    // The clock of our next move: after every move we have seen
+    fn next_clock(&self) -> u64 {
+        self.clock.saturating_add(1)
+    }
+
+    /// The most recently started game: the start with the highest clock, ties broken by id.
+    pub fn game(&self) -> Option<Game> {
+        let (host, id, sealed_word) = self
+            .moves
+            .iter()
+            .filter_map(|(clock, author, wordle_move)| match wordle_move {
+                WordleMove::Start {
+                    game, sealed_word, ..
+                } => Some(((*clock, *game), *author, sealed_word)),
+                _ => None,
+            })
+            .max_by_key(|(order, _, _)| *order)
+            .map(|((_, game), host, sealed_word)| (host, game, sealed_word))?;
+        let mut game = Game {
+            id,
+            host,
+            guesses: BTreeMap::new(),
+            word: None,
+            wrong_scores: 0,
+        };
+        // When each player's last guess was scored: a guess made before seeing that is early
+        let mut scored_at: HashMap<PeerId, u64> = HashMap::new();
+        let moves = self.moves.iter().filter(|(_, _, m)| m.game() == id);
+        for (clock, author, wordle_move) in moves {
+            match wordle_move {
+                WordleMove::Guess { n, word, .. } => {
+                    let in_turn = game.next_guess(author) == Ok(*n)
+                        && scored_at.get(author).is_none_or(|at| at < clock);
+                    if in_turn && check_word(word).as_ref() == Ok(word) {
+                        game.guesses.entry(*author).or_default().push(Guess {
+                            word: word.clone(),
+                            marks: None,
+                        });
+                    }
+                }
+                WordleMove::Score {
+                    player,
+                    n,
+                    word,
+                    marks,
+                    ..
+                } if *author == host => {
+                    let guess = game
+                        .guesses
+                        .get_mut(player)
+                        .and_then(|guesses| guesses.get_mut(n.checked_sub(1)?));
+                    if let Some(guess) = guess.filter(|g| g.word == *word && g.marks.is_none()) {
+                        guess.marks = Some(*marks);
+                        scored_at.insert(*player, *clock);
+                    }
+                }
+                WordleMove::Reveal { key, .. } if *author == host && !game.is_over() => {
+                    game.word = open_word(key, sealed_word);
+                }
+                _ => {}
+            }
+        }
+        if let Some(word) = &game.word {
+            game.wrong_scores = game
+                .guesses
+                .values()
+                .flatten()
+                .filter(|guess| guess.marks.is_some_and(|m| m != score(word, &guess.word)))
+                .count();
+        }
+        Some(game)
+    }
+}
+
+/// A word as played: `word` in lowercase, if it is [`WORD_LEN`] letters from a to z.
+pub fn check_word(word: &str) -> Result<String, String> {
+    let word = word.trim().to_ascii_lowercase();
+    if word.len() == WORD_LEN && word.bytes().all(|b| b.is_ascii_lowercase()) {
+        Ok(word)
+    } else {
+        Err(format!("words are {WORD_LEN} letters from a to z"))
+    }
+}
+
+/// Mark each letter of `guess` against `word`, both checked with [`check_word`]. A letter
+/// guessed more often than the word has it is only yellow as many times as the word has it
+/// elsewhere.
+pub fn score(word: &str, guess: &str) -> Marks {
+    let (word, guess) = (word.as_bytes(), guess.as_bytes());
+    let mut marks = [Mark::Gray; WORD_LEN];
+    // Letters of the word not matched in place, by letter
+    let mut left = [0u8; 26];
+    for i in 0..WORD_LEN {
+        if guess[i] == word[i] {
+            marks[i] = Mark::Green;
+        } else {
+            left[usize::from(word[i] - b'a')] += 1;
+        }
+    }
+    for i in 0..WORD_LEN {
+        let count = &mut left[usize::from(guess[i] - b'a')];
+        if marks[i] != Mark::Green && *count > 0 {
+            marks[i] = Mark::Yellow;
+            *count -= 1;
+        }
+    }
+    marks
+}
+
+/// A guess as shown in the terminal: its marks as colored squares, then the word.
+pub fn render(guess: &Guess) -> String {
+    let squares: String = match guess.marks {
+        Some(marks) => marks
+            .iter()
+            .map(|mark| match mark {
+                Mark::Green => '🟩',
+                Mark::Yellow => '🟨',
+                Mark::Gray => '⬜',
+            })
+            .collect(),
+        None => "·".repeat(WORD_LEN),
+    };
+    format!("{squares} {}", guess.word.to_uppercase())
+}
+
+/// The topic that carries the Wordle games of the room on `topic`.
+pub fn wordle_topic_for(topic: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("{topic}/_wordle"))
+}
+
+/// The topic that carries the Wordle games of the default chat topic.
+pub fn wordle_topic() -> gossipsub::IdentTopic {
+    wordle_topic_for(node::default_topic().hash().as_str())
+}
+
+// Seal `word` under a fresh random key, returning the key and the sealed word.
+fn seal_word(word: &str) -> (Vec<u8>, Vec<u8>) {
+    let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = XChaCha20Poly1305::new(&key)
+        .encrypt(&nonce, word.as_bytes())
+        .expect("a word is far below the cipher's length limit");
+    (key.to_vec(), [nonce.as_slice(), &ciphertext].concat())
+}
+
+// The word sealed with `key`, if it opens and is a word.
+fn open_word(key: &[u8], sealed: &[u8]) -> Option<String> {
+    if key.len() != 32 || sealed.len() < NONCE_LEN {
+        return None;
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let word = XChaCha20Poly1305::new(Key::from_slice(key))
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .ok()?;
+    let word = String::from_utf8(word).ok()?;
+    check_word(&word).ok().filter(|checked| *checked == word)
+}
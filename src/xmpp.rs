@@ -0,0 +1,769 @@
+// A bridge mirroring an XMPP multi-user chat room: the node logs in to an XMPP server, joins
+// the room, publishes what is said there to the room and says there what is said in the room.
+//
+// Only the little of XMPP a bot in one room needs is spoken: STARTTLS, SASL PLAIN, resource
+// binding, joining the room, groupchat messages and pings. Stanzas are cut out of the stream
+// here and parsed one at a time.
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use data_encoding::BASE64;
+use futures_rustls::client::TlsStream;
+use libp2p::futures::{
+    future::Either,
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf},
+    AsyncRead,
+};
+use tokio::sync::mpsc;
+use tracing::debug;
+use url::{Host, Url};
+use xmltree::{Element, XMLNode};
+
+use crate::{cli::Cli, runtime, tls};
+
+/// The origin of the chat messages copied in from XMPP.
+pub const ORIGIN: &str = "xmpp";
+
+/// Messages waiting for the room. Once that many are, more are refused until some go out.
+pub const QUEUE: usize = 256;
+
+/// First wait before connecting again after losing the server, doubled after each failure.
+pub const RECONNECT_MIN: Duration = Duration::from_secs(1);
+
+/// Longest wait before connecting again.
+pub const RECONNECT_MAX: Duration = Duration::from_secs(60);
+
+/// How long connecting and logging in, TLS handshake included, may take.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Interval of the pings checking the server is still there. It is given up on when nothing
+/// came from it for two intervals.
+pub const PING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Port of XMPP servers for clients.
+pub const PORT: u16 = 5222;
+
+/// Largest stanza taken from the server.
+pub const MAX_STANZA_BYTES: usize = 256 * 1024;
+
+/// The resource the bridge binds, the part after the `/` of its full JID.
+pub const RESOURCE: &str = "p2p-chat";
+
+const STREAM_NS: &str = "http://etherx.jabber.org/streams";
+const TLS_NS: &str = "urn:ietf:params:xml:ns:xmpp-tls";
+const SASL_NS: &str = "urn:ietf:params:xml:ns:xmpp-sasl";
+const BIND_NS: &str = "urn:ietf:params:xml:ns:xmpp-bind";
+const MUC_NS: &str = "http://jabber.org/protocol/muc";
+const MUC_USER_NS: &str = "http://jabber.org/protocol/muc#user";
+const PING_NS: &str = "urn:xmpp:ping";
+const DELAY_NS: &str = "urn:xmpp:delay";
+
+/// Parse a bare JID, `user@domain`, as `--xmpp-jid` and `--xmpp-room` take.
+pub fn bare_jid(s: &str) -> Result<String, String> {
+    let valid = match s.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !s.contains(['/', ' ', '<', '>', '&', '"', '\'', ':'])
+                && !s.chars().any(char::is_control)
+        }
+        None => false,
+    };
+    if valid {
+        Ok(s.to_string())
+    } else {
+        Err(format!("{s} isn't a bare JID such as user@example.com"))
+    }
+}
+
+/// The server, account and room the bridge uses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XmppSettings {
+    /// The server, as `host` or `host:port`.
+    pub server: String,
+    /// The account, as a bare JID.
+    pub jid: String,
+    pub password: String,
+    /// The multi-user chat room, as a bare JID such as `team@conference.example.com`.
+    pub room: String,
+}
+
+impl XmppSettings {
+    /// The settings of `--xmpp-server` and the flags going with it, if it was given.
+    pub fn from_cli(cli: &Cli) -> Option<Self> {
+        Some(XmppSettings {
+            server: cli.xmpp_server.clone()?,
+            jid: cli.xmpp_jid.clone()?,
+            password: cli.xmpp_password.clone()?,
+            room: cli.xmpp_room.clone()?,
+        })
+    }
+
+    /// The host and port of the server, [`PORT`] when none is given.
+    pub fn address(&self) -> Result<(String, u16), String> {
+        let url = Url::parse(&format!("xmpp://{}", self.server))
+            .map_err(|_| format!("{} isn't a host or host:port", self.server))?;
+        let host = match url.host() {
+            Some(Host::Domain(domain)) => domain.to_string(),
+            Some(Host::Ipv4(ip)) => ip.to_string(),
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            None => return Err("the XMPP server has no host".to_string()),
+        };
+        Ok((host, url.port().unwrap_or(PORT)))
+    }
+
+    /// The user part of the JID, which is also the nick asked for in the room.
+    pub fn user(&self) -> &str {
+        self.jid.split_once('@').map_or("", |(user, _)| user)
+    }
+
+    /// The domain of the JID, which the server has to be serving.
+    pub fn domain(&self) -> &str {
+        self.jid.split_once('@').map_or("", |(_, domain)| domain)
+    }
+}
+
+/// What happens on the connection to the XMPP server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XmppEvent {
+    /// Logged in as `jid`, or again, and joined the room as `nick`.
+    Connected { jid: String, nick: String },
+    /// The connection was lost or couldn't be made. Connecting is tried again with backoff,
+    /// and this isn't repeated until a connection was made.
+    Disconnected(String),
+    /// Someone said `text` in the room.
+    Message { nick: String, text: String },
+    /// Someone did something in the room with `/me`.
+    Action { nick: String, text: String },
+    /// Someone joined the room.
+    Joined(String),
+    /// Someone left the room.
+    Left(String),
+}
+
+/// The connection to an XMPP room, kept on a task of its own that reconnects whenever it is
+/// lost. Dropping it closes the connection.
+#[derive(Debug)]
+pub struct XmppBridge {
+    settings: XmppSettings,
+    outgoing: mpsc::Sender<String>,
+    incoming: mpsc::Receiver<XmppEvent>,
+    task: runtime::Task<()>,
+}
+
+impl XmppBridge {
+    /// Log in and join the room of `settings`.
+    pub fn spawn(settings: XmppSettings) -> Self {
+        let (outgoing, queue) = mpsc::channel(QUEUE);
+        let (events, incoming) = mpsc::channel(QUEUE);
+        let task = runtime::spawn(run(settings.clone(), queue, events));
+        XmppBridge {
+            settings,
+            outgoing,
+            incoming,
+            task,
+        }
+    }
+
+    pub fn settings(&self) -> &XmppSettings {
+        &self.settings
+    }
+
+    /// Say `text` in the room for `nick`, as `<nick> text`. It goes out as soon as the room is
+    /// joined. Fails while [`QUEUE`] messages are waiting.
+    pub fn relay(&self, nick: &str, text: &str) -> Result<(), String> {
+        self.outgoing
+            .try_send(format!("<{nick}> {text}"))
+            .map_err(|_| format!("{QUEUE} messages are already waiting for the XMPP room"))
+    }
+
+    /// The next thing that happened on the connection.
+    pub async fn next(&mut self) -> Option<XmppEvent> {
+        self.incoming.recv().await
+    }
+}
+
+impl Drop for XmppBridge {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// `text` fit for XML character data or an attribute value: markup escaped, and characters
+/// XML can't carry dropped.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// What comes next in the stream.
+#[derive(Debug, PartialEq, Eq)]
+enum Frame {
+    // More has to be read first
+    Partial,
+    // The first `usize` bytes are the opening of a stream, an XML declaration or whitespace
+    Skip(usize),
+    // The first `usize` bytes are a stanza
+    Stanza(usize),
+    // The server closed the stream
+    Closed,
+}
+
+// Find where the stanza at the start of `buffer`, between stanzas, ends.
+fn frame(buffer: &[u8]) -> Frame {
+    let blank = buffer
+        .iter()
+        .take_while(|b| b.is_ascii_whitespace())
+        .count();
+    if blank > 0 {
+        return Frame::Skip(blank);
+    }
+    if buffer.is_empty() {
+        return Frame::Partial;
+    }
+    if buffer.starts_with(b"<?") {
+        return find(buffer, b"?>").map_or(Frame::Partial, Frame::Skip);
+    }
+    if buffer.starts_with(b"</") {
+        return Frame::Closed;
+    }
+    if buffer.starts_with(b"<stream:stream") {
+        return tag_end(buffer, 0).map_or(Frame::Partial, Frame::Skip);
+    }
+    let mut depth = 0usize;
+    let mut at = 0;
+    while at < buffer.len() {
+        let Some(start) = buffer[at..].iter().position(|&b| b == b'<') else {
+            return Frame::Partial;
+        };
+        let start = at + start;
+        let rest = &buffer[start..];
+        let end = if rest.starts_with(b"<!--") {
+            find(rest, b"-->")
+        } else if rest.starts_with(b"<![CDATA[") {
+            find(rest, b"]]>")
+        } else {
+            tag_end(buffer, start).map(|end| end - start)
+        };
+        let Some(end) = end else {
+            return Frame::Partial;
+        };
+        let tag = &rest[..end];
+        if tag.starts_with(b"</") {
+            depth = depth.saturating_sub(1);
+        } else if !tag.starts_with(b"<!") && !tag.ends_with(b"/>") {
+            depth += 1;
+        }
+        at = start + end;
+        if depth == 0 {
+            return Frame::Stanza(at);
+        }
+    }
+    Frame::Partial
+}
+
+// The length of `buffer` up to and including `pattern`.
+fn find(buffer: &[u8], pattern: &[u8]) -> Option<usize> {
+    buffer
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .map(|at| at + pattern.len())
+}
+
+// The end of the tag starting at `start`, skipping `>` in quoted attribute values.
+fn tag_end(buffer: &[u8], start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (at, &b) in buffer.iter().enumerate().skip(start) {
+        match quote {
+            Some(q) if b == q => quote = None,
+            Some(_) => {}
+            None if b == b'"' || b == b'\'' => quote = Some(b),
+            None if b == b'>' => return Some(at + 1),
+            None => {}
+        }
+    }
+    None
+}
+
+// Parse a stanza cut out of the stream, with the namespaces it had there.
+fn parse(stanza: &[u8]) -> Result<Element, String> {
+    let mut document = format!("<s xmlns='jabber:client' xmlns:stream='{STREAM_NS}'>").into_bytes();
+    document.extend_from_slice(stanza);
+    document.extend_from_slice(b"</s>");
+    let wrapper = Element::parse(document.as_slice()).map_err(|e| e.to_string())?;
+    wrapper
+        .children
+        .into_iter()
+        .find_map(|node| match node {
+            XMLNode::Element(element) => Some(element),
+            _ => None,
+        })
+        .ok_or_else(|| "the server sent an empty stanza".to_string())
+}
+
+// The name of the first child element, such as the condition of an error.
+fn condition(element: &Element) -> String {
+    element
+        .children
+        .iter()
+        .filter_map(XMLNode::as_element)
+        .find(|child| child.name != "text")
+        .map_or_else(|| "no reason given".to_string(), |child| child.name.clone())
+}
+
+fn attribute<'a>(element: &'a Element, name: &str) -> &'a str {
+    element.attributes.get(name).map_or("", String::as_str)
+}
+
+fn text(element: &Element) -> Cow<'_, str> {
+    element.get_text().unwrap_or_default()
+}
+
+// Stanzas read from one side of a connection.
+struct Reader<R> {
+    inner: R,
+    buffer: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    fn new(inner: R) -> Self {
+        Reader {
+            inner,
+            buffer: Vec::new(),
+        }
+    }
+
+    // The next stanza. A stream error or the end of the stream fails.
+    async fn stanza(&mut self) -> Result<Element, String> {
+        loop {
+            match frame(&self.buffer) {
+                Frame::Skip(len) => {
+                    self.buffer.drain(..len);
+                    continue;
+                }
+                Frame::Stanza(len) => {
+                    let stanza = parse(&self.buffer[..len]);
+                    self.buffer.drain(..len);
+                    let stanza = stanza?;
+                    if stanza.name == "error" && stanza.namespace.as_deref() == Some(STREAM_NS) {
+                        return Err(format!("stream error: {}", condition(&stanza)));
+                    }
+                    return Ok(stanza);
+                }
+                Frame::Closed => return Err("the server closed the stream".to_string()),
+                Frame::Partial if self.buffer.len() >= MAX_STANZA_BYTES => {
+                    return Err(format!("a stanza is over {MAX_STANZA_BYTES} bytes"));
+                }
+                Frame::Partial => {}
+            }
+            // Only what a read returned in full is added, so a read given up on loses nothing
+            let mut chunk = [0; 4096];
+            let read = self
+                .inner
+                .read(&mut chunk)
+                .await
+                .map_err(|e| e.to_string())?;
+            if read == 0 {
+                return Err("the server closed the connection".to_string());
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+async fn send(writer: &mut (impl AsyncWrite + Unpin), xml: &str) -> Result<(), String> {
+    writer
+        .write_all(xml.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    writer.flush().await.map_err(|e| e.to_string())
+}
+
+type Socket = Either<TlsStream<runtime::TcpStream>, runtime::TcpStream>;
+
+// Keep in the room until the node goes away.
+async fn run(
+    settings: XmppSettings,
+    mut queue: mpsc::Receiver<String>,
+    events: mpsc::Sender<XmppEvent>,
+) {
+    let mut backoff = RECONNECT_MIN;
+    let mut reported = false;
+    loop {
+        let connected = runtime::timeout(CONNECT_TIMEOUT, login(&settings))
+            .await
+            .map_err(|_| "logging in timed out".to_string())
+            .and_then(|connected| connected);
+        let error = match connected {
+            Ok((reader, jid)) => {
+                let session = Session {
+                    settings: &settings,
+                    events: &events,
+                    jid,
+                    nick: settings.user().to_string(),
+                    joined: false,
+                    occupants: HashSet::new(),
+                    next_id: 0,
+                };
+                let (error, joined) = session.run(reader, &mut queue).await;
+                if joined {
+                    backoff = RECONNECT_MIN;
+                    reported = false;
+                }
+                error
+            }
+            Err(e) => e,
+        };
+        if events.is_closed() {
+            return;
+        }
+        debug!(
+            "[xmpp] {}: {error}, connecting again in {backoff:?}",
+            settings.server
+        );
+        if !reported {
+            reported = true;
+            let _ = events.send(XmppEvent::Disconnected(error)).await;
+        }
+        runtime::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+// Open a stream to the server, and read the features it offers.
+async fn open(reader: &mut Reader<Socket>, domain: &str) -> Result<Element, String> {
+    let header = format!(
+        "<?xml version='1.0'?><stream:stream to='{}' version='1.0' xmlns='jabber:client' \
+         xmlns:stream='{STREAM_NS}'>",
+        escape(domain)
+    );
+    send(&mut reader.inner, &header).await?;
+    let features = reader.stanza().await?;
+    if features.name != "features" {
+        return Err(format!(
+            "the server sent {} instead of features",
+            features.name
+        ));
+    }
+    Ok(features)
+}
+
+// Connect over TLS, log in and bind a resource. Returns the connection and the full JID.
+async fn login(settings: &XmppSettings) -> Result<(Reader<Socket>, String), String> {
+    let (host, port) = settings.address()?;
+    let domain = settings.domain();
+    let tcp = runtime::connect_tcp(&host, port)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut reader = Reader::new(Either::Right(tcp));
+    let mut features = open(&mut reader, domain).await?;
+    if features.get_child(("starttls", TLS_NS)).is_some() {
+        send(&mut reader.inner, &format!("<starttls xmlns='{TLS_NS}'/>")).await?;
+        let answer = reader.stanza().await?;
+        if answer.name != "proceed" {
+            return Err("the server wouldn't start TLS".to_string());
+        }
+        let Either::Right(tcp) = reader.inner else {
+            unreachable!("TLS is only started once");
+        };
+        // The certificate is for the domain, whichever host serves it
+        reader = Reader::new(Either::Left(tls::connect(domain, tcp).await?));
+        features = open(&mut reader, domain).await?;
+    } else if !is_loopback(&host) {
+        return Err("the server doesn't offer TLS, so the password isn't sent".to_string());
+    }
+
+    let plain = features
+        .get_child(("mechanisms", SASL_NS))
+        .is_some_and(|mechanisms| {
+            mechanisms
+                .children
+                .iter()
+                .filter_map(XMLNode::as_element)
+                .any(|mechanism| text(mechanism) == "PLAIN")
+        });
+    if !plain {
+        return Err("the server doesn't take passwords with SASL PLAIN".to_string());
+    }
+    let credentials = format!("\0{}\0{}", settings.user(), settings.password);
+    let auth = format!(
+        "<auth xmlns='{SASL_NS}' mechanism='PLAIN'>{}</auth>",
+        BASE64.encode(credentials.as_bytes())
+    );
+    send(&mut reader.inner, &auth).await?;
+    let answer = reader.stanza().await?;
+    if answer.name != "success" {
+        return Err(format!(
+            "the server refused the password: {}",
+            condition(&answer)
+        ));
+    }
+
+    open(&mut reader, domain).await?;
+    let bind = format!(
+        "<iq type='set' id='bind'><bind xmlns='{BIND_NS}'><resource>{RESOURCE}</resource>\
+         </bind></iq>"
+    );
+    send(&mut reader.inner, &bind).await?;
+    loop {
+        let answer = reader.stanza().await?;
+        if answer.name != "iq" || attribute(&answer, "id") != "bind" {
+            continue;
+        }
+        if attribute(&answer, "type") != "result" {
+            return Err(format!("binding a resource failed: {}", error_of(&answer)));
+        }
+        let jid = answer
+            .get_child(("bind", BIND_NS))
+            .and_then(|bind| bind.get_child("jid"))
+            .map(|jid| text(jid).into_owned())
+            .unwrap_or_else(|| format!("{}/{RESOURCE}", settings.jid));
+        return Ok((reader, jid));
+    }
+}
+
+// The condition of the error in an error stanza.
+fn error_of(stanza: &Element) -> String {
+    stanza
+        .get_child("error")
+        .map_or_else(|| "no reason given".to_string(), condition)
+}
+
+// Whether `host` is this machine, where a server without TLS is trusted with the password.
+fn is_loopback(host: &str) -> bool {
+    host == "localhost" || host.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+// One connection to the server, after logging in.
+struct Session<'a> {
+    settings: &'a XmppSettings,
+    events: &'a mpsc::Sender<XmppEvent>,
+    jid: String,
+    // Our nick in the room, which may have `_` added
+    nick: String,
+    joined: bool,
+    // The nicks of the others in the room
+    occupants: HashSet<String>,
+    next_id: u64,
+}
+
+impl Session<'_> {
+    // Join the room, then pass what is said there on to the node and what the node has to
+    // say to the room, until the connection fails. Returns why it did, and whether the room
+    // was joined.
+    async fn run(
+        mut self,
+        reader: Reader<Socket>,
+        queue: &mut mpsc::Receiver<String>,
+    ) -> (String, bool) {
+        let (inner, mut writer) = reader.inner.split();
+        let mut reader: Reader<ReadHalf<Socket>> = Reader {
+            inner,
+            buffer: reader.buffer,
+        };
+        if let Err(e) = send(&mut writer, &self.join()).await {
+            return (e, false);
+        }
+        let mut ping = runtime::interval(PING_INTERVAL);
+        // The first tick is straight away, before the server could have been silent
+        ping.tick().await;
+        let mut heard = Instant::now();
+        let error = loop {
+            tokio::select! {
+                stanza = reader.stanza() => {
+                    let stanza = match stanza {
+                        Ok(stanza) => stanza,
+                        Err(e) => break e,
+                    };
+                    heard = Instant::now();
+                    if let Err(e) = self.handle(stanza, &mut writer).await {
+                        break e;
+                    }
+                }
+                text = queue.recv(), if self.joined => {
+                    let Some(text) = text else {
+                        break "the node went away".to_string();
+                    };
+                    let message = format!(
+                        "<message to='{}' type='groupchat' id='{}'><body>{}</body></message>",
+                        escape(&self.settings.room),
+                        self.id(),
+                        escape(&text)
+                    );
+                    if let Err(e) = send(&mut writer, &message).await {
+                        break e;
+                    }
+                }
+                _ = ping.tick() => {
+                    if heard.elapsed() > 2 * PING_INTERVAL {
+                        break "the server stopped answering".to_string();
+                    }
+                    let ping = format!(
+                        "<iq type='get' id='{}' to='{}'><ping xmlns='{PING_NS}'/></iq>",
+                        self.id(),
+                        escape(self.settings.domain())
+                    );
+                    if let Err(e) = send(&mut writer, &ping).await {
+                        break e;
+                    }
+                }
+            }
+        };
+        (error, self.joined)
+    }
+
+    fn id(&mut self) -> String {
+        self.next_id += 1;
+        format!("p2p-chat-{}", self.next_id)
+    }
+
+    // The presence joining the room under our nick, asking for none of its history.
+    fn join(&self) -> String {
+        format!(
+            "<presence to='{}/{}'><x xmlns='{MUC_NS}'><history maxstanzas='0'/></x></presence>",
+            escape(&self.settings.room),
+            escape(&self.nick)
+        )
+    }
+
+    // Answer a stanza from the server, and tell the node about what happened in the room.
+    // Fails when the connection has to be given up.
+    async fn handle(
+        &mut self,
+        stanza: Element,
+        writer: &mut WriteHalf<Socket>,
+    ) -> Result<(), String> {
+        match stanza.name.as_str() {
+            "presence" => return self.presence(&stanza, writer).await,
+            "iq" => return self.iq(&stanza, writer).await,
+            "message" => {}
+            _ => return Ok(()),
+        }
+        let Some((room, nick)) = attribute(&stanza, "from").split_once('/') else {
+            return Ok(());
+        };
+        let ours = nick == self.nick;
+        // History the room replays carries a delay, and subjects have no body
+        let delayed = stanza.get_child(("delay", DELAY_NS)).is_some();
+        let body = stanza.get_child("body").map(text);
+        let groupchat = attribute(&stanza, "type") == "groupchat";
+        let (Some(body), false, false, true) = (body, ours, delayed, groupchat) else {
+            return Ok(());
+        };
+        if !room.eq_ignore_ascii_case(&self.settings.room) {
+            return Ok(());
+        }
+        let nick = nick.to_string();
+        let event = match body.strip_prefix("/me ") {
+            Some(text) => XmppEvent::Action {
+                nick,
+                text: text.to_string(),
+            },
+            None => XmppEvent::Message {
+                nick,
+                text: body.into_owned(),
+            },
+        };
+        self.emit(event).await
+    }
+
+    async fn presence(
+        &mut self,
+        stanza: &Element,
+        writer: &mut WriteHalf<Socket>,
+    ) -> Result<(), String> {
+        let Some((room, nick)) = attribute(stanza, "from").split_once('/') else {
+            return Ok(());
+        };
+        if !room.eq_ignore_ascii_case(&self.settings.room) {
+            return Ok(());
+        }
+        let kind = attribute(stanza, "type");
+        let ours = nick == self.nick
+            || stanza.get_child(("x", MUC_USER_NS)).is_some_and(|x| {
+                x.children
+                    .iter()
+                    .filter_map(XMLNode::as_element)
+                    .any(|status| status.name == "status" && attribute(status, "code") == "110")
+            });
+        if kind == "error" {
+            let error = error_of(stanza);
+            if error != "conflict" || self.joined {
+                return Err(format!("couldn't join {}: {error}", self.settings.room));
+            }
+            self.nick.push('_');
+            return send(writer, &self.join()).await;
+        }
+        if ours {
+            if kind == "unavailable" {
+                return Err(format!("removed from {}", self.settings.room));
+            }
+            if !self.joined {
+                self.joined = true;
+                let connected = XmppEvent::Connected {
+                    jid: self.jid.clone(),
+                    nick: self.nick.clone(),
+                };
+                return self.emit(connected).await;
+            }
+            return Ok(());
+        }
+        // Those in the room before us are listed as we join, and aren't news
+        let nick = nick.to_string();
+        if kind == "unavailable" {
+            if self.occupants.remove(&nick) && self.joined {
+                return self.emit(XmppEvent::Left(nick)).await;
+            }
+        } else if self.occupants.insert(nick.clone()) && self.joined {
+            return self.emit(XmppEvent::Joined(nick)).await;
+        }
+        Ok(())
+    }
+
+    // Answer pings, and turn down anything else asked of us.
+    async fn iq(&mut self, stanza: &Element, writer: &mut WriteHalf<Socket>) -> Result<(), String> {
+        let kind = attribute(stanza, "type");
+        if kind != "get" && kind != "set" {
+            return Ok(());
+        }
+        let id = escape(attribute(stanza, "id"));
+        // Without a `from`, the server itself asked
+        let to = match attribute(stanza, "from") {
+            "" => String::new(),
+            from => format!(" to='{}'", escape(from)),
+        };
+        let answer = if kind == "get" && stanza.get_child(("ping", PING_NS)).is_some() {
+            format!("<iq type='result' id='{id}'{to}/>")
+        } else {
+            format!(
+                "<iq type='error' id='{id}'{to}><error type='cancel'>\
+                 <service-unavailable xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></iq>"
+            )
+        };
+        send(writer, &answer).await
+    }
+
+    async fn emit(&self, event: XmppEvent) -> Result<(), String> {
+        self.events
+            .send(event)
+            .await
+            .map_err(|_| "the node went away".to_string())
+    }
+}
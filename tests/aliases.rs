@@ -0,0 +1,78 @@
+// Local aliases set with `/alias`, shown instead of a peer's own nick.
+mod common;
+
+use std::{env, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, AliasCommand, UserCommand},
+    message::ChatMessage,
+};
+use libp2p::{gossipsub, PeerId};
+
+fn message(source: PeerId, seq: u64, nick: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new(nick, format!("message {seq}"), 0);
+    common::message_event(source, seq, chat.encode())
+}
+
+#[test]
+fn alias_command_parses() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(parse("/alias"), Ok(UserCommand::Alias(AliasCommand::List)));
+    assert_eq!(
+        parse("/alias xXdragonXx Sam"),
+        Ok(UserCommand::Alias(AliasCommand::Set {
+            target: "xXdragonXx".to_string(),
+            alias: "Sam".to_string()
+        }))
+    );
+    assert_eq!(
+        parse("/alias xXdragonXx \"Sam from work\""),
+        Ok(UserCommand::Alias(AliasCommand::Set {
+            target: "xXdragonXx".to_string(),
+            alias: "Sam from work".to_string()
+        }))
+    );
+    assert_eq!(
+        parse("/alias remove Sam"),
+        Ok(UserCommand::Alias(AliasCommand::Remove("Sam".to_string())))
+    );
+    assert!(parse("/alias xXdragonXx").is_err());
+}
+
+#[tokio::test]
+async fn aliases_outlive_nick_changes_and_restarts() {
+    let config = env::temp_dir().join(format!("p2p-chat-aliases-{}.json", process::id()));
+    let cli = common::cli(&["--config", config.to_str().unwrap()]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    let (sam, other) = (PeerId::random(), PeerId::random());
+    node.receive(message(sam, 0, "xXdragonXx"));
+
+    node.handle_line("/alias xXdragonXx Sam").await;
+    assert_eq!(node.display_name(&sam), "Sam");
+    node.receive(message(sam, 1, "dragon2"));
+    assert_eq!(
+        node.display_name(&sam),
+        "Sam",
+        "the alias outlives a new nick"
+    );
+
+    // Someone actually called Sam is told apart, and the alias wins when resolving
+    node.receive(message(other, 0, "sam"));
+    assert_ne!(node.display_name(&other), "sam");
+    assert!(node.display_name(&other).starts_with("sam·"));
+    node.handle_line("/alias sam Samuel").await;
+    assert_eq!(node.display_name(&sam), "Samuel");
+
+    drop(node);
+    let mut node = ChatNode::new(&cli).unwrap();
+    assert_eq!(node.display_name(&sam), "Samuel", "aliases are saved");
+
+    node.receive(message(sam, 2, "dragon2"));
+    node.handle_line("/alias remove Samuel").await;
+    assert_eq!(node.display_name(&sam), "dragon2");
+    drop(node);
+    let node = ChatNode::new(&cli).unwrap();
+    assert_eq!(node.display_name(&sam), sam.to_string());
+    std::fs::remove_file(config).unwrap();
+}
@@ -0,0 +1,122 @@
+// Allocations on the path of a received chat message. The benchmark prints its numbers, run it
+// with `cargo test --release --test allocations -- --ignored --nocapture`.
+mod common;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::Arc,
+    time::Instant,
+};
+
+use concurrent_chat_server::{chat::ChatNode, message::ChatMessage};
+use libp2p::{gossipsub, PeerId};
+
+// Counts allocations and their bytes, per thread so tests running alongside don't interfere
+struct Counting;
+
+thread_local! {
+    static ALLOCATED: Cell<(u64, u64)> = const { Cell::new((0, 0)) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|count| {
+            let (allocations, bytes) = count.get();
+            count.set((allocations + 1, bytes + layout.size() as u64));
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// Allocations and allocated bytes while running `f`.
+fn allocated<T>(f: impl FnOnce() -> T) -> (u64, u64, T) {
+    let before = ALLOCATED.with(Cell::get);
+    let value = f();
+    let after = ALLOCATED.with(Cell::get);
+    (after.0 - before.0, after.1 - before.1, value)
+}
+
+fn message(seq: u64, body: &str) -> gossipsub::Event {
+    let author = PeerId::random();
+    let chat = ChatMessage::new(format!("peer{seq}"), body, seq);
+    common::message_event(author, seq, chat.encode())
+}
+
+#[test]
+fn copies_of_a_message_share_its_body() {
+    let body = "x".repeat(4096);
+    let sent = ChatMessage::new("peer1", body.as_str(), 1);
+    let chat = ChatMessage::decode(&sent.encode(), 0);
+    let (allocations, bytes, copy) = allocated(|| chat.clone());
+    assert!(Arc::ptr_eq(&chat.body, &copy.body));
+    // Only the nick is copied
+    assert_eq!(allocations, 1);
+    assert!(bytes < 64);
+
+    // Plain text from older peers is copied into the body once
+    let (_, bytes, plain) = allocated(|| ChatMessage::decode(body.as_bytes(), 0));
+    assert_eq!(&*plain.body, body);
+    assert!(bytes < 2 * body.len() as u64, "{bytes} bytes");
+}
+
+#[test]
+fn stored_messages_are_not_copied_again() {
+    let mut node = ChatNode::new(&common::cli(&["--rate-limit", "1000000"])).unwrap();
+    let body = "y".repeat(4096);
+    for seq in 0..10 {
+        node.receive(message(seq, &body));
+    }
+    assert_eq!(node.history().count(), 10);
+    let (_, bytes, copies) = allocated(|| {
+        node.history()
+            .map(|stored| stored.message.clone())
+            .collect::<Vec<_>>()
+    });
+    assert!(bytes < body.len() as u64, "{bytes} bytes");
+    assert!(copies
+        .iter()
+        .zip(node.history())
+        .all(|(copy, stored)| Arc::ptr_eq(&copy.body, &stored.message.body)));
+}
+
+#[test]
+#[ignore = "benchmark; run with --release -- --ignored --nocapture"]
+fn allocations_per_received_message() {
+    const MESSAGES: u64 = 20_000;
+    let mut node = ChatNode::new(&common::cli(&["--rate-limit", "1000000"])).unwrap();
+    for (label, body) in [("64 B", "z".repeat(64)), ("4 KiB", "z".repeat(4096))] {
+        let events: Vec<_> = (0..MESSAGES).map(|seq| message(seq, &body)).collect();
+        let started = Instant::now();
+        let (allocations, bytes, ()) = allocated(|| {
+            for event in events {
+                node.receive(event);
+            }
+        });
+        let elapsed = started.elapsed();
+        // Readers of the history, like reports, take copies
+        let (copy_allocations, copy_bytes, _) = allocated(|| {
+            node.history()
+                .map(|stored| stored.message.clone())
+                .collect::<Vec<_>>()
+        });
+        let stored = node.history().count() as u64;
+        println!(
+            "{label} bodies: {:.1} allocations and {} bytes per received message, {:.0} \
+             messages/s; {:.1} allocations and {} bytes per copy from the history",
+            allocations as f64 / MESSAGES as f64,
+            bytes / MESSAGES,
+            MESSAGES as f64 / elapsed.as_secs_f64(),
+            copy_allocations as f64 / stored as f64,
+            copy_bytes / stored,
+        );
+        assert!(copy_bytes / stored < body.len() as u64);
+    }
+}
@@ -0,0 +1,54 @@
+// Refusing connections to peers missing from `--allowlist-file`.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{allowlist, message::ChatMessage};
+use libp2p::PeerId;
+
+#[test]
+fn allowlists_skip_comments_and_reject_bad_lines() {
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    let allowed = allowlist::parse(&format!("# office\n{alice}\n\n  {bob}  \n")).unwrap();
+    assert_eq!(allowed.len(), 2);
+    assert!(allowed.contains(&alice) && allowed.contains(&bob));
+
+    let err = allowlist::parse(&format!("{alice}\nnot-a-peer\n")).unwrap_err();
+    assert!(err.contains("line 2"), "{err}");
+}
+
+#[tokio::test]
+async fn only_allowlisted_peers_may_connect() {
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut carol, carol_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let path = env::temp_dir().join(format!("p2p-chat-allowlist-{}", process::id()));
+    fs::write(&path, format!("# only bob\n{}\n", bob.local_peer_id())).unwrap();
+    let cli = common::cli(&["--allowlist-file", path.to_str().unwrap()]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&cli).await;
+    fs::remove_file(&path).unwrap();
+
+    // Carol is refused both ways
+    alice.swarm.dial(carol_addr).unwrap();
+    carol.swarm.dial(alice_addr).unwrap();
+    common::run_until(
+        &mut alice,
+        &mut carol,
+        Duration::from_secs(10),
+        |alice, _| alice.stats().counters.not_allowlisted == 2,
+    )
+    .await;
+    assert!(!alice.swarm.is_connected(&carol.local_peer_id()));
+
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &topic)
+    })
+    .await;
+    let message = ChatMessage::new("bob", "let me in", 1);
+    bob.publish(&message).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+}
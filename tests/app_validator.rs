@@ -0,0 +1,123 @@
+// Extended validation: HMAC-tagged chat messages and content checks.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    message::ChatMessage,
+    validator::{AppValidator, MAX_BODY_BYTES},
+};
+use libp2p::{gossipsub::MessageAcceptance, PeerId};
+
+const KEY: &str = "000102030405060708090a0b0c0d0e0f";
+
+fn chat(body: &str) -> Vec<u8> {
+    ChatMessage::new("bob", body, 0).encode()
+}
+
+fn acceptance(node: &mut ChatNode, seq: u64, data: Vec<u8>) -> MessageAcceptance {
+    let validation = node
+        .receive(common::message_event(PeerId::random(), seq, data))
+        .expect("chat messages are validated");
+    validation.acceptance
+}
+
+fn key_file(name: &str) -> String {
+    let path = env::temp_dir().join(format!("p2p-chat-hmac-{name}-{}.key", process::id()));
+    fs::write(&path, KEY).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+fn tags_verify_only_with_the_same_key() {
+    let validator = AppValidator::new(Some(hex::decode(KEY).unwrap()));
+    let tagged = validator.tag(b"hello".to_vec());
+    assert_eq!(validator.verify(&tagged).ok(), Some(&b"hello"[..]));
+
+    let mut tampered = tagged.clone();
+    tampered[0] ^= 1;
+    assert!(matches!(
+        validator.verify(&tampered),
+        Err(MessageAcceptance::Reject)
+    ));
+    assert!(matches!(
+        validator.verify(b"short"),
+        Err(MessageAcceptance::Reject)
+    ));
+    let other = AppValidator::new(Some(vec![7; 16]));
+    assert!(other.verify(&tagged).is_err());
+    // Without a key, payloads pass through untouched
+    assert_eq!(
+        AppValidator::default().verify(b"hello").ok(),
+        Some(&b"hello"[..])
+    );
+}
+
+#[test]
+fn authentic_content_is_accepted_and_the_rest_rejected_or_ignored() {
+    let path = key_file("verdicts");
+    let mut node = ChatNode::new(&common::cli(&["--hmac-key", &path])).unwrap();
+    let validator = AppValidator::new(Some(AppValidator::load_key(path.as_ref()).unwrap()));
+    fs::remove_file(&path).unwrap();
+
+    assert!(matches!(
+        acceptance(&mut node, 1, validator.tag(chat("hi"))),
+        MessageAcceptance::Accept
+    ));
+    assert!(matches!(
+        acceptance(&mut node, 2, chat("no tag")),
+        MessageAcceptance::Reject
+    ));
+    assert!(matches!(
+        acceptance(&mut node, 3, validator.tag(chat("   "))),
+        MessageAcceptance::Ignore
+    ));
+    let huge = "x".repeat(MAX_BODY_BYTES + 1);
+    assert!(matches!(
+        acceptance(&mut node, 4, validator.tag(chat(&huge))),
+        MessageAcceptance::Ignore
+    ));
+    assert_eq!(node.history().count(), 1);
+}
+
+#[test]
+fn short_keys_are_refused() {
+    let path = env::temp_dir().join(format!("p2p-chat-hmac-short-{}.key", process::id()));
+    fs::write(&path, "0011").unwrap();
+    let result = ChatNode::new(&common::cli(&["--hmac-key", path.to_str().unwrap()]));
+    fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn rejected_messages_lower_the_forwarders_score() {
+    let path = key_file("score");
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--hmac-key", &path])).await;
+    fs::remove_file(&path).unwrap();
+    // Bob doesn't have the key, so his messages carry no tag
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    bob.swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic.clone(), chat("untagged"))
+        .unwrap();
+    let bob_id = bob.local_peer_id();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice
+            .swarm
+            .behaviour()
+            .gossipsub
+            .peer_score(&bob_id)
+            .is_some_and(|score| score < 0.0)
+    })
+    .await;
+    assert_eq!(alice.history().count(), 0);
+}
@@ -0,0 +1,174 @@
+// Files attached to chat messages by content id and fetched from their sender.
+mod common;
+
+use std::{env, fs, path::PathBuf, process, time::Duration};
+
+use concurrent_chat_server::{
+    attachment::{
+        AttachmentRef, Cid, ContentStore, CHUNK_BYTES, MAX_ATTACHMENT_BYTES, MAX_SHARED_FILES,
+    },
+    commands::{self, UserCommand},
+    message::ChatMessage,
+};
+
+fn temp_file(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("p2p-chat-attachment-{}-{name}", process::id()))
+}
+
+#[test]
+fn content_ids_are_cids_of_the_bytes() {
+    let cid = Cid::of(b"hello");
+    let text = cid.to_string();
+    assert!(text.starts_with("bafkrei"), "{text}");
+    assert_eq!(text.parse::<Cid>(), Ok(cid));
+    assert_eq!(serde_json::to_string(&cid).unwrap(), format!("\"{text}\""));
+    assert_ne!(Cid::of(b"hello!"), cid);
+    for bad in ["", "hello", "bafkrei", &text[1..]] {
+        assert!(bad.parse::<Cid>().is_err(), "{bad:?} accepted");
+    }
+}
+
+#[test]
+fn attaching_reads_the_file_and_names_it() {
+    let path = temp_file("notes.txt");
+    fs::write(&path, "hello world").unwrap();
+    let attachment = AttachmentRef::read(&path).unwrap();
+    assert_eq!(attachment.cid, Cid::of(b"hello world"));
+    assert_eq!(attachment.size, 11);
+    assert_eq!(attachment.mime_type, "text/plain");
+    assert!(attachment.filename.ends_with("notes.txt"));
+    assert!(attachment.to_string().ends_with("notes.txt (11 B)]"));
+    assert!(attachment.to_string().starts_with("[📎 "));
+
+    let large = temp_file("large.bin");
+    fs::File::create(&large)
+        .unwrap()
+        .set_len(MAX_ATTACHMENT_BYTES + 1)
+        .unwrap();
+    assert!(AttachmentRef::read(&large).is_err());
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&large).unwrap();
+}
+
+#[test]
+fn received_names_lose_their_paths_and_escapes() {
+    let attachment = AttachmentRef {
+        cid: Cid::of(b""),
+        size: 3 * 1024 * 1024 / 2,
+        mime_type: "text/plain".to_string(),
+        filename: "../../.ssh/\u{1b}[2Jauthorized_keys".to_string(),
+    }
+    .sanitized();
+    assert!(!attachment.filename.contains('/'));
+    assert!(!attachment.filename.contains('\u{1b}'));
+    assert!(attachment
+        .to_string()
+        .ends_with("authorized_keys (1.5 MiB)]"));
+
+    let dots = AttachmentRef {
+        filename: "..".to_string(),
+        ..attachment
+    };
+    assert_eq!(dots.sanitized().filename, "attachment");
+}
+
+#[test]
+fn messages_without_attachments_look_as_before() {
+    let message = ChatMessage::new("alice", "hi", 1);
+    let encoded = String::from_utf8(message.encode()).unwrap();
+    assert_eq!(encoded, r#"{"nick":"alice","body":"hi","timestamp":1}"#);
+    assert_eq!(ChatMessage::decode(encoded.as_bytes(), 0), message);
+}
+
+#[test]
+fn the_store_serves_chunks_of_the_newest_files() {
+    let path = temp_file("served.bin");
+    let data: Vec<u8> = (0..CHUNK_BYTES * 2 + 10).map(|n| n as u8).collect();
+    fs::write(&path, &data).unwrap();
+    let cid = Cid::of(&data);
+    let mut store = ContentStore::default();
+    store.insert(cid, path.clone(), data.len() as u64);
+
+    let chunk = |offset: u64, len: u64| store.read(&cid, offset, len).unwrap().unwrap();
+    assert_eq!(chunk(0, 100), data[..100]);
+    // At most a chunk, and no more than there is
+    assert_eq!(chunk(0, u64::MAX).len() as u64, CHUNK_BYTES);
+    assert_eq!(
+        chunk(CHUNK_BYTES * 2, CHUNK_BYTES),
+        data[CHUNK_BYTES as usize * 2..]
+    );
+    assert!(store.read(&Cid::of(b"other"), 0, 1).is_none());
+
+    // A file that changed since isn't served as the content it was
+    fs::write(&path, b"changed").unwrap();
+    assert!(store.read(&cid, 0, 1).is_none());
+    fs::remove_file(&path).unwrap();
+
+    for n in 0..MAX_SHARED_FILES {
+        store.insert(Cid::of(&n.to_be_bytes()), path.clone(), 1);
+    }
+    assert_eq!(store.path(&cid), None);
+    assert_eq!(store.len(), MAX_SHARED_FILES);
+}
+
+#[test]
+fn attach_and_fetch_commands_parse() {
+    assert_eq!(
+        commands::parse("/attach notes.txt"),
+        Some(Ok(UserCommand::Attach("notes.txt".into())))
+    );
+    assert_eq!(
+        commands::parse("/fetch 1a2b out.txt"),
+        Some(Ok(UserCommand::Fetch {
+            target: "1a2b".to_string(),
+            path: "out.txt".into()
+        }))
+    );
+    assert!(commands::parse("/attach").unwrap().is_err());
+    assert!(commands::parse("/fetch 1a2b").unwrap().is_err());
+}
+
+#[tokio::test]
+async fn a_peer_fetches_an_attachment_from_its_sender() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    let data: Vec<u8> = (0..100_000u32).map(|n| n as u8).collect();
+    let path = temp_file("photo.png");
+    fs::write(&path, &data).unwrap();
+    alice
+        .handle_line(&format!("/attach {}", path.display()))
+        .await;
+    alice.handle_line("see attached").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+    let received = bob.history().next().unwrap().clone();
+    let attachment = received.message.attachment.unwrap();
+    assert_eq!(attachment.cid, Cid::of(&data));
+    assert_eq!(attachment.mime_type, "image/png");
+
+    let saved = temp_file("fetched.png");
+    let _ = fs::remove_file(&saved);
+    bob.handle_line(&format!("/fetch {} {}", received.id, saved.display()))
+        .await;
+    assert_eq!(bob.fetching(), 1);
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.fetching() == 0
+    })
+    .await;
+    bob.flush_writes().await;
+    assert_eq!(fs::read(&saved).unwrap(), data);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&saved).unwrap();
+}
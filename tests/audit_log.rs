@@ -0,0 +1,108 @@
+// The local audit log of security-relevant events.
+mod common;
+
+use std::{env, fs, io::Write, path::PathBuf, process};
+
+use concurrent_chat_server::{
+    audit::{AuditEvent, AuditLog, AUDIT_FILE, DEFAULT_TAIL},
+    chat::ChatNode,
+    commands::{self, UserCommand},
+};
+use libp2p::PeerId;
+
+// A fresh directory per test, since the log is kept next to the config file.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("p2p-chat-audit-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn tail_skips_a_line_cut_off_by_a_crash() {
+    let dir = scratch_dir("torn");
+    let log = AuditLog::new(Some(dir.join(AUDIT_FILE)));
+    let (actor, peer) = (PeerId::random(), PeerId::random());
+    log.record(actor, AuditEvent::Unverified { peer }).unwrap();
+    log.record(
+        actor,
+        AuditEvent::InviteUsed {
+            room: "chat".to_string(),
+        },
+    )
+    .unwrap();
+    assert_eq!(log.tail(1).unwrap().len(), 1);
+
+    // A crash in the middle of a write leaves half a line behind
+    fs::OpenOptions::new()
+        .append(true)
+        .open(log.path().unwrap())
+        .unwrap()
+        .write_all(br#"{"timestamp":17"#)
+        .unwrap();
+    log.record(
+        actor,
+        AuditEvent::KeyRotation {
+            old: peer,
+            new: actor,
+        },
+    )
+    .unwrap();
+
+    let entries = log.tail(DEFAULT_TAIL).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let events: Vec<_> = entries.iter().map(|entry| &entry.event).collect();
+    assert_eq!(
+        events,
+        [
+            &AuditEvent::Unverified { peer },
+            &AuditEvent::InviteUsed {
+                room: "chat".to_string()
+            },
+            &AuditEvent::KeyRotation {
+                old: peer,
+                new: actor
+            },
+        ]
+    );
+    assert!(entries.iter().all(|entry| entry.actor == actor));
+}
+
+#[tokio::test]
+async fn node_records_invites_and_verifications() {
+    let dir = scratch_dir("node");
+    let config = dir.join("config.json");
+    let mut node = ChatNode::new(&common::cli(&["--config", config.to_str().unwrap()])).unwrap();
+    let local = node.local_peer_id();
+    let peer = PeerId::random();
+
+    node.create_invite(3600, Some(peer)).unwrap();
+    node.handle_line(&format!("/verify {peer} confirm")).await;
+    node.handle_line(&format!("/unverify {peer}")).await;
+    node.flush_writes().await;
+
+    let entries = AuditLog::new(Some(dir.join(AUDIT_FILE))).tail(10).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(entries.len(), 3);
+    assert!(entries.iter().all(|entry| entry.actor == local));
+    assert!(matches!(
+        &entries[0].event,
+        AuditEvent::InviteCreated { invitee: Some(p), .. } if *p == peer
+    ));
+    assert!(matches!(&entries[1].event, AuditEvent::Verified { peer: p, .. } if *p == peer));
+    assert_eq!(entries[2].event, AuditEvent::Unverified { peer });
+}
+
+#[test]
+fn audit_tail_takes_an_optional_count() {
+    assert_eq!(
+        commands::parse("/audit tail"),
+        Some(Ok(UserCommand::AuditTail(DEFAULT_TAIL)))
+    );
+    assert_eq!(
+        commands::parse("/audit tail 5"),
+        Some(Ok(UserCommand::AuditTail(5)))
+    );
+    assert!(commands::parse("/audit tail many").unwrap().is_err());
+    assert!(commands::parse("/audit").unwrap().is_err());
+}
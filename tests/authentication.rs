@@ -0,0 +1,210 @@
+// Custom authentication of peers after they connect, before their messages are accepted.
+mod common;
+
+use std::{
+    future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::Poll,
+    time::Duration,
+};
+
+use concurrent_chat_server::{
+    auth::{AsyncReadWrite, ConnectionAuthenticator},
+    error::AuthError,
+    message::ChatMessage,
+};
+use libp2p::{
+    futures::{AsyncReadExt, AsyncWriteExt},
+    gossipsub::{self, MessageAcceptance},
+    PeerId,
+};
+use sha2::{Digest, Sha256};
+
+// Proves knowledge of a shared secret by hashing it with a random challenge.
+struct SharedSecret(&'static str);
+
+impl SharedSecret {
+    fn proof(&self, challenge: &[u8]) -> Vec<u8> {
+        Sha256::new()
+            .chain_update(self.0)
+            .chain_update(challenge)
+            .finalize()
+            .to_vec()
+    }
+
+    async fn check(&self, stream: &mut impl AsyncReadWrite) -> Result<(), AuthError> {
+        let challenge: [u8; 16] = rand::random();
+        stream.write_all(&challenge).await?;
+        let mut answer = Vec::new();
+        stream.read_to_end(&mut answer).await?;
+        if answer != self.proof(&challenge) {
+            return Err(AuthError::Rejected("wrong answer".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn answer(&self, stream: &mut impl AsyncReadWrite) -> Result<(), AuthError> {
+        let mut challenge = Vec::new();
+        stream.read_to_end(&mut challenge).await?;
+        stream.write_all(&self.proof(&challenge)).await?;
+        Ok(())
+    }
+}
+
+impl ConnectionAuthenticator for SharedSecret {
+    async fn authenticate(
+        &self,
+        _peer_id: PeerId,
+        stream: &mut impl AsyncReadWrite,
+    ) -> Result<(), AuthError> {
+        self.check(stream).await
+    }
+
+    async fn respond(
+        &self,
+        _peer_id: PeerId,
+        stream: &mut impl AsyncReadWrite,
+    ) -> Result<(), AuthError> {
+        self.answer(stream).await
+    }
+}
+
+// Checks the answer, then waits for the test to let the peer in, like a lookup elsewhere would.
+struct Gated {
+    secret: SharedSecret,
+    checked: Arc<AtomicBool>,
+    open: Arc<AtomicBool>,
+}
+
+impl ConnectionAuthenticator for Gated {
+    async fn authenticate(
+        &self,
+        _peer_id: PeerId,
+        stream: &mut impl AsyncReadWrite,
+    ) -> Result<(), AuthError> {
+        self.secret.check(stream).await?;
+        self.checked.store(true, Ordering::SeqCst);
+        future::poll_fn(|_| match self.open.load(Ordering::SeqCst) {
+            true => Poll::Ready(Ok(())),
+            false => Poll::Pending,
+        })
+        .await
+    }
+
+    async fn respond(
+        &self,
+        _peer_id: PeerId,
+        stream: &mut impl AsyncReadWrite,
+    ) -> Result<(), AuthError> {
+        self.secret.answer(stream).await
+    }
+}
+
+fn chat_from(peer: PeerId, seq: u64) -> gossipsub::Event {
+    let chat = ChatMessage::new("bob", format!("hello {seq}"), seq);
+    common::message_event(peer, seq, chat.encode())
+}
+
+#[tokio::test]
+async fn peers_knowing_the_secret_authenticate_each_other_and_chat() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.set_authenticator(SharedSecret("open sesame"));
+    bob.set_authenticator(SharedSecret("open sesame"));
+    let (alice_id, bob_id) = (alice.local_peer_id(), bob.local_peer_id());
+
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| {
+            alice.pending_auth().is_authenticated(&bob_id)
+                && bob.pending_auth().is_authenticated(&alice_id)
+        },
+    )
+    .await;
+    assert!(!alice.pending_auth().is_pending(&bob_id));
+
+    bob.handle_line("hi alice").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn a_wrong_answer_gets_the_peer_disconnected() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.set_authenticator(SharedSecret("open sesame"));
+    bob.set_authenticator(SharedSecret("open barley"));
+    let bob_id = bob.local_peer_id();
+
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.swarm.is_connected(&bob_id)
+    })
+    .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        !alice.swarm.is_connected(&bob_id)
+    })
+    .await;
+    assert!(!alice.pending_auth().is_authenticated(&bob_id));
+    assert!(!alice.pending_auth().is_pending(&bob_id));
+}
+
+#[tokio::test]
+async fn peers_that_cannot_answer_are_disconnected() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.set_authenticator(SharedSecret("open sesame"));
+    let bob_id = bob.local_peer_id();
+
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.swarm.is_connected(&bob_id)
+    })
+    .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(15), |alice, _| {
+        !alice.swarm.is_connected(&bob_id)
+    })
+    .await;
+    assert!(!alice.pending_auth().is_authenticated(&bob_id));
+}
+
+#[tokio::test]
+async fn messages_are_ignored_until_the_peer_passes() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let checked = Arc::new(AtomicBool::new(false));
+    let open = Arc::new(AtomicBool::new(false));
+    alice.set_authenticator(Gated {
+        secret: SharedSecret("open sesame"),
+        checked: checked.clone(),
+        open: open.clone(),
+    });
+    bob.set_authenticator(SharedSecret("open sesame"));
+    let bob_id = bob.local_peer_id();
+
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, _| {
+        checked.load(Ordering::SeqCst)
+    })
+    .await;
+    assert!(alice.pending_auth().is_pending(&bob_id));
+    let validation = alice.receive(chat_from(bob_id, 1)).unwrap();
+    assert!(matches!(validation.acceptance, MessageAcceptance::Ignore));
+    assert_eq!(alice.history().count(), 0);
+
+    // The authenticator is polled again every tick
+    open.store(true, Ordering::SeqCst);
+    alice.tick();
+    assert!(alice.pending_auth().is_authenticated(&bob_id));
+    let validation = alice.receive(chat_from(bob_id, 2)).unwrap();
+    assert!(matches!(validation.acceptance, MessageAcceptance::Accept));
+    assert_eq!(alice.history().count(), 1);
+}
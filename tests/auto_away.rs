@@ -0,0 +1,138 @@
+// Away status: automatic after idling at the keyboard, or set with `/status`.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    clock,
+    commands::{self, StatusCommand, UserCommand},
+    control,
+    presence::Presence,
+    roster::{Roster, RosterEvent},
+};
+use libp2p::PeerId;
+
+#[test]
+fn going_away_and_coming_back_keep_peers_in_the_roster() {
+    let alice = PeerId::random();
+    let mut presence = Presence::new(10);
+    let mut roster = Roster::default();
+    let room = "lobby".to_string();
+    roster.subscribe("lobby", alice);
+    presence.heartbeat("lobby", alice, 10, false, 100);
+    roster.update(&presence, 100);
+
+    presence.heartbeat("lobby", alice, 10, true, 101);
+    assert_eq!(
+        roster.update(&presence, 101),
+        [RosterEvent::Away {
+            room: room.clone(),
+            peer: alice
+        }]
+    );
+    assert!(roster.is_away("lobby", &alice));
+    // Chat messages between heartbeats don't change the away state
+    presence.seen("lobby", alice, 102);
+    assert_eq!(roster.update(&presence, 102), []);
+
+    presence.heartbeat("lobby", alice, 10, false, 103);
+    assert_eq!(
+        roster.update(&presence, 103),
+        [RosterEvent::Back { room, peer: alice }]
+    );
+    assert_eq!(roster.online("lobby").count(), 1);
+}
+
+#[test]
+fn status_command_parses() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(
+        parse("/status"),
+        Ok(UserCommand::Status(StatusCommand::Show))
+    );
+    assert_eq!(
+        parse("/status away"),
+        Ok(UserCommand::Status(StatusCommand::Away))
+    );
+    assert_eq!(
+        parse("/status online"),
+        Ok(UserCommand::Status(StatusCommand::Online))
+    );
+    assert_eq!(
+        parse("/status auto"),
+        Ok(UserCommand::Status(StatusCommand::Auto))
+    );
+    assert!(parse("/status busy").is_err());
+}
+
+#[tokio::test]
+async fn idle_peers_go_away_and_come_back_on_input() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&["--away-after", "1"])).await;
+    let mut events = alice.subscribe_roster();
+    let (bob_id, room) = (bob.local_peer_id(), common::topic().hash().into_string());
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.roster().is_subscribed(&room, &bob_id)
+            && common::has_subscriber(alice, &control)
+            && alice
+                .presence()
+                .status(&room, &bob_id, clock::unix_time())
+                .is_some()
+    })
+    .await;
+    alice.tick();
+    assert!(matches!(events.try_recv(), Ok(RosterEvent::Joined { .. })));
+
+    // Network traffic isn't input, so bob goes away while messages keep flowing
+    common::run_for(&mut alice, &mut bob, Duration::from_millis(1100)).await;
+    bob.tick();
+    assert!(bob.is_away());
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.presence().is_away(&room, &bob_id)
+    })
+    .await;
+    alice.tick();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        RosterEvent::Away {
+            room: room.clone(),
+            peer: bob_id
+        }
+    );
+    assert_eq!(alice.roster().online(&room).count(), 1);
+
+    bob.handle_line("/peers").await;
+    assert!(!bob.is_away());
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        !alice.presence().is_away(&room, &bob_id)
+    })
+    .await;
+    alice.tick();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        RosterEvent::Back { room, peer: bob_id }
+    );
+    assert_eq!(alice.history().count(), 0, "no chat messages were sent");
+}
+
+#[tokio::test]
+async fn manual_status_overrides_auto_away_until_cleared() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--away-after", "1"])).await;
+
+    alice.handle_line("/status away").await;
+    assert!(alice.is_away());
+    alice.handle_line("/peers").await;
+    assert!(alice.is_away(), "typing doesn't end a manual away");
+
+    alice.handle_line("/status online").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    alice.tick();
+    assert!(!alice.is_away(), "idling doesn't end a manual online");
+
+    alice.handle_line("/status auto").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    alice.tick();
+    assert!(alice.is_away());
+}
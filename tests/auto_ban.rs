@@ -0,0 +1,120 @@
+// Automatic temporary bans for flooding and invalid messages.
+mod common;
+
+use std::{env, process};
+
+use concurrent_chat_server::{
+    autoban::{AutoBanSettings, AutoBanner, TempBan, Violation, INVALID_WINDOW, RATE_WINDOW},
+    chat::ChatNode,
+    clock,
+    config::Config,
+};
+use libp2p::PeerId;
+
+fn banner() -> AutoBanner {
+    AutoBanner::new(AutoBanSettings {
+        rate_limit: 3,
+        invalid_limit: 2,
+        ban_duration: 600,
+    })
+}
+
+#[test]
+fn flooding_past_the_rate_limit_bans() {
+    let mut bans = banner();
+    let peer = PeerId::random();
+    for _ in 0..3 {
+        assert_eq!(bans.record_message(peer, 100), None);
+    }
+    let ban = bans.record_message(peer, 100).expect("fourth message bans");
+    assert_eq!(ban.violation, Violation::RateLimit);
+    assert_eq!(ban.expires_at, 700);
+    assert!(bans.is_banned(&peer));
+
+    // Messages spread out over several windows stay under the limit
+    let calm = PeerId::random();
+    for i in 0..10 {
+        assert_eq!(bans.record_message(calm, i * RATE_WINDOW), None);
+    }
+}
+
+#[test]
+fn repeat_offenses_double_the_ban() {
+    let mut bans = banner();
+    let peer = PeerId::random();
+    let flood =
+        |bans: &mut AutoBanner, now| (0..4).find_map(|_| bans.record_invalid(peer, now));
+
+    let first = flood(&mut bans, 0).unwrap();
+    assert_eq!(first.violation, Violation::InvalidMessages);
+    assert_eq!(first.expires_at - first.started_at, 600);
+
+    assert_eq!(bans.expire(599), vec![]);
+    assert_eq!(bans.expire(600), vec![first]);
+    assert!(!bans.is_banned(&peer));
+
+    let second = flood(&mut bans, 600 + INVALID_WINDOW).unwrap();
+    assert_eq!(second.offense, 2);
+    assert_eq!(second.expires_at - second.started_at, 1200);
+}
+
+#[test]
+fn restored_bans_skip_expired_ones_and_remember_offenses() {
+    let (active, expired) = (PeerId::random(), PeerId::random());
+    let ban = |peer, offense, expires_at| TempBan {
+        peer,
+        violation: Violation::RateLimit,
+        offense,
+        started_at: 0,
+        expires_at,
+    };
+    let mut bans = banner();
+    bans.restore([ban(active, 2, 2000), ban(expired, 1, 500)], 1000);
+    assert!(bans.is_banned(&active));
+    assert!(!bans.is_banned(&expired));
+
+    // Lifting a ban early still counts the offense towards the next one
+    bans.unban(&active);
+    let next = (0..4)
+        .find_map(|_| bans.record_message(active, 1000))
+        .unwrap();
+    assert_eq!(next.offense, 3);
+    assert_eq!(next.expires_at - next.started_at, 2400);
+}
+
+#[test]
+fn durations_are_readable() {
+    assert_eq!(clock::format_duration(45), "45s");
+    assert_eq!(clock::format_duration(600), "10m");
+    assert_eq!(clock::format_duration(90), "1m30s");
+    assert_eq!(clock::format_duration(7500), "2h5m");
+}
+
+#[tokio::test]
+async fn bans_survive_a_restart_until_lifted() {
+    let path = env::temp_dir().join(format!("p2p-chat-bans-{}.json", process::id()));
+    let peer = PeerId::random();
+    let config = Config {
+        bans: vec![TempBan {
+            peer,
+            violation: Violation::RateLimit,
+            offense: 1,
+            started_at: clock::unix_time(),
+            expires_at: clock::unix_time() + 600,
+        }],
+        ..Config::default()
+    };
+    config.save(&path).unwrap();
+
+    let cli = common::cli(&["--config", path.to_str().unwrap()]);
+    let mut chat = ChatNode::new(&cli).unwrap();
+    assert!(chat.is_blocked(&peer));
+    assert_eq!(chat.bans().bans().count(), 1);
+
+    chat.handle_line(&format!("/unblock {peer}")).await;
+    assert!(!chat.is_blocked(&peer));
+    chat.flush_writes().await;
+    let saved = Config::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(saved.bans.is_empty());
+}
@@ -0,0 +1,145 @@
+// Publishing with nobody to publish to yet: messages wait for a peer instead of being lost.
+mod common;
+
+use std::time::{Duration, Instant};
+
+use concurrent_chat_server::{
+    error::ChatError,
+    message::ChatMessage,
+    outbox::{self, Outbox, FIRST_RETRY, MAX_RETRY, PUBLISH_TIMEOUT},
+};
+use libp2p::{futures::FutureExt, gossipsub::TopicHash};
+use tokio::sync::oneshot;
+
+fn chat(body: &str) -> Vec<u8> {
+    ChatMessage::new("alice", body, 1).encode()
+}
+
+#[test]
+fn retries_back_off_exponentially_up_to_a_cap() {
+    assert_eq!(outbox::backoff(0), FIRST_RETRY);
+    assert_eq!(outbox::backoff(1), FIRST_RETRY * 2);
+    assert_eq!(outbox::backoff(3), FIRST_RETRY * 8);
+    assert_eq!(outbox::backoff(10), MAX_RETRY);
+    assert_eq!(outbox::backoff(u32::MAX), MAX_RETRY);
+}
+
+#[test]
+fn the_outbox_retries_until_the_deadline_and_drops_cancelled_messages() {
+    let topic = TopicHash::from_raw("room");
+    let now = Instant::now();
+    let mut outbox = Outbox::default();
+    let (reply, outcome) = oneshot::channel();
+    outbox.push(topic.clone(), b"hi".to_vec(), Some(reply), now);
+    assert_eq!(outbox.next_retry(), Some(now));
+
+    // Each failed try waits twice as long as the one before
+    let queued = outbox.take_due(now, None).pop().unwrap();
+    assert!(outbox.retry_later(queued, now).is_none());
+    assert_eq!(outbox.next_retry(), Some(now + FIRST_RETRY));
+    assert!(outbox.take_due(now, None).is_empty());
+    let later = now + FIRST_RETRY;
+    let queued = outbox.take_due(later, None).pop().unwrap();
+    assert!(outbox.retry_later(queued, later).is_none());
+    assert_eq!(outbox.next_retry(), Some(later + FIRST_RETRY * 2));
+
+    // A peer subscribing makes the topic's messages due at once
+    let other = TopicHash::from_raw("elsewhere");
+    assert!(outbox.take_due(later, Some(&other)).is_empty());
+    let queued = outbox.take_due(later, Some(&topic)).pop().unwrap();
+
+    // Past the deadline the message comes back to be failed
+    let expired = outbox.retry_later(queued, now + PUBLISH_TIMEOUT).unwrap();
+    assert!(outbox.is_empty());
+    assert!(expired
+        .resolve(Err(ChatError::PublishTimedOut(PUBLISH_TIMEOUT)))
+        .is_none());
+    assert!(matches!(
+        outcome.now_or_never(),
+        Some(Ok(Err(ChatError::PublishTimedOut(_))))
+    ));
+
+    // Nobody waiting for the outcome cancels the message
+    let (reply, outcome) = oneshot::channel();
+    outbox.push(topic.clone(), b"hi".to_vec(), Some(reply), now);
+    drop(outcome);
+    assert!(outbox.take_due(now, None).is_empty());
+    assert!(outbox.is_empty());
+
+    // Lines typed into an empty room have their errors printed instead
+    outbox.push(topic.clone(), b"hi".to_vec(), None, now);
+    assert_eq!(outbox.held(), 0);
+    assert!(outbox
+        .take_due(now, None)
+        .pop()
+        .unwrap()
+        .resolve(Err(ChatError::Shutdown))
+        .is_some());
+    // and are kept as they were typed, to be encoded once they go out
+    outbox.hold(topic, ChatMessage::new("alice", "hi", 1), now);
+    assert_eq!(outbox.held(), 1);
+    let held = outbox.take_due(now, None).pop().unwrap();
+    assert!(held.data.is_empty() && held.message.is_some());
+    assert!(held.resolve(Err(ChatError::Shutdown)).is_some());
+}
+
+#[tokio::test]
+async fn messages_wait_for_a_peer_to_join() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let topic = alice.topic().hash();
+    let mut sent = Box::pin(alice.publish_with_backpressure(topic, chat("anyone there?")));
+    assert_eq!(alice.queued(), 1);
+    assert!(sent.as_mut().now_or_never().is_none());
+
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| alice.queued() == 0 && bob.history().count() == 1,
+    )
+    .await;
+    assert_eq!(
+        &*bob.history().next().unwrap().message.body,
+        "anyone there?"
+    );
+    assert!(matches!(sent.now_or_never(), Some(Ok(_))));
+}
+
+#[tokio::test]
+async fn dropping_the_future_cancels_the_message() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let sent = alice.publish_with_backpressure(alice.topic().hash(), chat("never mind"));
+    assert_eq!(alice.queued(), 1);
+    drop(sent);
+    tokio::time::sleep(FIRST_RETRY).await;
+    alice.tick();
+    assert_eq!(alice.queued(), 0);
+}
+
+#[tokio::test]
+async fn lines_typed_into_an_empty_room_go_out_once_someone_joins() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.handle_line("hello?").await;
+    assert_eq!(alice.queued(), 1);
+
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| alice.queued() == 0 && bob.history().count() == 1,
+    )
+    .await;
+    assert_eq!(&*bob.history().next().unwrap().message.body, "hello?");
+}
+
+#[tokio::test]
+async fn read_only_nodes_refuse_at_once() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--no-publish"])).await;
+    let sent = alice.publish_with_backpressure(alice.topic().hash(), chat("hi"));
+    assert_eq!(alice.queued(), 0);
+    assert!(matches!(sent.await, Err(ChatError::ReadOnlyMode)));
+}
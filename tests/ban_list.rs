@@ -0,0 +1,166 @@
+// The one persisted list of blocks and bans, reapplied on startup and reviewed with /bans.
+mod common;
+
+use std::{env, fs, path::PathBuf, process};
+
+use concurrent_chat_server::{
+    autoban::Violation,
+    bans::{BanOrigin, BanRecord, BanScope},
+    chat::ChatNode,
+    clock,
+    commands::{self, BansCommand, UserCommand},
+    config::Config,
+};
+use libp2p::PeerId;
+
+fn config_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("p2p-chat-banlist-{name}-{}.json", process::id()))
+}
+
+fn record(peer: PeerId, scope: BanScope, origin: BanOrigin, expires_at: Option<u64>) -> BanRecord {
+    BanRecord {
+        peer,
+        scope,
+        origin,
+        created_at: clock::unix_time() - 60,
+        expires_at,
+        reason: "spam".to_string(),
+    }
+}
+
+fn auto() -> BanOrigin {
+    BanOrigin::Auto {
+        violation: Violation::RateLimit,
+        offense: 1,
+    }
+}
+
+#[test]
+fn bans_are_reapplied_and_expired_ones_dropped_on_restart() {
+    let path = config_path("restart");
+    let now = clock::unix_time();
+    let (active, expired, blocked, banned) = (
+        PeerId::random(),
+        PeerId::random(),
+        PeerId::random(),
+        PeerId::random(),
+    );
+    let room = common::topic().hash().into_string();
+    let mut config = Config::default();
+    config
+        .banlist
+        .insert(record(active, BanScope::Global, auto(), Some(now + 600)));
+    // Ran out while the node was down
+    config
+        .banlist
+        .insert(record(expired, BanScope::Global, auto(), Some(now - 1)));
+    config
+        .banlist
+        .insert(record(blocked, BanScope::Global, BanOrigin::Manual, None));
+    let moderator = BanOrigin::Moderator {
+        by: PeerId::random(),
+    };
+    config
+        .banlist
+        .insert(record(banned, BanScope::Room(room), moderator, None));
+    config.save(&path).unwrap();
+
+    let (logs, _guard) = common::capture_logs();
+    let node = ChatNode::new(&common::cli(&["--config", path.to_str().unwrap()])).unwrap();
+    let saved = Config::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert!(node.is_blocked(&active));
+    assert!(!node.is_blocked(&expired));
+    assert!(node.is_blocked(&blocked));
+    assert!(!node.is_blocked(&banned));
+    assert!(node.is_removed(&banned));
+    assert!(logs.contains(&format!(
+        "[ban] automatic ban on {expired} everywhere has ended"
+    )));
+    let peers: Vec<PeerId> = saved.banlist.iter().map(|r| r.peer).collect();
+    assert_eq!(peers, [active, blocked, banned]);
+}
+
+#[test]
+fn bans_from_older_configs_are_migrated() {
+    let path = config_path("legacy");
+    let peer = PeerId::random();
+    fs::write(
+        &path,
+        format!(
+            r#"{{"bans": [{{"peer": "{peer}", "violation": "repetition", "offense": 2,
+                "started_at": 1, "expires_at": {}}}]}}"#,
+            clock::unix_time() + 600
+        ),
+    )
+    .unwrap();
+
+    let node = ChatNode::new(&common::cli(&["--config", path.to_str().unwrap()])).unwrap();
+    let saved = Config::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(node.is_blocked(&peer));
+    assert!(saved.bans.is_empty());
+    let [record] = saved.banlist.iter().collect::<Vec<_>>()[..] else {
+        panic!("one migrated record");
+    };
+    assert_eq!(
+        record.origin,
+        BanOrigin::Auto {
+            violation: Violation::Repetition,
+            offense: 2
+        }
+    );
+}
+
+#[tokio::test]
+async fn bans_command_lifts_blocks_and_automatic_bans() {
+    let path = config_path("command");
+    let (auto_banned, blocked) = (PeerId::random(), PeerId::random());
+    let mut config = Config::default();
+    let expires_at = Some(clock::unix_time() + 600);
+    config
+        .banlist
+        .insert(record(auto_banned, BanScope::Global, auto(), expires_at));
+    config.save(&path).unwrap();
+    let mut node = ChatNode::new(&common::cli(&["--config", path.to_str().unwrap()])).unwrap();
+
+    node.handle_line(&format!("/block {blocked} rude")).await;
+    node.flush_writes().await;
+    let saved = Config::load(&path).unwrap();
+    assert!(saved
+        .banlist
+        .iter()
+        .any(|r| r.peer == blocked && r.origin == BanOrigin::Manual && r.reason == "rude"));
+
+    node.handle_line("/bans clear auto").await;
+    assert!(!node.is_blocked(&auto_banned));
+    node.handle_line(&format!("/bans remove {blocked}")).await;
+    assert!(!node.is_blocked(&blocked));
+    node.flush_writes().await;
+    let saved = Config::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(saved.banlist.is_empty());
+}
+
+#[test]
+fn bans_commands_parse() {
+    let peer = PeerId::random();
+    assert_eq!(
+        commands::parse("/bans"),
+        Some(Ok(UserCommand::Bans(BansCommand::List)))
+    );
+    assert_eq!(
+        commands::parse(&format!("/bans remove {peer}")),
+        Some(Ok(UserCommand::Bans(BansCommand::Remove(peer))))
+    );
+    assert_eq!(
+        commands::parse("/bans clear expired"),
+        Some(Ok(UserCommand::Bans(BansCommand::ClearExpired)))
+    );
+    assert_eq!(
+        commands::parse("/bans clear auto"),
+        Some(Ok(UserCommand::Bans(BansCommand::ClearAuto)))
+    );
+    assert!(commands::parse("/bans clear everything").unwrap().is_err());
+}
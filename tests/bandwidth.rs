@@ -0,0 +1,82 @@
+// The outbound bandwidth cap: the flag, the pacing of writes and a chat that still works under it.
+mod common;
+
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use concurrent_chat_server::{
+    bandwidth::{BandwidthLimiter, ThrottledStream, MIN_BURST},
+    cli::Cli,
+    message::ChatMessage,
+};
+use libp2p::futures::{io::Cursor, AsyncWriteExt};
+
+#[test]
+fn upload_cap_flag_parses() {
+    let cli = common::cli(&["--max-upload-kbps", "256"]);
+    assert_eq!(cli.max_upload_kbps, Some(256));
+    assert_eq!(common::cli(&[]).max_upload_kbps, None);
+    assert!(Cli::try_parse_from(["p2p-chat", "--max-upload-kbps", "0"]).is_err());
+    assert_eq!(BandwidthLimiter::from_kbps(256).bytes_per_second(), 32_000);
+}
+
+#[tokio::test]
+async fn limiter_hands_out_a_burst_then_the_rate() {
+    // 10 000 bytes a second, with the smallest burst
+    let limiter = BandwidthLimiter::from_kbps(80);
+    let started = Instant::now();
+    assert_eq!(limiter.acquire(1_000_000).await, MIN_BURST);
+    assert!(started.elapsed() < Duration::from_millis(50));
+
+    let mut taken = 0;
+    while taken < 10_000 {
+        taken += limiter.acquire(10_000 - taken).await;
+    }
+    let elapsed = started.elapsed();
+    assert!(
+        elapsed >= Duration::from_millis(550) && elapsed < Duration::from_secs(2),
+        "{elapsed:?}"
+    );
+}
+
+#[tokio::test]
+async fn throttled_writes_wait_for_the_cap_and_lose_nothing() {
+    let data: Vec<u8> = (0..MIN_BURST + 5_000).map(|i| i as u8).collect();
+    let limiter = BandwidthLimiter::from_kbps(80);
+    let mut stream = ThrottledStream::new(Cursor::new(Vec::new()), Some(limiter));
+    let started = Instant::now();
+    stream.write_all(&data).await.unwrap();
+    // The burst goes out at once and the rest at 10 000 bytes a second
+    assert!(started.elapsed() >= Duration::from_millis(400));
+    stream.flush().await.unwrap();
+    assert_eq!(stream.get_ref().get_ref(), &data);
+
+    let mut free = ThrottledStream::new(Cursor::new(Vec::new()), None);
+    let started = Instant::now();
+    free.write_all(&data).await.unwrap();
+    assert!(started.elapsed() < Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn chat_gets_through_under_the_cap() {
+    let capped = common::cli(&["--max-upload-kbps", "512"]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&capped).await;
+    let (mut bob, _) = common::spawn_chat_node(&capped).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(30), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    // 48 KB at 64 000 bytes a second, after the 16 000 byte burst
+    let message = ChatMessage::new("bob", "x".repeat(48_000), 1);
+    let started = Instant::now();
+    bob.publish(&message).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(30), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(400), "{elapsed:?}");
+}
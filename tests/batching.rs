@@ -0,0 +1,143 @@
+// Batching small chat messages: when batches go out, and receivers seeing single messages.
+mod common;
+
+use std::{
+    collections::HashMap,
+    env, fs, process,
+    time::{Duration, Instant},
+};
+
+use concurrent_chat_server::{
+    batch::{self, Batch, Batcher},
+    config::Config,
+    message::ChatMessage,
+    room::RoomSettings,
+};
+use libp2p::futures::{stream, StreamExt};
+use tokio::sync::oneshot;
+
+fn message(body: &str) -> ChatMessage {
+    ChatMessage::new("sensor", body, 1)
+}
+
+#[test]
+fn batches_go_out_when_full_or_due() {
+    let now = Instant::now();
+    let mut batcher = Batcher::new(Duration::from_millis(50), 200);
+    assert_eq!(batcher.deadline(), None);
+    assert!(batcher.push(message("21.5"), now).is_none());
+    assert_eq!(batcher.deadline(), Some(now + Duration::from_millis(50)));
+    // Later messages don't push the deadline back
+    let later = now + Duration::from_millis(10);
+    assert!(batcher.push(message("21.6"), later).is_none());
+    assert_eq!(batcher.deadline(), Some(now + Duration::from_millis(50)));
+    assert_eq!(batcher.take().unwrap().batch.len(), 2);
+    assert_eq!(batcher.deadline(), None);
+    assert!(batcher.take().is_none());
+
+    // A message that doesn't fit sends the ones waiting and starts the next batch
+    let mut sent = Vec::new();
+    for n in 0..10 {
+        sent.extend(batcher.push(message(&format!("reading {n}")), now));
+    }
+    assert!(!sent.is_empty());
+    for full in &sent {
+        assert!(full.encode().len() <= batcher.max_bytes());
+    }
+    let waiting = batcher.take().map_or(0, |rest| rest.batch.len());
+    let total: usize = sent.iter().map(|full| full.batch.len()).sum();
+    assert_eq!(total + waiting, 10);
+
+    let mut many = Batcher::new(Duration::from_secs(1), usize::MAX);
+    assert_eq!(many.max_bytes(), batch::MAX_BATCH_BYTES);
+    let full = (0..batch::MAX_BATCH_MESSAGES)
+        .find_map(|n| many.push(message(&n.to_string()), now))
+        .unwrap();
+    assert_eq!(full.batch.len(), batch::MAX_BATCH_MESSAGES);
+}
+
+#[test]
+fn batches_decode_apart_from_single_messages() {
+    let two = Batch {
+        batch: vec![message("21.5"), message("21.6")],
+    };
+    assert_eq!(Batch::decode(&two.encode()), Some(two));
+    assert_eq!(Batch::decode(&message("21.5").encode()), None);
+    assert_eq!(Batch::decode(br#"{"batch":[]}"#), None);
+    let too_many = Batch {
+        batch: vec![message("x"); batch::MAX_BATCH_MESSAGES + 1],
+    };
+    assert_eq!(Batch::decode(&too_many.encode()), None);
+}
+
+#[tokio::test]
+async fn receivers_see_batched_messages_one_by_one() {
+    let batching = common::cli(&["--batch-ms", "100"]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&batching).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    // The window is up while the node runs, so the lines go out together
+    let lines = stream::iter((0..20).map(|n| format!("reading {n}")));
+    let published = alice.stats().counters.published;
+    let (done, finished) = oneshot::channel();
+    let alice_runs = alice.run(lines, async {
+        let _ = finished.await;
+    });
+    let bob_listens = async {
+        let all = tokio::time::timeout(Duration::from_secs(10), async {
+            while bob.history().count() < 20 {
+                let event = bob.swarm.select_next_some().await;
+                bob.handle_event(event);
+            }
+        })
+        .await;
+        let _ = done.send(());
+        all.is_ok()
+    };
+    let ((), all) = tokio::join!(alice_runs, bob_listens);
+    assert!(all, "bob got {} of 20 messages", bob.history().count());
+    let bodies: Vec<&str> = bob.history().map(|m| &*m.message.body).collect();
+    assert_eq!(bodies[0], "reading 0");
+    assert_eq!(bodies[19], "reading 19");
+    // One batch, and the leave announced on shutdown
+    assert!(alice.stats().counters.published - published <= 3);
+}
+
+#[tokio::test]
+async fn rooms_can_turn_batching_off() {
+    let path = env::temp_dir().join(format!("p2p-chat-batching-{}.json", process::id()));
+    let room = RoomSettings {
+        batch_ms: Some(0),
+        ..RoomSettings::default()
+    };
+    let config = Config {
+        rooms: HashMap::from([(common::topic().hash().into_string(), room)]),
+        ..Config::default()
+    };
+    config.save(&path).unwrap();
+    let cli = common::cli(&["--batch-ms", "100", "--config", path.to_str().unwrap()]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&cli).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    let published = alice.stats().counters.published;
+    alice.handle_line("first reading").await;
+    alice.handle_line("second reading").await;
+    assert_eq!(alice.stats().counters.published - published, 2);
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 2
+    })
+    .await;
+    fs::remove_file(path).unwrap();
+}
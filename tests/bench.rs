@@ -0,0 +1,116 @@
+// `p2p-chat bench`: its flags, the statistics it reports and short runs over real nodes.
+use std::time::Duration;
+
+use clap::Parser;
+use concurrent_chat_server::{
+    bench::{self, BenchResult, BenchSpec},
+    cli::{BenchTransport, Cli, Command},
+};
+
+#[test]
+fn bench_flags_parse() {
+    let cli = Cli::parse_from(["p2p-chat", "bench"]);
+    let Some(Command::Bench {
+        nodes,
+        transport,
+        sizes,
+        messages,
+    }) = cli.command
+    else {
+        panic!("expected the bench subcommand");
+    };
+    assert_eq!(nodes, 2);
+    assert_eq!(
+        transport,
+        [
+            BenchTransport::Tcp,
+            BenchTransport::Quic,
+            BenchTransport::Memory
+        ]
+    );
+    assert_eq!(sizes, [64, 1024, 16384]);
+    assert_eq!(messages, 1000);
+
+    let cli = Cli::parse_from([
+        "p2p-chat",
+        "bench",
+        "--nodes",
+        "5",
+        "--transport",
+        "memory,tcp",
+        "--sizes",
+        "10,20",
+    ]);
+    let Some(Command::Bench {
+        nodes,
+        transport,
+        sizes,
+        ..
+    }) = cli.command
+    else {
+        panic!("expected the bench subcommand");
+    };
+    assert_eq!(nodes, 5);
+    assert_eq!(transport, [BenchTransport::Memory, BenchTransport::Tcp]);
+    assert_eq!(sizes, [10, 20]);
+    assert!(Cli::try_parse_from(["p2p-chat", "bench", "--nodes", "1"]).is_err());
+    assert!(Cli::try_parse_from(["p2p-chat", "bench", "--transport", "carrier-pigeon"]).is_err());
+}
+
+#[test]
+fn results_report_rates_and_percentiles() {
+    let result = BenchResult {
+        spec: BenchSpec {
+            transport: BenchTransport::Memory,
+            nodes: 3,
+            payload: 64,
+            messages: 100,
+        },
+        latencies: (1..=200).map(Duration::from_millis).collect(),
+        elapsed: Duration::from_secs(2),
+    };
+    assert_eq!(result.spec.expected(), 200);
+    assert_eq!(result.delivered(), 200);
+    assert_eq!(result.rate(), 50.0);
+    assert_eq!(result.percentile(50), Some(Duration::from_millis(100)));
+    assert_eq!(result.percentile(99), Some(Duration::from_millis(198)));
+    assert_eq!(result.percentile(100), Some(Duration::from_millis(200)));
+    let table = bench::table(std::slice::from_ref(&result));
+    let row = table.lines().nth(1).unwrap();
+    assert!(row.starts_with("memory"), "{row}");
+    assert!(
+        row.contains("200/200") && row.contains("100.00 ms"),
+        "{row}"
+    );
+
+    // A run that delivered nothing has no latencies to report
+    let nothing = BenchResult {
+        latencies: Vec::new(),
+        elapsed: Duration::ZERO,
+        ..result
+    };
+    assert_eq!(nothing.percentile(50), None);
+    assert_eq!(nothing.rate(), 0.0);
+    assert!(bench::table(&[nothing]).contains("0/200"));
+}
+
+#[tokio::test]
+async fn short_runs_deliver_every_message() {
+    let specs: Vec<BenchSpec> = [BenchTransport::Memory, BenchTransport::Tcp]
+        .into_iter()
+        .map(|transport| BenchSpec {
+            transport,
+            nodes: 3,
+            payload: 2048,
+            messages: 50,
+        })
+        .collect();
+    let results = bench::run_all(&specs).await.unwrap();
+    for result in &results {
+        assert_eq!(result.delivered(), 100, "{:?}", result.spec);
+        assert!(result.latencies.is_sorted());
+        assert!(result.rate() > 0.0);
+        assert!(result.percentile(99) <= Some(result.elapsed));
+    }
+    assert_eq!(bench::table(&results).lines().count(), 3);
+}
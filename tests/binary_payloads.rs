@@ -0,0 +1,91 @@
+// Messages whose payload isn't UTF-8 text.
+mod common;
+
+use std::{env, fs};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, UserCommand},
+    message::{self, ChatMessage, Identity, Incoming},
+};
+use libp2p::{
+    gossipsub::{self, MessageId},
+    PeerId,
+};
+
+// A gzip header followed by bytes that aren't valid UTF-8
+const GZIP: &[u8] = &[0x1f, 0x8b, 0x08, 0x00, 0xde, 0xad, 0xbe, 0xef, 0xff, 0xfe];
+
+fn plain(data: &[u8], seq: u64) -> gossipsub::Event {
+    common::message_event(PeerId::random(), seq, data.to_vec())
+}
+
+#[test]
+fn binary_payloads_get_a_preview_instead_of_replacement_characters() {
+    assert_eq!(
+        message::binary_preview(GZIP),
+        "⟨binary, 10 bytes: 1f8b0800deadbeef…⟩"
+    );
+    assert_eq!(message::binary_preview(&[0xff]), "⟨binary, 1 bytes: ff⟩");
+
+    let incoming = Incoming::decode(GZIP, 7);
+    assert_eq!(&*incoming.chat.body, message::binary_preview(GZIP));
+    assert!(!incoming.chat.body.contains('\u{fffd}'));
+    assert_eq!(incoming.chat.timestamp, 7);
+    assert_eq!(incoming.binary.as_deref(), Some(GZIP));
+
+    // Text is kept as text, control characters and all, and the sanitizer deals with those
+    let text = Incoming::decode(b"null\0byte\x07bell", 0);
+    assert_eq!(text.binary, None);
+    assert_eq!(&*text.chat.body, "null\0byte\x07bell");
+    let (line, _) = message::render(
+        &text.chat,
+        false,
+        Identity::Unverified,
+        &MessageId::from("1"),
+        &PeerId::random(),
+    );
+    assert!(line.contains("'nullbytebell'"), "{line}");
+}
+
+#[tokio::test]
+async fn binary_messages_are_counted_and_saved_byte_for_byte() {
+    let mut node = ChatNode::new(&common::cli(&[])).unwrap();
+    node.receive(plain(GZIP, 1));
+    node.receive(plain(b"just text", 2));
+
+    assert_eq!(node.stats().counters.binary, 1);
+    assert!(node.stats().to_string().contains("binary: 1"));
+    let stored = node.history().next().unwrap();
+    assert_eq!(stored.binary.as_deref(), Some(GZIP));
+    assert_eq!(&*stored.message.body, message::binary_preview(GZIP));
+    let ids: Vec<_> = node.history().map(|stored| stored.id.clone()).collect();
+
+    let dir = env::temp_dir().join(format!("p2p-chat-binary-{}", PeerId::random()));
+    fs::create_dir_all(&dir).unwrap();
+    let (binary, text) = (dir.join("payload.gz"), dir.join("text.txt"));
+    node.handle_line(&format!("/save {} {}", ids[0], binary.display()))
+        .await;
+    node.handle_line(&format!("/save {} {}", ids[1], text.display()))
+        .await;
+    node.flush_writes().await;
+    let saved = (fs::read(&binary), fs::read(&text));
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(saved.0.unwrap(), GZIP);
+    assert_eq!(saved.1.unwrap(), b"just text");
+}
+
+#[test]
+fn save_takes_an_id_and_a_path() {
+    assert_eq!(
+        commands::parse("/save abc123 /tmp/out file.bin"),
+        Some(Ok(UserCommand::Save {
+            id: "abc123".to_string(),
+            path: "/tmp/out file.bin".into(),
+        }))
+    );
+    assert!(matches!(commands::parse("/save abc123"), Some(Err(_))));
+    // JSON messages never carry a binary payload
+    let chat = ChatMessage::new("alice", "hi", 1);
+    assert_eq!(Incoming::decode(&chat.encode(), 0), Incoming::from(chat));
+}
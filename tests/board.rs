@@ -0,0 +1,201 @@
+// The room's bulletin board: posts with `/post`, pins by moderators and syncing newcomers.
+mod common;
+
+use std::{cmp::Ordering, env, fs, path::Path, process, time::Duration};
+
+use concurrent_chat_server::{
+    board::{self, BulletinBoard, Post, SignedPost, VersionVector},
+    chat::ChatNode,
+    commands::{self, UserCommand},
+};
+use libp2p::{identity::Keypair, PeerId};
+
+fn signed_post(keypair: &Keypair, title: &str, posted_at: u64) -> SignedPost {
+    let author = keypair.public().to_peer_id();
+    let post = Post::new(author, "alice", title, "details inside", posted_at);
+    SignedPost::sign(keypair, &post).unwrap()
+}
+
+#[test]
+fn board_commands_parse() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(
+        parse("/post Meetup | Friday at 6, bring snacks"),
+        Ok(UserCommand::Post {
+            title: "Meetup".to_string(),
+            body: "Friday at 6, bring snacks".to_string()
+        })
+    );
+    assert_eq!(parse("/board"), Ok(UserCommand::Board));
+    assert_eq!(
+        parse("/unpin 3f2a9c1e"),
+        Ok(UserCommand::Pin {
+            post: "3f2a9c1e".to_string(),
+            pinned: false
+        })
+    );
+    assert!(parse("/post no body").is_err());
+    assert!(parse("/post | body only").is_err());
+    assert!(parse("/pin").is_err());
+}
+
+#[test]
+fn version_vectors_tell_later_changes_from_concurrent_ones() {
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    let mut a = VersionVector::default();
+    a.increment(alice);
+    let mut b = a.clone();
+    b.increment(bob);
+    assert_eq!(b.compare(&a), Some(Ordering::Greater));
+    assert_eq!(a.compare(&b), Some(Ordering::Less));
+    a.increment(alice);
+    assert_eq!(a.compare(&b), None);
+    a.merge(&b);
+    assert_eq!(a.compare(&b), Some(Ordering::Greater));
+}
+
+#[test]
+fn boards_converge_whatever_the_order() {
+    let (author, mod1, mod2) = (
+        Keypair::generate_ed25519(),
+        PeerId::random(),
+        PeerId::random(),
+    );
+    let old = signed_post(&author, "old news", 100);
+    let new = signed_post(&author, "new news", 200);
+    let mut one = BulletinBoard::new("lobby", None);
+    let mut two = BulletinBoard::new("lobby", None);
+    let old_id = one.insert(&old).unwrap().unwrap();
+    one.insert(&new).unwrap();
+    assert_eq!(one.insert(&new), Ok(None), "posts are only added once");
+    two.insert(&new).unwrap();
+    two.insert(&old).unwrap();
+
+    // One moderator pins the old post while another, unaware of that, unpins it
+    let pin = one.pin(&old_id, mod1, true).unwrap();
+    let unpin = two.pin(&old_id, mod2, false).unwrap();
+    assert!(one.apply_pin(&unpin));
+    assert!(two.apply_pin(&pin));
+    assert!(!one.apply_pin(&pin), "a change is only applied once");
+    for board in [&one, &two] {
+        let titles: Vec<&str> = board.sorted().iter().map(|p| p.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            ["old news", "new news"],
+            "pinning wins, then newest first"
+        );
+    }
+
+    // A later unpin, made after seeing both, replaces the pin
+    let unpin = one.pin(&old_id, mod2, false).unwrap();
+    assert!(two.apply_pin(&unpin));
+    assert!(!two.get(&old_id).unwrap().pinned);
+    assert_eq!(one.find(&old_id.to_string()[..8]).unwrap().id, old_id);
+}
+
+#[test]
+fn posts_must_be_signed_by_their_author() {
+    let (author, forger) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let post = Post::new(author.public().to_peer_id(), "alice", "hi", "hello", 1);
+    let forged = SignedPost::sign(&forger, &post).unwrap();
+    let mut board = BulletinBoard::new("lobby", None);
+    assert!(board.insert(&forged).is_err());
+    assert!(board.is_empty());
+}
+
+#[test]
+fn boards_are_saved_per_room() {
+    let path = env::temp_dir().join(format!("p2p-chat-board-{}.board.json", process::id()));
+    let author = Keypair::generate_ed25519();
+    let mut lobby = BulletinBoard::load("lobby", path.clone()).unwrap();
+    let id = lobby
+        .insert(&signed_post(&author, "lobby", 1))
+        .unwrap()
+        .unwrap();
+    lobby.pin(&id, PeerId::random(), true);
+    lobby.save().unwrap();
+    let mut other = BulletinBoard::load("other", path.clone()).unwrap();
+    assert!(other.is_empty());
+    other.insert(&signed_post(&author, "other", 1)).unwrap();
+    other.save().unwrap();
+
+    let lobby = BulletinBoard::load("lobby", path.clone()).unwrap();
+    assert_eq!(lobby.len(), 1);
+    assert!(lobby.get(&id).unwrap().pinned);
+    assert_eq!(BulletinBoard::load("other", path.clone()).unwrap().len(), 1);
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn posts_and_pins_reach_the_room_and_newcomers() {
+    let dir = env::temp_dir();
+    let file = |name: &str| dir.join(format!("p2p-chat-board-{name}-{}.json", process::id()));
+    let (identity, alice_config, bob_config, carol_config) =
+        (file("key"), file("a"), file("b"), file("c"));
+    let identity_arg = identity.to_str().unwrap();
+    // Alice moderates the room, in her own view as well as everyone else's
+    let alice_id = ChatNode::new(&common::cli(&["--identity", identity_arg]))
+        .unwrap()
+        .local_peer_id()
+        .to_string();
+    let moderated = |config: &Path, extra: &[&str]| {
+        let mut args = vec![
+            "--config",
+            config.to_str().unwrap(),
+            "--moderator",
+            &alice_id,
+        ];
+        args.extend(extra);
+        common::cli(&args)
+    };
+    let (mut alice, alice_addr) =
+        common::spawn_chat_node(&moderated(&alice_config, &["--identity", identity_arg])).await;
+    let (mut bob, _) = common::spawn_chat_node(&moderated(&bob_config, &[])).await;
+    bob.swarm.dial(alice_addr.clone()).unwrap();
+    let topic = board::board_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    alice.handle_line("/post Meetup | Friday at 6").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.board().len() == 1
+    })
+    .await;
+    let id = bob.board().sorted()[0].id;
+    assert_eq!(bob.board().get(&id).unwrap().title, "Meetup");
+
+    // Only moderators can pin
+    bob.handle_line(&format!("/pin {id}")).await;
+    assert!(!bob.board().get(&id).unwrap().pinned);
+    alice
+        .handle_line(&format!("/pin {}", &id.to_string()[..8]))
+        .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.board().get(&id).unwrap().pinned
+    })
+    .await;
+    drop(bob);
+
+    // A newcomer gets the board from the members already there
+    let (mut carol, _) = common::spawn_chat_node(&moderated(&carol_config, &[])).await;
+    carol.swarm.dial(alice_addr).unwrap();
+    common::run_until(
+        &mut alice,
+        &mut carol,
+        Duration::from_secs(10),
+        |_, carol| carol.board().get(&id).is_some_and(|post| post.pinned),
+    )
+    .await;
+
+    // And the board outlives a restart
+    drop(alice);
+    let alice = ChatNode::new(&moderated(&alice_config, &["--identity", identity_arg])).unwrap();
+    assert!(alice.board().get(&id).unwrap().pinned);
+    for path in [alice_config, bob_config, carol_config] {
+        let _ = fs::remove_file(board::path_beside(&path));
+        let _ = fs::remove_file(path);
+    }
+    fs::remove_file(identity).unwrap();
+}
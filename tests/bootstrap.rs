@@ -0,0 +1,131 @@
+// Bootstrap peers given with `--bootstrap` or published in DNS TXT records.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    bootstrap::{self, Bootstrap},
+    commands::{self, UserCommand},
+};
+use libp2p::{multiaddr::Protocol, Multiaddr, PeerId};
+
+#[test]
+fn records_list_bootstrap_addresses() {
+    assert_eq!(
+        bootstrap::record_names("example.com."),
+        [
+            "_p2pchat._tcp.example.com.".to_string(),
+            "_dnsaddr.example.com.".to_string()
+        ]
+    );
+
+    let peer = PeerId::random();
+    let tcp = format!("/ip4/192.0.2.1/tcp/4001/p2p/{peer}");
+    let quic = format!("/dns4/boot.example.com/udp/4001/quic-v1/p2p/{peer}");
+    let records = [
+        format!("dnsaddr={tcp}"),
+        format!(" {quic} "),
+        // Already seen
+        tcp.clone(),
+        // Other records on the name
+        "v=spf1 -all".to_string(),
+        // No peer id, so it can't be told apart from a peer we are connected to
+        "dnsaddr=/ip4/192.0.2.2/tcp/4001".to_string(),
+        // Needs lookups of its own
+        format!("dnsaddr=/dnsaddr/boot.example.com/p2p/{peer}"),
+    ];
+    let expected: Vec<Multiaddr> = vec![tcp.parse().unwrap(), quic.parse().unwrap()];
+    assert_eq!(
+        bootstrap::parse_records(records.iter().map(String::as_str)),
+        expected
+    );
+
+    let many: Vec<String> = (0..100)
+        .map(|port| format!("/ip4/192.0.2.1/tcp/{port}/p2p/{peer}"))
+        .collect();
+    assert_eq!(
+        bootstrap::parse_records(many.iter().map(String::as_str)).len(),
+        bootstrap::MAX_ADDRESSES
+    );
+    assert!(bootstrap::parse_address("/ip4/192.0.2.1/tcp/4001").is_err());
+
+    assert_eq!(commands::parse("/net"), Some(Ok(UserCommand::Net)));
+}
+
+#[test]
+fn lookups_fall_back_to_the_static_list() {
+    let given: Multiaddr = format!("/ip4/192.0.2.1/tcp/4001/p2p/{}", PeerId::random())
+        .parse()
+        .unwrap();
+    let published: Multiaddr = format!("/ip4/192.0.2.2/tcp/4001/p2p/{}", PeerId::random())
+        .parse()
+        .unwrap();
+    let mut peers = Bootstrap::new(vec![given.clone()], Some("example.com".to_string()), true);
+    assert_eq!(peers.addresses(), std::slice::from_ref(&given));
+
+    assert!(!peers.record(Err("timed out".to_string()), 100));
+    assert_eq!(peers.addresses(), std::slice::from_ref(&given));
+    assert_eq!(peers.last_error(), Some("timed out"));
+
+    assert!(peers.record(Ok(vec![published.clone()]), 200));
+    assert_eq!(peers.addresses(), std::slice::from_ref(&published));
+    assert_eq!(
+        peers.resolved(),
+        Some(([published.clone()].as_slice(), 200))
+    );
+    assert_eq!(peers.last_error(), None);
+    assert!(!peers.record(Ok(vec![published.clone()]), 500));
+
+    // A failed refresh keeps what was found
+    assert!(!peers.record(Err("timed out".to_string()), 800));
+    assert_eq!(peers.addresses(), [published]);
+
+    // Without a domain there is nothing to look up
+    let mut peers = Bootstrap::new(vec![given], None, true);
+    assert!(!peers.start_lookup(0));
+    assert!(peers.poll_lookup().is_none());
+}
+
+#[tokio::test]
+async fn bootstrap_peers_are_dialed() {
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    let bob_addr = bob_addr.with(Protocol::P2p(bob.local_peer_id()));
+    let (mut alice, _) =
+        common::spawn_chat_node(&common::cli(&["--bootstrap", &bob_addr.to_string()])).await;
+    alice.bootstrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        a.swarm.is_connected(&b.local_peer_id())
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn published_peers_replace_the_given_ones_once_found() {
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    let (mut carol, carol_addr) = common::spawn_chat_node(&common::cli(&["--nick", "carol"])).await;
+    let bob_addr = bob_addr.with(Protocol::P2p(bob.local_peer_id()));
+    let carol_addr = carol_addr.with(Protocol::P2p(carol.local_peer_id()));
+    // The domain is never looked up, as neither `bootstrap` nor `tick` runs
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[
+        "--bootstrap",
+        &carol_addr.to_string(),
+        "--bootstrap-domain",
+        "example.invalid",
+    ]))
+    .await;
+
+    // Nothing found yet: the peers of --bootstrap are dialed
+    alice.handle_bootstrap_lookup(Err("no records".to_string()));
+    common::run_until(&mut alice, &mut carol, Duration::from_secs(10), |a, c| {
+        a.swarm.is_connected(&c.local_peer_id())
+    })
+    .await;
+    assert_eq!(alice.bootstrap_peers().last_error(), Some("no records"));
+
+    alice.handle_bootstrap_lookup(Ok(vec![bob_addr.clone()]));
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        a.swarm.is_connected(&b.local_peer_id())
+    })
+    .await;
+    assert_eq!(alice.bootstrap_peers().addresses(), [bob_addr]);
+}
@@ -0,0 +1,159 @@
+// The room's shared canvas, merged cell by cell with the last writer winning.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    canvas::{self, Canvas, CanvasDelta, BLANK, HEIGHT, WIDTH},
+    commands::{self, UserCommand},
+};
+use libp2p::PeerId;
+
+fn delta(x: u8, y: u8, ch: char, author: &str, ts: u64) -> CanvasDelta {
+    CanvasDelta {
+        x,
+        y,
+        ch,
+        author: author.to_string(),
+        ts,
+    }
+}
+
+#[test]
+fn deltas_merge_the_same_in_any_order() {
+    let deltas = [
+        delta(1, 1, 'a', "alice", 10),
+        delta(1, 1, 'b', "bob", 12),
+        delta(1, 1, 'c', "carol", 12),
+        delta(1, 1, BLANK, "alice", 11),
+        delta(2, 3, '#', "bob", 5),
+    ];
+    let mut expected = Canvas::default();
+    for delta in &deltas {
+        expected.apply(delta).unwrap();
+    }
+    // The latest stamp wins, and the author breaks the tie
+    assert_eq!(expected.get(1, 1), 'c');
+    assert_eq!(expected.get(2, 3), '#');
+    assert_eq!(expected.drawn(), 2);
+
+    let orders = [[4, 3, 2, 1, 0], [2, 0, 4, 1, 3], [1, 2, 3, 0, 4]];
+    for order in orders {
+        let mut canvas = Canvas::default();
+        for i in order {
+            canvas.apply(&deltas[i]).unwrap();
+            // Applying a delta again changes nothing
+            assert!(!canvas.apply(&deltas[i]).unwrap());
+        }
+        assert_eq!(canvas, expected, "order {order:?}");
+    }
+}
+
+#[test]
+fn a_stroke_wins_over_the_one_it_covers_even_with_a_clock_behind() {
+    let mut canvas = Canvas::default();
+    canvas.apply(&delta(0, 0, 'x', "zed", 1_000)).unwrap();
+    let local = PeerId::random();
+    let drawn = canvas.draw(0, 0, 'o', local, 10).unwrap();
+    assert_eq!(drawn.ts, 1_001);
+    assert_eq!(drawn.author, local.to_string());
+    assert_eq!(canvas.get(0, 0), 'o');
+    // Clearing is a stroke like any other
+    canvas.draw(0, 0, BLANK, local, 10).unwrap();
+    assert_eq!(canvas.get(0, 0), BLANK);
+    assert_eq!(canvas.drawn(), 0);
+}
+
+#[test]
+fn strokes_off_the_canvas_or_of_control_characters_are_refused() {
+    let mut canvas = Canvas::default();
+    assert!(canvas.apply(&delta(WIDTH, 0, 'x', "a", 1)).is_err());
+    assert!(canvas.apply(&delta(0, HEIGHT, 'x', "a", 1)).is_err());
+    assert!(canvas.apply(&delta(0, 0, '\u{1b}', "a", 1)).is_err());
+    assert!(canvas.apply(&delta(0, 0, '\u{202e}', "a", 1)).is_err());
+    assert!(canvas
+        .draw(WIDTH - 1, HEIGHT - 1, '█', PeerId::random(), 1)
+        .is_ok());
+}
+
+#[test]
+fn the_canvas_renders_in_a_frame() {
+    let mut canvas = Canvas::default();
+    canvas.apply(&delta(0, 0, '@', "a", 1)).unwrap();
+    canvas.apply(&delta(79, 23, '#', "a", 1)).unwrap();
+
+    let plain = canvas.render(false);
+    let lines: Vec<&str> = plain.lines().collect();
+    assert_eq!(lines.len(), usize::from(HEIGHT) + 2);
+    assert_eq!(lines[0], format!("+{}+", "-".repeat(80)));
+    assert_eq!(lines[1], format!("|@{}|", " ".repeat(79)));
+    assert_eq!(lines[24], format!("|{}#|", " ".repeat(79)));
+
+    let ansi = canvas.render(true);
+    let lines: Vec<&str> = ansi.lines().collect();
+    assert_eq!(lines[1], "|\x1b[2G@\x1b[82G|");
+    assert_eq!(lines[2], "|\x1b[82G|");
+}
+
+#[test]
+fn draw_and_canvas_commands_parse() {
+    assert_eq!(
+        commands::parse("/draw 3 4 *"),
+        Some(Ok(UserCommand::Draw {
+            x: 3,
+            y: 4,
+            ch: '*'
+        }))
+    );
+    assert_eq!(
+        commands::parse("/draw 3 4"),
+        Some(Ok(UserCommand::Draw {
+            x: 3,
+            y: 4,
+            ch: BLANK
+        }))
+    );
+    assert_eq!(commands::parse("/canvas"), Some(Ok(UserCommand::Canvas)));
+    for bad in [
+        "/draw",
+        "/draw 3",
+        "/draw 3 4 ab",
+        "/draw -1 4 x",
+        "/draw x y z",
+    ] {
+        assert!(commands::parse(bad).unwrap().is_err(), "{bad} accepted");
+    }
+}
+
+#[tokio::test]
+async fn concurrent_strokes_converge_on_both_nodes() {
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = canvas::canvas_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    // Both draw on the same cell before seeing the other's stroke
+    alice.handle_line("/draw 10 5 A").await;
+    bob.handle_line("/draw 10 5 B").await;
+    bob.handle_line("/draw 11 5 b").await;
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| alice.canvas().drawn() == 2 && alice.canvas() == bob.canvas(),
+    )
+    .await;
+    assert_eq!(alice.canvas(), bob.canvas());
+    assert!(['A', 'B'].contains(&alice.canvas().get(10, 5)));
+
+    bob.handle_line("/draw 11 5").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.canvas().get(11, 5) == BLANK
+    })
+    .await;
+    assert_eq!(alice.history().count(), 0, "strokes aren't chat");
+}
@@ -0,0 +1,24 @@
+// Nodes with different cipher preferences must still agree on a security protocol.
+mod common;
+
+async fn exchange_message(dialer_cipher: &str, listener_cipher: &str) {
+    let (mut dialer, _) =
+        common::spawn_node(&common::cli(&["--noise-cipher", dialer_cipher])).await;
+    let (mut listener, addr) =
+        common::spawn_node(&common::cli(&["--noise-cipher", listener_cipher])).await;
+
+    let received =
+        common::publish_and_receive(&mut dialer, &mut listener, addr, b"hello across ciphers")
+            .await;
+    assert_eq!(received, b"hello across ciphers");
+}
+
+#[tokio::test]
+async fn chacha20_dialer_reaches_aesgcm_listener() {
+    exchange_message("chacha20", "aesgcm").await;
+}
+
+#[tokio::test]
+async fn aesgcm_dialer_reaches_chacha20_listener() {
+    exchange_message("aesgcm", "chacha20").await;
+}
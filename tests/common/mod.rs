@@ -0,0 +1,267 @@
+// Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use std::{
+    env,
+    future::Future,
+    io,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use clap::Parser;
+use concurrent_chat_server::{
+    chat::ChatNode,
+    cli::Cli,
+    gossip::Gossip,
+    node::{self, MyBehaviour, MyBehaviourEvent},
+    runtime,
+};
+use libp2p::{
+    futures::StreamExt,
+    gossipsub::{self, MessageAcceptance, MessageId, TopicHash},
+    swarm::SwarmEvent,
+    Multiaddr, PeerId, Swarm,
+};
+use mockall::mock;
+use tracing::subscriber::DefaultGuard;
+
+mock! {
+    /// Gossipsub as seen by the chat node's event handlers.
+    pub Gossipsub {}
+    impl Gossip for Gossipsub {
+        fn report_validation(
+            &mut self,
+            message_id: &MessageId,
+            propagation_source: &PeerId,
+            acceptance: MessageAcceptance,
+        ) -> bool;
+        fn add_explicit_peer(&mut self, peer: &PeerId);
+        fn remove_explicit_peer(&mut self, peer: &PeerId);
+    }
+}
+
+/// Parse test flags, always disabling mDNS so concurrent tests don't discover each other, and
+/// desktop notifications so they don't pop up.
+pub fn cli(args: &[&str]) -> Cli {
+    Cli::parse_from(["p2p-chat", "--no-mdns", "--no-notify"].iter().chain(args))
+}
+
+/// Build a swarm subscribed to the chat topic and listening on a loopback TCP port.
+pub async fn spawn_node(cli: &Cli) -> (Swarm<MyBehaviour>, Multiaddr) {
+    let mut swarm = node::build_swarm(cli).expect("swarm builds");
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&topic())
+        .expect("subscribes");
+    swarm
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .expect("listens");
+
+    // Wait until the listener reports its actual address.
+    loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = swarm.select_next_some().await {
+            return (swarm, address);
+        }
+    }
+}
+
+/// The topic every test node subscribes to.
+pub fn topic() -> gossipsub::IdentTopic {
+    node::default_topic()
+}
+
+/// `data` published by `source` on the chat topic as its `seq`th message, as the node's swarm
+/// hands it over. Its id is `<source>-<seq>`.
+pub fn message_event(source: PeerId, seq: u64, data: Vec<u8>) -> gossipsub::Event {
+    message_event_on(topic().hash(), source, seq, data)
+}
+
+/// [`message_event`] on `topic` instead of the chat topic.
+pub fn message_event_on(
+    topic: TopicHash,
+    source: PeerId,
+    seq: u64,
+    data: Vec<u8>,
+) -> gossipsub::Event {
+    gossipsub::Event::Message {
+        propagation_source: source,
+        message_id: MessageId::from(format!("{source}-{seq}")),
+        message: gossipsub::Message {
+            source: Some(source),
+            data,
+            sequence_number: Some(seq),
+            topic,
+        },
+    }
+}
+
+/// `event` published without an author, so only the peer that relayed it is known.
+pub fn unsigned(mut event: gossipsub::Event) -> gossipsub::Event {
+    if let gossipsub::Event::Message { message, .. } = &mut event {
+        message.source = None;
+    }
+    event
+}
+
+/// A path in the temporary directory for this test run, with `name` at the end.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("p2p-chat-{}-{name}", process::id()))
+}
+
+/// What `events`, a task's next report, resolves to, panicking if nothing comes in time or the
+/// task is gone.
+pub async fn next<T>(events: impl Future<Output = Option<T>>) -> T {
+    runtime::timeout(Duration::from_secs(10), events)
+        .await
+        .expect("something happens")
+        .unwrap()
+}
+
+/// Dial `listener` from `dialer`, publish `payload` once the listener has subscribed, and
+/// return the data the listener received.
+pub async fn publish_and_receive(
+    dialer: &mut Swarm<MyBehaviour>,
+    listener: &mut Swarm<MyBehaviour>,
+    listener_addr: Multiaddr,
+    payload: &[u8],
+) -> Vec<u8> {
+    dialer.dial(listener_addr).unwrap();
+
+    let result = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            tokio::select! {
+                event = dialer.select_next_some() => match event {
+                    // Publish as soon as the listener's subscription reaches us.
+                    SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Subscribed { .. },
+                    )) => {
+                        dialer
+                            .behaviour_mut()
+                            .gossipsub
+                            .publish(topic(), payload)
+                            .unwrap();
+                    }
+                    SwarmEvent::OutgoingConnectionError { error, .. } => {
+                        panic!("dial failed: {error}")
+                    }
+                    _ => {}
+                },
+                event = listener.select_next_some() => {
+                    if let SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(
+                        gossipsub::Event::Message { message, .. },
+                    )) = event
+                    {
+                        return message.data;
+                    }
+                }
+            }
+        }
+    });
+    result.await.expect("message delivered before timeout")
+}
+
+/// Build a chat node listening on a loopback TCP port.
+pub async fn spawn_chat_node(cli: &Cli) -> (ChatNode, Multiaddr) {
+    let mut chat = ChatNode::new(cli).expect("node builds");
+    chat.swarm
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .expect("listens");
+    loop {
+        let event = chat.swarm.select_next_some().await;
+        if let SwarmEvent::NewListenAddr { address, .. } = event {
+            return (chat, address);
+        }
+        chat.handle_event(event);
+    }
+}
+
+/// Drive both nodes' event loops until `done` returns true, panicking after `timeout`.
+pub async fn run_until(
+    a: &mut ChatNode,
+    b: &mut ChatNode,
+    timeout: Duration,
+    mut done: impl FnMut(&ChatNode, &ChatNode) -> bool,
+) {
+    tokio::time::timeout(timeout, async {
+        while !done(a, b) {
+            tokio::select! {
+                event = a.swarm.select_next_some() => a.handle_event(event),
+                event = b.swarm.select_next_some() => b.handle_event(event),
+            }
+        }
+    })
+    .await
+    .expect("condition reached before timeout");
+}
+
+/// Drive both nodes' event loops for `duration`, e.g. to check that something doesn't happen.
+pub async fn run_for(a: &mut ChatNode, b: &mut ChatNode, duration: Duration) {
+    let _ = tokio::time::timeout(duration, async {
+        loop {
+            tokio::select! {
+                event = a.swarm.select_next_some() => a.handle_event(event),
+                event = b.swarm.select_next_some() => b.handle_event(event),
+            }
+        }
+    })
+    .await;
+}
+
+/// Whether `node` has a Gossipsub peer subscribed to `topic`.
+pub fn has_subscriber(node: &ChatNode, topic: &gossipsub::IdentTopic) -> bool {
+    node.swarm
+        .behaviour()
+        .gossipsub
+        .all_peers()
+        .any(|(_, topics)| topics.contains(&&topic.hash()))
+}
+
+/// Log lines captured by [`capture_logs`].
+#[derive(Clone, Default)]
+pub struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    pub fn lines(&self) -> Vec<String> {
+        let buffer = self.0.lock().unwrap();
+        String::from_utf8_lossy(&buffer)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Whether a captured line contains `needle`.
+    pub fn contains(&self, needle: &str) -> bool {
+        self.lines().iter().any(|line| line.contains(needle))
+    }
+}
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Capture the node's log lines, debug ones included, on this thread until the guard is
+/// dropped. Tests on the default single-threaded tokio runtime see everything their nodes log.
+pub fn capture_logs() -> (Logs, DefaultGuard) {
+    let logs = Logs::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(move || writer.clone())
+        .with_max_level(tracing::Level::DEBUG)
+        .without_time()
+        .with_level(false)
+        .with_target(false)
+        .with_ansi(false)
+        .finish();
+    (logs, tracing::subscriber::set_default(subscriber))
+}
@@ -0,0 +1,104 @@
+// Dialing a peer with `ChatNode::connect_to` and waiting for the outcome.
+mod common;
+
+use std::{io, time::Duration};
+
+use concurrent_chat_server::error::{self, DialError};
+use libp2p::{
+    core::transport::TransportError, futures::StreamExt, multiaddr::Protocol, swarm, Multiaddr,
+    PeerId,
+};
+
+#[tokio::test]
+async fn connects_to_the_named_peer() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let addr = bob_addr.with(Protocol::P2p(bob.local_peer_id()));
+
+    tokio::select! {
+        result = alice.connect_to(addr.clone()) => result.unwrap(),
+        _ = async { loop { bob.swarm.select_next_some().await; } } => unreachable!(),
+    }
+    assert!(alice.swarm.is_connected(&bob.local_peer_id()));
+    // Already connected counts as success
+    alice.connect_to(addr).await.unwrap();
+}
+
+#[tokio::test]
+async fn wrong_peer_id_is_reported() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let addr = bob_addr.with(Protocol::P2p(PeerId::random()));
+
+    let result = tokio::select! {
+        result = alice.connect_to(addr) => result,
+        _ = async { loop { bob.swarm.select_next_some().await; } } => unreachable!(),
+    };
+    assert!(matches!(
+        result,
+        Err(DialError::Swarm(swarm::DialError::WrongPeerId { .. }))
+    ));
+}
+
+#[tokio::test]
+async fn refuses_to_dial_itself_and_times_out() {
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let own = alice_addr.with(Protocol::P2p(alice.local_peer_id()));
+    assert!(matches!(
+        alice.connect_to(own).await,
+        Err(DialError::SelfDial)
+    ));
+
+    // A listener that never completes a handshake
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    alice.set_dial_timeout(Duration::from_millis(500));
+    let silent = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+    assert!(matches!(
+        alice.connect_to(silent).await,
+        Err(DialError::Timeout(_))
+    ));
+}
+
+#[tokio::test]
+async fn dial_failures_say_why() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    // A port nothing listens on
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let closed: Multiaddr = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+    let Err(DialError::Swarm(refused)) = alice.connect_to(closed.clone()).await else {
+        panic!("the dial should fail");
+    };
+    assert_eq!(
+        error::describe_dial_error(&refused),
+        format!("{closed}: connection refused, nothing is listening there")
+    );
+
+    let timed_out = swarm::DialError::Transport(vec![(
+        closed.clone(),
+        TransportError::Other(io::Error::other(io::Error::from(io::ErrorKind::TimedOut))),
+    )]);
+    assert_eq!(
+        error::describe_dial_error(&timed_out),
+        format!("{closed}: timed out")
+    );
+    // Bob is there, but not under the expected peer id
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let addr = bob_addr.with(Protocol::P2p(PeerId::random()));
+    let result = tokio::select! {
+        result = alice.connect_to(addr.clone()) => result,
+        _ = async { loop { bob.swarm.select_next_some().await; } } => unreachable!(),
+    };
+    let Err(DialError::Swarm(wrong)) = result else {
+        panic!("the dial should fail");
+    };
+    assert_eq!(
+        error::describe_dial_error(&wrong),
+        format!(
+            "{addr}: wrong peer id, the node there is {}",
+            bob.local_peer_id()
+        )
+    );
+}
@@ -0,0 +1,67 @@
+// Keeping the most valuable peers when the node nears `--max-peers`.
+mod common;
+
+use concurrent_chat_server::{
+    connections::{ConnectedPeer, ConnectionManager, Priority},
+    error::DialError,
+};
+use libp2p::{futures::StreamExt, multiaddr::Protocol, swarm, PeerId};
+
+fn peer(in_mesh: bool, score: f64) -> ConnectedPeer {
+    ConnectedPeer {
+        peer: PeerId::random(),
+        in_mesh,
+        score,
+    }
+}
+
+#[test]
+fn priority_follows_mesh_membership_and_score() {
+    assert_eq!(Priority::of(true, 0.0), Priority::High);
+    assert_eq!(Priority::of(true, -1.0), Priority::Normal);
+    assert_eq!(Priority::of(false, 5.0), Priority::Normal);
+    assert_eq!(Priority::of(false, -1.0), Priority::Low);
+}
+
+#[test]
+fn low_priority_peers_go_first_and_mesh_peers_never() {
+    let manager = ConnectionManager::new(10);
+    let mut connected: Vec<_> = (0..9).map(|_| peer(true, 1.0)).collect();
+    // At the high water mark nothing is disconnected yet
+    assert_eq!(manager.evictions(&connected), []);
+
+    let (worst, bad, neutral) = (peer(false, -5.0), peer(false, -1.0), peer(false, 0.0));
+    connected.extend([neutral, bad, worst]);
+    // Twelve connected, trimmed down to eight: but only the three outside the mesh may go
+    assert_eq!(
+        manager.evictions(&connected),
+        [worst.peer, bad.peer, neutral.peer]
+    );
+
+    let mut connected: Vec<_> = (0..7).map(|_| peer(true, 0.0)).collect();
+    connected.extend([neutral, bad, worst]);
+    assert_eq!(manager.evictions(&connected), [worst.peer, bad.peer]);
+}
+
+#[tokio::test]
+async fn connections_beyond_the_limit_are_refused() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--max-peers", "1"])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut carol, carol_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let bob_addr = bob_addr.with(Protocol::P2p(bob.local_peer_id()));
+    let carol_addr = carol_addr.with(Protocol::P2p(carol.local_peer_id()));
+
+    let result = tokio::select! {
+        result = async {
+            alice.connect_to(bob_addr).await.unwrap();
+            alice.connect_to(carol_addr).await
+        } => result,
+        _ = async { loop { bob.swarm.select_next_some().await; } } => unreachable!(),
+        _ = async { loop { carol.swarm.select_next_some().await; } } => unreachable!(),
+    };
+    assert!(matches!(
+        result,
+        Err(DialError::Swarm(swarm::DialError::Denied { .. }))
+    ));
+    assert_eq!(alice.swarm.connected_peers().count(), 1);
+}
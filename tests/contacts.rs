@@ -0,0 +1,121 @@
+// Contacts saved with `/contact` and listed with `/contacts`.
+mod common;
+
+use std::{env, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, ContactCommand, UserCommand},
+    contacts::{Contacts, MAX_NOTES_CHARS},
+    message::ChatMessage,
+};
+use libp2p::{gossipsub, PeerId};
+
+fn message(source: PeerId, seq: u64, nick: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new(nick, format!("message {seq}"), 0);
+    common::message_event(source, seq, chat.encode())
+}
+
+#[test]
+fn contact_commands_parse() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(parse("/contacts"), Ok(UserCommand::Contacts));
+    assert_eq!(
+        parse("/contact add alice"),
+        Ok(UserCommand::Contact(ContactCommand::Add {
+            target: "alice".to_string(),
+            label: None
+        }))
+    );
+    assert_eq!(
+        parse("/contact add alice \"Alice B\""),
+        Ok(UserCommand::Contact(ContactCommand::Add {
+            target: "alice".to_string(),
+            label: Some("Alice B".to_string())
+        }))
+    );
+    assert_eq!(
+        parse("/contact note alice met at the meetup"),
+        Ok(UserCommand::Contact(ContactCommand::Note {
+            target: "alice".to_string(),
+            notes: "met at the meetup".to_string()
+        }))
+    );
+    assert_eq!(
+        parse("/contact unfav alice"),
+        Ok(UserCommand::Contact(ContactCommand::Favorite {
+            target: "alice".to_string(),
+            favorite: false
+        }))
+    );
+    assert!(parse("/contact add").is_err());
+    assert!(parse("/contact fav alice bob").is_err());
+    assert!(parse("/contact forget alice").is_err());
+}
+
+#[test]
+fn labels_are_unique_and_favorites_come_first() {
+    let (alice, bob, carol) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut contacts = Contacts::default();
+    contacts.add(alice, "alice").unwrap();
+    contacts.add(bob, "Bob\u{202e}").unwrap();
+    assert_eq!(contacts.get(&bob).unwrap().label, "Bob");
+    assert!(contacts.add(carol, "ALICE").is_err());
+    assert!(contacts.add(carol, "  ").is_err());
+
+    // Adding a contact again relabels it
+    contacts.add(alice, "al").unwrap();
+    assert_eq!(contacts.by_label("AL").unwrap().peer, alice);
+    assert_eq!(contacts.iter().count(), 2);
+
+    contacts.get_mut(&bob).unwrap().favorite = true;
+    let order: Vec<PeerId> = contacts.sorted().iter().map(|c| c.peer).collect();
+    assert_eq!(order, [bob, alice]);
+
+    assert!(contacts.set_notes(&alice, "a\nb").is_ok());
+    assert_eq!(contacts.get(&alice).unwrap().notes, "a b");
+    assert!(contacts
+        .set_notes(&alice, &"x".repeat(MAX_NOTES_CHARS + 1))
+        .is_err());
+    assert!(contacts.set_notes(&carol, "who?").is_err());
+
+    assert!(contacts.seen(&alice, 100));
+    assert!(!contacts.seen(&alice, 90), "older sightings are ignored");
+    assert_eq!(contacts.get(&alice).unwrap().last_seen, Some(100));
+    assert!(contacts.remove(&alice).is_some());
+    assert!(contacts.get(&alice).is_none());
+}
+
+#[tokio::test]
+async fn contacts_persist_and_their_labels_come_before_nicks() {
+    let config = env::temp_dir().join(format!("p2p-chat-contacts-{}.json", process::id()));
+    let cli = common::cli(&["--config", config.to_str().unwrap()]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    let (alice, impostor) = (PeerId::random(), PeerId::random());
+    node.receive(message(alice, 0, "alice"));
+
+    node.handle_line("/contact add alice sis").await;
+    let contact = node.contacts().get(&alice).unwrap();
+    assert_eq!(contact.label, "sis");
+    assert!(contact.last_seen.is_some(), "alice was just heard from");
+
+    // Someone else using the label as a nick doesn't take the contact's place
+    node.receive(message(impostor, 0, "sis"));
+    node.handle_line("/contact fav sis").await;
+    node.handle_line("/contact note sis \"call on Sundays\"")
+        .await;
+    let contact = node.contacts().get(&alice).unwrap();
+    assert!(contact.favorite);
+    assert_eq!(contact.notes, "call on Sundays");
+
+    node.handle_line(&format!("/contact remove {impostor}"))
+        .await;
+    assert_eq!(node.contacts().iter().count(), 1);
+
+    // Contacts survive a restart
+    drop(node);
+    let node = ChatNode::new(&cli).unwrap();
+    assert_eq!(node.contacts().get(&alice).unwrap().label, "sis");
+    assert!(node.contacts().get(&alice).unwrap().favorite);
+    std::fs::remove_file(config).unwrap();
+}
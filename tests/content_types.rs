@@ -0,0 +1,303 @@
+// Message formats peers advertise with Identify, and what they are sent.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, UserCommand},
+    content_type::{self, ContentType, ContentTypeNegotiator},
+    message::ChatMessage,
+    node::MyBehaviourEvent,
+};
+use libp2p::{
+    futures::StreamExt,
+    identify,
+    swarm::{ConnectionId, SwarmEvent},
+    PeerId,
+};
+
+#[test]
+fn peers_get_the_richest_format_they_accept() {
+    let ours = content_type::agent_version();
+    assert!(ours.ends_with("Accept: [text/plain, application/x-p2pchat-v1, text/markdown]"));
+    assert_eq!(
+        content_type::parse_accept(&ours),
+        Some(content_type::ACCEPTED.to_vec())
+    );
+    assert_eq!(content_type::parse_accept("rust-libp2p/0.45.0"), None);
+
+    let (full, envelope, plain, old) = (
+        PeerId::random(),
+        PeerId::random(),
+        PeerId::random(),
+        PeerId::random(),
+    );
+    let mut formats = ContentTypeNegotiator::default();
+    formats.learn(full, &ours);
+    // Formats we don't know are left out
+    formats.learn(
+        envelope,
+        "bot/1 Accept: [application/x-p2pchat-v1, text/html]",
+    );
+    formats.learn(plain, "bot/1 Accept: [text/plain]");
+    formats.learn(old, "rust-libp2p/0.45.0");
+    // Naming none of our formats is saying nothing, and chat nodes always read the envelope
+    let (html, chat) = (PeerId::random(), PeerId::random());
+    formats.learn(html, "bot/1 Accept: [text/html]");
+    formats.learn(chat, "p2p-chat/0.2.0 Accept: [text/plain]");
+    assert_eq!(formats.accepted(&html), None);
+    assert_eq!(formats.pick_format(html), ContentType::Envelope);
+    assert_eq!(formats.pick_format(chat), ContentType::Envelope);
+    assert_eq!(formats.pick_format(full), ContentType::Markdown);
+    assert_eq!(formats.pick_format(envelope), ContentType::Envelope);
+    assert_eq!(formats.pick_format(plain), ContentType::PlainText);
+    // Peers that don't say get the envelope every chat node reads
+    assert_eq!(formats.accepted(&old), None);
+    assert_eq!(formats.pick_format(old), ContentType::Envelope);
+
+    assert_eq!(formats.pick_for_all([full]), ContentType::Markdown);
+    assert_eq!(
+        formats.pick_for_all([full, envelope]),
+        ContentType::Envelope
+    );
+    // One member reading only plain text doesn't take the rest from the others
+    assert_eq!(
+        formats.pick_for_all([full, plain, old]),
+        ContentType::Envelope
+    );
+    assert_eq!(formats.pick_for_all([full, plain]), ContentType::Markdown);
+    assert_eq!(formats.pick_for_all([plain]), ContentType::PlainText);
+    assert_eq!(formats.pick_for_all([]), ContentType::Markdown);
+    formats.forget(&plain);
+    assert_eq!(formats.pick_format(plain), ContentType::Envelope);
+}
+
+#[test]
+fn markdown_falls_back_to_plain_text() {
+    assert_eq!(
+        content_type::to_plain("# Release\n**v2** is *out*, see [notes](https://example.org)"),
+        "Release\nv2 is out, see notes (https://example.org)"
+    );
+    assert_eq!(
+        content_type::to_plain("run `cargo test` and ~~pray~~ __wait__"),
+        "run cargo test and pray wait"
+    );
+    // Marks that aren't emphasis stay
+    assert_eq!(
+        content_type::to_plain("2 * 3 * 4 and snake_case"),
+        "2 * 3 * 4 and snake_case"
+    );
+
+    let message = ChatMessage {
+        nick: "alice".to_string(),
+        body: "**hi**".into(),
+        timestamp: 1,
+        attachment: None,
+        origin: None,
+        content_type: Some(ContentType::Markdown),
+    };
+    assert!(String::from_utf8(message.encode())
+        .unwrap()
+        .contains(r#""content_type":"text/markdown""#));
+    assert_eq!(*message.fit(ContentType::Markdown), message);
+    let fitted = message.fit(ContentType::Envelope);
+    assert_eq!((&*fitted.body, fitted.content_type), ("hi", None));
+    assert_eq!(message.plain_text(), "hi");
+
+    assert_eq!(
+        commands::parse("/md **hi**"),
+        Some(Ok(UserCommand::Markdown("**hi**".to_string())))
+    );
+    assert!(commands::parse("/md").unwrap().is_err());
+}
+
+// Identify `peer` to `node` with `agent_version`, as if it had announced it.
+fn identify(node: &mut ChatNode, peer: PeerId, agent_version: &str) {
+    let info = identify::Info {
+        public_key: libp2p::identity::Keypair::generate_ed25519().public(),
+        protocol_version: "/p2p-chat/1.0.0/p2pchat".to_string(),
+        agent_version: agent_version.to_string(),
+        listen_addrs: Vec::new(),
+        protocols: Vec::new(),
+        observed_addr: "/ip4/127.0.0.1/tcp/1".parse().unwrap(),
+    };
+    node.handle_event(SwarmEvent::Behaviour(MyBehaviourEvent::Identify(
+        identify::Event::Received {
+            connection_id: ConnectionId::new_unchecked(0),
+            peer_id: peer,
+            info,
+        },
+    )));
+}
+
+// Drive three nodes' event loops until `done` returns true.
+async fn run_three_until(
+    nodes: [&mut ChatNode; 3],
+    mut done: impl FnMut(&ChatNode, &ChatNode, &ChatNode) -> bool,
+) {
+    let [a, b, c] = nodes;
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while !done(a, b, c) {
+            tokio::select! {
+                event = a.swarm.select_next_some() => a.handle_event(event),
+                event = b.swarm.select_next_some() => b.handle_event(event),
+                event = c.swarm.select_next_some() => c.handle_event(event),
+            }
+        }
+    })
+    .await
+    .expect("condition reached before timeout");
+}
+
+#[tokio::test]
+async fn markdown_reaches_peers_that_read_it_and_plain_text_others() {
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    let alice_id = alice.local_peer_id();
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic)
+            && common::has_subscriber(b, &topic)
+            && b.formats().accepted(&a.local_peer_id()).is_some()
+    })
+    .await;
+
+    bob.handle_line("/md **release** is out").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, _| {
+        a.history().count() == 1
+    })
+    .await;
+    let received = &alice.history().next().unwrap().message;
+    assert_eq!(&*received.body, "**release** is out");
+    assert_eq!(received.content_type, Some(ContentType::Markdown));
+
+    // Once alice says it only reads plain text, and is the only one in the room, it gets the
+    // body alone
+    identify(&mut bob, alice_id, "bot/1 Accept: [text/plain]");
+    bob.handle_line("/md *second* release").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, _| {
+        a.history().count() == 2
+    })
+    .await;
+    let received = &alice.history().nth(1).unwrap().message;
+    assert_eq!(&*received.body, "second release");
+    assert_eq!(received.content_type, None);
+    // Plain text carries no nick
+    assert_eq!(received.nick, "");
+}
+
+#[tokio::test]
+async fn a_member_reading_only_plain_text_leaves_the_room_its_attachments() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    let (mut carol, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.swarm.dial(bob_addr.clone()).unwrap();
+    carol.swarm.dial(bob_addr).unwrap();
+    let (alice_id, carol_id) = (alice.local_peer_id(), carol.local_peer_id());
+    let topic = common::topic();
+    run_three_until([&mut alice, &mut bob, &mut carol], |a, b, c| {
+        let members = b
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .filter(|(_, topics)| topics.contains(&&topic.hash()))
+            .count();
+        members == 2
+            && common::has_subscriber(a, &topic)
+            && common::has_subscriber(c, &topic)
+            && b.formats().accepted(&alice_id).is_some()
+            && b.formats().accepted(&carol_id).is_some()
+    })
+    .await;
+    identify(&mut bob, carol_id, "bot/1 Accept: [text/plain]");
+    assert_eq!(bob.formats().pick_format(carol_id), ContentType::PlainText);
+
+    let path = env::temp_dir().join(format!("p2p-chat-formats-{}-notes.txt", process::id()));
+    fs::write(&path, b"minutes").unwrap();
+    bob.handle_line(&format!("/attach {}", path.display()))
+        .await;
+    bob.handle_line("see attached").await;
+    run_three_until([&mut alice, &mut bob, &mut carol], |a, _, _| {
+        a.history().count() == 1
+    })
+    .await;
+    let received = &alice.history().next().unwrap().message;
+    assert_eq!(received.nick, "bob");
+    assert!(received.attachment.is_some());
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn held_lines_are_encoded_for_whoever_joins() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    alice.handle_line("/md **anyone** there?").await;
+    assert_eq!(alice.queued(), 1);
+
+    // Bob joins reading only plain text; what his node really announces is left out
+    identify(
+        &mut alice,
+        bob.local_peer_id(),
+        "bot/1 Accept: [text/plain]",
+    );
+    alice.swarm.dial(bob_addr).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while alice.queued() > 0 || bob.history().count() == 0 {
+            tokio::select! {
+                event = alice.swarm.select_next_some() => {
+                    if !matches!(
+                        event,
+                        SwarmEvent::Behaviour(MyBehaviourEvent::Identify(
+                            identify::Event::Received { .. }
+                        ))
+                    ) {
+                        alice.handle_event(event);
+                    }
+                }
+                event = bob.swarm.select_next_some() => bob.handle_event(event),
+            }
+        }
+    })
+    .await
+    .expect("condition reached before timeout");
+    let received = &bob.history().next().unwrap().message;
+    assert_eq!(&*received.body, "anyone there?");
+    assert_eq!(received.content_type, None);
+    assert_eq!(received.nick, "");
+}
+
+#[tokio::test]
+async fn batched_markdown_is_fitted_to_the_room() {
+    let (mut alice, alice_addr) =
+        common::spawn_chat_node(&common::cli(&["--batch-ms", "60000"])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    let bob_id = bob.local_peer_id();
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic)
+            && common::has_subscriber(b, &topic)
+            && a.formats().accepted(&bob_id).is_some()
+    })
+    .await;
+    identify(
+        &mut alice,
+        bob_id,
+        "bot/1 Accept: [application/x-p2pchat-v1]",
+    );
+
+    alice.handle_line("/md **first**").await;
+    alice.handle_line("/md *second*").await;
+    alice.flush_batch().unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, b| {
+        b.history().count() == 2
+    })
+    .await;
+    for (stored, body) in bob.history().zip(["first", "second"]) {
+        assert_eq!(&*stored.message.body, body);
+        assert_eq!(stored.message.content_type, None);
+    }
+}
@@ -0,0 +1,111 @@
+// Do not disturb: set with `/dnd`, shown to peers and kept across restarts.
+mod common;
+
+use std::{env, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    clock,
+    commands::{self, DndCommand, UserCommand},
+    control,
+    dnd::DoNotDisturb,
+    presence::Presence,
+};
+use libp2p::PeerId;
+
+#[test]
+fn dnd_command_parses() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(parse("/dnd"), Ok(UserCommand::Dnd(DndCommand::Show)));
+    assert_eq!(parse("/dnd on"), Ok(UserCommand::Dnd(DndCommand::On)));
+    assert_eq!(parse("/dnd off"), Ok(UserCommand::Dnd(DndCommand::Off)));
+    assert_eq!(
+        parse("/dnd 45m"),
+        Ok(UserCommand::Dnd(DndCommand::For(45 * 60)))
+    );
+    assert!(parse("/dnd 0").is_err());
+    assert!(parse("/dnd later").is_err());
+}
+
+#[test]
+fn timed_dnd_runs_out() {
+    let dnd = DoNotDisturb::for_secs(45 * 60, 1000);
+    assert!(!dnd.is_over(1000 + 44 * 60));
+    assert_eq!(dnd.describe(1000 + 60), "for another 44m");
+    assert!(dnd.is_over(1000 + 45 * 60));
+
+    let dnd = DoNotDisturb::indefinite();
+    assert!(!dnd.is_over(u64::MAX));
+    assert_eq!(dnd.describe(1000), "until /dnd off");
+}
+
+#[test]
+fn dnd_lasts_until_the_next_heartbeat_or_leave() {
+    let alice = PeerId::random();
+    let mut presence = Presence::new(10);
+    presence.heartbeat("lobby", alice, 10, false, 100);
+    presence.set_dnd("lobby", &alice, true);
+    assert!(presence.is_dnd("lobby", &alice));
+    assert!(!presence.is_dnd("other", &alice));
+
+    // Chat messages and hints between heartbeats don't change it
+    presence.seen("lobby", alice, 101);
+    presence.hint("lobby", alice, 102, false);
+    assert!(presence.is_dnd("lobby", &alice));
+
+    presence.depart("lobby", alice, 103);
+    assert!(!presence.is_dnd("lobby", &alice));
+}
+
+#[tokio::test]
+async fn dnd_survives_restarts_until_it_runs_out() {
+    let config = env::temp_dir().join(format!("p2p-chat-dnd-{}.json", process::id()));
+    let cli = common::cli(&["--config", config.to_str().unwrap()]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    assert!(!node.is_dnd());
+
+    node.handle_line("/dnd 45m").await;
+    assert!(node.is_dnd());
+    drop(node);
+    let mut node = ChatNode::new(&cli).unwrap();
+    assert!(node.is_dnd(), "still on after a restart");
+
+    node.handle_line("/dnd 1s").await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    node.tick();
+    assert!(!node.is_dnd());
+    drop(node);
+    let node = ChatNode::new(&cli).unwrap();
+    assert!(!node.is_dnd(), "ran out for good");
+    std::fs::remove_file(config).unwrap();
+}
+
+#[tokio::test]
+async fn peers_see_dnd_in_their_roster() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (bob_id, room) = (bob.local_peer_id(), common::topic().hash().into_string());
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &control) && common::has_subscriber(b, &control)
+    })
+    .await;
+
+    bob.handle_line("/dnd on").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.presence().is_dnd(&room, &bob_id)
+    })
+    .await;
+    assert!(!alice.presence().is_away(&room, &bob_id));
+    assert!(alice
+        .presence()
+        .status(&room, &bob_id, clock::unix_time())
+        .is_some());
+
+    bob.handle_line("/dnd off").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        !alice.presence().is_dnd(&room, &bob_id)
+    })
+    .await;
+}
@@ -0,0 +1,103 @@
+// Checks of the environment, from `p2p-chat doctor` and `/doctor`.
+use std::{env, fs, net::TcpListener, process};
+
+use clap::Parser;
+use concurrent_chat_server::{
+    cli::{Cli, Command},
+    commands::{self, UserCommand},
+    doctor::{self, ClockSamples, Settings, Status, CLOCK_FAIL_SECS, MIN_CLOCK_SAMPLES},
+};
+
+#[test]
+fn doctor_parses_as_a_subcommand_and_a_command() {
+    let cli = Cli::try_parse_from(["p2p-chat", "doctor"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Doctor)));
+    assert_eq!(commands::parse("/doctor"), Some(Ok(UserCommand::Doctor)));
+}
+
+#[test]
+fn the_clock_is_judged_by_how_far_off_it_is() {
+    let check = doctor::judge_clock(1, "peers' clocks");
+    assert_eq!(check.status, Status::Pass);
+    assert_eq!(check.detail, "1 s behind peers' clocks");
+    let check = doctor::judge_clock(-10, "peers' clocks");
+    assert_eq!(check.status, Status::Warn);
+    assert_eq!(check.detail, "10 s ahead of peers' clocks");
+    assert!(check.hint.is_some());
+    assert_eq!(
+        doctor::judge_clock(CLOCK_FAIL_SECS, "pool.ntp.org").status,
+        Status::Fail
+    );
+}
+
+#[test]
+fn peers_clocks_count_once_enough_messages_were_seen() {
+    let mut samples = ClockSamples::default();
+    for _ in 1..MIN_CLOCK_SAMPLES {
+        samples.record(1_000, 1_000);
+    }
+    assert_eq!(samples.offset(), None);
+    // One message held up on the way doesn't move the median
+    samples.record(1_000, 1_100);
+    samples.record(1_000, 1_000);
+    assert_eq!(samples.offset(), Some(0));
+}
+
+#[test]
+fn an_unwritable_config_directory_fails() {
+    let dir = env::temp_dir().join(format!("p2p-chat-doctor-{}", process::id()));
+    assert_eq!(
+        doctor::check_config_dir(&dir.join("p2p-chat")).status,
+        Status::Pass
+    );
+    // A file where the directory should be
+    fs::write(&dir, "").unwrap();
+    assert_eq!(
+        doctor::check_config_dir(&dir.join("p2p-chat")).status,
+        Status::Fail
+    );
+    fs::remove_file(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn a_config_directory_others_can_write_to_warns() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = env::temp_dir().join(format!("p2p-chat-doctor-shared-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+    assert_eq!(doctor::check_config_dir(&dir).status, Status::Pass);
+    fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+    assert_eq!(doctor::check_config_dir(&dir).status, Status::Warn);
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn the_relay_is_dialed() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let relay = format!("/ip4/127.0.0.1/tcp/{port}").parse().unwrap();
+    assert_eq!(doctor::check_relay(&relay).status, Status::Pass);
+    drop(listener);
+    assert_eq!(doctor::check_relay(&relay).status, Status::Fail);
+    let quic = "/ip4/127.0.0.1/udp/4001/quic-v1".parse().unwrap();
+    assert_eq!(doctor::check_relay(&quic).status, Status::Pass);
+}
+
+#[test]
+fn the_report_lists_every_check_with_a_summary() {
+    let settings = Settings {
+        peer_offset: Some(0),
+        ..Settings::default()
+    };
+    let report = doctor::run(&settings);
+    let names: Vec<_> = report.checks.iter().map(|check| check.name).collect();
+    assert_eq!(names, ["TCP", "UDP (QUIC)", "mDNS", "clock"]);
+    assert!(!report.has_failures(), "{report}");
+    let text = report.to_string();
+    assert!(
+        text.lines().all(|line| line.starts_with("[doctor] ")),
+        "{text}"
+    );
+    assert!(text.ends_with("0 failed"), "{text}");
+}
@@ -0,0 +1,149 @@
+// Messages carried from node to node over the simulated Bluetooth link.
+mod common;
+
+use std::{collections::HashSet, fs, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    dtn::{self, Bundle, Carrier, SignedBundle, BUNDLE_TTL},
+    message::ChatMessage,
+};
+use libp2p::identity::Keypair;
+use serde_json::Value;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+const NOW: u64 = 1_700_000_000;
+
+// Short, since Unix domain socket paths are.
+
+fn bundle(keypair: &Keypair, text: &str, now: u64) -> (SignedBundle, Bundle) {
+    let message = ChatMessage::new("alice", text, now);
+    let bundle = Bundle::new("lobby", &message, now);
+    (SignedBundle::sign(keypair, &bundle).unwrap(), bundle)
+}
+
+#[test]
+fn the_carrier_keeps_the_newest_unexpired_bundles() {
+    let keypair = Keypair::generate_ed25519();
+    let mut carrier = Carrier::new(2);
+    let (first, first_bundle) = bundle(&keypair, "one", NOW);
+    let (second, second_bundle) = bundle(&keypair, "two", NOW);
+    let (third, third_bundle) = bundle(&keypair, "three", NOW);
+    assert!(carrier.carry(first.clone(), &first_bundle, NOW));
+    assert!(!carrier.carry(first, &first_bundle, NOW));
+    assert!(carrier.carry(second, &second_bundle, NOW));
+    // The oldest makes room
+    assert!(carrier.carry(third, &third_bundle, NOW));
+    let ids: Vec<_> = carrier.ids().collect();
+    assert_eq!(ids, [second_bundle.id.as_str(), third_bundle.id.as_str()]);
+    let have = HashSet::from([second_bundle.id.clone()]);
+    assert_eq!(carrier.missing(&have).len(), 1);
+
+    // Nothing is carried past its expiry, however far out the author put it
+    let (late, late_bundle) = bundle(&keypair, "late", NOW - BUNDLE_TTL);
+    assert!(!carrier.carry(late, &late_bundle, NOW));
+    let (far, mut far_bundle) = bundle(&keypair, "far", NOW);
+    far_bundle.expires = u64::MAX;
+    assert!(carrier.carry(far, &far_bundle, NOW));
+    carrier.expire(NOW + BUNDLE_TTL);
+    assert!(carrier.is_empty());
+}
+
+#[tokio::test]
+async fn neighbours_swap_what_the_other_lacks() {
+    let (alice_key, bob_key) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let (alice_id, bob_id) = (
+        alice_key.public().to_peer_id(),
+        bob_key.public().to_peer_id(),
+    );
+    let mut alice = Carrier::new(dtn::MAX_CARRIED);
+    let mut bob = Carrier::new(dtn::MAX_CARRIED);
+    let (hello, hello_bundle) = bundle(&alice_key, "hello", NOW);
+    let (shared, shared_bundle) = bundle(&alice_key, "both have it", NOW);
+    let (reply, reply_bundle) = bundle(&bob_key, "hi", NOW);
+    alice.carry(hello, &hello_bundle, NOW);
+    alice.carry(shared.clone(), &shared_bundle, NOW);
+    bob.carry(shared, &shared_bundle, NOW);
+    bob.carry(reply, &reply_bundle, NOW);
+    // Bob carries a bundle someone altered on the way
+    let (forged, forged_bundle) = bundle(&bob_key, "pay me", NOW);
+    let mut altered = serde_json::to_value(&forged).unwrap();
+    let payload = altered["payload"]
+        .as_str()
+        .unwrap()
+        .replace("pay me", "pay us");
+    altered["payload"] = Value::String(payload);
+    let altered: SignedBundle = serde_json::from_value(altered).unwrap();
+    bob.carry(altered, &forged_bundle, NOW);
+
+    let (a, b) = tokio::net::UnixStream::pair().unwrap();
+    let (met_bob, met_alice) = tokio::join!(
+        dtn::encounter(a.compat(), alice_id, &mut alice, NOW),
+        dtn::encounter(b.compat(), bob_id, &mut bob, NOW),
+    );
+    let (met_bob, met_alice) = (met_bob.unwrap(), met_alice.unwrap());
+    assert_eq!((met_bob.peer, met_bob.sent), (bob_id, 1));
+    assert_eq!(met_bob.received, [(bob_id, reply_bundle)]);
+    assert_eq!(met_bob.forged, 1);
+    assert_eq!((met_alice.peer, met_alice.sent), (alice_id, 2));
+    assert_eq!(met_alice.received, [(alice_id, hello_bundle)]);
+    assert_eq!((alice.len(), bob.len()), (3, 4));
+}
+
+// Drive the radios of both nodes until `done` returns true.
+async fn meet_until(
+    a: &mut ChatNode,
+    b: &mut ChatNode,
+    mut done: impl FnMut(&ChatNode, &ChatNode) -> bool,
+) {
+    tokio::time::timeout(Duration::from_secs(20), async {
+        while !done(a, b) {
+            tokio::select! {
+                Some(event) = a.next_dtn_event() => a.handle_dtn_event(event),
+                Some(event) = b.next_dtn_event() => b.handle_dtn_event(event),
+            }
+        }
+    })
+    .await
+    .expect("carried before the timeout");
+}
+
+#[tokio::test]
+async fn a_carrier_takes_messages_across_a_gap() {
+    let range = common::temp_path("range");
+    let _ = fs::remove_dir_all(&range);
+    let args = ["--dtn-mode", "--dtn-range", range.to_str().unwrap()];
+    let node = |nick: &str| {
+        let cli = common::cli(&[&args[..], &["--nick", nick]].concat());
+        ChatNode::new(&cli).unwrap()
+    };
+
+    // Alice writes while only Carol is in range, and goes away
+    let mut alice = node("alice");
+    let mut carol = node("carol");
+    let advertisement = alice.dtn().unwrap().advertisement().to_path_buf();
+    assert!(advertisement.exists());
+    alice.handle_line("anyone out there?").await;
+    meet_until(&mut alice, &mut carol, |_, carol| {
+        carol.history().count() == 1
+    })
+    .await;
+    let alice_id = alice.local_peer_id();
+    drop(alice);
+    assert!(!advertisement.exists());
+
+    // Bob only ever meets Carol, who hands him what she carries
+    let mut bob = node("bob");
+    // Both sides of the encounter are counted before looking
+    meet_until(&mut carol, &mut bob, |carol, bob| {
+        bob.history().count() == 1 && carol.stats().counters.dtn_sent == 1
+    })
+    .await;
+    let carried = bob.history().next().unwrap();
+    assert_eq!(carried.source, Some(alice_id));
+    assert_eq!(&*carried.message.body, "anyone out there?");
+    assert_eq!(carried.id, carol.history().next().unwrap().id);
+    let stats = carol.stats().counters;
+    assert_eq!((stats.dtn_received, stats.dtn_sent), (1, 1));
+    fs::remove_dir_all(&range).unwrap();
+}
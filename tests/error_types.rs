@@ -0,0 +1,62 @@
+// Errors of the public API can be matched on by type.
+mod common;
+
+use std::{env, fs, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    error::{self, ChatError, ConfigError},
+    node,
+};
+use libp2p::gossipsub;
+
+#[test]
+fn missing_swarm_key_is_a_read_error() {
+    let cli = common::cli(&["--swarm-key", "/nonexistent/swarm.key"]);
+    match node::build_swarm(&cli) {
+        Err(ChatError::Config(ConfigError::Read { path, .. })) => {
+            assert_eq!(path.to_str(), Some("/nonexistent/swarm.key"))
+        }
+        other => panic!("expected a read error, got {:?}", other.err()),
+    }
+}
+
+#[test]
+fn malformed_files_are_parse_errors() {
+    let dir = env::temp_dir();
+    let key = dir.join(format!("p2p-chat-bad-key-{}", process::id()));
+    fs::write(&key, "not a swarm key").unwrap();
+    let result = node::build_swarm(&common::cli(&["--swarm-key", key.to_str().unwrap()]));
+    fs::remove_file(&key).unwrap();
+    assert!(matches!(
+        result,
+        Err(ChatError::Config(ConfigError::SwarmKey { .. }))
+    ));
+
+    let config = dir.join(format!("p2p-chat-bad-config-{}.json", process::id()));
+    fs::write(&config, "{").unwrap();
+    let result = ChatNode::new(&common::cli(&["--config", config.to_str().unwrap()]));
+    fs::remove_file(&config).unwrap();
+    let Err(err) = result else {
+        panic!("config should be rejected");
+    };
+    assert!(matches!(err, ChatError::Config(ConfigError::Parse { .. })));
+    assert!(err.to_string().starts_with("invalid config file"));
+}
+
+#[test]
+fn publish_errors_say_what_to_do() {
+    let alone = ChatError::from(gossipsub::PublishError::InsufficientPeers);
+    assert!(matches!(
+        alone,
+        ChatError::Gossipsub(gossipsub::PublishError::InsufficientPeers)
+    ));
+    assert!(
+        alone.to_string().contains("no peers in the room yet"),
+        "{alone}"
+    );
+    let large = error::describe_publish_error(&gossipsub::PublishError::MessageTooLarge);
+    assert!(large.contains("Shorten it"), "{large}");
+    let duplicate = error::describe_publish_error(&gossipsub::PublishError::Duplicate);
+    assert!(duplicate.contains("Change it"), "{duplicate}");
+}
@@ -0,0 +1,131 @@
+// Gossipsub event handlers driven by a mock instead of a real network.
+mod common;
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    message::{self, ChatMessage, Identity},
+};
+use libp2p::{
+    gossipsub::{self, MessageAcceptance, MessageId},
+    PeerId,
+};
+
+use common::MockGossipsub;
+
+// A message from `source`, relayed directly by it unless it is unsigned.
+fn message(source: Option<PeerId>, seq: u64, body: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new("bob", body, 0);
+    let event = common::message_event(source.unwrap_or_else(PeerId::random), seq, chat.encode());
+    match source {
+        Some(_) => event,
+        None => common::unsigned(event),
+    }
+}
+
+fn expect(gossip: &mut MockGossipsub, times: usize, accepted: fn(&MessageAcceptance) -> bool) {
+    gossip
+        .expect_report_validation()
+        .withf(move |_, _, acceptance| accepted(acceptance))
+        .times(times)
+        .return_const(true);
+}
+
+fn deliver(node: &mut ChatNode, gossip: &mut MockGossipsub, event: gossipsub::Event) {
+    if let Some(validation) = node.receive(event) {
+        validation.report(gossip);
+    }
+}
+
+#[test]
+fn messages_render_with_nick_and_signature_status() {
+    let chat = ChatMessage::new("bob\u{202e}", "hi\nthere", 0);
+    let id = MessageId::from("42");
+    let via = PeerId::random();
+    let (line, links) = message::render(&chat, true, Identity::Unverified, &id, &via);
+    assert_eq!(
+        line,
+        format!("Got message: 'hi there' from bob with id: {id} from peer: {via}")
+    );
+    assert!(links.is_empty());
+    let (line, _) = message::render(&chat, false, Identity::Unverified, &id, &via);
+    assert!(line.contains("from bob (unsigned) with id"));
+    let (line, _) = message::render(&chat, true, Identity::Verified, &id, &via);
+    assert!(line.contains("from bob ✓ with id"));
+}
+
+#[test]
+fn valid_messages_are_accepted_and_stored() {
+    let mut node = ChatNode::new(&common::cli(&[])).unwrap();
+    let mut gossip = MockGossipsub::new();
+    let bob = PeerId::random();
+    gossip
+        .expect_report_validation()
+        .withf(move |id, source, acceptance| {
+            *id == MessageId::from(format!("{bob}-1"))
+                && *source == bob
+                && matches!(acceptance, MessageAcceptance::Accept)
+        })
+        .times(1)
+        .return_const(true);
+
+    deliver(&mut node, &mut gossip, message(Some(bob), 1, "hello"));
+    let stored: Vec<_> = node.history().collect();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].topic, common::topic().hash().as_str());
+    assert_eq!(node.display_name(&bob), "bob");
+}
+
+#[test]
+fn flooding_past_the_rate_limit_bans_the_sender() {
+    let mut node = ChatNode::new(&common::cli(&["--rate-limit", "2"])).unwrap();
+    let mut gossip = MockGossipsub::new();
+    expect(&mut gossip, 3, |a| matches!(a, MessageAcceptance::Accept));
+    let bob = PeerId::random();
+    for seq in 0..3 {
+        deliver(
+            &mut node,
+            &mut gossip,
+            message(Some(bob), seq, &format!("msg {seq}")),
+        );
+    }
+    assert!(node.is_blocked(&bob));
+    assert_eq!(node.history().count(), 2);
+}
+
+#[test]
+fn republished_content_counts_as_duplicate_and_floods_are_ignored() {
+    let cli = common::cli(&["--repeat-threshold", "2"]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    let mut gossip = MockGossipsub::new();
+    expect(&mut gossip, 2, |a| matches!(a, MessageAcceptance::Accept));
+    expect(&mut gossip, 1, |a| matches!(a, MessageAcceptance::Ignore));
+    let bob = PeerId::random();
+    for seq in 0..3 {
+        deliver(
+            &mut node,
+            &mut gossip,
+            message(Some(bob), seq, "the same old thing"),
+        );
+    }
+    assert_eq!(node.stats().counters.duplicates, 2);
+    assert_eq!(node.history().count(), 2);
+}
+
+#[test]
+fn unsigned_messages_are_rejected_in_strict_mode() {
+    let mut node = ChatNode::new(&common::cli(&["--require-signed"])).unwrap();
+    let mut gossip = MockGossipsub::new();
+    expect(&mut gossip, 1, |a| matches!(a, MessageAcceptance::Reject));
+    deliver(&mut node, &mut gossip, message(None, 0, "anonymous"));
+    assert_eq!(node.history().count(), 0);
+}
+
+#[test]
+fn subscriptions_need_no_validation() {
+    let mut node = ChatNode::new(&common::cli(&[])).unwrap();
+    let event = gossipsub::Event::Subscribed {
+        peer_id: PeerId::random(),
+        topic: common::topic().hash(),
+    };
+    assert!(node.receive(event).is_none());
+}
@@ -0,0 +1,76 @@
+// Keeping slow work off the event loop, and noticing when it isn't.
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use concurrent_chat_server::{
+    disk::DiskWriter,
+    watchdog::{self, Activity, SLOW_ITERATION},
+};
+
+#[test]
+fn activities_name_commands_but_not_messages() {
+    assert_eq!(
+        Activity::input("/ban alice spam"),
+        Activity::Command("ban".to_string())
+    );
+    assert_eq!(
+        Activity::input("  /stats "),
+        Activity::Command("stats".to_string())
+    );
+    assert_eq!(Activity::input("my password is hunter2"), Activity::Chat);
+    assert_eq!(Activity::input("/").to_string(), "the command /");
+    assert_eq!(
+        Activity::Event("connection closed").to_string(),
+        "a swarm event (connection closed)"
+    );
+}
+
+#[test]
+fn the_watchdog_warns_about_slow_iterations() {
+    let (logs, _guard) = common::capture_logs();
+    assert!(!watchdog::check(&Activity::Tick, SLOW_ITERATION));
+    assert!(!logs.contains("[watchdog]"));
+
+    let slow = SLOW_ITERATION + Duration::from_millis(150);
+    assert!(watchdog::check(
+        &Activity::input("/export-topology x.dot"),
+        slow
+    ));
+    assert!(logs.contains("250 ms on the command /export-topology"));
+}
+
+#[tokio::test]
+async fn disk_writes_run_in_order_off_the_caller() {
+    let disk = DiskWriter::spawn();
+    let done = Arc::new(Mutex::new(Vec::new()));
+    for write in 0..5 {
+        let done = done.clone();
+        disk.submit(move || {
+            thread::sleep(Duration::from_millis(20));
+            done.lock().unwrap().push(write);
+        });
+    }
+    // Queuing didn't wait for the writes
+    assert!(done.lock().unwrap().len() < 5);
+
+    disk.flush().await;
+    assert_eq!(*done.lock().unwrap(), [0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn dropping_the_disk_writer_finishes_its_writes() {
+    let disk = DiskWriter::spawn();
+    let done = Arc::new(Mutex::new(false));
+    let written = done.clone();
+    disk.submit(move || {
+        thread::sleep(Duration::from_millis(50));
+        *written.lock().unwrap() = true;
+    });
+    drop(disk);
+    assert!(*done.lock().unwrap());
+}
@@ -0,0 +1,111 @@
+// Collapsing of messages a peer keeps repeating.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    autoban::{AutoBanSettings, AutoBanner, Violation},
+    flood::{self, FloodDetector, FloodSettings, Run, Verdict, SUMMARY_DELAY},
+};
+use libp2p::PeerId;
+
+fn detector() -> FloodDetector {
+    FloodDetector::new(FloodSettings {
+        threshold: 3,
+        window: 60,
+        min_length: 4,
+    })
+}
+
+#[test]
+fn copies_past_the_threshold_are_collapsed() {
+    let mut floods = detector();
+    let (bob, alice) = (PeerId::random(), PeerId::random());
+    for _ in 0..3 {
+        assert_eq!(
+            floods.check(bob, "buy cheap coins", 0),
+            (Verdict::Show, None)
+        );
+    }
+    // Case, punctuation and spacing don't make a copy new
+    assert_eq!(
+        floods.check(bob, "BUY cheap  coins!!", 1).0,
+        Verdict::Collapse
+    );
+    assert_eq!(
+        floods.check(bob, "buy cheap coins", 2),
+        (Verdict::Collapse, None)
+    );
+    // Someone else saying the same thing is not part of bob's run
+    assert_eq!(floods.check(alice, "buy cheap coins", 2).0, Verdict::Show);
+
+    // A different message ends the run so it can be summarized
+    let (verdict, ended) = floods.check(bob, "sorry about that", 3);
+    assert_eq!(verdict, Verdict::Show);
+    assert_eq!(
+        ended,
+        Some(Run {
+            peer: bob,
+            body: "BUY cheap  coins!!".to_string(),
+            hidden: 2,
+        })
+    );
+
+    // Copies only count within the window
+    assert_eq!(floods.check(bob, "buy cheap coins", 61).0, Verdict::Show);
+}
+
+#[test]
+fn short_messages_are_exempt_and_idle_runs_end() {
+    let mut floods = detector();
+    let peer = PeerId::random();
+    assert_eq!(flood::normalize("+1 OK!"), "1ok");
+    for now in 0..20 {
+        assert_eq!(floods.check(peer, "+1", now).0, Verdict::Show);
+    }
+
+    for _ in 0..5 {
+        floods.check(peer, "same old line", 100);
+    }
+    assert!(floods.finish(100 + SUMMARY_DELAY - 1).is_empty());
+    let runs = floods.finish(100 + SUMMARY_DELAY);
+    assert_eq!(runs.len(), 1);
+    assert_eq!(runs[0].hidden, 2);
+    assert!(floods.finish(200).is_empty());
+}
+
+#[test]
+fn repeats_count_towards_a_ban() {
+    let mut bans = AutoBanner::new(AutoBanSettings {
+        rate_limit: 100,
+        invalid_limit: 2,
+        ban_duration: 600,
+    });
+    let peer = PeerId::random();
+    assert_eq!(bans.record_repeat(peer, 0), None);
+    assert_eq!(bans.record_repeat(peer, 0), None);
+    let ban = bans.record_repeat(peer, 0).unwrap();
+    assert_eq!(ban.violation, Violation::Repetition);
+}
+
+#[tokio::test]
+async fn repeated_messages_stay_out_of_history() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let bob_cli = common::cli(&["--repeat-threshold", "2"]);
+    let (mut bob, bob_addr) = common::spawn_chat_node(&bob_cli).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = alice.topic().clone();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &topic)
+    })
+    .await;
+
+    for _ in 0..4 {
+        alice.handle_line("join my server now").await;
+    }
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.stats().counters.received == 4
+    })
+    .await;
+    assert_eq!(bob.history().count(), 2);
+}
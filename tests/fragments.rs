@@ -0,0 +1,110 @@
+// Large chat messages split into fragments and put back together.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    fragment::{self, Assembly, FragmentError, Reassembler, REASSEMBLY_TIMEOUT},
+    message::ChatMessage,
+};
+use libp2p::PeerId;
+
+fn message(len: usize) -> ChatMessage {
+    ChatMessage::new("alice", "x".repeat(len), 1)
+}
+
+#[test]
+fn fragments_reassemble_in_any_order() {
+    let original = message(10_000);
+    let mut frags = fragment::fragment(&original, 1024);
+    assert_eq!(frags.len(), 10);
+    assert!(frags.iter().all(|f| f.total == 10 && f.data.len() <= 1024));
+    frags.reverse();
+    assert_eq!(fragment::reassemble(frags.clone()).unwrap(), original);
+
+    frags.remove(3);
+    assert!(matches!(
+        fragment::reassemble(frags),
+        Err(FragmentError::Missing(_))
+    ));
+    let mut mixed = fragment::fragment(&original, 1024);
+    mixed[0].msg_id = fragment::fragment(&original, 1024)[0].msg_id;
+    assert!(matches!(
+        fragment::reassemble(mixed),
+        Err(FragmentError::Mismatched)
+    ));
+}
+
+#[test]
+fn reassembler_delivers_once_every_fragment_is_in() {
+    let (alice, mallory) = (PeerId::random(), PeerId::random());
+    let original = message(3000);
+    let mut frags = fragment::fragment(&original, 1000).into_iter();
+    let mut reassembler = Reassembler::default();
+
+    let first = frags.next().unwrap();
+    assert!(matches!(
+        reassembler.add(alice, first.clone(), 100),
+        Assembly::Pending
+    ));
+    // Another peer can't slip fragments into alice's message
+    assert!(matches!(
+        reassembler.add(mallory, first, 100),
+        Assembly::Dropped
+    ));
+    let mut last = Assembly::Pending;
+    for frag in frags {
+        last = reassembler.add(alice, frag, 101);
+    }
+    match last {
+        Assembly::Complete(Ok(chat)) => assert_eq!(chat, original),
+        other => panic!("expected the whole message, got {other:?}"),
+    }
+    assert_eq!(reassembler.pending(), 0);
+}
+
+#[test]
+fn incomplete_messages_expire() {
+    let alice = PeerId::random();
+    let mut reassembler = Reassembler::default();
+    let first = fragment::fragment(&message(3000), 1000).remove(0);
+    reassembler.add(alice, first, 100);
+
+    assert_eq!(reassembler.expire(100 + REASSEMBLY_TIMEOUT - 1), 0);
+    assert_eq!(reassembler.expire(100 + REASSEMBLY_TIMEOUT), 1);
+    assert_eq!(reassembler.pending(), 0);
+}
+
+#[tokio::test]
+async fn large_messages_arrive_whole() {
+    let cli = common::cli(&["--max-body", "2000000"]);
+    let (mut alice, _) = common::spawn_chat_node(&cli).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&cli).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = alice.topic().clone();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &topic)
+    })
+    .await;
+
+    let body = "y".repeat(fragment::FRAGMENT_THRESHOLD + 100_000);
+    let published = alice.stats().counters.published;
+    alice.handle_line(&body).await;
+    assert_eq!(
+        alice.stats().counters.published,
+        published + 3,
+        "sent in three fragments"
+    );
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+    assert_eq!(&*bob.history().next().unwrap().message.body, body);
+}
+
+#[tokio::test]
+async fn messages_over_the_body_limit_are_not_sent() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--max-body", "10"])).await;
+    alice.handle_line("this is more than ten bytes").await;
+    assert_eq!(alice.stats().counters.published, 0);
+}
@@ -0,0 +1,216 @@
+// Gossip propagation across a small star network, each node running on its own task.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{chat::ChatNode, message::ChatMessage};
+use libp2p::{futures::StreamExt, multiaddr::Protocol, Multiaddr, PeerId};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+const NODES: usize = 5;
+
+// Instructions the test sends to a node's task.
+enum Command {
+    Dial(Multiaddr),
+    Disconnect(PeerId),
+    Publish(String),
+    // Reply with the number of peers subscribed to the chat topic
+    Subscribers(oneshot::Sender<usize>),
+}
+
+// A chat message shown by node `node`.
+#[derive(Debug, Clone)]
+struct Delivery {
+    node: usize,
+    body: String,
+}
+
+struct Handle {
+    peer: PeerId,
+    addr: Multiaddr,
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Handle {
+    fn send(&self, command: Command) {
+        self.commands.send(command).expect("node task is running");
+    }
+
+    async fn subscribers(&self) -> usize {
+        let (tx, rx) = oneshot::channel();
+        self.send(Command::Subscribers(tx));
+        rx.await.expect("node task replies")
+    }
+}
+
+// Run a chat node on its own task, reporting every message it stores on `deliveries`.
+async fn spawn(node: usize, deliveries: broadcast::Sender<Delivery>) -> Handle {
+    let (mut chat, addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let peer = chat.local_peer_id();
+    let addr = addr.with(Protocol::P2p(peer));
+    let (commands, mut rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                event = chat.swarm.select_next_some() => {
+                    let before = chat.history().count();
+                    chat.handle_event(event);
+                    for stored in chat.history().skip(before) {
+                        let _ = deliveries.send(Delivery {
+                            node,
+                            body: stored.message.body.to_string(),
+                        });
+                    }
+                }
+                command = rx.recv() => match command {
+                    Some(command) => run(&mut chat, command),
+                    None => return,
+                },
+            }
+        }
+    });
+    Handle {
+        peer,
+        addr,
+        commands,
+    }
+}
+
+fn run(chat: &mut ChatNode, command: Command) {
+    match command {
+        Command::Dial(addr) => chat.swarm.dial(addr).expect("dials"),
+        Command::Disconnect(peer) => {
+            let _ = chat.swarm.disconnect_peer_id(peer);
+        }
+        Command::Publish(body) => {
+            let message = ChatMessage::new("a", body, 0);
+            let topic = chat.topic().clone();
+            chat.swarm
+                .behaviour_mut()
+                .gossipsub
+                .publish(topic, message.encode())
+                .expect("publishes");
+        }
+        Command::Subscribers(reply) => {
+            let topic = chat.topic().hash();
+            let count = chat
+                .swarm
+                .behaviour()
+                .gossipsub
+                .all_peers()
+                .filter(|(_, topics)| topics.contains(&&topic))
+                .count();
+            let _ = reply.send(count);
+        }
+    }
+}
+
+// Start the network: node 0 is the hub, every other node a leaf connected only to it.
+async fn star() -> (Vec<Handle>, broadcast::Sender<Delivery>) {
+    let (deliveries, _) = broadcast::channel(256);
+    let mut nodes = Vec::new();
+    for node in 0..NODES {
+        nodes.push(spawn(node, deliveries.clone()).await);
+    }
+    for leaf in &nodes[1..] {
+        leaf.send(Command::Dial(nodes[0].addr.clone()));
+    }
+    wait_for_subscriptions(&nodes[0], &nodes[1..]).await;
+    (nodes, deliveries)
+}
+
+// Wait until the hub and each of `leaves` see each other's subscriptions.
+async fn wait_for_subscriptions(hub: &Handle, leaves: &[Handle]) {
+    let expected = NODES - 1;
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let mut ready = hub.subscribers().await == expected;
+            for leaf in leaves {
+                ready &= leaf.subscribers().await == 1;
+            }
+            if ready {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("subscriptions propagate before timeout");
+}
+
+// Collect the nodes that show `body` until all of `nodes` have, or `timeout` passes.
+async fn delivered_to(
+    rx: &mut broadcast::Receiver<Delivery>,
+    body: &str,
+    nodes: &[usize],
+    timeout: Duration,
+) -> Vec<usize> {
+    let mut seen = Vec::new();
+    let _ = tokio::time::timeout(timeout, async {
+        while !nodes.iter().all(|node| seen.contains(node)) {
+            let delivery = rx.recv().await.expect("deliveries keep flowing");
+            if delivery.body == body && !seen.contains(&delivery.node) {
+                seen.push(delivery.node);
+            }
+        }
+    })
+    .await;
+    seen.sort_unstable();
+    seen
+}
+
+#[tokio::test]
+async fn message_reaches_every_node() {
+    let (nodes, deliveries) = star().await;
+    let mut rx = deliveries.subscribe();
+
+    // Published by a leaf, so it has to be forwarded by the hub to reach the other leaves
+    nodes[1].send(Command::Publish("hello everyone".to_string()));
+    let others = [0, 2, 3, 4];
+    let seen = delivered_to(&mut rx, "hello everyone", &others, Duration::from_secs(5)).await;
+    assert_eq!(seen, others);
+}
+
+#[tokio::test]
+async fn partitioned_nodes_catch_up_after_reconnecting() {
+    let (nodes, deliveries) = star().await;
+    let mut rx = deliveries.subscribe();
+
+    // Cut leaves 3 and 4 off from the hub
+    for leaf in &nodes[3..] {
+        leaf.send(Command::Disconnect(nodes[0].peer));
+    }
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while nodes[0].subscribers().await != 2 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("partition takes effect before timeout");
+
+    let body = "sent during the partition";
+    nodes[1].send(Command::Publish(body.to_string()));
+    let seen = delivered_to(&mut rx, body, &[0, 2, 3, 4], Duration::from_secs(2)).await;
+    assert_eq!(seen, [0, 2]);
+
+    // Heal the partition. Gossipsub doesn't replay messages to peers that missed them, so the
+    // publisher resends, as a user would, until the healed mesh carries it to the rest.
+    for leaf in &nodes[3..] {
+        leaf.send(Command::Dial(nodes[0].addr.clone()));
+    }
+    wait_for_subscriptions(&nodes[0], &nodes[1..]).await;
+    let mut caught_up = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while caught_up.len() < 2 {
+            nodes[1].send(Command::Publish(body.to_string()));
+            for node in delivered_to(&mut rx, body, &[3, 4], Duration::from_secs(1)).await {
+                if node >= 3 && !caught_up.contains(&node) {
+                    caught_up.push(node);
+                }
+            }
+        }
+    })
+    .await
+    .expect("partitioned nodes receive the message before timeout");
+}
@@ -0,0 +1,122 @@
+// Leaving the chat cleanly when the node is told to shut down.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    control::{self, ControlMessage, SignedControl},
+    message::ChatMessage,
+    node::{MyBehaviour, MyBehaviourEvent},
+};
+use libp2p::{
+    futures::{stream, StreamExt},
+    gossipsub,
+    swarm::SwarmEvent,
+    Swarm,
+};
+use tokio::sync::{mpsc, oneshot};
+
+type Event = SwarmEvent<MyBehaviourEvent>;
+
+fn message(event: &Event) -> Option<&gossipsub::Message> {
+    match event {
+        SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(gossipsub::Event::Message {
+            message,
+            ..
+        })) => Some(message),
+        _ => None,
+    }
+}
+
+fn is_leave(event: &Event) -> bool {
+    let Some(message) = message(event).filter(|m| m.topic == control::control_topic().hash())
+    else {
+        return false;
+    };
+    let signed: SignedControl = serde_json::from_slice(&message.data).unwrap();
+    matches!(signed.verify(), Ok((_, ControlMessage::Leave { .. })))
+}
+
+// An observer listening on the chat and control topics.
+async fn observer() -> Swarm<MyBehaviour> {
+    let (mut swarm, _) = common::spawn_node(&common::cli(&[])).await;
+    swarm
+        .behaviour_mut()
+        .gossipsub
+        .subscribe(&control::control_topic())
+        .unwrap();
+    swarm
+}
+
+#[tokio::test]
+async fn shutdown_announces_leaving_and_closes_everything() {
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let mut observer = observer().await;
+    observer.dial(alice_addr).unwrap();
+
+    // Stand-ins for stdin and Ctrl-C
+    let (lines, rx) = mpsc::unbounded_channel::<String>();
+    let input = stream::unfold(rx, |mut rx| async move {
+        let line = rx.recv().await?;
+        Some((line, rx))
+    });
+    let (ctrl_c, pressed) = oneshot::channel::<()>();
+    let mut node = tokio::spawn(async move {
+        alice
+            .run(input, async {
+                let _ = pressed.await;
+            })
+            .await;
+        alice
+    });
+
+    // Send one message once alice has joined the chat topic, and press Ctrl-C once it arrives
+    let mut left = false;
+    tokio::time::timeout(Duration::from_secs(15), async {
+        loop {
+            let event = observer.select_next_some().await;
+            left |= is_leave(&event);
+            match &event {
+                SwarmEvent::Behaviour(MyBehaviourEvent::Gossipsub(
+                    gossipsub::Event::Subscribed { topic, .. },
+                )) if *topic == common::topic().hash() => {
+                    lines.send("last words".to_string()).unwrap();
+                }
+                event => {
+                    if let Some(message) =
+                        message(event).filter(|m| m.topic == common::topic().hash())
+                    {
+                        assert_eq!(&*ChatMessage::decode(&message.data, 0).body, "last words");
+                        return;
+                    }
+                }
+            }
+        }
+    })
+    .await
+    .expect("message delivered before timeout");
+    ctrl_c.send(()).unwrap();
+
+    let alice = tokio::time::timeout(Duration::from_secs(3), async {
+        loop {
+            tokio::select! {
+                alice = &mut node => return alice.expect("event loop doesn't panic"),
+                event = observer.select_next_some() => left |= is_leave(&event),
+            }
+        }
+    })
+    .await
+    .expect("event loop resolves within 3 seconds of Ctrl-C");
+
+    assert!(left, "peers are told that alice is leaving");
+    assert_eq!(alice.swarm.behaviour().gossipsub.topics().count(), 0);
+    assert_eq!(alice.swarm.connected_peers().count(), 0);
+    // The observer sees the connection close too
+    tokio::time::timeout(Duration::from_secs(1), async {
+        while observer.is_connected(&alice.local_peer_id()) {
+            observer.select_next_some().await;
+        }
+    })
+    .await
+    .expect("observer is disconnected");
+}
@@ -0,0 +1,304 @@
+// Hooks posting to the room over HTTP: their settings, what the server refuses, and a node
+// publishing a post for peers to show as one.
+mod common;
+
+use std::{fs, net::SocketAddr, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    config::Config,
+    hooks::{self, HookEvent, HookServer, HookSettings},
+    message::{self, Identity},
+    node, runtime,
+};
+use libp2p::{gossipsub::MessageId, PeerId};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const TOKEN: &str = "ci-0123456789abcdef";
+
+// Send a request, and read the status, headers and JSON body of the answer.
+async fn request(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> (u16, String, Value) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut answer = String::new();
+    runtime::timeout(Duration::from_secs(10), stream.read_to_string(&mut answer))
+        .await
+        .expect("the server answers")
+        .unwrap();
+    let (head, body) = answer.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (
+        status,
+        head.to_string(),
+        serde_json::from_str(body).unwrap(),
+    )
+}
+
+async fn post(address: SocketAddr, token: &str, body: Value) -> (u16, String, Value) {
+    request(
+        address,
+        "POST",
+        &format!("/hooks/{token}"),
+        &body.to_string(),
+    )
+    .await
+}
+
+#[test]
+fn settings_default_and_are_checked() {
+    let settings: HookSettings =
+        serde_json::from_str(&format!(r#"{{"token": "{TOKEN}", "rooms": ["ops"]}}"#)).unwrap();
+    assert_eq!(settings, HookSettings::new(TOKEN, &["ops"]));
+    assert_eq!((settings.max_bytes, settings.per_minute), (4096, 30));
+    assert!(settings.check().is_ok());
+    for bad in [
+        HookSettings::new("short", &["ops"]),
+        HookSettings::new("not/url/safe/0123456789", &["ops"]),
+        HookSettings::new(TOKEN, &[]),
+        HookSettings {
+            per_minute: 0,
+            ..settings
+        },
+    ] {
+        assert!(bad.check().is_err(), "{bad:?} accepted");
+    }
+}
+
+#[tokio::test]
+async fn the_server_takes_only_what_a_hook_may_post() {
+    let hooks = [
+        (
+            "ci".to_string(),
+            HookSettings {
+                max_bytes: 200,
+                per_minute: 2,
+                ..HookSettings::new(TOKEN, &["ops", "deploys"])
+            },
+        ),
+        (
+            "alerts".to_string(),
+            HookSettings::new("alerts-0123456789abcdef", &["deploys"]),
+        ),
+    ];
+    let localhost = "127.0.0.1:0".parse().unwrap();
+    let mut server = HookServer::bind(localhost, "ops", 40, hooks.into(), None).unwrap();
+    let address = server.local_addr();
+
+    let (status, _, answer) = post(
+        address,
+        TOKEN,
+        json!({"room": "ops", "text": "deploy finished", "nick": "deployer"}),
+    )
+    .await;
+    assert_eq!((status, answer), (202, json!({"status": "accepted"})));
+    assert_eq!(
+        common::next(server.next()).await,
+        HookEvent::Posted {
+            hook: "ci".to_string(),
+            nick: "deployer".to_string(),
+            text: "deploy finished".to_string()
+        }
+    );
+
+    let refused = [
+        // Nobody goes by the token, or nothing is there
+        (
+            "POST",
+            "/hooks/nobody-0123456789abcdef",
+            json!({"room": "ops", "text": "hi"}),
+            401,
+        ),
+        // Nor by one that only starts like a real one
+        (
+            "POST",
+            &format!("/hooks/{}", &TOKEN[..TOKEN.len() - 1]),
+            json!({"room": "ops", "text": "hi"}),
+            401,
+        ),
+        (
+            "POST",
+            &format!("/hooks/{TOKEN}x"),
+            json!({"room": "ops", "text": "hi"}),
+            401,
+        ),
+        (
+            "POST",
+            "/elsewhere",
+            json!({"room": "ops", "text": "hi"}),
+            404,
+        ),
+        ("GET", &format!("/hooks/{TOKEN}"), Value::Null, 405),
+        // This node isn't in the room, or the hook may not post to it
+        (
+            "POST",
+            &format!("/hooks/{TOKEN}"),
+            json!({"room": "deploys", "text": "hi"}),
+            404,
+        ),
+        (
+            "POST",
+            "/hooks/alerts-0123456789abcdef",
+            json!({"room": "ops", "text": "hi"}),
+            403,
+        ),
+        // Too much for the hook, or for a message
+        (
+            "POST",
+            &format!("/hooks/{TOKEN}"),
+            json!({"room": "ops", "text": "x".repeat(200)}),
+            413,
+        ),
+        (
+            "POST",
+            &format!("/hooks/{TOKEN}"),
+            json!({"room": "ops", "text": "x".repeat(41)}),
+            413,
+        ),
+        // Not a post at all
+        (
+            "POST",
+            &format!("/hooks/{TOKEN}"),
+            json!({"text": "hi"}),
+            400,
+        ),
+        (
+            "POST",
+            &format!("/hooks/{TOKEN}"),
+            json!({"room": "ops", "text": " "}),
+            400,
+        ),
+        (
+            "POST",
+            &format!("/hooks/{TOKEN}"),
+            json!({"room": "ops", "text": "hi", "nick": "a\nb"}),
+            400,
+        ),
+    ];
+    for (method, path, body, expected) in refused {
+        let (status, _, answer) = request(address, method, path, &body.to_string()).await;
+        assert_eq!(status, expected, "{method} {path} {body}: {answer}");
+        assert!(answer["error"].is_string());
+        let HookEvent::Rejected { status, .. } = common::next(server.next()).await else {
+            panic!("{method} {path} {body} was taken");
+        };
+        assert_eq!(status, expected);
+    }
+
+    // The hook's own nick stands in, and it may post twice a minute
+    let (status, _, _) = post(address, TOKEN, json!({"room": "ops", "text": "again"})).await;
+    assert_eq!(status, 202);
+    let HookEvent::Posted { nick, .. } = common::next(server.next()).await else {
+        panic!("the second post was refused");
+    };
+    assert_eq!(nick, "ci");
+    let (status, head, answer) = post(address, TOKEN, json!({"room": "ops", "text": "more"})).await;
+    assert_eq!(status, 429, "{answer}");
+    assert!(head.contains("Retry-After: "), "{head}");
+}
+
+#[tokio::test]
+async fn a_node_publishes_posts_marked_as_from_a_hook() {
+    let dir = common::temp_path("node");
+    fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("config.json");
+    let config = Config {
+        hooks: [("ci".to_string(), HookSettings::new(TOKEN, &[node::TOPIC]))].into(),
+        ..Config::default()
+    };
+    config.save(&config_path).unwrap();
+    let alice_cli = common::cli(&[
+        "--config",
+        config_path.to_str().unwrap(),
+        "--http",
+        "127.0.0.1:0",
+    ]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&alice_cli).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    let address = alice.hook_server().unwrap().local_addr();
+    let body = json!({"room": node::TOPIC, "text": "deploy finished"});
+    let (status, _, _) = post(address, TOKEN, body).await;
+    assert_eq!(status, 202);
+    next_hook_event(&mut alice).await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+    let posted = &bob.history().next().unwrap().message;
+    assert_eq!(posted.nick, "ci");
+    assert_eq!(posted.origin.as_deref(), Some(hooks::ORIGIN));
+    let (line, _) = message::render(
+        posted,
+        true,
+        Identity::Unverified,
+        &MessageId::new(b"id"),
+        &PeerId::random(),
+    );
+    assert!(line.contains("'deploy finished' from [hook] ci "), "{line}");
+    assert_eq!(alice.stats().counters.hook_posted, 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+async fn next_hook_event(node: &mut ChatNode) {
+    let event = common::next(node.next_hook_event()).await;
+    node.handle_hook_event(event);
+}
+
+#[tokio::test]
+async fn health_tells_whether_the_node_is_ready() {
+    let alice_cli = common::cli(&["--http", "127.0.0.1:0"]);
+    let (mut alice, _) = common::spawn_chat_node(&alice_cli).await;
+    let address = alice.hook_server().unwrap().local_addr();
+    // Nothing is reported before the node's first tick
+    let (status, _, health) = request(address, "GET", "/health", "").await;
+    assert_eq!((status, health), (503, json!({"ready": false})));
+
+    // Listening, but with nobody to talk to
+    alice.tick();
+    let (status, _, health) = request(address, "GET", "/health", "").await;
+    assert_eq!(status, 503);
+    assert_eq!(health["ready"], json!(false));
+    assert_eq!(health["listening"], json!(true));
+    assert_eq!(health["peer_count"], json!(0));
+    let (status, head, _) = request(address, "POST", "/health", "").await;
+    assert_eq!(status, 405);
+    assert!(head.contains("Allow: GET"), "{head}");
+
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+    let (status, _, health) = request(address, "GET", "/health", "").await;
+    assert_eq!(status, 200);
+    assert_eq!(health["ready"], json!(true));
+    assert_eq!(health["peer_count"], json!(1));
+    assert!(health["uptime"].is_u64());
+}
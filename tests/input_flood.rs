@@ -0,0 +1,67 @@
+// A flood of piped-in lines must not starve the network side of the event loop.
+mod common;
+
+use std::{
+    io::Cursor,
+    time::{Duration, Instant},
+};
+
+use concurrent_chat_server::input;
+use libp2p::futures::StreamExt;
+use tokio::sync::oneshot;
+
+const LINES: u64 = 100_000;
+
+// Longest a peer may go without hearing anything while the flood is being sent
+const MAX_GAP: Duration = Duration::from_secs(2);
+
+#[tokio::test]
+async fn piped_lines_arrive_in_order() {
+    let text: String = (0..1000).map(|n| format!("line {n}\n")).collect();
+    let lines: Vec<String> = input::spawn_lines(Cursor::new(text)).collect().await;
+    assert_eq!(lines.len(), 1000);
+    assert_eq!(lines[0], "line 0");
+    assert_eq!(lines[999], "line 999");
+}
+
+#[tokio::test]
+async fn a_piped_flood_does_not_starve_the_swarm() {
+    let generous = common::cli(&["--rate-limit", "1000000"]);
+    let (mut alice, _) = common::spawn_chat_node(&generous).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&generous).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    let text: String = (0..LINES).map(|n| format!("flood line {n}\n")).collect();
+    let flood = input::spawn_lines(Cursor::new(text));
+    let (done, finished) = oneshot::channel();
+    let alice_runs = alice.run(flood, async {
+        let _ = finished.await;
+    });
+    let bob_listens = async {
+        let mut max_gap = Duration::ZERO;
+        let mut last = Instant::now();
+        let all = tokio::time::timeout(Duration::from_secs(300), async {
+            while bob.stats().counters.received < LINES {
+                let event = bob.swarm.select_next_some().await;
+                bob.handle_event(event);
+                max_gap = max_gap.max(last.elapsed());
+                last = Instant::now();
+            }
+        })
+        .await;
+        let _ = done.send(());
+        (all.is_ok(), max_gap)
+    };
+    let ((), (all, max_gap)) = tokio::join!(alice_runs, bob_listens);
+    assert!(
+        all,
+        "bob got {} of {LINES} lines",
+        bob.stats().counters.received
+    );
+    assert!(max_gap < MAX_GAP, "bob heard nothing for {max_gap:?}");
+}
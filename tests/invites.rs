@@ -0,0 +1,158 @@
+// Signed, expiring invites to invite-only rooms.
+mod common;
+
+use std::{env, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    clock,
+    commands::{self, UserCommand},
+    error::ChatError,
+    invite::{self, Invite, InviteError, SignedInvite, DEFAULT_TTL},
+    node::TOPIC,
+};
+use libp2p::{identity::Keypair, PeerId};
+
+fn invite(expires_at: u64, invitee: Option<PeerId>) -> Invite {
+    Invite {
+        room: TOPIC.to_string(),
+        moderators: vec![],
+        invitee,
+        issued_at: 0,
+        expires_at,
+    }
+}
+
+#[test]
+fn valid_invites_name_their_owner() {
+    let owner = Keypair::generate_ed25519();
+    let signed = SignedInvite::sign(&owner, &invite(100, None)).unwrap();
+    let token = invite::encode_token(&signed);
+    assert_eq!(invite::decode_token(&token), Ok(signed.clone()));
+
+    let (signer, _) = invite::check(&signed, TOPIC, None, PeerId::random(), 99).unwrap();
+    assert_eq!(signer, owner.public().to_peer_id());
+}
+
+#[test]
+fn expired_forged_and_misused_invites_are_refused() {
+    let owner = Keypair::generate_ed25519();
+    let owner_id = owner.public().to_peer_id();
+    let presenter = PeerId::random();
+    let signed = SignedInvite::sign(&owner, &invite(100, None)).unwrap();
+
+    assert_eq!(
+        invite::check(&signed, TOPIC, Some(owner_id), presenter, 100),
+        Err(InviteError::Expired)
+    );
+    assert!(matches!(
+        invite::check(&signed, "elsewhere", None, presenter, 0),
+        Err(InviteError::WrongRoom(_))
+    ));
+
+    // Signed by someone other than the room's owner
+    let forger = Keypair::generate_ed25519();
+    let forged = SignedInvite::sign(&forger, &invite(100, None)).unwrap();
+    assert_eq!(
+        invite::check(&forged, TOPIC, Some(owner_id), presenter, 0),
+        Err(InviteError::NotOwner(forger.public().to_peer_id()))
+    );
+
+    // The owner's signature doesn't cover a tampered expiry
+    let token = invite::encode_token(&signed);
+    let json = String::from_utf8(hex::decode(&token[invite::TOKEN_PREFIX.len()..]).unwrap())
+        .unwrap()
+        .replace(r#"\"expires_at\":100"#, r#"\"expires_at\":999"#);
+    let tampered = format!("{}{}", invite::TOKEN_PREFIX, hex::encode(json));
+    let tampered = invite::decode_token(&tampered).unwrap();
+    assert!(matches!(
+        invite::check(&tampered, TOPIC, Some(owner_id), presenter, 0),
+        Err(InviteError::BadSignature(_))
+    ));
+
+    let personal = SignedInvite::sign(&owner, &invite(100, Some(PeerId::random()))).unwrap();
+    assert_eq!(
+        invite::check(&personal, TOPIC, Some(owner_id), presenter, 0),
+        Err(InviteError::WrongInvitee)
+    );
+    assert_eq!(invite::decode_token("hello"), Err(InviteError::Malformed));
+}
+
+#[test]
+fn invite_command_parses() {
+    assert_eq!(
+        commands::parse("/invite create"),
+        Some(Ok(UserCommand::InviteCreate {
+            ttl: DEFAULT_TTL,
+            invitee: None,
+            qr: false,
+        }))
+    );
+    let peer = PeerId::random();
+    assert_eq!(
+        commands::parse(&format!("/invite create 2h {peer}")),
+        Some(Ok(UserCommand::InviteCreate {
+            ttl: 7200,
+            invitee: Some(peer),
+            qr: false,
+        }))
+    );
+    assert_eq!(clock::parse_duration("90"), Some(90));
+    assert_eq!(clock::parse_duration("7d"), Some(7 * 24 * 3600));
+    assert_eq!(clock::parse_duration("soon"), None);
+}
+
+#[test]
+fn joining_with_an_expired_invite_fails() {
+    let owner = Keypair::generate_ed25519();
+    let room = common::topic().hash().into_string();
+    let expired = Invite {
+        room,
+        ..invite(clock::unix_time() - 1, None)
+    };
+    let expired = SignedInvite::sign(&owner, &expired).unwrap();
+    let token = invite::encode_token(&expired);
+    let result = ChatNode::new(&common::cli(&["--join-with", &token]));
+    assert!(matches!(
+        result,
+        Err(ChatError::Invite(InviteError::Expired))
+    ));
+}
+
+#[tokio::test]
+async fn owner_admits_a_peer_presenting_its_invite() {
+    let config = |name: &str| {
+        env::temp_dir()
+            .join(format!("p2p-chat-invite-{name}-{}.json", process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+    let (alice_config, bob_config) = (config("alice"), config("bob"));
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--config", &alice_config])).await;
+    let token = alice.create_invite(60, None).unwrap();
+
+    let bob_cli = common::cli(&["--config", &bob_config, "--join-with", &token]);
+    let (mut bob, bob_addr) = common::spawn_chat_node(&bob_cli).await;
+    let bob_id = bob.local_peer_id();
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.is_member(&bob_id)
+    })
+    .await;
+
+    // Bob's messages now reach Alice's history instead of being ignored
+    let topic = bob.topic().clone();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        common::has_subscriber(bob, &topic)
+    })
+    .await;
+    bob.handle_line("thanks for the invite").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+
+    std::fs::remove_file(alice_config).unwrap();
+    std::fs::remove_file(bob_config).unwrap();
+}
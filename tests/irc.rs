@@ -0,0 +1,399 @@
+// The IRC bridge: the line protocol, splitting for the line limit, and a node mirroring a
+// channel.
+mod common;
+
+use std::{fs, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    clock,
+    config::Config,
+    irc::{self, IrcBridge, IrcEvent, IrcSettings, Message, MAX_LINE, SOURCE_RESERVE},
+    runtime,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener,
+    },
+};
+
+// An IRC server that takes one connection at a time, driven by the test.
+struct FakeServer {
+    listener: TcpListener,
+}
+
+impl FakeServer {
+    async fn bind() -> Self {
+        FakeServer {
+            listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        }
+    }
+
+    fn address(&self) -> String {
+        self.listener.local_addr().unwrap().to_string()
+    }
+
+    async fn accept(&self) -> Client {
+        let (tcp, _) = self.listener.accept().await.unwrap();
+        let (reader, writer) = tcp.into_split();
+        Client {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+}
+
+// The bridge's connection, as the server sees it.
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn receive(&mut self) -> Message {
+        let mut line = String::new();
+        runtime::timeout(Duration::from_secs(10), self.reader.read_line(&mut line))
+            .await
+            .expect("the bridge sends something")
+            .unwrap();
+        assert!(line.ends_with("\r\n"), "{line:?} isn't a whole line");
+        assert!(
+            line.len() <= MAX_LINE - SOURCE_RESERVE,
+            "{line:?} is too long"
+        );
+        Message::parse(&line).unwrap()
+    }
+
+    // The next message with `command`, skipping any others.
+    async fn receive_command(&mut self, command: &str) -> Message {
+        loop {
+            let message = self.receive().await;
+            if message.command == command {
+                return message;
+            }
+        }
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    // Take the bridge's registration and let it join `channel` as `nick`.
+    async fn welcome(&mut self, nick: &str, channel: &str) {
+        assert_eq!(self.receive().await.command, "NICK");
+        assert_eq!(self.receive().await.command, "USER");
+        self.send(&format!(":irc.test 001 {nick} :Welcome")).await;
+        let join = self.receive().await;
+        assert_eq!(join, Message::new("JOIN", &[channel]));
+        self.send(&format!(":{nick}!bridge@host JOIN {channel}"))
+            .await;
+    }
+}
+
+fn settings(server: &str) -> IrcSettings {
+    IrcSettings {
+        server: server.to_string(),
+        tls: false,
+        channel: "#p2p".to_string(),
+        nick: "bridge".to_string(),
+        announce: true,
+    }
+}
+
+#[test]
+fn irc_messages_parse_and_format() {
+    let message =
+        Message::parse("@time=2024-01-01T00:00:00Z :carol!c@host PRIVMSG #p2p :hi there\r\n")
+            .unwrap();
+    assert_eq!(message.source.as_deref(), Some("carol!c@host"));
+    assert_eq!(message.nick(), Some("carol"));
+    assert_eq!(message.command, "PRIVMSG");
+    assert_eq!(message.params, ["#p2p", "hi there"]);
+    assert_eq!(message.param(2), "");
+    assert_eq!(message.to_string(), ":carol!c@host PRIVMSG #p2p :hi there");
+
+    let ping = Message::parse("ping irc.test").unwrap();
+    assert_eq!(ping, Message::new("PING", &["irc.test"]));
+    assert_eq!(ping.nick(), None);
+    assert_eq!(
+        Message::new("PRIVMSG", &["#p2p", ":)"]).to_string(),
+        "PRIVMSG #p2p ::)"
+    );
+    assert_eq!(Message::new("QUIT", &[""]).to_string(), "QUIT :");
+    for bad in ["", "   ", ":source-only", "@tags-only"] {
+        assert_eq!(Message::parse(bad), None, "{bad:?} parsed");
+    }
+
+    assert_eq!(
+        irc::strip_formatting("\x02bold\x02 \x0304,12red\x03 \x031,x \x1ditalic\x0f"),
+        "bold red ,x italic"
+    );
+}
+
+#[test]
+fn long_messages_are_split_to_fit_the_line_limit() {
+    let words = "lorem ipsum dolor sit amet ".repeat(60);
+    let messages = irc::say("PRIVMSG", "#p2p", "<alice> ", &words);
+    assert!(messages.len() > 1);
+    let mut pieces = Vec::new();
+    for message in &messages {
+        assert!(message.to_string().len() + 2 + SOURCE_RESERVE <= MAX_LINE);
+        let text = message.param(1).strip_prefix("<alice> ").unwrap();
+        assert!(!text.starts_with(' ') && !text.ends_with(' '));
+        pieces.push(text.to_string());
+    }
+    assert_eq!(pieces.join(" "), words.trim());
+
+    // Without spaces, lines break between characters, never inside one
+    let unbroken = "é".repeat(600);
+    let messages = irc::say("PRIVMSG", "#p2p", "", &unbroken);
+    let rejoined: String = messages.iter().map(|message| message.param(1)).collect();
+    assert_eq!(rejoined, unbroken);
+
+    // Every line of a message is a message of its own, and nothing says an empty line
+    let messages = irc::say("NOTICE", "#p2p", "* ", "one\r\n\ntwo");
+    assert_eq!(
+        messages,
+        [
+            Message::new("NOTICE", &["#p2p", "* one"]),
+            Message::new("NOTICE", &["#p2p", "* two"])
+        ]
+    );
+    assert_eq!(irc::split("a  b", 2), ["a", "b"]);
+}
+
+#[test]
+fn bridge_settings_load_from_the_config_file() {
+    let config: Config = serde_json::from_str(
+        r##"{"irc": {"server": "irc.example.com", "channel": "#team", "nick": "p2p"}}"##,
+    )
+    .unwrap();
+    let settings = config.irc.unwrap();
+    assert!(!settings.tls && !settings.announce);
+    assert_eq!(
+        settings.address(),
+        Ok(("irc.example.com".to_string(), 6667))
+    );
+    settings.check().unwrap();
+    assert!(Config::default().irc.is_none());
+
+    let tls = IrcSettings {
+        tls: true,
+        ..settings.clone()
+    };
+    assert_eq!(tls.address().unwrap().1, irc::TLS_PORT);
+    for (server, address) in [
+        ("irc.example.com:7000", ("irc.example.com", 7000)),
+        ("[::1]:6668", ("::1", 6668)),
+        ("::1", ("::1", 6667)),
+    ] {
+        let settings = IrcSettings {
+            server: server.to_string(),
+            ..settings.clone()
+        };
+        assert_eq!(settings.address(), Ok((address.0.to_string(), address.1)));
+    }
+    for (server, channel, nick) in [
+        ("irc.example.com:port", "#team", "p2p"),
+        (":6667", "#team", "p2p"),
+        ("irc.example.com", "team", "p2p"),
+        ("irc.example.com", "#a team", "p2p"),
+        ("irc.example.com", "#team", ""),
+        ("irc.example.com", "#team", "p 2 p"),
+        ("irc.example.com", "#team", "2p"),
+    ] {
+        let settings = IrcSettings {
+            server: server.to_string(),
+            channel: channel.to_string(),
+            nick: nick.to_string(),
+            ..settings.clone()
+        };
+        assert!(settings.check().is_err(), "{settings:?} accepted");
+    }
+}
+
+#[tokio::test]
+async fn the_bridge_registers_joins_relays_and_reconnects() {
+    let server = FakeServer::bind().await;
+    let mut bridge = IrcBridge::spawn(settings(&server.address()));
+    // Said before joining, so it waits for the channel
+    bridge.relay("alice", "hello irc").unwrap();
+
+    // The nick is taken: one with an underscore is asked for instead
+    let mut client = server.accept().await;
+    assert_eq!(client.receive().await, Message::new("NICK", &["bridge"]));
+    assert_eq!(client.receive().await.command, "USER");
+    client
+        .send(":irc.test 433 * bridge :Nickname is already in use")
+        .await;
+    assert_eq!(client.receive().await, Message::new("NICK", &["bridge_"]));
+    assert_eq!(
+        common::next(bridge.next()).await,
+        IrcEvent::NickInUse {
+            taken: "bridge".to_string(),
+            trying: "bridge_".to_string()
+        }
+    );
+    client.send(":irc.test 001 bridge_ :Welcome").await;
+    assert_eq!(client.receive().await, Message::new("JOIN", &["#p2p"]));
+    client.send("PING :irc.test").await;
+    assert_eq!(client.receive().await, Message::new("PONG", &["irc.test"]));
+    client.send(":bridge_!b@host JOIN #p2p").await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        IrcEvent::Connected {
+            nick: "bridge_".to_string()
+        }
+    );
+    assert_eq!(
+        client.receive().await,
+        Message::new("PRIVMSG", &["#p2p", "<alice> hello irc"])
+    );
+
+    // What happens on the channel is passed on, except our own lines and other channels
+    for line in [
+        ":carol!c@host PRIVMSG #p2p :\x02hi\x02 all",
+        ":carol!c@host PRIVMSG #elsewhere :not here",
+        ":bridge_!b@host PRIVMSG #p2p :our own",
+        ":carol!c@host PRIVMSG #p2p :\x01ACTION waves\x01",
+        ":carol!c@host PRIVMSG #p2p :\x01VERSION\x01",
+        ":dave!d@host JOIN #P2P",
+        ":dave!d@host PART #p2p :bye",
+        ":erin!e@host QUIT :Ping timeout",
+        ":op!o@host KICK #p2p frank :spam",
+    ] {
+        client.send(line).await;
+    }
+    let expected = [
+        IrcEvent::Message {
+            nick: "carol".to_string(),
+            text: "hi all".to_string(),
+        },
+        IrcEvent::Action {
+            nick: "carol".to_string(),
+            text: "waves".to_string(),
+        },
+        IrcEvent::Joined("dave".to_string()),
+        IrcEvent::Left {
+            nick: "dave".to_string(),
+            reason: "bye".to_string(),
+        },
+        IrcEvent::Left {
+            nick: "erin".to_string(),
+            reason: "Ping timeout".to_string(),
+        },
+        IrcEvent::Left {
+            nick: "frank".to_string(),
+            reason: "kicked by op: spam".to_string(),
+        },
+    ];
+    for event in expected {
+        assert_eq!(common::next(bridge.next()).await, event);
+    }
+    bridge.announce("bob joined the room").unwrap();
+    assert_eq!(
+        client.receive().await,
+        Message::new("NOTICE", &["#p2p", "* bob joined the room"])
+    );
+
+    // Kicked, the bridge connects again and rejoins
+    client.send(":op!o@host KICK #p2p bridge_ :out").await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        IrcEvent::Disconnected("kicked by op: out".to_string())
+    );
+    let mut client = server.accept().await;
+    client.welcome("bridge", "#p2p").await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        IrcEvent::Connected {
+            nick: "bridge".to_string()
+        }
+    );
+    drop(client);
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        IrcEvent::Disconnected(_)
+    ));
+}
+
+// Connect `bob` to `alice` and wait until both are in the room.
+async fn join(alice: &mut ChatNode, bob: &mut ChatNode, alice_addr: libp2p::Multiaddr) {
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(alice, bob, Duration::from_secs(10), |alice, bob| {
+        common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn a_bridge_node_mirrors_the_channel_without_loops() {
+    let server = FakeServer::bind().await;
+    let dir = common::temp_path("node");
+    fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("config.json");
+    let config = Config {
+        irc: Some(settings(&server.address())),
+        ..Config::default()
+    };
+    config.save(&config_path).unwrap();
+    let alice_cli = common::cli(&["--config", config_path.to_str().unwrap()]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&alice_cli).await;
+    let mut client = server.accept().await;
+    client.welcome("bridge", "#p2p").await;
+    assert!(matches!(
+        common::next(alice.next_irc_event()).await,
+        IrcEvent::Connected { .. }
+    ));
+
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    join(&mut alice, &mut bob, alice_addr).await;
+    // Bob joining the room is announced on the channel, once his presence is known
+    let (bob_id, room) = (bob.local_peer_id(), common::topic().hash().into_string());
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.roster().is_subscribed(&room, &bob_id)
+            && alice
+                .presence()
+                .status(&room, &bob_id, clock::unix_time())
+                .is_some()
+    })
+    .await;
+    alice.tick();
+    let notice = client.receive_command("NOTICE").await;
+    assert!(notice.param(1).ends_with(" joined the room"), "{notice}");
+
+    // The room's messages are said on the channel with their author's nick
+    bob.handle_line("hello irc").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+    let said = client.receive_command("PRIVMSG").await;
+    assert_eq!(said, Message::new("PRIVMSG", &["#p2p", "<bob> hello irc"]));
+
+    // The channel's lines come to the room marked as from IRC, and aren't said back
+    client.send(":carol!c@host PRIVMSG #p2p :hi from irc").await;
+    client.send(":dave!d@host JOIN #p2p").await;
+    for _ in 0..2 {
+        let event = common::next(alice.next_irc_event()).await;
+        alice.handle_irc_event(event);
+    }
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 2
+    })
+    .await;
+    let bodies: Vec<&str> = bob.history().map(|stored| &*stored.message.body).collect();
+    assert_eq!(bodies, ["* dave joined #p2p on IRC", "<carol> hi from irc"]);
+    assert!(bob
+        .history()
+        .all(|stored| stored.message.origin.as_deref() == Some(irc::ORIGIN)));
+    let counters = alice.stats().counters;
+    assert_eq!((counters.irc_sent, counters.irc_received), (1, 2));
+    fs::remove_dir_all(&dir).unwrap();
+}
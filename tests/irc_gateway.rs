@@ -0,0 +1,252 @@
+// The IRC gateway: IRC clients registering, joining the room's channel and chatting with each
+// other and with the room.
+mod common;
+
+use std::time::Duration;
+
+use clap::Parser;
+use concurrent_chat_server::{
+    chat::ChatNode,
+    cli::Cli,
+    gateway::{Gateway, GatewayEvent},
+    irc::Message,
+    runtime,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+// An IRC client connected to a gateway.
+struct Client {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    async fn connect(gateway: &Gateway) -> Self {
+        let tcp = TcpStream::connect(gateway.local_addr()).await.unwrap();
+        let (reader, writer) = tcp.into_split();
+        Client {
+            reader: BufReader::new(reader),
+            writer,
+        }
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.writer
+            .write_all(format!("{line}\r\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    async fn receive(&mut self) -> Message {
+        let mut line = String::new();
+        runtime::timeout(Duration::from_secs(10), self.reader.read_line(&mut line))
+            .await
+            .expect("the gateway sends something")
+            .unwrap();
+        assert!(line.ends_with("\r\n"), "{line:?} isn't a whole line");
+        Message::parse(&line).unwrap()
+    }
+
+    // The next message with `command`, skipping any others.
+    async fn receive_command(&mut self, command: &str) -> Message {
+        loop {
+            let message = self.receive().await;
+            if message.command == command {
+                return message;
+            }
+        }
+    }
+
+    // Whether the gateway closed the connection, reading whatever came before.
+    async fn closed(&mut self) -> bool {
+        let mut rest = String::new();
+        runtime::timeout(Duration::from_secs(10), self.reader.read_line(&mut rest))
+            .await
+            .is_ok_and(|read| read.unwrap() == 0)
+    }
+}
+
+// Handle what happened on the next client connection.
+async fn step(gateway: &mut Gateway) -> Option<GatewayEvent> {
+    let event = runtime::timeout(Duration::from_secs(10), gateway.next())
+        .await
+        .expect("a client does something")
+        .unwrap();
+    gateway.handle(event)
+}
+
+// Pass the gateway's next client event to the node.
+async fn step_node(node: &mut ChatNode) {
+    let event = runtime::timeout(Duration::from_secs(10), node.next_gateway_event())
+        .await
+        .expect("a client does something")
+        .unwrap();
+    node.handle_gateway_event(event);
+}
+
+#[test]
+fn the_gateway_address_parses() {
+    let cli = Cli::try_parse_from(["p2p-chat", "--irc-gateway", "127.0.0.1:6667"]).unwrap();
+    assert_eq!(cli.irc_gateway, Some("127.0.0.1:6667".parse().unwrap()));
+    assert!(Cli::try_parse_from(["p2p-chat", "--irc-gateway", "localhost"]).is_err());
+}
+
+#[tokio::test]
+async fn clients_register_join_and_chat() {
+    let mut gateway = Gateway::bind("127.0.0.1:0".parse().unwrap(), "room").unwrap();
+    assert_eq!(gateway.channel(), "#room");
+    let mut carol = Client::connect(&gateway).await;
+    assert_eq!(step(&mut gateway).await, None);
+    let mut dave = Client::connect(&gateway).await;
+    assert_eq!(step(&mut gateway).await, None);
+
+    // Nothing but registering is allowed before a nick is picked
+    carol.send("JOIN #room").await;
+    assert_eq!(step(&mut gateway).await, None);
+    assert_eq!(carol.receive().await.command, "451");
+    carol.send("NICK carol").await;
+    match step(&mut gateway).await {
+        Some(GatewayEvent::Registered { nick, .. }) => assert_eq!(nick, "carol"),
+        event => panic!("{event:?}"),
+    }
+    assert_eq!(carol.receive().await.command, "001");
+    for line in ["NICK CAROL", "NICK 2dave", "NICK dave"] {
+        dave.send(line).await;
+    }
+    assert_eq!(step(&mut gateway).await, None);
+    assert_eq!(dave.receive().await.command, "433");
+    assert_eq!(step(&mut gateway).await, None);
+    assert_eq!(dave.receive().await.command, "432");
+    assert!(matches!(
+        step(&mut gateway).await,
+        Some(GatewayEvent::Registered { .. })
+    ));
+
+    // Only the room's channel can be joined, and its members are listed on joining
+    carol.send("JOIN #elsewhere").await;
+    carol.send("JOIN #room").await;
+    dave.send("JOIN #ROOM").await;
+    for _ in 0..3 {
+        assert_eq!(step(&mut gateway).await, None);
+    }
+    assert_eq!(carol.receive_command("403").await.param(1), "#elsewhere");
+    let joined = carol.receive_command("JOIN").await;
+    assert_eq!(joined.source.as_deref(), Some("carol!carol@p2p-chat"));
+    let names = dave.receive_command("353").await;
+    assert_eq!(names.param(3), "carol dave");
+    dave.receive_command("366").await;
+    let joined = carol.receive_command("JOIN").await;
+    assert_eq!(joined.nick(), Some("dave"));
+
+    // What a client says goes to the node and to the other clients
+    carol.send("PRIVMSG #room :\x02hi\x02 dave").await;
+    assert_eq!(
+        step(&mut gateway).await,
+        Some(GatewayEvent::Said {
+            nick: "carol".to_string(),
+            text: "hi dave".to_string()
+        })
+    );
+    let said = dave.receive_command("PRIVMSG").await;
+    assert_eq!(said.nick(), Some("carol"));
+    assert_eq!(said.params, ["#room", "\x02hi\x02 dave"]);
+    dave.send("PRIVMSG #room :\x01ACTION waves\x01").await;
+    assert_eq!(
+        step(&mut gateway).await,
+        Some(GatewayEvent::Said {
+            nick: "dave".to_string(),
+            text: "* dave waves".to_string()
+        })
+    );
+    let said = carol.receive_command("PRIVMSG").await;
+    assert_eq!(said.param(1), "\x01ACTION waves\x01");
+    dave.send("PRIVMSG erin :hello").await;
+    assert_eq!(step(&mut gateway).await, None);
+    assert_eq!(dave.receive_command("401").await.param(1), "erin");
+    dave.send("TOPIC #room").await;
+    assert_eq!(step(&mut gateway).await, None);
+    assert_eq!(dave.receive_command("421").await.param(1), "TOPIC");
+
+    // The room's messages go to everyone in the channel
+    gateway.deliver("bob smith", "hello from the room");
+    for client in [&mut carol, &mut dave] {
+        let said = client.receive_command("PRIVMSG").await;
+        assert_eq!(said.nick(), Some("bob_smith"));
+        assert_eq!(said.params, ["#room", "hello from the room"]);
+    }
+
+    // Quitting closes the connection and tells the channel
+    carol.send("QUIT :bye").await;
+    assert_eq!(
+        step(&mut gateway).await,
+        Some(GatewayEvent::Left {
+            nick: "carol".to_string(),
+            reason: "Quit: bye".to_string()
+        })
+    );
+    assert_eq!(carol.receive_command("ERROR").await.command, "ERROR");
+    assert!(carol.closed().await);
+    let quit = dave.receive_command("QUIT").await;
+    assert_eq!(quit.nick(), Some("carol"));
+    assert_eq!(gateway.nicks().collect::<Vec<_>>(), ["dave"]);
+}
+
+#[tokio::test]
+async fn a_gateway_node_bridges_clients_and_the_room() {
+    let alice_cli = common::cli(&["--nick", "alice", "--irc-gateway", "127.0.0.1:0"]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&alice_cli).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    let gateway = alice.gateway().unwrap();
+    let channel = gateway.channel().to_string();
+    let mut carol = Client::connect(gateway).await;
+    carol.send("NICK carol").await;
+    carol.send("USER carol 0 * :Carol").await;
+    carol.send(&format!("JOIN {channel}")).await;
+    for _ in 0..4 {
+        step_node(&mut alice).await;
+    }
+    carol.receive_command("366").await;
+
+    // Peers' messages are said on the channel under their nick
+    bob.handle_line("hello irc").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+    let said = carol.receive_command("PRIVMSG").await;
+    assert_eq!(said.nick(), Some("bob"));
+    assert_eq!(said.params, [channel.as_str(), "hello irc"]);
+
+    // And so are the node's own
+    alice.handle_line("hello from alice").await;
+    let said = carol.receive_command("PRIVMSG").await;
+    assert_eq!(said.nick(), Some("alice"));
+    assert_eq!(said.param(1), "hello from alice");
+
+    // What the client says is published to the room under its nick
+    carol.send(&format!("PRIVMSG {channel} :hi from irc")).await;
+    step_node(&mut alice).await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 2
+    })
+    .await;
+    let latest = &bob.history().next().unwrap().message;
+    assert_eq!((&*latest.nick, &*latest.body), ("carol", "hi from irc"));
+    assert_eq!(latest.origin, None);
+}
@@ -0,0 +1,108 @@
+// Rotating the identity key and moving peers' trust to the new key.
+mod common;
+
+use std::{env, fs, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    clock,
+    control::{self, ControlMessage, SignedControl},
+    identity::{self, Rotation, RotationError, SignedRotation, ROTATION_GRACE},
+    message::ChatMessage,
+};
+use libp2p::{gossipsub, identity::Keypair, PeerId};
+
+fn chat(source: PeerId, nick: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new(nick, "hello", 0);
+    common::message_event(source, 0, chat.encode())
+}
+
+// `rotation` announced on the control topic by `announcer`.
+fn announce(announcer: &Keypair, rotation: SignedRotation) -> gossipsub::Event {
+    let source = announcer.public().to_peer_id();
+    let signed = SignedControl::sign(announcer, &ControlMessage::Rotation(rotation)).unwrap();
+    let data = serde_json::to_vec(&signed).unwrap();
+    common::message_event_on(control::control_topic().hash(), source, 1, data)
+}
+
+#[test]
+fn rotating_replaces_the_key_and_keeps_a_statement() {
+    let path = env::temp_dir().join(format!("p2p-chat-identity-{}.key", process::id()));
+    let _ = fs::remove_file(&path);
+    let old = identity::load_or_create(&path).unwrap();
+    let old_peer = old.public().to_peer_id();
+    let identity_arg = path.to_str().unwrap();
+    let node = ChatNode::new(&common::cli(&["--identity", identity_arg])).unwrap();
+    assert_eq!(node.local_peer_id(), old_peer);
+
+    let now = clock::unix_time();
+    let rotation = identity::rotate(&path, now).unwrap();
+    assert_eq!(rotation.old, old_peer);
+    let new = identity::load(&path).unwrap();
+    assert_eq!(rotation.new, new.public().to_peer_id());
+
+    let statement = identity::pending_rotation(&path, rotation.new, now).unwrap();
+    assert_eq!(
+        identity::check(&statement, rotation.new, now),
+        Ok(rotation.clone())
+    );
+    // Only announced for the new key, and only during the grace period
+    assert!(identity::pending_rotation(&path, old_peer, now).is_none());
+    assert!(identity::pending_rotation(&path, rotation.new, now + ROTATION_GRACE).is_none());
+
+    let node = ChatNode::new(&common::cli(&["--identity", identity_arg])).unwrap();
+    assert_eq!(node.local_peer_id(), rotation.new);
+    let _ = fs::remove_file(identity::rotation_path(&path));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn trust_moves_only_for_statements_signed_by_the_old_key() {
+    let config = env::temp_dir().join(format!("p2p-chat-rotation-{}.json", process::id()));
+    let _ = fs::remove_file(&config);
+    let mut node = ChatNode::new(&common::cli(&["--config", config.to_str().unwrap()])).unwrap();
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    let old = Keypair::generate_ed25519();
+    let alice = old.public().to_peer_id();
+    node.receive(chat(alice, "alice"));
+    rt.block_on(node.handle_line("/verify alice confirm"));
+    assert!(node.is_verified(&alice));
+
+    // Mallory signs a statement claiming alice moved to mallory's key
+    let mallory = Keypair::generate_ed25519();
+    let forged = Rotation {
+        old: alice,
+        new: mallory.public().to_peer_id(),
+        timestamp: clock::unix_time(),
+    };
+    let forged = SignedRotation::sign(&mallory, &forged).unwrap();
+    assert!(matches!(
+        identity::check(&forged, mallory.public().to_peer_id(), clock::unix_time()),
+        Err(RotationError::WrongSigner(_))
+    ));
+    node.receive(announce(&mallory, forged));
+    assert!(node.is_verified(&alice));
+
+    let new = Keypair::generate_ed25519();
+    let rotation = Rotation {
+        old: alice,
+        new: new.public().to_peer_id(),
+        timestamp: clock::unix_time(),
+    };
+    let genuine = SignedRotation::sign(&old, &rotation).unwrap();
+    // Replayed by someone other than the new key, it isn't applied either
+    node.receive(announce(&mallory, genuine.clone()));
+    assert!(node.is_verified(&alice));
+
+    node.receive(announce(&new, genuine));
+    assert!(!node.is_verified(&alice));
+    assert!(node.is_verified(&rotation.new));
+    assert_eq!(node.display_name(&rotation.new), "alice ✓");
+    // The move was saved
+    rt.block_on(node.flush_writes());
+    assert!(fs::read_to_string(&config)
+        .unwrap()
+        .contains(&rotation.new.to_string()));
+    let _ = fs::remove_file(&config);
+}
@@ -0,0 +1,146 @@
+// A passphrase room's key split among trusted peers with Shamir's Secret Sharing, and recovered
+// from their shares.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, UserCommand},
+    keyshare::{self, HeldShare, Recovery, SealedShare},
+    passphrase::RoomKey,
+};
+use libp2p::identity::Keypair;
+use uuid::Uuid;
+
+#[test]
+fn any_threshold_of_the_shares_rebuild_the_secret() {
+    let secret: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(7) ^ 3).collect();
+    let shares = keyshare::split(&secret, 3, 5).unwrap();
+    assert_eq!(shares.len(), 5);
+    for a in 0..5 {
+        for b in a + 1..5 {
+            for c in b + 1..5 {
+                let picked = [a, b, c].map(|i| shares[i].clone());
+                assert_eq!(keyshare::combine(&picked).unwrap(), secret);
+            }
+            // Fewer than the threshold rebuild something else
+            let picked = [a, b].map(|i| shares[i].clone());
+            assert_ne!(keyshare::combine(&picked).unwrap(), secret);
+        }
+    }
+    assert_eq!(keyshare::combine(&shares).unwrap(), secret);
+
+    let twice = [shares[0].clone(), shares[0].clone()];
+    assert!(keyshare::combine(&twice).unwrap_err().contains("twice"));
+    assert!(keyshare::split(&secret, 4, 3).is_err());
+    assert!(keyshare::split(&secret, 0, 3).is_err());
+    assert!(keyshare::split(&secret, 2, keyshare::MAX_SHARES + 1).is_err());
+}
+
+#[test]
+fn shares_open_only_for_their_holder() {
+    let (bob, mallory) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let share = keyshare::split(b"secret", 1, 1).unwrap().remove(0);
+    let sealed = SealedShare::seal(&share, &bob.public().to_peer_id()).unwrap();
+    assert_eq!(sealed.open(&bob).unwrap(), share);
+    assert!(sealed.open(&mallory).is_err());
+
+    // A split is rebuilt once as many of its shares as it takes are back
+    let alice = Keypair::generate_ed25519();
+    let secret = [7; 64];
+    let set = Uuid::new_v4();
+    let held = |share| HeldShare {
+        set,
+        room: "room".to_string(),
+        threshold: 2,
+        sealed: SealedShare::seal(share, &alice.public().to_peer_id()).unwrap(),
+    };
+    let shares = keyshare::split(&secret, 2, 3).unwrap();
+    let mut recovery = Recovery::default();
+    assert_eq!(recovery.add(&alice, held(&shares[2])), Ok(None));
+    assert_eq!(recovery.add(&alice, held(&shares[2])), Ok(None));
+    assert_eq!(recovery.count(&set), 1);
+    assert!(recovery.add(&bob, held(&shares[0])).is_err());
+    assert_eq!(
+        recovery.add(&alice, held(&shares[0])),
+        Ok(Some(("room".to_string(), secret.to_vec())))
+    );
+}
+
+#[test]
+fn keyshare_commands_parse() {
+    assert_eq!(
+        commands::parse("/share-key 2 bob carol dave"),
+        Some(Ok(UserCommand::ShareKey {
+            threshold: 2,
+            holders: vec!["bob".to_string(), "carol".to_string(), "dave".to_string()],
+        }))
+    );
+    assert_eq!(
+        commands::parse("/recover-key"),
+        Some(Ok(UserCommand::RecoverKey(None)))
+    );
+    assert_eq!(
+        commands::parse("/recover-key /tmp/room.key"),
+        Some(Ok(UserCommand::RecoverKey(Some("/tmp/room.key".into()))))
+    );
+    for bad in ["/share-key", "/share-key 2", "/share-key two bob"] {
+        assert!(commands::parse(bad).unwrap().is_err(), "{bad} accepted");
+    }
+}
+
+#[tokio::test]
+async fn a_holder_hands_the_room_key_back() {
+    let dir = env::temp_dir().join(format!("p2p-chat-keyshare-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let (alice_config, bob_config) = (config("alice.json"), config("bob.json"));
+    let (mut bob, bob_addr) =
+        common::spawn_chat_node(&common::cli(&["--config", &bob_config])).await;
+    let keypair = Keypair::generate_ed25519();
+    let cli = common::cli(&["--room-pass", "correct horse", "--config", &alice_config]);
+    let mut alice = ChatNode::with_identity(keypair.clone(), &cli).unwrap();
+    let room = alice.topic().hash();
+    alice.swarm.dial(bob_addr.clone()).unwrap();
+    let topic = keyshare::keyshare_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    // Bob keeps the share, sealed to Bob's identity
+    alice
+        .handle_line(&format!("/share-key 1 {}", bob.local_peer_id()))
+        .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, b| {
+        !b.key_shares().is_empty()
+    })
+    .await;
+    let kept = &bob.key_shares()[0];
+    assert_eq!(kept.owner, alice.local_peer_id());
+    assert_eq!(kept.share.room, room.as_str());
+    drop(alice);
+
+    // Alice forgot the phrase, and asks Bob from the lobby with the same identity
+    let cli = common::cli(&["--config", &alice_config]);
+    let mut alice = ChatNode::with_identity(keypair, &cli).unwrap();
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, _| {
+        common::has_subscriber(a, &topic)
+    })
+    .await;
+    alice.handle_line("/recover-key").await;
+    let key_path = keyshare::path_beside(alice_config.as_ref());
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, _| {
+        key_path.exists()
+    })
+    .await;
+
+    // The saved key leads back into the room
+    let key = RoomKey::load(&key_path).unwrap();
+    assert_eq!(key.secret(), RoomKey::derive("correct horse").secret());
+    let cli = common::cli(&["--room-key-file", key_path.to_str().unwrap()]);
+    assert_eq!(ChatNode::new(&cli).unwrap().topic().hash(), room);
+    fs::remove_dir_all(&dir).unwrap();
+}
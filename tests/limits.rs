@@ -0,0 +1,196 @@
+// Ceilings on the state peers can make grow: each structure driven past its limit stays there.
+mod common;
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, UserCommand},
+    fragment::{self, Assembly, Reassembler, ReassemblyLimits, MAX_BUFFERS, MAX_PEER_BUFFERS},
+    limits::{EvictionWatch, LruMap, Usage, EVICTION_WARNING, EVICTION_WINDOW},
+    message::ChatMessage,
+    presence::{Presence, PresenceStatus},
+};
+use libp2p::{gossipsub, PeerId};
+
+fn message(len: usize) -> ChatMessage {
+    ChatMessage::new("alice", "x".repeat(len), 1)
+}
+
+// A signed chat message from a new author each time.
+fn from_stranger(seq: u64) -> gossipsub::Event {
+    let author = PeerId::random();
+    let chat = ChatMessage::new(format!("peer{seq}"), format!("hello from {seq}"), seq);
+    common::message_event(author, seq, chat.encode())
+}
+
+#[test]
+fn lru_map_evicts_what_was_updated_longest_ago() {
+    let mut nicks = LruMap::new(3);
+    for (key, nick) in [(1, "a"), (2, "b"), (3, "c")] {
+        assert_eq!(nicks.insert(key, nick), None);
+    }
+    // Updating 1 makes 2 the oldest
+    assert_eq!(nicks.insert(1, "a2"), Some("a"));
+    for key in 4..100 {
+        nicks.insert(key, "new");
+        assert!(nicks.len() <= 3);
+    }
+    assert_eq!(nicks.len(), 3);
+    assert_eq!(nicks.evictions(), 96);
+    assert!(!nicks.contains_key(&2));
+    assert!([97, 98, 99].iter().all(|key| nicks.contains_key(key)));
+    assert_eq!(nicks.remove(&99), Some("new"));
+    assert_eq!(nicks.len(), 2);
+}
+
+#[test]
+fn presence_forgets_the_longest_silent_peers_past_its_limit() {
+    let mut presence = Presence::new(10).with_limit(50);
+    let first = PeerId::random();
+    presence.seen("lobby", first, 100);
+    for n in 0..1000 {
+        presence.seen("lobby", PeerId::random(), 101 + n);
+    }
+    assert_eq!(presence.len(), 50);
+    assert_eq!(presence.evictions(), 951);
+    assert_eq!(presence.status("lobby", &first, 1100), None);
+    // The peers kept are the 50 heard from last
+    assert!(presence
+        .room("lobby", 1100)
+        .iter()
+        .all(|(_, status, at)| *at > 1050 && *status != PresenceStatus::Offline));
+}
+
+#[test]
+fn one_sender_only_holds_its_share_of_reassembly() {
+    let (mallory, alice) = (PeerId::random(), PeerId::random());
+    let mut reassembler = Reassembler::new(ReassemblyLimits {
+        bytes: 100_000,
+        peer_bytes: 20_000,
+    });
+
+    // Alice's message is started first, so it would be the oldest
+    let alice_first = fragment::fragment(&message(3000), 1000).remove(0);
+    assert!(matches!(
+        reassembler.add(alice, alice_first, 100),
+        Assembly::Pending
+    ));
+    // Mallory starts many messages and sends a lot of each, never finishing one
+    for n in 0..50 {
+        let mut frags = fragment::fragment(&message(16_000), 2000);
+        frags.pop();
+        for frag in frags {
+            reassembler.add(mallory, frag, 101 + n);
+        }
+        assert!(reassembler.largest_peer_bytes() <= 20_000);
+        assert!(reassembler.pending() <= MAX_PEER_BUFFERS + 1);
+    }
+    // Mallory only ever evicted its own messages
+    assert_eq!(reassembler.pending(), 2);
+    assert!(reassembler.evictions() >= 49);
+
+    // A message larger than a sender's share is dropped rather than evicting others
+    let frags = fragment::fragment(&message(40_000), 4000);
+    let bob = PeerId::random();
+    let outcomes: Vec<_> = frags
+        .into_iter()
+        .map(|frag| reassembler.add(bob, frag, 200))
+        .collect();
+    assert!(outcomes.iter().any(|o| matches!(o, Assembly::Dropped)));
+    assert!(outcomes.iter().all(|o| !matches!(o, Assembly::Complete(_))));
+    assert!(reassembler.bytes() <= 100_000);
+}
+
+#[test]
+fn many_senders_stay_within_the_overall_budget() {
+    let mut reassembler = Reassembler::new(ReassemblyLimits {
+        bytes: 30_000,
+        peer_bytes: 20_000,
+    });
+    for n in 0..100 {
+        let mut frags = fragment::fragment(&message(12_000), 1000);
+        frags.pop();
+        let sender = PeerId::random();
+        for frag in frags {
+            reassembler.add(sender, frag, n);
+        }
+        assert!(reassembler.bytes() <= 30_000);
+        assert!(reassembler.pending() <= MAX_BUFFERS);
+    }
+    assert!(reassembler.evictions() >= 97);
+
+    // Messages that fit still come through whole
+    let original = message(5000);
+    let sender = PeerId::random();
+    let last = fragment::fragment(&original, 1000)
+        .into_iter()
+        .map(|frag| reassembler.add(sender, frag, 200))
+        .last()
+        .unwrap();
+    match last {
+        Assembly::Complete(Ok(chat)) => assert_eq!(chat, original),
+        other => panic!("expected the whole message, got {other:?}"),
+    }
+}
+
+#[test]
+fn a_node_keeps_its_nicks_and_peers_within_the_flags() {
+    let cli = common::cli(&[
+        "--rate-limit",
+        "1000000",
+        "--max-known-nicks",
+        "20",
+        "--max-tracked-peers",
+        "30",
+    ]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    for seq in 0..500 {
+        node.receive(from_stranger(seq));
+    }
+    let memory = node.memory();
+    let nicks = memory.get("nick cache").unwrap();
+    assert_eq!((nicks.entries, nicks.max_entries), (20, 20));
+    assert_eq!(nicks.evictions, 480);
+    let peers = memory.get("peer registry").unwrap();
+    assert_eq!((peers.entries, peers.max_entries), (30, 30));
+    assert!(peers.evictions >= 470);
+    let fragments = memory.get("fragment reassembly").unwrap();
+    assert_eq!(fragments.entries, 0);
+    assert_eq!(
+        fragments.bytes,
+        Some((0, fragment::DEFAULT_REASSEMBLY_BYTES))
+    );
+
+    let report = memory.to_string();
+    assert!(report.contains("nick cache"), "{report}");
+    assert!(report.contains("20 / 20 entries, 480 evicted (--max-known-nicks)"));
+    assert_eq!(
+        commands::parse("/stats memory"),
+        Some(Ok(UserCommand::MemoryStats))
+    );
+    assert_eq!(commands::parse("/stats"), Some(Ok(UserCommand::Stats)));
+}
+
+#[test]
+fn frequent_evictions_get_a_warning() {
+    let (logs, _guard) = common::capture_logs();
+    let mut watch = EvictionWatch::default();
+    let mut usage = Usage {
+        name: "nick cache",
+        flag: "--max-known-nicks",
+        entries: 10,
+        max_entries: 10,
+        bytes: None,
+        peer_bytes: None,
+        evictions: 0,
+    };
+    assert!(!watch.check(&usage, 1000));
+    usage.evictions = EVICTION_WARNING - 1;
+    assert!(!watch.check(&usage, 1000 + EVICTION_WINDOW));
+    assert!(!logs.contains("[limits]"));
+
+    usage.evictions += EVICTION_WARNING;
+    assert!(!watch.check(&usage, 1000 + EVICTION_WINDOW + 1));
+    assert!(watch.check(&usage, 1000 + 2 * EVICTION_WINDOW));
+    assert!(logs.contains("the nick cache evicted 100 entries in the last 60 s"));
+    assert!(logs.contains("raise --max-known-nicks"));
+}
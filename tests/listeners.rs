@@ -0,0 +1,43 @@
+// Listeners that close are opened again.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::chat::{ChatNode, LISTEN_RETRY};
+use libp2p::{futures::StreamExt, swarm::SwarmEvent};
+
+#[tokio::test]
+async fn closed_listeners_listen_again() {
+    let mut node = ChatNode::new(&common::cli(&[])).unwrap();
+    let listener = node
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    loop {
+        let event = node.swarm.select_next_some().await;
+        let listening = matches!(event, SwarmEvent::NewListenAddr { .. });
+        node.handle_event(event);
+        if listening {
+            break;
+        }
+    }
+
+    // As if the interface went away
+    assert!(node.swarm.remove_listener(listener));
+    let mut ticks = tokio::time::interval(Duration::from_secs(1));
+    let relistened = tokio::time::timeout(Duration::from_secs(LISTEN_RETRY + 10), async {
+        loop {
+            tokio::select! {
+                event = node.swarm.select_next_some() => {
+                    if matches!(event, SwarmEvent::NewListenAddr { .. }) {
+                        return;
+                    }
+                    node.handle_event(event);
+                }
+                _ = ticks.tick() => node.tick(),
+            }
+        }
+    })
+    .await;
+    assert!(relistened.is_ok(), "the node didn't listen again");
+    assert_eq!(node.swarm.listeners().count(), 1);
+}
@@ -0,0 +1,169 @@
+// Connections that die without closing, judged from failed pings, silence and timeouts.
+mod common;
+
+use std::time::{Duration, Instant};
+
+use concurrent_chat_server::{
+    clock,
+    liveness::{Liveness, DEAD_PINGS, SILENT_AFTER},
+    node::MyBehaviourEvent,
+    presence::PresenceStatus,
+};
+use libp2p::{
+    ping,
+    swarm::{dial_opts::DialOpts, ConnectionId, SwarmEvent},
+    Multiaddr, PeerId,
+};
+
+// A ping every 15 seconds, each given 20 seconds to answer.
+const PING_ROUND: Duration = Duration::from_secs(35);
+
+fn ping(
+    peer: PeerId,
+    connection: ConnectionId,
+    result: Result<Duration, ping::Failure>,
+) -> ping::Event {
+    ping::Event {
+        peer,
+        connection,
+        result,
+    }
+}
+
+fn tracked(liveness: &mut Liveness, now: Instant) -> (PeerId, ConnectionId) {
+    let (peer, connection) = (PeerId::random(), ConnectionId::new_unchecked(7));
+    let address: Multiaddr = "/ip4/192.168.1.7/tcp/4001".parse().unwrap();
+    liveness.connected(connection, peer, Some(address), now);
+    (peer, connection)
+}
+
+#[test]
+fn a_black_holed_peer_is_judged_dead() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    let (peer, connection) = tracked(&mut liveness, start);
+
+    let mut now = start;
+    for round in 1..=DEAD_PINGS {
+        now += PING_ROUND;
+        assert!(liveness.dead(now).is_empty(), "dead after {round} rounds");
+        liveness.ping(&ping(peer, connection, Err(ping::Failure::Timeout)), now);
+    }
+    // Three failed pings and silence since it connected
+    assert!(now.duration_since(start) >= SILENT_AFTER);
+    let dead = liveness.dead(now);
+    assert_eq!(dead.len(), 1);
+    assert_eq!((dead[0].connection, dead[0].peer), (connection, peer));
+    assert!(dead[0].address.is_some());
+    assert!(!liveness.is_connected(&peer));
+    assert!(liveness.dead(now).is_empty());
+}
+
+#[test]
+fn a_slow_link_that_answers_is_never_judged_dead() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    let (peer, connection) = tracked(&mut liveness, start);
+
+    // Answers taking 15 seconds, and every third ping lost, for an hour
+    let mut now = start;
+    for round in 1..100 {
+        now += PING_ROUND;
+        let result = match round % 3 {
+            0 => Err(ping::Failure::Timeout),
+            _ => Ok(Duration::from_secs(15)),
+        };
+        liveness.ping(&ping(peer, connection, result), now);
+        liveness.timed_out(&peer);
+        assert!(liveness.dead(now).is_empty(), "dead after {round} rounds");
+    }
+    assert!(liveness.is_connected(&peer));
+}
+
+#[test]
+fn silence_alone_or_failed_pings_alone_are_not_enough() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    let (quiet, answering) = tracked(&mut liveness, start);
+    // Nobody has said anything for hours, but pings are answered
+    let later = start + SILENT_AFTER * 100;
+    liveness.ping(
+        &ping(quiet, answering, Ok(Duration::from_millis(40))),
+        later,
+    );
+    assert!(liveness.dead(later + SILENT_AFTER).is_empty());
+
+    // And a chatty peer whose pings fail keeps its connection while messages come in
+    let (chatty, failing) = (PeerId::random(), ConnectionId::new_unchecked(8));
+    let mut liveness = Liveness::default();
+    liveness.connected(failing, chatty, None, start);
+    let mut now = start;
+    for _ in 0..DEAD_PINGS {
+        now += PING_ROUND;
+        liveness.ping(&ping(chatty, failing, Err(ping::Failure::Timeout)), now);
+        liveness.heard(&chatty, now);
+    }
+    assert_eq!(liveness.score(&failing, now), Some(DEAD_PINGS));
+    assert!(liveness.dead(now).is_empty());
+
+    // Until a request to it times out as well
+    liveness.timed_out(&chatty);
+    assert_eq!(liveness.dead(now).len(), 1);
+}
+
+#[test]
+fn peers_without_ping_are_judged_on_the_rest() {
+    let start = Instant::now();
+    let mut liveness = Liveness::default();
+    let (peer, connection) = tracked(&mut liveness, start);
+    let mut now = start;
+    for _ in 0..10 {
+        now += PING_ROUND;
+        liveness.ping(
+            &ping(peer, connection, Err(ping::Failure::Unsupported)),
+            now,
+        );
+        liveness.timed_out(&peer);
+    }
+    assert!(liveness.dead(now).is_empty());
+}
+
+#[tokio::test]
+async fn a_dead_connection_is_closed_its_peer_marked_offline_and_dialed_again() {
+    let (logs, _guard) = common::capture_logs();
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let bob_id = bob.local_peer_id();
+    let room = alice.topic().hash().into_string();
+    let opts = DialOpts::unknown_peer_id().address(bob_addr).build();
+    let connection = opts.connection_id();
+    alice.swarm.dial(opts).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice
+            .presence()
+            .status(&room, &bob_id, clock::unix_time())
+            .is_some_and(|(status, _)| status == PresenceStatus::Online)
+    })
+    .await;
+
+    // Bob's side goes quiet, as behind a pulled cable: pings stop being answered
+    for _ in 0..=DEAD_PINGS {
+        let failed = ping(bob_id, connection, Err(ping::Failure::Timeout));
+        alice.handle_event(SwarmEvent::Behaviour(MyBehaviourEvent::Ping(failed)));
+    }
+    alice.tick();
+    let (status, _) = alice
+        .presence()
+        .status(&room, &bob_id, clock::unix_time())
+        .unwrap();
+    assert_eq!(status, PresenceStatus::Offline);
+
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, _| {
+        logs.contains(&format!("[liveness] dialing {bob_id} again"))
+    })
+    .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.swarm.is_connected(&bob_id)
+    })
+    .await;
+}
@@ -0,0 +1,60 @@
+// Behaviors that are only signaled in the node's log, checked by capturing it.
+mod common;
+
+use concurrent_chat_server::{chat::ChatNode, control, message::ChatMessage};
+use libp2p::{gossipsub, PeerId};
+
+fn chat(source: Option<PeerId>, seq: u64, body: &str) -> gossipsub::Event {
+    let message = ChatMessage::new("bob", body, 0);
+    let event = common::message_event(source.unwrap_or_else(PeerId::random), seq, message.encode());
+    match source {
+        Some(_) => event,
+        None => common::unsigned(event),
+    }
+}
+
+#[test]
+fn rate_limit_bans_and_drops_are_logged() {
+    let (logs, _guard) = common::capture_logs();
+    let mut node = ChatNode::new(&common::cli(&["--rate-limit", "2"])).unwrap();
+    let bob = PeerId::random();
+    for seq in 1..=4 {
+        node.receive(chat(Some(bob), seq, &format!("message {seq}")));
+    }
+    assert!(logs.contains(&format!("[ban] {bob} exceeded the rate limit: banned for")));
+    assert!(logs.contains(&format!("[ban] dropped a message from blocked peer {bob}")));
+}
+
+#[test]
+fn duplicates_are_logged() {
+    let (logs, _guard) = common::capture_logs();
+    let mut node = ChatNode::new(&common::cli(&[])).unwrap();
+    let bob = PeerId::random();
+    node.receive(chat(Some(bob), 1, "same words"));
+    assert!(!logs.contains("[dedup]"));
+    // Published again, so Gossipsub sees a new message id
+    node.receive(chat(Some(bob), 2, "same words"));
+    assert_eq!(
+        logs.lines()
+            .iter()
+            .filter(|line| line.starts_with("[dedup]"))
+            .collect::<Vec<_>>(),
+        [&format!("[dedup] {bob} sent a message it already sent")]
+    );
+}
+
+#[test]
+fn authentication_failures_are_logged() {
+    let (logs, _guard) = common::capture_logs();
+    let mut node = ChatNode::new(&common::cli(&["--require-signed"])).unwrap();
+    node.receive(common::message_event_on(
+        control::control_topic().hash(),
+        PeerId::random(),
+        1,
+        b"{\"not\":\"signed\"}".to_vec(),
+    ));
+    assert!(logs.contains("[control] dropped invalid control message"));
+
+    node.receive(chat(None, 2, "who am i"));
+    assert!(logs.contains("[unsigned] dropped an unsigned message relayed by"));
+}
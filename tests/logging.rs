@@ -0,0 +1,126 @@
+// Log notices sent to the journal and syslog, and chat messages kept out of them.
+mod common;
+
+use std::{env, fs, os::unix::net::UnixDatagram, path::Path, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    logging::{self, Record},
+    message::ChatMessage,
+};
+use libp2p::PeerId;
+use tracing::Level;
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+fn record(message: &str, fields: &[(&str, &str)]) -> Record {
+    Record {
+        message: message.to_string(),
+        fields: fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+#[test]
+fn levels_map_to_syslog_severities() {
+    let severities = [
+        Level::ERROR,
+        Level::WARN,
+        Level::INFO,
+        Level::DEBUG,
+        Level::TRACE,
+    ]
+    .map(|level| logging::severity(&level));
+    assert_eq!(severities, [3, 4, 6, 7, 7]);
+
+    // Daemon facility, and the message on one line with its fields after it
+    let line = logging::syslog_line(
+        &Level::WARN,
+        &record("rejected\na message", &[("peer", "12D3")]),
+        42,
+    );
+    assert_eq!(line, "<28>p2p-chat[42]: rejected a message peer=12D3");
+}
+
+#[test]
+fn journal_entries_carry_fields_in_capitals() {
+    let entry = logging::journal_entry(
+        &Level::INFO,
+        "concurrent_chat_server::chat",
+        &record(
+            "hi",
+            &[("peer", "12D3"), ("_trusted", "no"), ("2nd.try", "yes")],
+        ),
+    );
+    assert_eq!(
+        String::from_utf8(entry).unwrap(),
+        "PRIORITY=6\nSYSLOG_IDENTIFIER=p2p-chat\nTARGET=concurrent_chat_server::chat\n\
+         MESSAGE=hi\nPEER=12D3\nTRUSTED=no\nND_TRY=yes\n"
+    );
+    assert_eq!(logging::journal_field_name("._"), None);
+
+    // A value spanning lines is sent with its length
+    let entry = logging::journal_entry(&Level::ERROR, "t", &record("two\nlines", &[]));
+    let mut expected = b"PRIORITY=3\nSYSLOG_IDENTIFIER=p2p-chat\nTARGET=t\nMESSAGE\n".to_vec();
+    expected.extend_from_slice(&9u64.to_le_bytes());
+    expected.extend_from_slice(b"two\nlines\n");
+    assert_eq!(entry, expected);
+}
+
+// Have a node take a chat message while logging to a journal listening on `journal`, with
+// transcripts or without, and return its author and the entries the journal got.
+fn journal_of_a_message(
+    journal: &UnixDatagram,
+    path: &Path,
+    transcripts: bool,
+) -> (PeerId, Vec<String>) {
+    let subscriber = Registry::default()
+        .with(logging::journald(path).unwrap())
+        .with(logging::filter(transcripts));
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let mut node = ChatNode::new(&common::cli(&[])).unwrap();
+    let message = ChatMessage::new("bob", "meet at noon", 0);
+    let bob = PeerId::random();
+    node.receive(common::message_event(bob, 1, message.encode()));
+    tracing::warn!(target: "concurrent_chat_server::chat", peer = %bob, "[test] done");
+    let mut entries = Vec::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = journal.recv(&mut buf).unwrap();
+        let entry = String::from_utf8_lossy(&buf[..n]).into_owned();
+        let done = entry.contains("MESSAGE=[test] done");
+        entries.push(entry);
+        if done {
+            return (bob, entries);
+        }
+    }
+}
+
+#[test]
+fn chat_messages_reach_the_journal_only_with_transcripts() {
+    let path = env::temp_dir().join(format!("journal-{}", process::id()));
+    let _ = fs::remove_file(&path);
+    let journal = UnixDatagram::bind(&path).unwrap();
+
+    let (bob, entries) = journal_of_a_message(&journal, &path, false);
+    assert!(!entries.iter().any(|entry| entry.contains("meet at noon")));
+    let done = entries.last().unwrap();
+    assert!(done.starts_with("PRIORITY=4\n"));
+    assert!(done.contains(&format!("\nPEER={bob}\n")));
+
+    let (bob, entries) = journal_of_a_message(&journal, &path, true);
+    let transcript: Vec<_> = entries
+        .iter()
+        .filter(|entry| entry.contains("MESSAGE=meet at noon\n"))
+        .collect();
+    let [transcript] = transcript.as_slice() else {
+        panic!("one transcript entry in {entries:?}");
+    };
+    assert!(transcript.starts_with("PRIORITY=6\n"));
+    assert!(transcript.contains(&format!("TARGET={}\n", logging::TRANSCRIPT)));
+    assert!(transcript.contains(&format!("\nTOPIC={}\n", common::topic().hash())));
+    assert!(transcript.contains("\nNICK=bob\n"));
+    assert!(transcript.contains(&format!("\nPEER={bob}\n")));
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,140 @@
+// Simulated packet loss and latency: the flags, the stalls they cause and a chat that survives them.
+#![cfg(debug_assertions)]
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use concurrent_chat_server::{
+    cli::Cli,
+    lossy::{self, Impairment, LatencyStream, LossyStream},
+    message::ChatMessage,
+};
+use libp2p::futures::{io::Cursor, AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn impairment_flags_parse() {
+    let cli = common::cli(&[
+        "--simulate-packet-loss",
+        "10",
+        "--simulate-latency-ms",
+        "50",
+        "5",
+    ]);
+    assert_eq!(
+        Impairment::from_cli(&cli),
+        Some(Impairment {
+            loss: 0.1,
+            latency: Some((Duration::from_millis(50), Duration::from_millis(5))),
+        })
+    );
+    assert_eq!(Impairment::from_cli(&common::cli(&[])), None);
+    let no_loss = common::cli(&["--simulate-packet-loss", "0"]);
+    assert_eq!(Impairment::from_cli(&no_loss), None);
+    for args in [
+        ["--simulate-packet-loss", "101"].as_slice(),
+        &["--simulate-latency-ms", "50"],
+    ] {
+        let args = ["p2p-chat"].iter().chain(args);
+        assert!(Cli::try_parse_from(args).is_err());
+    }
+}
+
+#[test]
+fn delays_follow_the_settings() {
+    let lost = Impairment {
+        loss: 1.0,
+        latency: None,
+    };
+    assert_eq!(lost.loss_delay(), lossy::RETRANSMIT_TIMEOUT);
+    assert_eq!(lost.latency_delay(), Duration::ZERO);
+    let fixed = Impairment {
+        loss: 0.0,
+        latency: Some((Duration::from_millis(30), Duration::ZERO)),
+    };
+    assert_eq!(fixed.loss_delay(), Duration::ZERO);
+    let sample = fixed.latency_delay();
+    assert!(sample.abs_diff(Duration::from_millis(30)) < Duration::from_micros(1));
+    assert_eq!(Impairment::default().latency_delay(), Duration::ZERO);
+
+    // Log-normal samples keep the mean, are never negative and have a long tail
+    let spread = Impairment {
+        loss: 0.0,
+        latency: Some((Duration::from_millis(50), Duration::from_millis(30))),
+    };
+    let samples: Vec<Duration> = (0..10_000).map(|_| spread.latency_delay()).collect();
+    let mean = samples.iter().sum::<Duration>() / 10_000;
+    assert!(samples.iter().all(|sample| *sample > Duration::ZERO));
+    assert!(samples
+        .iter()
+        .any(|sample| *sample > Duration::from_millis(150)));
+    assert!(
+        mean > Duration::from_millis(47) && mean < Duration::from_millis(53),
+        "{mean:?}"
+    );
+}
+
+#[tokio::test]
+async fn lost_reads_stall_but_lose_nothing() {
+    let data = b"every byte arrives".to_vec();
+    let lost = Impairment {
+        loss: 1.0,
+        latency: None,
+    };
+    let mut stream = LossyStream::new(Cursor::new(data.clone()), lost);
+    let started = Instant::now();
+    let mut read = Vec::new();
+    stream.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+    assert!(started.elapsed() >= lossy::RETRANSMIT_TIMEOUT);
+
+    let mut clear = LossyStream::new(Cursor::new(data.clone()), Impairment::default());
+    let started = Instant::now();
+    let mut read = Vec::new();
+    clear.read_to_end(&mut read).await.unwrap();
+    assert_eq!(read, data);
+    assert!(started.elapsed() < lossy::RETRANSMIT_TIMEOUT);
+}
+
+#[tokio::test]
+async fn writes_wait_out_the_latency() {
+    let slow = Impairment {
+        loss: 0.0,
+        latency: Some((Duration::from_millis(40), Duration::ZERO)),
+    };
+    let mut stream = LatencyStream::new(Cursor::new(Vec::new()), slow);
+    let started = Instant::now();
+    stream.write_all(b"hello").await.unwrap();
+    stream.write_all(b" there").await.unwrap();
+    // Each write waits, while flushing doesn't
+    assert!(started.elapsed() >= Duration::from_millis(80));
+    let started = Instant::now();
+    stream.flush().await.unwrap();
+    assert!(started.elapsed() < Duration::from_millis(40));
+}
+
+#[tokio::test]
+async fn chat_gets_through_a_lossy_link() {
+    let lossy = common::cli(&[
+        "--simulate-packet-loss",
+        "20",
+        "--simulate-latency-ms",
+        "5",
+        "2",
+    ]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&lossy).await;
+    let (mut bob, _) = common::spawn_chat_node(&lossy).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(30), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+    let message = ChatMessage::new("bob", "can you hear me", 1);
+    bob.publish(&message).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(30), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+}
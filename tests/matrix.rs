@@ -0,0 +1,490 @@
+// The Matrix bridge: its flags, a bridge talking to a homeserver, and a node mirroring a
+// Matrix room.
+mod common;
+
+use std::{collections::HashMap, fs, net::SocketAddr, time::Duration};
+
+use clap::Parser;
+use concurrent_chat_server::{
+    cli::Cli,
+    http,
+    matrix::{self, MatrixBridge, MatrixEvent, MatrixLogin, MatrixSettings, MatrixState},
+    runtime,
+};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, Mutex},
+};
+use url::Url;
+
+// A homeserver reading requests on every connection at once, as the bridge sends a message
+// while its sync is pending, to be answered by the test.
+struct FakeHomeserver {
+    address: SocketAddr,
+    requests: Mutex<mpsc::Receiver<Request>>,
+}
+
+// A request the fake homeserver hasn't answered yet.
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    token: Option<String>,
+    body: Value,
+    stream: BufReader<TcpStream>,
+}
+
+impl Request {
+    async fn read(tcp: TcpStream) -> Option<Self> {
+        let mut stream = BufReader::new(tcp);
+        let mut request_line = String::new();
+        stream.read_line(&mut request_line).await.ok()?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next()?.to_string();
+        let target = Url::parse(&format!("http://homeserver{}", parts.next()?)).unwrap();
+        let (mut length, mut token) = (0, None);
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await.ok()?;
+            if header.trim().is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').unwrap();
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap();
+            } else if name.eq_ignore_ascii_case("authorization") {
+                token = value.trim().strip_prefix("Bearer ").map(str::to_string);
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.ok()?;
+        Some(Request {
+            method,
+            path: target.path().to_string(),
+            query: target.query_pairs().into_owned().collect(),
+            token,
+            body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+            stream,
+        })
+    }
+
+    async fn respond(mut self, status: u16, body: Value) {
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 {status} Whatever\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        self.stream
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .unwrap();
+    }
+}
+
+impl FakeHomeserver {
+    async fn bind() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, requests) = mpsc::channel(16);
+        tokio::spawn(async move {
+            while let Ok((tcp, _)) = listener.accept().await {
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    // Connections the bridge gives up on before asking anything are let go
+                    if let Some(request) = Request::read(tcp).await {
+                        let _ = sender.send(request).await;
+                    }
+                });
+            }
+        });
+        FakeHomeserver {
+            address,
+            requests: Mutex::new(requests),
+        }
+    }
+
+    fn url(&self) -> Url {
+        format!("http://{}", self.address).parse().unwrap()
+    }
+
+    async fn request(&self) -> Request {
+        let mut requests = self.requests.lock().await;
+        runtime::timeout(Duration::from_secs(10), requests.recv())
+            .await
+            .expect("the bridge calls the homeserver")
+            .unwrap()
+    }
+
+    // A message sent while the bridge syncs, which it may send before or after starting the
+    // sync: the path and body of the message, answered, and the sync, left for the test.
+    async fn sent_during_sync(&self) -> (String, Value, Request) {
+        let (mut sent, mut sync) = (None, None);
+        while sent.is_none() || sync.is_none() {
+            let request = self.request().await;
+            if request.method == "PUT" {
+                sent = Some((request.path.clone(), request.body.clone()));
+                request.respond(200, json!({"event_id": "$sent"})).await;
+            } else {
+                sync = Some(request);
+            }
+        }
+        let (path, body) = sent.unwrap();
+        (path, body, sync.unwrap())
+    }
+
+    // Answer the bridge logging in with a token and joining `#team:test`.
+    async fn welcome(&self) {
+        let whoami = self.request().await;
+        assert_eq!(whoami.path, "/_matrix/client/v3/account/whoami");
+        assert_eq!(whoami.token.as_deref(), Some("secret"));
+        whoami
+            .respond(200, json!({"user_id": "@bridge:test"}))
+            .await;
+        let join = self.request().await;
+        assert_eq!(
+            (join.method.as_str(), join.path.as_str()),
+            ("POST", "/_matrix/client/v3/join/%23team:test")
+        );
+        join.respond(200, json!({"room_id": "!room:test"})).await;
+    }
+}
+
+fn settings(homeserver: Url) -> MatrixSettings {
+    MatrixSettings {
+        homeserver,
+        room: "#team:test".to_string(),
+        login: MatrixLogin::Token("secret".to_string()),
+    }
+}
+
+// A sync answer with `events` in the room's timeline.
+fn sync(next_batch: &str, events: Value) -> Value {
+    json!({
+        "next_batch": next_batch,
+        "rooms": {"join": {"!room:test": {"timeline": {"events": events}}}},
+    })
+}
+
+fn message(id: &str, sender: &str, msgtype: &str, body: &str) -> Value {
+    json!({
+        "type": "m.room.message",
+        "event_id": id,
+        "sender": sender,
+        "content": {"msgtype": msgtype, "body": body},
+    })
+}
+
+#[test]
+fn the_flags_need_a_room_and_a_login() {
+    let parse = |args: &[&str]| {
+        Cli::try_parse_from(
+            ["p2p-chat", "--matrix-homeserver", "https://matrix.test"]
+                .iter()
+                .chain(args),
+        )
+    };
+    let cli = parse(&["--matrix-room", "#team:test", "--matrix-token", "t"]).unwrap();
+    assert_eq!(
+        MatrixSettings::from_cli(&cli),
+        Some(MatrixSettings {
+            homeserver: "https://matrix.test".parse().unwrap(),
+            room: "#team:test".to_string(),
+            login: MatrixLogin::Token("t".to_string()),
+        })
+    );
+    let cli = parse(&[
+        "--matrix-room",
+        "!abc:test",
+        "--matrix-user",
+        "@bridge:test",
+        "--matrix-password",
+        "pw",
+    ])
+    .unwrap();
+    assert!(matches!(
+        MatrixSettings::from_cli(&cli).unwrap().login,
+        MatrixLogin::Password { .. }
+    ));
+    for args in [
+        &["--matrix-token", "t"][..],
+        &["--matrix-room", "#team:test"],
+        &[
+            "--matrix-room",
+            "#team:test",
+            "--matrix-user",
+            "@bridge:test",
+        ],
+        &["--matrix-room", "team", "--matrix-token", "t"],
+        &[
+            "--matrix-room",
+            "#team:test",
+            "--matrix-token",
+            "t",
+            "--matrix-user",
+            "@bridge:test",
+            "--matrix-password",
+            "pw",
+        ],
+        &[
+            "--matrix-room",
+            "#team:test",
+            "--matrix-token",
+            "t",
+            "--room-pass",
+            "secret",
+        ],
+    ] {
+        assert!(parse(args).is_err(), "{args:?} accepted");
+    }
+    assert!(Cli::try_parse_from(["p2p-chat", "--matrix-room", "#team:test"]).is_err());
+    assert!(MatrixSettings::from_cli(&Cli::parse_from(["p2p-chat"])).is_none());
+
+    assert!(matrix::room_name("#team:matrix.org").is_ok());
+    assert!(matrix::room_name("!AbCd:matrix.org").is_ok());
+    for bad in [
+        "team",
+        "#team",
+        "#:matrix.org",
+        "@user:matrix.org",
+        "#a b:test",
+    ] {
+        assert!(matrix::room_name(bad).is_err(), "{bad} accepted");
+    }
+}
+
+#[tokio::test]
+async fn the_bridge_syncs_relays_and_marks_messages_read() {
+    let homeserver = FakeHomeserver::bind().await;
+    let mut bridge = MatrixBridge::spawn(settings(homeserver.url()), None);
+    // Said before joining, so it waits for the room
+    bridge.relay("alice", "hello matrix").unwrap();
+    homeserver.welcome().await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MatrixEvent::Connected {
+            user_id: "@bridge:test".to_string(),
+            room_id: "!room:test".to_string()
+        }
+    );
+    let (path, body, first) = homeserver.sent_during_sync().await;
+    assert!(path.starts_with("/_matrix/client/v3/rooms/!room:test/send/m.room.message/"));
+    assert_eq!(
+        body,
+        json!({"msgtype": "m.text", "body": "<alice> hello matrix"})
+    );
+    // The first sync only finds out where the room is up to
+    assert_eq!(first.path, "/_matrix/client/v3/sync");
+    assert!(!first.query.contains_key("since"));
+    let old = message("$old", "@carol:test", "m.text", "said before we came");
+    first.respond(200, sync("s1", json!([old]))).await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MatrixEvent::Synced(MatrixState {
+            next_batch: "s1".to_string(),
+            read_up_to: Some("$old".to_string()),
+        })
+    );
+
+    // Others' messages and emotes are passed on, and marked read; our own and notices aren't
+    let synced = homeserver.request().await;
+    assert_eq!(synced.query["since"], "s1");
+    let events = json!([
+        message("$own", "@bridge:test", "m.text", "<alice> hello matrix"),
+        message("$1", "@carol:test", "m.text", "hi from matrix"),
+        message("$2", "@dave:test", "m.emote", "waves"),
+        message("$3", "@bot:test", "m.notice", "beep"),
+    ]);
+    synced.respond(200, sync("s2", events)).await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MatrixEvent::Message {
+            sender: "@carol:test".to_string(),
+            body: "hi from matrix".to_string()
+        }
+    );
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MatrixEvent::Emote {
+            sender: "@dave:test".to_string(),
+            body: "waves".to_string()
+        }
+    );
+    let receipt = homeserver.request().await;
+    assert_eq!(
+        receipt.path,
+        "/_matrix/client/v3/rooms/!room:test/receipt/m.read/$3"
+    );
+    receipt.respond(200, json!({})).await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MatrixEvent::Synced(MatrixState {
+            next_batch: "s2".to_string(),
+            read_up_to: Some("$3".to_string()),
+        })
+    );
+
+    // A refused token is reported, and connecting is tried again
+    homeserver
+        .request()
+        .await
+        .respond(
+            401,
+            json!({"errcode": "M_UNKNOWN_TOKEN", "error": "Invalid token"}),
+        )
+        .await;
+    let MatrixEvent::Disconnected(reason) = common::next(bridge.next()).await else {
+        panic!("the bridge should have lost the homeserver");
+    };
+    assert!(reason.contains("M_UNKNOWN_TOKEN"), "{reason}");
+    homeserver.welcome().await;
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        MatrixEvent::Connected { .. }
+    ));
+    let resumed = homeserver.request().await;
+    assert_eq!(resumed.query["since"], "s2");
+}
+
+#[tokio::test]
+async fn a_password_login_asks_for_a_token() {
+    let homeserver = FakeHomeserver::bind().await;
+    let settings = MatrixSettings {
+        login: MatrixLogin::Password {
+            user: "bridge".to_string(),
+            password: "hunter2".to_string(),
+        },
+        ..settings(homeserver.url())
+    };
+    let state = MatrixState {
+        next_batch: "s9".to_string(),
+        read_up_to: None,
+    };
+    let mut bridge = MatrixBridge::spawn(settings, Some(state));
+    let login = homeserver.request().await;
+    assert_eq!(login.path, "/_matrix/client/v3/login");
+    assert_eq!(login.body["type"], "m.login.password");
+    assert_eq!(login.body["identifier"]["user"], "bridge");
+    assert_eq!(login.body["password"], "hunter2");
+    login
+        .respond(
+            200,
+            json!({"access_token": "secret", "user_id": "@bridge:test"}),
+        )
+        .await;
+    let join = homeserver.request().await;
+    assert_eq!(join.token.as_deref(), Some("secret"));
+    join.respond(200, json!({"room_id": "!room:test"})).await;
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        MatrixEvent::Connected { .. }
+    ));
+    // A saved sync token picks up where the last run left off
+    let synced = homeserver.request().await;
+    assert_eq!(synced.query["since"], "s9");
+}
+
+#[tokio::test]
+async fn a_bridge_node_mirrors_the_room_without_loops() {
+    let homeserver = FakeHomeserver::bind().await;
+    let dir = common::temp_path("node");
+    fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("config.json");
+    let url = homeserver.url().to_string();
+    let alice_cli = common::cli(&[
+        "--config",
+        config_path.to_str().unwrap(),
+        "--matrix-homeserver",
+        &url,
+        "--matrix-room",
+        "#team:test",
+        "--matrix-token",
+        "secret",
+    ]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&alice_cli).await;
+    homeserver.welcome().await;
+    assert!(matches!(
+        common::next(alice.next_matrix_event()).await,
+        MatrixEvent::Connected { .. }
+    ));
+    homeserver
+        .request()
+        .await
+        .respond(200, sync("s1", json!([])))
+        .await;
+    let event = common::next(alice.next_matrix_event()).await;
+    alice.handle_matrix_event(event);
+
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    // The room's messages are posted to the Matrix room with their author's nick
+    bob.handle_line("hello matrix").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+    let (_, body, synced) = homeserver.sent_during_sync().await;
+    assert_eq!(body["body"], "<bob> hello matrix");
+
+    // The Matrix room's messages come to the room marked as from Matrix, and aren't posted back
+    let events = json!([message("$1", "@carol:test", "m.text", "hi from matrix")]);
+    synced.respond(200, sync("s2", events)).await;
+    let event = common::next(alice.next_matrix_event()).await;
+    alice.handle_matrix_event(event);
+    homeserver.request().await.respond(200, json!({})).await;
+    let event = common::next(alice.next_matrix_event()).await;
+    assert!(matches!(event, MatrixEvent::Synced(_)));
+    alice.handle_matrix_event(event);
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+    let bridged = &bob.history().next().unwrap().message;
+    assert_eq!(&*bridged.body, "[matrix] @carol:test: hi from matrix");
+    assert_eq!(bridged.origin.as_deref(), Some(matrix::ORIGIN));
+    let counters = alice.stats().counters;
+    assert_eq!((counters.matrix_sent, counters.matrix_received), (1, 1));
+
+    // The sync token is kept beside the config file for the next run
+    let state_path = matrix::path_beside(&config_path);
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, _| {
+        MatrixState::load(&state_path)
+            .ok()
+            .flatten()
+            .is_some_and(|state| state.next_batch == "s2")
+    })
+    .await;
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn chunks_over_the_response_cap_are_refused() {
+    for size in ["ffffffffffffffff", "400001"] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let answer = format!("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{size}\r\n");
+        tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let _ = tcp.write_all(answer.as_bytes()).await;
+            // Hold the connection open, so only the size can end the read
+            let _ = tcp.read(&mut [0; 1024]).await;
+            runtime::sleep(Duration::from_secs(10)).await;
+        });
+        let response = http::request("GET", &url, None, None, Duration::from_secs(5)).await;
+        assert_eq!(
+            response.unwrap_err(),
+            format!("the answer is over {} bytes", http::MAX_RESPONSE_BYTES)
+        );
+    }
+}
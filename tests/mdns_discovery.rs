@@ -0,0 +1,166 @@
+// Peers found by mDNS becoming explicit Gossipsub peers, with discovery faked for determinism.
+mod common;
+
+use std::{num::NonZeroU32, time::Duration};
+
+use clap::Parser;
+use concurrent_chat_server::{
+    chat::ChatNode, cli::Cli, gossip, message::ChatMessage, node::MyBehaviourEvent,
+};
+use libp2p::{
+    core::ConnectedPoint,
+    mdns,
+    swarm::{ConnectionId, SwarmEvent},
+    Multiaddr, PeerId,
+};
+use mockall::predicate::eq;
+
+use common::MockGossipsub;
+
+// Stands in for mDNS on the local network, announcing peers as discovered or expired.
+struct FakeMdns;
+
+impl FakeMdns {
+    fn discovered(peer: PeerId, addr: &Multiaddr) -> SwarmEvent<MyBehaviourEvent> {
+        SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Discovered(vec![(
+            peer,
+            addr.clone(),
+        )])))
+    }
+
+    fn expired(peer: PeerId, addr: &Multiaddr) -> SwarmEvent<MyBehaviourEvent> {
+        SwarmEvent::Behaviour(MyBehaviourEvent::Mdns(mdns::Event::Expired(vec![(
+            peer,
+            addr.clone(),
+        )])))
+    }
+}
+
+// A connection `peer` dialed, as the swarm reports it once established.
+fn connected(peer: PeerId, addr: &Multiaddr) -> SwarmEvent<MyBehaviourEvent> {
+    SwarmEvent::ConnectionEstablished {
+        peer_id: peer,
+        connection_id: ConnectionId::new_unchecked(1),
+        endpoint: ConnectedPoint::Listener {
+            local_addr: addr.clone(),
+            send_back_addr: addr.clone(),
+        },
+        num_established: NonZeroU32::MIN,
+        concurrent_dial_errors: None,
+        established_in: Duration::ZERO,
+    }
+}
+
+fn in_mesh(node: &ChatNode, peer: &PeerId) -> bool {
+    node.swarm
+        .behaviour()
+        .gossipsub
+        .mesh_peers(&node.topic().hash())
+        .any(|p| p == peer)
+}
+
+fn publish(node: &mut ChatNode, body: &str) {
+    let message = ChatMessage::new("a", body, 0);
+    let topic = node.topic().clone();
+    node.swarm
+        .behaviour_mut()
+        .gossipsub
+        .publish(topic, message.encode())
+        .unwrap();
+}
+
+#[test]
+fn discovery_adds_and_expiry_removes_explicit_peers() {
+    let peer = PeerId::random();
+    let addr: Multiaddr = "/ip4/192.168.1.7/tcp/4001".parse().unwrap();
+    let mut gossip = MockGossipsub::new();
+    gossip
+        .expect_add_explicit_peer()
+        .with(eq(peer))
+        .times(1)
+        .return_const(());
+    gossip.expect_remove_explicit_peer().times(0);
+    gossip::discovery(
+        mdns::Event::Discovered(vec![(peer, addr.clone())]),
+        &mut gossip,
+    );
+    gossip.checkpoint();
+
+    gossip
+        .expect_remove_explicit_peer()
+        .with(eq(peer))
+        .times(1)
+        .return_const(());
+    gossip.expect_add_explicit_peer().times(0);
+    gossip::discovery(mdns::Event::Expired(vec![(peer, addr)]), &mut gossip);
+}
+
+#[tokio::test]
+async fn no_mdns_leaves_discovery_off() {
+    // Built from the flags alone, as common::cli adds --no-mdns to every test node
+    let node = ChatNode::new(&Cli::parse_from(["p2p-chat", "--no-mdns"])).unwrap();
+    assert!(!node.swarm.behaviour().mdns.is_enabled());
+    let node = ChatNode::new(&Cli::parse_from(["p2p-chat"])).unwrap();
+    assert!(node.swarm.behaviour().mdns.is_enabled());
+}
+
+#[tokio::test]
+async fn discovered_peers_receive_messages() {
+    let (mut a, a_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut b, b_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (a_id, b_id) = (a.local_peer_id(), b.local_peer_id());
+
+    a.handle_event(FakeMdns::discovered(b_id, &b_addr));
+    b.handle_event(FakeMdns::discovered(a_id, &a_addr));
+    // Real mDNS also hands the swarm the address to dial
+    a.swarm.dial(b_addr.clone()).unwrap();
+    let topic = a.topic().clone();
+    common::run_until(&mut a, &mut b, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    publish(&mut a, "found you over mdns");
+    common::run_until(&mut a, &mut b, Duration::from_secs(5), |_, b| {
+        b.history()
+            .any(|m| &*m.message.body == "found you over mdns")
+    })
+    .await;
+    // Explicit peers get every message directly and are kept out of the mesh
+    assert!(!in_mesh(&a, &b_id));
+
+    // Once expired, b is an ordinary peer again and a grafts it on a heartbeat
+    a.handle_event(FakeMdns::expired(b_id, &b_addr));
+    b.handle_event(FakeMdns::expired(a_id, &a_addr));
+    // Grafting happens inside the heartbeat without a swarm event, so look once a second
+    for _ in 0..20 {
+        if in_mesh(&a, &b_id) {
+            return;
+        }
+        common::run_for(&mut a, &mut b, Duration::from_secs(1)).await;
+    }
+    panic!("b never joined a's mesh");
+}
+
+#[tokio::test]
+async fn blocked_peers_found_by_mdns_are_refused() {
+    let (logs, _guard) = common::capture_logs();
+    let (mut a, a_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut b, b_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let b_id = b.local_peer_id();
+    a.handle_line(&format!("/block {b_id}")).await;
+
+    // Found again, as after a restart, b isn't made an explicit peer to dial
+    a.handle_event(FakeMdns::discovered(b_id, &b_addr));
+    assert!(logs.contains(&format!(
+        "[ban] ignored blocked peer {b_id} discovered over mDNS"
+    )));
+    b.swarm.dial(a_addr).unwrap();
+    common::run_for(&mut a, &mut b, Duration::from_secs(2)).await;
+    assert!(!a.swarm.is_connected(&b_id));
+    assert!(!b.swarm.is_connected(&a.local_peer_id()));
+
+    // A connection that gets through anyway is closed straight away
+    a.handle_event(connected(b_id, &b_addr));
+    assert!(logs.contains(&format!("[blocked peer attempted connection: {b_id}]")));
+}
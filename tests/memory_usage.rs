@@ -0,0 +1,73 @@
+// Memory use of a node under a long stream of messages. The heap check needs realistic
+// allocator behavior, so run it with `cargo test --release --test memory_usage -- --ignored`.
+mod common;
+
+use std::{env, fs};
+
+use concurrent_chat_server::{
+    chat::{ChatNode, MAX_HISTORY},
+    message::ChatMessage,
+};
+use libp2p::{gossipsub, PeerId};
+
+const MESSAGES: u64 = 10_000;
+
+// Allowed growth of the resident set while the messages are handled, overridable with
+// P2P_CHAT_MEMORY_LIMIT_MIB
+const DEFAULT_LIMIT_MIB: u64 = 32;
+
+// Resident set size in KiB, from /proc/self/status. None where there is no procfs.
+fn resident_kib() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+// A signed message from a new author each time, so no per-peer state is reused.
+fn message(seq: u64) -> gossipsub::Event {
+    let author = PeerId::random();
+    let chat = ChatMessage::new(
+        format!("peer{seq}"),
+        format!("message number {seq} with some padding to look like real chat"),
+        seq,
+    );
+    common::message_event(author, seq, chat.encode())
+}
+
+#[test]
+#[ignore = "measures process memory; run alone with --release -- --ignored"]
+fn history_stays_bounded_over_many_messages() {
+    let limit_mib = env::var("P2P_CHAT_MEMORY_LIMIT_MIB")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_LIMIT_MIB);
+    let mut node = ChatNode::new(&common::cli(&["--rate-limit", "1000000"])).unwrap();
+    let capacity = node.history_capacity();
+
+    // Warm up, so the allocations every node makes anyway aren't counted as growth
+    for seq in 0..MAX_HISTORY as u64 {
+        node.receive(message(seq));
+    }
+    let before = resident_kib();
+    for seq in MAX_HISTORY as u64..MESSAGES {
+        node.receive(message(seq));
+    }
+    let after = resident_kib();
+
+    assert_eq!(node.history().count(), MAX_HISTORY);
+    assert_eq!(
+        node.history().last().unwrap().message.timestamp,
+        MESSAGES - 1
+    );
+    assert!(capacity <= MAX_HISTORY.next_power_of_two());
+    assert_eq!(node.history_capacity(), capacity, "history reallocated");
+    // Every message arrived within the dedup TTL
+    assert_eq!(node.dedup().len(), MESSAGES as usize);
+    if let (Some(before), Some(after)) = (before, after) {
+        let grown = after.saturating_sub(before) / 1024;
+        assert!(
+            grown <= limit_mib,
+            "resident memory grew by {grown} MiB, more than {limit_mib} MiB"
+        );
+    }
+}
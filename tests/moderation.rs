@@ -0,0 +1,133 @@
+// Room moderators and their signed kick and room-ban messages.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    control::{ControlMessage, SignedControl},
+    room::{ModAction, Moderation, ModerationOutcome, RoomSettings, Rooms},
+};
+use libp2p::{identity::Keypair, PeerId};
+
+fn moderation(action: ModAction, target: PeerId, timestamp: u64) -> Moderation {
+    Moderation {
+        action,
+        room: "lobby".to_string(),
+        target,
+        reason: "spam".to_string(),
+        timestamp,
+    }
+}
+
+#[test]
+fn only_moderators_are_honored() {
+    let (alice, mallory, target) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut rooms = Rooms::new(PeerId::random());
+    let mut settings = RoomSettings {
+        moderators: vec![alice],
+        ..RoomSettings::default()
+    };
+
+    let kick = moderation(ModAction::Kick, target, 1);
+    assert_eq!(
+        rooms.receive(mallory, &kick, &mut settings),
+        ModerationOutcome::NotModerator
+    );
+    assert!(!rooms.is_ignored("lobby", &target, Some(&settings)));
+
+    assert_eq!(
+        rooms.receive(alice, &kick, &mut settings),
+        ModerationOutcome::Honored
+    );
+    assert!(rooms.is_ignored("lobby", &target, Some(&settings)));
+    // Kicks only last for the session, so the settings are untouched
+    assert!(settings.banned.is_empty());
+    // The same action again is a replay
+    assert_eq!(
+        rooms.receive(alice, &kick, &mut settings),
+        ModerationOutcome::Stale
+    );
+    // Rooms are independent
+    assert!(!rooms.is_ignored("other", &target, Some(&settings)));
+}
+
+#[test]
+fn room_bans_are_saved_and_can_be_lifted() {
+    let (local, alice, target) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut rooms = Rooms::new(local);
+    rooms.add_moderator("lobby", alice);
+    let mut settings = RoomSettings::default();
+
+    let ban = moderation(ModAction::RoomBan, target, 1);
+    assert_eq!(
+        rooms.receive(alice, &ban, &mut settings),
+        ModerationOutcome::Honored
+    );
+    assert_eq!(settings.banned, vec![target]);
+
+    // Moderators can't remove the local node from its own view
+    assert_eq!(
+        rooms.receive(alice, &moderation(ModAction::Kick, local, 2), &mut settings),
+        ModerationOutcome::TargetsSelf
+    );
+
+    assert!(rooms.readmit("lobby", &target, &mut settings));
+    assert!(!rooms.is_ignored("lobby", &target, Some(&settings)));
+}
+
+#[test]
+fn moderation_commands_parse() {
+    let peer = PeerId::random();
+    assert_eq!(
+        commands::parse(&format!("/kick {peer} off topic")),
+        Some(Ok(UserCommand::Kick {
+            peer,
+            reason: "off topic".to_string()
+        }))
+    );
+    assert_eq!(commands::parse("/modlist"), Some(Ok(UserCommand::ModList)));
+
+    let signed = SignedControl::sign(
+        &Keypair::generate_ed25519(),
+        &ControlMessage::Moderation(moderation(ModAction::RoomBan, peer, 1)),
+    )
+    .unwrap();
+    assert!(signed.verify().is_ok());
+}
+
+#[tokio::test]
+async fn members_honor_kicks_from_the_configured_moderator() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let alice_id = alice.local_peer_id().to_string();
+    let (mut bob, bob_addr) =
+        common::spawn_chat_node(&common::cli(&["--moderator", &alice_id])).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = concurrent_chat_server::control::control_topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| {
+            common::has_subscriber(alice, &control) && common::has_subscriber(bob, &control)
+        },
+    )
+    .await;
+
+    // Bob is no moderator in Alice's view, so his kick is ignored
+    let spammer = PeerId::random();
+    bob.handle_line(&format!("/kick {spammer}")).await;
+    alice
+        .handle_line(&format!("/kick {spammer} flooding"))
+        .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.is_removed(&spammer)
+    })
+    .await;
+    assert!(alice.is_removed(&spammer));
+
+    let innocent = PeerId::random();
+    bob.handle_line(&format!("/kick {innocent}")).await;
+    common::run_for(&mut alice, &mut bob, Duration::from_millis(500)).await;
+    assert!(!alice.is_removed(&innocent));
+}
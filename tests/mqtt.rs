@@ -0,0 +1,467 @@
+// The MQTT bridge: its settings, templates and packets, a bridge talking to a broker, and a
+// node mirroring MQTT topics.
+mod common;
+
+use std::{fs, time::Duration};
+
+use concurrent_chat_server::{
+    attachment::{self, Cid},
+    mqtt::{self, Direction, MqttBridge, MqttEvent, MqttMapping, MqttSettings, Packet},
+    node, runtime,
+};
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\xff";
+
+// A broker taking one connection, whose packets the test reads and answers.
+struct FakeBroker {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl FakeBroker {
+    // Take the bridge's connection and accept it, then answer its subscription with `codes`.
+    // Returns the CONNECT and the topic filters subscribed to.
+    async fn accept(listener: &TcpListener, codes: &[u8]) -> (Self, Packet, Vec<(String, u8)>) {
+        let (stream, _) = runtime::timeout(Duration::from_secs(10), listener.accept())
+            .await
+            .expect("the bridge connects")
+            .unwrap();
+        let mut broker = FakeBroker {
+            stream,
+            buffer: Vec::new(),
+        };
+        let connect = broker.read().await;
+        broker
+            .send(Packet::ConnAck {
+                session_present: false,
+                code: 0,
+            })
+            .await;
+        let Packet::Subscribe { id, filters } = broker.read().await else {
+            panic!("the bridge didn't subscribe");
+        };
+        let codes = codes.to_vec();
+        broker.send(Packet::SubAck { id, codes }).await;
+        (broker, connect, filters)
+    }
+
+    async fn read(&mut self) -> Packet {
+        loop {
+            if let Some((packet, len)) = Packet::decode(&self.buffer).unwrap() {
+                self.buffer.drain(..len);
+                return packet;
+            }
+            let mut chunk = [0; 4096];
+            let read = runtime::timeout(Duration::from_secs(10), self.stream.read(&mut chunk))
+                .await
+                .expect("the bridge sends a packet")
+                .unwrap();
+            assert!(read > 0, "the bridge hung up");
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    async fn send(&mut self, packet: Packet) {
+        self.stream.write_all(&packet.encode()).await.unwrap();
+    }
+
+    async fn publish(&mut self, topic: &str, qos: u8, id: Option<u16>, payload: &[u8]) {
+        self.send(Packet::Publish {
+            topic: topic.to_string(),
+            id,
+            qos,
+            retain: false,
+            dup: false,
+            payload: payload.to_vec(),
+        })
+        .await;
+    }
+}
+
+fn mapping(topic: &str, direction: Direction, qos: u8) -> MqttMapping {
+    MqttMapping {
+        qos,
+        ..MqttMapping::new(topic, node::TOPIC, direction)
+    }
+}
+
+#[test]
+fn settings_templates_and_packets() {
+    let settings: MqttSettings = serde_json::from_value(json!({
+        "broker": "localhost",
+        "mappings": [{"mqtt_topic": "sensors/#", "room": "ops"}]
+    }))
+    .unwrap();
+    assert_eq!(
+        settings,
+        MqttSettings::new(
+            "localhost",
+            vec![MqttMapping::new("sensors/#", "ops", Direction::In)]
+        )
+    );
+    assert_eq!(
+        settings.address(),
+        Ok(("localhost".to_string(), mqtt::PORT))
+    );
+    assert!(settings.check().is_ok());
+    let bad_mappings = [
+        mapping("sensors/#", Direction::Out, 0),
+        mapping("sensors/#/temp", Direction::In, 0),
+        mapping("sensors/a+", Direction::In, 0),
+        mapping("", Direction::In, 0),
+        mapping("sensors", Direction::In, 3),
+    ];
+    for bad in bad_mappings {
+        let settings = MqttSettings::new("localhost", vec![bad.clone()]);
+        assert!(settings.check().is_err(), "{bad:?} accepted");
+    }
+    let login = MqttSettings {
+        password: Some("pw".to_string()),
+        ..settings.clone()
+    };
+    assert!(
+        login.check().is_err(),
+        "a password without a username accepted"
+    );
+    assert!(MqttSettings::new("localhost", Vec::new()).check().is_err());
+
+    for (filter, topic, matches) in [
+        ("sensors/+/temp", "sensors/kitchen/temp", true),
+        ("sensors/+/temp", "sensors/kitchen/humidity", false),
+        ("sensors/#", "sensors", true),
+        ("sensors/#", "sensors/a/b", true),
+        ("#", "$SYS/uptime", false),
+        ("lights", "lights/on", false),
+    ] {
+        assert_eq!(
+            mqtt::topic_matches(filter, topic),
+            matches,
+            "{filter} {topic}"
+        );
+    }
+
+    let templated = MqttMapping {
+        template: Some("{topic} is {payload.reading.celsius}°C {unknown}".to_string()),
+        ..mapping("sensors/#", Direction::In, 0)
+    };
+    let payload = json!({"reading": {"celsius": 21.5}}).to_string();
+    assert_eq!(
+        templated.render_in("sensors/kitchen", &payload),
+        "sensors/kitchen is 21.5°C {unknown}"
+    );
+    assert_eq!(
+        mapping("a", Direction::In, 0).render_in("a", "raw"),
+        "a: raw"
+    );
+    let commands = MqttMapping {
+        prefix: Some("!".to_string()),
+        template: Some("{nick}: {text}".to_string()),
+        ..mapping("cmd", Direction::Out, 0)
+    };
+    assert_eq!(
+        commands.render_out("bob", "!reboot").as_deref(),
+        Some("bob: reboot")
+    );
+    assert_eq!(commands.render_out("bob", "hello"), None);
+
+    let publish = Packet::Publish {
+        topic: "big".to_string(),
+        id: Some(300),
+        qos: 1,
+        retain: true,
+        dup: true,
+        payload: vec![7; 20_000],
+    };
+    for packet in [
+        publish,
+        Packet::Connect {
+            client_id: "id".to_string(),
+            username: Some("user".to_string()),
+            password: Some("pw".to_string()),
+            keep_alive: 30,
+        },
+        Packet::PubRel(9),
+        Packet::PingReq,
+    ] {
+        let encoded = packet.encode();
+        assert_eq!(Packet::decode(&encoded[..encoded.len() - 1]), Ok(None));
+        assert_eq!(Packet::decode(&encoded), Ok(Some((packet, encoded.len()))));
+    }
+    assert_eq!(attachment::sniff_mime_type(PNG), "image/png");
+    assert_eq!(
+        attachment::sniff_mime_type(&[0, 1, 2]),
+        "application/octet-stream"
+    );
+}
+
+#[tokio::test]
+async fn the_bridge_acknowledges_mirrors_and_sends_again() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let spool = common::temp_path("bridge");
+    let settings = MqttSettings {
+        username: Some("bridge".to_string()),
+        password: Some("secret".to_string()),
+        client_id: Some("node-1".to_string()),
+        ..MqttSettings::new(
+            &listener.local_addr().unwrap().to_string(),
+            vec![
+                MqttMapping {
+                    template: Some("{topic}: {payload.celsius}°C".to_string()),
+                    ..mapping("sensors/+/temp", Direction::In, 1)
+                },
+                mapping("camera/#", Direction::In, 0),
+                mapping("refused/#", Direction::In, 0),
+                MqttMapping {
+                    prefix: Some("!".to_string()),
+                    ..mapping("lights", Direction::Both, 2)
+                },
+            ],
+        )
+    };
+    let mut bridge = MqttBridge::spawn(settings, spool.clone());
+    let (mut broker, connect, filters) = FakeBroker::accept(&listener, &[0, 2, 0x80, 1]).await;
+    assert_eq!(
+        connect,
+        Packet::Connect {
+            client_id: "node-1".to_string(),
+            username: Some("bridge".to_string()),
+            password: Some("secret".to_string()),
+            keep_alive: 60,
+        }
+    );
+    let expected = [
+        ("camera/#", 0),
+        ("lights", 2),
+        ("refused/#", 0),
+        ("sensors/+/temp", 1),
+    ];
+    let expected: Vec<_> = expected.map(|(f, qos)| (f.to_string(), qos)).into();
+    assert_eq!(filters, expected);
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MqttEvent::Refused("refused/#".to_string())
+    );
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MqttEvent::Connected { subscriptions: 3 }
+    );
+
+    // QoS 1 is acknowledged, and JSON payloads fill in the template
+    let reading = json!({"celsius": 21.5}).to_string();
+    broker
+        .publish("sensors/kitchen/temp", 1, Some(7), reading.as_bytes())
+        .await;
+    assert_eq!(broker.read().await, Packet::PubAck(7));
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MqttEvent::Message {
+            topic: "sensors/kitchen/temp".to_string(),
+            body: "sensors/kitchen/temp: 21.5°C".to_string(),
+            attachment: None,
+        }
+    );
+
+    // Retained payloads are old news, and binary ones are attached rather than mangled
+    broker
+        .send(Packet::Publish {
+            topic: "camera/front".to_string(),
+            id: None,
+            qos: 0,
+            retain: true,
+            dup: false,
+            payload: b"old".to_vec(),
+        })
+        .await;
+    broker.publish("camera/front", 0, None, PNG).await;
+    let MqttEvent::Message {
+        body,
+        attachment: Some((attachment, path)),
+        ..
+    } = common::next(bridge.next()).await
+    else {
+        panic!("the image wasn't attached");
+    };
+    assert!(
+        body.starts_with("camera/front: ⟨binary, 17 bytes"),
+        "{body}"
+    );
+    assert_eq!(attachment.cid, Cid::of(PNG));
+    assert_eq!(
+        (attachment.mime_type.as_str(), attachment.filename.as_str()),
+        ("image/png", "front")
+    );
+    assert_eq!(fs::read(&path).unwrap(), PNG);
+
+    // QoS 2 is passed on once, however often the broker repeats it before releasing it
+    broker.publish("lights", 2, Some(9), b"on").await;
+    assert_eq!(broker.read().await, Packet::PubRec(9));
+    broker
+        .send(Packet::Publish {
+            topic: "lights".to_string(),
+            id: Some(9),
+            qos: 2,
+            retain: false,
+            dup: true,
+            payload: b"on".to_vec(),
+        })
+        .await;
+    assert_eq!(broker.read().await, Packet::PubRec(9));
+    broker.send(Packet::PubRel(9)).await;
+    assert_eq!(broker.read().await, Packet::PubComp(9));
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        MqttEvent::Message { body, .. } if body == "lights: on"
+    ));
+
+    // Commands go out with QoS 2, and don't come back in when the broker echoes them
+    assert_eq!(bridge.relay("bob", "hello"), Ok(0));
+    assert_eq!(bridge.relay("bob", "!off"), Ok(1));
+    let Packet::Publish {
+        topic,
+        id: Some(id),
+        qos: 2,
+        payload,
+        ..
+    } = broker.read().await
+    else {
+        panic!("the command wasn't published with QoS 2");
+    };
+    assert_eq!(
+        (topic.as_str(), payload.as_slice()),
+        ("lights", &b"off"[..])
+    );
+    broker.send(Packet::PubRec(id)).await;
+    assert_eq!(broker.read().await, Packet::PubRel(id));
+    broker.send(Packet::PubComp(id)).await;
+    broker.publish("lights", 0, None, b"off").await;
+    broker.publish("lights", 0, None, b"dimmed").await;
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        MqttEvent::Message { body, .. } if body == "lights: dimmed"
+    ));
+
+    // What the broker hadn't acknowledged when it went away is sent again
+    assert_eq!(bridge.relay("bob", "!dim"), Ok(1));
+    let Packet::Publish { id: Some(id), .. } = broker.read().await else {
+        panic!("the command wasn't published");
+    };
+    drop(broker);
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        MqttEvent::Disconnected(_)
+    ));
+    let (mut broker, _, _) = FakeBroker::accept(&listener, &[0, 2, 0, 1]).await;
+    let Packet::Publish {
+        id: Some(again),
+        dup: true,
+        payload,
+        ..
+    } = broker.read().await
+    else {
+        panic!("the command wasn't sent again");
+    };
+    assert_eq!((again, payload.as_slice()), (id, &b"dim"[..]));
+    assert_eq!(
+        common::next(bridge.next()).await,
+        MqttEvent::Connected { subscriptions: 4 }
+    );
+    fs::remove_dir_all(&spool).unwrap();
+}
+
+#[cfg(feature = "mqtt")]
+#[tokio::test]
+async fn a_bridge_node_mirrors_topics_without_loops() {
+    use concurrent_chat_server::config::Config;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let dir = common::temp_path("node");
+    fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("config.json");
+    let config = Config {
+        mqtt: Some(MqttSettings::new(
+            &listener.local_addr().unwrap().to_string(),
+            vec![
+                mapping("sensors/#", Direction::In, 0),
+                mapping("cmd", Direction::Out, 1),
+                MqttMapping::new("elsewhere", "another-room", Direction::In),
+            ],
+        )),
+        ..Config::default()
+    };
+    config.save(&config_path).unwrap();
+    let alice_cli = common::cli(&["--config", config_path.to_str().unwrap()]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&alice_cli).await;
+    let (mut broker, _, filters) = FakeBroker::accept(&listener, &[0]).await;
+    assert_eq!(filters, [("sensors/#".to_string(), 0)]);
+    let event = common::next(alice.next_mqtt_event()).await;
+    assert_eq!(event, MqttEvent::Connected { subscriptions: 1 });
+    alice.handle_mqtt_event(event);
+
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    // The room's messages are published to the command topic
+    bob.handle_line("reboot").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+    let Packet::Publish {
+        topic: published,
+        id: Some(id),
+        payload,
+        ..
+    } = broker.read().await
+    else {
+        panic!("the command wasn't published with QoS 1");
+    };
+    assert_eq!(
+        (published.as_str(), payload.as_slice()),
+        ("cmd", &b"reboot"[..])
+    );
+    broker.send(Packet::PubAck(id)).await;
+
+    // Readings come to the room marked as from MQTT, images as attachments
+    broker.publish("sensors/kitchen", 0, None, b"21.5").await;
+    broker.publish("sensors/camera", 0, None, PNG).await;
+    for _ in 0..2 {
+        let event = common::next(alice.next_mqtt_event()).await;
+        alice.handle_mqtt_event(event);
+    }
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 2
+    })
+    .await;
+    let mirrored: Vec<_> = bob.history().map(|stored| &stored.message).collect();
+    assert!(mirrored
+        .iter()
+        .all(|message| message.origin.as_deref() == Some(mqtt::ORIGIN)));
+    assert_eq!(&*mirrored[1].body, "sensors/kitchen: 21.5");
+    let attached = mirrored[0]
+        .attachment
+        .as_ref()
+        .expect("the image is attached");
+    assert_eq!(
+        (attached.cid, attached.mime_type.as_str()),
+        (Cid::of(PNG), "image/png")
+    );
+    assert!(mqtt::dir_beside(&config_path)
+        .join(Cid::of(PNG).to_string())
+        .exists());
+    let counters = alice.stats().counters;
+    assert_eq!((counters.mqtt_sent, counters.mqtt_received), (1, 2));
+    fs::remove_dir_all(&dir).unwrap();
+}
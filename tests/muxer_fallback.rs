@@ -0,0 +1,80 @@
+// Falling back to mplex for peers without Yamux, only with `--allow-mplex`.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::chat::ChatNode;
+use libp2p::{
+    futures::StreamExt,
+    noise,
+    swarm::{dummy, SwarmEvent},
+    tcp, Multiaddr, Swarm, SwarmBuilder,
+};
+use libp2p_mplex::MplexConfig;
+
+// A peer that only speaks mplex, like some older implementations.
+fn mplex_only_peer() -> Swarm<dummy::Behaviour> {
+    SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(tcp::Config::default(), noise::Config::new, MplexConfig::new)
+        .unwrap()
+        .with_behaviour(|_| dummy::Behaviour)
+        .unwrap()
+        .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
+        .build()
+}
+
+// Dial `node` from an mplex-only peer and report whether `node` accepted the connection. The
+// dialer can't tell: it only offers one multiplexer, so it assumes it was accepted.
+async fn dial_with_mplex(node: &mut ChatNode, addr: Multiaddr) -> bool {
+    let mut peer = mplex_only_peer();
+    peer.dial(addr).unwrap();
+    let outcome = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            tokio::select! {
+                _ = peer.select_next_some() => {}
+                event = node.swarm.select_next_some() => match event {
+                    SwarmEvent::ConnectionEstablished { .. } => return true,
+                    SwarmEvent::IncomingConnectionError { .. } => return false,
+                    event => node.handle_event(event),
+                },
+            }
+        }
+    });
+    outcome.await.expect("dial finished before timeout")
+}
+
+#[tokio::test]
+async fn mplex_only_peers_connect_only_when_allowed() {
+    let (mut strict, strict_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    assert!(!dial_with_mplex(&mut strict, strict_addr).await);
+    assert_eq!(strict.stats().counters.mplex_connections, 0);
+
+    let (mut lenient, lenient_addr) =
+        common::spawn_chat_node(&common::cli(&["--allow-mplex"])).await;
+    assert!(dial_with_mplex(&mut lenient, lenient_addr).await);
+    let counters = lenient.stats().counters;
+    assert_eq!(
+        (counters.yamux_connections, counters.mplex_connections),
+        (0, 1)
+    );
+}
+
+#[tokio::test]
+async fn yamux_is_preferred_when_both_sides_allow_mplex() {
+    let cli = common::cli(&["--allow-mplex"]);
+    let (mut alice, _) = common::spawn_chat_node(&cli).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&cli).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| {
+            alice.stats().counters.yamux_connections == 1
+                && bob.stats().counters.yamux_connections == 1
+        },
+    )
+    .await;
+    assert_eq!(alice.stats().counters.mplex_connections, 0);
+}
@@ -0,0 +1,108 @@
+// Peers online under the same nick are told apart by a PeerId suffix.
+mod common;
+
+use std::{env, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    collision::{self, Collision, Collisions},
+    message::ChatMessage,
+};
+use libp2p::{gossipsub, PeerId};
+
+fn message(source: PeerId, seq: u64, nick: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new(nick, format!("message {seq}"), 0);
+    common::message_event(source, seq, chat.encode())
+}
+
+#[test]
+fn suffixes_fit_and_can_be_split_off() {
+    let peer = PeerId::random();
+    let name = collision::disambiguate("alex", &peer);
+    assert_eq!(
+        collision::split(&name),
+        Some(("alex", collision::suffix(&peer).as_str()))
+    );
+    assert!(peer.to_base58().ends_with(&collision::suffix(&peer)));
+    let long = collision::disambiguate(&"x".repeat(40), &peer);
+    assert_eq!(long.chars().count(), 32);
+    assert_eq!(collision::split("alex"), None);
+}
+
+#[test]
+fn three_peers_sharing_a_nick_join_and_leave() {
+    let (a, b, c) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut collisions = Collisions::default();
+    assert_eq!(collisions.update([(a, "alex")]), []);
+    assert!(!collisions.is_ambiguous(&a));
+
+    // The second alex makes the nick ambiguous, case notwithstanding
+    let clash = collisions.update([(a, "alex"), (b, "Alex")]);
+    assert_eq!(clash.len(), 1);
+    assert_eq!(clash[0].peers.len(), 2);
+    assert!(collisions.is_ambiguous(&a) && collisions.is_ambiguous(&b));
+    // Only the change is reported
+    assert_eq!(collisions.update([(a, "alex"), (b, "Alex")]), []);
+
+    // A third one is reported again, with all three
+    let clash = collisions.update([(a, "alex"), (b, "Alex"), (c, "alex")]);
+    assert!(matches!(&clash[..], [Collision { peers, .. }] if peers.len() == 3));
+
+    // One leaving keeps the other two apart, without a notice
+    assert_eq!(collisions.update([(a, "alex"), (c, "alex")]), []);
+    assert!(!collisions.is_ambiguous(&b));
+    assert!(collisions.is_ambiguous(&c));
+
+    // A rename resolves it
+    assert_eq!(collisions.update([(a, "alex"), (c, "carol")]), []);
+    assert!(!collisions.is_ambiguous(&a) && !collisions.is_ambiguous(&c));
+}
+
+#[tokio::test]
+async fn names_get_suffixes_while_peers_share_a_nick() {
+    let config = env::temp_dir().join(format!("p2p-chat-collisions-{}.json", process::id()));
+    let cli = common::cli(&["--config", config.to_str().unwrap()]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    let topic = common::topic().hash();
+    let alexes = [PeerId::random(), PeerId::random(), PeerId::random()];
+
+    for (seq, peer) in alexes.iter().enumerate() {
+        node.receive(gossipsub::Event::Subscribed {
+            peer_id: *peer,
+            topic: topic.clone(),
+        });
+        node.receive(message(*peer, seq as u64, "alex"));
+        node.tick();
+    }
+    for peer in &alexes {
+        assert_eq!(
+            node.display_name(peer),
+            collision::disambiguate("alex", peer)
+        );
+    }
+
+    // As they leave, the last one left is plain alex again
+    for peer in &alexes[..2] {
+        node.receive(gossipsub::Event::Unsubscribed {
+            peer_id: *peer,
+            topic: topic.clone(),
+        });
+    }
+    node.tick();
+    assert_eq!(node.display_name(&alexes[2]), "alex");
+
+    // Renaming works the same way
+    node.receive(gossipsub::Event::Subscribed {
+        peer_id: alexes[0],
+        topic: topic.clone(),
+    });
+    node.tick();
+    assert_eq!(
+        node.display_name(&alexes[0]),
+        collision::disambiguate("alex", &alexes[0])
+    );
+    node.receive(message(alexes[0], 10, "alexandra"));
+    assert_eq!(node.display_name(&alexes[0]), "alexandra");
+    assert_eq!(node.display_name(&alexes[2]), "alex");
+    let _ = std::fs::remove_file(config);
+}
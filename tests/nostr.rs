@@ -0,0 +1,306 @@
+// The bridge to Nostr: signed text notes, the relay protocol, and a node talking to a relay.
+mod common;
+
+use std::time::Duration;
+
+use clap::Parser;
+use concurrent_chat_server::{
+    cli::Cli,
+    nostr::{self, Event, Filter, NostrKeys, Relay, RelayEvent, RelayMessage, TEXT_NOTE},
+    runtime,
+};
+use libp2p::identity::Keypair;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use soketto::{
+    connection::{Receiver, Sender},
+    handshake::{server::Response, Server},
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+type Socket = Compat<TcpStream>;
+
+// A relay that takes one connection at a time, driven by the test.
+struct FakeRelay {
+    listener: TcpListener,
+}
+
+impl FakeRelay {
+    async fn bind() -> Self {
+        FakeRelay {
+            listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("ws://{}/", self.listener.local_addr().unwrap())
+    }
+
+    async fn accept(&self) -> (Sender<Socket>, Receiver<Socket>) {
+        let (tcp, _) = self.listener.accept().await.unwrap();
+        let mut server = Server::new(tcp.compat());
+        let key = server.receive_request().await.unwrap().key();
+        let accept = Response::Accept {
+            key,
+            protocol: None,
+        };
+        server.send_response(&accept).await.unwrap();
+        server.into_builder().finish()
+    }
+}
+
+async fn receive(receiver: &mut Receiver<Socket>) -> Value {
+    let mut data = Vec::new();
+    runtime::timeout(Duration::from_secs(10), receiver.receive_data(&mut data))
+        .await
+        .expect("the client sends something")
+        .unwrap();
+    serde_json::from_slice(&data).unwrap()
+}
+
+async fn send(sender: &mut Sender<Socket>, message: Value) {
+    sender.send_text(message.to_string()).await.unwrap();
+    sender.flush().await.unwrap();
+}
+
+#[test]
+fn notes_are_signed_and_their_ids_follow_nip_01() {
+    let keys = NostrKeys::derive(&Keypair::generate_ed25519());
+    let note = Event::text_note(&keys, "hi \"there\"\n", "room", 1_700_000_000);
+    assert_eq!(note.kind, TEXT_NOTE);
+    assert_eq!(note.pubkey, keys.public_key());
+    assert!(note.has_hashtag("ROOM"));
+    let serialized = format!(
+        "[0,\"{}\",1700000000,1,[[\"t\",\"room\"]],\"hi \\\"there\\\"\\n\"]",
+        note.pubkey
+    );
+    assert_eq!(note.id, hex::encode(Sha256::digest(serialized)));
+    note.verify().unwrap();
+
+    let mut forged = note.clone();
+    forged.content = "something else".to_string();
+    assert!(forged.verify().is_err());
+    forged.id = hex::encode(forged.compute_id());
+    assert!(forged.verify().is_err(), "the signature covers the id");
+    let mut stolen = note.clone();
+    stolen.pubkey = NostrKeys::derive(&Keypair::generate_ed25519()).public_key();
+    stolen.id = hex::encode(stolen.compute_id());
+    assert!(stolen.verify().is_err());
+}
+
+#[test]
+fn an_identity_always_has_the_same_nostr_key() {
+    let keypair = Keypair::generate_ed25519();
+    let key = NostrKeys::derive(&keypair).public_key();
+    assert_eq!(key.len(), 64);
+    assert_eq!(NostrKeys::derive(&keypair).public_key(), key);
+    assert_ne!(
+        NostrKeys::derive(&Keypair::generate_ed25519()).public_key(),
+        key
+    );
+}
+
+#[test]
+fn relay_messages_parse() {
+    let keys = NostrKeys::derive(&Keypair::generate_ed25519());
+    let note = Event::text_note(&keys, "hello", "room", 1);
+    assert_eq!(
+        RelayMessage::parse(&json!(["EVENT", "sub", note]).to_string()),
+        Ok(RelayMessage::Event {
+            subscription: "sub".to_string(),
+            event: note.clone(),
+        })
+    );
+    assert_eq!(
+        RelayMessage::parse(r#"["OK","abc",false,"blocked: no spam"]"#),
+        Ok(RelayMessage::Ok {
+            id: "abc".to_string(),
+            accepted: false,
+            message: "blocked: no spam".to_string(),
+        })
+    );
+    assert_eq!(
+        RelayMessage::parse(r#"["EOSE","sub"]"#),
+        Ok(RelayMessage::EndOfStored("sub".to_string()))
+    );
+    assert_eq!(
+        RelayMessage::parse(r#"["NOTICE","slow down"]"#),
+        Ok(RelayMessage::Notice("slow down".to_string()))
+    );
+    for bad in ["{}", "[]", r#"["AUTH","challenge"]"#, r#"["OK","abc"]"#] {
+        assert!(RelayMessage::parse(bad).is_err(), "{bad} parsed");
+    }
+
+    let filter = Filter {
+        kinds: vec![TEXT_NOTE],
+        hashtags: vec!["room".to_string()],
+        since: Some(5),
+    };
+    let req: Value = serde_json::from_str(&nostr::req_message("sub", &filter)).unwrap();
+    assert_eq!(
+        req,
+        json!(["REQ", "sub", {"kinds": [1], "#t": ["room"], "since": 5}])
+    );
+}
+
+#[test]
+fn the_relay_flags_parse() {
+    let cli =
+        Cli::try_parse_from(["p2p-chat", "--nostr-relay", "wss://relay.example.com"]).unwrap();
+    assert_eq!(
+        cli.nostr_relay.unwrap().as_str(),
+        "wss://relay.example.com/"
+    );
+    for bad in ["https://relay.example.com", "relay.example.com", "ws://"] {
+        assert!(
+            Cli::try_parse_from(["p2p-chat", "--nostr-relay", bad]).is_err(),
+            "{bad} accepted"
+        );
+    }
+    assert!(Cli::try_parse_from(["p2p-chat", "--nostr-only"]).is_err());
+    // Passphrase rooms are private
+    assert!(Cli::try_parse_from([
+        "p2p-chat",
+        "--nostr-relay",
+        "ws://localhost",
+        "--room-pass",
+        "secret"
+    ])
+    .is_err());
+}
+
+#[tokio::test]
+async fn the_relay_connection_subscribes_publishes_and_reconnects() {
+    let fake = FakeRelay::bind().await;
+    let keys = NostrKeys::derive(&Keypair::generate_ed25519());
+    let own = keys.public_key();
+    let url = nostr::relay_url(&fake.url()).unwrap();
+    let mut relay = Relay::spawn(url, keys.clone(), "room".to_string());
+
+    let (mut sender, mut receiver) = fake.accept().await;
+    assert_eq!(common::next(relay.next()).await, RelayEvent::Connected);
+    let req = receive(&mut receiver).await;
+    assert_eq!(req[0], "REQ");
+    assert_eq!(req[2]["kinds"], json!([1]));
+    assert_eq!(req[2]["#t"], json!(["room"]));
+    let subscription = req[1].clone();
+
+    // Someone else's note comes through once, ours and forged ones not at all
+    let other = NostrKeys::derive(&Keypair::generate_ed25519());
+    let theirs = Event::text_note(&other, "hello from nostr", "room", 10);
+    let ours = Event::text_note(&keys, "our own", "room", 10);
+    let mut forged = Event::text_note(&other, "forged", "room", 10);
+    forged.content = "changed".to_string();
+    let untagged = Event::text_note(&other, "elsewhere", "another", 10);
+    for event in [&ours, &forged, &untagged, &theirs, &theirs] {
+        send(&mut sender, json!(["EVENT", subscription, event])).await;
+    }
+    send(&mut sender, json!(["NOTICE", "welcome"])).await;
+    assert_eq!(
+        common::next(relay.next()).await,
+        RelayEvent::Note(theirs.clone())
+    );
+    assert_eq!(
+        common::next(relay.next()).await,
+        RelayEvent::Notice("welcome".to_string())
+    );
+
+    // A published note reaches the relay, which may refuse it
+    let published = relay.publish("from the chat", 20).unwrap();
+    assert_eq!(published.pubkey, own);
+    let event = receive(&mut receiver).await;
+    assert_eq!(event[0], "EVENT");
+    let received: Event = serde_json::from_value(event[1].clone()).unwrap();
+    assert_eq!(received, published);
+    received.verify().unwrap();
+    send(
+        &mut sender,
+        json!(["OK", published.id, false, "blocked: not today"]),
+    )
+    .await;
+    assert_eq!(
+        common::next(relay.next()).await,
+        RelayEvent::Rejected {
+            id: published.id.clone(),
+            reason: "blocked: not today".to_string(),
+        }
+    );
+
+    // The relay goes away; notes wait for the next connection
+    drop((sender, receiver));
+    assert!(matches!(
+        common::next(relay.next()).await,
+        RelayEvent::Disconnected(_)
+    ));
+    let waiting = relay.publish("while away", 30).unwrap();
+    let (mut sender, mut receiver) = fake.accept().await;
+    assert_eq!(common::next(relay.next()).await, RelayEvent::Connected);
+    let req = receive(&mut receiver).await;
+    assert_eq!(req[0], "REQ");
+    let event = receive(&mut receiver).await;
+    assert_eq!(event[1]["id"], json!(waiting.id));
+    assert_eq!(relay.pending(), 0);
+
+    // The note seen before isn't shown again
+    send(&mut sender, json!(["EVENT", req[1], theirs])).await;
+    send(&mut sender, json!(["NOTICE", "done"])).await;
+    assert_eq!(
+        common::next(relay.next()).await,
+        RelayEvent::Notice("done".to_string())
+    );
+}
+
+#[tokio::test]
+async fn chat_messages_are_bridged_as_notes() {
+    let fake = FakeRelay::bind().await;
+    let url = fake.url();
+    let (mut node, _) = common::spawn_chat_node(&common::cli(&["--nostr-relay", &url])).await;
+    let (mut sender, mut receiver) = fake.accept().await;
+    let req = receive(&mut receiver).await;
+    let hashtag = nostr::hashtag_for(common::topic().hash().as_str());
+    assert_eq!(req[2]["#t"], json!([hashtag]));
+
+    node.handle_line("hello nostr").await;
+    let event = receive(&mut receiver).await;
+    let note: Event = serde_json::from_value(event[1].clone()).unwrap();
+    note.verify().unwrap();
+    assert_eq!(note.content, "hello nostr");
+    assert!(note.has_hashtag(&hashtag));
+    assert_eq!(Some(note.pubkey), node.nostr().map(Relay::public_key));
+    assert_eq!(node.stats().counters.notes_published, 1);
+
+    // Notes from the relay are shown like chat, through the room's filter
+    let other = NostrKeys::derive(&Keypair::generate_ed25519());
+    let theirs = Event::text_note(&other, "hello libp2p", &hashtag, 10);
+    send(&mut sender, json!(["EVENT", req[1], theirs])).await;
+    let event = runtime::timeout(Duration::from_secs(10), node.next_relay_event())
+        .await
+        .unwrap();
+    assert_eq!(event, Some(RelayEvent::Connected));
+    let event = runtime::timeout(Duration::from_secs(10), node.next_relay_event())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event, RelayEvent::Note(theirs));
+    node.handle_relay_event(event);
+    assert_eq!(node.stats().counters.notes_received, 1);
+    assert_eq!(node.history().count(), 0, "notes aren't Gossipsub messages");
+}
+
+#[tokio::test]
+async fn nostr_only_nodes_leave_gossipsub_out() {
+    let fake = FakeRelay::bind().await;
+    let url = fake.url();
+    let (mut node, _) =
+        common::spawn_chat_node(&common::cli(&["--nostr-relay", &url, "--nostr-only"])).await;
+    let (_sender, mut receiver) = fake.accept().await;
+    receive(&mut receiver).await;
+    node.handle_line("only to nostr").await;
+    let event = receive(&mut receiver).await;
+    assert_eq!(event[1]["content"], "only to nostr");
+    let counters = node.stats().counters;
+    assert_eq!(counters.notes_published, 1);
+    assert_eq!(counters.published, 0);
+    assert_eq!(node.queued(), 0, "nothing waits for a peer either");
+}
@@ -0,0 +1,160 @@
+// Desktop notifications of mentions and favorite contacts' messages, with bursts summed up.
+mod common;
+
+use std::{
+    env, process,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    message::ChatMessage,
+    notify::{self, Backend, Notification, Notifier, Reason},
+};
+use libp2p::{gossipsub, PeerId};
+
+// Notifications shown, kept for the test to look at.
+#[derive(Clone, Default)]
+struct Shown(Arc<Mutex<Vec<Notification>>>);
+
+impl Backend for Shown {
+    fn show(&mut self, notification: Notification) {
+        self.0.lock().unwrap().push(notification);
+    }
+}
+
+impl Shown {
+    fn titles(&self) -> Vec<String> {
+        let shown = self.0.lock().unwrap();
+        shown.iter().map(|n| n.title.clone()).collect()
+    }
+}
+
+fn message(source: PeerId, seq: u64, nick: &str, body: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new(nick, body, 0);
+    common::message_event(source, seq, chat.encode())
+}
+
+#[test]
+fn notifications_name_the_sender_and_cut_the_body() {
+    let long = "word ".repeat(100);
+    let mention = Notification::of("bob", &long, Reason::Mention);
+    assert_eq!(mention.title, "bob mentioned you");
+    assert_eq!(mention.body.chars().count(), notify::MAX_BODY_CHARS);
+    assert!(mention.body.ends_with('…'));
+    assert_eq!(
+        Notification::of("carol", "lunch?", Reason::Favorite),
+        Notification {
+            title: "carol".to_string(),
+            body: "lunch?".to_string()
+        }
+    );
+
+    let senders = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    let summary = Notification::summary(1, &senders(&["bob"]));
+    assert_eq!(
+        (summary.title.as_str(), summary.body.as_str()),
+        ("1 more message", "from bob")
+    );
+    let summary = Notification::summary(4, &senders(&["bob", "carol", "dave"]));
+    assert_eq!(summary.body, "from bob, carol and dave");
+    let summary = Notification::summary(9, &senders(&["bob", "carol", "dave", "erin"]));
+    assert_eq!(summary.title, "9 more messages");
+    assert_eq!(summary.body, "from bob, carol, dave and others");
+}
+
+#[test]
+fn platform_scripts_quote_the_text() {
+    let notification = Notification {
+        title: "bob \"the\" builder".to_string(),
+        body: "it's C:\\done".to_string(),
+    };
+    assert_eq!(
+        notify::applescript(&notification),
+        "display notification \"it's C:\\\\done\" with title \"bob \\\"the\\\" builder\""
+    );
+    let script = notify::powershell(&notification);
+    assert!(script.contains("CreateTextNode('bob \"the\" builder')"));
+    assert!(script.contains("CreateTextNode('it''s C:\\done')"));
+    assert!(script.contains("CreateToastNotifier('p2p-chat')"));
+
+    assert_eq!(
+        notify::active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n"),
+        Some(0x3a00007)
+    );
+    assert_eq!(
+        notify::active_window("_NET_ACTIVE_WINDOW:  not found.\n"),
+        None
+    );
+}
+
+#[test]
+fn a_burst_is_summed_up_in_one_notification() {
+    let shown = Shown::default();
+    let window = Duration::from_secs(10);
+    let mut notifier = Notifier::new(Box::new(shown.clone()), window);
+    let start = Instant::now();
+
+    notifier.notify("bob", "hi alice", Reason::Mention, start);
+    for (i, sender) in ["carol", "bob", "carol"].iter().enumerate() {
+        notifier.notify(
+            sender,
+            "alice?",
+            Reason::Mention,
+            start + Duration::from_secs(i as u64),
+        );
+    }
+    notifier.flush(start + Duration::from_secs(5));
+    assert_eq!(
+        shown.titles(),
+        ["bob mentioned you"],
+        "held back during the burst"
+    );
+
+    notifier.flush(start + window);
+    assert_eq!(shown.titles()[1], "3 more messages");
+    assert_eq!(shown.0.lock().unwrap()[1].body, "from carol and bob");
+
+    // The summary starts a window of its own, and a quiet one ends without a notification
+    notifier.notify("dave", "alice!", Reason::Mention, start + window);
+    notifier.flush(start + window * 2);
+    notifier.flush(start + window * 3);
+    assert_eq!(shown.titles().len(), 3);
+    notifier.notify("dave", "alice!!", Reason::Mention, start + window * 3);
+    assert_eq!(shown.titles()[3], "dave mentioned you");
+
+    // Messages held back when do not disturb starts are dropped
+    notifier.notify("erin", "alice", Reason::Mention, start + window * 3);
+    notifier.clear();
+    notifier.flush(start + window * 4);
+    assert_eq!(shown.titles().len(), 4);
+}
+
+#[tokio::test]
+async fn mentions_and_favorites_notify_unless_dnd() {
+    let config = env::temp_dir().join(format!("p2p-chat-notify-{}.json", process::id()));
+    let cli = common::cli(&["--nick", "alice", "--config", config.to_str().unwrap()]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    let shown = Shown::default();
+    // No window, so each message gets a notification of its own
+    node.set_notifier(Notifier::new(Box::new(shown.clone()), Duration::ZERO));
+    let (bob, carol) = (PeerId::random(), PeerId::random());
+
+    node.receive(message(bob, 1, "bob", "anyone around?"));
+    node.receive(message(bob, 2, "bob", "Alice, lunch?"));
+    node.receive(message(carol, 1, "carol", "hello"));
+    node.handle_line(&format!("/contact add {carol} carol"))
+        .await;
+    node.handle_line("/contact fav carol").await;
+    node.receive(message(carol, 2, "carol", "hello again"));
+    assert_eq!(shown.titles(), ["bob mentioned you", "carol"]);
+    assert_eq!(shown.0.lock().unwrap()[1].body, "hello again");
+
+    node.handle_line("/dnd on").await;
+    node.receive(message(bob, 3, "bob", "alice?"));
+    node.receive(message(carol, 3, "carol", "still there?"));
+    assert_eq!(shown.titles().len(), 2);
+    node.flush_writes().await;
+    std::fs::remove_file(config).unwrap();
+}
@@ -0,0 +1,63 @@
+// Private rooms derived from a passphrase.
+mod common;
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    message::ChatMessage,
+    node::{self, DEFAULT_TOPIC_PREFIX},
+    passphrase::{OpenError, RoomKey},
+};
+use libp2p::{gossipsub, PeerId};
+
+fn sealed(node: &ChatNode, key: &RoomKey, seq: u64, body: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new("bob", body, 0);
+    let data = key.seal(&chat.encode());
+    common::message_event_on(node.topic().hash(), PeerId::random(), seq, data)
+}
+
+#[test]
+fn phrases_derive_unguessable_topics_and_keys() {
+    let key = RoomKey::derive("correct horse battery staple");
+    let same = RoomKey::derive("correct horse battery staple");
+    let other = RoomKey::derive("correct horse battery stapler");
+    assert_eq!(key.topic(), same.topic());
+    assert_ne!(key.topic(), other.topic());
+    assert!(key.topic().starts_with("p2p-chat/room/"));
+    assert!(!key.topic().contains("horse"));
+
+    let sealed = key.seal(b"hello");
+    assert_ne!(
+        sealed,
+        key.seal(b"hello"),
+        "every message gets a fresh nonce"
+    );
+    assert!(!sealed.windows(5).any(|w| w == b"hello"));
+    assert_eq!(same.open(&sealed).unwrap(), b"hello");
+    assert_eq!(other.open(&sealed), Err(OpenError::WrongKey));
+    assert_eq!(key.open(b"hello"), Err(OpenError::NotSealed));
+    let mut tampered = sealed.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert_eq!(key.open(&tampered), Err(OpenError::WrongKey));
+}
+
+#[test]
+fn only_messages_sealed_with_the_room_key_are_shown() {
+    let phrase = "meet me at the usual place";
+    let mut node = ChatNode::new(&common::cli(&["--room-pass", phrase])).unwrap();
+    let key = RoomKey::derive(phrase);
+    assert_eq!(
+        node.topic().hash(),
+        node::chat_topic(DEFAULT_TOPIC_PREFIX, key.topic()).hash()
+    );
+    assert_ne!(node.topic().hash(), common::topic().hash());
+
+    node.receive(sealed(&node, &key, 1, "right phrase"));
+    node.receive(sealed(
+        &node,
+        &RoomKey::derive("wrong phrase"),
+        2,
+        "wrong phrase",
+    ));
+    let shown: Vec<_> = node.history().map(|m| &*m.message.body).collect();
+    assert_eq!(shown, ["right phrase"]);
+}
@@ -0,0 +1,49 @@
+// Peer score adjustments from ping round-trip times.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::latency::{PingScorer, FAST_BONUS, MAX_ADJUSTMENT, SLOW_PENALTY};
+use libp2p::PeerId;
+
+#[test]
+fn fast_peers_gain_and_slow_peers_lose_each_minute() {
+    let (fast, slow, middling) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut scorer = PingScorer::default();
+    scorer.record(fast, Duration::from_millis(10));
+    scorer.record(slow, Duration::from_millis(800));
+    scorer.record(middling, Duration::from_millis(200));
+
+    let changed = scorer.minute();
+    assert_eq!(changed.len(), 2);
+    assert!(!changed.contains(&middling));
+    scorer.minute();
+    assert!((scorer.adjustment(&fast) - 2.0 * FAST_BONUS).abs() < 1e-9);
+    assert!((scorer.adjustment(&slow) + 2.0 * SLOW_PENALTY).abs() < 1e-9);
+    assert_eq!(scorer.adjustment(&middling), 0.0);
+
+    // Only the latest round-trip time counts, and adjustments are bounded
+    scorer.record(slow, Duration::from_millis(20));
+    for _ in 0..1000 {
+        scorer.minute();
+    }
+    assert_eq!(scorer.adjustment(&slow), MAX_ADJUSTMENT);
+    assert_eq!(scorer.minute(), [], "nothing changes at the bound");
+
+    scorer.remove(&fast);
+    assert_eq!(scorer.adjustment(&fast), 0.0);
+    assert_eq!(scorer.rtt(&fast), None);
+}
+
+#[tokio::test]
+async fn connected_peers_are_pinged() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let bob_id = bob.local_peer_id();
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.pings().rtt(&bob_id).is_some()
+    })
+    .await;
+    assert!(alice.pings().rtt(&bob_id).unwrap() < Duration::from_millis(500));
+}
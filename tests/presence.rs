@@ -0,0 +1,104 @@
+// Presence heartbeats and last-seen tracking.
+mod common;
+
+use std::{pin::pin, time::Duration};
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    control,
+    presence::{self, Presence, PresenceStatus},
+};
+use libp2p::{futures::StreamExt, PeerId};
+
+#[test]
+fn peers_go_stale_then_offline_by_their_own_interval() {
+    let (alice, bob, carol) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut presence = Presence::new(30);
+    presence.heartbeat("lobby", alice, 10, false, 100);
+    // Bob only sent a chat message, so the default interval applies
+    presence.seen("lobby", bob, 100);
+    presence.heartbeat("lobby", carol, 10, false, 100);
+    presence.depart("lobby", carol, 105);
+
+    let status = |peer, now| {
+        presence
+            .status("lobby", &peer, now)
+            .map(|(status, _)| status)
+    };
+    assert_eq!(status(alice, 119), Some(PresenceStatus::Online));
+    assert_eq!(status(alice, 120), Some(PresenceStatus::Stale));
+    assert_eq!(status(alice, 150), Some(PresenceStatus::Offline));
+    assert_eq!(status(bob, 150), Some(PresenceStatus::Online));
+    assert_eq!(status(carol, 106), Some(PresenceStatus::Offline));
+    assert_eq!(presence.status("other", &alice, 100), None);
+
+    let room = presence.room("lobby", 106);
+    assert_eq!(room[0], (carol, PresenceStatus::Offline, 105));
+    assert_eq!(room.len(), 3);
+}
+
+#[test]
+fn heartbeats_are_jittered_around_the_interval() {
+    for _ in 0..100 {
+        let next = presence::next_heartbeat(1000, 30);
+        assert!((1024..=1036).contains(&next), "{next}");
+    }
+}
+
+#[test]
+fn peers_command_parses() {
+    assert_eq!(commands::parse("/peers"), Some(Ok(UserCommand::Peers)));
+}
+
+#[tokio::test]
+async fn heartbeats_mark_peers_online_and_leaving_marks_them_offline() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) =
+        common::spawn_chat_node(&common::cli(&["--presence-interval", "5"])).await;
+    let bob_id = bob.local_peer_id();
+    let room = common::topic().hash().into_string();
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| {
+            common::has_subscriber(alice, &control) && common::has_subscriber(bob, &control)
+        },
+    )
+    .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.presence().status(&room, &bob_id, u64::MAX).is_some()
+    })
+    .await;
+    let now = concurrent_chat_server::clock::unix_time();
+    assert!(matches!(
+        alice.presence().status(&room, &bob_id, now),
+        Some((PresenceStatus::Online, _))
+    ));
+    assert_eq!(
+        alice.history().count(),
+        0,
+        "heartbeats are not chat messages"
+    );
+
+    {
+        // Alice reads on while bob leaves, as a running node would, so his leave isn't lost
+        // behind the closed connection
+        let mut shutdown = pin!(bob.shutdown());
+        loop {
+            tokio::select! {
+                () = &mut shutdown => break,
+                event = alice.swarm.select_next_some() => alice.handle_event(event),
+            }
+        }
+    }
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        matches!(
+            alice.presence().status(&room, &bob_id, now),
+            Some((PresenceStatus::Offline, _))
+        )
+    })
+    .await;
+}
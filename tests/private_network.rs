@@ -0,0 +1,65 @@
+// Private networks: only nodes holding the same swarm key can talk to each other.
+mod common;
+
+use std::{path::PathBuf, time::Duration};
+
+use concurrent_chat_server::psk;
+use libp2p::{
+    futures::StreamExt,
+    swarm::{DialError, SwarmEvent},
+};
+
+/// Write a fresh swarm key to a unique temporary file.
+fn temp_key(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("p2p-chat-{name}-{}.key", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    psk::write_new(&path).unwrap();
+    path
+}
+
+#[tokio::test]
+async fn nodes_sharing_a_key_exchange_messages() {
+    let key = temp_key("shared");
+    let key = key.to_str().unwrap();
+    let (mut dialer, _) = common::spawn_node(&common::cli(&["--swarm-key", key])).await;
+    let (mut listener, addr) = common::spawn_node(&common::cli(&["--swarm-key", key])).await;
+
+    let received =
+        common::publish_and_receive(&mut dialer, &mut listener, addr, b"private hello").await;
+    assert_eq!(received, b"private hello");
+}
+
+#[tokio::test]
+async fn mismatched_keys_fail_fast_with_psk_mismatch() {
+    let dialer_key = temp_key("dialer");
+    let listener_key = temp_key("listener");
+    let (mut dialer, _) =
+        common::spawn_node(&common::cli(&["--swarm-key", dialer_key.to_str().unwrap()])).await;
+    let (mut listener, addr) = common::spawn_node(&common::cli(&[
+        "--swarm-key",
+        listener_key.to_str().unwrap(),
+    ]))
+    .await;
+    dialer.dial(addr).unwrap();
+
+    let error = tokio::time::timeout(Duration::from_secs(8), async {
+        loop {
+            tokio::select! {
+                event = dialer.select_next_some() => {
+                    if let SwarmEvent::OutgoingConnectionError { error, .. } = event {
+                        return error;
+                    }
+                }
+                _ = listener.select_next_some() => {}
+            }
+        }
+    })
+    .await
+    .expect("dial fails instead of hanging");
+
+    // The mismatch must be spelled out in what the user sees, not buried in a protocol error.
+    assert!(
+        matches!(error, DialError::Transport(_)) && error.to_string().contains("PSK mismatch"),
+        "expected a PSK mismatch, got: {error}"
+    );
+}
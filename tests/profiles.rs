@@ -0,0 +1,123 @@
+// Profiles peers publish about themselves, and how they are checked and shown.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, ProfileCommand, UserCommand},
+    control,
+    profile::{self, Profile, ProfileField, MAX_BIO_CHARS},
+};
+
+#[test]
+fn fields_are_bounded_and_sanitized() {
+    let mut profile = Profile::default();
+    assert!(profile.is_empty());
+    profile.set(ProfileField::Bio, "likes\nboats").unwrap();
+    assert_eq!(profile.bio, "likes boats");
+    profile
+        .set(ProfileField::DisplayName, "Al\u{202e}ice")
+        .unwrap();
+    assert_eq!(profile.display_name, "Alice");
+    assert!(profile
+        .set(ProfileField::Bio, &"x".repeat(MAX_BIO_CHARS + 1))
+        .is_err());
+    assert!(profile.set(ProfileField::Avatar, "not a hash").is_err());
+
+    // Received profiles are checked as a whole, then sanitized for display
+    let received = Profile {
+        pronouns: "they/\u{1b}[31mthem".to_string(),
+        avatar: Some("AB".repeat(32)),
+        ..Profile::default()
+    };
+    assert_eq!(received.check(), Ok(()));
+    let shown = received.sanitized();
+    assert!(!shown.pronouns.contains('\u{1b}'));
+    assert_eq!(shown.avatar, Some("ab".repeat(32)));
+    let too_long = Profile {
+        bio: "x".repeat(MAX_BIO_CHARS + 1),
+        ..Profile::default()
+    };
+    assert!(too_long.check().is_err());
+}
+
+#[test]
+fn profile_commands_parse() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(
+        parse("/profile"),
+        Ok(UserCommand::Profile(ProfileCommand::Show(None)))
+    );
+    assert_eq!(
+        parse("/profile show alice"),
+        Ok(UserCommand::Profile(ProfileCommand::Show(Some(
+            "alice".to_string()
+        ))))
+    );
+    assert_eq!(
+        parse("/profile set bio \"sails, mostly\""),
+        Ok(UserCommand::Profile(ProfileCommand::Set {
+            field: ProfileField::Bio,
+            value: "sails, mostly".to_string()
+        }))
+    );
+    assert_eq!(
+        parse("/profile clear pronouns"),
+        Ok(UserCommand::Profile(ProfileCommand::Clear(
+            ProfileField::Pronouns
+        )))
+    );
+    assert!(parse("/profile set age 30").is_err());
+    assert!(parse("/profile set bio").is_err());
+}
+
+#[tokio::test]
+async fn profiles_reach_peers_on_joining_and_persist() {
+    let dir = env::temp_dir().join(format!("p2p-chat-profile-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.json");
+    let avatar = dir.join("avatar.png");
+    fs::write(&avatar, b"not really a png").unwrap();
+    let alice_cli = common::cli(&["--config", config.to_str().unwrap()]);
+    let (mut alice, _) = common::spawn_chat_node(&alice_cli).await;
+    alice.handle_line("/profile set name Alice A.").await;
+    alice
+        .handle_line("/profile set bio \"sails, mostly\"")
+        .await;
+    alice
+        .handle_line(&format!("/profile set avatar {}", avatar.display()))
+        .await;
+    let alice_id = alice.local_peer_id();
+
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &control)
+    })
+    .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.profile(&alice_id).is_some()
+    })
+    .await;
+    let received = bob.profile(&alice_id).unwrap();
+    assert_eq!(received.display_name, "Alice A.");
+    assert_eq!(received.bio, "sails, mostly");
+    assert_eq!(
+        received.avatar,
+        Some(profile::avatar_hash(&avatar).unwrap())
+    );
+
+    // Clearing a field is sent right away
+    alice.handle_line("/profile clear bio").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.profile(&alice_id).is_some_and(|p| p.bio.is_empty())
+    })
+    .await;
+
+    let restarted = ChatNode::new(&alice_cli).unwrap();
+    let own = restarted.profile(&restarted.local_peer_id()).unwrap();
+    assert_eq!(own.display_name, "Alice A.");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,191 @@
+// Outbound connections through a SOCKS5 proxy given with `--proxy`.
+mod common;
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use concurrent_chat_server::{
+    error::{self, DialError},
+    proxy::{self, Target},
+};
+use libp2p::{futures::StreamExt, multiaddr::Protocol, Multiaddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// A SOCKS5 proxy without authentication, answering every request with `reply`, and
+/// connecting through when that is success. Returns its address and the targets asked for.
+async fn socks_proxy(reply: u8) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let targets = Arc::new(Mutex::new(Vec::new()));
+    let seen = targets.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let seen = seen.clone();
+            tokio::spawn(async move {
+                let mut greeting = [0; 2];
+                client.read_exact(&mut greeting).await?;
+                let mut methods = vec![0; greeting[1].into()];
+                client.read_exact(&mut methods).await?;
+                client.write_all(&[5, 0]).await?;
+
+                let mut request = [0; 4];
+                client.read_exact(&mut request).await?;
+                let host = match request[3] {
+                    1 => {
+                        let mut ip = [0; 4];
+                        client.read_exact(&mut ip).await?;
+                        std::net::Ipv4Addr::from(ip).to_string()
+                    }
+                    3 => {
+                        let len = client.read_u8().await?;
+                        let mut name = vec![0; len.into()];
+                        client.read_exact(&mut name).await?;
+                        String::from_utf8(name).unwrap()
+                    }
+                    atyp => panic!("unexpected address type {atyp}"),
+                };
+                let port = client.read_u16().await?;
+                let target = format!("{host}:{port}");
+                seen.lock().unwrap().push(target.clone());
+                client
+                    .write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0])
+                    .await?;
+                if reply == 0 {
+                    let mut server = TcpStream::connect(target).await?;
+                    tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+                }
+                Ok::<_, std::io::Error>(())
+            });
+        }
+    });
+    (address, targets)
+}
+
+#[tokio::test]
+async fn dials_go_through_the_proxy_which_resolves_names() {
+    let (proxy, targets) = socks_proxy(0).await;
+    let url = format!("socks5://{proxy}");
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--proxy", &url])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut carol, carol_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let Some(Protocol::Tcp(carol_port)) = carol_addr.iter().nth(1) else {
+        panic!("carol listens on TCP");
+    };
+    let bob_addr = bob_addr.with(Protocol::P2p(bob.local_peer_id()));
+    // A name only the proxy looks up
+    let carol_addr: Multiaddr = format!(
+        "/dns4/localhost/tcp/{carol_port}/p2p/{}",
+        carol.local_peer_id()
+    )
+    .parse()
+    .unwrap();
+
+    tokio::select! {
+        result = async {
+            alice.connect_to(bob_addr.clone()).await?;
+            alice.connect_to(carol_addr).await
+        } => result.unwrap(),
+        _ = async { loop { bob.swarm.select_next_some().await; } } => unreachable!(),
+        _ = async { loop { carol.swarm.select_next_some().await; } } => unreachable!(),
+    }
+    assert!(alice.swarm.is_connected(&bob.local_peer_id()));
+    assert!(alice.swarm.is_connected(&carol.local_peer_id()));
+    let Some(Protocol::Tcp(bob_port)) = bob_addr.iter().nth(1) else {
+        panic!("bob listens on TCP");
+    };
+    assert_eq!(
+        *targets.lock().unwrap(),
+        [
+            format!("127.0.0.1:{bob_port}"),
+            format!("localhost:{carol_port}")
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failures_say_whether_the_proxy_or_the_destination_failed() {
+    let destination: Multiaddr = "/ip4/127.0.0.1/tcp/9".parse().unwrap();
+    let describe = |result| match result {
+        Err(DialError::Swarm(e)) => error::describe_dial_error(&e),
+        other => panic!("the dial should fail, not {other:?}"),
+    };
+
+    // Nothing listening where the proxy should be
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = closed.local_addr().unwrap().port();
+    drop(closed);
+    let url = format!("socks5://127.0.0.1:{port}");
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--proxy", &url])).await;
+    let reason = describe(alice.connect_to(destination.clone()).await);
+    assert!(
+        reason.contains(&format!("cannot reach the proxy at 127.0.0.1:{port}")),
+        "{reason}"
+    );
+
+    // The proxy is there, but the destination refuses it
+    let (proxy, _) = socks_proxy(5).await;
+    let url = format!("socks5://{proxy}");
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--proxy", &url])).await;
+    let reason = describe(alice.connect_to(destination.clone()).await);
+    assert_eq!(
+        reason,
+        format!(
+            "{destination}: 127.0.0.1:9 is not reachable through the proxy: connection refused"
+        )
+    );
+
+    // The proxy won't connect anywhere
+    let (proxy, _) = socks_proxy(2).await;
+    let url = format!("socks5://{proxy}");
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--proxy", &url])).await;
+    let reason = describe(alice.connect_to(destination.clone()).await);
+    assert_eq!(
+        reason,
+        format!(
+            "{destination}: the proxy at {proxy} refused to connect to 127.0.0.1:9: not allowed \
+             by its rules"
+        )
+    );
+}
+
+#[test]
+fn proxy_urls_and_targets_parse() {
+    let tor = proxy::proxy_url("socks5h://circuit:x@127.0.0.1:9050").unwrap();
+    assert_eq!(tor.address, "127.0.0.1:9050".parse().unwrap());
+    assert_eq!(
+        tor.credentials,
+        Some(("circuit".to_string(), "x".to_string()))
+    );
+    let local = proxy::proxy_url("socks5://[::1]:1080").unwrap();
+    assert_eq!(local.address, "[::1]:1080".parse().unwrap());
+    assert_eq!(local.credentials, None);
+    assert!(proxy::proxy_url("http://127.0.0.1:8080").is_err());
+    assert!(proxy::proxy_url("socks5://127.0.0.1").is_err());
+
+    let target = |addr: &str| Target::from_multiaddr(&addr.parse().unwrap());
+    assert_eq!(
+        target("/dns/example.org/tcp/4001"),
+        Some(Target::Domain("example.org".to_string(), 4001))
+    );
+    let onion = "/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:1234";
+    assert_eq!(
+        target(onion),
+        Some(Target::Domain(
+            "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion".to_string(),
+            1234
+        ))
+    );
+    // QUIC can't go through the proxy, and neither can a relay circuit's second hop
+    assert_eq!(target("/ip4/127.0.0.1/udp/4001/quic-v1"), None);
+    let relayed = format!(
+        "/ip4/127.0.0.1/tcp/4001/p2p/{}/p2p-circuit",
+        libp2p::PeerId::random()
+    );
+    assert_eq!(target(&relayed), None);
+}
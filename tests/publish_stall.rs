@@ -0,0 +1,139 @@
+// Receipts for published chat messages, and the warning when they stop coming.
+mod common;
+
+use std::time::{Duration, Instant};
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    control,
+    stall::{PublishDiagnosis, StallWatch, RECEIPT_WINDOW, STALL_MESSAGES},
+};
+use libp2p::gossipsub::MessageId;
+
+fn id(n: usize) -> MessageId {
+    MessageId::from(format!("message {n}"))
+}
+
+fn diagnosis(connected: usize, mesh: usize, stale: usize, unconfirmed: usize) -> PublishDiagnosis {
+    PublishDiagnosis {
+        connected,
+        subscribed: connected,
+        mesh,
+        stale,
+        unconfirmed,
+        last_receipt: None,
+    }
+}
+
+#[test]
+fn unconfirmed_messages_in_a_row_warn_once() {
+    let start = Instant::now();
+    let mut watch = StallWatch::default();
+    for n in 0..STALL_MESSAGES {
+        watch.sent(&id(n), start);
+    }
+    // Still within their window
+    assert!(!watch.check(start + RECEIPT_WINDOW / 2, 3));
+    assert_eq!(watch.awaiting(), STALL_MESSAGES);
+
+    let later = start + RECEIPT_WINDOW;
+    assert!(watch.check(later, 3));
+    assert!(watch.is_stalled());
+    assert_eq!(watch.unconfirmed(), STALL_MESSAGES);
+    watch.sent(&id(STALL_MESSAGES), later);
+    assert!(!watch.check(later + RECEIPT_WINDOW, 3));
+
+    // A receipt ends the stall, and the next one warns again
+    watch.sent(&id(10), later);
+    assert!(watch.confirmed(&[id(10).to_string()], later));
+    assert!(!watch.is_stalled());
+    assert_eq!(watch.last_receipt(), Some(later));
+    for n in 11..11 + STALL_MESSAGES {
+        watch.sent(&id(n), later);
+    }
+    assert!(watch.check(later + RECEIPT_WINDOW, 3));
+}
+
+#[test]
+fn no_peers_means_no_stall_warning() {
+    let start = Instant::now();
+    let mut watch = StallWatch::default();
+    for n in 0..STALL_MESSAGES {
+        watch.sent(&id(n), start);
+    }
+    assert!(!watch.check(start + RECEIPT_WINDOW, 0));
+    // Once someone connects, the stall is reported
+    assert!(watch.check(start + RECEIPT_WINDOW, 1));
+}
+
+#[test]
+fn receipts_for_other_messages_change_nothing() {
+    let now = Instant::now();
+    let mut watch = StallWatch::default();
+    watch.sent(&id(1), now);
+    assert!(!watch.confirmed(&["someone else's".to_string()], now));
+    assert_eq!(watch.last_receipt(), None);
+    assert_eq!(watch.awaiting(), 1);
+}
+
+#[test]
+fn the_diagnosis_names_what_is_wrong() {
+    assert_eq!(
+        diagnosis(3, 0, 0, 3).problem().as_deref(),
+        Some("3 peers connected but none in mesh")
+    );
+    assert_eq!(
+        diagnosis(0, 0, 0, 3).problem().as_deref(),
+        Some("no peers connected")
+    );
+    assert_eq!(
+        diagnosis(3, 2, 2, 0).problem().as_deref(),
+        Some("all 2 peers in mesh stopped answering")
+    );
+    assert_eq!(
+        diagnosis(3, 2, 0, 4).problem().as_deref(),
+        Some("2 peers in mesh but none confirmed the last 4 messages")
+    );
+    let healthy = diagnosis(3, 2, 1, 0);
+    assert_eq!(healthy.problem(), None);
+    assert!(healthy
+        .to_string()
+        .ends_with("[doctor] publishing looks healthy"));
+    assert_eq!(
+        commands::parse("/doctor publish"),
+        Some(Ok(UserCommand::DoctorPublish))
+    );
+    assert!(commands::parse("/doctor mesh").unwrap().is_err());
+}
+
+#[tokio::test]
+async fn receivers_confirm_messages_to_their_author() {
+    let quiet = common::cli(&["--presence-interval", "0"]);
+    let (mut alice, _) = common::spawn_chat_node(&quiet).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&quiet).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let (topic, control) = (alice.topic().clone(), control::control_topic());
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &control),
+    )
+    .await;
+
+    alice.handle_line("hello").await;
+    assert_eq!(alice.stall().awaiting(), 1);
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+    bob.tick();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.stall().last_receipt().is_some()
+    })
+    .await;
+    assert_eq!(alice.stall().awaiting(), 0);
+    let diagnosis = alice.diagnose_publish();
+    assert_eq!((diagnosis.connected, diagnosis.subscribed), (1, 1));
+    assert_eq!(diagnosis.problem(), None);
+}
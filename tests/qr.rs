@@ -0,0 +1,101 @@
+// Invites drawn as QR codes in the terminal.
+mod common;
+
+use std::{env, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, UserCommand},
+    invite::{self, DEFAULT_TTL},
+    qr::{self, QrCode},
+};
+
+#[test]
+fn codes_take_the_smallest_version_that_holds_the_text() {
+    // Alphanumeric text packs denser than bytes
+    assert_eq!(QrCode::encode("HELLO WORLD", 40).unwrap().version(), 1);
+    assert_eq!(QrCode::encode(&"A".repeat(25), 40).unwrap().version(), 1);
+    assert_eq!(QrCode::encode(&"A".repeat(26), 40).unwrap().version(), 2);
+    assert_eq!(QrCode::encode(&"a".repeat(17), 40).unwrap().version(), 1);
+    assert_eq!(QrCode::encode(&"a".repeat(18), 40).unwrap().version(), 2);
+    assert_eq!(QrCode::encode(&"A".repeat(4296), 40).unwrap().version(), 40);
+    assert!(QrCode::encode(&"A".repeat(4297), 40).is_none());
+    assert!(QrCode::encode(&"A".repeat(26), 1).is_none());
+
+    // Versions grow by four modules a side, and the quiet zone adds four columns
+    assert_eq!(qr::max_version(80), 14);
+    assert_eq!(qr::max_version(20), 0);
+    let code = QrCode::encode(&"A".repeat(600), qr::max_version(80)).unwrap();
+    assert_eq!(code.size(), code.version() * 4 + 17);
+    assert!(code.width() <= 80);
+}
+
+#[test]
+fn codes_are_drawn_two_rows_to_a_line() {
+    let code = QrCode::encode("HELLO WORLD", 40).unwrap();
+    // Finder patterns in three corners, with the separator around them light
+    for (x, y) in [(0, 0), (code.size() - 7, 0), (0, code.size() - 7)] {
+        assert!(code.is_dark(x, y) && code.is_dark(x + 6, y + 6) && code.is_dark(x + 3, y + 3));
+        assert!(!code.is_dark(x + 1, y + 3) && !code.is_dark(x + 5, y + 3));
+    }
+    assert!(!code.is_dark(7, 7));
+    // The module next to the bottom left finder is always dark
+    assert!(code.is_dark(8, code.size() - 8));
+
+    let drawn = code.render();
+    let lines: Vec<&str> = drawn.lines().collect();
+    assert_eq!(code.width(), 25);
+    assert_eq!(lines.len(), 13);
+    assert!(lines.iter().all(|line| line.chars().count() == 25));
+    // Light modules are blocks: the quiet zone, then the top of the top left finder pattern
+    assert_eq!(lines[0], "█".repeat(25));
+    assert!(lines[1].starts_with("██ ▄▄▄▄▄ █"));
+    assert_eq!(lines[12], "▀".repeat(25));
+}
+
+#[tokio::test]
+async fn invites_fit_a_code_for_an_80_column_terminal() {
+    let config = |name: &str| {
+        env::temp_dir()
+            .join(format!("p2p-chat-qr-{name}-{}.json", process::id()))
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+    let (alice_config, bob_config) = (config("alice"), config("bob"));
+    let cli = common::cli(&["--config", &alice_config]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    let token = node.create_invite(DEFAULT_TTL, None).unwrap();
+    let signed = invite::decode_token(&token).unwrap();
+
+    // The QR form is upper case base32, shorter than the hex token, and decodes the same
+    let qr_token = invite::encode_qr_token(&signed);
+    assert!(qr_token.starts_with("P2PCHAT-INVITE:"));
+    assert!(qr_token.len() < token.len());
+    assert_eq!(invite::decode_token(&qr_token), Ok(signed));
+    assert!(invite::decode_token("P2PCHAT-INVITE:not-base32").is_err());
+    let code = QrCode::encode(&qr_token, qr::max_version(80)).unwrap();
+    assert!(code.width() <= 80);
+
+    // Either form joins the room
+    let cli = common::cli(&["--config", &bob_config, "--join-with", &qr_token]);
+    let mut joined = ChatNode::new(&cli).unwrap();
+    assert_eq!(joined.topic().hash(), node.topic().hash());
+    node.flush_writes().await;
+    joined.flush_writes().await;
+    std::fs::remove_file(alice_config).unwrap();
+    std::fs::remove_file(bob_config).unwrap();
+}
+
+#[test]
+fn invite_qr_parses() {
+    assert_eq!(
+        commands::parse("/invite qr 2h"),
+        Some(Ok(UserCommand::InviteCreate {
+            ttl: 7200,
+            invitee: None,
+            qr: true,
+        }))
+    );
+    assert!(commands::parse("/invite show").unwrap().is_err());
+}
@@ -0,0 +1,381 @@
+// Room bans decided by a quorum of moderators, each signing the proposal, with the signatures
+// put together in one decision.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    clock,
+    commands::{self, UserCommand},
+    control::{self, ControlMessage, SignedControl},
+    decision::{self, AggregateDecision, DecisionError, Decisions, PartialSig},
+    identity,
+    room::{ModAction, Moderation},
+};
+use libp2p::{gossipsub, identity::Keypair, PeerId};
+
+fn ban(target: PeerId) -> Moderation {
+    Moderation {
+        action: ModAction::RoomBan,
+        room: common::topic().hash().into_string(),
+        target,
+        reason: "spam".to_string(),
+        timestamp: clock::unix_time(),
+    }
+}
+
+// A decision on `moderation` signed by all of `moderators`.
+fn decide(moderation: &Moderation, moderators: &[&Keypair]) -> ControlMessage {
+    let hash = decision::proposal_hash(moderation);
+    let signatures: Vec<_> = moderators
+        .iter()
+        .map(|keypair| {
+            let sig = PartialSig::sign(keypair, hash).unwrap().sig;
+            (keypair.public().to_peer_id(), sig)
+        })
+        .collect();
+    ControlMessage::Decision {
+        moderation: moderation.clone(),
+        decision: AggregateDecision::aggregate(hash, &signatures),
+    }
+}
+
+// `message` published on the control topic by `author`.
+fn control(author: &Keypair, message: &ControlMessage) -> gossipsub::Event {
+    let source = author.public().to_peer_id();
+    let signed = SignedControl::sign(author, message).unwrap();
+    let data = serde_json::to_vec(&signed).unwrap();
+    common::message_event_on(control::control_topic().hash(), source, 1, data)
+}
+
+fn config(name: &str) -> String {
+    env::temp_dir()
+        .join(format!("p2p-chat-quorum-{name}-{}.json", process::id()))
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn decisions_hold_a_quorum_of_moderators_signatures() {
+    let keys: Vec<Keypair> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+    let moderators: Vec<PeerId> = keys.iter().map(|k| k.public().to_peer_id()).collect();
+    let moderation = ban(PeerId::random());
+    let hash = decision::proposal_hash(&moderation);
+    let signature = |i: usize| {
+        let partial = PartialSig::sign(&keys[i], hash).unwrap();
+        partial.verify(&moderators[i]).unwrap();
+        (moderators[i], partial.sig)
+    };
+
+    let decision = AggregateDecision::aggregate(hash, &[signature(0), signature(2)]);
+    assert_eq!(decision.verify(&moderation, &moderators, 2), Ok(()));
+    assert_eq!(
+        decision.verify(&moderation, &moderators, 3),
+        Err(DecisionError::TooFewSigners {
+            signers: 2,
+            quorum: 3
+        })
+    );
+    assert_eq!(
+        decision.verify(&ban(PeerId::random()), &moderators, 2),
+        Err(DecisionError::WrongProposal)
+    );
+    assert_eq!(
+        decision.verify(&moderation, &moderators[..2], 2),
+        Err(DecisionError::NotModerator(moderators[2]))
+    );
+
+    // One moderator signing twice doesn't make a quorum
+    let twice = AggregateDecision::aggregate(hash, &[signature(1), signature(1)]);
+    assert_eq!(
+        twice.verify(&moderation, &moderators, 2),
+        Err(DecisionError::DuplicateSigner(moderators[1]))
+    );
+    // Signers claimed without their signature, or with someone else's
+    let mut forged = decision.clone();
+    forged.signers = vec![moderators[0], moderators[1]];
+    assert_eq!(
+        forged.verify(&moderation, &moderators, 2),
+        Err(DecisionError::BadSignature(moderators[1]))
+    );
+    let mut short = decision.clone();
+    short.agg_sig.truncate(70);
+    assert_eq!(
+        short.verify(&moderation, &moderators, 2),
+        Err(DecisionError::Malformed)
+    );
+}
+
+#[test]
+fn proposals_collect_signatures_until_decided() {
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let (alice_id, bob_id) = (alice.public().to_peer_id(), bob.public().to_peer_id());
+    let moderation = ban(PeerId::random());
+    let mut decisions = Decisions::default();
+    let hash = decisions.propose(alice_id, moderation.clone()).unwrap();
+    let id = decision::short_id(&hash);
+    assert_eq!(decisions.find(&id), Some(hash));
+    assert_eq!(decisions.find(&id.to_uppercase()), Some(hash));
+    assert_eq!(decisions.find("zz"), None);
+
+    let partial = PartialSig::sign(&alice, hash).unwrap();
+    assert_eq!(decisions.sign(alice_id, &partial), Ok(1));
+    assert_eq!(decisions.sign(alice_id, &partial), Ok(1));
+    // A signature only counts for its signer
+    assert_eq!(
+        decisions.sign(bob_id, &partial),
+        Err(DecisionError::BadSignature(bob_id))
+    );
+    let partial = PartialSig::sign(&bob, hash).unwrap();
+    assert_eq!(decisions.sign(bob_id, &partial), Ok(2));
+
+    let pending = decisions.decide(hash).unwrap();
+    assert_eq!(pending.proposer, alice_id);
+    assert!(decisions.is_decided(&hash));
+    assert_eq!(
+        decisions.sign(bob_id, &partial),
+        Err(DecisionError::UnknownProposal)
+    );
+
+    // Past the limit, only the oldest decided proposal is forgotten
+    let hashes: Vec<[u8; 32]> = (0..decision::MAX_DECIDED as u32)
+        .map(|n| {
+            let mut hash = [0; 32];
+            hash[..4].copy_from_slice(&n.to_be_bytes());
+            hash
+        })
+        .collect();
+    for hash in &hashes {
+        decisions.decide(*hash);
+    }
+    assert!(!decisions.is_decided(&hash));
+    assert!(decisions.is_decided(&hashes[0]));
+    decisions.decide(hashes[0]);
+    assert!(decisions.is_decided(&hashes[1]));
+}
+
+#[test]
+fn old_actions_and_those_before_a_lift_are_stale() {
+    let now = clock::unix_time();
+    let moderation = ban(PeerId::random());
+    assert_eq!(decision::check_fresh(&moderation, None, now), Ok(()));
+    assert_eq!(
+        decision::check_fresh(&moderation, None, now + decision::MAX_AGE + 1),
+        Err(DecisionError::Stale)
+    );
+    assert_eq!(
+        decision::check_fresh(&moderation, Some(moderation.timestamp), now),
+        Err(DecisionError::Stale)
+    );
+    assert_eq!(
+        decision::check_fresh(&moderation, Some(moderation.timestamp - 1), now),
+        Ok(())
+    );
+}
+
+#[test]
+fn proposals_that_can_no_longer_be_honored_make_room_for_new_ones() {
+    let alice = PeerId::random();
+    let now = clock::unix_time();
+    let mut decisions = Decisions::default();
+    let old = |n| Moderation {
+        timestamp: now - decision::MAX_AGE - 1 - n,
+        ..ban(PeerId::random())
+    };
+    for n in 0..decision::MAX_PENDING as u64 {
+        decisions.propose(alice, old(n)).unwrap();
+    }
+    let fresh = ban(PeerId::random());
+    assert_eq!(
+        decisions.propose(alice, fresh.clone()),
+        Err(DecisionError::Full)
+    );
+    assert_eq!(decisions.expire(now, |_| None), decision::MAX_PENDING);
+    let hash = decisions.propose(alice, fresh.clone()).unwrap();
+
+    // Nor is a ban on someone let back in since it was proposed
+    let lifted = ban(PeerId::random());
+    let lifted_hash = decisions.propose(alice, lifted.clone()).unwrap();
+    let lifted_at =
+        |moderation: &Moderation| (moderation.target == lifted.target).then_some(lifted.timestamp);
+    assert_eq!(decisions.expire(now, lifted_at), 1);
+    assert!(decisions.get(&lifted_hash).is_none());
+    assert_eq!(decisions.get(&hash).unwrap().moderation, fresh);
+}
+
+#[test]
+fn quorum_commands_parse() {
+    assert_eq!(
+        commands::parse("/quorum"),
+        Some(Ok(UserCommand::Quorum(None)))
+    );
+    assert_eq!(
+        commands::parse("/quorum 2"),
+        Some(Ok(UserCommand::Quorum(Some(2))))
+    );
+    assert_eq!(
+        commands::parse("/quorum off"),
+        Some(Ok(UserCommand::Quorum(Some(1))))
+    );
+    assert!(commands::parse("/quorum 0").unwrap().is_err());
+    assert_eq!(
+        commands::parse("/approve 1a2b3c4d"),
+        Some(Ok(UserCommand::Approve("1a2b3c4d".to_string())))
+    );
+    assert!(commands::parse("/approve").unwrap().is_err());
+    assert_eq!(
+        commands::parse("/proposals"),
+        Some(Ok(UserCommand::Proposals))
+    );
+}
+
+#[tokio::test]
+async fn members_only_honor_bans_a_quorum_signed() {
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let (alice_id, bob_id) = (alice.public().to_peer_id(), bob.public().to_peer_id());
+    let carol_config = config("carol");
+    let cli = common::cli(&[
+        "--config",
+        &carol_config,
+        "--moderator",
+        &alice_id.to_string(),
+        "--moderator",
+        &bob_id.to_string(),
+    ]);
+    let mut carol = ChatNode::new(&cli).unwrap();
+    carol.handle_line("/quorum 2").await;
+
+    // One moderator's ban isn't enough any more, nor is a decision only that one signed
+    let spammer = PeerId::random();
+    let moderation = ban(spammer);
+    carol.receive(control(
+        &alice,
+        &ControlMessage::Moderation(moderation.clone()),
+    ));
+    let hash = decision::proposal_hash(&moderation);
+    let alice_sig = PartialSig::sign(&alice, hash).unwrap();
+    let alone = AggregateDecision::aggregate(hash, &[(alice_id, alice_sig.sig.clone())]);
+    let message = ControlMessage::Decision {
+        moderation: moderation.clone(),
+        decision: alone,
+    };
+    carol.receive(control(&alice, &message));
+    assert!(!carol.is_removed(&spammer));
+
+    let bob_sig = PartialSig::sign(&bob, hash).unwrap();
+    let decision =
+        AggregateDecision::aggregate(hash, &[(alice_id, alice_sig.sig), (bob_id, bob_sig.sig)]);
+    let message = ControlMessage::Decision {
+        moderation,
+        decision,
+    };
+    carol.receive(control(&bob, &message));
+    assert!(carol.is_removed(&spammer));
+    carol.flush_writes().await;
+    fs::remove_file(carol_config).unwrap();
+}
+
+#[tokio::test]
+async fn a_ban_waits_for_a_second_moderator() {
+    // Identities made up front, so each node can name both as moderators
+    let identity = |name: &str| {
+        let path = env::temp_dir().join(format!("p2p-chat-quorum-{name}-{}.key", process::id()));
+        let _ = fs::remove_file(&path);
+        let peer = identity::load_or_create(&path)
+            .unwrap()
+            .public()
+            .to_peer_id();
+        (path.to_str().unwrap().to_string(), peer.to_string())
+    };
+    let (alice_key, alice_id) = identity("alice");
+    let (bob_key, bob_id) = identity("bob");
+    let (alice_config, bob_config) = (config("alice"), config("bob"));
+    let cli = |key: &str, config: &str| {
+        common::cli(&[
+            "--identity",
+            key,
+            "--config",
+            config,
+            "--moderator",
+            &alice_id,
+            "--moderator",
+            &bob_id,
+        ])
+    };
+    let (mut alice, _) = common::spawn_chat_node(&cli(&alice_key, &alice_config)).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&cli(&bob_key, &bob_config)).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+    alice.handle_line("/quorum 2").await;
+    bob.handle_line("/quorum 2").await;
+
+    let spammer = PeerId::random();
+    alice
+        .handle_line(&format!("/roomban {spammer} flooding"))
+        .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, b| {
+        b.proposals().next().is_some()
+    })
+    .await;
+    assert!(!alice.is_removed(&spammer) && !bob.is_removed(&spammer));
+
+    let hash = decision::proposal_hash(bob.proposals().next().unwrap());
+    bob.handle_line(&format!("/approve {}", decision::short_id(&hash)))
+        .await;
+    assert!(bob.is_removed(&spammer));
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, _| {
+        a.is_removed(&spammer)
+    })
+    .await;
+    alice.flush_writes().await;
+    bob.flush_writes().await;
+    for path in [alice_key, bob_key, alice_config, bob_config] {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn decisions_are_not_replayed_after_a_ban_is_lifted() {
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let (alice_id, bob_id) = (alice.public().to_peer_id(), bob.public().to_peer_id());
+    let carol_config = config("replay");
+    let _ = fs::remove_file(&carol_config);
+    let cli = common::cli(&[
+        "--config",
+        &carol_config,
+        "--moderator",
+        &alice_id.to_string(),
+        "--moderator",
+        &bob_id.to_string(),
+    ]);
+    let mut carol = ChatNode::new(&cli).unwrap();
+    carol.handle_line("/quorum 2").await;
+
+    // Too old to honor
+    let spammer = PeerId::random();
+    let mut old = ban(spammer);
+    old.timestamp -= decision::MAX_AGE + 1;
+    carol.receive(control(&bob, &decide(&old, &[&alice, &bob])));
+    assert!(!carol.is_removed(&spammer));
+
+    let decision = decide(&ban(spammer), &[&alice, &bob]);
+    carol.receive(control(&bob, &decision));
+    assert!(carol.is_removed(&spammer));
+    carol.handle_line(&format!("/bans remove {spammer}")).await;
+    assert!(!carol.is_removed(&spammer));
+    carol.flush_writes().await;
+    drop(carol);
+
+    // The lift outlasts a restart, which forgets what was decided
+    let mut carol = ChatNode::new(&cli).unwrap();
+    carol.receive(control(&bob, &decision));
+    assert!(!carol.is_removed(&spammer));
+    carol.flush_writes().await;
+    fs::remove_file(carol_config).unwrap();
+}
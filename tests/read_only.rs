@@ -0,0 +1,67 @@
+// Observers started with `--no-publish`, which receive and relay but never publish.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{error::ChatError, message::ChatMessage};
+use libp2p::futures::StreamExt;
+
+fn message(body: &str) -> ChatMessage {
+    ChatMessage::new("bob", body, 1)
+}
+
+#[tokio::test]
+async fn read_only_nodes_refuse_to_publish() {
+    let (mut node, _) = common::spawn_chat_node(&common::cli(&["--no-publish"])).await;
+    assert!(node.is_read_only());
+    assert!(matches!(
+        node.publish(&message("hi")),
+        Err(ChatError::ReadOnlyMode)
+    ));
+
+    // Typed lines stay local, and so does our presence
+    node.handle_line("note to self").await;
+    node.handle_line("/status away").await;
+    node.tick();
+    assert_eq!(node.stats().counters.published, 0);
+    assert_eq!(node.history().count(), 0);
+}
+
+#[tokio::test]
+async fn read_only_nodes_still_relay() {
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&["--no-publish"])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut carol, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let topic = common::topic();
+
+    // Bob and carol only know alice, the observer
+    bob.swarm.dial(alice_addr.clone()).unwrap();
+    carol.swarm.dial(alice_addr).unwrap();
+    let relayed = tokio::time::timeout(Duration::from_secs(20), async {
+        let mut sent = false;
+        while carol.history().count() == 0 {
+            if !sent
+                && common::has_subscriber(&bob, &topic)
+                && common::has_subscriber(&carol, &topic)
+            {
+                bob.publish(&message("through the observer")).unwrap();
+                sent = true;
+            }
+            tokio::select! {
+                event = alice.swarm.select_next_some() => alice.handle_event(event),
+                event = bob.swarm.select_next_some() => bob.handle_event(event),
+                event = carol.swarm.select_next_some() => carol.handle_event(event),
+            }
+        }
+    });
+    relayed
+        .await
+        .expect("the message was relayed before the timeout");
+    assert!(!carol.swarm.is_connected(&bob.local_peer_id()));
+    assert_eq!(
+        &*carol.history().next().unwrap().message.body,
+        "through the observer"
+    );
+    assert_eq!(alice.history().count(), 1, "the observer sees it too");
+    assert_eq!(alice.stats().counters.published, 0);
+}
@@ -0,0 +1,119 @@
+// Reservations on a Circuit Relay v2 server with `--relay-server`.
+mod common;
+
+use std::time::Duration;
+
+use clap::Parser;
+use concurrent_chat_server::{chat::ChatNode, cli::Cli};
+use libp2p::{
+    futures::StreamExt, multiaddr::Protocol, noise, relay, swarm::SwarmEvent, tcp, yamux,
+    Multiaddr, Swarm, SwarmBuilder,
+};
+
+// A relay server listening on a loopback TCP port, and the address to reserve a slot at.
+async fn spawn_relay() -> (Swarm<relay::Behaviour>, Multiaddr) {
+    let mut relay = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )
+        .unwrap()
+        .with_behaviour(|key| relay::Behaviour::new(key.public().to_peer_id(), Default::default()))
+        .unwrap()
+        .build();
+    relay
+        .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .unwrap();
+    loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = relay.select_next_some().await {
+            relay.add_external_address(address.clone());
+            let id = *relay.local_peer_id();
+            return (relay, address.with(Protocol::P2p(id)));
+        }
+    }
+}
+
+#[test]
+fn relay_address_must_name_the_relay() {
+    assert!(
+        Cli::try_parse_from(["p2p-chat", "--relay-server", "/ip4/127.0.0.1/tcp/4001"]).is_err()
+    );
+    assert!(Cli::try_parse_from([
+        "p2p-chat",
+        "--relay-server",
+        "/ip4/127.0.0.1/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN",
+        "--swarm-key",
+        "swarm.key",
+    ])
+    .is_err());
+}
+
+#[tokio::test]
+async fn peers_dial_a_reserved_node_through_the_relay() {
+    let (mut relay, relay_addr) = spawn_relay().await;
+    let (mut alice, _) =
+        common::spawn_chat_node(&common::cli(&["--relay-server", &relay_addr.to_string()])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let alice_id = alice.local_peer_id();
+    alice.listen_on_relay().unwrap();
+
+    let result = tokio::time::timeout(Duration::from_secs(10), async {
+        let mut dialed = false;
+        while !alice.swarm.is_connected(&bob.local_peer_id()) {
+            // Once the relay holds alice's slot, bob can reach alice at the circuit address
+            if alice.has_relay_reservation() && !dialed {
+                let circuit = relay_addr
+                    .clone()
+                    .with(Protocol::P2pCircuit)
+                    .with(Protocol::P2p(alice_id));
+                bob.swarm.dial(circuit).unwrap();
+                dialed = true;
+            }
+            tokio::select! {
+                _ = relay.select_next_some() => {}
+                event = alice.swarm.select_next_some() => alice.handle_event(event),
+                event = bob.swarm.select_next_some() => bob.handle_event(event),
+            }
+        }
+    });
+    result
+        .await
+        .expect("connected through the relay before the timeout");
+}
+
+#[tokio::test]
+async fn relayed_connections_try_to_hole_punch() {
+    let (mut relay, relay_addr) = spawn_relay().await;
+    let (mut alice, _) =
+        common::spawn_chat_node(&common::cli(&["--relay-server", &relay_addr.to_string()])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let alice_id = alice.local_peer_id();
+    alice.listen_on_relay().unwrap();
+
+    let attempted = |alice: &ChatNode| {
+        let counters = alice.stats().counters;
+        counters.quic_hole_punches + counters.tcp_hole_punches + counters.failed_hole_punches
+    };
+    let result = tokio::time::timeout(Duration::from_secs(30), async {
+        let mut dialed = false;
+        while attempted(&alice) == 0 {
+            if alice.has_relay_reservation() && !dialed {
+                let circuit = relay_addr
+                    .clone()
+                    .with(Protocol::P2pCircuit)
+                    .with(Protocol::P2p(alice_id));
+                bob.swarm.dial(circuit).unwrap();
+                dialed = true;
+            }
+            tokio::select! {
+                _ = relay.select_next_some() => {}
+                event = alice.swarm.select_next_some() => alice.handle_event(event),
+                event = bob.swarm.select_next_some() => bob.handle_event(event),
+            }
+        }
+    });
+    // Loopback peers have no observed addresses to punch through, but the attempt is counted
+    result.await.expect("hole punch attempted before the timeout");
+}
@@ -0,0 +1,149 @@
+// Abuse reports sent by room members to the room's moderators.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    audit::{AuditEvent, AuditLog, AUDIT_FILE},
+    commands::{self, ReportTarget, UserCommand},
+    control,
+    message::ChatMessage,
+    report::{Report, ReportOutcome, Reports, MAX_REPORTS_PER_WINDOW},
+};
+use libp2p::PeerId;
+
+fn report(moderators: Vec<PeerId>, message_id: &str, author: PeerId) -> Report {
+    Report {
+        room: "lobby".to_string(),
+        moderators,
+        message_id: message_id.to_string(),
+        author: Some(author),
+        message: ChatMessage::new("carol", "buy cheap stuff", 0),
+        reason: "spam".to_string(),
+        timestamp: 0,
+    }
+}
+
+#[test]
+fn duplicate_reports_are_coalesced_and_volume_is_limited() {
+    let (moderator, reporter, spammer) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut reports = Reports::new(moderator);
+
+    assert_eq!(
+        reports.receive(reporter, report(vec![PeerId::random()], "m0", spammer), 0),
+        ReportOutcome::NotAddressed
+    );
+    assert_eq!(
+        reports.receive(reporter, report(vec![moderator], "m1", spammer), 0),
+        ReportOutcome::New(1)
+    );
+    let mut again = report(vec![moderator], "m1", spammer);
+    again.reason = "still spamming".to_string();
+    assert_eq!(
+        reports.receive(reporter, again, 1),
+        ReportOutcome::Duplicate(1)
+    );
+    assert_eq!(reports.pending().count(), 1);
+    assert_eq!(
+        reports.pending().next().unwrap().report.reason,
+        "still spamming"
+    );
+
+    for n in 2..=MAX_REPORTS_PER_WINDOW {
+        let outcome = reports.receive(
+            reporter,
+            report(vec![moderator], &format!("m{n}"), spammer),
+            2,
+        );
+        assert!(matches!(outcome, ReportOutcome::New(_)));
+    }
+    assert_eq!(
+        reports.receive(
+            reporter,
+            report(vec![moderator], "one-too-many", spammer),
+            3
+        ),
+        ReportOutcome::RateLimited
+    );
+    // Other reporters are counted separately
+    assert!(matches!(
+        reports.receive(PeerId::random(), report(vec![moderator], "m1", spammer), 3),
+        ReportOutcome::New(_)
+    ));
+
+    assert_eq!(
+        reports.resolve("lobby", &spammer).len(),
+        MAX_REPORTS_PER_WINDOW + 1
+    );
+    assert_eq!(reports.pending().count(), 0);
+}
+
+#[test]
+fn report_commands_parse() {
+    assert_eq!(
+        commands::parse("/report 3a4f abusive language"),
+        Some(Ok(UserCommand::Report {
+            target: ReportTarget::Id("3a4f".to_string()),
+            reason: "abusive language".to_string(),
+        }))
+    );
+    assert_eq!(
+        commands::parse("/report last from bob"),
+        Some(Ok(UserCommand::Report {
+            target: ReportTarget::LastFrom("bob".to_string()),
+            reason: String::new(),
+        }))
+    );
+    assert!(commands::parse("/report").unwrap().is_err());
+    assert!(commands::parse("/report last from").unwrap().is_err());
+    assert_eq!(commands::parse("/reports"), Some(Ok(UserCommand::Reports)));
+}
+
+#[tokio::test]
+async fn moderators_receive_reports_and_record_acting_on_them() {
+    let dir = env::temp_dir().join(format!("p2p-chat-reports-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let config = dir.join("config.json");
+    let (mut alice, _) =
+        common::spawn_chat_node(&common::cli(&["--config", config.to_str().unwrap()])).await;
+    let alice_id = alice.local_peer_id().to_string();
+    let (mut bob, bob_addr) =
+        common::spawn_chat_node(&common::cli(&["--moderator", &alice_id])).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| {
+            common::has_subscriber(alice, &control) && common::has_subscriber(bob, &control)
+        },
+    )
+    .await;
+
+    // Bob received a message from carol, who isn't connected to alice
+    let carol = PeerId::random();
+    let spam = ChatMessage::new("carol", "buy cheap stuff", 0);
+    bob.receive(common::message_event(carol, 1, spam.encode()));
+    bob.handle_line(&format!("/report last from {carol} spam"))
+        .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.reports().pending().count() == 1
+    })
+    .await;
+    let received = alice.reports().pending().next().unwrap().clone();
+    assert_eq!(received.reporter, bob.local_peer_id());
+    assert_eq!(received.report.author, Some(carol));
+    assert_eq!(received.report.message, spam);
+
+    alice.handle_line(&format!("/kick {carol}")).await;
+    assert_eq!(alice.reports().pending().count(), 0);
+    alice.flush_writes().await;
+    let entries = AuditLog::new(Some(dir.join(AUDIT_FILE))).tail(10).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(entries.iter().any(|entry| matches!(
+        &entry.event,
+        AuditEvent::ReportActedOn { target, reporter, .. }
+            if *target == carol && *reporter == bob.local_peer_id()
+    )));
+}
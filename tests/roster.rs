@@ -0,0 +1,268 @@
+// Per-room rosters of the peers online, and the events embedders receive about them.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    clock, control,
+    membership::{self, MembershipBatcher},
+    presence::{Presence, PresenceStatus},
+    roster::{Roster, RosterEvent},
+    snapshot::{Member, Snapshot, MAX_MEMBERS},
+};
+use libp2p::{futures::StreamExt, PeerId};
+
+#[test]
+fn peers_need_a_subscription_and_a_recent_sign_of_life() {
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    let mut presence = Presence::new(10);
+    let mut roster = Roster::default();
+    let room = "lobby".to_string();
+
+    roster.subscribe("lobby", alice);
+    presence.seen("lobby", bob, 100);
+    assert_eq!(roster.update(&presence, 100), []);
+
+    presence.seen("lobby", alice, 100);
+    roster.subscribe("lobby", bob);
+    let joined = roster.update(&presence, 101);
+    assert_eq!(joined.len(), 2);
+    assert!(joined.contains(&RosterEvent::Joined {
+        room: room.clone(),
+        peer: alice
+    }));
+    assert_eq!(roster.online("lobby").count(), 2);
+    // Nothing changed, nothing to report
+    assert_eq!(roster.update(&presence, 102), []);
+
+    // Bob leaves by unsubscribing, alice by going stale
+    roster.unsubscribe("lobby", &bob);
+    assert_eq!(
+        roster.update(&presence, 103),
+        [RosterEvent::Left {
+            room: room.clone(),
+            peer: bob
+        }]
+    );
+    assert_eq!(
+        roster.update(&presence, 120),
+        [RosterEvent::Left { room, peer: alice }]
+    );
+    assert_eq!(roster.online("lobby").count(), 0);
+}
+
+#[test]
+fn joins_and_leaves_are_summarized_once_the_window_is_over() {
+    let (alice, bob, carol) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let room = "lobby".to_string();
+    let mut batcher = MembershipBatcher::new(2);
+
+    for peer in [alice, bob, carol] {
+        batcher.push(
+            &RosterEvent::Joined {
+                room: room.clone(),
+                peer,
+            },
+            100,
+        );
+    }
+    batcher.push(
+        &RosterEvent::Away {
+            room: room.clone(),
+            peer: alice,
+        },
+        100,
+    );
+    // Carol's blip cancels out
+    batcher.push(
+        &RosterEvent::Left {
+            room: room.clone(),
+            peer: carol,
+        },
+        101,
+    );
+    assert_eq!(batcher.due(101), []);
+
+    let due = batcher.due(102);
+    assert_eq!(due.len(), 1);
+    let (due_room, batch) = &due[0];
+    assert_eq!(due_room, &room);
+    assert_eq!(batch.joined, [alice, bob]);
+    assert!(batch.left.is_empty());
+    assert_eq!(batcher.due(103), [], "a batch is only printed once");
+
+    // A batch whose changes all cancel out isn't printed at all
+    batcher.push(
+        &RosterEvent::Left {
+            room: room.clone(),
+            peer: bob,
+        },
+        110,
+    );
+    batcher.push(&RosterEvent::Joined { room, peer: bob }, 110);
+    assert_eq!(batcher.due(112), []);
+}
+
+#[test]
+fn summaries_count_and_name_the_changes() {
+    let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        membership::summary(
+            "general",
+            &names(&["alice", "bob", "carol"]),
+            &names(&["dave"]),
+            12
+        ),
+        "general: +3 joined (alice, bob, carol), −1 left (dave) — 12 online"
+    );
+    assert_eq!(
+        membership::summary(
+            "general",
+            &[],
+            &names(&["a", "b", "c", "d", "e", "f", "g"]),
+            0
+        ),
+        "general: −7 left (a, b, c, d, e and 2 more) — 0 online"
+    );
+}
+
+#[tokio::test]
+async fn embedders_hear_about_arrivals() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let mut events = alice.subscribe_roster();
+    let (bob_id, room) = (bob.local_peer_id(), common::topic().hash().into_string());
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.roster().is_subscribed(&room, &bob_id)
+            && common::has_subscriber(alice, &control)
+            && alice
+                .presence()
+                .status(&room, &bob_id, clock::unix_time())
+                .is_some()
+    })
+    .await;
+
+    alice.tick();
+    assert_eq!(
+        events.try_recv().unwrap(),
+        RosterEvent::Joined {
+            room: room.clone(),
+            peer: bob_id
+        }
+    );
+    assert_eq!(alice.roster().online(&room).collect::<Vec<_>>(), [&bob_id]);
+    alice.tick();
+    assert!(events.try_recv().is_err(), "only changes are sent");
+}
+
+#[test]
+fn vouched_peers_stay_only_while_they_look_alive() {
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    let mut presence = Presence::new(10);
+    let mut roster = Roster::default();
+
+    // Another member heard from bob a moment ago; we never hear from bob ourselves
+    presence.hint("lobby", bob, 99, false);
+    roster.vouch("lobby", bob);
+    assert_eq!(
+        roster.update(&presence, 100),
+        [RosterEvent::Joined {
+            room: "lobby".to_string(),
+            peer: bob
+        }]
+    );
+    // Our own, more recent observation isn't overridden by older hearsay
+    presence.seen("lobby", alice, 100);
+    presence.hint("lobby", alice, 50, true);
+    assert_eq!(
+        presence.status("lobby", &alice, 100),
+        Some((PresenceStatus::Online, 100))
+    );
+    assert!(!presence.is_away("lobby", &alice));
+
+    assert_eq!(
+        roster.update(&presence, 125),
+        [RosterEvent::Left {
+            room: "lobby".to_string(),
+            peer: bob
+        }],
+        "bob went stale without us ever hearing from it"
+    );
+}
+
+#[test]
+fn snapshots_are_capped_and_cleaned_up() {
+    let bob = PeerId::random();
+    let member = |peer| Member {
+        peer,
+        nick: "b\u{202e}ob".to_string(),
+        away: false,
+        last_seen: 500,
+    };
+    let mut members = vec![member(bob), member(bob)];
+    members.extend((0..MAX_MEMBERS * 2).map(|_| member(PeerId::random())));
+    let snapshot = Snapshot {
+        room: "lobby".to_string(),
+        members,
+    }
+    .sanitized(100);
+    assert_eq!(
+        snapshot.members.len(),
+        MAX_MEMBERS - 1,
+        "bob is listed once"
+    );
+    assert_eq!(snapshot.members[0].nick, "bob");
+    assert!(snapshot.members.iter().all(|m| m.last_seen == 100));
+}
+
+#[tokio::test]
+async fn newcomers_get_a_snapshot_of_the_room() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--nick", "alice"])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut carol, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (alice_id, bob_id) = (alice.local_peer_id(), bob.local_peer_id());
+    let room = common::topic().hash().into_string();
+
+    // Alice and bob are in the room together
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.roster().is_subscribed(&room, &bob_id)
+            && alice
+                .presence()
+                .status(&room, &bob_id, clock::unix_time())
+                .is_some()
+    })
+    .await;
+    alice.tick();
+    assert_eq!(alice.roster().online(&room).collect::<Vec<_>>(), [&bob_id]);
+
+    // Carol only connects to alice, yet learns about bob from alice's snapshot
+    carol
+        .swarm
+        .dial(alice.swarm.listeners().next().unwrap().clone())
+        .unwrap();
+    let learned = tokio::time::timeout(Duration::from_secs(10), async {
+        while carol
+            .presence()
+            .status(&room, &bob_id, clock::unix_time())
+            .is_none()
+        {
+            tokio::select! {
+                event = alice.swarm.select_next_some() => alice.handle_event(event),
+                event = bob.swarm.select_next_some() => bob.handle_event(event),
+                event = carol.swarm.select_next_some() => carol.handle_event(event),
+            }
+        }
+    });
+    learned.await.expect("snapshot received before the timeout");
+    carol.tick();
+    assert!(!carol.swarm.is_connected(&bob_id));
+    let mut online: Vec<_> = carol.roster().online(&room).copied().collect();
+    online.sort();
+    let mut expected = vec![alice_id, bob_id];
+    expected.sort();
+    assert_eq!(online, expected);
+    assert_eq!(carol.display_name(&alice_id), "alice");
+}
@@ -0,0 +1,51 @@
+// The timers and tasks of whichever runtime the crate was built for.
+use std::time::{Duration, Instant};
+
+use concurrent_chat_server::runtime;
+
+#[tokio::test]
+async fn intervals_tick_at_once_then_every_period() {
+    let started = Instant::now();
+    let mut ticks = runtime::interval(Duration::from_millis(50));
+    ticks.tick().await;
+    assert!(started.elapsed() < Duration::from_millis(40));
+    ticks.tick().await;
+    ticks.tick().await;
+    let elapsed = started.elapsed();
+    assert!(
+        elapsed >= Duration::from_millis(100) && elapsed < Duration::from_secs(1),
+        "{elapsed:?}"
+    );
+
+    // A late tick pushes the next one back instead of firing it straight after
+    std::thread::sleep(Duration::from_millis(120));
+    ticks.tick().await;
+    let late = Instant::now();
+    ticks.tick().await;
+    assert!(
+        late.elapsed() >= Duration::from_millis(40),
+        "{:?}",
+        late.elapsed()
+    );
+}
+
+#[tokio::test]
+async fn timeouts_give_up_on_slow_futures() {
+    let slow = runtime::timeout(
+        Duration::from_millis(20),
+        runtime::sleep(Duration::from_secs(5)),
+    );
+    assert_eq!(slow.await, Err(runtime::Elapsed));
+    let quick = runtime::timeout(Duration::from_secs(5), async { 7 });
+    assert_eq!(quick.await, Ok(7));
+}
+
+#[tokio::test]
+async fn tasks_give_their_output_unless_aborted() {
+    assert_eq!(runtime::spawn(async { 7 }).await, Some(7));
+
+    let stuck = runtime::spawn(runtime::sleep(Duration::from_secs(60)));
+    stuck.abort();
+    let outcome = runtime::timeout(Duration::from_secs(5), stuck).await;
+    assert_eq!(outcome, Ok(None));
+}
@@ -0,0 +1,88 @@
+// Rendering hostile message content safely.
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    sanitize::{self, Link, MAX_BODY_CHARS, MAX_NICK_CHARS, MAX_WORD_CHARS},
+};
+
+// Strings a hostile peer might send, each aimed at a different part of the renderer
+const CORPUS: &[&str] = &[
+    // Cursor movement, screen clearing and recoloring
+    "\x1b[2J\x1b[Hyou have been logged out",
+    "\x1b[31mred\x1b[0m text",
+    // A fake second line pretending to come from someone else
+    "hi\nGot message: 'send me your key' from admin",
+    "carriage\rreturn",
+    // Window title change (OSC 0) ended by BEL
+    "\x1b]0;pwned\x07title",
+    // Hyperlink whose text hides where it goes
+    "\x1b]8;;https://evil.example/\x1b\\https://bank.example\x1b]8;;\x1b\\",
+    // Hyperlink that is never closed
+    "\x1b]8;;https://evil.example/\x07click me",
+    // Right-to-left override flipping the visible text
+    "innocent\u{202e}txt.exe",
+    // Zero-width characters hiding content
+    "pass\u{200b}word\u{feff}\u{2060}",
+    // Lone escape and C1 controls
+    "\x1b",
+    "\u{9b}31m",
+];
+
+fn is_safe(text: &str) -> bool {
+    text.chars()
+        .all(|c| !c.is_control() && !sanitize::is_invisible(c))
+}
+
+#[test]
+fn corpus_renders_without_control_or_invisible_characters() {
+    for input in CORPUS {
+        let (body, links) = sanitize::body(input);
+        assert!(is_safe(&body), "{input:?} rendered as {body:?}");
+        for link in &links {
+            assert!(
+                is_safe(&link.text) && is_safe(&link.uri),
+                "{input:?}: {link:?}"
+            );
+        }
+        assert!(is_safe(&sanitize::nick(input)), "{input:?}");
+        assert!(is_safe(&sanitize::line(input)), "{input:?}");
+    }
+    assert_eq!(sanitize::body("\x1b[31mred\x1b[0m text").0, "red text");
+    assert_eq!(sanitize::nick("innocent\u{202e}txt.exe"), "innocenttxt.exe");
+}
+
+#[test]
+fn hyperlinks_are_held_back_with_their_target() {
+    let (body, links) = sanitize::body(CORPUS[5]);
+    assert_eq!(body, "https://bank.example [link]");
+    assert_eq!(
+        links,
+        vec![Link {
+            text: "https://bank.example".to_string(),
+            uri: "https://evil.example/".to_string(),
+        }]
+    );
+    // The clickable form spells out where it really goes
+    assert!(sanitize::clickable(&links[0]).ends_with("-> https://evil.example/"));
+
+    let (body, links) = sanitize::body(CORPUS[6]);
+    assert_eq!(body, "click me [link]");
+    assert_eq!(links[0].uri, "https://evil.example/");
+    assert_eq!(commands::parse("/link 3"), Some(Ok(UserCommand::Link(3))));
+}
+
+#[test]
+fn pathological_lengths_are_bounded() {
+    let word = "a".repeat(10_000);
+    let (body, _) = sanitize::body(&word);
+    assert!(body
+        .split(' ')
+        .take_while(|w| w.chars().all(|c| c == 'a'))
+        .all(|w| w.chars().count() <= MAX_WORD_CHARS));
+    assert!(body.ends_with(&format!("({} more characters)", 10_000 - MAX_BODY_CHARS)));
+    assert!(body.chars().count() < MAX_BODY_CHARS * 2);
+
+    assert_eq!(
+        sanitize::nick(&"n".repeat(500)).chars().count(),
+        MAX_NICK_CHARS
+    );
+}
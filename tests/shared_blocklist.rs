@@ -0,0 +1,171 @@
+// Blocklist updates shared between trusted peers.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    blocklist::{
+        BlockAction, BlockOrigin, Blocklist, BlocklistUpdate, Change, UpdateOutcome, UpdateStatus,
+        MAX_BLOCKED_PEERS,
+    },
+    control::{self, ControlMessage, SignedControl},
+};
+use libp2p::{identity::Keypair, PeerId};
+
+fn update(action: BlockAction, target: PeerId, timestamp: u64) -> BlocklistUpdate {
+    BlocklistUpdate {
+        action,
+        target,
+        reason: "spam".to_string(),
+        timestamp,
+    }
+}
+
+#[test]
+fn untrusted_updates_are_ignored() {
+    let mut list = Blocklist::new(PeerId::random());
+    let target = PeerId::random();
+    let outcome = list.receive(
+        PeerId::random(),
+        update(BlockAction::Add, target, 1),
+        false,
+        true,
+        0,
+    );
+    assert_eq!(outcome, UpdateOutcome::Untrusted);
+    assert!(!list.is_blocked(&target));
+}
+
+#[test]
+fn trusted_updates_wait_for_review_then_revert() {
+    let mut list = Blocklist::new(PeerId::random());
+    let (alice, target) = (PeerId::random(), PeerId::random());
+
+    let UpdateOutcome::Pending(id) =
+        list.receive(alice, update(BlockAction::Add, target, 1), true, false, 0)
+    else {
+        panic!("update should be pending");
+    };
+    assert!(!list.is_blocked(&target));
+
+    assert_eq!(list.apply(id, 0), Ok(Change::Blocked(target)));
+    let (_, entry) = list.entries().next().unwrap();
+    assert_eq!(
+        entry.origin,
+        BlockOrigin::Shared {
+            via: alice,
+            update: id
+        }
+    );
+
+    assert_eq!(list.revert(id), Ok(Change::Unblocked(target)));
+    assert!(!list.is_blocked(&target));
+    assert_eq!(
+        list.updates().next().unwrap().status,
+        UpdateStatus::Reverted
+    );
+}
+
+#[test]
+fn replayed_and_own_updates_are_stale() {
+    let local = PeerId::random();
+    let mut list = Blocklist::new(local);
+    let (alice, target) = (PeerId::random(), PeerId::random());
+
+    assert!(matches!(
+        list.receive(alice, update(BlockAction::Add, target, 5), true, true, 0),
+        UpdateOutcome::Applied(..)
+    ));
+    // The same update coming round again through gossip, or an older one, changes nothing.
+    let replay = list.receive(alice, update(BlockAction::Remove, target, 5), true, true, 0);
+    assert_eq!(replay, UpdateOutcome::Stale);
+    let own = list.receive(local, update(BlockAction::Add, target, 9), true, true, 0);
+    assert_eq!(own, UpdateOutcome::Stale);
+    assert!(list.is_blocked(&target));
+}
+
+#[test]
+fn shared_removal_never_lifts_a_manual_block() {
+    let mut list = Blocklist::new(PeerId::random());
+    let (alice, target) = (PeerId::random(), PeerId::random());
+    list.block(target, "mine".into(), BlockOrigin::Manual, 0)
+        .unwrap();
+
+    let outcome = list.receive(alice, update(BlockAction::Remove, target, 1), true, true, 0);
+    assert!(matches!(
+        outcome,
+        UpdateOutcome::Applied(_, Change::Unchanged)
+    ));
+    assert!(list.is_blocked(&target));
+}
+
+#[test]
+fn blocklist_size_is_capped() {
+    let mut list = Blocklist::new(PeerId::random());
+    for _ in 0..MAX_BLOCKED_PEERS {
+        list.block(PeerId::random(), String::new(), BlockOrigin::Manual, 0)
+            .unwrap();
+    }
+    assert!(list
+        .block(PeerId::random(), String::new(), BlockOrigin::Manual, 0)
+        .is_err());
+    let outcome = list.receive(
+        PeerId::random(),
+        update(BlockAction::Add, PeerId::random(), 1),
+        true,
+        true,
+        0,
+    );
+    assert_eq!(outcome, UpdateOutcome::Full);
+}
+
+#[test]
+fn tampered_updates_fail_verification() {
+    let keypair = Keypair::generate_ed25519();
+    let message = ControlMessage::BlocklistUpdate(update(BlockAction::Add, PeerId::random(), 1));
+    let signed = SignedControl::sign(&keypair, &message).unwrap();
+
+    let (author, decoded) = signed.verify().unwrap();
+    assert_eq!(author, keypair.public().to_peer_id());
+    assert_eq!(decoded, message);
+
+    let json = serde_json::to_string(&signed)
+        .unwrap()
+        .replace("spam", "ham!");
+    let tampered: SignedControl = serde_json::from_str(&json).unwrap();
+    assert!(tampered.verify().is_err());
+}
+
+#[tokio::test]
+async fn trusted_peer_shares_a_block_over_the_network() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    bob.trust(alice.local_peer_id());
+    alice.swarm.dial(bob_addr).unwrap();
+
+    let control_topic = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &control_topic)
+    })
+    .await;
+
+    let spammer = PeerId::random();
+    alice
+        .handle_line(&format!("/blocklist add {spammer} flooding"))
+        .await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.blocklist().updates().next().is_some()
+    })
+    .await;
+
+    // Without --auto-apply the update waits for review.
+    let received = bob.blocklist().updates().next().unwrap();
+    assert_eq!(received.author, alice.local_peer_id());
+    assert_eq!(received.status, UpdateStatus::Pending);
+    assert!(!bob.blocklist().is_blocked(&spammer));
+    assert!(alice.blocklist().is_blocked(&spammer));
+
+    bob.handle_line(&format!("/blocklist apply {}", received.id))
+        .await;
+    assert!(bob.blocklist().is_blocked(&spammer));
+}
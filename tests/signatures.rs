@@ -0,0 +1,133 @@
+// Marking and rejecting messages that aren't signed by their author.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, UserCommand},
+    node::SignedSourceOnly,
+    signed,
+};
+use libp2p::{
+    futures::StreamExt,
+    gossipsub::{self, DataTransform},
+    noise,
+    swarm::SwarmEvent,
+    tcp, yamux, PeerId, Swarm,
+};
+
+// A peer misconfigured to publish anonymous, unsigned messages.
+fn anonymous_swarm() -> Swarm<gossipsub::Behaviour> {
+    let mut swarm = libp2p::SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_tcp(
+            tcp::Config::default(),
+            noise::Config::new,
+            yamux::Config::default,
+        )
+        .unwrap()
+        .with_behaviour(|_| {
+            let config = gossipsub::ConfigBuilder::default()
+                .validation_mode(gossipsub::ValidationMode::Anonymous)
+                .build()
+                .unwrap();
+            gossipsub::Behaviour::new(gossipsub::MessageAuthenticity::Anonymous, config).unwrap()
+        })
+        .unwrap()
+        .build();
+    swarm.behaviour_mut().subscribe(&common::topic()).unwrap();
+    swarm
+}
+
+// Publish one anonymous message to `chat` and drive both until `chat` has received it.
+async fn send_unsigned(chat: &mut ChatNode, addr: libp2p::Multiaddr) {
+    let mut anonymous = anonymous_swarm();
+    anonymous.dial(addr).unwrap();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while chat.stats().counters.received == 0 {
+            tokio::select! {
+                event = chat.swarm.select_next_some() => chat.handle_event(event),
+                event = anonymous.select_next_some() => match event {
+                    SwarmEvent::Behaviour(gossipsub::Event::Subscribed { topic, .. })
+                        if topic == common::topic().hash() =>
+                    {
+                        anonymous
+                            .behaviour_mut()
+                            .publish(common::topic(), b"trust me".to_vec())
+                            .unwrap();
+                    }
+                    _ => {}
+                },
+            }
+        }
+    })
+    .await
+    .expect("message delivered before timeout");
+}
+
+#[test]
+fn unsigned_messages_lose_their_claimed_source() {
+    let raw = |signature: Option<Vec<u8>>| gossipsub::RawMessage {
+        source: Some(PeerId::random()),
+        data: b"hi".to_vec(),
+        sequence_number: Some(1),
+        topic: common::topic().hash(),
+        signature,
+        key: None,
+        validated: false,
+    };
+    let transform = SignedSourceOnly;
+    assert_eq!(transform.inbound_transform(raw(None)).unwrap().source, None);
+    assert!(transform
+        .inbound_transform(raw(Some(vec![1, 2, 3])))
+        .unwrap()
+        .source
+        .is_some());
+}
+
+#[tokio::test]
+async fn unsigned_messages_are_marked_by_default() {
+    let (mut chat, addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    send_unsigned(&mut chat, addr).await;
+    let stored: Vec<_> = chat.history().collect();
+    assert_eq!(stored.len(), 1);
+    assert_eq!(stored[0].source, None);
+    assert_eq!(&*stored[0].message.body, "trust me");
+}
+
+#[tokio::test]
+async fn require_signed_drops_unsigned_messages() {
+    let (mut chat, addr) = common::spawn_chat_node(&common::cli(&["--require-signed"])).await;
+    send_unsigned(&mut chat, addr).await;
+    assert_eq!(chat.history().count(), 0);
+}
+
+#[tokio::test]
+async fn whois_shows_the_key_verified_on_signed_messages() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let alice_id = alice.local_peer_id();
+    assert_eq!(bob.verified_key(&alice_id), None);
+
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = alice.topic().clone();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &topic)
+    })
+    .await;
+    alice.handle_line("signed and sealed").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+
+    let (key, count) = bob.verified_key(&alice_id).unwrap();
+    assert_eq!(key.to_peer_id(), alice_id);
+    assert_eq!(count, 1);
+    assert!(signed::describe_key(&key).starts_with("ed25519 "));
+    assert_eq!(
+        commands::parse(&format!("/whois {alice_id}")),
+        Some(Ok(UserCommand::Whois(alice_id)))
+    );
+}
@@ -0,0 +1,168 @@
+// Spam reported by members together, hidden past a threshold, with Merkle proofs of the reports.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, ReportTarget, UserCommand},
+    message::ChatMessage,
+    spam::{self, SpamMessage, SpamReport, SpamReportStore},
+};
+use libp2p::{
+    gossipsub::{self, MessageId},
+    identity::Keypair,
+    PeerId,
+};
+
+fn report(keypair: &Keypair, id: &str) -> SpamReport {
+    SpamReport::sign(keypair, MessageId::new(id.as_bytes())).unwrap()
+}
+
+// `source`'s first chat message, as the node's swarm hands it over, with Gossipsub id
+// `<source>-1`.
+fn chat(source: PeerId, body: &str) -> gossipsub::Event {
+    let message = ChatMessage::new("mallory", body, 0);
+    common::message_event(source, 1, message.encode())
+}
+
+fn on_spam_topic(source: PeerId, message: &SpamMessage) -> gossipsub::Event {
+    let data = serde_json::to_vec(message).unwrap();
+    common::message_event_on(spam::spam_topic().hash(), source, 1, data)
+}
+
+#[test]
+fn reports_count_once_per_signed_reporter() {
+    let mut store = SpamReportStore::new(2);
+    let (alice, bob) = (Keypair::generate_ed25519(), Keypair::generate_ed25519());
+    let id = MessageId::new(b"m1");
+    assert_eq!(store.add_report(report(&alice, "m1")), Ok(true));
+    assert_eq!(store.add_report(report(&alice, "m1")), Ok(false));
+    assert!(!store.is_suppressed(&id));
+
+    // A report signed by someone other than its reporter is refused
+    let mut forged = report(&alice, "m1");
+    forged.reporter_peer_id = bob.public().to_peer_id();
+    assert!(store.add_report(forged).is_err());
+    assert_eq!(store.count(&id), 1);
+
+    assert_eq!(store.add_report(report(&bob, "m1")), Ok(true));
+    assert!(store.is_suppressed(&id));
+    assert!(store.reported_by(&id, &bob.public().to_peer_id()));
+    assert_eq!(store.suppressed().collect::<Vec<_>>(), [&id]);
+}
+
+#[test]
+fn proofs_lead_from_the_reports_to_the_root() {
+    let reporters: Vec<_> = (0..3).map(|_| Keypair::generate_ed25519()).collect();
+    let mut store = SpamReportStore::new(spam::DEFAULT_THRESHOLD);
+    assert_eq!(store.root(), [0; 32]);
+    // Five leaves, so one is the odd one out at some level
+    let ids = ["a", "b", "c", "d", "e"];
+    for (i, id) in ids.iter().enumerate() {
+        for reporter in &reporters[..=i % 3] {
+            store.add_report(report(reporter, id)).unwrap();
+        }
+    }
+    for (i, id) in ids.iter().enumerate() {
+        let proof = store
+            .generate_proof(&MessageId::new(id.as_bytes()))
+            .unwrap();
+        assert_eq!(proof.root, store.root());
+        assert_eq!(proof.verify(), Ok(i % 3 + 1));
+    }
+    assert_eq!(store.generate_proof(&MessageId::new(b"z")), None);
+
+    // Peers holding the same reports have the same root, in whatever order they came
+    let mut other = SpamReportStore::new(spam::DEFAULT_THRESHOLD);
+    for id in ids.iter().rev() {
+        other
+            .add_proof(
+                store
+                    .generate_proof(&MessageId::new(id.as_bytes()))
+                    .unwrap(),
+            )
+            .unwrap();
+    }
+    assert_eq!(other.root(), store.root());
+    assert!(other.is_suppressed(&MessageId::new(b"c")));
+
+    // Leaving out a report, or reporting a reporter twice, breaks the proof
+    let full = store.generate_proof(&MessageId::new(b"c")).unwrap();
+    let mut proof = full.clone();
+    proof.reports.pop();
+    assert_eq!(
+        proof.verify(),
+        Err("the path doesn't lead to the root".to_string())
+    );
+    let mut proof = full.clone();
+    proof.reports[1] = proof.reports[0].clone();
+    assert!(proof.verify().unwrap_err().contains("reported twice"));
+    let mut proof = full;
+    proof.path[0].sibling[0] ^= 1;
+    assert!(proof.verify().is_err());
+}
+
+#[test]
+fn spam_commands_parse() {
+    assert_eq!(
+        commands::parse("/spam 3a4f"),
+        Some(Ok(UserCommand::Spam(ReportTarget::Id("3a4f".to_string()))))
+    );
+    assert_eq!(
+        commands::parse("/spam last from mallory"),
+        Some(Ok(UserCommand::Spam(ReportTarget::LastFrom(
+            "mallory".to_string()
+        ))))
+    );
+    for bad in ["/spam", "/spam last from", "/spam 3a4f because"] {
+        assert!(commands::parse(bad).unwrap().is_err(), "{bad} accepted");
+    }
+}
+
+#[tokio::test]
+async fn messages_reported_by_enough_members_are_hidden() {
+    let args = ["--spam-threshold", "2"];
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&args)).await;
+    let (mut carol, _) = common::spawn_chat_node(&common::cli(&args)).await;
+    carol.swarm.dial(bob_addr).unwrap();
+    let topic = spam::spam_topic();
+    common::run_until(&mut bob, &mut carol, Duration::from_secs(10), |b, c| {
+        common::has_subscriber(b, &topic) && common::has_subscriber(c, &topic)
+    })
+    .await;
+
+    // Both got Mallory's message, and both report it
+    let mallory = PeerId::random();
+    let id = MessageId::from(format!("{mallory}-1"));
+    for node in [&mut bob, &mut carol] {
+        node.receive(chat(mallory, "buy cheap pills"));
+        node.handle_line(&format!("/spam last from {mallory}"))
+            .await;
+    }
+    let hidden = |node: &ChatNode| node.history().all(|stored| !stored.shown);
+    common::run_until(&mut bob, &mut carol, Duration::from_secs(10), |b, c| {
+        hidden(b) && hidden(c)
+    })
+    .await;
+    assert_eq!(bob.spam().count(&id), 2);
+    assert_eq!(bob.spam().root(), carol.spam().root());
+    assert_eq!(bob.filtered_count(), 0, "spam isn't counted as filtered");
+
+    // A newcomer handed a proof hides the message when it comes
+    let mut dave = ChatNode::new(&common::cli(&args)).unwrap();
+    let proof = bob.spam().generate_proof(&id).unwrap();
+    dave.receive(on_spam_topic(
+        bob.local_peer_id(),
+        &SpamMessage::Proof(proof),
+    ));
+    dave.receive(chat(mallory, "buy cheap pills"));
+    assert!(hidden(&dave));
+    assert_eq!(dave.history().count(), 1);
+
+    // Reports that don't verify are dropped
+    let mut forged = report(&Keypair::generate_ed25519(), "other");
+    forged.signature[0] ^= 1;
+    dave.receive(on_spam_topic(mallory, &SpamMessage::Report(forged)));
+    assert_eq!(dave.spam().count(&MessageId::new(b"other")), 0);
+}
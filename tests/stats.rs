@@ -0,0 +1,106 @@
+// Diagnostics reported by `/stats`.
+mod common;
+
+use std::time::{Duration, Instant};
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    stats::{TimedDedup, DEDUP_TTL},
+};
+use libp2p::PeerId;
+
+#[test]
+fn dedup_cache_spots_republished_content() {
+    let mut cache = TimedDedup::default();
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    let now = Instant::now();
+    assert!(!cache.check(&alice, b"hello", now));
+    assert!(cache.check(&alice, b"hello", now));
+    // The same words from someone else are not a duplicate
+    assert!(!cache.check(&bob, b"hello", now));
+    assert_eq!(commands::parse("/stats"), Some(Ok(UserCommand::Stats)));
+}
+
+#[test]
+fn dedup_cache_forgets_content_after_its_ttl() {
+    let mut cache = TimedDedup::default();
+    let alice = PeerId::random();
+    let hello = TimedDedup::fingerprint(&alice, b"hello");
+    let start = Instant::now();
+    assert!(cache.insert(hello.clone(), start));
+    assert!(cache.contains(&hello, start + DEDUP_TTL));
+    assert!(!cache.contains(&hello, start + DEDUP_TTL + Duration::from_secs(120)));
+    for minute in 1..30 {
+        let id = TimedDedup::fingerprint(&alice, format!("message {minute}").as_bytes());
+        cache.insert(id, start + Duration::from_secs(60 * minute));
+    }
+
+    // Memory follows the rate: only the last few minutes of fingerprints are kept
+    assert!(cache.len() <= 7, "{} fingerprints", cache.len());
+    let later = start + Duration::from_secs(60 * 30);
+    assert!(!cache.check(&alice, b"hello", later));
+    assert!(cache.check(&alice, b"hello", later));
+}
+
+#[tokio::test]
+async fn stats_count_mesh_peers_and_messages() {
+    // Without heartbeats, so only the chat message is counted
+    let quiet = common::cli(&["--presence-interval", "0"]);
+    let (mut alice, _) = common::spawn_chat_node(&quiet).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&quiet).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = alice.topic().clone();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &topic)
+    })
+    .await;
+
+    alice.handle_line("hello").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.stats().counters.received == 1
+    })
+    .await;
+
+    let stats = alice.stats();
+    // The chat topic, its control topic, its board, its task list, its Wordle games, its
+    // canvas, its spam reports and the key shares
+    assert_eq!(stats.topics.len(), 8);
+    let chat = stats
+        .topics
+        .iter()
+        .find(|t| t.topic == topic.hash())
+        .unwrap();
+    assert_eq!(chat.subscribed_peers, 1);
+    assert_eq!(stats.counters.published, 1);
+    assert_eq!(bob.stats().counters.duplicates, 0);
+    assert!(stats.to_string().contains("scoring disabled"));
+}
+
+#[tokio::test]
+async fn health_reports_isolated_and_connected_nodes() {
+    let quiet = common::cli(&["--presence-interval", "0"]);
+    let (mut alice, _) = common::spawn_chat_node(&quiet).await;
+    let health = alice.health();
+    assert!(health.listening);
+    assert!(!health.is_ready());
+    assert_eq!(health.last_message_received, None);
+
+    let (mut bob, bob_addr) = common::spawn_chat_node(&quiet).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = alice.topic().clone();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &topic)
+    })
+    .await;
+    alice.handle_line("hello").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.health().last_message_received.is_some()
+    })
+    .await;
+
+    let health = bob.health();
+    assert!(health.is_ready());
+    assert_eq!(health.peer_count, 1);
+    assert_eq!(health.mesh_peer_count_per_topic.len(), 8);
+    assert_eq!(health.bytes_received, alice.health().bytes_sent);
+}
@@ -0,0 +1,104 @@
+// Free-form status lines set with `/status set`, broadcast with presence heartbeats.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    clock,
+    commands::{self, StatusCommand, UserCommand},
+    control,
+    presence::{self, Presence, MAX_STATUS_CHARS},
+};
+use libp2p::PeerId;
+
+#[test]
+fn status_line_commands_parse() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(
+        parse("/status set in a meeting until 3"),
+        Ok(UserCommand::Status(StatusCommand::Set(
+            "in a meeting until 3".to_string()
+        )))
+    );
+    assert_eq!(
+        parse("/status clear"),
+        Ok(UserCommand::Status(StatusCommand::Clear))
+    );
+    assert!(parse("/status set").is_err());
+    assert!(parse("/status set   ").is_err());
+}
+
+#[test]
+fn status_lines_are_cleaned_and_shortened() {
+    assert_eq!(
+        presence::clean_status_line(" reviewing\nPRs\u{202e} ").as_deref(),
+        Some("reviewing PRs")
+    );
+    assert_eq!(presence::clean_status_line("\u{200b} "), None);
+    let long = "x".repeat(MAX_STATUS_CHARS + 10);
+    assert_eq!(
+        presence::clean_status_line(&long).unwrap().chars().count(),
+        MAX_STATUS_CHARS
+    );
+
+    assert_eq!(presence::shorten("reviewing PRs", 20), "reviewing PRs");
+    assert_eq!(presence::shorten("in a meeting until 3", 10), "in a meet…");
+}
+
+#[test]
+fn status_lines_keep_the_latest_and_end_with_the_peer() {
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    let mut presence = Presence::new(10);
+    presence.heartbeat("lobby", alice, 10, false, 100);
+    presence.set_status_line("lobby", &alice, Some("lunch".to_string()));
+    presence.set_status_line("lobby", &alice, Some("reviewing PRs".to_string()));
+    // Chat messages don't touch it
+    presence.seen("lobby", alice, 105);
+    assert_eq!(
+        presence.status_line("lobby", &alice, 105),
+        Some("reviewing PRs")
+    );
+    assert_eq!(presence.status_line("other", &alice, 105), None);
+
+    // It expires once the peer is offline, and leaving drops it for good
+    assert_eq!(presence.status_line("lobby", &alice, 155), None);
+    presence.depart("lobby", alice, 110);
+    presence.seen("lobby", alice, 111);
+    assert_eq!(presence.status_line("lobby", &alice, 111), None);
+
+    presence.set_status_line("lobby", &bob, Some("unknown".to_string()));
+    assert_eq!(presence.status_line("lobby", &bob, 100), None);
+}
+
+#[tokio::test]
+async fn peers_see_the_latest_status_line() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (bob_id, room) = (bob.local_peer_id(), common::topic().hash().into_string());
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &control) && common::has_subscriber(b, &control)
+    })
+    .await;
+
+    let status = |alice: &ChatNode| {
+        alice
+            .presence()
+            .status_line(&room, &bob_id, clock::unix_time())
+            .map(str::to_string)
+    };
+    bob.handle_line("/status set in a meeting until 3").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        status(alice).as_deref() == Some("in a meeting until 3")
+    })
+    .await;
+
+    bob.handle_line("/status clear").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        status(alice).is_none()
+    })
+    .await;
+    assert_eq!(alice.history().count(), 0, "status lines aren't history");
+}
@@ -0,0 +1,66 @@
+// What the node does once its input ends, as when a pipe into it closes.
+mod common;
+
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use concurrent_chat_server::{
+    chat::CONNECT_GRACE,
+    cli::{Cli, StdinEof},
+};
+use libp2p::futures::{future, stream, StreamExt};
+use tokio::sync::oneshot;
+
+#[test]
+fn the_action_is_left_to_the_terminal_unless_given() {
+    assert_eq!(common::cli(&[]).on_stdin_eof, None);
+    let cli = Cli::try_parse_from(["p2p-chat", "--on-stdin-eof", "exit"]).unwrap();
+    assert_eq!(cli.on_stdin_eof, Some(StdinEof::Exit));
+    assert!(Cli::try_parse_from(["p2p-chat", "--on-stdin-eof", "wait"]).is_err());
+}
+
+#[tokio::test]
+async fn piped_input_ending_sends_what_is_left_and_exits() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--on-stdin-eof", "exit"])).await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    let input = stream::iter(["last words".to_string()]);
+    let (done, finished) = oneshot::channel();
+    let alice_runs = async {
+        let exited =
+            tokio::time::timeout(Duration::from_secs(20), alice.run(input, future::pending()))
+                .await;
+        let _ = done.send(());
+        exited.is_ok()
+    };
+    let bob_listens = async {
+        let mut finished = finished;
+        loop {
+            tokio::select! {
+                event = bob.swarm.select_next_some() => bob.handle_event(event),
+                _ = &mut finished => break,
+            }
+        }
+    };
+    let (exited, ()) = tokio::join!(alice_runs, bob_listens);
+    assert!(exited, "the node kept running after its input ended");
+    assert_eq!(&*bob.history().next().unwrap().message.body, "last words");
+}
+
+#[tokio::test]
+async fn interactive_input_ending_keeps_the_node_listening() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    let started = Instant::now();
+    let until = CONNECT_GRACE + Duration::from_secs(2);
+    alice.run(stream::empty(), tokio::time::sleep(until)).await;
+    assert!(started.elapsed() >= until);
+}
@@ -0,0 +1,230 @@
+// The JSON commands and events spoken on stdin and stdout with `--io json`.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    roster::RosterEvent,
+    stdio::{self, ChatCommand, ChatEvent, Query},
+};
+use libp2p::{futures::StreamExt, multiaddr::Protocol, PeerId};
+use serde_json::json;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+// The events sent so far.
+fn drain(events: &mut UnboundedReceiver<ChatEvent>) -> Vec<ChatEvent> {
+    let mut drained = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        drained.push(event);
+    }
+    drained
+}
+
+#[test]
+fn commands_parse_with_their_ids() {
+    assert_eq!(
+        stdio::parse(r#"{"id": 1, "command": "publish", "text": "hello"}"#),
+        (
+            Some(json!(1)),
+            Ok(ChatCommand::Publish {
+                text: "hello".to_string()
+            })
+        )
+    );
+    assert_eq!(
+        stdio::parse(r#"{"command": "dial", "address": "/ip4/127.0.0.1/tcp/4001"}"#),
+        (
+            None,
+            Ok(ChatCommand::Dial {
+                address: "/ip4/127.0.0.1/tcp/4001".parse().unwrap()
+            })
+        )
+    );
+    assert_eq!(
+        stdio::parse(r#"{"id": "q", "command": "query", "what": "history"}"#),
+        (
+            Some(json!("q")),
+            Ok(ChatCommand::Query {
+                what: Query::History
+            })
+        )
+    );
+    assert_eq!(
+        stdio::parse(r#"{"command": "join", "invite": "p2pchat-invite:00"}"#).1,
+        Ok(ChatCommand::Join {
+            invite: "p2pchat-invite:00".to_string()
+        })
+    );
+
+    // Lines that aren't commands keep what id they have, for the error reply
+    let (id, command) = stdio::parse("/help");
+    assert_eq!(id, None);
+    assert!(command.unwrap_err().starts_with("not JSON"));
+    let (id, command) = stdio::parse("[1, 2]");
+    assert_eq!((id, command), (None, Err("not a JSON object".to_string())));
+    let (id, command) = stdio::parse(r#"{"id": 7, "command": "shout"}"#);
+    assert_eq!(id, Some(json!(7)));
+    assert!(command.unwrap_err().contains("unknown variant `shout`"));
+    let (id, command) = stdio::parse(r#"{"id": 8, "command": "dial", "address": "nowhere"}"#);
+    assert_eq!(id, Some(json!(8)));
+    assert!(command.is_err());
+}
+
+#[test]
+fn events_encode_as_one_object_per_line() {
+    let peer = PeerId::random();
+    assert_eq!(
+        ChatEvent::Done {
+            id: Some(json!(1)),
+            result: None
+        }
+        .encode(),
+        r#"{"event":"done","id":1}"#
+    );
+    assert_eq!(
+        ChatEvent::Error {
+            id: None,
+            error: "not a JSON object".to_string()
+        }
+        .encode(),
+        r#"{"event":"error","error":"not a JSON object"}"#
+    );
+    assert_eq!(
+        ChatEvent::Connected { peer }.encode(),
+        format!(r#"{{"event":"connected","peer":"{peer}"}}"#)
+    );
+
+    // Roster changes become events of their own, and multi-line text stays on one line
+    let joined = ChatEvent::from(RosterEvent::Joined {
+        room: "lobby".to_string(),
+        peer,
+    });
+    assert_eq!(
+        joined,
+        ChatEvent::Joined {
+            room: "lobby".to_string(),
+            peer
+        }
+    );
+    let message = ChatEvent::Message {
+        id: "abc".to_string(),
+        room: "lobby".to_string(),
+        peer: None,
+        nick: "alice".to_string(),
+        text: "two\nlines".to_string(),
+        timestamp: 1_700_000_000,
+    };
+    let line = message.encode();
+    assert!(!line.contains('\n'));
+    assert_eq!(serde_json::from_str::<ChatEvent>(&line).unwrap(), message);
+}
+
+#[tokio::test]
+async fn a_node_runs_json_commands_and_reports_events() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--io", "json"])).await;
+    let (mut bob, bob_addr) =
+        common::spawn_chat_node(&common::cli(&["--io", "json", "--nick", "bob"])).await;
+    let mut alice_events = alice.subscribe_events();
+    let mut bob_events = bob.subscribe_events();
+    let bob_id = bob.local_peer_id();
+
+    // Dialing replies once connected
+    let dial = json!({
+        "id": 1,
+        "command": "dial",
+        "address": bob_addr.with(Protocol::P2p(bob_id)),
+    })
+    .to_string();
+    tokio::select! {
+        () = alice.handle_line(&dial) => {}
+        _ = async {
+            loop {
+                let event = bob.swarm.select_next_some().await;
+                bob.handle_event(event);
+            }
+        } => unreachable!(),
+    }
+    assert_eq!(
+        drain(&mut alice_events),
+        [
+            ChatEvent::Connected { peer: bob_id },
+            ChatEvent::Done {
+                id: Some(json!(1)),
+                result: None
+            }
+        ]
+    );
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    // Published text is sent as it is, slash and all, and arrives as a message event
+    alice
+        .handle_line(r#"{"id": 2, "command": "publish", "text": "/help is just text"}"#)
+        .await;
+    assert_eq!(
+        drain(&mut alice_events),
+        [ChatEvent::Done {
+            id: Some(json!(2)),
+            result: None
+        }]
+    );
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+    let arrived: Vec<_> = drain(&mut bob_events)
+        .into_iter()
+        .filter(|event| matches!(event, ChatEvent::Message { .. }))
+        .collect();
+    let [ChatEvent::Message {
+        peer, nick, text, ..
+    }] = arrived.as_slice()
+    else {
+        panic!("one message event, not {arrived:?}");
+    };
+    assert_eq!(
+        (*peer, nick.as_str(), text.as_str()),
+        (
+            Some(alice.local_peer_id()),
+            alice.nick(),
+            "/help is just text"
+        )
+    );
+
+    // Queries answer in the reply
+    bob.handle_line(r#"{"id": 3, "command": "query", "what": "history"}"#)
+        .await;
+    bob.handle_line(r#"{"id": 4, "command": "query", "what": "stats"}"#)
+        .await;
+    let replies = drain(&mut bob_events);
+    let [ChatEvent::Done {
+        result: Some(history),
+        ..
+    }, ChatEvent::Done {
+        result: Some(stats),
+        ..
+    }] = replies.as_slice()
+    else {
+        panic!("two replies, not {replies:?}");
+    };
+    assert_eq!(history[0]["event"], "message");
+    assert_eq!(history[0]["text"], "/help is just text");
+    assert_eq!(stats["peers"], 1);
+
+    // A bad line is answered with an error, and the node carries on
+    bob.handle_line("hello?").await;
+    bob.handle_line(r#"{"id": 5, "command": "join", "invite": "bogus"}"#)
+        .await;
+    let replies = drain(&mut bob_events);
+    assert!(matches!(
+        replies.as_slice(),
+        [ChatEvent::Error { id: None, .. }, ChatEvent::Error { id: Some(id), .. }] if *id == json!(5)
+    ));
+    assert_eq!(bob.history().count(), 1);
+}
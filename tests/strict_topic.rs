@@ -0,0 +1,37 @@
+// `--strict-topic`: messages relayed for topics the node isn't subscribed to.
+mod common;
+
+use concurrent_chat_server::{chat::ChatNode, message::ChatMessage};
+use libp2p::{
+    gossipsub::{self, MessageAcceptance},
+    PeerId,
+};
+
+fn relayed(topic: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new("mallory", "not meant for you", 0);
+    let topic = gossipsub::IdentTopic::new(topic).hash();
+    common::message_event_on(topic, PeerId::random(), 1, chat.encode())
+}
+
+#[test]
+fn strict_topic_drops_messages_for_other_topics() {
+    let mut node = ChatNode::new(&common::cli(&["--strict-topic"])).unwrap();
+    let validation = node.receive(relayed("elsewhere")).unwrap();
+    assert!(matches!(validation.acceptance, MessageAcceptance::Ignore));
+    assert_eq!(node.history().count(), 0);
+    assert_eq!(node.stats().counters.out_of_topic, 1);
+
+    // Messages on the chat topic are unaffected
+    let validation = node.receive(relayed(&common::topic().to_string())).unwrap();
+    assert!(matches!(validation.acceptance, MessageAcceptance::Accept));
+    assert_eq!(node.history().count(), 1);
+    assert!(node.stats().to_string().contains("out of topic: 1"));
+}
+
+#[test]
+fn other_topics_are_processed_without_the_flag() {
+    let mut node = ChatNode::new(&common::cli(&[])).unwrap();
+    node.receive(relayed("elsewhere"));
+    assert_eq!(node.history().count(), 1);
+    assert_eq!(node.stats().counters.out_of_topic, 0);
+}
@@ -0,0 +1,176 @@
+// The room's shared task list: `/task` commands, merging concurrent edits and syncing peers.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, TaskCommand, UserCommand},
+    tasks::{self, TaskList},
+};
+use libp2p::PeerId;
+
+#[test]
+fn task_commands_parse() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(parse("/task"), Ok(UserCommand::Task(TaskCommand::List)));
+    assert_eq!(
+        parse("/task add Book the venue"),
+        Ok(UserCommand::Task(TaskCommand::Add(
+            "Book the venue".to_string()
+        )))
+    );
+    assert_eq!(
+        parse("/task undone 3f2a"),
+        Ok(UserCommand::Task(TaskCommand::Done {
+            task: "3f2a".to_string(),
+            done: false
+        }))
+    );
+    assert_eq!(
+        parse("/task assign 3f2a bob"),
+        Ok(UserCommand::Task(TaskCommand::Assign {
+            task: "3f2a".to_string(),
+            assignee: Some("bob".to_string())
+        }))
+    );
+    assert_eq!(
+        parse("/task assign 3f2a"),
+        Ok(UserCommand::Task(TaskCommand::Assign {
+            task: "3f2a".to_string(),
+            assignee: None
+        }))
+    );
+    assert!(parse("/task add").is_err());
+    assert!(parse("/task done").is_err());
+    assert!(parse("/task frobnicate").is_err());
+}
+
+#[test]
+fn concurrent_edits_converge_whatever_the_order() {
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    let mut one = TaskList::default();
+    let (id, add) = one.add("Book the venue", 100);
+    let mut two = TaskList::default();
+    assert!(two.merge(&add));
+    assert!(!two.merge(&add), "a delta is only applied once");
+
+    // Alice removes the task while Bob, unaware of that, marks it done and adds another
+    let remove = one.remove(&id).unwrap();
+    let done = two.set_done(&id, true, bob, 101).unwrap();
+    let (other, add_other) = two.add("Order snacks", 102);
+    let assigned = one.assign(&id, Some("alice"), alice, 103);
+    assert!(assigned.is_none(), "removed tasks can't be changed");
+    for delta in [&done, &add_other] {
+        one.merge(delta);
+    }
+    two.merge(&remove);
+    assert_eq!(one, two);
+    assert_eq!(one.tasks().len(), 1);
+    assert!(one.get(&id).is_none());
+
+    // Whole lists merge like deltas
+    two.set_done(&other, true, bob, 150).unwrap();
+    one.merge(&two);
+    assert!(one.get(&other).unwrap().done);
+
+    // Concurrent assignments: the later write wins on both sides
+    let to_alice = one.assign(&other, Some("alice"), alice, 200).unwrap();
+    let to_bob = two.assign(&other, Some("bob"), bob, 201).unwrap();
+    one.merge(&to_bob);
+    two.merge(&to_alice);
+    assert_eq!(one, two);
+    assert_eq!(one.get(&other).unwrap().assignee.as_deref(), Some("bob"));
+
+    // A change made after seeing another wins even with a slow clock
+    let unassign = one.assign(&other, None, alice, 50).unwrap();
+    two.merge(&unassign);
+    assert_eq!(two.get(&other).unwrap().assignee, None);
+    assert_eq!(one.find(&other.to_string()[..8]).unwrap().id, other);
+}
+
+#[test]
+fn received_titles_are_cleaned() {
+    let mut sender = TaskList::default();
+    let (short, add_short) = sender.add("Book\nthe venue\u{202e}", 1);
+    let (long, add_long) = sender.add(&"venue ".repeat(tasks::MAX_TASK_TITLE_CHARS), 2);
+    let mut receiver = TaskList::default();
+    receiver.merge(&add_short);
+    receiver.merge(&add_long);
+    assert_eq!(receiver.get(&short).unwrap().title, "Book the venue");
+    let title = receiver.get(&long).unwrap().title;
+    assert_eq!(title.chars().count(), tasks::MAX_TASK_TITLE_CHARS);
+}
+
+#[test]
+fn task_lists_are_saved_per_room() {
+    let path = env::temp_dir().join(format!("p2p-chat-tasks-{}.tasks.json", process::id()));
+    let mut lobby = tasks::load(&path, "lobby").unwrap();
+    let (id, _) = lobby.add("Book the venue", 1);
+    lobby.set_done(&id, true, PeerId::random(), 2);
+    tasks::save(&path, "lobby", &lobby).unwrap();
+    let mut other = tasks::load(&path, "other").unwrap();
+    assert!(other.is_empty());
+    other.add("Order snacks", 1);
+    tasks::save(&path, "other", &other).unwrap();
+
+    let lobby = tasks::load(&path, "lobby").unwrap();
+    assert!(lobby.get(&id).unwrap().done);
+    assert_eq!(tasks::load(&path, "other").unwrap().tasks().len(), 1);
+    fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn task_changes_reach_the_room_and_newcomers() {
+    let dir = env::temp_dir();
+    let file = |name: &str| dir.join(format!("p2p-chat-tasks-{name}-{}.json", process::id()));
+    let (alice_config, bob_config, carol_config) = (file("a"), file("b"), file("c"));
+    let config = |path: &std::path::Path| common::cli(&["--config", path.to_str().unwrap()]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&config(&alice_config)).await;
+    let (mut bob, _) = common::spawn_chat_node(&config(&bob_config)).await;
+    bob.swarm.dial(alice_addr.clone()).unwrap();
+    let topic = tasks::tasks_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    alice.handle_line("/task add Book the venue").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.tasks().tasks().len() == 1
+    })
+    .await;
+    let id = bob.tasks().tasks()[0].id;
+    bob.handle_line(&format!("/task assign {} bob", &id.to_string()[..8]))
+        .await;
+    bob.handle_line(&format!("/task done {id}")).await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice
+            .tasks()
+            .get(&id)
+            .is_some_and(|task| task.done && task.assignee.as_deref() == Some("bob"))
+    })
+    .await;
+    assert_eq!(alice.history().count(), 0, "tasks aren't history");
+    drop(bob);
+
+    // A newcomer gets the list from the members already there
+    let (mut carol, _) = common::spawn_chat_node(&config(&carol_config)).await;
+    carol.swarm.dial(alice_addr).unwrap();
+    common::run_until(
+        &mut alice,
+        &mut carol,
+        Duration::from_secs(10),
+        |_, carol| carol.tasks().get(&id).is_some_and(|task| task.done),
+    )
+    .await;
+
+    // And the list outlives a restart
+    drop(alice);
+    let alice = ChatNode::new(&config(&alice_config)).unwrap();
+    assert!(alice.tasks().get(&id).unwrap().done);
+    for path in [alice_config, bob_config, carol_config] {
+        let _ = fs::remove_file(tasks::path_beside(&path));
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,138 @@
+// How queued terminal output is laid out when the printer writes it.
+use std::time::{Duration, Instant};
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    output::{Backlog, Output, MAX_LAG, OUTPUT_BUFFER},
+};
+
+fn push_lines(backlog: &mut Backlog, lines: &[&str], at: Instant) {
+    for line in lines {
+        backlog.push(Output::Stdout(line.to_string()), at).unwrap();
+    }
+}
+
+// The text of each write, prefixed with the stream it goes to.
+fn writes(batch: Vec<Output>) -> Vec<String> {
+    batch
+        .into_iter()
+        .map(|output| match output {
+            Output::Stdout(text) => format!("out:{text}"),
+            Output::Stderr(text) => format!("err:{text}"),
+            Output::Flushed(_) => "flushed".to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn a_burst_is_written_at_once_with_repeats_collapsed() {
+    let now = Instant::now();
+    let mut backlog = Backlog::new(false);
+    push_lines(&mut backlog, &["hi", "spam", "spam", "spam", "bye"], now);
+    backlog
+        .push(Output::Stderr("[ban] logged\n".to_string()), now)
+        .unwrap();
+    push_lines(&mut backlog, &["spam", "spam"], now);
+
+    assert_eq!(
+        writes(backlog.take(now)),
+        [
+            "out:hi\nspam ×3\nbye\n",
+            "err:[ban] logged\n",
+            "out:spam ×2\n"
+        ]
+    );
+    assert!(backlog.is_empty());
+    assert!(backlog.take(now).is_empty());
+}
+
+#[test]
+fn a_terminal_skips_what_it_fell_behind_on() {
+    let then = Instant::now();
+    let now = then + MAX_LAG + Duration::from_secs(1);
+    let mut backlog = Backlog::new(false);
+    let stale: Vec<String> = (0..312).map(|n| format!("old {n}")).collect();
+    let stale: Vec<&str> = stale.iter().map(String::as_str).collect();
+    push_lines(&mut backlog, &stale, then);
+    push_lines(&mut backlog, &["new"], now);
+
+    assert_eq!(
+        writes(backlog.take(now)),
+        ["out:… 312 messages not shown, see /history …\nnew\n"]
+    );
+    // The marker is only shown once
+    push_lines(&mut backlog, &["newer"], now);
+    assert_eq!(writes(backlog.take(now)), ["out:newer\n"]);
+}
+
+#[test]
+fn a_full_terminal_backlog_drops_its_oldest_lines() {
+    let now = Instant::now();
+    let mut backlog = Backlog::new(false);
+    for n in 0..OUTPUT_BUFFER + 10 {
+        backlog
+            .push(Output::Stdout(format!("line {n}")), now)
+            .unwrap();
+    }
+    assert_eq!(backlog.len(), OUTPUT_BUFFER);
+    let batch = writes(backlog.take(now));
+    let text = batch[0].strip_prefix("out:").unwrap();
+    let mut lines = text.lines();
+    assert_eq!(
+        lines.next(),
+        Some("… 10 messages not shown, see /history …")
+    );
+    assert_eq!(lines.next(), Some("line 10"));
+    assert_eq!(
+        lines.last(),
+        Some(format!("line {}", OUTPUT_BUFFER + 9).as_str())
+    );
+}
+
+#[test]
+fn a_pipe_gets_every_line_and_holds_the_sender_up_instead() {
+    let then = Instant::now();
+    let now = then + MAX_LAG * 10;
+    let mut backlog = Backlog::new(true);
+    for _ in 0..OUTPUT_BUFFER {
+        backlog
+            .push(Output::Stdout("same".to_string()), then)
+            .unwrap();
+    }
+    let refused = backlog.push(Output::Stdout("one more".to_string()), then);
+    assert!(matches!(refused, Err(Output::Stdout(line)) if line == "one more"));
+
+    let batch = writes(backlog.take(now));
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch[0], format!("out:{}", "same\n".repeat(OUTPUT_BUFFER)));
+    assert!(backlog
+        .push(Output::Stdout("one more".to_string()), now)
+        .is_ok());
+}
+
+#[test]
+fn flushes_keep_their_place() {
+    let now = Instant::now();
+    let mut backlog = Backlog::new(false);
+    let (done, _written) = std::sync::mpsc::channel();
+    push_lines(&mut backlog, &["before"], now);
+    backlog.push(Output::Flushed(done), now).unwrap();
+    push_lines(&mut backlog, &["after"], now);
+    assert_eq!(
+        writes(backlog.take(now)),
+        ["out:before\n", "flushed", "out:after\n"]
+    );
+}
+
+#[test]
+fn history_takes_an_optional_count() {
+    assert_eq!(
+        commands::parse("/history"),
+        Some(Ok(UserCommand::History(20)))
+    );
+    assert_eq!(
+        commands::parse("/history 300"),
+        Some(Ok(UserCommand::History(300)))
+    );
+    assert!(commands::parse("/history lots").unwrap().is_err());
+}
@@ -0,0 +1,142 @@
+// Aliases a room's chat messages travel under, picked and rotated by its moderators.
+mod common;
+
+use std::{env, fs, process, time::Duration};
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    config::Config,
+    control, identity,
+    topic_alias::{AliasResolver, TopicAlias},
+};
+use libp2p::gossipsub::TopicHash;
+
+fn alias(alias: &str, issued_at: u64) -> TopicAlias {
+    TopicAlias {
+        room: common::topic().hash().into_string(),
+        alias: alias.to_string(),
+        issued_at,
+    }
+}
+
+#[test]
+fn rooms_resolve_to_their_newest_alias() {
+    let room = common::topic().hash().into_string();
+    let mut resolver = AliasResolver::default();
+    assert_eq!(resolver.resolve(&room), TopicHash::from_raw(&room));
+
+    assert!(resolver.learn(alias("0123456789ab", 100)));
+    let (prefix, _) = room.rsplit_once('/').unwrap();
+    assert_eq!(
+        resolver.resolve(&room),
+        TopicHash::from_raw(format!("{prefix}/0123456789ab"))
+    );
+    // An older alias, or the same one again, changes nothing
+    assert!(!resolver.learn(alias("ba9876543210", 99)));
+    assert!(!resolver.learn(alias("0123456789ab", 100)));
+    // Of two picked in the same second, every member keeps the greater
+    assert!(resolver.learn(alias("ffffffffffff", 100)));
+    assert!(!resolver.learn(alias("0123456789ab", 100)));
+    assert!(resolver.learn(alias("000000000000", 101)));
+    assert_eq!(resolver.get(&room).unwrap().alias, "000000000000");
+    // Other rooms keep their own names
+    assert_eq!(resolver.resolve("other"), TopicHash::from_raw("other"));
+
+    assert!(alias("0123456789ab", 100).validate(100).is_ok());
+    assert!(alias("0123456789AB", 100).validate(100).is_err());
+    assert!(alias("0123", 100).validate(100).is_err());
+    assert!(alias("../chat/abcd", 100).validate(100).is_err());
+    assert!(alias("0123456789ab", 10_000).validate(100).is_err());
+    let fresh = TopicAlias::generate(&room, 100);
+    assert!(fresh.validate(100).is_ok());
+    assert_ne!(fresh.alias, TopicAlias::generate(&room, 100).alias);
+}
+
+#[test]
+fn topic_alias_commands_parse() {
+    assert_eq!(
+        commands::parse("/topic-alias"),
+        Some(Ok(UserCommand::TopicAlias))
+    );
+    assert_eq!(
+        commands::parse("/topic-alias rotate"),
+        Some(Ok(UserCommand::TopicAliasRotate))
+    );
+    assert!(matches!(commands::parse("/topic-alias off"), Some(Err(_))));
+}
+
+#[tokio::test]
+async fn members_follow_their_moderators_alias() {
+    let path = |name: &str, ext: &str| {
+        let path = env::temp_dir().join(format!(
+            "p2p-chat-topic-alias-{name}-{}.{ext}",
+            process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    };
+    let alice_key = path("alice", "key");
+    let alice_id = identity::load_or_create(alice_key.as_ref())
+        .unwrap()
+        .public()
+        .to_peer_id()
+        .to_string();
+    let (alice_config, bob_config) = (path("alice", "json"), path("bob", "json"));
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&[
+        "--identity",
+        &alice_key,
+        "--config",
+        &alice_config,
+        "--moderator",
+        &alice_id,
+    ]))
+    .await;
+    let (mut bob, bob_addr) = common::spawn_chat_node(&common::cli(&[
+        "--config",
+        &bob_config,
+        "--moderator",
+        &alice_id,
+    ]))
+    .await;
+    alice.swarm.dial(bob_addr).unwrap();
+    let control = control::control_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &control) && common::has_subscriber(b, &control)
+    })
+    .await;
+
+    // Bob is no moderator, so he can't move the room
+    bob.handle_line("/topic-alias rotate").await;
+    assert_eq!(bob.wire_topic().hash(), common::topic().hash());
+
+    alice.handle_line("/topic-alias rotate").await;
+    let aliased = alice.wire_topic().clone();
+    assert_ne!(aliased.hash(), common::topic().hash());
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        b.wire_topic().hash() == aliased.hash() && common::has_subscriber(a, &aliased)
+    })
+    .await;
+    assert!(!common::has_subscriber(&alice, &common::topic()));
+
+    // Chat goes on under the alias, still filed under the room's own name
+    bob.handle_line("hello under the alias").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, _| {
+        a.history()
+            .any(|stored| &*stored.message.body == "hello under the alias")
+    })
+    .await;
+    let stored = alice.history().last().unwrap();
+    assert_eq!(stored.topic, common::topic().hash().as_str());
+
+    // The alias is kept for the next run
+    bob.flush_writes().await;
+    let saved = Config::load(bob_config.as_ref()).unwrap();
+    assert_eq!(
+        saved.topic_aliases.resolve(common::topic().hash().as_str()),
+        aliased.hash()
+    );
+    alice.flush_writes().await;
+    for path in [alice_key, alice_config, bob_config] {
+        fs::remove_file(path).unwrap();
+    }
+}
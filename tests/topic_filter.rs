@@ -0,0 +1,118 @@
+// Client-side message filters and their persistence in the config file.
+mod common;
+
+use std::{env, process, time::Duration};
+
+use concurrent_chat_server::{
+    commands::{self, FilterCommand, UserCommand},
+    config::Config,
+    filter::TopicFilter,
+    message::ChatMessage,
+};
+
+fn message(nick: &str, body: &str, timestamp: u64) -> ChatMessage {
+    ChatMessage::new(nick, body, timestamp)
+}
+
+#[test]
+fn every_criterion_has_to_match() {
+    let filter = TopicFilter::parse("nick:alice,Bob since:100 body:rust|libp2p").unwrap();
+    assert_eq!(
+        filter.nick_includes,
+        Some(vec!["alice".to_string(), "Bob".to_string()])
+    );
+    assert_eq!(filter.min_timestamp, Some(100));
+
+    assert!(filter.matches(&message("bob", "I like rust", 100)));
+    assert!(!filter.matches(&message("carol", "I like rust", 100)));
+    assert!(!filter.matches(&message("alice", "I like go", 100)));
+    assert!(!filter.matches(&message("alice", "I like rust", 99)));
+}
+
+#[test]
+fn body_regex_takes_the_rest_of_the_line() {
+    let filter = TopicFilter::parse("body:hello world nick:x").unwrap();
+    assert_eq!(filter.nick_includes, None);
+    assert!(filter.matches(&message("anyone", "say hello world nick:x", 0)));
+    assert_eq!(filter.to_string(), "body:hello world nick:x");
+}
+
+#[test]
+fn invalid_filters_are_rejected() {
+    assert!(TopicFilter::parse("").is_err());
+    assert!(TopicFilter::parse("nick:").is_err());
+    assert!(TopicFilter::parse("since:yesterday").is_err());
+    assert!(TopicFilter::parse("body:(").is_err());
+    assert!(TopicFilter::parse("colour:red").is_err());
+}
+
+#[test]
+fn filter_command_parses() {
+    assert_eq!(
+        commands::parse("/filter set nick:alice,bob"),
+        Some(Ok(UserCommand::Filter(FilterCommand::Set(
+            TopicFilter::parse("nick:alice,bob").unwrap()
+        ))))
+    );
+    assert_eq!(
+        commands::parse("/filter"),
+        Some(Ok(UserCommand::Filter(FilterCommand::Show)))
+    );
+    assert_eq!(
+        commands::parse("/filter clear"),
+        Some(Ok(UserCommand::Filter(FilterCommand::Clear)))
+    );
+}
+
+#[test]
+fn filters_survive_a_config_round_trip() {
+    let path = env::temp_dir().join(format!("p2p-chat-filter-{}.json", process::id()));
+    let mut config = Config::default();
+    config.filters.insert(
+        "general".to_string(),
+        TopicFilter::parse("nick:alice body:^!").unwrap(),
+    );
+    config.save(&path).unwrap();
+    let loaded = Config::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, config);
+
+    // A missing file just means nothing has been configured yet
+    assert_eq!(Config::load(&path).unwrap(), Config::default());
+}
+
+#[tokio::test]
+async fn filtered_messages_are_stored_but_counted_as_hidden() {
+    let config = env::temp_dir().join(format!("p2p-chat-filter-e2e-{}.json", process::id()));
+    let config_arg = config.to_str().unwrap();
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--nick", "alice"])).await;
+    let (mut bob, bob_addr) =
+        common::spawn_chat_node(&common::cli(&["--config", config_arg])).await;
+    bob.handle_line("/filter set nick:carol").await;
+    assert!(bob.filter().is_some());
+
+    alice.swarm.dial(bob_addr).unwrap();
+    let topic = alice.topic().clone();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        common::has_subscriber(alice, &topic)
+    })
+    .await;
+
+    alice.handle_line("hi bob").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+
+    let stored = bob.history().next().unwrap();
+    assert_eq!(stored.message.nick, "alice");
+    assert_eq!(&*stored.message.body, "hi bob");
+    assert!(!stored.shown);
+    assert_eq!(bob.filtered_count(), 1);
+
+    // The filter was persisted for the chat topic
+    bob.flush_writes().await;
+    let saved = Config::load(&config).unwrap();
+    std::fs::remove_file(&config).unwrap();
+    assert!(saved.filters.contains_key(topic.hash().as_str()));
+}
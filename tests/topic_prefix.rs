@@ -0,0 +1,89 @@
+// Topic prefixes that keep separate deployments on one network apart.
+mod common;
+
+use std::time::Duration;
+
+use clap::Parser;
+use concurrent_chat_server::{
+    chat::ChatNode,
+    cli::Cli,
+    control,
+    node::{self, DEFAULT_TOPIC_PREFIX, TOPIC},
+};
+
+#[test]
+fn every_topic_name_starts_with_the_prefix() {
+    assert_eq!(common::topic().hash().as_str(), format!("p2pchat/{TOPIC}"));
+    assert_eq!(
+        control::control_topic().hash().as_str(),
+        format!("p2pchat/{TOPIC}/_control")
+    );
+
+    let node = ChatNode::new(&common::cli(&["--topic-prefix", "team-a"])).unwrap();
+    assert_eq!(node.topic().hash().as_str(), format!("team-a/{TOPIC}"));
+    let subscribed: Vec<String> = node
+        .swarm
+        .behaviour()
+        .gossipsub
+        .topics()
+        .map(|topic| topic.to_string())
+        .collect();
+    assert_eq!(subscribed.len(), 8);
+    assert!(subscribed.iter().all(|topic| topic.starts_with("team-a/")));
+}
+
+#[test]
+fn prefixes_are_short_letters_digits_and_hyphens() {
+    assert_eq!(common::cli(&[]).topic_prefix, DEFAULT_TOPIC_PREFIX);
+    let long = "a".repeat(32);
+    for ok in ["team-a", "Lab42", long.as_str()] {
+        let cli = Cli::try_parse_from(["p2p-chat", "--topic-prefix", ok]).unwrap();
+        assert_eq!(cli.topic_prefix, ok);
+    }
+    let too_long = "a".repeat(33);
+    for bad in [
+        "",
+        "team/a",
+        "team a",
+        "équipe",
+        "_control",
+        too_long.as_str(),
+    ] {
+        assert!(
+            Cli::try_parse_from(["p2p-chat", "--topic-prefix", bad]).is_err(),
+            "{bad:?} accepted"
+        );
+    }
+}
+
+#[test]
+fn only_chat_nodes_with_another_prefix_are_other_deployments() {
+    let ours = node::protocol_version("team-a");
+    assert!(!node::is_other_deployment(&ours, "team-a"));
+    assert!(node::is_other_deployment(&ours, "team-b"));
+    // Nodes from before prefixes, and peers that aren't chat nodes at all, such as a relay
+    assert!(node::is_other_deployment(node::PROTOCOL_VERSION, "team-a"));
+    assert!(!node::is_other_deployment("/ipfs/0.1.0", "team-a"));
+}
+
+#[tokio::test]
+async fn nodes_of_another_deployment_are_disconnected() {
+    let (mut alice, _) = common::spawn_chat_node(&common::cli(&["--topic-prefix", "team-a"])).await;
+    let (mut bob, bob_addr) =
+        common::spawn_chat_node(&common::cli(&["--topic-prefix", "team-b"])).await;
+    let bob_id = bob.local_peer_id();
+
+    alice.swarm.dial(bob_addr).unwrap();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.swarm.is_connected(&bob_id)
+    })
+    .await;
+    // Once identified, and for good
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        !alice.swarm.is_connected(&bob_id)
+    })
+    .await;
+    common::run_for(&mut alice, &mut bob, Duration::from_secs(2)).await;
+    assert!(!alice.swarm.is_connected(&bob_id));
+    assert!(!common::has_subscriber(&alice, alice.topic()));
+}
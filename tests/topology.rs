@@ -0,0 +1,85 @@
+// `/export-topology`: parsing, the DOT output and the links of two connected nodes.
+mod common;
+
+use std::{env, fs, path::PathBuf, process, time::Duration};
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand},
+    topology::{Link, Topology, TopologyPeer},
+};
+use libp2p::PeerId;
+
+fn peer(name: &str, score: Option<f64>, link: Option<Link>) -> TopologyPeer {
+    TopologyPeer {
+        peer: PeerId::random(),
+        name: name.to_string(),
+        score,
+        link,
+    }
+}
+
+#[test]
+fn export_topology_parses() {
+    assert_eq!(
+        commands::parse("/export-topology mesh.dot"),
+        Some(Ok(UserCommand::ExportTopology(PathBuf::from("mesh.dot"))))
+    );
+    assert!(commands::parse("/export-topology").unwrap().is_err());
+}
+
+#[test]
+fn mesh_links_are_solid_and_others_dashed() {
+    let topology = Topology {
+        local: peer("me", None, None),
+        peers: vec![
+            peer("alice", Some(1.5), Some(Link::Mesh)),
+            peer("bob", None, Some(Link::Metadata)),
+            peer("carol\" [color=red", None, None),
+        ],
+    };
+    let dot = topology.to_dot();
+    assert!(dot.starts_with("graph topology {\n"));
+    assert!(dot.ends_with("}\n"));
+    let [me, alice, bob, carol] = [
+        &topology.local,
+        &topology.peers[0],
+        &topology.peers[1],
+        &topology.peers[2],
+    ]
+    .map(|node| node.peer);
+    assert!(dot.contains(&format!("\"{me}\" [label=\"me\", style=bold];")));
+    assert!(dot.contains(&format!("\"{alice}\" [label=\"alice\\nscore 1.50\"];")));
+    assert!(dot.contains(&format!("\"{me}\" -- \"{alice}\" [style=solid];")));
+    assert!(dot.contains(&format!("\"{me}\" -- \"{bob}\" [style=dashed];")));
+    // Peers we aren't connected to have no edge, and their names can't break out of the label
+    assert!(!dot.contains(&format!("-- \"{carol}\"")));
+    assert!(dot.contains(r#"[label="carol\" [color=red"];"#));
+}
+
+#[tokio::test]
+async fn connected_peers_show_up_in_the_mesh() {
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&["--nick", "alice"])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let bob_id = bob.local_peer_id();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, _| {
+        a.topology()
+            .peers
+            .iter()
+            .any(|peer| peer.peer == bob_id && peer.link == Some(Link::Mesh))
+    })
+    .await;
+    let topology = alice.topology();
+    assert_eq!(topology.local.peer, alice.local_peer_id());
+    assert_eq!(topology.local.name, "alice");
+    assert_eq!(topology.peers.len(), 1);
+
+    let path = env::temp_dir().join(format!("p2p-chat-topology-{}.dot", process::id()));
+    alice
+        .handle_line(&format!("/export-topology {}", path.display()))
+        .await;
+    alice.flush_writes().await;
+    let dot = fs::read_to_string(&path).unwrap();
+    assert!(dot.contains(&format!("-- \"{bob_id}\" [style=solid];")));
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,232 @@
+// Fetches of attachments that survive a dropped connection or a restart and pick up where they
+// stopped.
+mod common;
+
+use std::{fs, time::Duration};
+
+use concurrent_chat_server::{
+    attachment::{AttachmentRef, Cid, CHUNK_BYTES},
+    chat::ChatNode,
+    commands::{self, TransfersCommand, UserCommand},
+    transfer::{self, Manifest, Ranges, State, Transfer, Transfers, MAX_IN_FLIGHT},
+};
+use libp2p::PeerId;
+
+fn attachment(size: u64) -> AttachmentRef {
+    AttachmentRef {
+        cid: Cid::of(&size.to_be_bytes()),
+        size,
+        mime_type: "application/octet-stream".to_string(),
+        filename: "big.bin".to_string(),
+    }
+}
+
+fn manifest(size: u64) -> Manifest {
+    Manifest {
+        attachment: attachment(size),
+        sender: PeerId::random(),
+        destination: common::temp_path("big.bin"),
+        done: Ranges::default(),
+    }
+}
+
+#[test]
+fn ranges_merge_and_name_the_chunks_still_missing() {
+    let mut ranges = Ranges::default();
+    ranges.insert(CHUNK_BYTES, 2 * CHUNK_BYTES);
+    ranges.insert(3 * CHUNK_BYTES, 4 * CHUNK_BYTES);
+    ranges.insert(2 * CHUNK_BYTES, 3 * CHUNK_BYTES);
+    assert_eq!(ranges.covered(), 3 * CHUNK_BYTES);
+    assert!(ranges.contains(CHUNK_BYTES, 4 * CHUNK_BYTES));
+
+    let size = 5 * CHUNK_BYTES + 1;
+    let missing: Vec<(u64, u64)> = ranges.missing(size).collect();
+    assert_eq!(
+        missing,
+        [
+            (0, CHUNK_BYTES),
+            (4 * CHUNK_BYTES, CHUNK_BYTES),
+            (5 * CHUNK_BYTES, 1)
+        ]
+    );
+    assert_eq!(ranges.missing(0).count(), 0);
+}
+
+#[test]
+fn damaged_manifests_load_with_their_ranges_merged() {
+    let ranges: Ranges = serde_json::from_str("[[4, 2], [0, 10], [5, 20], [30, 30]]").unwrap();
+    assert_eq!(ranges, serde_json::from_str("[[0, 20]]").unwrap());
+    assert_eq!(ranges.covered(), 20);
+
+    // A range over a huge file is skipped in one step rather than chunk by chunk
+    let whole: Ranges = serde_json::from_str(&format!("[[0, {}]]", u64::MAX)).unwrap();
+    assert_eq!(whole.missing(u64::MAX).next(), None);
+    let most: Ranges = serde_json::from_str(&format!("[[0, {}]]", u64::MAX - 1)).unwrap();
+    let last = (u64::MAX - 1) / CHUNK_BYTES * CHUNK_BYTES;
+    assert_eq!(
+        most.missing(u64::MAX).collect::<Vec<_>>(),
+        [(last, u64::MAX - last)]
+    );
+}
+
+#[test]
+fn a_transfer_keeps_a_few_chunks_in_flight_and_stops_when_parked() {
+    let mut transfer = Transfer::new(manifest(10 * CHUNK_BYTES), State::Active);
+    let first = transfer.next_chunks();
+    assert_eq!(first.len(), MAX_IN_FLIGHT);
+    assert!(transfer.next_chunks().is_empty(), "all in flight already");
+
+    transfer.arrived(first[1].0, first[1].1);
+    assert_eq!(transfer.next_chunks(), [(4 * CHUNK_BYTES, CHUNK_BYTES)]);
+    assert_eq!(transfer.received(), CHUNK_BYTES);
+
+    assert!(transfer.park());
+    assert!(!transfer.park(), "parked already");
+    assert_eq!(transfer.in_flight(), 0);
+    assert!(transfer.next_chunks().is_empty());
+    // A chunk still arriving counts
+    transfer.arrived(first[0].0, first[0].1);
+    assert_eq!(transfer.received(), 2 * CHUNK_BYTES);
+    assert!(!transfer.is_complete());
+}
+
+#[test]
+fn saved_manifests_load_as_parked_transfers() {
+    let dir = common::temp_path("manifests");
+    let mut saved = manifest(3 * CHUNK_BYTES);
+    saved.done.insert(0, CHUNK_BYTES);
+    let transfers = Transfers::load(dir.clone());
+    assert!(transfers.is_empty());
+    let cid = saved.attachment.cid;
+    transfer::save_manifest(&transfers.manifest_path(&cid), &saved).unwrap();
+    fs::write(dir.join("notes.txt"), "not a manifest").unwrap();
+    fs::write(dir.join("broken.json"), "{").unwrap();
+
+    let transfers = Transfers::load(dir.clone());
+    assert_eq!(transfers.len(), 1);
+    let loaded = transfers.get(&cid).unwrap();
+    assert_eq!(loaded.state, State::Parked);
+    assert_eq!(loaded.manifest, saved);
+    assert_eq!(transfers.find(&cid.to_string()[..12]), Ok(cid));
+    assert!(transfers.find("bafkzzz").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn transfers_commands_parse() {
+    let list = Some(Ok(UserCommand::Transfers(TransfersCommand::List)));
+    assert_eq!(commands::parse("/transfers"), list);
+    assert_eq!(commands::parse("/transfers list"), list);
+    assert_eq!(
+        commands::parse("/transfers discard bafkrei"),
+        Some(Ok(UserCommand::Transfers(TransfersCommand::Discard(
+            "bafkrei".to_string()
+        ))))
+    );
+    assert!(commands::parse("/transfers discard").unwrap().is_err());
+}
+
+// Connect `bob` to `alice` and wait until both are in the room.
+async fn join(alice: &mut ChatNode, bob: &mut ChatNode, alice_addr: libp2p::Multiaddr) {
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(alice, bob, Duration::from_secs(10), |alice, bob| {
+        common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic)
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn an_interrupted_fetch_resumes_after_a_disconnect_and_a_restart() {
+    let dir = common::temp_path("node");
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.json");
+    let bob_cli = common::cli(&["--config", config.to_str().unwrap()]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, _) = common::spawn_chat_node(&bob_cli).await;
+    join(&mut alice, &mut bob, alice_addr.clone()).await;
+
+    let data: Vec<u8> = (0..16 * CHUNK_BYTES).map(|n| (n % 251) as u8).collect();
+    let cid = Cid::of(&data);
+    let source = dir.join("source.bin");
+    fs::write(&source, &data).unwrap();
+    alice
+        .handle_line(&format!("/attach {}", source.display()))
+        .await;
+    alice.handle_line("the recording").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 1
+    })
+    .await;
+    let id = bob.history().next().unwrap().id.clone();
+    let saved = dir.join("saved.bin");
+    bob.handle_line(&format!("/fetch {id} {}", saved.display()))
+        .await;
+    let received = |bob: &ChatNode| bob.transfers().get(&cid).map_or(0, Transfer::received);
+
+    // The connection drops midway: the fetch is parked with what arrived
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        received(bob) > 0
+    })
+    .await;
+    let _ = alice.swarm.disconnect_peer_id(bob.local_peer_id());
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.fetching() == 0
+    })
+    .await;
+    let parked = bob.transfers().get(&cid).unwrap();
+    assert_eq!(parked.state, State::Parked);
+    assert!(received(&bob) < data.len() as u64);
+
+    // Reconnecting resumes it, and killing the node midway leaves the partial file behind
+    join(&mut alice, &mut bob, alice_addr.clone()).await;
+    let before = received(&bob);
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        received(bob) > before
+    })
+    .await;
+    bob.flush_writes().await;
+    let partial = received(&bob);
+    assert!(partial < data.len() as u64);
+    drop(bob);
+
+    // The restarted node finds the partial file and fetches only what is missing
+    let (mut bob, _) = common::spawn_chat_node(&bob_cli).await;
+    let loaded = bob.transfers().get(&cid).unwrap();
+    assert_eq!(loaded.state, State::Parked);
+    assert_eq!(loaded.received(), partial);
+    join(&mut alice, &mut bob, alice_addr).await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.transfers().is_empty()
+    })
+    .await;
+    bob.flush_writes().await;
+    assert_eq!(fs::read(&saved).unwrap(), data);
+    assert_eq!(fs::read_dir(bob.transfers().dir()).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_discarded_fetch_leaves_nothing_behind() {
+    let dir = common::temp_path("discard");
+    let config = dir.join("config.json");
+    let (bob, _) =
+        common::spawn_chat_node(&common::cli(&["--config", config.to_str().unwrap()])).await;
+    let mut saved = manifest(3 * CHUNK_BYTES);
+    saved.done.insert(0, CHUNK_BYTES);
+    let cid = saved.attachment.cid;
+    let part = bob.transfers().part_path(&cid);
+    transfer::write_chunk(&part, 0, &vec![1; CHUNK_BYTES as usize]).unwrap();
+    transfer::save_manifest(&bob.transfers().manifest_path(&cid), &saved).unwrap();
+    drop(bob);
+
+    let (mut bob, _) =
+        common::spawn_chat_node(&common::cli(&["--config", config.to_str().unwrap()])).await;
+    assert_eq!(bob.transfers().len(), 1);
+    bob.handle_line(&format!("/transfers discard {}", &cid.to_string()[..16]))
+        .await;
+    bob.flush_writes().await;
+    assert!(bob.transfers().is_empty());
+    assert!(!part.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,74 @@
+// Out-of-band verification of peers with fingerprints.
+mod common;
+
+use std::{collections::HashSet, env, process};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    commands::{self, UserCommand},
+    message::ChatMessage,
+    verify::{Fingerprint, WORDS},
+};
+use libp2p::{gossipsub, PeerId};
+
+fn message(source: PeerId, seq: u64, nick: &str) -> gossipsub::Event {
+    let chat = ChatMessage::new(nick, format!("message {seq}"), 0);
+    common::message_event(source, seq, chat.encode())
+}
+
+#[test]
+fn fingerprints_are_stable_and_readable() {
+    let peer = PeerId::random();
+    let fingerprint = Fingerprint::of(&peer);
+    assert_eq!(fingerprint, Fingerprint::of(&peer));
+    assert_ne!(fingerprint, Fingerprint::of(&PeerId::random()));
+
+    let hex = fingerprint.hex();
+    assert_eq!(hex.len(), 19);
+    assert_eq!(hex.split(' ').count(), 4);
+    assert_eq!(fingerprint.words().split(' ').count(), 8);
+    assert_eq!(WORDS.iter().collect::<HashSet<_>>().len(), 256);
+
+    assert_eq!(
+        commands::parse("/verify"),
+        Some(Ok(UserCommand::Verify {
+            target: None,
+            confirm: false
+        }))
+    );
+    assert_eq!(
+        commands::parse("/verify alice confirm"),
+        Some(Ok(UserCommand::Verify {
+            target: Some("alice".to_string()),
+            confirm: true
+        }))
+    );
+    assert!(matches!(commands::parse("/verify alice yes"), Some(Err(_))));
+}
+
+#[tokio::test]
+async fn verified_peers_persist_and_impostors_are_not_bound() {
+    let config = env::temp_dir().join(format!("p2p-chat-verify-{}.json", process::id()));
+    let cli = common::cli(&["--config", config.to_str().unwrap()]);
+    let mut node = ChatNode::new(&cli).unwrap();
+    let alice = PeerId::random();
+    node.receive(message(alice, 0, "alice"));
+    assert_eq!(node.display_name(&alice), "alice");
+
+    node.handle_line("/verify alice confirm").await;
+    assert!(node.is_verified(&alice));
+    assert_eq!(node.display_name(&alice), "alice ✓");
+
+    // Someone else claiming the nick doesn't take it over
+    let impostor = PeerId::random();
+    node.receive(message(impostor, 0, "Alice"));
+    assert!(!node.is_verified(&impostor));
+    assert_eq!(node.display_name(&impostor), impostor.to_string());
+    assert_eq!(node.history().count(), 2);
+
+    // Verifications survive a restart
+    drop(node);
+    let node = ChatNode::new(&cli).unwrap();
+    assert!(node.is_verified(&alice));
+    std::fs::remove_file(config).unwrap();
+}
@@ -0,0 +1,263 @@
+// Webhooks: which messages they want, what is posted to them, batching, retries, resting a
+// failing webhook, and a node forwarding the room's messages.
+mod common;
+
+use std::{fs, time::Duration};
+
+use concurrent_chat_server::{
+    chat::ChatNode,
+    config::Config,
+    runtime,
+    webhook::{self, Forwarded, WebhookEvent, WebhookSettings, Webhooks},
+};
+use libp2p::PeerId;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+};
+
+// A web server taking one request at a time, answering with the status the test asks for.
+struct FakeEndpoint {
+    listener: TcpListener,
+}
+
+impl FakeEndpoint {
+    async fn bind() -> Self {
+        FakeEndpoint {
+            listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        }
+    }
+
+    fn url(&self) -> String {
+        format!("http://{}/hooks/team", self.listener.local_addr().unwrap())
+    }
+
+    // The request line and JSON body of the next request, answered with `status`.
+    async fn request(&self, status: u16) -> (String, Value) {
+        let (tcp, _) = runtime::timeout(Duration::from_secs(10), self.listener.accept())
+            .await
+            .expect("the webhook is posted to")
+            .unwrap();
+        let mut reader = BufReader::new(tcp);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await.unwrap();
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).await.unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await.unwrap();
+        let response = format!("HTTP/1.1 {status} Whatever\r\nContent-Length: 0\r\n\r\n");
+        reader
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .unwrap();
+        (
+            request_line.trim_end().to_string(),
+            serde_json::from_slice(&body).unwrap(),
+        )
+    }
+}
+
+fn message(nick: &str, body: &str) -> Forwarded {
+    Forwarded {
+        nick: nick.to_string(),
+        room: "ops".to_string(),
+        timestamp: 1_700_000_000,
+        body: body.to_string(),
+    }
+}
+
+#[test]
+fn settings_default_and_pick_the_messages_wanted() {
+    let settings: WebhookSettings =
+        serde_json::from_str(r#"{"url": "https://hooks.example.com/T0/B0"}"#).unwrap();
+    assert_eq!(
+        settings,
+        WebhookSettings::new("https://hooks.example.com/T0/B0")
+    );
+    assert_eq!(settings.template, "{nick}: {body}");
+    assert!(settings.check().is_ok());
+    for bad in [
+        WebhookSettings::new("ftp://hooks.example.com/"),
+        WebhookSettings::new("not a url"),
+        WebhookSettings {
+            template: " ".to_string(),
+            ..settings.clone()
+        },
+    ] {
+        assert!(bad.check().is_err(), "{bad:?} accepted");
+    }
+
+    let (alice, bob) = (PeerId::random(), PeerId::random());
+    assert!(settings.wants(&alice, "alice", "hello", "carol"));
+    let filtered = WebhookSettings {
+        mentions_only: true,
+        senders: vec!["Alice".to_string(), bob.to_string()],
+        ..settings
+    };
+    assert!(filtered.wants(&alice, "alice", "@carol look", "carol"));
+    assert!(filtered.wants(&bob, "robert", "CAROL: look", "carol"));
+    assert!(!filtered.wants(&alice, "alice", "hello", "carol"));
+    assert!(!filtered.wants(&PeerId::random(), "dave", "carol look", "carol"));
+
+    assert!(webhook::mentions("hi carol!", "carol"));
+    assert!(!webhook::mentions("caroline", "carol"));
+    assert!(!webhook::mentions("hi carol_2", "carol"));
+    assert!(!webhook::mentions("anything", ""));
+}
+
+#[test]
+fn batches_are_posted_as_rendered_text_and_messages() {
+    let template = "[{room}] {nick} at {timestamp}: {body}";
+    let one = message("alice", "deploy {nick} done");
+    assert_eq!(
+        webhook::render(template, &one),
+        "[ops] alice at 1700000000: deploy {nick} done"
+    );
+    let payload: Value = serde_json::from_str(&webhook::payload(
+        "{nick}: {body}",
+        &[one, message("bob", "ok")],
+    ))
+    .unwrap();
+    assert_eq!(
+        payload,
+        json!({
+            "text": "alice: deploy {nick} done\nbob: ok",
+            "messages": [
+                {"nick": "alice", "room": "ops", "timestamp": 1_700_000_000, "body": "deploy {nick} done"},
+                {"nick": "bob", "room": "ops", "timestamp": 1_700_000_000, "body": "ok"},
+            ],
+        })
+    );
+}
+
+#[tokio::test]
+async fn quick_messages_go_out_together_and_server_errors_are_retried() {
+    let endpoint = FakeEndpoint::bind().await;
+    let mut webhooks = Webhooks::default();
+    let settings = WebhookSettings {
+        batch_ms: 300,
+        retries: 1,
+        ..WebhookSettings::new(&endpoint.url())
+    };
+    webhooks.add("ops", settings).unwrap();
+    let peer = PeerId::random();
+    for body in ["one", "two", "three"] {
+        assert!(webhooks
+            .forward(&peer, message("alice", body), "me")
+            .unwrap());
+    }
+    // A message for a room without a webhook isn't taken
+    let elsewhere = Forwarded {
+        room: "elsewhere".to_string(),
+        ..message("alice", "four")
+    };
+    assert!(!webhooks.forward(&peer, elsewhere, "me").unwrap());
+
+    let (line, first) = endpoint.request(503).await;
+    assert_eq!(line, "POST /hooks/team HTTP/1.1");
+    assert_eq!(first["text"], "alice: one\nalice: two\nalice: three");
+    let (_, again) = endpoint.request(200).await;
+    assert_eq!(again, first);
+    assert_eq!(
+        common::next(webhooks.next()).await,
+        WebhookEvent::Delivered {
+            room: "ops".to_string(),
+            messages: 3
+        }
+    );
+}
+
+#[tokio::test]
+async fn a_webhook_that_keeps_failing_is_rested() {
+    let endpoint = FakeEndpoint::bind().await;
+    let mut webhooks = Webhooks::default();
+    let settings = WebhookSettings {
+        batch_ms: 0,
+        retries: 0,
+        ..WebhookSettings::new(&endpoint.url())
+    };
+    webhooks.add("ops", settings).unwrap();
+    let peer = PeerId::random();
+    for _ in 0..webhook::BREAKER_FAILURES {
+        webhooks
+            .forward(&peer, message("alice", "anyone?"), "me")
+            .unwrap();
+        endpoint.request(404).await;
+        assert_eq!(
+            common::next(webhooks.next()).await,
+            WebhookEvent::Failed {
+                room: "ops".to_string(),
+                messages: 1,
+                error: "the webhook answered 404".to_string()
+            }
+        );
+    }
+    assert_eq!(
+        common::next(webhooks.next()).await,
+        WebhookEvent::Opened {
+            room: "ops".to_string()
+        }
+    );
+    let refused = webhooks.forward(&peer, message("alice", "still there?"), "me");
+    assert!(refused.unwrap_err().contains("keeps failing"));
+}
+
+#[tokio::test]
+async fn a_node_posts_the_rooms_messages_to_its_webhook() {
+    let endpoint = FakeEndpoint::bind().await;
+    let dir = common::temp_path("node");
+    fs::create_dir_all(&dir).unwrap();
+    let config_path = dir.join("config.json");
+    let settings = WebhookSettings {
+        batch_ms: 0,
+        ..WebhookSettings::new(&endpoint.url())
+    };
+    let config = Config {
+        webhooks: [(common::topic().hash().into_string(), settings)].into(),
+        ..Config::default()
+    };
+    config.save(&config_path).unwrap();
+    let alice_cli = common::cli(&["--config", config_path.to_str().unwrap()]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&alice_cli).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    let posted = tokio::spawn(async move { endpoint.request(200).await });
+    bob.handle_line("deploy finished").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+    let (_, payload) = posted.await.unwrap();
+    assert_eq!(payload["text"], "bob: deploy finished");
+    assert_eq!(payload["messages"][0]["room"], topic.hash().as_str());
+    next_webhook_event(&mut alice).await;
+    assert_eq!(alice.stats().counters.webhook_delivered, 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+async fn next_webhook_event(node: &mut ChatNode) {
+    let event = common::next(node.next_webhook_event()).await;
+    node.handle_webhook_event(event);
+}
@@ -0,0 +1,212 @@
+// Wordle over Gossipsub: scoring, turns, the game as a CRDT and a game between two nodes.
+mod common;
+
+use std::time::Duration;
+
+use concurrent_chat_server::{
+    commands::{self, UserCommand, WordleCommand},
+    wordle::{self, Mark, Wordle, WordleMove},
+};
+use libp2p::PeerId;
+
+use Mark::{Gray, Green, Yellow};
+
+#[test]
+fn wordle_commands_parse() {
+    let parse = |line| commands::parse(line).unwrap();
+    assert_eq!(
+        parse("/wordle"),
+        Ok(UserCommand::Wordle(WordleCommand::Show))
+    );
+    assert_eq!(
+        parse("/wordle start crane"),
+        Ok(UserCommand::Wordle(WordleCommand::Start(
+            "crane".to_string()
+        )))
+    );
+    assert_eq!(
+        parse("/wordle guess slate"),
+        Ok(UserCommand::Wordle(WordleCommand::Guess(
+            "slate".to_string()
+        )))
+    );
+    assert_eq!(
+        parse("/wordle end"),
+        Ok(UserCommand::Wordle(WordleCommand::End))
+    );
+    assert!(parse("/wordle guess").is_err());
+    assert!(parse("/wordle guess two words").is_err());
+}
+
+#[test]
+fn guesses_are_scored_like_wordle() {
+    assert_eq!(
+        wordle::score("crane", "crane"),
+        [Green, Green, Green, Green, Green]
+    );
+    assert_eq!(
+        wordle::score("apple", "paper"),
+        [Yellow, Yellow, Green, Yellow, Gray]
+    );
+    // Repeated letters are only yellow as often as the word has them elsewhere
+    assert_eq!(
+        wordle::score("apple", "ppppp"),
+        [Gray, Green, Green, Gray, Gray]
+    );
+    assert_eq!(
+        wordle::score("abbey", "babes"),
+        [Yellow, Yellow, Green, Green, Gray]
+    );
+    assert_eq!(wordle::check_word(" CRANE ").as_deref(), Ok("crane"));
+    assert!(wordle::check_word("cranes").is_err());
+    assert!(wordle::check_word("crâne").is_err());
+}
+
+// Records a move made by `author`, failing when the game refused it.
+fn play(
+    moves: &mut Vec<(PeerId, WordleMove)>,
+    author: PeerId,
+    made: Result<WordleMove, String>,
+) -> Result<(), String> {
+    moves.push((author, made?));
+    Ok(())
+}
+
+#[test]
+fn games_converge_and_players_take_turns() {
+    let (host, alice, bob) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut hosting = Wordle::default();
+    let mut moves = Vec::new();
+    play(&mut moves, host, hosting.start(host, "crane")).unwrap();
+    assert!(hosting.guess(host, "slate").is_err(), "the host can't play");
+
+    // Alice guesses and may not guess again before her guess is scored
+    let mut alices = Wordle::default();
+    alices.insert(host, moves[0].1.clone());
+    play(&mut moves, alice, alices.guess(alice, "slate")).unwrap();
+    assert!(alices.guess(alice, "trace").is_err());
+    hosting.insert(alice, moves[1].1.clone());
+    for score in hosting.score_waiting(host) {
+        alices.insert(host, score.clone());
+        moves.push((host, score));
+    }
+    assert!(
+        hosting.score_waiting(host).is_empty(),
+        "guesses are scored once"
+    );
+    play(&mut moves, alice, alices.guess(alice, "crane")).unwrap();
+    hosting.insert(alice, moves.last().unwrap().1.clone());
+    moves.extend(hosting.score_waiting(host).into_iter().map(|m| (host, m)));
+    play(&mut moves, host, hosting.reveal(host)).unwrap();
+
+    // Bob makes a guess out of turn, before the host scored his first one
+    let bob_first = WordleMove::Guess {
+        game: moves[0].1.game(),
+        n: 1,
+        word: "about".to_string(),
+        clock: 2,
+    };
+    let bob_early = WordleMove::Guess {
+        game: moves[0].1.game(),
+        n: 2,
+        word: "would".to_string(),
+        clock: 3,
+    };
+    moves.push((bob, bob_first));
+    moves.push((bob, bob_early));
+
+    // Whatever order the moves arrive in, everyone ends up with the same game
+    let mut forward = Wordle::default();
+    let mut backward = Wordle::default();
+    for (author, wordle_move) in &moves {
+        forward.insert(*author, wordle_move.clone());
+    }
+    for (author, wordle_move) in moves.iter().rev() {
+        backward.insert(*author, wordle_move.clone());
+    }
+    assert!(!forward.insert(moves[0].0, moves[0].1.clone()));
+    let game = forward.game().unwrap();
+    assert_eq!(Some(&game), backward.game().as_ref());
+    assert_eq!(game.word.as_deref(), Some("crane"));
+    assert_eq!(game.wrong_scores, 0);
+    assert_eq!(game.solvers().collect::<Vec<_>>(), [&alice]);
+    let alices_guesses = &game.guesses[&alice];
+    assert_eq!(
+        alices_guesses[0].marks,
+        Some([Gray, Gray, Green, Gray, Green])
+    );
+    assert_eq!(game.guesses[&bob].len(), 1, "the early guess is ignored");
+    assert!(game.guesses[&bob][0].marks.is_none());
+}
+
+#[test]
+fn only_the_host_scores_and_the_reveal_catches_wrong_scores() {
+    let (host, alice, mallory) = (PeerId::random(), PeerId::random(), PeerId::random());
+    let mut hosting = Wordle::default();
+    let start = hosting.start(host, "crane").unwrap();
+    let game = start.game();
+    let mut player = Wordle::default();
+    player.insert(host, start);
+    let guess = player.guess(alice, "crate").unwrap();
+    hosting.insert(alice, guess);
+    let fake = |clock| WordleMove::Score {
+        game,
+        player: alice,
+        n: 1,
+        word: "crate".to_string(),
+        marks: [Green; wordle::WORD_LEN],
+        clock,
+    };
+    // Mallory can't score, and a host that lies is caught when the word is revealed
+    hosting.insert(mallory, fake(10));
+    assert!(hosting.game().unwrap().guesses[&alice][0].marks.is_none());
+    hosting.insert(host, fake(11));
+    hosting.reveal(host).unwrap();
+    let game = hosting.game().unwrap();
+    assert_eq!(game.wrong_scores, 1);
+    assert!(hosting.guess(alice, "crane").is_err(), "the game is over");
+}
+
+#[tokio::test]
+async fn a_game_between_two_nodes() {
+    let (mut alice, alice_addr) = common::spawn_chat_node(&common::cli(&[])).await;
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&[])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = wordle::wordle_topic();
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |a, b| {
+        common::has_subscriber(a, &topic) && common::has_subscriber(b, &topic)
+    })
+    .await;
+
+    alice.handle_line("/wordle start crane").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.wordle().game().is_some()
+    })
+    .await;
+    let bob_id = bob.local_peer_id();
+    let scored = |n: usize| {
+        move |_: &_, bob: &concurrent_chat_server::chat::ChatNode| {
+            bob.wordle()
+                .game()
+                .and_then(|game| game.guesses.get(&bob_id).cloned())
+                .is_some_and(|guesses| guesses.len() == n && guesses[n - 1].marks.is_some())
+        }
+    };
+    bob.handle_line("/wordle guess slate").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), scored(1)).await;
+    assert!(bob.wordle().game().unwrap().word.is_none(), "still sealed");
+
+    // Finding the word ends the game, and the host reveals it
+    bob.handle_line("/wordle guess crane").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.wordle()
+            .game()
+            .is_some_and(|game| game.is_over() && game.solvers().count() == 1)
+    })
+    .await;
+    let game = bob.wordle().game().unwrap();
+    assert_eq!(game.word.as_deref(), Some("crane"));
+    assert_eq!(game.solvers().collect::<Vec<_>>(), [&bob_id]);
+    assert_eq!(game.wrong_scores, 0);
+    assert_eq!(alice.history().count(), 0, "moves aren't chat");
+}
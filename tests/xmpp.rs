@@ -0,0 +1,365 @@
+// The XMPP bridge: its flags, a bridge logging in to a server and joining a room, and a node
+// mirroring an XMPP room.
+mod common;
+
+use std::time::Duration;
+
+use clap::Parser;
+use concurrent_chat_server::{
+    cli::Cli,
+    runtime,
+    xmpp::{self, XmppBridge, XmppEvent, XmppSettings},
+};
+use data_encoding::BASE64;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+const ROOM: &str = "team@conference.test";
+
+const HEADER: &str = "<?xml version='1.0'?><stream:stream from='test' id='s1' version='1.0' \
+                      xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>";
+
+// A server on this machine, which doesn't offer TLS and is sent the password in the clear.
+struct FakeServer {
+    stream: TcpStream,
+    // What the bridge sent that the test hasn't looked at yet
+    buffer: String,
+}
+
+impl FakeServer {
+    // Take the bridge's connection and log it in as bridge@test with `password`, up to the
+    // presence joining the room, which is returned.
+    async fn accept(listener: &TcpListener, password: &str) -> (Self, String) {
+        let (stream, _) = runtime::timeout(Duration::from_secs(10), listener.accept())
+            .await
+            .expect("the bridge connects")
+            .unwrap();
+        let mut server = FakeServer {
+            stream,
+            buffer: String::new(),
+        };
+        server.read_until("streams'>").await;
+        server
+            .send(&format!(
+                "{HEADER}<stream:features><mechanisms xmlns='urn:ietf:params:xml:ns:xmpp-sasl'>\
+                 <mechanism>SCRAM-SHA-1</mechanism><mechanism>PLAIN</mechanism></mechanisms>\
+                 </stream:features>"
+            ))
+            .await;
+        let auth = server.read_until("</auth>").await;
+        let credentials = auth
+            .strip_prefix("<auth xmlns='urn:ietf:params:xml:ns:xmpp-sasl' mechanism='PLAIN'>")
+            .and_then(|rest| rest.strip_suffix("</auth>"))
+            .unwrap_or_else(|| panic!("{auth} isn't a PLAIN auth"));
+        let credentials = BASE64.decode(credentials.as_bytes()).unwrap();
+        assert_eq!(credentials, format!("\0bridge\0{password}").as_bytes());
+        server
+            .send("<success xmlns='urn:ietf:params:xml:ns:xmpp-sasl'/>")
+            .await;
+        server.read_until("streams'>").await;
+        server
+            .send(&format!(
+                "{HEADER}<stream:features><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'/>\
+                 </stream:features>"
+            ))
+            .await;
+        let bind = server.read_until("</iq>").await;
+        assert!(bind.contains("<resource>p2p-chat</resource>"), "{bind}");
+        server
+            .send(
+                "<iq type='result' id='bind'><bind xmlns='urn:ietf:params:xml:ns:xmpp-bind'>\
+                 <jid>bridge@test/p2p-chat</jid></bind></iq>",
+            )
+            .await;
+        let join = server.read_until("</presence>").await;
+        (server, join)
+    }
+
+    // What the bridge sent, up to and including `end`.
+    async fn read_until(&mut self, end: &str) -> String {
+        loop {
+            if let Some(at) = self.buffer.find(end) {
+                let rest = self.buffer.split_off(at + end.len());
+                return std::mem::replace(&mut self.buffer, rest);
+            }
+            let mut chunk = [0; 4096];
+            let read = runtime::timeout(Duration::from_secs(10), self.stream.read(&mut chunk))
+                .await
+                .unwrap_or_else(|_| panic!("the bridge sends {end}, it sent {}", self.buffer))
+                .unwrap();
+            assert!(read > 0, "the bridge hung up before sending {end}");
+            self.buffer
+                .push_str(std::str::from_utf8(&chunk[..read]).unwrap());
+        }
+    }
+
+    async fn send(&mut self, xml: &str) {
+        self.stream.write_all(xml.as_bytes()).await.unwrap();
+    }
+
+    // Welcome `nick` into the room, after `others` who are already there.
+    async fn joined(&mut self, nick: &str, others: &[&str]) {
+        for other in others {
+            self.send(&format!("<presence from='{ROOM}/{other}'/>"))
+                .await;
+        }
+        self.send(&format!(
+            "<presence from='{ROOM}/{nick}'><x xmlns='http://jabber.org/protocol/muc#user'>\
+             <item role='participant'/><status code='110'/></x></presence>"
+        ))
+        .await;
+    }
+}
+
+fn settings(address: std::net::SocketAddr) -> XmppSettings {
+    XmppSettings {
+        server: address.to_string(),
+        jid: "bridge@test".to_string(),
+        password: "secret".to_string(),
+        room: ROOM.to_string(),
+    }
+}
+
+fn message(nick: &str, body: &str) -> String {
+    format!("<message from='{ROOM}/{nick}' type='groupchat'><body>{body}</body></message>")
+}
+
+#[test]
+fn the_flags_need_an_account_and_a_room() {
+    let parse = |args: &[&str]| {
+        Cli::try_parse_from(
+            ["p2p-chat", "--xmpp-server", "xmpp.test:5223"]
+                .iter()
+                .chain(args),
+        )
+    };
+    let full = [
+        "--xmpp-jid",
+        "bridge@test",
+        "--xmpp-password",
+        "pw",
+        "--xmpp-room",
+        ROOM,
+    ];
+    let settings = XmppSettings::from_cli(&parse(&full).unwrap()).unwrap();
+    assert_eq!(settings.address(), Ok(("xmpp.test".to_string(), 5223)));
+    assert_eq!((settings.user(), settings.domain()), ("bridge", "test"));
+    let settings = XmppSettings {
+        server: "xmpp.test".to_string(),
+        ..settings
+    };
+    assert_eq!(
+        settings.address(),
+        Ok(("xmpp.test".to_string(), xmpp::PORT))
+    );
+    for args in [
+        &full[..4],
+        &full[2..],
+        &[
+            "--xmpp-jid",
+            "bridge",
+            "--xmpp-password",
+            "pw",
+            "--xmpp-room",
+            ROOM,
+        ],
+        &[&full[..], &["--room-pass", "secret"]].concat()[..],
+    ] {
+        assert!(parse(args).is_err(), "{args:?} accepted");
+    }
+    assert!(Cli::try_parse_from(["p2p-chat", "--xmpp-room", ROOM]).is_err());
+    assert!(XmppSettings::from_cli(&Cli::parse_from(["p2p-chat"])).is_none());
+
+    for bad in [
+        "team",
+        "@test",
+        "team@",
+        "a@b@c",
+        "team@test/res",
+        "a b@test",
+    ] {
+        assert!(xmpp::bare_jid(bad).is_err(), "{bad} accepted");
+    }
+    assert_eq!(
+        xmpp::escape("<a & 'b'>\u{7}"),
+        "&lt;a &amp; &apos;b&apos;&gt;"
+    );
+}
+
+#[tokio::test]
+async fn the_bridge_joins_the_room_and_relays() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut bridge = XmppBridge::spawn(settings(listener.local_addr().unwrap()));
+    let (mut server, join) = FakeServer::accept(&listener, "secret").await;
+    assert!(join.contains(&format!("to='{ROOM}/bridge'")), "{join}");
+    assert!(join.contains("<history maxstanzas='0'/>"), "{join}");
+
+    // The nick is taken, so the bridge joins with another
+    server
+        .send(&format!(
+            "<presence from='{ROOM}/bridge' type='error'><error type='cancel'>\
+             <conflict xmlns='urn:ietf:params:xml:ns:xmpp-stanzas'/></error></presence>"
+        ))
+        .await;
+    let join = server.read_until("</presence>").await;
+    assert!(join.contains(&format!("to='{ROOM}/bridge_'")), "{join}");
+    server.joined("bridge_", &["alice"]).await;
+    assert_eq!(
+        common::next(bridge.next()).await,
+        XmppEvent::Connected {
+            jid: "bridge@test/p2p-chat".to_string(),
+            nick: "bridge_".to_string()
+        }
+    );
+
+    // History, our own messages and subjects aren't news, nor are those there before us
+    server
+        .send(&format!(
+            "<message from='{ROOM}/alice' type='groupchat'><body>old</body>\
+             <delay xmlns='urn:xmpp:delay' stamp='2024-01-01T00:00:00Z'/></message>"
+        ))
+        .await;
+    server.send(&message("bridge_", "echo")).await;
+    server
+        .send(&format!(
+            "<message from='{ROOM}' type='groupchat'><subject>plans</subject></message>"
+        ))
+        .await;
+    server.send(&message("alice", "hi &amp; welcome")).await;
+    server.send(&message("alice", "/me waves")).await;
+    server
+        .send(&format!("<presence from='{ROOM}/carol'/>"))
+        .await;
+    server
+        .send(&format!(
+            "<presence from='{ROOM}/carol' type='unavailable'/>"
+        ))
+        .await;
+    for expected in [
+        XmppEvent::Message {
+            nick: "alice".to_string(),
+            text: "hi & welcome".to_string(),
+        },
+        XmppEvent::Action {
+            nick: "alice".to_string(),
+            text: "waves".to_string(),
+        },
+        XmppEvent::Joined("carol".to_string()),
+        XmppEvent::Left("carol".to_string()),
+    ] {
+        assert_eq!(common::next(bridge.next()).await, expected);
+    }
+
+    // Pings from the server are answered, and messages go to the room escaped
+    server
+        .send("<iq type='get' id='p1' from='test'><ping xmlns='urn:xmpp:ping'/></iq>")
+        .await;
+    let pong = server.read_until("/>").await;
+    assert!(pong.contains("type='result' id='p1'"), "{pong}");
+    bridge.relay("bob", "1 < 2 & 3").unwrap();
+    let sent = server.read_until("</message>").await;
+    assert!(
+        sent.contains(&format!("to='{ROOM}' type='groupchat'")),
+        "{sent}"
+    );
+    assert!(
+        sent.contains("<body>&lt;bob&gt; 1 &lt; 2 &amp; 3</body>"),
+        "{sent}"
+    );
+
+    // Losing the server is reported once, and the bridge logs in again
+    drop(server);
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        XmppEvent::Disconnected(_)
+    ));
+    let (mut server, _) = FakeServer::accept(&listener, "secret").await;
+    server.joined("bridge", &[]).await;
+    assert!(matches!(
+        common::next(bridge.next()).await,
+        XmppEvent::Connected { .. }
+    ));
+}
+
+#[tokio::test]
+async fn a_bridge_node_mirrors_the_room_without_loops() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let alice_cli = common::cli(&[
+        "--xmpp-server",
+        &address,
+        "--xmpp-jid",
+        "bridge@test",
+        "--xmpp-password",
+        "secret",
+        "--xmpp-room",
+        ROOM,
+    ]);
+    let (mut alice, alice_addr) = common::spawn_chat_node(&alice_cli).await;
+    let (mut server, _) = FakeServer::accept(&listener, "secret").await;
+    server.joined("bridge", &[]).await;
+    let event = common::next(alice.next_xmpp_event()).await;
+    assert!(matches!(event, XmppEvent::Connected { .. }));
+    alice.handle_xmpp_event(event);
+
+    let (mut bob, _) = common::spawn_chat_node(&common::cli(&["--nick", "bob"])).await;
+    bob.swarm.dial(alice_addr).unwrap();
+    let topic = common::topic();
+    common::run_until(
+        &mut alice,
+        &mut bob,
+        Duration::from_secs(10),
+        |alice, bob| common::has_subscriber(alice, &topic) && common::has_subscriber(bob, &topic),
+    )
+    .await;
+
+    // The room's messages are said in the XMPP room with their author's nick
+    bob.handle_line("hello xmpp").await;
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |alice, _| {
+        alice.history().count() == 1
+    })
+    .await;
+    let sent = server.read_until("</message>").await;
+    assert!(
+        sent.contains("<body>&lt;bob&gt; hello xmpp</body>"),
+        "{sent}"
+    );
+
+    // What is said in the XMPP room, and who joins it, comes to the room marked as from
+    // XMPP, and isn't said back
+    server.send(&message("carol", "hi from xmpp")).await;
+    server
+        .send(&format!("<presence from='{ROOM}/dave'/>"))
+        .await;
+    for _ in 0..2 {
+        let event = common::next(alice.next_xmpp_event()).await;
+        alice.handle_xmpp_event(event);
+    }
+    common::run_until(&mut alice, &mut bob, Duration::from_secs(10), |_, bob| {
+        bob.history().count() == 2
+    })
+    .await;
+    let bodies: Vec<_> = bob
+        .history()
+        .map(|stored| {
+            assert_eq!(stored.message.origin.as_deref(), Some(xmpp::ORIGIN));
+            stored.message.body.to_string()
+        })
+        .collect();
+    assert_eq!(
+        bodies,
+        [
+            format!("* dave joined {ROOM} on XMPP"),
+            "<carol> hi from xmpp".to_string()
+        ]
+    );
+    let counters = alice.stats().counters;
+    assert_eq!((counters.xmpp_sent, counters.xmpp_received), (1, 2));
+    server
+        .send("<iq type='get' id='p1'><ping xmlns='urn:xmpp:ping'/></iq>")
+        .await;
+    let next = server.read_until("/>").await;
+    assert!(next.contains("id='p1'"), "only the pong is sent: {next}");
+}
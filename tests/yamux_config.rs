@@ -0,0 +1,18 @@
+// Custom Yamux flow-control settings must stay wire compatible with default nodes.
+mod common;
+
+#[tokio::test]
+async fn tuned_yamux_node_talks_to_default_node() {
+    let (mut dialer, _) = common::spawn_node(&common::cli(&[
+        "--yamux-window-size",
+        "1048576",
+        "--yamux-max-buffer",
+        "4194304",
+    ]))
+    .await;
+    let (mut listener, addr) = common::spawn_node(&common::cli(&[])).await;
+
+    let received =
+        common::publish_and_receive(&mut dialer, &mut listener, addr, b"big window").await;
+    assert_eq!(received, b"big window");
+}